@@ -0,0 +1,81 @@
+//! Password hashing and token signing, the CPU-bound parts of every login
+//!
+//! Run with `cargo bench --bench crypto`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use tokio::runtime::Runtime;
+
+use rocket_auth_boilerplate::auth::jwt::JwtService;
+use rocket_auth_boilerplate::auth::paseto::PasetoSigner;
+use rocket_auth_boilerplate::auth::password::PasswordHasher;
+use rocket_auth_boilerplate::auth::signer::HmacSigner;
+use rocket_auth_boilerplate::config::{PasetoPurpose, PasswordHashing};
+
+const PASSWORD: &str = "correct horse battery staple";
+
+/// The default and a few stronger work factors, so the cost of raising them is visible
+fn hashing_settings() -> Vec<(String, PasswordHashing)> {
+    let mut settings: Vec<(String, PasswordHashing)> = [10, 12]
+        .into_iter()
+        .map(|cost| (format!("bcrypt-{}", cost), PasswordHashing::Bcrypt { cost }))
+        .collect();
+    settings.push((
+        "argon2-default".to_string(),
+        PasswordHashing::Argon2 {
+            memory_kib: argon2::Params::DEFAULT_M_COST,
+            iterations: argon2::Params::DEFAULT_T_COST,
+            parallelism: argon2::Params::DEFAULT_P_COST,
+        },
+    ));
+    settings.push((
+        "argon2-64mib".to_string(),
+        PasswordHashing::Argon2 { memory_kib: 64 * 1024, iterations: 3, parallelism: 1 },
+    ));
+    settings
+}
+
+fn password_hashing(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("password");
+    group.sample_size(10);
+
+    for (name, hashing) in hashing_settings() {
+        let hasher = PasswordHasher::new(hashing, &[]);
+        let stored = runtime.block_on(hasher.hash(PASSWORD)).unwrap();
+
+        group.bench_with_input(BenchmarkId::new("hash", &name), &hasher, |b, hasher| {
+            b.to_async(&runtime).iter(|| hasher.hash(PASSWORD));
+        });
+        group.bench_with_input(BenchmarkId::new("verify", &name), &hasher, |b, hasher| {
+            b.to_async(&runtime).iter(|| hasher.verify(PASSWORD, &stored));
+        });
+    }
+    group.finish();
+}
+
+fn tokens(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let key = "2a".repeat(32);
+    let services = [
+        ("hmac-jwt", JwtService::new(HmacSigner::new("bench-secret-do-not-use-in-production"))),
+        ("paseto-local", JwtService::new(PasetoSigner::new(PasetoPurpose::Local, &key).unwrap())),
+        ("paseto-public", JwtService::new(PasetoSigner::new(PasetoPurpose::Public, &key).unwrap())),
+    ];
+    let (user_id, session_id) = (uuid::Uuid::new_v4().to_string(), uuid::Uuid::new_v4().to_string());
+
+    let mut group = c.benchmark_group("token");
+    for (name, service) in &services {
+        let token = runtime.block_on(service.generate_token(user_id.clone(), session_id.clone())).unwrap();
+
+        group.bench_with_input(BenchmarkId::new("encode", name), service, |b, service| {
+            b.to_async(&runtime).iter(|| service.generate_token(user_id.clone(), session_id.clone()));
+        });
+        group.bench_with_input(BenchmarkId::new("verify", name), service, |b, service| {
+            b.to_async(&runtime).iter(|| service.verify_token(&token));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, password_hashing, tokens);
+criterion_main!(benches);
@@ -0,0 +1,44 @@
+//! The full `POST /auth/login` handler against a real database
+//!
+//! Run with `cargo bench --bench login`. Needs Docker or `TEST_DATABASE_URL`,
+//! like the integration tests; every iteration creates a session there.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use rocket::http::Status;
+use tokio::runtime::Runtime;
+
+use rocket_auth_boilerplate::config::PasswordHashing;
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::TestApp;
+
+fn login(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    // Production's default work factor rather than the tests' cheap one
+    let app = runtime.block_on(TestApp::spawn_with(|config| {
+        config.password_hashing = PasswordHashing::Bcrypt { cost: bcrypt::DEFAULT_COST };
+        // Repeated wrong passwords would otherwise lock the address out
+        config.login_lockout_attempts = 0;
+    }));
+    let user = runtime.block_on(UserFactory::verified().insert(&app.pool));
+    // The first login re-hashes the fixture's password with the configured cost
+    runtime.block_on(app.login_token(user.email(), &user.password));
+
+    let mut group = c.benchmark_group("login");
+    group.sample_size(20);
+    group.bench_function("success", |b| {
+        b.to_async(&runtime).iter(|| async {
+            let response = app.login(user.email(), &user.password).await;
+            assert_eq!(response.status(), Status::Ok);
+        });
+    });
+    group.bench_function("wrong_password", |b| {
+        b.to_async(&runtime).iter(|| async {
+            let response = app.login(user.email(), "not-the-password").await;
+            assert_eq!(response.status(), Status::Unauthorized);
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, login);
+criterion_main!(benches);
@@ -0,0 +1,193 @@
+use std::net::IpAddr;
+
+use chrono::{Duration, Utc};
+use rocket::fairing::AdHoc;
+use rocket_db_pools::Database;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::email::sender::Mailer;
+use crate::email::templates;
+use crate::models::user::User;
+use crate::repositories::{admin_alerts, audit, login_attempts};
+use crate::settings::Settings;
+use crate::Postgres;
+
+/// How often each server instance looks for anomalies
+const CHECK_INTERVAL_SECONDS: u64 = 60;
+
+/// How far back audit sink dead letters count towards an alert
+const SINK_FAILURE_WINDOW_MINUTES: i64 = 60;
+
+/// Something admins should look at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdminAlert {
+    /// Many addresses are locked out at once, as in a password spraying attack
+    LockoutSpike { locked_out: i64 },
+    /// An audit sink keeps failing to take events
+    SinkFailing { sink: String, dead_letters: i64 },
+    /// An admin logged in from a country they hadn't logged in from before
+    AdminNewCountry { user_id: Uuid, email: String, country: String, ip: Option<IpAddr> },
+}
+
+impl AdminAlert {
+    /// What the alert is about; alerts with the same key share a cooldown
+    pub fn key(&self) -> String {
+        match self {
+            AdminAlert::LockoutSpike { .. } => "lockout_spike".to_string(),
+            AdminAlert::SinkFailing { sink, .. } => format!("sink_failing:{}", sink),
+            AdminAlert::AdminNewCountry { user_id, country, .. } => format!("admin_new_country:{}:{}", user_id, country),
+        }
+    }
+
+    fn summary(&self) -> String {
+        match self {
+            AdminAlert::LockoutSpike { .. } => "login lockouts spiking".to_string(),
+            AdminAlert::SinkFailing { sink, .. } => format!("audit sink {} failing", sink),
+            AdminAlert::AdminNewCountry { country, .. } => format!("admin login from {}", country),
+        }
+    }
+
+    fn details(&self, config: &AppConfig) -> String {
+        match self {
+            AdminAlert::LockoutSpike { locked_out } => format!(
+                "{} email addresses are locked out after {} or more failed logins in the last {} minutes.",
+                locked_out, config.login_lockout_attempts, config.login_lockout_minutes
+            ),
+            AdminAlert::SinkFailing { sink, dead_letters } => format!(
+                "The {} audit sink gave up on {} event(s) in the last {} minutes. \
+                 They are kept in audit_dead_letters and can be replayed once the sink is back.",
+                sink, dead_letters, SINK_FAILURE_WINDOW_MINUTES
+            ),
+            AdminAlert::AdminNewCountry { email, country, ip, .. } => {
+                let origin = ip.map(|ip| format!(" ({})", ip)).unwrap_or_default();
+                format!(
+                    "The admin account {} logged in from {}{}, a country it hadn't logged in from before.",
+                    email, country, origin
+                )
+            }
+        }
+    }
+}
+
+/// Email `alert` to `ROCKET_ADMIN_ALERT_EMAILS`, unless one with its key went out within the cooldown
+///
+/// Returns whether it was sent. Safe to call from several instances at once;
+/// only one sends.
+pub async fn send(conn: &mut PgConnection, config: &AppConfig, mailer: &Mailer, alert: &AdminAlert) -> Result<bool, sqlx::Error> {
+    if config.admin_alert_emails.is_empty() {
+        return Ok(false);
+    }
+    let quiet_since = Utc::now() - Duration::minutes(config.admin_alert_cooldown_minutes as i64);
+    if !admin_alerts::claim(conn, &alert.key(), quiet_since).await? {
+        return Ok(false);
+    }
+
+    let (summary, details) = (alert.summary(), alert.details(config));
+    for to in &config.admin_alert_emails {
+        if let Err(e) = mailer.send(templates::admin_alert(to, &summary, &details)).await {
+            eprintln!("Failed to send admin alert: {}", e);
+        }
+    }
+    Ok(true)
+}
+
+/// Look for lockout spikes and failing audit sinks once, alerting on what's found
+///
+/// Returns how many alerts were sent.
+pub async fn check(conn: &mut PgConnection, config: &AppConfig, mailer: &Mailer) -> Result<usize, sqlx::Error> {
+    let mut found = Vec::new();
+    let now = Utc::now();
+
+    if config.admin_alert_lockout_spike > 0 && config.login_lockout_attempts > 0 {
+        let since = now - Duration::minutes(config.login_lockout_minutes as i64);
+        let locked_out = login_attempts::count_locked_out(conn, config.login_lockout_attempts, since).await?;
+        if locked_out >= config.admin_alert_lockout_spike as i64 {
+            found.push(AdminAlert::LockoutSpike { locked_out });
+        }
+    }
+
+    if config.admin_alert_sink_failures > 0 {
+        let since = now - Duration::minutes(SINK_FAILURE_WINDOW_MINUTES);
+        let failing = audit::count_dead_letters_by_sink(conn, since, config.admin_alert_sink_failures as i64).await?;
+        found.extend(failing.into_iter().map(|(sink, dead_letters)| AdminAlert::SinkFailing { sink, dead_letters }));
+    }
+
+    let mut sent = 0;
+    for alert in &found {
+        if send(conn, config, mailer, alert).await? {
+            sent += 1;
+        }
+    }
+    Ok(sent)
+}
+
+/// Note the country of an admin's successful login, alerting when it's a new one; failures are logged, not returned
+pub async fn admin_login(
+    conn: &mut PgConnection,
+    config: &AppConfig,
+    mailer: &Mailer,
+    user: &User,
+    country: Option<&str>,
+    ip: Option<IpAddr>,
+) {
+    let Some(country) = country.filter(|_| user.role == "admin" && !config.admin_alert_emails.is_empty()) else {
+        return;
+    };
+    let country = country.to_uppercase();
+    if country.len() > 8 || !country.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return;
+    }
+
+    let result = match admin_alerts::record_login_country(conn, user.id, &country).await {
+        Ok(true) => {
+            let alert = AdminAlert::AdminNewCountry {
+                user_id: user.id,
+                email: user.email.clone(),
+                country,
+                ip,
+            };
+            send(conn, config, mailer, &alert).await.map(|_| ())
+        }
+        Ok(false) => Ok(()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        eprintln!("Database error: {}", e);
+    }
+}
+
+/// Fairing that checks for anomalies every minute while the server runs, if alerts have recipients
+pub fn fairing() -> AdHoc {
+    AdHoc::on_liftoff("Admin Alerts", |rocket| {
+        Box::pin(async move {
+            let (Some(settings), Some(mailer), Some(db)) =
+                (rocket.state::<Settings>(), rocket.state::<Mailer>(), Postgres::fetch(rocket))
+            else {
+                eprintln!("Admin alerts not started: missing Settings, Mailer or database");
+                return;
+            };
+            if settings.configured().admin_alert_emails.is_empty() {
+                return;
+            }
+
+            let (settings, mailer, pool) = (settings.clone(), mailer.clone(), PgPool::clone(db));
+            rocket::tokio::spawn(async move {
+                loop {
+                    // The lockout thresholds may be changed at runtime
+                    let result = match pool.acquire().await {
+                        Ok(mut conn) => check(&mut conn, &settings.current(), &mailer).await,
+                        Err(e) => Err(e),
+                    };
+                    match result {
+                        Ok(sent) if sent > 0 => println!("⚠ Sent {} admin alert(s)", sent),
+                        Ok(_) => {}
+                        Err(e) => eprintln!("Admin alert check failed: {}", e),
+                    }
+                    rocket::tokio::time::sleep(std::time::Duration::from_secs(CHECK_INTERVAL_SECONDS)).await;
+                }
+            });
+        })
+    })
+}
@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+use serde_json::json;
+
+use crate::audit::{AuditSink, SinkError};
+use crate::config::HttpSinkFormat;
+use crate::events::SecurityEvent;
+
+/// POSTs each event to an HTTP collector such as the Splunk HTTP Event Collector
+pub struct HttpSink {
+    client: reqwest::Client,
+    url: String,
+    authorization: Option<String>,
+    format: HttpSinkFormat,
+}
+
+impl HttpSink {
+    pub fn new(url: String, authorization: Option<String>, format: HttpSinkFormat) -> Self {
+        HttpSink {
+            client: reqwest::Client::new(),
+            url,
+            authorization,
+            format,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl AuditSink for HttpSink {
+    fn name(&self) -> &str {
+        "http"
+    }
+
+    async fn send(&self, event: &SecurityEvent) -> Result<(), SinkError> {
+        let body = match self.format {
+            HttpSinkFormat::Json => json!(event),
+            HttpSinkFormat::SplunkHec => json!({
+                "time": event.occurred_at.timestamp_millis() as f64 / 1000.0,
+                "source": "rocket-auth",
+                "sourcetype": "_json",
+                "event": event
+            }),
+        };
+
+        let mut request = self.client.post(&self.url).json(&body).timeout(Duration::from_secs(10));
+        if let Some(authorization) = &self.authorization {
+            request = request.header(reqwest::header::AUTHORIZATION, authorization);
+        }
+        let response = request.send().await.map_err(|e| SinkError(e.to_string()))?;
+        if !response.status().is_success() {
+            return Err(SinkError(format!("{} answered {}", self.url, response.status())));
+        }
+        Ok(())
+    }
+}
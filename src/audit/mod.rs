@@ -0,0 +1,187 @@
+pub mod http;
+pub mod stdout;
+pub mod syslog;
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rocket::fairing::AdHoc;
+use rocket_db_pools::Database;
+use sqlx::PgPool;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::config::{AppConfig, AuditSinkConfig};
+use crate::events::{EventBus, SecurityEvent};
+use crate::repositories::audit;
+use crate::Postgres;
+
+/// Errors returned by audit sinks
+#[derive(Debug)]
+pub struct SinkError(pub String);
+
+impl fmt::Display for SinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Audit sink error: {}", self.0)
+    }
+}
+
+impl std::error::Error for SinkError {}
+
+/// Receives every security event as it happens
+///
+/// Implement this to forward events somewhere else (Kafka, a cloud logging API, ...).
+#[rocket::async_trait]
+pub trait AuditSink: Send + Sync {
+    /// Short name used in error logs
+    fn name(&self) -> &str;
+
+    async fn send(&self, event: &SecurityEvent) -> Result<(), SinkError>;
+}
+
+/// The configured audit sinks
+///
+/// Managed as Rocket state. Clones share the sinks.
+#[derive(Clone)]
+pub struct AuditSinks {
+    sinks: Vec<Arc<dyn AuditSink>>,
+    max_attempts: u32,
+    retry: Duration,
+}
+
+impl AuditSinks {
+    pub fn from_config(config: &AppConfig) -> Self {
+        let sinks = config
+            .audit_sinks
+            .iter()
+            .map(|sink| -> Arc<dyn AuditSink> {
+                match sink {
+                    AuditSinkConfig::Stdout => Arc::new(stdout::StdoutSink),
+                    AuditSinkConfig::Syslog { address } => Arc::new(syslog::SyslogSink::new(address.clone())),
+                    AuditSinkConfig::Http { url, authorization, format } => {
+                        Arc::new(http::HttpSink::new(url.clone(), authorization.clone(), *format))
+                    }
+                }
+            })
+            .collect();
+        AuditSinks {
+            sinks,
+            max_attempts: config.audit_sink_max_attempts.max(1),
+            retry: Duration::from_secs(config.audit_sink_retry_seconds),
+        }
+    }
+
+    /// Also forward events to `sink`
+    pub fn with(mut self, sink: Arc<dyn AuditSink>) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    /// The sink called `name`
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn AuditSink>> {
+        self.sinks.iter().find(|sink| sink.name() == name)
+    }
+
+    /// Send an event to every sink
+    ///
+    /// A sink that fails is retried in the background, waiting twice as long
+    /// each time. Once it has failed every attempt, the delivery is recorded
+    /// in `audit_dead_letters` and an alert is logged.
+    pub async fn deliver(&self, pool: &PgPool, event: &SecurityEvent) {
+        for sink in &self.sinks {
+            let Err(error) = sink.send(event).await else {
+                continue;
+            };
+
+            let (sink, pool, event) = (sink.clone(), pool.clone(), event.clone());
+            let (max_attempts, retry) = (self.max_attempts, self.retry);
+            rocket::tokio::spawn(async move {
+                let mut attempts = 1;
+                let mut error = error;
+                while attempts < max_attempts {
+                    rocket::tokio::time::sleep(retry * 2u32.pow(attempts - 1)).await;
+                    attempts += 1;
+                    match sink.send(&event).await {
+                        Ok(()) => return,
+                        Err(e) => error = e,
+                    }
+                }
+
+                eprintln!(
+                    "⚠ Audit sink {} failed event {} after {} attempt(s), moved to audit_dead_letters: {}",
+                    sink.name(),
+                    event.id,
+                    attempts,
+                    error
+                );
+                let recorded = match pool.acquire().await {
+                    Ok(mut conn) => {
+                        audit::record_dead_letter(
+                            &mut conn,
+                            event.id,
+                            event.occurred_at,
+                            sink.name(),
+                            attempts as i32,
+                            &error.to_string(),
+                        )
+                        .await
+                    }
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = recorded {
+                    eprintln!("Database error: {}", e);
+                }
+            });
+        }
+    }
+}
+
+/// Fairing that forwards events from the managed `EventBus` to the `AuditSinks`
+///
+/// Every instance receives every event, so each one is claimed in
+/// `audit_events` first and only the instance that claims it forwards it.
+/// Events published while no instance is running aren't forwarded.
+pub fn forwarder() -> AdHoc {
+    AdHoc::on_liftoff("Audit Sink Forwarder", |rocket| {
+        Box::pin(async move {
+            let (Some(sinks), Some(bus), Some(db)) =
+                (rocket.state::<AuditSinks>(), rocket.state::<EventBus>(), Postgres::fetch(rocket))
+            else {
+                eprintln!("Audit sink forwarder not started: missing AuditSinks, EventBus or database");
+                return;
+            };
+            if sinks.is_empty() {
+                return;
+            }
+
+            let (sinks, pool) = (sinks.clone(), PgPool::clone(db));
+            let mut events = bus.subscribe();
+            rocket::tokio::spawn(async move {
+                loop {
+                    let event = match events.recv().await {
+                        Ok(event) => event,
+                        Err(RecvError::Lagged(missed)) => {
+                            eprintln!("Audit sink forwarder fell behind; {} event(s) not forwarded", missed);
+                            continue;
+                        }
+                        Err(RecvError::Closed) => return,
+                    };
+
+                    let claimed = match pool.acquire().await {
+                        Ok(mut conn) => audit::claim_forwarding(&mut conn, event.id, event.occurred_at).await,
+                        Err(e) => Err(e),
+                    };
+                    match claimed {
+                        Ok(true) => sinks.deliver(&pool, &event).await,
+                        Ok(false) => {}
+                        Err(e) => eprintln!("Database error: {}", e),
+                    }
+                }
+            });
+        })
+    })
+}
@@ -0,0 +1,18 @@
+use crate::audit::{AuditSink, SinkError};
+use crate::events::SecurityEvent;
+
+/// Prints each event to stdout as one line of JSON, for log shippers that tail the process output
+pub struct StdoutSink;
+
+#[rocket::async_trait]
+impl AuditSink for StdoutSink {
+    fn name(&self) -> &str {
+        "stdout"
+    }
+
+    async fn send(&self, event: &SecurityEvent) -> Result<(), SinkError> {
+        let line = serde_json::to_string(event).map_err(|e| SinkError(e.to_string()))?;
+        println!("{}", line);
+        Ok(())
+    }
+}
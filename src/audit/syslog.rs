@@ -0,0 +1,72 @@
+use chrono::SecondsFormat;
+use tokio::net::{lookup_host, UdpSocket, UnixDatagram};
+
+use crate::audit::{AuditSink, SinkError};
+use crate::events::SecurityEvent;
+
+/// Facility `authpriv` (10), severity `notice` (5)
+const PRIORITY: u8 = 10 * 8 + 5;
+
+/// `APP-NAME` in the syslog header
+const APP_NAME: &str = "rocket-auth";
+
+/// Sends each event to a syslog daemon as an RFC 5424 message
+///
+/// Addresses starting with `/` are Unix datagram sockets (`/dev/log`), anything
+/// else is a UDP `host:port`. The event type is the `MSGID` and the event as
+/// JSON the message.
+pub struct SyslogSink {
+    address: String,
+    hostname: String,
+}
+
+impl SyslogSink {
+    pub fn new(address: String) -> Self {
+        let hostname = std::env::var("HOSTNAME")
+            .ok()
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "-".to_string());
+        SyslogSink { address, hostname }
+    }
+
+    fn format(&self, event: &SecurityEvent) -> Result<String, SinkError> {
+        let message = serde_json::to_string(event).map_err(|e| SinkError(e.to_string()))?;
+        Ok(format!(
+            "<{}>1 {} {} {} {} {} - {}",
+            PRIORITY,
+            event.occurred_at.to_rfc3339_opts(SecondsFormat::Millis, true),
+            self.hostname,
+            APP_NAME,
+            std::process::id(),
+            event.kind.name(),
+            message
+        ))
+    }
+}
+
+#[rocket::async_trait]
+impl AuditSink for SyslogSink {
+    fn name(&self) -> &str {
+        "syslog"
+    }
+
+    async fn send(&self, event: &SecurityEvent) -> Result<(), SinkError> {
+        let message = self.format(event)?;
+        let error = |e: std::io::Error| SinkError(format!("{}: {}", self.address, e));
+
+        if self.address.starts_with('/') {
+            let socket = UnixDatagram::unbound().map_err(error)?;
+            socket.send_to(message.as_bytes(), &self.address).await.map_err(error)?;
+        } else {
+            let target = lookup_host(&self.address)
+                .await
+                .map_err(error)?
+                .next()
+                .ok_or_else(|| SinkError(format!("{}: no address found", self.address)))?;
+            let local = if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+            let socket = UdpSocket::bind(local).await.map_err(error)?;
+            socket.send_to(message.as_bytes(), target).await.map_err(error)?;
+        }
+        Ok(())
+    }
+}
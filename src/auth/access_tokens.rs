@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::auth::jwt::{JwtService, TOKEN_LIFETIME_HOURS};
+use crate::auth::signer::SignerError;
+use crate::auth::tokens;
+use crate::config::{AppConfig, TokenStrategy};
+use crate::models::session::{ClientGrant, Session, SessionDevice};
+use crate::models::user::User;
+use crate::repositories::plans;
+use crate::repositories::sessions::{self, SessionRef};
+use crate::telemetry::TelemetryWriter;
+
+/// Marks a bearer credential as an opaque access token rather than a JWT
+pub const OPAQUE_TOKEN_PREFIX: &str = "at_";
+
+/// Cached lookups kept before expired ones are swept out
+const CACHE_SWEEP_THRESHOLD: usize = 10_000;
+
+pub fn is_opaque_token(token: &str) -> bool {
+    token.starts_with(OPAQUE_TOKEN_PREFIX)
+}
+
+#[derive(Debug)]
+pub enum AccessTokenError {
+    /// The session couldn't be stored
+    Database(sqlx::Error),
+    /// The JWT couldn't be signed
+    Signer(SignerError),
+}
+
+impl fmt::Display for AccessTokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessTokenError::Database(e) => write!(f, "Database error: {}", e),
+            AccessTokenError::Signer(e) => write!(f, "Token error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AccessTokenError {}
+
+/// The active session an opaque token belongs to
+#[derive(Debug, Clone)]
+pub struct TokenSession {
+    pub user_id: Uuid,
+    pub session_id: Uuid,
+    pub guest: bool,
+    pub scopes: Option<Vec<String>>,
+    expires_at: DateTime<Utc>,
+}
+
+/// Issues access tokens for new sessions with the configured strategy, and resolves opaque ones
+///
+/// Managed as Rocket state. Sessions end at their absolute maximum age, or
+/// earlier once they've been idle for the idle timeout, if one is set. Opaque tokens are random strings; only their
+/// SHA-256 is stored, on the session row, so revoking the session revokes
+/// the token. Successful lookups are cached in memory for a few seconds;
+/// revocations in this process evict them right away (see `forget_user`),
+/// revocations elsewhere (the admin CLI, other replicas) take effect once
+/// the cache entry ages out.
+pub struct AccessTokens {
+    strategy: TokenStrategy,
+    max_age: TimeDelta,
+    idle_timeout: Option<TimeDelta>,
+    cache: Mutex<HashMap<String, (TokenSession, Instant)>>,
+    telemetry: TelemetryWriter,
+}
+
+impl AccessTokens {
+    pub fn new(strategy: TokenStrategy) -> Self {
+        AccessTokens {
+            strategy,
+            max_age: TimeDelta::hours(TOKEN_LIFETIME_HOURS),
+            idle_timeout: None,
+            cache: Mutex::new(HashMap::new()),
+            telemetry: TelemetryWriter::direct(),
+        }
+    }
+
+    /// End sessions after `max_age`, or after `idle_timeout` without use
+    pub fn with_session_limits(mut self, max_age: TimeDelta, idle_timeout: Option<TimeDelta>) -> Self {
+        self.max_age = max_age;
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Record session touches, logins and session events through `telemetry`
+    pub fn with_telemetry(mut self, telemetry: TelemetryWriter) -> Self {
+        self.telemetry = telemetry;
+        self
+    }
+
+    pub fn from_config(config: &AppConfig) -> Self {
+        let idle_timeout = (config.session_idle_timeout_minutes > 0)
+            .then(|| TimeDelta::minutes(config.session_idle_timeout_minutes as i64));
+        AccessTokens::new(config.token_strategy)
+            .with_session_limits(TimeDelta::hours(config.session_max_age_hours as i64), idle_timeout)
+    }
+
+    /// Where session touches, logins and session events are written
+    pub fn telemetry(&self) -> &TelemetryWriter {
+        &self.telemetry
+    }
+
+    /// Absolute lifetime of new sessions
+    pub fn max_age(&self) -> TimeDelta {
+        self.max_age
+    }
+
+    /// How long sessions may go unused, if they expire when idle
+    pub fn idle_timeout(&self) -> Option<TimeDelta> {
+        self.idle_timeout
+    }
+
+    /// When a session started now reaches its maximum age
+    pub fn session_expiry(&self) -> DateTime<Utc> {
+        Utc::now() + self.max_age
+    }
+
+    /// Sessions last used before this have gone idle
+    pub fn active_since(&self) -> Option<DateTime<Utc>> {
+        self.idle_timeout.map(|idle_timeout| Utc::now() - idle_timeout)
+    }
+
+    /// Start a session for a user on `device` and return it with its access token
+    pub async fn issue(
+        &self,
+        conn: &mut PgConnection,
+        jwt: &JwtService,
+        user: &User,
+        device: &SessionDevice,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(Session, String), AccessTokenError> {
+        match self.strategy {
+            TokenStrategy::Jwt => {
+                let session = sessions::create(conn, user.id, expires_at, device)
+                    .await
+                    .map_err(AccessTokenError::Database)?;
+                let plan = plans::active_plan(conn, user.id).await.map_err(AccessTokenError::Database)?;
+                let claims = jwt.claims_for(user, session.id, expires_at).plan(plan);
+                let token = jwt.sign_session(conn, &claims).await.map_err(AccessTokenError::Signer)?;
+                Ok((session, token))
+            }
+            TokenStrategy::Opaque { .. } => {
+                let token = format!("{}{}{}", OPAQUE_TOKEN_PREFIX, Uuid::new_v4().simple(), Uuid::new_v4().simple());
+                let session = sessions::create_with_token(conn, user.id, expires_at, device, &tokens::hash(&token))
+                    .await
+                    .map_err(AccessTokenError::Database)?;
+                Ok((session, token))
+            }
+        }
+    }
+
+    /// Start a session an OAuth client holds for a user, limited by `grant`, and return it with its access token
+    ///
+    /// Tokens for another service (`grant.audience`) are always signed, since
+    /// that service verifies them itself.
+    pub async fn issue_for_client(
+        &self,
+        conn: &mut PgConnection,
+        jwt: &JwtService,
+        user: &User,
+        grant: &ClientGrant<'_>,
+    ) -> Result<(Session, String), AccessTokenError> {
+        match self.strategy {
+            TokenStrategy::Opaque { .. } if grant.audience.is_none() => {
+                let token = format!("{}{}{}", OPAQUE_TOKEN_PREFIX, Uuid::new_v4().simple(), Uuid::new_v4().simple());
+                let hash = tokens::hash(&token);
+                let session = sessions::create_for_client(conn, user.id, grant, Some(&hash))
+                    .await
+                    .map_err(AccessTokenError::Database)?;
+                Ok((session, token))
+            }
+            _ => {
+                let session = sessions::create_for_client(conn, user.id, grant, None)
+                    .await
+                    .map_err(AccessTokenError::Database)?;
+                let plan = plans::active_plan(conn, user.id).await.map_err(AccessTokenError::Database)?;
+                let mut claims = jwt.claims_for(user, session.id, grant.expires_at).plan(plan);
+                claims.scope = Some(grant.scopes.to_vec());
+                claims.aud = grant.audience.map(str::to_string);
+                let token = jwt.sign_session(conn, &claims).await.map_err(AccessTokenError::Signer)?;
+                Ok((session, token))
+            }
+        }
+    }
+
+    /// The active session behind an opaque token, or `None` if it's unknown, revoked or expired
+    pub async fn resolve(&self, conn: &mut PgConnection, token: &str) -> Result<Option<TokenSession>, sqlx::Error> {
+        let hash = tokens::hash(token);
+        let ttl = self.cache_ttl();
+
+        if let Some((session, cached_at)) = self.cache.lock().unwrap().get(&hash)
+            && cached_at.elapsed() < ttl
+            && session.expires_at > Utc::now()
+        {
+            return Ok(Some(session.clone()));
+        }
+
+        let session = sessions::find_active_by_token_hash(conn, &hash, self.active_since())
+            .await?
+            .map(|(session, guest)| TokenSession {
+                user_id: session.user_id,
+                session_id: session.id,
+                guest,
+                scopes: session.scopes,
+                expires_at: session.expires_at,
+            });
+
+        if let Some(session) = &session {
+            self.telemetry.touch_session(conn, session.session_id).await?;
+        }
+        if let Some(session) = &session
+            && !ttl.is_zero()
+        {
+            let mut cache = self.cache.lock().unwrap();
+            if cache.len() >= CACHE_SWEEP_THRESHOLD {
+                cache.retain(|_, (_, cached_at)| cached_at.elapsed() < ttl);
+            }
+            cache.insert(hash, (session.clone(), Instant::now()));
+        }
+        Ok(session)
+    }
+
+    /// The user's session behind a JWT, or `None` if it's revoked, expired or idle for too long
+    pub async fn find_session(
+        &self,
+        conn: &mut PgConnection,
+        session_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<Session>, sqlx::Error> {
+        let session = sessions::find_active(conn, session_id, user_id, self.active_since()).await?;
+        if session.is_some() {
+            self.telemetry.touch_session(conn, session_id).await?;
+        }
+        Ok(session)
+    }
+
+    /// Revoke an access token issued to an OAuth client (RFC 7009)
+    ///
+    /// Returns false for tokens that are unknown, invalid, already revoked or
+    /// another client's; revocation requests don't reveal which.
+    pub async fn revoke_for_client(
+        &self,
+        conn: &mut PgConnection,
+        jwt: &JwtService,
+        token: &str,
+        oauth_client_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let hash = is_opaque_token(token).then(|| tokens::hash(token));
+        let session = match &hash {
+            Some(hash) => SessionRef::TokenHash(hash),
+            None => match jwt.verify_session(conn, token).await.ok().and_then(|claims| Uuid::parse_str(&claims.sid).ok()) {
+                Some(session_id) => SessionRef::Id(session_id),
+                None => return Ok(false),
+            },
+        };
+
+        match sessions::revoke_client_session(conn, session, oauth_client_id).await? {
+            Some(user_id) => {
+                self.forget_user(user_id);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Drop cached lookups for a user whose sessions were just revoked or who was deleted
+    pub fn forget_user(&self, user_id: Uuid) {
+        self.cache
+            .lock()
+            .unwrap()
+            .retain(|_, (session, _)| session.user_id != user_id);
+    }
+
+    fn cache_ttl(&self) -> Duration {
+        match self.strategy {
+            TokenStrategy::Opaque { cache_seconds } => Duration::from_secs(cache_seconds),
+            // Opaque tokens issued before switching to JWTs still resolve, uncached
+            TokenStrategy::Jwt => Duration::ZERO,
+        }
+    }
+}
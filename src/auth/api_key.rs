@@ -0,0 +1,34 @@
+use uuid::Uuid;
+
+use crate::auth::tokens;
+
+/// Marks a bearer credential as an API key rather than a JWT
+pub const API_KEY_PREFIX: &str = "ak_";
+
+/// Characters of a key kept in the clear for display
+const DISPLAY_PREFIX_LEN: usize = 11;
+
+/// A freshly generated key; `key` is shown to the user once and never stored
+pub struct GeneratedApiKey {
+    pub key: String,
+    pub prefix: String,
+    pub hash: String,
+}
+
+pub fn generate() -> GeneratedApiKey {
+    let key = format!("{}{}{}", API_KEY_PREFIX, Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    GeneratedApiKey {
+        prefix: key[..DISPLAY_PREFIX_LEN].to_string(),
+        hash: hash(&key),
+        key,
+    }
+}
+
+/// SHA-256 of the key, hex encoded; keys are random enough not to need a slow hash
+pub fn hash(key: &str) -> String {
+    tokens::hash(key)
+}
+
+pub fn is_api_key(token: &str) -> bool {
+    token.starts_with(API_KEY_PREFIX)
+}
@@ -0,0 +1,143 @@
+use std::time::Duration;
+
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::auth::external::ExternalClaims;
+use crate::auth::oauth::{self, AuthorizationRequest, OAuthError, OAuthIdentity, OAuthProvider, RoleSync};
+use crate::config::DiscordConfig;
+
+const API: &str = "https://discord.com/api/v10";
+
+/// Namespace of Discord user ids in `external_identities`
+const ISSUER: &str = "https://discord.com";
+
+#[derive(Debug, Deserialize)]
+struct DiscordUser {
+    id: String,
+    #[serde(default)]
+    email: Option<String>,
+    #[serde(default)]
+    verified: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DiscordGuild {
+    id: String,
+}
+
+/// Sign-in with Discord
+///
+/// Discord isn't an OpenID Connect provider: the user is fetched with the
+/// access token instead of read from an ID token. With guild roles
+/// configured, the user's guilds are fetched too and the first configured
+/// guild they're in decides their role.
+pub struct DiscordProvider {
+    config: DiscordConfig,
+    client: reqwest::Client,
+}
+
+impl DiscordProvider {
+    pub fn new(config: DiscordConfig) -> Self {
+        DiscordProvider {
+            config,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(&self, path: &str, access_token: &str) -> Result<T, OAuthError> {
+        self.client
+            .get(format!("{}{}", API, path))
+            .bearer_auth(access_token)
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| OAuthError::Provider(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| OAuthError::Provider(e.to_string()))
+    }
+}
+
+#[rocket::async_trait]
+impl OAuthProvider for DiscordProvider {
+    fn authorization_url(&self, request: &AuthorizationRequest<'_>) -> String {
+        let scope = match self.config.guild_roles.is_empty() {
+            true => "identify email",
+            false => "identify email guilds",
+        };
+        Url::parse_with_params(
+            "https://discord.com/oauth2/authorize",
+            &[
+                ("client_id", self.config.client_id.as_str()),
+                ("response_type", "code"),
+                ("redirect_uri", request.redirect_uri),
+                ("scope", scope),
+                ("state", request.state),
+                ("code_challenge", request.code_challenge),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .expect("authorization endpoint is a valid URL")
+        .to_string()
+    }
+
+    async fn exchange(
+        &self,
+        code: &str,
+        redirect_uri: &str,
+        code_verifier: &str,
+        _nonce: &str,
+    ) -> Result<OAuthIdentity, OAuthError> {
+        let tokens = oauth::request_token(
+            &self.client,
+            &format!("{}/oauth2/token", API),
+            &[
+                ("client_id", self.config.client_id.as_str()),
+                ("client_secret", self.config.client_secret.as_str()),
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", redirect_uri),
+                ("code_verifier", code_verifier),
+            ],
+        )
+        .await?;
+
+        let profile: Value = self.get("/users/@me", &tokens.access_token).await?;
+        let user: DiscordUser = serde_json::from_value(profile.clone()).map_err(|e| OAuthError::Provider(e.to_string()))?;
+
+        let roles = match self.config.guild_roles.is_empty() {
+            true => None,
+            false => {
+                let guilds: Vec<DiscordGuild> = self.get("/users/@me/guilds", &tokens.access_token).await?;
+                let granted = self
+                    .config
+                    .guild_roles
+                    .iter()
+                    .find(|(guild_id, _)| guilds.iter().any(|guild| &guild.id == guild_id))
+                    .map(|(_, role)| role.clone());
+                Some(RoleSync {
+                    granted,
+                    managed: self.config.guild_roles.iter().map(|(_, role)| role.clone()).collect(),
+                })
+            }
+        };
+
+        Ok(OAuthIdentity {
+            issuer: ISSUER.to_string(),
+            claims: ExternalClaims {
+                iss: ISSUER.to_string(),
+                sub: user.id,
+                email: user.email,
+                email_verified: user.verified,
+                tid: None,
+                nonce: None,
+            },
+            tenant: None,
+            roles,
+            profile,
+        })
+    }
+}
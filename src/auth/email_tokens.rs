@@ -0,0 +1,196 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::config::{AppConfig, EmailTokenMode};
+use crate::repositories::{email_verifications, nonces, password_resets};
+
+/// What an emailed token lets its holder do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailTokenPurpose {
+    VerifyEmail,
+    ResetPassword,
+}
+
+impl EmailTokenPurpose {
+    /// Tag signed into the token, so one purpose's token can't be used for another
+    fn tag(self) -> &'static str {
+        match self {
+            EmailTokenPurpose::VerifyEmail => "v",
+            EmailTokenPurpose::ResetPassword => "r",
+        }
+    }
+}
+
+/// A verification or password reset token found in the database or checked by its signature
+#[derive(Debug, Clone)]
+pub struct EmailToken {
+    pub user_id: Uuid,
+    /// Set when the token verifies a secondary address rather than the primary one
+    pub email_id: Option<Uuid>,
+    pub expires_at: DateTime<Utc>,
+    pub used: bool,
+    source: Source,
+}
+
+#[derive(Debug, Clone)]
+enum Source {
+    /// A row in `password_reset_tokens` or `email_verification_tokens`
+    Stored { purpose: EmailTokenPurpose, token: String },
+    /// A signed token, used up by recording its nonce in `token_nonces`
+    Signed { nonce: String },
+}
+
+/// Create a token for an email link
+///
+/// With `ROCKET_EMAIL_TOKENS=stored` a random token is stored hashed, as
+/// before. With `signed` nothing is written: the token carries its purpose,
+/// user, address and expiry, authenticated with HMAC SHA-256.
+pub async fn issue(
+    conn: &mut PgConnection,
+    config: &AppConfig,
+    purpose: EmailTokenPurpose,
+    user_id: Uuid,
+    email_id: Option<Uuid>,
+    expires_at: DateTime<Utc>,
+) -> Result<String, sqlx::Error> {
+    if let EmailTokenMode::Signed { secret } = &config.email_tokens {
+        return Ok(sign(secret, purpose, user_id, email_id, expires_at));
+    }
+
+    let token = Uuid::new_v4().to_string();
+    match (purpose, email_id) {
+        (EmailTokenPurpose::ResetPassword, _) => password_resets::create(conn, user_id, &token, expires_at).await?,
+        (EmailTokenPurpose::VerifyEmail, None) => email_verifications::create(conn, user_id, &token, expires_at).await?,
+        (EmailTokenPurpose::VerifyEmail, Some(email_id)) => {
+            email_verifications::create_for_address(conn, user_id, email_id, &token, expires_at).await?
+        }
+    }
+    Ok(token)
+}
+
+/// Look up a token for `purpose`, stored or signed, whatever mode is configured
+///
+/// Stored tokens keep working after switching to signed ones. Signed tokens
+/// are only accepted while a secret is configured. Expiry and `used` are
+/// reported rather than checked, so callers can tell the user which it was.
+pub async fn find(
+    conn: &mut PgConnection,
+    config: &AppConfig,
+    purpose: EmailTokenPurpose,
+    token: &str,
+) -> Result<Option<EmailToken>, sqlx::Error> {
+    if is_signed(token) {
+        let EmailTokenMode::Signed { secret } = &config.email_tokens else {
+            return Ok(None);
+        };
+        let Some((mut found, nonce)) = verify(secret, purpose, token) else {
+            return Ok(None);
+        };
+        found.used = nonces::is_consumed(conn, &nonce).await?;
+        return Ok(Some(found));
+    }
+
+    let source = Source::Stored {
+        purpose,
+        token: token.to_string(),
+    };
+    let found = match purpose {
+        EmailTokenPurpose::ResetPassword => password_resets::find_by_token(conn, token)
+            .await?
+            .map(|reset| EmailToken {
+                user_id: reset.user_id,
+                email_id: None,
+                expires_at: reset.expires_at,
+                used: reset.used,
+                source,
+            }),
+        EmailTokenPurpose::VerifyEmail => email_verifications::find_by_token(conn, token)
+            .await?
+            .map(|verification| EmailToken {
+                user_id: verification.user_id,
+                email_id: verification.email_id,
+                expires_at: verification.expires_at,
+                used: verification.used,
+                source,
+            }),
+    };
+    Ok(found)
+}
+
+/// Use a token up so it can't be replayed
+///
+/// Returns false if it was used up meanwhile, e.g. by a concurrent request.
+pub async fn mark_used(conn: &mut PgConnection, token: &EmailToken) -> Result<bool, sqlx::Error> {
+    match &token.source {
+        Source::Stored {
+            purpose: EmailTokenPurpose::ResetPassword,
+            token,
+        } => password_resets::mark_used(conn, token).await,
+        Source::Stored {
+            purpose: EmailTokenPurpose::VerifyEmail,
+            token,
+        } => email_verifications::mark_used(conn, token).await,
+        Source::Signed { nonce } => nonces::consume(conn, nonce, token.expires_at).await,
+    }
+}
+
+/// Stored tokens are UUIDs; signed ones are dot-separated fields
+fn is_signed(token: &str) -> bool {
+    token.contains('.')
+}
+
+/// `<purpose>.<user>.<address>.<expiry>.<nonce>.<mac>`, URL safe without encoding
+fn sign(
+    secret: &str,
+    purpose: EmailTokenPurpose,
+    user_id: Uuid,
+    email_id: Option<Uuid>,
+    expires_at: DateTime<Utc>,
+) -> String {
+    let payload = format!(
+        "{}.{}.{}.{}.{}",
+        purpose.tag(),
+        user_id.simple(),
+        email_id.map(|id| id.simple().to_string()).unwrap_or_default(),
+        expires_at.timestamp(),
+        Uuid::new_v4().simple()
+    );
+    let signature = hex::encode(mac(secret, &payload).finalize().into_bytes());
+    format!("{}.{}", payload, signature)
+}
+
+/// Check a signed token's MAC and purpose, returning it with its nonce
+fn verify(secret: &str, purpose: EmailTokenPurpose, token: &str) -> Option<(EmailToken, String)> {
+    let (payload, signature) = token.rsplit_once('.')?;
+    mac(secret, payload).verify_slice(&hex::decode(signature).ok()?).ok()?;
+
+    let [tag, user_id, email_id, expires_at, nonce] = payload.split('.').collect::<Vec<_>>().try_into().ok()?;
+    if tag != purpose.tag() {
+        return None;
+    }
+    let email_id = match email_id {
+        "" => None,
+        id => Some(Uuid::parse_str(id).ok()?),
+    };
+    let token = EmailToken {
+        user_id: Uuid::parse_str(user_id).ok()?,
+        email_id,
+        expires_at: DateTime::from_timestamp(expires_at.parse().ok()?, 0)?,
+        used: false,
+        source: Source::Signed {
+            nonce: nonce.to_string(),
+        },
+    };
+    Some((token, nonce.to_string()))
+}
+
+fn mac(secret: &str, payload: &str) -> Hmac<Sha256> {
+    // Domain-separated in case the same secret also signs JWTs
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(b"email-token:");
+    mac.update(payload.as_bytes());
+    mac
+}
@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+use sqlx::PgConnection;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use crate::auth::password::{PasswordError, PasswordHasher};
+use crate::config::ExternalJwtConfig;
+use crate::repositories::{external_identities, users};
+
+/// How long fetched signing keys are trusted before they're fetched again
+const JWKS_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Least time between fetches triggered by an unknown key id, so tokens
+/// with made-up `kid`s can't hammer the issuer
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(60);
+
+/// Stands for the token's `tid` in the issuers of multi-tenant providers (Microsoft Entra)
+pub const TENANT_PLACEHOLDER: &str = "{tenantid}";
+
+#[derive(Debug)]
+pub enum ExternalTokenError {
+    /// The token was malformed, expired, or had an invalid signature, issuer or audience
+    Invalid(String),
+    /// The issuer's keys couldn't be fetched
+    Jwks(String),
+}
+
+impl fmt::Display for ExternalTokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExternalTokenError::Invalid(message) => write!(f, "Invalid external token: {}", message),
+            ExternalTokenError::Jwks(message) => write!(f, "Failed to fetch issuer keys: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ExternalTokenError {}
+
+#[derive(Debug)]
+pub enum ProvisionError {
+    /// The token's email is registered locally but the issuer doesn't vouch for it
+    EmailTaken,
+    /// The subject has no local user and new ones may not be created
+    SignupClosed,
+    Database(sqlx::Error),
+    Password(PasswordError),
+}
+
+impl fmt::Display for ProvisionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProvisionError::EmailTaken => write!(f, "Email is already registered"),
+            ProvisionError::SignupClosed => write!(f, "Registration is closed"),
+            ProvisionError::Database(e) => write!(f, "Database error: {}", e),
+            ProvisionError::Password(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ProvisionError {}
+
+impl From<sqlx::Error> for ProvisionError {
+    fn from(e: sqlx::Error) -> Self {
+        ProvisionError::Database(e)
+    }
+}
+
+/// Claims of an external token that matter for provisioning
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExternalClaims {
+    pub iss: String,
+    pub sub: String,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: Option<bool>,
+    /// Directory (tenant) id, sent by Microsoft Entra
+    #[serde(default)]
+    pub tid: Option<String>,
+    /// Echo of the nonce from an OpenID Connect authorization request
+    #[serde(default)]
+    pub nonce: Option<String>,
+}
+
+impl ExternalClaims {
+    /// The email, if the issuer vouches for it
+    pub fn verified_email(&self) -> Option<&str> {
+        self.email.as_deref().filter(|_| self.email_verified == Some(true))
+    }
+}
+
+#[derive(Deserialize)]
+struct IssuerClaim {
+    iss: Option<String>,
+    tid: Option<String>,
+}
+
+#[derive(Default)]
+struct KeyCache {
+    keys: HashMap<String, DecodingKey>,
+    fetched_at: Option<Instant>,
+}
+
+/// Verifies tokens from a hosted identity provider against its published keys (JWKS)
+///
+/// Managed as Rocket state when `ROCKET_EXTERNAL_JWT_ISSUER` is set. Keys
+/// are cached for an hour and refetched early when a token names a key id
+/// that isn't cached, which is how issuers roll keys. Only asymmetric
+/// algorithms are accepted.
+pub struct ExternalIssuer {
+    /// Accepted `iss` values, which may contain `TENANT_PLACEHOLDER`; the first one namespaces linked identities
+    issuers: Vec<String>,
+    /// Accepted `aud` values
+    audiences: Vec<String>,
+    jwks_url: String,
+    client: reqwest::Client,
+    cache: RwLock<KeyCache>,
+}
+
+impl ExternalIssuer {
+    pub fn new(config: ExternalJwtConfig) -> Self {
+        ExternalIssuer::with_issuers(vec![config.issuer], vec![config.audience], config.jwks_url)
+    }
+
+    /// An issuer known by several `iss` values, accepting tokens for any of `audiences`
+    pub fn with_issuers(issuers: Vec<String>, audiences: Vec<String>, jwks_url: String) -> Self {
+        ExternalIssuer {
+            issuers,
+            audiences,
+            jwks_url,
+            client: reqwest::Client::new(),
+            cache: RwLock::new(KeyCache::default()),
+        }
+    }
+
+    /// The configured `iss`, used as the namespace for linked identities
+    pub fn issuer(&self) -> &str {
+        &self.issuers[0]
+    }
+
+    /// Whether a token claims to come from this issuer (before any verification)
+    pub fn issued(&self, token: &str) -> bool {
+        unverified::<IssuerClaim>(token)
+            .is_some_and(|claims| claims.iss.as_ref().is_some_and(|iss| self.accepted_issuers(&claims).contains(iss)))
+    }
+
+    /// The configured issuers, with the token's tenant filled in where needed
+    fn accepted_issuers(&self, claims: &IssuerClaim) -> Vec<String> {
+        self.issuers
+            .iter()
+            .filter_map(|iss| match (iss.contains(TENANT_PLACEHOLDER), &claims.tid) {
+                (false, _) => Some(iss.clone()),
+                (true, Some(tid)) => Some(iss.replace(TENANT_PLACEHOLDER, tid)),
+                (true, None) => None,
+            })
+            .collect()
+    }
+
+    /// Verify a token's signature, expiry, issuer and audience
+    pub async fn verify(&self, token: &str) -> Result<ExternalClaims, ExternalTokenError> {
+        let header = decode_header(token).map_err(|e| ExternalTokenError::Invalid(e.to_string()))?;
+        if matches!(header.alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+            return Err(ExternalTokenError::Invalid("symmetric algorithms are not accepted".to_string()));
+        }
+        let kid = header
+            .kid
+            .ok_or_else(|| ExternalTokenError::Invalid("token has no key id".to_string()))?;
+
+        let key = self.key(&kid).await?;
+
+        // The signature covers `tid` too, so a tenant filled in here can't be forged
+        let issuers = unverified::<IssuerClaim>(token)
+            .map(|claims| self.accepted_issuers(&claims))
+            .unwrap_or_default();
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&issuers);
+        validation.set_audience(&self.audiences);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        decode::<ExternalClaims>(token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(|e| ExternalTokenError::Invalid(e.to_string()))
+    }
+
+    /// The signing key with this id, fetching the key set when needed
+    async fn key(&self, kid: &str) -> Result<DecodingKey, ExternalTokenError> {
+        {
+            let cache = self.cache.read().await;
+            let fresh = cache.fetched_at.is_some_and(|at| at.elapsed() < JWKS_TTL);
+            match cache.keys.get(kid) {
+                Some(key) if fresh => return Ok(key.clone()),
+                None if cache.fetched_at.is_some_and(|at| at.elapsed() < JWKS_MIN_REFRESH) => {
+                    return Err(ExternalTokenError::Invalid(format!("unknown key id '{}'", kid)));
+                }
+                _ => {}
+            }
+        }
+
+        let mut cache = self.cache.write().await;
+        // Another request may have refreshed the keys while we waited
+        if cache.fetched_at.is_none_or(|at| at.elapsed() >= JWKS_MIN_REFRESH) {
+            cache.keys = self.fetch_keys().await?;
+            cache.fetched_at = Some(Instant::now());
+        }
+        cache
+            .keys
+            .get(kid)
+            .cloned()
+            .ok_or_else(|| ExternalTokenError::Invalid(format!("unknown key id '{}'", kid)))
+    }
+
+    async fn fetch_keys(&self) -> Result<HashMap<String, DecodingKey>, ExternalTokenError> {
+        let jwks: JwkSet = self
+            .client
+            .get(&self.jwks_url)
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| ExternalTokenError::Jwks(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| ExternalTokenError::Jwks(e.to_string()))?;
+
+        // Keys without an id or of an unsupported type are skipped
+        Ok(jwks
+            .keys
+            .iter()
+            .filter_map(|jwk| {
+                let kid = jwk.common.key_id.clone()?;
+                let key = DecodingKey::from_jwk(jwk).ok()?;
+                Some((kid, key))
+            })
+            .collect())
+    }
+}
+
+/// Every claim of a token `verify` accepted, for reading claims `ExternalClaims` leaves out (like `groups`)
+pub fn all_claims(token: &str) -> Value {
+    unverified(token).unwrap_or(Value::Null)
+}
+
+/// A token's claims, without checking anything
+fn unverified<T: DeserializeOwned>(token: &str) -> Option<T> {
+    let mut validation = Validation::new(Algorithm::RS256);
+    validation.insecure_disable_signature_validation();
+    validation.validate_exp = false;
+    validation.validate_aud = false;
+    validation.required_spec_claims.clear();
+
+    decode::<T>(token, &DecodingKey::from_secret(&[]), &validation)
+        .ok()
+        .map(|data| data.claims)
+}
+
+/// The local user for a verified external subject, provisioning and linking one on first sight
+///
+/// `issuer` namespaces the subject in `external_identities`. A verified email links to the existing account with that address. An
+/// unverified email that's already registered is refused, so an issuer that
+/// lets anyone claim any address can't take over local accounts. Subjects
+/// without an email get a placeholder address. Without `allow_signup`
+/// only existing users are linked.
+pub async fn local_user(
+    conn: &mut PgConnection,
+    passwords: &PasswordHasher,
+    issuer: &str,
+    claims: &ExternalClaims,
+    allow_signup: bool,
+) -> Result<Uuid, ProvisionError> {
+    if let Some(user_id) = external_identities::find_user_id(conn, issuer, &claims.sub).await? {
+        return Ok(user_id);
+    }
+
+    let existing = match claims.verified_email() {
+        Some(email) => users::find_by_email(conn, email).await?.map(|user| user.id),
+        None => None,
+    };
+
+    let user_id = match existing {
+        Some(user_id) => user_id,
+        None if !allow_signup => return Err(ProvisionError::SignupClosed),
+        None => {
+            let email = match claims.email.as_deref() {
+                Some(email) => email.to_string(),
+                None => format!("external-{}@external.invalid", Uuid::new_v4()),
+            };
+            if users::email_exists(conn, &email).await? {
+                return Err(ProvisionError::EmailTaken);
+            }
+
+            // Random password nobody knows; the issuer handles sign-in
+            let password_hash = passwords
+                .hash(&Uuid::new_v4().to_string())
+                .await
+                .map_err(ProvisionError::Password)?;
+            let user = users::create(conn, &email, &password_hash, "user").await?;
+            if claims.verified_email().is_some() {
+                users::mark_email_verified(conn, user.id).await?;
+            }
+            user.id
+        }
+    };
+
+    let linked = external_identities::link(conn, issuer, &claims.sub, user_id).await?;
+    // Another request provisioned this subject first; drop our copy if we made one
+    if linked != user_id
+        && existing.is_none()
+        && let Err(e) = users::delete(conn, user_id).await
+    {
+        eprintln!("Database error: {}", e);
+    }
+    Ok(linked)
+}
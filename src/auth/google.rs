@@ -0,0 +1,55 @@
+use std::ops::Deref;
+
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+
+use crate::auth::external::ExternalIssuer;
+use crate::config::AppConfig;
+
+/// `iss` values Google puts in ID tokens; the first namespaces linked identities
+const GOOGLE_ISSUERS: [&str; 2] = ["https://accounts.google.com", "accounts.google.com"];
+
+const GOOGLE_JWKS_URL: &str = "https://www.googleapis.com/oauth2/v3/certs";
+
+/// Verifies Google ID tokens (from One Tap, Sign in with Google or the mobile SDKs)
+///
+/// Managed as Rocket state when `ROCKET_GOOGLE_CLIENT_IDS` is set. Tokens
+/// must be issued to one of those client ids. Unlike `ExternalIssuer` on its
+/// own, these tokens are never accepted as bearer tokens; they are exchanged
+/// once for a session of this API.
+pub struct GoogleIdTokens(ExternalIssuer);
+
+impl GoogleIdTokens {
+    pub fn new(client_ids: Vec<String>) -> Self {
+        GoogleIdTokens(ExternalIssuer::with_issuers(
+            GOOGLE_ISSUERS.iter().map(|iss| iss.to_string()).collect(),
+            client_ids,
+            GOOGLE_JWKS_URL.to_string(),
+        ))
+    }
+
+    pub fn from_config(config: &AppConfig) -> Option<Self> {
+        (!config.google_client_ids.is_empty()).then(|| GoogleIdTokens::new(config.google_client_ids.clone()))
+    }
+}
+
+impl Deref for GoogleIdTokens {
+    type Target = ExternalIssuer;
+
+    fn deref(&self) -> &ExternalIssuer {
+        &self.0
+    }
+}
+
+/// Request guard for the Google sign-in endpoint; forwards to a 404 when it isn't configured
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for &'r GoogleIdTokens {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.rocket().state::<GoogleIdTokens>() {
+            Some(google) => Outcome::Success(google),
+            None => Outcome::Forward(Status::NotFound),
+        }
+    }
+}
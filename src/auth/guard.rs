@@ -1,12 +1,44 @@
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::time::Duration;
+
 use rocket::request::{FromRequest, Request, Outcome};
 use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket_db_pools::Connection;
+use chrono::Utc;
+use sqlx::PgConnection;
+use uuid::Uuid;
+use crate::auth::access_tokens::{self, AccessTokens};
+use crate::auth::api_key;
+use crate::auth::external::{self, ExternalIssuer, ExternalTokenError, ProvisionError};
+use crate::auth::ip_range::IpRange;
 use crate::auth::jwt::JwtService;
+use crate::auth::org_keys;
+use crate::auth::signer::SignerError;
+use crate::auth::mfa;
+use crate::auth::password::PasswordHasher;
+use crate::auth::permissions::Permission;
+use crate::auth::plans::Plan;
+use crate::auth::scopes::Scope;
+use crate::auth::token_versions::TokenVersions;
+use crate::cache::SharedCache;
+use crate::config::{AppConfig, VerificationMode};
+use crate::errors::ErrorResponse;
+use crate::authz::{Authz, Resource};
+use crate::models::user::AccountStanding;
+use crate::rate_limit::{self, ApiKeyQuota};
+use crate::request_log::record_user;
+use crate::repositories::{api_keys, plans, users};
+use crate::settings::Settings;
+use crate::travel;
+use crate::Postgres;
 
 /// Request guard for authenticated users
 /// Use this in route handlers to protect routes that require authentication
-/// 
+///
 /// Example:
-/// ```rust
+/// ```rust,ignore
 /// #[get("/protected")]
 /// fn protected_route(user: AuthenticatedUser) -> String {
 ///     format!("Hello, user {}!", user.user_id)
@@ -14,6 +46,22 @@ use crate::auth::jwt::JwtService;
 /// ```
 pub struct AuthenticatedUser {
     pub user_id: String,
+    /// Session behind the token, or the key id for API keys
+    pub session_id: String,
+    /// Token belongs to an anonymous guest account
+    pub guest: bool,
+    /// Scopes the credential (an API key or an OAuth client's token) is limited to; `None` for unrestricted session tokens
+    pub scopes: Option<Vec<String>>,
+}
+
+impl AuthenticatedUser {
+    /// Whether the credential may act with `scope`
+    pub fn has_scope(&self, scope: &str) -> bool {
+        match &self.scopes {
+            Some(scopes) => scopes.iter().any(|granted| granted == scope),
+            None => true,
+        }
+    }
 }
 
 #[rocket::async_trait]
@@ -21,30 +69,842 @@ impl<'r> FromRequest<'r> for AuthenticatedUser {
     type Error = ();
 
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        // Get the Authorization header
-        let auth_header = request.headers().get_one("Authorization");
-        
-        match auth_header {
-            Some(header) => {
-                // Check if it starts with "Bearer "
-                if !header.starts_with("Bearer ") {
+        match authenticate(request).await {
+            // Only the endpoints for the pending step are open to these users
+            Outcome::Success((_, Some(step))) => forbid(request, step.reason()),
+            // Scoped credentials only reach endpoints that ask for a scope
+            Outcome::Success((user, None)) if user.scopes.is_some() => {
+                forbid(request, ForbiddenReason::ScopedCredential)
+            }
+            Outcome::Success((user, None)) => Outcome::Success(user),
+            Outcome::Error(e) => Outcome::Error(e),
+            Outcome::Forward(status) => Outcome::Forward(status),
+        }
+    }
+}
+
+/// Request guard for endpoints that API keys may call with scope `S`
+///
+/// Session tokens always pass; API keys need `S` among their scopes and get
+/// 403 naming the scope otherwise. Derefs to `AuthenticatedUser`.
+///
+/// Example:
+/// ```rust,ignore
+/// #[get("/me")]
+/// fn me(user: Scoped<UsersRead>) -> String {
+///     format!("Hello, user {}!", user.user_id)
+/// }
+/// ```
+pub struct Scoped<S: Scope> {
+    user: AuthenticatedUser,
+    scope: PhantomData<S>,
+}
+
+impl<S: Scope> Deref for Scoped<S> {
+    type Target = AuthenticatedUser;
+
+    fn deref(&self) -> &AuthenticatedUser {
+        &self.user
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, S: Scope> FromRequest<'r> for Scoped<S> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match authenticate(request).await {
+            Outcome::Success((_, Some(step))) => forbid(request, step.reason()),
+            Outcome::Success((user, None)) if !user.has_scope(S::NAME) => {
+                forbid(request, ForbiddenReason::MissingScope(S::NAME))
+            }
+            Outcome::Success((user, None)) => Outcome::Success(Scoped {
+                user,
+                scope: PhantomData,
+            }),
+            Outcome::Error(e) => Outcome::Error(e),
+            Outcome::Forward(status) => Outcome::Forward(status),
+        }
+    }
+}
+
+/// Request guard that also admits users who must change their password
+///
+/// Only use this for the change-password endpoint; everything else should
+/// take `AuthenticatedUser`, which refuses those users with 403.
+pub struct PasswordChangeUser {
+    pub user_id: String,
+    pub session_id: String,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for PasswordChangeUser {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match authenticate(request).await {
+            Outcome::Success((user, _)) => Outcome::Success(PasswordChangeUser {
+                user_id: user.user_id,
+                session_id: user.session_id,
+            }),
+            Outcome::Error(e) => Outcome::Error(e),
+            Outcome::Forward(status) => Outcome::Forward(status),
+        }
+    }
+}
+
+/// Request guard for the MFA enrollment endpoints
+///
+/// Also admits users whose MFA grace period is over, who can use nothing
+/// else until they enroll. Refuses API keys and OAuth clients' tokens.
+pub struct MfaEnrollmentUser {
+    pub user_id: String,
+    pub session_id: String,
+    pub guest: bool,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for MfaEnrollmentUser {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match authenticate(request).await {
+            Outcome::Success((_, Some(PendingStep::PasswordChange))) => {
+                forbid(request, ForbiddenReason::PasswordChangeRequired)
+            }
+            Outcome::Success((user, _)) if user.scopes.is_some() => forbid(request, ForbiddenReason::ScopedCredential),
+            Outcome::Success((user, _)) => Outcome::Success(MfaEnrollmentUser {
+                user_id: user.user_id,
+                session_id: user.session_id,
+                guest: user.guest,
+            }),
+            Outcome::Error(e) => Outcome::Error(e),
+            Outcome::Forward(status) => Outcome::Forward(status),
+        }
+    }
+}
+
+/// Request guard that checks a session token in full, whatever the verification mode
+///
+/// For renewing tokens: under stateless verification the other guards trust
+/// a token's claims, so this is where revoked sessions and stale token
+/// versions are caught. Admits users with a pending step, whose new token
+/// carries it on; refuses API keys and OAuth clients' tokens.
+pub struct VerifiedSession {
+    pub user_id: String,
+    pub session_id: String,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for VerifiedSession {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match verify(request, false).await {
+            Outcome::Success((user, _)) if user.scopes.is_some() => forbid(request, ForbiddenReason::ScopedCredential),
+            Outcome::Success((user, _)) => Outcome::Success(VerifiedSession {
+                user_id: user.user_id,
+                session_id: user.session_id,
+            }),
+            Outcome::Error(e) => Outcome::Error(e),
+            Outcome::Forward(status) => Outcome::Forward(status),
+        }
+    }
+}
+
+/// Something a user has to do before the rest of the API opens up again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PendingStep {
+    /// An admin requires a new password
+    PasswordChange,
+    /// MFA is required for the user's role and the grace period is over
+    MfaEnrollment,
+}
+
+impl PendingStep {
+    fn reason(self) -> ForbiddenReason {
+        match self {
+            PendingStep::PasswordChange => ForbiddenReason::PasswordChangeRequired,
+            PendingStep::MfaEnrollment => ForbiddenReason::MfaEnrollmentRequired,
+        }
+    }
+}
+
+/// The step the user must complete first, if any
+///
+/// Read on every request so changes apply at once, unless
+/// `ROCKET_USER_CACHE_SECONDS` lets the standing be cached for a while.
+async fn pending_step(
+    request: &Request<'_>,
+    conn: &mut PgConnection,
+    user_id: Uuid,
+) -> Result<Option<PendingStep>, sqlx::Error> {
+    let ttl = request
+        .rocket()
+        .state::<AppConfig>()
+        .map_or(Duration::ZERO, |config| Duration::from_secs(config.user_cache_seconds));
+    let cache = request.rocket().state::<SharedCache>().filter(|_| !ttl.is_zero());
+    let key = format!("account_standing:{}", user_id);
+
+    let cached = match cache {
+        Some(cache) => cache.get::<AccountStanding>(&key).await,
+        None => None,
+    };
+    let standing = match cached {
+        Some(standing) => Some(standing),
+        None => {
+            let standing = users::standing(conn, user_id).await?;
+            if let (Some(cache), Some(standing)) = (cache, &standing) {
+                cache.set(&key, standing, ttl).await;
+            }
+            standing
+        }
+    };
+    Ok(standing.and_then(|standing| pending_for(request, &standing)))
+}
+
+/// The step a user in `standing` must complete first, if any
+fn pending_for(request: &Request<'_>, standing: &AccountStanding) -> Option<PendingStep> {
+    if standing.must_change_password {
+        return Some(PendingStep::PasswordChange);
+    }
+
+    let enrollment_overdue = match (request.rocket().state::<Settings>(), standing.mfa_required_since) {
+        (Some(settings), Some(required_since)) => {
+            let config = settings.current();
+            standing.totp_enabled_at.is_none()
+                && mfa::required_for(&config, &standing.role)
+                && mfa::enrollment_deadline(&config, required_since) <= Utc::now()
+        }
+        _ => false,
+    };
+    enrollment_overdue.then_some(PendingStep::MfaEnrollment)
+}
+
+/// Verify the bearer token as configured; also reports a step the user must complete first
+async fn authenticate(request: &Request<'_>) -> Outcome<(AuthenticatedUser, Option<PendingStep>), ()> {
+    let stateless = matches!(
+        request.rocket().state::<AppConfig>().map(|config| config.verification_mode),
+        Some(VerificationMode::Stateless { .. })
+    );
+    verify(request, stateless).await
+}
+
+/// Verify the bearer token, and its session unless `trust_claims` lets a session JWT's claims stand in for it
+async fn verify(
+    request: &Request<'_>,
+    trust_claims: bool,
+) -> Outcome<(AuthenticatedUser, Option<PendingStep>), ()> {
+    // Get the Authorization header
+    let auth_header = request.headers().get_one("Authorization");
+
+    match auth_header {
+        Some(header) => {
+            // Check if it starts with "Bearer "
+            if !header.starts_with("Bearer ") {
+                return Outcome::Error((Status::Unauthorized, ()));
+            }
+
+            // Extract the token
+            let token = &header[7..]; // Skip "Bearer "
+
+            if api_key::is_api_key(token) {
+                return authenticate_api_key(request, token).await;
+            }
+
+            if access_tokens::is_opaque_token(token) {
+                return authenticate_opaque(request, token).await;
+            }
+
+            if let Some(external) = request.rocket().state::<ExternalIssuer>()
+                && external.issued(token)
+            {
+                return authenticate_external(request, external, token).await;
+            }
+
+            let jwt = match request.rocket().state::<JwtService>() {
+                Some(jwt) => jwt,
+                None => return Outcome::Error((Status::InternalServerError, ())),
+            };
+
+            // Verify the token; action tokens only confirm their action, they don't sign in
+            let verified = if org_keys::kid(token).is_some() {
+                // Signed with an organization's key, which lives in the database
+                let mut db = match request.guard::<Connection<Postgres>>().await {
+                    Outcome::Success(db) => db,
+                    _ => return Outcome::Error((Status::InternalServerError, ())),
+                };
+                match jwt.verify_session(&mut db, token).await {
+                    Err(SignerError::Backend(e)) => {
+                        eprintln!("Database error: {}", e);
+                        return Outcome::Error((Status::InternalServerError, ()));
+                    }
+                    verified => verified,
+                }
+            } else {
+                jwt.verify_token(token).await
+            };
+            let claims = match verified {
+                Ok(claims) if claims.act.is_none() => claims,
+                _ => return Outcome::Error((Status::Unauthorized, ())),
+            };
+
+            let (user_id, session_id) = match (Uuid::parse_str(&claims.sub), Uuid::parse_str(&claims.sid)) {
+                (Ok(user_id), Ok(session_id)) => (user_id, session_id),
+                _ => return Outcome::Error((Status::Unauthorized, ())),
+            };
+
+            // Stateless verification: the signature and expiry are all there is to check. Tokens
+            // issued before it was enabled carry no standing and fall through to the full check
+            if trust_claims && let Some(standing) = &claims.standing {
+                if !for_this_service(request, claims.aud.as_deref()) {
                     return Outcome::Error((Status::Unauthorized, ()));
                 }
-                
-                // Extract the token
-                let token = &header[7..]; // Skip "Bearer "
-                
-                // Verify the token
-                match JwtService::verify_token(token) {
-                    Ok(claims) => {
-                        Outcome::Success(AuthenticatedUser {
-                            user_id: claims.sub,
-                        })
-                    }
-                    Err(_) => Outcome::Error((Status::Unauthorized, ())),
+                let pending = pending_for(request, standing);
+                let user = AuthenticatedUser {
+                    user_id: claims.sub,
+                    session_id: claims.sid,
+                    guest: claims.guest,
+                    scopes: claims.scope,
+                };
+                record_user(request, &user.user_id);
+                return Outcome::Success((user, pending));
+            }
+
+            // Reject tokens whose session has been revoked or has expired
+            let mut db = match request.guard::<Connection<Postgres>>().await {
+                Outcome::Success(db) => db,
+                _ => return Outcome::Error((Status::InternalServerError, ())),
+            };
+
+            let access_tokens = match request.rocket().state::<AccessTokens>() {
+                Some(access_tokens) => access_tokens,
+                None => return Outcome::Error((Status::InternalServerError, ())),
+            };
+
+            let session = match access_tokens.find_session(&mut db, session_id, user_id).await {
+                // Tokens exchanged for another service are only good there
+                Ok(Some(session)) if !for_this_service(request, session.audience.as_deref()) => {
+                    return Outcome::Error((Status::Unauthorized, ()));
+                }
+                Ok(Some(session)) => session,
+                Ok(None) => return Outcome::Error((Status::Unauthorized, ())),
+                Err(e) => {
+                    eprintln!("Database error: {}", e);
+                    return Outcome::Error((Status::InternalServerError, ()));
+                }
+            };
+
+            // A session used from two countries too close together may have been stolen
+            match travel::check(request, &mut db, user_id, session_id).await {
+                Ok(false) => {}
+                Ok(true) => return Outcome::Error((Status::Unauthorized, ())),
+                Err(e) => {
+                    eprintln!("Database error: {}", e);
+                    return Outcome::Error((Status::InternalServerError, ()));
+                }
+            }
+
+            // Signing out everywhere or changing the password bumps the version, killing older tokens
+            let token_versions = match request.rocket().state::<TokenVersions>() {
+                Some(token_versions) => token_versions,
+                None => return Outcome::Error((Status::InternalServerError, ())),
+            };
+            match token_versions.current(&mut db, user_id).await {
+                Ok(Some(version)) if version == claims.ver => {}
+                Ok(_) => return Outcome::Error((Status::Unauthorized, ())),
+                Err(e) => {
+                    eprintln!("Database error: {}", e);
+                    return Outcome::Error((Status::InternalServerError, ()));
                 }
             }
-            None => Outcome::Error((Status::Unauthorized, ())),
+
+            let pending = match pending_step(request, &mut db, user_id).await {
+                Ok(pending) => pending,
+                Err(e) => {
+                    eprintln!("Database error: {}", e);
+                    return Outcome::Error((Status::InternalServerError, ()));
+                }
+            };
+
+            let user = AuthenticatedUser {
+                user_id: claims.sub,
+                session_id: claims.sid,
+                guest: claims.guest,
+                // OAuth clients' sessions are limited to the scopes the user granted
+                scopes: session.scopes,
+            };
+            record_user(request, &user.user_id);
+            Outcome::Success((user, pending))
+        }
+        None => Outcome::Error((Status::Unauthorized, ())),
+    }
+}
+
+/// Whether a credential bound to `audience` may be used here
+///
+/// Unbound credentials work everywhere; bound ones only where
+/// `ROCKET_SERVICE_AUDIENCE` names their audience.
+fn for_this_service(request: &Request<'_>, audience: Option<&str>) -> bool {
+    let service = request
+        .rocket()
+        .state::<AppConfig>()
+        .and_then(|config| config.service_audience.as_deref());
+    audience.is_none() || audience == service
+}
+
+/// Look up an API key and count the request against its quotas; keys act for their owner with the key's scopes
+async fn authenticate_api_key(request: &Request<'_>, key: &str) -> Outcome<(AuthenticatedUser, Option<PendingStep>), ()> {
+    let mut db = match request.guard::<Connection<Postgres>>().await {
+        Outcome::Success(db) => db,
+        _ => return Outcome::Error((Status::InternalServerError, ())),
+    };
+
+    let key = match api_keys::use_key(&mut db, &api_key::hash(key)).await {
+        Ok(Some(key)) => key,
+        Ok(None) => return Outcome::Error((Status::Unauthorized, ())),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Outcome::Error((Status::InternalServerError, ()));
+        }
+    };
+
+    if !for_this_service(request, key.audience.as_deref()) {
+        return Outcome::Error((Status::Unauthorized, ()));
+    }
+    if !key.allowed_ips.is_empty() {
+        let allowed = request.client_ip().is_some_and(|ip| {
+            key.allowed_ips
+                .iter()
+                .filter_map(|range| range.parse::<IpRange>().ok())
+                .any(|range| range.contains(ip))
+        });
+        if !allowed {
+            return forbid(request, ForbiddenReason::AddressNotAllowed);
+        }
+    }
+
+    if let Some(quota) = request.rocket().state::<ApiKeyQuota>() {
+        match quota.consume(&mut db, key.id).await {
+            Ok(Some(exceeded)) => {
+                return rate_limit::refuse(request, exceeded, "API key quota exceeded", "quota_exceeded");
+            }
+            Ok(None) => {}
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                return Outcome::Error((Status::InternalServerError, ()));
+            }
+        }
+    }
+
+    let pending = match pending_step(request, &mut db, key.user_id).await {
+        Ok(pending) => pending,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Outcome::Error((Status::InternalServerError, ()));
+        }
+    };
+
+    let user = AuthenticatedUser {
+        user_id: key.user_id.to_string(),
+        session_id: key.id.to_string(),
+        guest: false,
+        scopes: Some(key.scopes),
+    };
+    record_user(request, &user.user_id);
+    Outcome::Success((user, pending))
+}
+
+/// Look up an opaque access token; it's valid exactly as long as its session
+async fn authenticate_opaque(request: &Request<'_>, token: &str) -> Outcome<(AuthenticatedUser, Option<PendingStep>), ()> {
+    let access_tokens = match request.rocket().state::<AccessTokens>() {
+        Some(access_tokens) => access_tokens,
+        None => return Outcome::Error((Status::InternalServerError, ())),
+    };
+
+    let mut db = match request.guard::<Connection<Postgres>>().await {
+        Outcome::Success(db) => db,
+        _ => return Outcome::Error((Status::InternalServerError, ())),
+    };
+
+    let session = match access_tokens.resolve(&mut db, token).await {
+        Ok(Some(session)) => session,
+        Ok(None) => return Outcome::Error((Status::Unauthorized, ())),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Outcome::Error((Status::InternalServerError, ()));
+        }
+    };
+
+    match travel::check(request, &mut db, session.user_id, session.session_id).await {
+        Ok(false) => {}
+        Ok(true) => return Outcome::Error((Status::Unauthorized, ())),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Outcome::Error((Status::InternalServerError, ()));
+        }
+    }
+
+    let pending = match pending_step(request, &mut db, session.user_id).await {
+        Ok(pending) => pending,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Outcome::Error((Status::InternalServerError, ()));
+        }
+    };
+
+    let user = AuthenticatedUser {
+        user_id: session.user_id.to_string(),
+        session_id: session.session_id.to_string(),
+        guest: session.guest,
+        scopes: session.scopes,
+    };
+    record_user(request, &user.user_id);
+    Outcome::Success((user, pending))
+}
+
+/// Verify a token from the external issuer, provisioning a local user the first time its subject is seen
+///
+/// External tokens aren't tied to a local session: `session_id` is empty,
+/// and signing out is up to the issuer (the token stays valid until it expires).
+async fn authenticate_external(
+    request: &Request<'_>,
+    external: &ExternalIssuer,
+    token: &str,
+) -> Outcome<(AuthenticatedUser, Option<PendingStep>), ()> {
+    let claims = match external.verify(token).await {
+        Ok(claims) => claims,
+        Err(ExternalTokenError::Invalid(_)) => return Outcome::Error((Status::Unauthorized, ())),
+        Err(e) => {
+            eprintln!("{}", e);
+            return Outcome::Error((Status::InternalServerError, ()));
+        }
+    };
+
+    let mut db = match request.guard::<Connection<Postgres>>().await {
+        Outcome::Success(db) => db,
+        _ => return Outcome::Error((Status::InternalServerError, ())),
+    };
+    let passwords = match request.rocket().state::<PasswordHasher>() {
+        Some(passwords) => passwords,
+        None => return Outcome::Error((Status::InternalServerError, ())),
+    };
+
+    let user_id = match external::local_user(&mut db, passwords, external.issuer(), &claims, true).await {
+        Ok(user_id) => user_id,
+        Err(ProvisionError::EmailTaken) => return Outcome::Error((Status::Unauthorized, ())),
+        Err(e) => {
+            eprintln!("{}", e);
+            return Outcome::Error((Status::InternalServerError, ()));
+        }
+    };
+
+    let pending = match pending_step(request, &mut db, user_id).await {
+        Ok(pending) => pending,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Outcome::Error((Status::InternalServerError, ()));
+        }
+    };
+
+    let user = AuthenticatedUser {
+        user_id: user_id.to_string(),
+        session_id: String::new(),
+        guest: false,
+        scopes: None,
+    };
+    record_user(request, &user.user_id);
+    Outcome::Success((user, pending))
+}
+
+/// Why a guard refused a request with 403, cached on the request for the catcher
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum ForbiddenReason {
+    Unspecified,
+    PasswordChangeRequired,
+    /// MFA's grace period is over; only the enrollment endpoints are open
+    MfaEnrollmentRequired,
+    /// An API key on an endpoint that doesn't accept scoped credentials
+    ScopedCredential,
+    MissingScope(&'static str),
+    MissingPermission(&'static str),
+    /// The resource (named by kind) belongs to another user
+    NotOwner(&'static str),
+    /// A signed URL that was tampered with, expired or already used
+    InvalidSignedUrl,
+    /// An API key used from outside the address ranges it's bound to
+    AddressNotAllowed,
+    /// Not an owner or admin of the organization
+    NotOrgAdmin,
+    /// A member whose organization role doesn't grant the named permission
+    MissingOrgPermission(&'static str),
+    /// A tenant-scoped endpoint called by a user outside any organization
+    NoOrganization,
+    /// The user's plan (named) is lower than the endpoint requires
+    MissingPlan(&'static str),
+}
+
+pub(crate) fn forbid<T>(request: &Request<'_>, reason: ForbiddenReason) -> Outcome<T, ()> {
+    request.local_cache(|| reason);
+    Outcome::Error((Status::Forbidden, ()))
+}
+
+/// JSON body for 403 responses, naming the reason when a guard recorded one
+#[catch(403)]
+pub fn forbidden(request: &Request) -> Json<ErrorResponse> {
+    match *request.local_cache(|| ForbiddenReason::Unspecified) {
+        ForbiddenReason::PasswordChangeRequired => Json(
+            ErrorResponse::with_details(
+                "Password change required".to_string(),
+                "Change your password at /api/v1/auth/change-password to continue".to_string(),
+            )
+            .with_code("password_change_required"),
+        ),
+        ForbiddenReason::MfaEnrollmentRequired => Json(
+            ErrorResponse::with_details(
+                "MFA enrollment required".to_string(),
+                "Set up an authenticator at /api/v1/auth/mfa/totp/enroll to continue".to_string(),
+            )
+            .with_code("mfa_enrollment_required"),
+        ),
+        ForbiddenReason::ScopedCredential => Json(
+            ErrorResponse::with_details(
+                "Insufficient scope".to_string(),
+                "This endpoint can't be used with an API key".to_string(),
+            )
+            .with_code("insufficient_scope"),
+        ),
+        ForbiddenReason::MissingScope(scope) => Json(
+            ErrorResponse::with_details(
+                "Insufficient scope".to_string(),
+                format!("This endpoint requires the '{}' scope", scope),
+            )
+            .with_code("insufficient_scope"),
+        ),
+        ForbiddenReason::MissingPermission(permission) => Json(
+            ErrorResponse::with_details(
+                "Missing permission".to_string(),
+                format!("This endpoint requires the '{}' permission", permission),
+            )
+            .with_code("missing_permission"),
+        ),
+        ForbiddenReason::NotOwner(kind) => Json(
+            ErrorResponse::with_details(
+                "Not the owner".to_string(),
+                format!("This {} belongs to another user", kind),
+            )
+            .with_code("not_owner"),
+        ),
+        ForbiddenReason::InvalidSignedUrl => Json(
+            ErrorResponse::with_details(
+                "Invalid link".to_string(),
+                "This link is invalid, has expired or was already used; ask for a new one".to_string(),
+            )
+            .with_code("invalid_signed_url"),
+        ),
+        ForbiddenReason::AddressNotAllowed => Json(
+            ErrorResponse::with_details(
+                "Address not allowed".to_string(),
+                "This API key can't be used from your IP address".to_string(),
+            )
+            .with_code("ip_not_allowed"),
+        ),
+        ForbiddenReason::NotOrgAdmin => Json(
+            ErrorResponse::with_details(
+                "Not an organization admin".to_string(),
+                "Only the organization's owners and admins can do this".to_string(),
+            )
+            .with_code("not_org_admin"),
+        ),
+        ForbiddenReason::MissingOrgPermission(permission) => Json(
+            ErrorResponse::with_details(
+                "Missing organization permission".to_string(),
+                format!("Your organization role doesn't grant '{}'", permission),
+            )
+            .with_code("missing_org_permission"),
+        ),
+        ForbiddenReason::MissingPlan(plan) => Json(
+            ErrorResponse::with_details(
+                "Plan required".to_string(),
+                format!("This endpoint requires the '{}' plan or a higher one", plan),
+            )
+            .with_code("plan_required"),
+        ),
+        ForbiddenReason::NoOrganization => Json(
+            ErrorResponse::with_details(
+                "Not a member of an organization".to_string(),
+                "This is only available to members of an organization".to_string(),
+            )
+            .with_code("no_organization"),
+        ),
+        ForbiddenReason::Unspecified => Json(ErrorResponse::new("Forbidden".to_string())),
+    }
+}
+
+/// Request guard for users with the admin role
+///
+/// Fails with 401 when the request isn't authenticated and 403 when the
+/// user isn't an admin. The role is read from the database on each request,
+/// so demoting an admin takes effect immediately.
+pub struct AdminUser {
+    pub user_id: String,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminUser {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let user = match request.guard::<AuthenticatedUser>().await {
+            Outcome::Success(user) => user,
+            Outcome::Error(e) => return Outcome::Error(e),
+            Outcome::Forward(status) => return Outcome::Forward(status),
+        };
+
+        let user_id = match Uuid::parse_str(&user.user_id) {
+            Ok(id) => id,
+            Err(_) => return Outcome::Error((Status::Unauthorized, ())),
+        };
+
+        let mut db = match request.guard::<Connection<Postgres>>().await {
+            Outcome::Success(db) => db,
+            _ => return Outcome::Error((Status::InternalServerError, ())),
+        };
+
+        match users::find_by_id(&mut db, user_id).await {
+            Ok(Some(user)) if user.role == "admin" => Outcome::Success(AdminUser {
+                user_id: user.id.to_string(),
+            }),
+            Ok(_) => Outcome::Error((Status::Forbidden, ())),
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                Outcome::Error((Status::InternalServerError, ()))
+            }
+        }
+    }
+}
+
+/// Request guard for users with a real (non-guest) account
+///
+/// Fails with 401 when the request isn't authenticated and 403 for guest
+/// tokens. Upgrading a guest revokes its guest sessions, so the claim is
+/// never stale.
+pub struct RegisteredUser {
+    pub user_id: String,
+    pub session_id: String,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RegisteredUser {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.guard::<AuthenticatedUser>().await {
+            Outcome::Success(user) if user.guest => Outcome::Error((Status::Forbidden, ())),
+            Outcome::Success(user) => Outcome::Success(RegisteredUser {
+                user_id: user.user_id,
+                session_id: user.session_id,
+            }),
+            Outcome::Error(e) => Outcome::Error(e),
+            Outcome::Forward(status) => Outcome::Forward(status),
+        }
+    }
+}
+
+/// Request guard for users allowed to perform action `P`
+///
+/// Fails with 401 when the request isn't authenticated and 403 naming the
+/// permission otherwise. The decision comes from the configured
+/// `PolicyEngine` (by default: the role has the permission), evaluated on
+/// each request, so granting or revoking takes effect immediately.
+///
+/// Example:
+/// ```rust,ignore
+/// #[delete("/users/<id>")]
+/// fn delete_user(user: HasPermission<UsersDelete>, id: &str) -> String {
+///     format!("{} deletes {}", user.user_id, id)
+/// }
+/// ```
+pub struct HasPermission<P: Permission> {
+    pub user_id: String,
+    permission: PhantomData<P>,
+}
+
+#[rocket::async_trait]
+impl<'r, P: Permission> FromRequest<'r> for HasPermission<P> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let authz = match request.guard::<Authz>().await {
+            Outcome::Success(authz) => authz,
+            Outcome::Error(e) => return Outcome::Error(e),
+            Outcome::Forward(status) => return Outcome::Forward(status),
+        };
+
+        match authz.allows(P::NAME, &Resource::none()).await {
+            Ok(true) => Outcome::Success(HasPermission {
+                user_id: authz.subject.user_id.to_string(),
+                permission: PhantomData,
+            }),
+            Ok(false) => forbid(request, ForbiddenReason::MissingPermission(P::NAME)),
+            Err(e) => {
+                eprintln!("{}", e);
+                Outcome::Error((Status::InternalServerError, ()))
+            }
+        }
+    }
+}
+
+/// Request guard for users with plan `P` or a higher one
+///
+/// Fails with 401 when the request isn't authenticated and 403 (code
+/// `plan_required`) naming the plan otherwise. Entitlements of the user and
+/// of their organization count, read on each request, so upgrades and
+/// downgrades take effect without a new token.
+///
+/// Example:
+/// ```rust,ignore
+/// #[get("/reports")]
+/// fn reports(user: RequiresPlan<Pro>) -> String {
+///     format!("Reports for {}", user.user_id)
+/// }
+/// ```
+pub struct RequiresPlan<P: Plan> {
+    pub user_id: String,
+    plan: PhantomData<P>,
+}
+
+#[rocket::async_trait]
+impl<'r, P: Plan> FromRequest<'r> for RequiresPlan<P> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let user = match request.guard::<AuthenticatedUser>().await {
+            Outcome::Success(user) => user,
+            Outcome::Error(e) => return Outcome::Error(e),
+            Outcome::Forward(status) => return Outcome::Forward(status),
+        };
+
+        let user_id = match Uuid::parse_str(&user.user_id) {
+            Ok(id) => id,
+            Err(_) => return Outcome::Error((Status::Unauthorized, ())),
+        };
+
+        let mut db = match request.guard::<Connection<Postgres>>().await {
+            Outcome::Success(db) => db,
+            _ => return Outcome::Error((Status::InternalServerError, ())),
+        };
+
+        match plans::has_plan(&mut db, user_id, P::NAME).await {
+            Ok(true) => Outcome::Success(RequiresPlan {
+                user_id: user.user_id,
+                plan: PhantomData,
+            }),
+            Ok(false) => forbid(request, ForbiddenReason::MissingPlan(P::NAME)),
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                Outcome::Error((Status::InternalServerError, ()))
+            }
         }
     }
 }
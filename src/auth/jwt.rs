@@ -1,56 +1,267 @@
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgConnection;
+
+use crate::auth::org_keys;
+use crate::auth::paseto::PasetoSigner;
+use crate::auth::signer::{HmacSigner, SignerError, TokenSigner};
+use crate::config::{AppConfig, SignerConfig, VerificationMode};
+use crate::models::user::{AccountStanding, User};
+use crate::repositories::organizations;
+use uuid::Uuid;
+
+/// How long access tokens (and the sessions behind them) stay valid
+pub const TOKEN_LIFETIME_HOURS: i64 = 24;
+
+/// How long single-use action tokens stay valid
+pub const ACTION_TOKEN_LIFETIME_MINUTES: i64 = 60;
+
+/// How long the "this wasn't me" link in a password change notification stays valid
+pub const SECURE_ACCOUNT_TOKEN_LIFETIME_DAYS: i64 = 7;
+
+/// What a single-use action token confirms
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TokenAction {
+    /// Move the account to a new email address
+    ChangeEmail { email: String },
+    /// Delete the account
+    DeleteAccount,
+    /// Sign out everywhere and send a password reset, after a password change the user didn't make
+    SecureAccount,
+}
+
+impl TokenAction {
+    /// How long a token for this action stays valid
+    pub fn lifetime(&self) -> Duration {
+        match self {
+            TokenAction::SecureAccount => Duration::days(SECURE_ACCOUNT_TOKEN_LIFETIME_DAYS),
+            _ => Duration::minutes(ACTION_TOKEN_LIFETIME_MINUTES),
+        }
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String, // user id
+    pub sid: String, // session id
+    #[serde(default)]
+    pub jti: String, // token id, unique per token
     pub exp: usize,  // expiration time
     pub iat: usize,  // issued at
+    #[serde(default)]
+    pub ver: i32, // user's token_version when issued; older versions are refused
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub guest: bool, // anonymous guest account
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_metadata: Option<serde_json::Value>, // with ROCKET_JWT_APP_METADATA
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<TokenAction>, // single-use action token; never accepted for authentication
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>, // service an exchanged token is meant for; refused here unless it's ROCKET_SERVICE_AUDIENCE
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<Vec<String>>, // scopes an OAuth client's token is limited to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub standing: Option<AccountStanding>, // with stateless verification; trusted instead of reading the user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plan: Option<String>, // the user's highest plan when issued, for clients; RequiresPlan reads the database
 }
 
 impl Claims {
-    pub fn new(user_id: String) -> Self {
+    pub fn new(user_id: String, session_id: String) -> Self {
         let now = Utc::now();
-        let exp = now + Duration::hours(24); // Token expires in 24 hours
-        
+        let exp = now + Duration::hours(TOKEN_LIFETIME_HOURS);
+
         Claims {
             sub: user_id,
+            sid: session_id,
+            jti: Uuid::new_v4().to_string(),
             exp: exp.timestamp() as usize,
             iat: now.timestamp() as usize,
+            ver: 0,
+            guest: false,
+            app_metadata: None,
+            act: None,
+            aud: None,
+            scope: None,
+            standing: None,
+            plan: None,
         }
     }
+
+    /// Claims for a short-lived token confirming `action`, issued from one of the user's sessions
+    pub fn action(user_id: String, session_id: String, action: TokenAction) -> Self {
+        let mut claims = Claims::new(user_id, session_id);
+        claims.exp = (Utc::now() + action.lifetime()).timestamp() as usize;
+        claims.act = Some(action);
+        claims
+    }
+
+    /// Mark the token as belonging to a guest account
+    pub fn guest(mut self, guest: bool) -> Self {
+        self.guest = guest;
+        self
+    }
+
+    /// Embed the user's `app_metadata`
+    pub fn app_metadata(mut self, app_metadata: serde_json::Value) -> Self {
+        self.app_metadata = Some(app_metadata);
+        self
+    }
+
+    /// Embed the user's active plan
+    pub fn plan(mut self, plan: Option<String>) -> Self {
+        self.plan = plan;
+        self
+    }
 }
 
-pub struct JwtService;
+/// Issues and verifies JWTs (or PASETO tokens) using the configured signer
+///
+/// Managed as Rocket state; use `&State<JwtService>` in handlers.
+pub struct JwtService {
+    signer: Box<dyn TokenSigner>,
+    app_metadata_claim: bool,
+    verification: VerificationMode,
+    org_signing_keys: bool,
+}
 
 impl JwtService {
-    /// Generate a JWT token for a user
-    pub fn generate_token(user_id: String) -> Result<String, jsonwebtoken::errors::Error> {
-        let secret = std::env::var("ROCKET_JWT_SECRET")
-            .expect("ROCKET_JWT_SECRET must be set in .env file");
-        
-        let claims = Claims::new(user_id);
-        let token = encode(
-            &Header::default(),
-            &claims,
-            &EncodingKey::from_secret(secret.as_ref()),
-        )?;
-        
-        Ok(token)
+    pub fn new(signer: impl TokenSigner + 'static) -> Self {
+        JwtService {
+            signer: Box::new(signer),
+            app_metadata_claim: false,
+            verification: VerificationMode::Stateful,
+            org_signing_keys: false,
+        }
+    }
+
+    /// Embed each user's `app_metadata` in the tokens from `claims_for`
+    pub fn with_app_metadata_claim(mut self, enabled: bool) -> Self {
+        self.app_metadata_claim = enabled;
+        self
+    }
+
+    /// Shape the tokens from `claims_for` for how the guard verifies them
+    ///
+    /// Under stateless verification they're short-lived and carry the
+    /// user's account standing.
+    pub fn with_verification_mode(mut self, verification: VerificationMode) -> Self {
+        self.verification = verification;
+        self
+    }
+
+    /// Sign session tokens of organization members with their organization's key, when it has one
+    pub fn with_org_signing_keys(mut self, enabled: bool) -> Self {
+        self.org_signing_keys = enabled;
+        self
+    }
+
+    /// Build the service for the signer selected in configuration
+    pub async fn from_config(config: &AppConfig) -> Result<Self, SignerError> {
+        let service = match &config.jwt_signer {
+            SignerConfig::Hmac { secret } => JwtService::new(HmacSigner::new(secret)),
+            SignerConfig::Paseto { purpose, key } => JwtService::new(PasetoSigner::new(*purpose, key)?),
+            #[cfg(feature = "aws-kms")]
+            SignerConfig::Kms { key_id } => {
+                let signer = crate::auth::kms::KmsSigner::from_env(key_id.clone()).await?;
+                JwtService::new(signer)
+            }
+            #[cfg(not(feature = "aws-kms"))]
+            SignerConfig::Kms { key_id } => {
+                return Err(SignerError::Backend(format!(
+                    "KMS key '{}' configured, but signing with KMS requires building with the `aws-kms` feature",
+                    key_id
+                )));
+            }
+        };
+        Ok(service
+            .with_app_metadata_claim(config.app_metadata_claim)
+            .with_verification_mode(config.verification_mode)
+            .with_org_signing_keys(config.org_signing_keys))
+    }
+
+    /// Generate a JWT token for a user's session
+    pub async fn generate_token(&self, user_id: String, session_id: String) -> Result<String, SignerError> {
+        let claims = Claims::new(user_id, session_id);
+        self.signer.sign(&claims).await
+    }
+
+    /// Generate a single-use token confirming `action`
+    ///
+    /// Action tokens are emailed as links and redeemed once; their `jti` is
+    /// recorded when they're used (see `repositories::nonces`).
+    pub async fn generate_action_token(
+        &self,
+        user_id: String,
+        session_id: String,
+        action: TokenAction,
+    ) -> Result<String, SignerError> {
+        self.signer.sign(&Claims::action(user_id, session_id, action)).await
+    }
+
+    /// Claims for a new token bound to one of the user's sessions, which ends at `session_expires_at`
+    pub fn claims_for(&self, user: &User, session_id: Uuid, session_expires_at: DateTime<Utc>) -> Claims {
+        let mut claims = Claims::new(user.id.to_string(), session_id.to_string()).guest(user.is_guest());
+        claims.ver = user.token_version;
+        claims.exp = session_expires_at.timestamp() as usize;
+        if let VerificationMode::Stateless { token_minutes } = self.verification {
+            let expires_at = session_expires_at.min(Utc::now() + Duration::minutes(token_minutes as i64));
+            claims.exp = expires_at.timestamp() as usize;
+            claims.standing = Some(AccountStanding::from(user));
+        }
+        if self.app_metadata_claim {
+            claims.app_metadata(user.app_metadata.clone())
+        } else {
+            claims
+        }
+    }
+
+    /// Sign prepared claims
+    pub async fn sign(&self, claims: &Claims) -> Result<String, SignerError> {
+        self.signer.sign(claims).await
     }
 
     /// Verify and decode a JWT token
-    pub fn verify_token(token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
-        let secret = std::env::var("ROCKET_JWT_SECRET")
-            .expect("ROCKET_JWT_SECRET must be set in .env file");
-        
-        let token_data = decode::<Claims>(
-            token,
-            &DecodingKey::from_secret(secret.as_ref()),
-            &Validation::default(),
-        )?;
-        
-        Ok(token_data.claims)
+    pub async fn verify_token(&self, token: &str) -> Result<Claims, SignerError> {
+        self.signer.verify(token).await
+    }
+
+    /// Sign claims for a session token, with the user's organization key if it has one
+    ///
+    /// Tokens for another service (`aud`) and action tokens always use the
+    /// service's own signer.
+    pub async fn sign_session(&self, conn: &mut PgConnection, claims: &Claims) -> Result<String, SignerError> {
+        if self.org_signing_keys
+            && claims.aud.is_none()
+            && claims.act.is_none()
+            && let Ok(user_id) = Uuid::parse_str(&claims.sub)
+            && let Some(key) = organizations::signing_key_for_user(conn, user_id)
+                .await
+                .map_err(|e| SignerError::Backend(e.to_string()))?
+        {
+            return org_keys::sign(claims, &key);
+        }
+        self.sign(claims).await
+    }
+
+    /// Verify a session token, which may be signed with an organization's key
+    ///
+    /// An organization key only verifies tokens of that organization's
+    /// members, and stops as soon as it's revoked.
+    pub async fn verify_session(&self, conn: &mut PgConnection, token: &str) -> Result<Claims, SignerError> {
+        let Some(kid) = org_keys::kid(token) else {
+            return self.verify_token(token).await;
+        };
+        let key = match org_keys::unverified_subject(token) {
+            Some(user_id) => organizations::verifying_key(conn, &kid, user_id)
+                .await
+                .map_err(|e| SignerError::Backend(e.to_string()))?,
+            None => None,
+        };
+        match key {
+            Some(key) => org_keys::verify(token, &key),
+            None => Err(SignerError::Jwt(jsonwebtoken::errors::ErrorKind::InvalidSignature.into())),
+        }
     }
 }
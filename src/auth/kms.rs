@@ -0,0 +1,131 @@
+use aws_sdk_kms::primitives::Blob;
+use aws_sdk_kms::types::{MessageType, SigningAlgorithmSpec};
+use aws_sdk_kms::Client;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Header};
+use tokio::sync::OnceCell;
+
+use crate::auth::jwt::Claims;
+use crate::auth::signer::{self, SignerError, TokenSigner};
+
+/// Signs JWTs (RS256) with an asymmetric AWS KMS key
+///
+/// The private key never leaves KMS. The key must be an RSA key with
+/// `SIGN_VERIFY` usage. Verification happens locally against the public
+/// key, which is fetched from KMS once and cached.
+pub struct KmsSigner {
+    client: Client,
+    key_id: String,
+    public_key: OnceCell<DecodingKey>,
+}
+
+impl KmsSigner {
+    pub fn new(client: Client, key_id: String) -> Self {
+        KmsSigner {
+            client,
+            key_id,
+            public_key: OnceCell::new(),
+        }
+    }
+
+    /// Create a signer using credentials and region from the standard AWS environment
+    pub async fn from_env(key_id: String) -> Result<Self, SignerError> {
+        let sdk_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+        Ok(KmsSigner::new(Client::new(&sdk_config), key_id))
+    }
+
+    /// Fetch the public key from KMS on first use
+    async fn decoding_key(&self) -> Result<&DecodingKey, SignerError> {
+        self.public_key
+            .get_or_try_init(|| async {
+                let response = self
+                    .client
+                    .get_public_key()
+                    .key_id(&self.key_id)
+                    .send()
+                    .await
+                    .map_err(|e| SignerError::Backend(format!("GetPublicKey failed: {}", e)))?;
+
+                let der = response
+                    .public_key()
+                    .ok_or_else(|| SignerError::Backend("KMS returned no public key".to_string()))?;
+
+                // KMS returns a DER-encoded SubjectPublicKeyInfo; wrap it as PEM
+                let encoded = STANDARD.encode(der.as_ref());
+                let mut pem = String::from("-----BEGIN PUBLIC KEY-----\n");
+                for line in encoded.as_bytes().chunks(64) {
+                    pem.push_str(std::str::from_utf8(line).unwrap_or_default());
+                    pem.push('\n');
+                }
+                pem.push_str("-----END PUBLIC KEY-----\n");
+
+                Ok(DecodingKey::from_rsa_pem(pem.as_bytes())?)
+            })
+            .await
+    }
+}
+
+#[rocket::async_trait]
+impl TokenSigner for KmsSigner {
+    async fn sign(&self, claims: &Claims) -> Result<String, SignerError> {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(self.key_id.clone());
+
+        let header_json = serde_json::to_vec(&header)
+            .map_err(|e| SignerError::Backend(format!("Failed to encode header: {}", e)))?;
+        let claims_json = serde_json::to_vec(claims)
+            .map_err(|e| SignerError::Backend(format!("Failed to encode claims: {}", e)))?;
+
+        let signing_input = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(header_json),
+            URL_SAFE_NO_PAD.encode(claims_json)
+        );
+
+        let response = self
+            .client
+            .sign()
+            .key_id(&self.key_id)
+            .message(Blob::new(signing_input.as_bytes()))
+            .message_type(MessageType::Raw)
+            .signing_algorithm(SigningAlgorithmSpec::RsassaPkcs1V15Sha256)
+            .send()
+            .await
+            .map_err(|e| SignerError::Backend(format!("Sign failed: {}", e)))?;
+
+        let signature = response
+            .signature()
+            .ok_or_else(|| SignerError::Backend("KMS returned no signature".to_string()))?;
+
+        Ok(format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(signature.as_ref())))
+    }
+
+    async fn verify(&self, token: &str) -> Result<Claims, SignerError> {
+        let key = self.decoding_key().await?;
+        let token_data = decode::<Claims>(token, key, &signer::validation(Algorithm::RS256))?;
+        Ok(token_data.claims)
+    }
+}
+
+/// Decrypt a base64-encoded KMS ciphertext, e.g. a password pepper
+///
+/// The ciphertext records which key encrypted it, so no key id is needed.
+pub async fn decrypt(ciphertext: &str) -> Result<Vec<u8>, String> {
+    let blob = STANDARD
+        .decode(ciphertext)
+        .map_err(|e| format!("ciphertext is not valid base64: {}", e))?;
+
+    let sdk_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+    let response = Client::new(&sdk_config)
+        .decrypt()
+        .ciphertext_blob(Blob::new(blob))
+        .send()
+        .await
+        .map_err(|e| format!("Decrypt failed: {}", e))?;
+
+    response
+        .plaintext()
+        .map(|plaintext| plaintext.as_ref().to_vec())
+        .ok_or_else(|| "KMS returned no plaintext".to_string())
+}
@@ -0,0 +1,153 @@
+use base32::Alphabet;
+use chrono::{DateTime, TimeDelta, Utc};
+use hmac::{Hmac, Mac};
+use qrcode::render::svg;
+use qrcode::{Color, QrCode};
+use rocket::http::uri::fmt::{Query, UriDisplay};
+use sha1::Sha1;
+use uuid::Uuid;
+
+use crate::auth::tokens;
+use crate::config::AppConfig;
+
+/// Digits in a TOTP code
+pub const DIGITS: u32 = 6;
+
+/// Seconds each TOTP code is valid for
+pub const STEP_SECONDS: i64 = 30;
+
+/// How long the `mfa_token` from a password login can be redeemed with a code
+pub const CHALLENGE_TTL_SECONDS: i64 = 300;
+
+/// Wrong codes a login challenge takes before it's dropped
+pub const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+
+/// How long the emailed link confirming an MFA recovery works
+pub const RECOVERY_LINK_TTL_MINUTES: i64 = 60;
+
+const SECRET_ALPHABET: Alphabet = Alphabet::Rfc4648 { padding: false };
+
+/// A new random TOTP secret, base32 encoded as authenticator apps expect it
+pub fn generate_secret() -> String {
+    let mut bytes = Vec::with_capacity(32);
+    bytes.extend_from_slice(Uuid::new_v4().as_bytes());
+    bytes.extend_from_slice(Uuid::new_v4().as_bytes());
+    base32::encode(SECRET_ALPHABET, &bytes)
+}
+
+/// A new login challenge token and its hash; only the hash is stored
+pub fn generate_challenge() -> (String, String) {
+    let token = format!("mfa_{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let hash = tokens::hash(&token);
+    (token, hash)
+}
+
+/// A new six-digit login code to email and its hash; only the hash is stored
+pub fn generate_email_code() -> (String, String) {
+    let code = format!("{:06}", Uuid::new_v4().as_u128() % 1_000_000);
+    let hash = tokens::hash(&code);
+    (code, hash)
+}
+
+/// A new token for an MFA recovery confirmation link and its hash; only the hash is stored
+pub fn generate_recovery_token() -> (String, String) {
+    let token = format!("mfar_{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let hash = tokens::hash(&token);
+    (token, hash)
+}
+
+/// The `otpauth://` URI authenticator apps enroll from, usually shown as a QR code
+pub fn provisioning_uri(issuer: &str, account: &str, secret: &str) -> String {
+    let label = format!("{}:{}", issuer, account);
+    format!(
+        "otpauth://totp/{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        &label.as_str() as &dyn UriDisplay<Query>,
+        secret,
+        &issuer as &dyn UriDisplay<Query>,
+        DIGITS,
+        STEP_SECONDS
+    )
+}
+
+/// Pixels per QR module in PNG renderings
+const QR_MODULE_PIXELS: usize = 8;
+
+/// Light modules around the QR symbol, as the spec requires
+const QR_QUIET_ZONE: usize = 4;
+
+/// The provisioning URI as an SVG QR code
+pub fn qr_svg(uri: &str) -> Result<String, qrcode::types::QrError> {
+    let code = QrCode::new(uri)?;
+    Ok(code
+        .render::<svg::Color>()
+        .min_dimensions(256, 256)
+        .build())
+}
+
+/// The provisioning URI as a grayscale PNG QR code
+pub fn qr_png(uri: &str) -> Result<Vec<u8>, String> {
+    let code = QrCode::new(uri).map_err(|e| e.to_string())?;
+    let modules = code.width();
+    let colors = code.to_colors();
+
+    let size = (modules + 2 * QR_QUIET_ZONE) * QR_MODULE_PIXELS;
+    let mut pixels = vec![0xffu8; size * size];
+    for (index, _) in colors.iter().enumerate().filter(|(_, color)| **color == Color::Dark) {
+        let left = (index % modules + QR_QUIET_ZONE) * QR_MODULE_PIXELS;
+        let top = (index / modules + QR_QUIET_ZONE) * QR_MODULE_PIXELS;
+        for row in top..top + QR_MODULE_PIXELS {
+            pixels[row * size + left..row * size + left + QR_MODULE_PIXELS].fill(0);
+        }
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, size as u32, size as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(&pixels).map_err(|e| e.to_string())?;
+    writer.finish().map_err(|e| e.to_string())?;
+    Ok(png)
+}
+
+/// The time step a code was valid for, if it matches `secret` now (allowing one step of clock drift)
+///
+/// Callers must refuse steps that were already used, so a code can't be replayed.
+pub fn verify(secret: &str, code: &str, now: DateTime<Utc>) -> Option<i64> {
+    let key = base32::decode(SECRET_ALPHABET, secret)?;
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+
+    let current = now.timestamp() / STEP_SECONDS;
+    (current - 1..=current + 1).find(|step| hotp(&key, *step as u64) == code)
+}
+
+/// The code for a time step (RFC 4226 with the step as counter, per RFC 6238)
+pub fn code_at(secret: &str, at: DateTime<Utc>) -> Option<String> {
+    let key = base32::decode(SECRET_ALPHABET, secret)?;
+    let step = at.timestamp() / STEP_SECONDS;
+    Some(format!("{:0width$}", hotp(&key, step as u64), width = DIGITS as usize))
+}
+
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([digest[offset], digest[offset + 1], digest[offset + 2], digest[offset + 3]]) & 0x7fff_ffff;
+    value % 10u32.pow(DIGITS)
+}
+
+/// Whether users with `role` must use MFA
+pub fn required_for(config: &AppConfig, role: &str) -> bool {
+    config.mfa_required_roles.iter().any(|required| required == role)
+}
+
+/// When a user who has to use MFA since `required_since` must have enrolled by
+pub fn enrollment_deadline(config: &AppConfig, required_since: DateTime<Utc>) -> DateTime<Utc> {
+    required_since + TimeDelta::days(config.mfa_grace_days as i64)
+}
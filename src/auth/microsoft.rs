@@ -0,0 +1,110 @@
+use reqwest::Url;
+
+use crate::auth::external::{self, ExternalIssuer, TENANT_PLACEHOLDER};
+use crate::auth::oauth::{self, AuthorizationRequest, OAuthError, OAuthIdentity, OAuthProvider};
+use crate::config::MicrosoftConfig;
+
+const AUTHORITY: &str = "https://login.microsoftonline.com";
+
+/// Tenant of all personal Microsoft accounts, which isn't an organization
+const CONSUMER_TENANT: &str = "9188040d-6c67-4c5b-b112-36a304b66dad";
+
+/// Sign-in with Microsoft Entra ID (Azure AD) through OpenID Connect
+///
+/// Single-tenant apps only accept accounts from the configured directory.
+/// Multi-tenant apps (`organizations` or `common`) accept any directory;
+/// each directory's `tid` is then mapped to an organization here, and
+/// identities are namespaced per directory, as Microsoft's issuer is.
+pub struct MicrosoftProvider {
+    config: MicrosoftConfig,
+    id_tokens: ExternalIssuer,
+    client: reqwest::Client,
+}
+
+impl MicrosoftProvider {
+    pub fn new(config: MicrosoftConfig) -> Self {
+        let issuer = match config.is_multi_tenant() {
+            true => format!("{}/{}/v2.0", AUTHORITY, TENANT_PLACEHOLDER),
+            false => format!("{}/{}/v2.0", AUTHORITY, config.tenant),
+        };
+        let id_tokens = ExternalIssuer::with_issuers(
+            vec![issuer],
+            vec![config.client_id.clone()],
+            format!("{}/{}/discovery/v2.0/keys", AUTHORITY, config.tenant),
+        );
+
+        MicrosoftProvider {
+            config,
+            id_tokens,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    fn endpoint(&self, path: &str) -> String {
+        format!("{}/{}/oauth2/v2.0/{}", AUTHORITY, self.config.tenant, path)
+    }
+}
+
+#[rocket::async_trait]
+impl OAuthProvider for MicrosoftProvider {
+    fn authorization_url(&self, request: &AuthorizationRequest<'_>) -> String {
+        Url::parse_with_params(
+            &self.endpoint("authorize"),
+            &[
+                ("client_id", self.config.client_id.as_str()),
+                ("response_type", "code"),
+                ("response_mode", "query"),
+                ("redirect_uri", request.redirect_uri),
+                ("scope", "openid email profile"),
+                ("state", request.state),
+                ("nonce", request.nonce),
+                ("code_challenge", request.code_challenge),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .expect("authorization endpoint is a valid URL")
+        .to_string()
+    }
+
+    async fn exchange(
+        &self,
+        code: &str,
+        redirect_uri: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<OAuthIdentity, OAuthError> {
+        let tokens = oauth::request_token(
+            &self.client,
+            &self.endpoint("token"),
+            &[
+                ("client_id", self.config.client_id.as_str()),
+                ("client_secret", self.config.client_secret.as_str()),
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", redirect_uri),
+                ("code_verifier", code_verifier),
+            ],
+        )
+        .await?;
+
+        let id_token = tokens
+            .id_token
+            .ok_or_else(|| OAuthError::Provider("no ID token in the token response".to_string()))?;
+        let claims = self.id_tokens.verify(&id_token).await?;
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(OAuthError::Invalid("ID token nonce doesn't match".to_string()));
+        }
+
+        let tenant = match self.config.is_multi_tenant() {
+            true => claims.tid.clone().filter(|tid| tid != CONSUMER_TENANT),
+            false => None,
+        };
+        Ok(OAuthIdentity {
+            issuer: claims.iss.clone(),
+            claims,
+            tenant,
+            roles: None,
+            profile: external::all_claims(&id_token),
+        })
+    }
+}
@@ -1,2 +1,31 @@
 pub mod jwt;
 pub mod guard;
+pub mod api_key;
+pub mod scopes;
+pub mod permissions;
+pub mod signer;
+pub mod paseto;
+pub mod tokens;
+pub mod password;
+pub mod mfa;
+pub mod external;
+pub mod google;
+pub mod oauth;
+pub mod microsoft;
+pub mod discord;
+pub mod facebook;
+pub mod generic_oauth;
+pub mod oidc;
+pub mod oauth_clients;
+pub mod token_exchange;
+pub mod access_tokens;
+pub mod token_versions;
+pub mod email_tokens;
+pub mod signed_urls;
+pub mod ip_range;
+pub mod org_keys;
+pub mod org_permissions;
+pub mod sso;
+pub mod plans;
+#[cfg(feature = "aws-kms")]
+pub mod kms;
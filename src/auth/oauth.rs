@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::auth::external::{ExternalClaims, ExternalTokenError};
+use crate::auth::discord::DiscordProvider;
+use crate::auth::facebook::FacebookProvider;
+use crate::auth::generic_oauth::GenericProvider;
+use crate::auth::microsoft::MicrosoftProvider;
+use crate::config::AppConfig;
+
+#[derive(Debug)]
+pub enum OAuthError {
+    /// The provider refused the code, or its answer didn't check out
+    Invalid(String),
+    /// The provider couldn't be reached or answered with something unexpected
+    Provider(String),
+}
+
+impl fmt::Display for OAuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OAuthError::Invalid(message) => write!(f, "OAuth sign-in refused: {}", message),
+            OAuthError::Provider(message) => write!(f, "OAuth provider error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for OAuthError {}
+
+impl From<ExternalTokenError> for OAuthError {
+    fn from(e: ExternalTokenError) -> Self {
+        match e {
+            ExternalTokenError::Invalid(message) => OAuthError::Invalid(message),
+            ExternalTokenError::Jwks(message) => OAuthError::Provider(message),
+        }
+    }
+}
+
+/// Someone who signed in at a provider
+#[derive(Debug, Clone)]
+pub struct OAuthIdentity {
+    /// Namespace of `claims.sub` in `external_identities`
+    pub issuer: String,
+    pub claims: ExternalClaims,
+    /// Tenant whose organization the user joins, for providers that map tenants to organizations
+    pub tenant: Option<String>,
+    /// Role to apply, for providers that map memberships to roles
+    pub roles: Option<RoleSync>,
+    /// Everything the provider said about the user (its userinfo, or the ID token's claims), read by SSO mapping rules
+    pub profile: Value,
+}
+
+/// A role decided by the provider on each sign-in
+#[derive(Debug, Clone)]
+pub struct RoleSync {
+    /// The role the user qualifies for, or `None` to fall back to `user`
+    pub granted: Option<String>,
+    /// Every role the mapping can grant; only users holding `user` or one of these are changed
+    pub managed: Vec<String>,
+}
+
+/// The parameters of one sign-in attempt, sent to the provider and checked on the way back
+pub struct AuthorizationRequest<'a> {
+    pub redirect_uri: &'a str,
+    pub state: &'a str,
+    pub nonce: &'a str,
+    /// PKCE S256 challenge of the verifier kept with the state
+    pub code_challenge: &'a str,
+}
+
+/// A sign-in provider using the OAuth 2.0 authorization code flow
+#[rocket::async_trait]
+pub trait OAuthProvider: Send + Sync {
+    /// Where to send the browser to sign in
+    fn authorization_url(&self, request: &AuthorizationRequest<'_>) -> String;
+
+    /// Redeem the code the provider redirected back with
+    async fn exchange(
+        &self,
+        code: &str,
+        redirect_uri: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<OAuthIdentity, OAuthError>;
+}
+
+/// The configured sign-in providers, by the name used in their routes
+///
+/// Managed as Rocket state; empty when no provider is configured.
+#[derive(Default)]
+pub struct OAuthProviders {
+    providers: HashMap<String, Box<dyn OAuthProvider>>,
+}
+
+impl OAuthProviders {
+    pub fn from_config(config: &AppConfig) -> Self {
+        let mut providers = OAuthProviders::default();
+        if let Some(microsoft) = &config.microsoft {
+            providers.register("microsoft", MicrosoftProvider::new(microsoft.clone()));
+        }
+        if let Some(discord) = &config.discord {
+            providers.register("discord", DiscordProvider::new(discord.clone()));
+        }
+        if let Some(facebook) = &config.facebook {
+            providers.register("facebook", FacebookProvider::new(facebook.clone()));
+        }
+        for generic in &config.oauth_providers {
+            providers.register(&generic.name, GenericProvider::new(generic.clone()));
+        }
+        providers
+    }
+
+    pub fn register(&mut self, name: &str, provider: impl OAuthProvider + 'static) {
+        self.providers.insert(name.to_string(), Box::new(provider));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn OAuthProvider> {
+        self.providers.get(name).map(|provider| provider.as_ref())
+    }
+}
+
+/// The frontend page providers redirect back to; it posts `code` and `state` to the callback endpoint
+pub fn redirect_uri(config: &AppConfig, provider: &str) -> String {
+    format!("{}/oauth/{}/callback", config.frontend_url, provider)
+}
+
+/// PKCE S256 challenge for a code verifier
+pub fn code_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+/// The parts of a token endpoint response sign-in needs
+#[derive(Debug, Deserialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    #[serde(default)]
+    pub id_token: Option<String>,
+}
+
+/// Redeem an authorization code at a provider's token endpoint
+pub async fn request_token(
+    client: &reqwest::Client,
+    token_url: &str,
+    form: &[(&str, &str)],
+) -> Result<TokenResponse, OAuthError> {
+    token_response(client.post(token_url).form(form)).await
+}
+
+/// Send a prepared token request, for endpoints that want the client authenticated another way
+pub async fn token_response(request: reqwest::RequestBuilder) -> Result<TokenResponse, OAuthError> {
+    // Some providers (GitHub among them) answer form-encoded unless asked for JSON
+    let response = request
+        .header(reqwest::header::ACCEPT, "application/json")
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .map_err(|e| OAuthError::Provider(e.to_string()))?;
+
+    // Invalid, expired or reused codes come back as 400 invalid_grant
+    let status = response.status();
+    if status.is_client_error() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(OAuthError::Invalid(format!("token endpoint answered {}: {}", status, body)));
+    }
+    response
+        .error_for_status()
+        .map_err(|e| OAuthError::Provider(e.to_string()))?
+        .json()
+        .await
+        .map_err(|e| OAuthError::Provider(e.to_string()))
+}
@@ -0,0 +1,120 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use rocket::http::uri::fmt::{Query, UriDisplay};
+use rocket::http::RawStr;
+use rocket::request::{FromRequest, Outcome, Request};
+use reqwest::Url;
+use uuid::Uuid;
+
+use crate::auth::tokens;
+
+/// Marks an OAuth client identifier
+pub const CLIENT_ID_PREFIX: &str = "client_";
+/// Marks an OAuth client secret
+pub const CLIENT_SECRET_PREFIX: &str = "cs_";
+/// Marks a registration access token of a dynamically registered client
+pub const REGISTRATION_TOKEN_PREFIX: &str = "rat_";
+
+/// How long an authorization code can be exchanged, in seconds
+pub const CODE_TTL_SECONDS: i64 = 600;
+/// How long access tokens issued to clients last, in seconds
+pub const ACCESS_TOKEN_TTL_SECONDS: i64 = 3600;
+
+pub fn generate_client_id() -> String {
+    format!("{}{}", CLIENT_ID_PREFIX, Uuid::new_v4().simple())
+}
+
+/// A new client secret and its hash; the secret is shown once and never stored
+pub fn generate_secret() -> (String, String) {
+    let secret = format!("{}{}{}", CLIENT_SECRET_PREFIX, Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let hash = tokens::hash(&secret);
+    (secret, hash)
+}
+
+/// A new registration access token and its hash; the token is shown once and never stored
+pub fn generate_registration_token() -> (String, String) {
+    let token = format!("{}{}{}", REGISTRATION_TOKEN_PREFIX, Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let hash = tokens::hash(&token);
+    (token, hash)
+}
+
+/// A single-use authorization code
+pub fn generate_code() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+/// Whether a client secret matches the stored hash
+pub fn secret_matches(secret: &str, hash: &str) -> bool {
+    tokens::hash(secret) == hash
+}
+
+/// The first redirect URI that isn't an absolute URI without a fragment
+///
+/// Browsers drop fragments on redirect, so they can't carry the code (RFC 6749 §3.1.2).
+pub fn invalid_redirect_uri(redirect_uris: &[String]) -> Option<&str> {
+    redirect_uris
+        .iter()
+        .find(|uri| Url::parse(uri).map_or(true, |url| url.fragment().is_some()))
+        .map(String::as_str)
+}
+
+/// Logos are shown on the consent page, so only https URLs are accepted
+pub fn is_valid_logo_url(logo_url: &str) -> bool {
+    matches!(Url::parse(logo_url), Ok(url) if url.scheme() == "https")
+}
+
+/// `redirect_uri` with response parameters added to its query
+pub fn redirect_with(redirect_uri: &str, params: &[(&str, &str)]) -> String {
+    let query = params
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, &value as &dyn UriDisplay<Query>))
+        .collect::<Vec<_>>()
+        .join("&");
+    let separator = if redirect_uri.contains('?') { '&' } else { '?' };
+    format!("{}{}{}", redirect_uri, separator, query)
+}
+
+/// The bearer token of a request, if any, for endpoints that take tokens other than sessions
+pub struct BearerToken(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BearerToken {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let token = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "))
+            .map(|token| token.trim().to_string());
+        Outcome::Success(BearerToken(token))
+    }
+}
+
+/// Client credentials sent with HTTP Basic authentication, if any
+///
+/// Clients may instead send `client_id` and `client_secret` in the form
+/// body, so this guard never fails; a malformed header just yields nothing.
+pub struct BasicCredentials(pub Option<(String, String)>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BasicCredentials {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let credentials = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Basic "))
+            .and_then(|encoded| STANDARD.decode(encoded.trim()).ok())
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .and_then(|decoded| {
+                // RFC 6749 §2.3.1: both parts are form-encoded before joining
+                let (id, secret) = decoded.split_once(':')?;
+                let id = RawStr::new(id).url_decode().ok()?.into_owned();
+                let secret = RawStr::new(secret).url_decode().ok()?.into_owned();
+                Some((id, secret))
+            });
+        Outcome::Success(BasicCredentials(credentials))
+    }
+}
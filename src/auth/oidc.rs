@@ -0,0 +1,52 @@
+use serde_json::{Map, Value};
+
+use crate::auth::scopes;
+use crate::config::AppConfig;
+use crate::models::user::User;
+
+/// Standard OpenID Connect profile claims, copied from `user_metadata` when present
+pub const PROFILE_CLAIMS: &[&str] = &[
+    "name",
+    "given_name",
+    "family_name",
+    "middle_name",
+    "nickname",
+    "preferred_username",
+    "profile",
+    "picture",
+    "website",
+    "gender",
+    "birthdate",
+    "zoneinfo",
+    "locale",
+];
+
+/// The issuer identifier: this API's public URL
+pub fn issuer(config: &AppConfig) -> String {
+    config.public_url.clone()
+}
+
+/// Claims about a user released for the given scopes
+///
+/// `sub` is always included; `email` and `email_verified` need the `email`
+/// scope, and profile claims (plus `updated_at`) need `profile`.
+pub fn user_claims(user: &User, has_scope: impl Fn(&str) -> bool) -> Map<String, Value> {
+    let mut claims = Map::new();
+    claims.insert("sub".to_string(), Value::String(user.id.to_string()));
+
+    if has_scope(scopes::EMAIL) {
+        claims.insert("email".to_string(), Value::String(user.email.clone()));
+        claims.insert("email_verified".to_string(), Value::Bool(user.email_verified_at.is_some()));
+    }
+
+    if has_scope(scopes::PROFILE) {
+        for claim in PROFILE_CLAIMS {
+            if let Some(value) = user.user_metadata.get(*claim).filter(|value| !value.is_null()) {
+                claims.insert(claim.to_string(), value.clone());
+            }
+        }
+        claims.insert("updated_at".to_string(), Value::from(user.updated_at.timestamp()));
+    }
+
+    claims
+}
@@ -0,0 +1,46 @@
+/// Invite people to join the organization (`POST /api/v1/orgs/<id>/invitations`)
+pub const MEMBERS_INVITE: &str = "members.invite";
+/// See the organization's plans (`GET /api/v1/orgs/<id>/billing`)
+pub const BILLING_MANAGE: &str = "billing.manage";
+/// Read the organization's audit log (`GET /api/v1/orgs/<id>/audit`)
+pub const AUDIT_VIEW: &str = "audit.view";
+
+/// Everything a custom organization role can be allowed to do
+///
+/// Owners and admins may do all of it; plain members none of it.
+pub const ALL: &[(&str, &str)] = &[
+    (MEMBERS_INVITE, "Invite people to join the organization"),
+    (BILLING_MANAGE, "See the organization's plans"),
+    (AUDIT_VIEW, "Read the organization's audit log"),
+];
+
+pub fn is_known(name: &str) -> bool {
+    ALL.iter().any(|(known, _)| *known == name)
+}
+
+/// A permission required by an `OrgPermitted<P>` guard
+pub trait OrgPermission: Send + Sync + 'static {
+    const NAME: &'static str;
+}
+
+macro_rules! org_permissions {
+    ($($(#[$doc:meta])* $marker:ident => $name:expr;)*) => {
+        $(
+            $(#[$doc])*
+            pub struct $marker;
+
+            impl OrgPermission for $marker {
+                const NAME: &'static str = $name;
+            }
+        )*
+    };
+}
+
+org_permissions! {
+    /// Requires `members.invite`
+    InviteMembers => MEMBERS_INVITE;
+    /// Requires `billing.manage`
+    ManageBilling => BILLING_MANAGE;
+    /// Requires `audit.view`
+    ViewAudit => AUDIT_VIEW;
+}
@@ -0,0 +1,84 @@
+use chrono::Utc;
+use ed25519_compact::{KeyPair, Seed};
+use pasetors::errors::{ClaimValidationError, Error as PasetoError};
+use pasetors::keys::{AsymmetricPublicKey, AsymmetricSecretKey, SymmetricKey};
+use pasetors::token::UntrustedToken;
+use pasetors::version4::{LocalToken, PublicToken, V4};
+use pasetors::{Local, Public};
+
+use crate::auth::jwt::Claims;
+use crate::auth::signer::{SignerError, TokenSigner};
+use crate::config::PasetoPurpose;
+
+enum PasetoKey {
+    Local(SymmetricKey<V4>),
+    Public {
+        secret: AsymmetricSecretKey<V4>,
+        public: AsymmetricPublicKey<V4>,
+    },
+}
+
+/// Issues PASETO v4 tokens instead of JWTs
+///
+/// `local` tokens are encrypted and authenticated with a shared key, so
+/// their claims can't be read by clients. `public` tokens are signed with
+/// Ed25519 and readable by anyone, like JWTs, but verifiable by services
+/// that only hold the public key. Either way the algorithm is fixed by the
+/// version, so there is no `alg` header to get wrong.
+pub struct PasetoSigner {
+    key: PasetoKey,
+}
+
+impl PasetoSigner {
+    /// Build a signer from a hex-encoded 32-byte key (the Ed25519 seed for `public`)
+    pub fn new(purpose: PasetoPurpose, key_hex: &str) -> Result<Self, SignerError> {
+        let bytes = hex::decode(key_hex.trim())
+            .map_err(|e| SignerError::Backend(format!("PASETO key is not valid hex: {}", e)))?;
+        let invalid_key = || SignerError::Backend("PASETO key must be 32 bytes (64 hex characters)".to_string());
+
+        let key = match purpose {
+            PasetoPurpose::Local => PasetoKey::Local(SymmetricKey::<V4>::from(&bytes).map_err(|_| invalid_key())?),
+            PasetoPurpose::Public => {
+                let key_pair = KeyPair::from_seed(Seed::from_slice(&bytes).map_err(|_| invalid_key())?);
+                PasetoKey::Public {
+                    secret: AsymmetricSecretKey::<V4>::from(key_pair.sk.as_ref())?,
+                    public: AsymmetricPublicKey::<V4>::from(key_pair.pk.as_ref())?,
+                }
+            }
+        };
+        Ok(PasetoSigner { key })
+    }
+}
+
+#[rocket::async_trait]
+impl TokenSigner for PasetoSigner {
+    async fn sign(&self, claims: &Claims) -> Result<String, SignerError> {
+        let payload = serde_json::to_vec(claims)
+            .map_err(|e| SignerError::Backend(format!("Failed to encode claims: {}", e)))?;
+
+        match &self.key {
+            PasetoKey::Local(key) => LocalToken::encrypt(key, &payload, None, None),
+            PasetoKey::Public { secret, .. } => PublicToken::sign(secret, &payload, None, None),
+        }
+        .map_err(SignerError::from)
+    }
+
+    async fn verify(&self, token: &str) -> Result<Claims, SignerError> {
+        let trusted = match &self.key {
+            PasetoKey::Local(key) => {
+                let untrusted = UntrustedToken::<Local, V4>::try_from(token)?;
+                LocalToken::decrypt(key, &untrusted, None, None)?
+            }
+            PasetoKey::Public { public, .. } => {
+                let untrusted = UntrustedToken::<Public, V4>::try_from(token)?;
+                PublicToken::verify(public, &untrusted, None, None)?
+            }
+        };
+
+        let claims: Claims = serde_json::from_str(trusted.payload()).map_err(|_| PasetoError::ClaimInvalidJson)?;
+        if claims.exp <= Utc::now().timestamp() as usize {
+            return Err(PasetoError::ClaimValidation(ClaimValidationError::Exp).into());
+        }
+        Ok(claims)
+    }
+}
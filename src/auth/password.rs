@@ -0,0 +1,311 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, SaltString};
+use argon2::{Algorithm, Argon2, Params, PasswordHasher as _, PasswordVerifier as _, Version};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::sync::{OnceCell, Semaphore};
+
+use crate::config::{AppConfig, PasswordHashing, PepperConfig, PepperSecret};
+
+/// Marks a stored hash whose password was peppered first: `pepper:<id>:<bcrypt hash>`
+const PEPPER_PREFIX: &str = "pepper:";
+
+/// Hashing faster than this on the host makes offline guessing too cheap
+pub const MIN_HASH_DURATION: Duration = Duration::from_millis(100);
+
+/// Hashes allowed to wait for a thread before more are refused, unless configured
+pub const DEFAULT_HASH_QUEUE: usize = 256;
+
+#[derive(Debug)]
+pub enum PasswordError {
+    Hash(String),
+    /// A stored hash names a pepper that isn't configured (anymore)
+    UnknownPepper(String),
+    /// A pepper's secret couldn't be loaded, e.g. KMS decryption failed
+    Pepper(String),
+    /// Every hashing thread is busy and the queue is full
+    Busy,
+}
+
+impl PasswordError {
+    /// Status for a request that failed with this error: 503 when busy, 500 otherwise
+    pub fn status(&self) -> rocket::http::Status {
+        match self {
+            PasswordError::Busy => rocket::http::Status::ServiceUnavailable,
+            _ => rocket::http::Status::InternalServerError,
+        }
+    }
+}
+
+impl fmt::Display for PasswordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PasswordError::Hash(message) => write!(f, "Password hashing failed: {}", message),
+            PasswordError::UnknownPepper(id) => write!(f, "Password hash uses unknown pepper '{}'", id),
+            PasswordError::Pepper(message) => write!(f, "Failed to load password pepper: {}", message),
+            PasswordError::Busy => write!(f, "Password hashing queue is full"),
+        }
+    }
+}
+
+impl std::error::Error for PasswordError {}
+
+/// A server-side secret mixed into passwords (HMAC-SHA256) before hashing
+///
+/// Unlike the hash, the pepper never touches the database, so a database
+/// leak alone isn't enough to start guessing passwords.
+struct Pepper {
+    id: String,
+    secret: PepperSecret,
+    /// Resolved secret (decrypted on first use for KMS-encrypted peppers)
+    key: OnceCell<Vec<u8>>,
+}
+
+impl Pepper {
+    async fn key(&self) -> Result<&[u8], PasswordError> {
+        let key = self
+            .key
+            .get_or_try_init(|| async {
+                match &self.secret {
+                    PepperSecret::Plain(secret) => Ok(secret.as_bytes().to_vec()),
+                    #[cfg(feature = "aws-kms")]
+                    PepperSecret::Kms { ciphertext } => crate::auth::kms::decrypt(ciphertext)
+                        .await
+                        .map_err(PasswordError::Pepper),
+                    #[cfg(not(feature = "aws-kms"))]
+                    PepperSecret::Kms { .. } => Err(PasswordError::Pepper(
+                        "KMS-encrypted peppers require the `aws-kms` feature".to_string(),
+                    )),
+                }
+            })
+            .await?;
+        Ok(key)
+    }
+
+    /// HMAC of the password, hex encoded (64 characters, within bcrypt's 72-byte limit)
+    async fn apply(&self, password: &str) -> Result<String, PasswordError> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.key().await?)
+            .map_err(|e| PasswordError::Pepper(e.to_string()))?;
+        mac.update(password.as_bytes());
+        Ok(hex::encode(mac.finalize().into_bytes()))
+    }
+}
+
+/// Hashes and verifies passwords, applying the configured pepper
+///
+/// Managed as Rocket state. New hashes use the configured algorithm and the
+/// first configured pepper; the other peppers only verify existing hashes,
+/// so a pepper can be rotated by adding a new one in front. Hashes made with
+/// other settings, or before any pepper was configured, keep working.
+///
+/// Hashing runs on the blocking thread pool, at most `threads` hashes at a
+/// time (one per CPU by default) so it can't starve the async runtime or
+/// oversubscribe the CPU. Up to `queue` more wait for a thread; beyond that
+/// calls fail right away with [`PasswordError::Busy`].
+pub struct PasswordHasher {
+    peppers: Vec<Pepper>,
+    hashing: PasswordHashing,
+    /// Hash checked when there's no account, made with the current settings on first use
+    dummy: OnceCell<String>,
+    /// One permit per hash allowed to run
+    running: Arc<Semaphore>,
+    /// One permit per hash allowed to run or wait
+    admitted: Arc<Semaphore>,
+}
+
+impl PasswordHasher {
+    pub fn new(hashing: PasswordHashing, peppers: &[PepperConfig]) -> Self {
+        PasswordHasher {
+            peppers: peppers
+                .iter()
+                .map(|pepper| Pepper {
+                    id: pepper.id.clone(),
+                    secret: pepper.secret.clone(),
+                    key: OnceCell::new(),
+                })
+                .collect(),
+            hashing,
+            dummy: OnceCell::new(),
+            running: Arc::new(Semaphore::new(0)),
+            admitted: Arc::new(Semaphore::new(0)),
+        }
+        .with_limits(0, DEFAULT_HASH_QUEUE)
+    }
+
+    /// Run at most `threads` hashes at once (0: one per CPU), with up to `queue` waiting
+    pub fn with_limits(mut self, threads: usize, queue: usize) -> Self {
+        let threads = match threads {
+            0 => std::thread::available_parallelism().map_or(1, |threads| threads.get()),
+            threads => threads,
+        };
+        self.running = Arc::new(Semaphore::new(threads));
+        self.admitted = Arc::new(Semaphore::new(threads + queue));
+        self
+    }
+
+    pub fn from_config(config: &AppConfig) -> Self {
+        PasswordHasher::new(config.password_hashing, &config.password_peppers)
+            .with_limits(config.password_hash_threads, config.password_hash_queue)
+    }
+
+    /// Run a hashing job on the blocking pool once a thread is free
+    async fn run<T: Send + 'static>(
+        &self,
+        job: impl FnOnce() -> Result<T, PasswordError> + Send + 'static,
+    ) -> Result<T, PasswordError> {
+        let admitted = self.admitted.clone().try_acquire_owned().map_err(|_| PasswordError::Busy)?;
+        let running = self.running.clone().acquire_owned().await.map_err(|e| PasswordError::Hash(e.to_string()))?;
+        // The permits go with the job, so a caller that gives up doesn't free its thread early
+        tokio::task::spawn_blocking(move || {
+            let _permits = (admitted, running);
+            job()
+        })
+        .await
+        .map_err(|e| PasswordError::Hash(e.to_string()))?
+    }
+
+    async fn hash_blocking(&self, password: String) -> Result<String, PasswordError> {
+        let hashing = self.hashing;
+        self.run(move || match hashing {
+            PasswordHashing::Bcrypt { cost } => {
+                bcrypt::hash(password, cost).map_err(|e| PasswordError::Hash(e.to_string()))
+            }
+            PasswordHashing::Argon2 {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                let salt = SaltString::generate(&mut OsRng);
+                argon2id(memory_kib, iterations, parallelism)?
+                    .hash_password(password.as_bytes(), &salt)
+                    .map(|hash| hash.to_string())
+                    .map_err(|e| PasswordError::Hash(e.to_string()))
+            }
+        })
+        .await
+    }
+
+    /// Verify against a bcrypt or Argon2 hash; the hash carries its own parameters
+    async fn verify_blocking(&self, password: String, hash: String) -> Result<bool, PasswordError> {
+        self.run(move || {
+            if !hash.starts_with("$argon2") {
+                return bcrypt::verify(password, &hash).map_err(|e| PasswordError::Hash(e.to_string()));
+            }
+            let parsed = PasswordHash::new(&hash).map_err(|e| PasswordError::Hash(e.to_string()))?;
+            match Argon2::default().verify_password(password.as_bytes(), &parsed) {
+                Ok(()) => Ok(true),
+                Err(argon2::password_hash::Error::Password) => Ok(false),
+                Err(e) => Err(PasswordError::Hash(e.to_string())),
+            }
+        })
+        .await
+    }
+
+    pub async fn hash(&self, password: &str) -> Result<String, PasswordError> {
+        match self.peppers.first() {
+            Some(pepper) => {
+                let hash = self.hash_blocking(pepper.apply(password).await?).await?;
+                Ok(format!("{}{}:{}", PEPPER_PREFIX, pepper.id, hash))
+            }
+            None => self.hash_blocking(password.to_string()).await,
+        }
+    }
+
+    pub async fn verify(&self, password: &str, stored: &str) -> Result<bool, PasswordError> {
+        match parse(stored) {
+            Some((id, hash)) => {
+                let pepper = self
+                    .peppers
+                    .iter()
+                    .find(|pepper| pepper.id == id)
+                    .ok_or_else(|| PasswordError::UnknownPepper(id.to_string()))?;
+                self.verify_blocking(pepper.apply(password).await?, hash.to_string()).await
+            }
+            None => self.verify_blocking(password.to_string(), stored.to_string()).await,
+        }
+    }
+
+    /// Spend as long as `verify` would, for a login whose email has no account
+    ///
+    /// Without this, unknown emails answer noticeably faster than wrong
+    /// passwords and the timing reveals which addresses are registered.
+    pub async fn verify_dummy(&self, password: &str) {
+        let dummy = self.dummy.get_or_try_init(|| self.hash("dummy-password-for-unknown-accounts")).await;
+        let result = match dummy {
+            Ok(hash) => self.verify(password, hash).await.map(|_| ()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+        }
+    }
+
+    /// Whether a stored hash should be replaced after a successful login:
+    /// it doesn't use the current pepper or algorithm, or its work factor
+    /// is below the configured one
+    pub fn needs_rehash(&self, stored: &str) -> bool {
+        let hash = match (self.peppers.first(), parse(stored)) {
+            (Some(current), Some((id, hash))) if current.id == id => hash,
+            (None, None) => stored,
+            _ => return true,
+        };
+        weaker_than(hash, self.hashing)
+    }
+
+    /// Time one hash with the configured settings, to compare against `MIN_HASH_DURATION`
+    pub async fn calibrate(&self) -> Result<Duration, PasswordError> {
+        let started = Instant::now();
+        self.hash("calibration-password").await?;
+        Ok(started.elapsed())
+    }
+}
+
+/// Split a peppered hash into pepper id and inner hash
+fn parse(stored: &str) -> Option<(&str, &str)> {
+    stored.strip_prefix(PEPPER_PREFIX)?.split_once(':')
+}
+
+/// Whether an (unpeppered) hash was made with another algorithm or less work than `hashing`
+fn weaker_than(hash: &str, hashing: PasswordHashing) -> bool {
+    match hashing {
+        PasswordHashing::Bcrypt { cost } => match bcrypt_cost(hash) {
+            Some(stored) => stored < cost,
+            None => true,
+        },
+        PasswordHashing::Argon2 {
+            memory_kib,
+            iterations,
+            parallelism,
+        } => {
+            let params = PasswordHash::new(hash)
+                .ok()
+                .filter(|hash| hash.algorithm == Algorithm::Argon2id.ident())
+                .and_then(|hash| Params::try_from(&hash).ok());
+            match params {
+                Some(stored) => {
+                    stored.m_cost() < memory_kib || stored.t_cost() < iterations || stored.p_cost() < parallelism
+                }
+                None => true,
+            }
+        }
+    }
+}
+
+/// The cost of a `$2b$12$...` bcrypt hash
+fn bcrypt_cost(hash: &str) -> Option<u32> {
+    let mut parts = hash.strip_prefix('$')?.split('$');
+    match parts.next()? {
+        "2a" | "2b" | "2x" | "2y" => parts.next()?.parse().ok(),
+        _ => None,
+    }
+}
+
+fn argon2id(memory_kib: u32, iterations: u32, parallelism: u32) -> Result<Argon2<'static>, PasswordError> {
+    let params =
+        Params::new(memory_kib, iterations, parallelism, None).map_err(|e| PasswordError::Hash(e.to_string()))?;
+    Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+}
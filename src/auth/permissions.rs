@@ -0,0 +1,75 @@
+/// Delete user accounts (`DELETE /api/v1/admin/users/<id>`)
+pub const USERS_DELETE: &str = "users.delete";
+/// Manage permissions and which roles have them
+pub const PERMISSIONS_MANAGE: &str = "permissions.manage";
+/// Register and manage OAuth clients
+pub const OAUTH_CLIENTS_MANAGE: &str = "oauth_clients.manage";
+/// Query and export the audit log (`GET /api/v1/admin/audit`)
+pub const AUDIT_READ: &str = "audit.read";
+/// Re-deliver stored events to an audit sink (`POST /api/v1/admin/audit/replay`)
+pub const AUDIT_REPLAY: &str = "audit.replay";
+/// Create plans and grant them to users and organizations
+pub const PLANS_MANAGE: &str = "plans.manage";
+
+/// Permissions the application checks itself, created by migrations and
+/// initially granted to the `admin` role
+pub const BUILT_IN: &[(&str, &str)] = &[
+    (USERS_DELETE, "Delete user accounts"),
+    (PERMISSIONS_MANAGE, "Manage permissions and role assignments"),
+    (OAUTH_CLIENTS_MANAGE, "Register and manage OAuth clients"),
+    (AUDIT_READ, "Query and export the audit log"),
+    (AUDIT_REPLAY, "Re-deliver audit events to an audit sink"),
+    (PLANS_MANAGE, "Create plans and grant them to users and organizations"),
+];
+
+pub fn is_built_in(name: &str) -> bool {
+    BUILT_IN.iter().any(|(built_in, _)| *built_in == name)
+}
+
+/// Permission names are lowercase words joined by dots, e.g. `reports.export`
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 100
+        && name.split('.').all(|part| {
+            !part.is_empty() && part.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        })
+}
+
+/// A permission required by a `HasPermission<P>` guard
+///
+/// Permissions are granted to roles in the `role_permissions` table, so
+/// changing who may do what doesn't need a deploy. Apps declare their own
+/// with `permissions!`.
+pub trait Permission: Send + Sync + 'static {
+    const NAME: &'static str;
+}
+
+/// Declare marker types for `HasPermission<P>`
+#[macro_export]
+macro_rules! permissions {
+    ($($(#[$doc:meta])* $marker:ident => $name:expr;)*) => {
+        $(
+            $(#[$doc])*
+            pub struct $marker;
+
+            impl $crate::auth::permissions::Permission for $marker {
+                const NAME: &'static str = $name;
+            }
+        )*
+    };
+}
+
+permissions! {
+    /// Requires `users.delete`
+    UsersDelete => USERS_DELETE;
+    /// Requires `permissions.manage`
+    PermissionsManage => PERMISSIONS_MANAGE;
+    /// Requires `oauth_clients.manage`
+    OAuthClientsManage => OAUTH_CLIENTS_MANAGE;
+    /// Requires `audit.read`
+    AuditRead => AUDIT_READ;
+    /// Requires `audit.replay`
+    AuditReplay => AUDIT_REPLAY;
+    /// Requires `plans.manage`
+    PlansManage => PLANS_MANAGE;
+}
@@ -0,0 +1,47 @@
+/// Plans created by migrations, with their rank; a higher plan includes everything lower ones do
+pub const BUILT_IN: &[(&str, i32, &str)] = &[
+    (FREE, 0, "Free"),
+    (PRO, 10, "Pro"),
+    (ENTERPRISE, 20, "Enterprise"),
+];
+
+pub const FREE: &str = "free";
+pub const PRO: &str = "pro";
+pub const ENTERPRISE: &str = "enterprise";
+
+/// Plan names are lowercase words joined by underscores or dashes, e.g. `pro` or `team-plus`
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 50
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+}
+
+/// A plan required by a `RequiresPlan<P>` guard
+///
+/// Users have a plan through entitlements granted to them or to their
+/// organization. Apps declare markers for their own plans with `plans!`.
+pub trait Plan: Send + Sync + 'static {
+    const NAME: &'static str;
+}
+
+/// Declare marker types for `RequiresPlan<P>`
+#[macro_export]
+macro_rules! plans {
+    ($($(#[$doc:meta])* $marker:ident => $name:expr;)*) => {
+        $(
+            $(#[$doc])*
+            pub struct $marker;
+
+            impl $crate::auth::plans::Plan for $marker {
+                const NAME: &'static str = $name;
+            }
+        )*
+    };
+}
+
+plans! {
+    /// Requires `pro` or a higher plan
+    Pro => PRO;
+    /// Requires `enterprise` or a higher plan
+    Enterprise => ENTERPRISE;
+}
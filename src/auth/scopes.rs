@@ -0,0 +1,49 @@
+/// Read the key owner's profile
+pub const USERS_READ: &str = "users:read";
+/// Update the key owner's profile (`user_metadata`)
+pub const USERS_WRITE: &str = "users:write";
+
+/// OpenID Connect: read the key owner's identity at `/userinfo`
+pub const OPENID: &str = "openid";
+/// OpenID Connect: include profile claims (`name`, `picture`, …) from `user_metadata`
+pub const PROFILE: &str = "profile";
+/// OpenID Connect: include `email` and `email_verified`
+pub const EMAIL: &str = "email";
+
+/// Every scope a key can be given
+pub const ALL: &[&str] = &[USERS_READ, USERS_WRITE, OPENID, PROFILE, EMAIL];
+
+pub fn is_known(scope: &str) -> bool {
+    ALL.contains(&scope)
+}
+
+/// A scope required by a `Scoped<S>` guard
+///
+/// Session tokens from login are unrestricted. Credentials with scopes (API
+/// keys) can only call endpoints that take a `Scoped<S>` guard naming one of
+/// their scopes; every other endpoint refuses them with 403.
+pub trait Scope: Send + Sync + 'static {
+    const NAME: &'static str;
+}
+
+macro_rules! scopes {
+    ($($(#[$doc:meta])* $marker:ident => $name:expr;)*) => {
+        $(
+            $(#[$doc])*
+            pub struct $marker;
+
+            impl Scope for $marker {
+                const NAME: &'static str = $name;
+            }
+        )*
+    };
+}
+
+scopes! {
+    /// Requires `users:read`
+    UsersRead => USERS_READ;
+    /// Requires `users:write`
+    UsersWrite => USERS_WRITE;
+    /// Requires `openid`
+    OpenId => OPENID;
+}
@@ -0,0 +1,110 @@
+use std::marker::PhantomData;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket_db_pools::Database;
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::auth::guard::{forbid, ForbiddenReason};
+use crate::config::AppConfig;
+use crate::repositories::nonces;
+use crate::Postgres;
+
+/// Something a signed URL can give access to, required by a `SignedUrl<R>` guard
+///
+/// The name is signed into the URL, so a URL for one resource can't be used
+/// for another.
+pub trait SignedResource: Send + Sync + 'static {
+    const NAME: &'static str;
+}
+
+/// The personal data export at `GET /api/v1/auth/me/export`
+pub struct DataExport;
+
+impl SignedResource for DataExport {
+    const NAME: &'static str = "data_export";
+}
+
+/// Add a signature to `url` letting whoever holds it reach `R` as `user_id`, once, until `expires_at`
+///
+/// `url` must not have a query string of its own.
+pub fn sign<R: SignedResource>(secret: &str, url: &str, user_id: Uuid, expires_at: DateTime<Utc>) -> String {
+    let (user, expires, nonce) = (user_id.simple().to_string(), expires_at.timestamp(), Uuid::new_v4().simple());
+    let payload = format!("{}.{}.{}.{}", R::NAME, user, expires, nonce);
+    let signature = hex::encode(mac(secret, &payload).finalize().into_bytes());
+    format!("{}?user={}&expires={}&nonce={}&signature={}", url, user, expires, nonce, signature)
+}
+
+/// Request guard for a URL from [`sign`], in place of an `Authorization` header
+///
+/// Refuses with 403 (code `invalid_signed_url`) URLs that were tampered
+/// with, expired, or already used; each URL works once. Routes using it are
+/// a 404 while `ROCKET_SIGNED_URL_SECRET` isn't set.
+pub struct SignedUrl<R: SignedResource> {
+    pub user_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+    resource: PhantomData<R>,
+}
+
+#[rocket::async_trait]
+impl<'r, R: SignedResource> FromRequest<'r> for SignedUrl<R> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(secret) = request
+            .rocket()
+            .state::<AppConfig>()
+            .and_then(|config| config.signed_url_secret.as_deref())
+        else {
+            return Outcome::Forward(Status::NotFound);
+        };
+
+        let param = |name: &str| request.query_value::<&str>(name).and_then(Result::ok);
+        let (Some(user), Some(expires), Some(nonce), Some(signature)) =
+            (param("user"), param("expires"), param("nonce"), param("signature"))
+        else {
+            return forbid(request, ForbiddenReason::InvalidSignedUrl);
+        };
+        let payload = format!("{}.{}.{}.{}", R::NAME, user, expires, nonce);
+        let authentic = hex::decode(signature)
+            .ok()
+            .is_some_and(|signature| mac(secret, &payload).verify_slice(&signature).is_ok());
+        let parsed = Uuid::parse_str(user)
+            .ok()
+            .zip(expires.parse().ok().and_then(|expires| DateTime::from_timestamp(expires, 0)));
+        let Some((user_id, expires_at)) = parsed.filter(|(_, expires_at)| authentic && *expires_at > Utc::now()) else {
+            return forbid(request, ForbiddenReason::InvalidSignedUrl);
+        };
+
+        let Some(db) = Postgres::fetch(request.rocket()) else {
+            return Outcome::Error((Status::InternalServerError, ()));
+        };
+        let consumed = match db.acquire().await {
+            Ok(mut conn) => nonces::consume(&mut conn, &format!("url:{}", nonce), expires_at).await,
+            Err(e) => Err(e),
+        };
+        match consumed {
+            Ok(true) => Outcome::Success(SignedUrl {
+                user_id,
+                expires_at,
+                resource: PhantomData,
+            }),
+            Ok(false) => forbid(request, ForbiddenReason::InvalidSignedUrl),
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                Outcome::Error((Status::InternalServerError, ()))
+            }
+        }
+    }
+}
+
+fn mac(secret: &str, payload: &str) -> Hmac<Sha256> {
+    // Domain-separated in case the same secret also signs other tokens
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(b"signed-url:");
+    mac.update(payload.as_bytes());
+    mac
+}
@@ -0,0 +1,91 @@
+use std::fmt;
+
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+
+use crate::auth::jwt::Claims;
+
+/// Errors returned by token signers
+#[derive(Debug)]
+pub enum SignerError {
+    /// The token was malformed, expired, or had an invalid signature
+    Jwt(jsonwebtoken::errors::Error),
+    /// The PASETO token was malformed, expired, or failed to decrypt or verify
+    Paseto(pasetors::errors::Error),
+    /// The signing backend (e.g. a remote key service) failed
+    Backend(String),
+}
+
+impl fmt::Display for SignerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignerError::Jwt(e) => write!(f, "JWT error: {}", e),
+            SignerError::Paseto(e) => write!(f, "PASETO error: {}", e),
+            SignerError::Backend(message) => write!(f, "Signing backend error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for SignerError {}
+
+impl From<jsonwebtoken::errors::Error> for SignerError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        SignerError::Jwt(e)
+    }
+}
+
+impl From<pasetors::errors::Error> for SignerError {
+    fn from(e: pasetors::errors::Error) -> Self {
+        SignerError::Paseto(e)
+    }
+}
+
+/// Signs and verifies access tokens
+///
+/// Implement this to move signature operations out of the process,
+/// e.g. into a KMS or HSM that never exposes the private key.
+#[rocket::async_trait]
+pub trait TokenSigner: Send + Sync {
+    /// Encode and sign the given claims
+    async fn sign(&self, claims: &Claims) -> Result<String, SignerError>;
+
+    /// Verify a token's signature and expiry and return its claims
+    async fn verify(&self, token: &str) -> Result<Claims, SignerError>;
+}
+
+/// JWT validation for this service's own tokens
+///
+/// `aud` is left to the auth guard: tokens exchanged for another service
+/// carry that service's audience and must still verify here, so they can be
+/// revoked and refused with a clear reason.
+pub fn validation(algorithm: Algorithm) -> Validation {
+    let mut validation = Validation::new(algorithm);
+    validation.validate_aud = false;
+    validation
+}
+
+/// HMAC SHA-256 signer using a shared secret
+pub struct HmacSigner {
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+}
+
+impl HmacSigner {
+    pub fn new(secret: &str) -> Self {
+        HmacSigner {
+            encoding_key: EncodingKey::from_secret(secret.as_ref()),
+            decoding_key: DecodingKey::from_secret(secret.as_ref()),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl TokenSigner for HmacSigner {
+    async fn sign(&self, claims: &Claims) -> Result<String, SignerError> {
+        Ok(encode(&Header::new(Algorithm::HS256), claims, &self.encoding_key)?)
+    }
+
+    async fn verify(&self, token: &str) -> Result<Claims, SignerError> {
+        let token_data = decode::<Claims>(token, &self.decoding_key, &validation(Algorithm::HS256))?;
+        Ok(token_data.claims)
+    }
+}
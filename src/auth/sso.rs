@@ -0,0 +1,96 @@
+use serde_json::Value;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::auth::generic_oauth;
+use crate::auth::oauth::OAuthProviders;
+use crate::config::AppConfig;
+use crate::models::organization::OrgRole;
+use crate::models::sso_mapping::SsoMapping;
+use crate::repositories::{organizations, sso_mappings, users};
+
+/// The provider Google ID tokens sign in with, at `/oauth/google/id-token`
+pub const GOOGLE: &str = "google";
+
+/// Whether organizations can require `provider`: it's configured, so members can sign in with it
+pub fn is_available(config: &AppConfig, providers: &OAuthProviders, provider: &str) -> bool {
+    match provider {
+        GOOGLE => !config.google_client_ids.is_empty(),
+        _ => providers.get(provider).is_some(),
+    }
+}
+
+/// Where members start signing in with `provider`
+///
+/// The authorize endpoint for redirect providers; Google ID tokens are posted
+/// to their own endpoint instead.
+pub fn sign_in_url(config: &AppConfig, provider: &str) -> String {
+    match provider {
+        GOOGLE => format!("{}/api/v1/auth/oauth/google/id-token", config.public_url),
+        _ => format!("{}/api/v1/auth/oauth/{}/authorize", config.public_url, provider),
+    }
+}
+
+/// Whether the profile's claim at `mapping.claim` is `mapping.value`, or a list holding it
+pub fn matches(mapping: &SsoMapping, profile: &Value) -> bool {
+    let is_value = |value: &Value| match value {
+        Value::String(text) => *text == mapping.value,
+        Value::Number(number) => number.to_string() == mapping.value,
+        Value::Bool(flag) => flag.to_string() == mapping.value,
+        _ => false,
+    };
+    match generic_oauth::field(profile, &mapping.claim) {
+        Some(Value::Array(items)) => items.iter().any(is_value),
+        Some(value) => is_value(value),
+        None => false,
+    }
+}
+
+/// Give a user who signed in at `provider` the roles and membership its mapping rules grant
+///
+/// Runs on every sign-in, so losing a claim at the provider takes away what
+/// it granted. Without rules nothing changes; with only role rules,
+/// memberships are left alone, and the other way around. When several rules
+/// grant a role or membership, the highest `priority` wins.
+pub async fn apply_mappings(
+    conn: &mut PgConnection,
+    provider: &str,
+    user_id: Uuid,
+    profile: &Value,
+) -> Result<(), sqlx::Error> {
+    let mappings = sso_mappings::list(conn, provider).await?;
+    let matched: Vec<&SsoMapping> = mappings.iter().filter(|mapping| matches(mapping, profile)).collect();
+
+    let managed_roles: Vec<String> = mappings.iter().filter_map(|mapping| mapping.role.clone()).collect();
+    if !managed_roles.is_empty() {
+        let granted = matched.iter().find_map(|mapping| mapping.role.as_deref());
+        users::sync_managed_role(conn, user_id, granted, &managed_roles).await?;
+    }
+
+    let managed_organizations: Vec<Uuid> = mappings.iter().filter_map(|mapping| mapping.organization_id).collect();
+    if !managed_organizations.is_empty() {
+        let granted = match matched.iter().find_map(|mapping| mapping.organization_id.map(|id| (id, mapping))) {
+            Some((organization_id, mapping)) => {
+                let role = organization_role(conn, organization_id, mapping.organization_role.as_deref()).await?;
+                Some((organization_id, role))
+            }
+            None => None,
+        };
+        let granted = granted.as_ref().map(|(organization_id, role)| (*organization_id, role.as_str()));
+        users::sync_managed_organization(conn, user_id, granted, &managed_organizations).await?;
+    }
+    Ok(())
+}
+
+/// The role a rule gives in its organization; `member` when it names none, or a custom role deleted since
+async fn organization_role(
+    conn: &mut PgConnection,
+    organization_id: Uuid,
+    role: Option<&str>,
+) -> Result<String, sqlx::Error> {
+    let role = role.unwrap_or("member");
+    if OrgRole::parse(role).is_some() || organizations::find_role(conn, organization_id, role).await?.is_some() {
+        return Ok(role.to_string());
+    }
+    Ok("member".to_string())
+}
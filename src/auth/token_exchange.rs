@@ -0,0 +1,74 @@
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::auth::access_tokens::{self, AccessTokens};
+use crate::auth::jwt::JwtService;
+use crate::auth::tokens;
+use crate::config::{AppConfig, TokenExchangePolicy};
+use crate::models::session::Session;
+use crate::repositories::{sessions, users};
+
+/// `grant_type` of token exchange requests
+pub const GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:token-exchange";
+/// The only token type exchanged and issued
+pub const ACCESS_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:access_token";
+
+/// The policy for an audience, if tokens may be exchanged for it at all
+pub fn policy<'a>(config: &'a AppConfig, audience: &str) -> Option<&'a TokenExchangePolicy> {
+    config.token_exchange.iter().find(|policy| policy.audience == audience)
+}
+
+/// The active session behind a user's access token (JWT or opaque)
+///
+/// API keys, action tokens and tokens already exchanged for another service
+/// can't be exchanged.
+pub async fn subject_session(
+    conn: &mut PgConnection,
+    jwt: &JwtService,
+    access_tokens: &AccessTokens,
+    token: &str,
+) -> Result<Option<Session>, sqlx::Error> {
+    let session = if access_tokens::is_opaque_token(token) {
+        sessions::find_active_by_token_hash(conn, &tokens::hash(token), access_tokens.active_since())
+            .await?
+            .filter(|(_, guest)| !guest)
+            .map(|(session, _)| session)
+    } else {
+        let claims = match jwt.verify_session(conn, token).await {
+            Ok(claims) if claims.act.is_none() && !claims.guest => claims,
+            _ => return Ok(None),
+        };
+        match (Uuid::parse_str(&claims.sub), Uuid::parse_str(&claims.sid)) {
+            (Ok(user_id), Ok(session_id)) if users::token_version(conn, user_id).await? == Some(claims.ver) => {
+                sessions::find_active(conn, session_id, user_id, access_tokens.active_since()).await?
+            }
+            _ => None,
+        }
+    };
+    Ok(session.filter(|session| session.audience.is_none()))
+}
+
+/// Scopes for the exchanged token: those requested (default: all allowed), within
+/// both the policy and the subject token's own scopes
+///
+/// Returns the first scope that can't be granted on failure.
+pub fn narrow_scopes(
+    policy: &TokenExchangePolicy,
+    subject_scopes: Option<&[String]>,
+    requested: Option<&str>,
+) -> Result<Vec<String>, String> {
+    let allowed = |scope: &String| {
+        policy.scopes.contains(scope) && subject_scopes.is_none_or(|subject| subject.contains(scope))
+    };
+
+    let mut scopes: Vec<String> = match requested {
+        Some(requested) => requested.split_whitespace().map(str::to_string).collect(),
+        None => policy.scopes.iter().filter(|scope| allowed(scope)).cloned().collect(),
+    };
+    scopes.sort();
+    scopes.dedup();
+    if let Some(denied) = scopes.iter().find(|scope| !allowed(scope)) {
+        return Err(denied.clone());
+    }
+    Ok(scopes)
+}
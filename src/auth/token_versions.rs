@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::cache::SharedCache;
+use crate::config::AppConfig;
+use crate::repositories::users;
+
+/// Users' current token versions, which every JWT must match
+///
+/// Managed as Rocket state. Bumping a user's version (`users::bump_token_version`)
+/// invalidates every JWT issued to them so far, without recording the tokens
+/// themselves. Versions are cached for a few seconds; bumps through `bump`
+/// update the cache right away, so they apply at once in this process (and
+/// on every replica sharing a Redis cache). Bumps elsewhere (the admin CLI)
+/// apply once the cache entry ages out.
+pub struct TokenVersions {
+    ttl: Duration,
+    cache: SharedCache,
+}
+
+impl TokenVersions {
+    pub fn new(ttl: Duration) -> Self {
+        TokenVersions {
+            ttl,
+            cache: SharedCache::memory(),
+        }
+    }
+
+    /// Keep versions in `cache` instead of a cache of their own
+    pub fn with_cache(mut self, cache: SharedCache) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    pub fn from_config(config: &AppConfig) -> Self {
+        TokenVersions::new(Duration::from_secs(config.token_version_cache_seconds))
+    }
+
+    /// The user's current token version, or `None` if the user doesn't exist
+    pub async fn current(&self, conn: &mut PgConnection, user_id: Uuid) -> Result<Option<i32>, sqlx::Error> {
+        if !self.ttl.is_zero()
+            && let Some(version) = self.cache.get(&key(user_id)).await
+        {
+            return Ok(Some(version));
+        }
+
+        let version = users::token_version(conn, user_id).await?;
+        if let Some(version) = version {
+            self.cache.set(&key(user_id), &version, self.ttl).await;
+        }
+        Ok(version)
+    }
+
+    /// Invalidate every JWT issued to the user so far, returning the new version
+    pub async fn bump(&self, conn: &mut PgConnection, user_id: Uuid) -> Result<Option<i32>, sqlx::Error> {
+        let version = users::bump_token_version(conn, user_id).await?;
+        match version {
+            Some(version) => self.cache.set(&key(user_id), &version, self.ttl).await,
+            None => self.cache.delete(&key(user_id)).await,
+        }
+        Ok(version)
+    }
+}
+
+fn key(user_id: Uuid) -> String {
+    format!("token_version:{}", user_id)
+}
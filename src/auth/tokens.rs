@@ -0,0 +1,12 @@
+use sha2::{Digest, Sha256};
+
+/// SHA-256 of a token, hex encoded, for storing tokens at rest
+///
+/// Tokens are random enough not to need a slow hash. A leaked table then
+/// holds nothing that can be used in a reset or verification link. Looking
+/// tokens up by hash also keeps the database's (non constant-time) comparison
+/// from leaking a token through timing, since a guess can't be steered
+/// towards a matching hash prefix.
+pub fn hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
@@ -0,0 +1,553 @@
+use std::fmt;
+use std::marker::PhantomData;
+use std::net::IpAddr;
+use std::ops::Deref;
+
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::status;
+use rocket::serde::json::{Json, Value, json};
+use rocket_db_pools::Connection;
+use serde::Serialize;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::auth::guard::{forbid, AuthenticatedUser, ForbiddenReason};
+use crate::auth::org_permissions::OrgPermission;
+use crate::config::{AppConfig, OwnershipDenial};
+use crate::models::api_key::ApiKey;
+use crate::models::organization::OrgRole;
+use crate::models::user::User;
+use crate::models::user_email::UserEmail;
+use crate::repositories::{api_keys, organizations, permissions, user_emails, users};
+use crate::Postgres;
+
+/// The user asking to do something
+#[derive(Debug, Clone, Serialize)]
+pub struct Subject {
+    pub user_id: Uuid,
+    pub role: String,
+    /// Permissions granted to the role
+    pub permissions: Vec<String>,
+    /// Status of the user's Stripe subscription, see `Subject::is_subscribed`
+    pub subscription_status: Option<String>,
+}
+
+impl Subject {
+    /// Load a user's role, permissions and subscription status; `None` if the user doesn't exist
+    pub async fn load(conn: &mut PgConnection, user_id: Uuid) -> Result<Option<Subject>, sqlx::Error> {
+        let subject = permissions::role_and_permissions(conn, user_id).await?;
+        Ok(subject.map(|(role, permissions, subscription_status)| Subject {
+            user_id,
+            role,
+            permissions,
+            subscription_status,
+        }))
+    }
+
+    /// The user's subscription is paid for or in its trial
+    pub fn is_subscribed(&self) -> bool {
+        matches!(self.subscription_status.as_deref(), Some("active" | "trialing"))
+    }
+}
+
+/// What the action applies to, e.g. `Resource::new("user", id)`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Resource {
+    pub kind: Option<String>,
+    pub id: Option<String>,
+}
+
+impl Resource {
+    pub fn new(kind: &str, id: &str) -> Self {
+        Resource {
+            kind: Some(kind.to_string()),
+            id: Some(id.to_string()),
+        }
+    }
+
+    /// For actions that aren't about a particular resource
+    pub fn none() -> Self {
+        Resource::default()
+    }
+}
+
+/// Details of the request being authorized
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Context {
+    pub method: String,
+    pub path: String,
+    pub ip: Option<IpAddr>,
+}
+
+impl Context {
+    pub fn from_request(request: &Request<'_>) -> Self {
+        Context {
+            method: request.method().as_str().to_string(),
+            path: request.uri().path().to_string(),
+            ip: request.client_ip(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    Deny,
+}
+
+/// The engine couldn't reach a decision (e.g. a remote policy server is down)
+#[derive(Debug)]
+pub struct PolicyError(pub String);
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Policy error: {}", self.0)
+    }
+}
+
+impl std::error::Error for PolicyError {}
+
+/// Decides whether a subject may perform an action on a resource
+///
+/// Actions are permission names like `users.delete`. Implement this to plug
+/// in Casbin, OPA or app-specific rules; routes only ever name the action
+/// and resource, so swapping engines doesn't touch them.
+#[rocket::async_trait]
+pub trait PolicyEngine: Send + Sync {
+    async fn decide(
+        &self,
+        subject: &Subject,
+        action: &str,
+        resource: &Resource,
+        context: &Context,
+    ) -> Result<Decision, PolicyError>;
+}
+
+/// Default engine: allowed when the subject's role has a permission named after the action
+pub struct RolePermissionPolicy;
+
+#[rocket::async_trait]
+impl PolicyEngine for RolePermissionPolicy {
+    async fn decide(
+        &self,
+        subject: &Subject,
+        action: &str,
+        _resource: &Resource,
+        _context: &Context,
+    ) -> Result<Decision, PolicyError> {
+        if subject.permissions.iter().any(|permission| permission == action) {
+            Ok(Decision::Allow)
+        } else {
+            Ok(Decision::Deny)
+        }
+    }
+}
+
+/// The configured policy engine, managed as Rocket state
+///
+/// Pass a different engine to `build_rocket_with_policy` to replace the default.
+pub struct Policy {
+    engine: Box<dyn PolicyEngine>,
+}
+
+impl Policy {
+    pub fn new(engine: impl PolicyEngine + 'static) -> Self {
+        Policy {
+            engine: Box::new(engine),
+        }
+    }
+
+    pub async fn decide(
+        &self,
+        subject: &Subject,
+        action: &str,
+        resource: &Resource,
+        context: &Context,
+    ) -> Result<Decision, PolicyError> {
+        self.engine.decide(subject, action, resource, context).await
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy::new(RolePermissionPolicy)
+    }
+}
+
+/// Request guard for authorization checks inside handlers
+///
+/// Use it when the decision depends on the resource:
+/// ```rust,ignore
+/// #[delete("/projects/<id>")]
+/// async fn delete_project(authz: Authz<'_>, id: &str) -> Result<..., status::Custom<Json<Value>>> {
+///     authz.require("projects.delete", &Resource::new("project", id)).await?;
+///     ...
+/// }
+/// ```
+/// For fixed permissions, `HasPermission<P>` is shorter.
+pub struct Authz<'r> {
+    pub subject: Subject,
+    context: Context,
+    policy: &'r Policy,
+}
+
+impl Authz<'_> {
+    /// Ask the policy engine; `Err` only if it couldn't decide
+    pub async fn allows(&self, action: &str, resource: &Resource) -> Result<bool, PolicyError> {
+        let decision = self.policy.decide(&self.subject, action, resource, &self.context).await?;
+        Ok(decision == Decision::Allow)
+    }
+
+    /// Fail with 403 naming the action unless the policy allows it
+    pub async fn require(&self, action: &str, resource: &Resource) -> Result<(), status::Custom<Json<Value>>> {
+        match self.allows(action, resource).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(status::Custom(
+                Status::Forbidden,
+                Json(json!({
+                    "error": "Missing permission",
+                    "details": format!("This endpoint requires the '{}' permission", action),
+                    "code": "missing_permission"
+                })),
+            )),
+            Err(e) => {
+                eprintln!("{}", e);
+                Err(status::Custom(
+                    Status::InternalServerError,
+                    Json(json!({
+                        "error": "Authorization check failed"
+                    })),
+                ))
+            }
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Authz<'r> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let user = match request.guard::<AuthenticatedUser>().await {
+            Outcome::Success(user) => user,
+            Outcome::Error(e) => return Outcome::Error(e),
+            Outcome::Forward(status) => return Outcome::Forward(status),
+        };
+
+        let user_id = match Uuid::parse_str(&user.user_id) {
+            Ok(id) => id,
+            Err(_) => return Outcome::Error((Status::Unauthorized, ())),
+        };
+
+        let policy = match request.rocket().state::<Policy>() {
+            Some(policy) => policy,
+            None => return Outcome::Error((Status::InternalServerError, ())),
+        };
+
+        let mut db = match request.guard::<Connection<Postgres>>().await {
+            Outcome::Success(db) => db,
+            _ => return Outcome::Error((Status::InternalServerError, ())),
+        };
+
+        match Subject::load(&mut db, user_id).await {
+            Ok(Some(subject)) => Outcome::Success(Authz {
+                subject,
+                context: Context::from_request(request),
+                policy,
+            }),
+            Ok(None) => Outcome::Error((Status::Unauthorized, ())),
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                Outcome::Error((Status::InternalServerError, ()))
+            }
+        }
+    }
+}
+
+/// A resource that belongs to a user, for use with `Owns<T>`
+#[rocket::async_trait]
+pub trait Owned: Sized + Send + Sync {
+    /// Name used in error messages, e.g. "API key"
+    const KIND: &'static str;
+
+    async fn find(conn: &mut PgConnection, id: Uuid) -> Result<Option<Self>, sqlx::Error>;
+
+    fn owner_id(&self) -> Uuid;
+
+    /// Whether the user may act on this resource
+    ///
+    /// Defaults to the owner only; override to also admit, say, members of
+    /// the owning organization.
+    async fn is_accessible_by(&self, _conn: &mut PgConnection, user_id: Uuid) -> Result<bool, sqlx::Error> {
+        Ok(self.owner_id() == user_id)
+    }
+}
+
+/// Why `load_owned` didn't return the resource
+#[derive(Debug)]
+pub enum OwnershipError {
+    NotFound,
+    NotOwner,
+    Database(sqlx::Error),
+}
+
+/// Load a resource by id, checking the user may act on it
+pub async fn load_owned<T: Owned>(conn: &mut PgConnection, user_id: Uuid, id: Uuid) -> Result<T, OwnershipError> {
+    let resource = match T::find(conn, id).await {
+        Ok(Some(resource)) => resource,
+        Ok(None) => return Err(OwnershipError::NotFound),
+        Err(e) => return Err(OwnershipError::Database(e)),
+    };
+
+    match resource.is_accessible_by(conn, user_id).await {
+        Ok(true) => Ok(resource),
+        Ok(false) => Err(OwnershipError::NotOwner),
+        Err(e) => Err(OwnershipError::Database(e)),
+    }
+}
+
+/// Request guard for a resource the signed-in user owns, loaded from the `<id>` route parameter
+///
+/// Name the parameter `<_id>` when the handler doesn't need it otherwise.
+/// Fails with 401 when the request isn't authenticated and 404 when there's
+/// no such resource. Someone else's resource is also a 404 by default, so
+/// ids can't be probed; set `ROCKET_OWNERSHIP_DENIAL=forbidden` for a 403
+/// with code `not_owner` instead.
+/// ```rust,ignore
+/// #[delete("/api-keys/<_id>")]
+/// async fn revoke(key: Owns<ApiKey>, _id: &str) -> ... {
+///     // key.resource is the caller's key
+/// }
+/// ```
+/// Outside of routes with an `<id>` parameter, call `load_owned` directly.
+pub struct Owns<T> {
+    pub user_id: Uuid,
+    pub resource: T,
+}
+
+impl<T> Owns<T> {
+    pub fn into_inner(self) -> T {
+        self.resource
+    }
+}
+
+impl<T> Deref for Owns<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.resource
+    }
+}
+
+/// The `<id>` (or `<_id>`) parameter of the matched route, if it has one
+fn id_param(request: &Request<'_>) -> Option<Uuid> {
+    let route = request.route()?;
+    let index = route
+        .uri
+        .unmounted_origin
+        .path()
+        .segments()
+        .position(|segment| segment == "<id>" || segment == "<_id>")?;
+    Uuid::parse_str(request.routed_segment(index)?).ok()
+}
+
+#[rocket::async_trait]
+impl<'r, T: Owned> FromRequest<'r> for Owns<T> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let user = match request.guard::<AuthenticatedUser>().await {
+            Outcome::Success(user) => user,
+            Outcome::Error(e) => return Outcome::Error(e),
+            Outcome::Forward(status) => return Outcome::Forward(status),
+        };
+
+        let user_id = match Uuid::parse_str(&user.user_id) {
+            Ok(id) => id,
+            Err(_) => return Outcome::Error((Status::Unauthorized, ())),
+        };
+
+        let id = match id_param(request) {
+            Some(id) => id,
+            None => return Outcome::Error((Status::NotFound, ())),
+        };
+
+        let mut db = match request.guard::<Connection<Postgres>>().await {
+            Outcome::Success(db) => db,
+            _ => return Outcome::Error((Status::InternalServerError, ())),
+        };
+
+        match load_owned::<T>(&mut db, user_id, id).await {
+            Ok(resource) => Outcome::Success(Owns { user_id, resource }),
+            Err(OwnershipError::NotFound) => Outcome::Error((Status::NotFound, ())),
+            Err(OwnershipError::NotOwner) => {
+                let denial = request
+                    .rocket()
+                    .state::<AppConfig>()
+                    .map(|config| config.ownership_denial)
+                    .unwrap_or(OwnershipDenial::NotFound);
+                match denial {
+                    OwnershipDenial::NotFound => Outcome::Error((Status::NotFound, ())),
+                    OwnershipDenial::Forbidden => forbid(request, ForbiddenReason::NotOwner(T::KIND)),
+                }
+            }
+            Err(OwnershipError::Database(e)) => {
+                eprintln!("Database error: {}", e);
+                Outcome::Error((Status::InternalServerError, ()))
+            }
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Owned for ApiKey {
+    const KIND: &'static str = "API key";
+
+    async fn find(conn: &mut PgConnection, id: Uuid) -> Result<Option<Self>, sqlx::Error> {
+        api_keys::find(conn, id).await
+    }
+
+    fn owner_id(&self) -> Uuid {
+        self.user_id
+    }
+}
+
+#[rocket::async_trait]
+impl Owned for UserEmail {
+    const KIND: &'static str = "email address";
+
+    async fn find(conn: &mut PgConnection, id: Uuid) -> Result<Option<Self>, sqlx::Error> {
+        user_emails::find_by_id(conn, id).await
+    }
+
+    fn owner_id(&self) -> Uuid {
+        self.user_id
+    }
+}
+
+/// Request guard for owners and admins of the organization in the route's `<id>` parameter
+///
+/// Platform admins pass as well. Fails with 401 when the request isn't
+/// authenticated and 403 (code `not_org_admin`) for anyone else, members
+/// of the organization included.
+/// ```rust,ignore
+/// #[get("/<_id>/signing-keys")]
+/// async fn list(org: OrgAdmin, _id: &str) -> ... { /* org.organization_id */ }
+/// ```
+pub struct OrgAdmin {
+    pub organization_id: Uuid,
+    pub user_id: Uuid,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for OrgAdmin {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let (user, organization_id, _db) = match org_request(request).await {
+            Outcome::Success(loaded) => loaded,
+            Outcome::Error(e) => return Outcome::Error(e),
+            Outcome::Forward(status) => return Outcome::Forward(status),
+        };
+
+        let org_admin = user.organization_id == Some(organization_id)
+            && OrgRole::parse(&user.organization_role).is_some_and(|role| role.is_admin());
+        if org_admin || user.role == "admin" {
+            Outcome::Success(OrgAdmin { organization_id, user_id: user.id })
+        } else {
+            forbid(request, ForbiddenReason::NotOrgAdmin)
+        }
+    }
+}
+
+/// Request guard for members of the organization in the route's `<id>` parameter allowed `P`
+///
+/// Owners, admins and platform admins are allowed everything; other members
+/// only what their custom role grants, read on each request. Fails with 401
+/// when the request isn't authenticated, 403 (code `missing_org_permission`)
+/// for members without the permission and 403 (code `not_org_admin`) for
+/// users outside the organization.
+/// ```rust,ignore
+/// #[get("/<_id>/audit")]
+/// async fn audit(org: OrgPermitted<ViewAudit>, _id: &str) -> ... { /* org.organization_id */ }
+/// ```
+pub struct OrgPermitted<P: OrgPermission> {
+    pub organization_id: Uuid,
+    pub user_id: Uuid,
+    permission: PhantomData<P>,
+}
+
+#[rocket::async_trait]
+impl<'r, P: OrgPermission> FromRequest<'r> for OrgPermitted<P> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let (user, organization_id, mut db) = match org_request(request).await {
+            Outcome::Success(loaded) => loaded,
+            Outcome::Error(e) => return Outcome::Error(e),
+            Outcome::Forward(status) => return Outcome::Forward(status),
+        };
+
+        let permitted = OrgPermitted {
+            organization_id,
+            user_id: user.id,
+            permission: PhantomData,
+        };
+        if user.role == "admin" {
+            return Outcome::Success(permitted);
+        }
+        if user.organization_id != Some(organization_id) {
+            return forbid(request, ForbiddenReason::NotOrgAdmin);
+        }
+
+        let allowed = match OrgRole::parse(&user.organization_role) {
+            Some(role) => role.is_admin(),
+            None => match organizations::find_role(&mut db, organization_id, &user.organization_role).await {
+                Ok(role) => role.is_some_and(|role| role.permissions.iter().any(|name| name == P::NAME)),
+                Err(e) => {
+                    eprintln!("Database error: {}", e);
+                    return Outcome::Error((Status::InternalServerError, ()));
+                }
+            },
+        };
+        if allowed {
+            Outcome::Success(permitted)
+        } else {
+            forbid(request, ForbiddenReason::MissingOrgPermission(P::NAME))
+        }
+    }
+}
+
+/// The authenticated user and the organization in the route's `<id>` parameter
+async fn org_request(request: &Request<'_>) -> Outcome<(User, Uuid, Connection<Postgres>), ()> {
+    let user = match request.guard::<AuthenticatedUser>().await {
+        Outcome::Success(user) => user,
+        Outcome::Error(e) => return Outcome::Error(e),
+        Outcome::Forward(status) => return Outcome::Forward(status),
+    };
+
+    let user_id = match Uuid::parse_str(&user.user_id) {
+        Ok(id) => id,
+        Err(_) => return Outcome::Error((Status::Unauthorized, ())),
+    };
+
+    let organization_id = match id_param(request) {
+        Some(id) => id,
+        None => return Outcome::Error((Status::NotFound, ())),
+    };
+
+    let mut db = match request.guard::<Connection<Postgres>>().await {
+        Outcome::Success(db) => db,
+        _ => return Outcome::Error((Status::InternalServerError, ())),
+    };
+
+    match users::find_by_id(&mut db, user_id).await {
+        Ok(Some(user)) => Outcome::Success((user, organization_id, db)),
+        Ok(None) => Outcome::Error((Status::Unauthorized, ())),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Outcome::Error((Status::InternalServerError, ()))
+        }
+    }
+}
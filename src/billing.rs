@@ -0,0 +1,174 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket_db_pools::Database;
+use serde::Deserialize;
+use sqlx::{PgConnection, PgPool};
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+use crate::config::{AppConfig, StripeConfig};
+use crate::events::{EventBus, SecurityEventKind};
+use crate::repositories::users;
+use crate::Postgres;
+
+/// How old a webhook's signature timestamp may be, against replays
+pub const WEBHOOK_TOLERANCE_SECONDS: i64 = 300;
+
+#[derive(Debug, Deserialize)]
+struct Customer {
+    id: String,
+}
+
+/// The subset of Stripe's API used for billing
+#[derive(Clone)]
+pub struct StripeClient {
+    config: StripeConfig,
+    client: reqwest::Client,
+}
+
+impl StripeClient {
+    pub fn new(config: StripeConfig) -> Self {
+        StripeClient {
+            config,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Create a customer for the user, returning its id
+    ///
+    /// The user id is the idempotency key, so instances handling the same
+    /// registration get the same customer back instead of creating another.
+    pub async fn create_customer(&self, user_id: Uuid, email: &str) -> Result<String, reqwest::Error> {
+        let customer: Customer = self
+            .client
+            .post(format!("{}/v1/customers", self.config.api_url))
+            .bearer_auth(&self.config.secret_key)
+            .header("Idempotency-Key", format!("customer-{}", user_id))
+            .form(&[("email", email), ("metadata[user_id]", &user_id.to_string())])
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(customer.id)
+    }
+}
+
+/// Give a newly registered user a Stripe customer, unless they have one
+///
+/// Guests get none until they upgrade.
+pub async fn create_customer(conn: &mut PgConnection, stripe: &StripeClient, user_id: Uuid) -> Result<(), sqlx::Error> {
+    let Some(user) = users::find_by_id(conn, user_id).await? else {
+        return Ok(());
+    };
+    if user.stripe_customer_id.is_some() || user.is_guest() {
+        return Ok(());
+    }
+
+    match stripe.create_customer(user.id, &user.email).await {
+        Ok(customer_id) => {
+            users::set_stripe_customer_if_unset(conn, user.id, &customer_id).await?;
+        }
+        Err(e) => eprintln!("Failed to create Stripe customer for {}: {}", user.id, e),
+    }
+    Ok(())
+}
+
+/// Check a `Stripe-Signature` header against the raw webhook payload
+///
+/// The header holds a timestamp `t` and one or more `v1` signatures
+/// (HMAC SHA-256 of `<t>.<payload>`); several are sent while the endpoint's
+/// secret is being rolled. Returns when the payload was signed, `None` if
+/// no signature matches or it's older than `WEBHOOK_TOLERANCE_SECONDS`.
+pub fn verify_webhook(secret: &str, header: &str, payload: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let mut timestamp = None;
+    let mut signatures = Vec::new();
+    for (key, value) in header.split(',').filter_map(|part| part.trim().split_once('=')) {
+        match key {
+            "t" => timestamp = value.parse::<i64>().ok(),
+            "v1" => signatures.extend(hex::decode(value).ok()),
+            _ => {}
+        }
+    }
+
+    let timestamp = timestamp?;
+    if (now.timestamp() - timestamp).abs() > WEBHOOK_TOLERANCE_SECONDS {
+        return None;
+    }
+    let signed = format!("{}.{}", timestamp, payload);
+    let authentic = signatures.iter().any(|signature| {
+        let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+        mac.update(signed.as_bytes());
+        mac.verify_slice(signature).is_ok()
+    });
+    authentic.then(|| DateTime::from_timestamp(timestamp, 0)).flatten()
+}
+
+/// The `Stripe-Signature` header of a webhook delivery
+pub struct StripeSignature<'r>(pub &'r str);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for StripeSignature<'r> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.headers().get_one("Stripe-Signature") {
+            Some(signature) => Outcome::Success(StripeSignature(signature)),
+            None => Outcome::Forward(Status::BadRequest),
+        }
+    }
+}
+
+/// Fairing that creates Stripe customers for `user_registered` events from the managed `EventBus`
+///
+/// Only started with `ROCKET_STRIPE_SECRET_KEY` set. Every instance
+/// receives every event; Stripe's idempotency keys keep them from creating
+/// more than one customer per user. Users who register while no instance is
+/// running don't get one until they're created some other way.
+pub fn fairing() -> AdHoc {
+    AdHoc::on_liftoff("Stripe Customers", |rocket| {
+        Box::pin(async move {
+            let (Some(config), Some(bus), Some(db)) =
+                (rocket.state::<AppConfig>(), rocket.state::<EventBus>(), Postgres::fetch(rocket))
+            else {
+                eprintln!("Stripe customers not started: missing AppConfig, EventBus or database");
+                return;
+            };
+            let Some(stripe) = config.stripe.clone() else {
+                return;
+            };
+
+            let (stripe, pool) = (StripeClient::new(stripe), PgPool::clone(db));
+            let mut events = bus.subscribe();
+            rocket::tokio::spawn(async move {
+                loop {
+                    let event = match events.recv().await {
+                        Ok(event) => event,
+                        Err(RecvError::Lagged(missed)) => {
+                            eprintln!("Stripe customers fell behind; {} event(s) skipped", missed);
+                            continue;
+                        }
+                        Err(RecvError::Closed) => return,
+                    };
+                    if !matches!(event.kind, SecurityEventKind::UserRegistered) {
+                        continue;
+                    }
+
+                    let created = match pool.acquire().await {
+                        Ok(mut conn) => create_customer(&mut conn, &stripe, event.user_id).await,
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = created {
+                        eprintln!("Database error: {}", e);
+                    }
+                }
+            });
+        })
+    })
+}
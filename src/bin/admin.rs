@@ -0,0 +1,228 @@
+use std::io::{self, BufRead, Write};
+use std::process::ExitCode;
+
+use chrono::{Duration, Utc};
+use clap::{Parser, Subcommand};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use rocket_auth_boilerplate::auth::password::PasswordHasher;
+use rocket_auth_boilerplate::config::AppConfig;
+use rocket_auth_boilerplate::events::{self, SecurityEvent, SecurityEventKind};
+use rocket_auth_boilerplate::{db, migrations};
+use rocket_auth_boilerplate::models::user::User;
+use rocket_auth_boilerplate::repositories::{invitations, sessions, users};
+use rocket_auth_boilerplate::retention;
+
+/// Operational tasks for the auth service, run against the configured database
+#[derive(Parser)]
+#[command(name = "admin")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create a user with the admin role
+    CreateAdminUser {
+        #[arg(long)]
+        email: String,
+        /// Password; read from stdin when omitted
+        #[arg(long)]
+        password: Option<String>,
+    },
+    /// Set a new password for a user; tokens issued before stop working
+    ResetPassword {
+        #[arg(long)]
+        email: String,
+        /// Password; read from stdin when omitted
+        #[arg(long)]
+        password: Option<String>,
+    },
+    /// Revoke all sessions of a user, logging them out everywhere
+    RevokeSessions {
+        #[arg(long)]
+        email: String,
+    },
+    /// Create an invitation code for invite-only registration
+    CreateInvitation {
+        /// Restrict the invitation to this address
+        #[arg(long)]
+        email: Option<String>,
+        /// Days until the invitation expires
+        #[arg(long, default_value_t = 7)]
+        days: i64,
+    },
+    /// List users
+    ListUsers {
+        #[arg(long, default_value_t = 50)]
+        limit: i64,
+        #[arg(long, default_value_t = 0)]
+        offset: i64,
+        /// Only users who haven't logged in for this many days
+        #[arg(long)]
+        inactive_days: Option<i64>,
+    },
+    /// Delete data older than the configured retention periods
+    Cleanup {
+        /// Only report what would be deleted
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Run database migrations
+    RunMigrations,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    dotenv::dotenv().ok();
+    let cli = Cli::parse();
+
+    let config = match AppConfig::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let pool = match db::connect(&config.database_url, &config.db_pool).await {
+        Ok(pool) => pool,
+        Err(e) => {
+            eprintln!("Failed to connect to database: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let passwords = PasswordHasher::from_config(&config);
+
+    match run(cli.command, &pool, &config, &passwords).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("Error: {}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(command: Command, pool: &PgPool, config: &AppConfig, passwords: &PasswordHasher) -> Result<(), String> {
+    let mut conn = pool.acquire().await.map_err(db_error)?;
+
+    match command {
+        Command::CreateAdminUser { email, password } => {
+            if users::email_exists(&mut conn, &email).await.map_err(db_error)? {
+                return Err(format!("User with email {} already exists", email));
+            }
+
+            let password = password_or_prompt(password)?;
+            let password_hash = passwords.hash(&password).await.map_err(|e| e.to_string())?;
+            let user = users::create(&mut conn, &email, &password_hash, "admin")
+                .await
+                .map_err(db_error)?;
+
+            println!("✓ Created admin user {} ({})", user.email, user.id);
+        }
+        Command::ResetPassword { email, password } => {
+            let user = find_user(&mut conn, &email).await?;
+
+            let password = password_or_prompt(password)?;
+            let password_hash = passwords.hash(&password).await.map_err(|e| e.to_string())?;
+            users::update_password(&mut conn, user.id, &password_hash)
+                .await
+                .map_err(db_error)?;
+            users::bump_token_version(&mut conn, user.id)
+                .await
+                .map_err(db_error)?;
+            events::emit_event(&mut conn, SecurityEvent::new(user.id, SecurityEventKind::PasswordChanged)).await;
+
+            println!("✓ Password reset for {}", user.email);
+        }
+        Command::RevokeSessions { email } => {
+            let user = find_user(&mut conn, &email).await?;
+            let revoked = sessions::revoke_all_for_user(&mut conn, user.id)
+                .await
+                .map_err(db_error)?;
+            events::emit_event(&mut conn, SecurityEvent::new(user.id, SecurityEventKind::AllSessionsRevoked { count: revoked })).await;
+
+            println!("✓ Revoked {} session(s) for {}", revoked, user.email);
+        }
+        Command::CreateInvitation { email, days } => {
+            let expires_at = Utc::now() + Duration::days(days);
+            let code = Uuid::new_v4().to_string();
+            let invitation = invitations::create(&mut conn, &code, email.as_deref(), None, expires_at)
+                .await
+                .map_err(db_error)?;
+
+            println!(
+                "✓ Invitation {} created (expires {})",
+                invitation.code,
+                invitation.expires_at.to_rfc3339()
+            );
+        }
+        Command::ListUsers { limit, offset, inactive_days } => {
+            let inactive_since = inactive_days.map(|days| Utc::now() - Duration::days(days));
+            let users = users::list(&mut conn, limit, offset, inactive_since).await.map_err(db_error)?;
+
+            println!("{:<36}  {:<8}  {:<25}  {:<25}  {:>6}  EMAIL", "ID", "ROLE", "CREATED", "LAST LOGIN", "LOGINS");
+            for user in users {
+                println!(
+                    "{:<36}  {:<8}  {:<25}  {:<25}  {:>6}  {}",
+                    user.id,
+                    user.role,
+                    user.created_at.to_rfc3339(),
+                    user.last_login_at.map_or_else(|| "never".to_string(), |at| at.to_rfc3339()),
+                    user.login_count,
+                    user.email
+                );
+            }
+        }
+        Command::Cleanup { dry_run } => {
+            if !retention::is_enabled(config) {
+                return Err("No retention period is set".to_string());
+            }
+            let report = retention::cleanup(&mut conn, config, dry_run).await.map_err(db_error)?;
+
+            let verb = if dry_run { "Would delete" } else { "Deleted" };
+            println!(
+                "✓ {} {} audit event(s), {} login attempt(s) and {} session(s)",
+                verb, report.audit_events, report.login_attempts, report.sessions
+            );
+        }
+        Command::RunMigrations => {
+            migrations::run_migrations(pool).await.map_err(db_error)?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn find_user(conn: &mut sqlx::PgConnection, email: &str) -> Result<User, String> {
+    users::find_by_email(conn, email)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| format!("No user with email {}", email))
+}
+
+/// Use the given password, or read one line from stdin
+fn password_or_prompt(password: Option<String>) -> Result<String, String> {
+    let password = match password {
+        Some(password) => password,
+        None => {
+            print!("Password: ");
+            io::stdout().flush().map_err(|e| e.to_string())?;
+            let mut line = String::new();
+            io::stdin().lock().read_line(&mut line).map_err(|e| e.to_string())?;
+            line.trim_end_matches(['\r', '\n']).to_string()
+        }
+    };
+
+    if password.len() < 6 {
+        return Err("Password must be at least 6 characters long".to_string());
+    }
+    Ok(password)
+}
+
+fn db_error(e: sqlx::Error) -> String {
+    format!("Database error: {}", e)
+}
@@ -0,0 +1,39 @@
+use rocket_auth_boilerplate::auth::jwt::JwtService;
+use rocket_auth_boilerplate::config::{AppConfig, DbPoolConfig};
+use rocket_auth_boilerplate::{build_rocket, db, lambda, migrations};
+
+/// Entry point for AWS Lambda (build with `--features lambda`)
+///
+/// Configured from the function's environment like the server. The request
+/// pool connects on first use, so cold starts that don't need the database
+/// don't wait for it, and migrations only run when this build's haven't
+/// been applied yet.
+#[tokio::main]
+async fn main() -> Result<(), lambda_runtime::Error> {
+    dotenv::dotenv().ok();
+
+    let mut config = AppConfig::from_env().expect("Invalid configuration");
+    config.db_pool.lazy_connect = true;
+
+    let jwt = JwtService::from_config(&config).await
+        .expect("Failed to initialize JWT signer");
+
+    // Holding the migration lock takes a connection of its own
+    let migration_pool = db::connect(
+        &config.database_url,
+        &DbPoolConfig {
+            max_connections: config.db_pool.max_connections.max(2),
+            ..config.db_pool
+        },
+    )
+    .await
+    .expect("Failed to connect to database");
+    let migrated = migrations::run_if_needed(&migration_pool).await
+        .expect("Failed to run migrations");
+    if !migrated {
+        println!("✓ Database migrations already applied");
+    }
+    migration_pool.close().await;
+
+    lambda::run(build_rocket(config, jwt)).await
+}
@@ -0,0 +1,192 @@
+use std::fmt;
+use std::io;
+use std::ops::{Deref, DerefMut};
+
+use rocket::data::{self, ByteUnit, FromData, Limits};
+use rocket::figment::Figment;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{Data, Request};
+use serde::de::DeserializeOwned;
+
+use crate::config::{AppConfig, BodyLimits};
+use crate::errors::ErrorResponse;
+
+/// Handlers taking passwords or other short credentials, held to the credentials limit
+pub const CREDENTIAL_ROUTES: &[&str] = &[
+    "register",
+    "login",
+    "login_mfa",
+    "forgot_password",
+    "reset_password",
+    "change_password",
+    "upgrade_guest",
+    "request_email_change",
+    "request_account_deletion",
+    "request_recovery",
+];
+
+/// Handlers taking bulk input, allowed the bulk limit
+pub const BULK_ROUTES: &[&str] = &["replay_audit_events"];
+
+/// Set Rocket's `json` limit, and a `json/<handler>` limit for each credential and bulk handler
+pub fn configure(figment: Figment, limits: &BodyLimits) -> Figment {
+    let figment = figment.merge(("limits.json", limits.json.as_u64()));
+    let routes = CREDENTIAL_ROUTES
+        .iter()
+        .map(|route| (route, limits.credentials))
+        .chain(BULK_ROUTES.iter().map(|route| (route, limits.bulk)));
+    routes.fold(figment, |figment, (route, limit)| {
+        figment.merge((format!("limits.json/{}", route), limit.as_u64()))
+    })
+}
+
+/// The limit for JSON bodies sent to the handler `request` was routed to
+fn json_limit(request: &Request<'_>) -> ByteUnit {
+    let limits = request.limits();
+    request
+        .route()
+        .and_then(|route| route.name.as_deref())
+        .and_then(|name| limits.get(format!("json/{}", name)))
+        .or_else(|| limits.get("json"))
+        .unwrap_or(Limits::JSON)
+}
+
+/// Why a request body was turned away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    TooLarge { limit: ByteUnit },
+    TooDeep { max_depth: usize },
+}
+
+#[derive(Debug)]
+pub enum BodyError {
+    Rejected(Rejection),
+    Io(io::Error),
+    Parse(serde_json::Error),
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BodyError::Rejected(Rejection::TooLarge { limit }) => write!(f, "body larger than {}", limit),
+            BodyError::Rejected(Rejection::TooDeep { max_depth }) => {
+                write!(f, "JSON nested deeper than {} levels", max_depth)
+            }
+            BodyError::Io(e) => write!(f, "i/o error: {}", e),
+            BodyError::Parse(e) => write!(f, "parse error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BodyError {}
+
+/// A JSON request body, read up to its handler's limit
+///
+/// Use this rather than `Json` as a data guard. Bodies over the limit, or
+/// nested deeper than `BodyLimits::json_depth`, fail with 413 and are answered
+/// by [`payload_too_large`]; like `Json`, malformed JSON fails with 400 and
+/// JSON of the wrong shape with 422.
+#[derive(Debug)]
+pub struct JsonBody<T>(pub T);
+
+impl<T> JsonBody<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for JsonBody<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for JsonBody<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+fn reject<'r, T>(request: &'r Request<'_>, rejection: Rejection) -> data::Outcome<'r, T, BodyError> {
+    request.local_cache(|| Some(rejection));
+    data::Outcome::Error((Status::PayloadTooLarge, BodyError::Rejected(rejection)))
+}
+
+#[rocket::async_trait]
+impl<'r, T: DeserializeOwned> FromData<'r> for JsonBody<T> {
+    type Error = BodyError;
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let limit = json_limit(request);
+        let body = match data.open(limit).into_bytes().await {
+            Ok(body) if body.is_complete() => body.into_inner(),
+            Ok(_) => return reject(request, Rejection::TooLarge { limit }),
+            Err(e) => return data::Outcome::Error((Status::BadRequest, BodyError::Io(e))),
+        };
+
+        let max_depth = request
+            .rocket()
+            .state::<AppConfig>()
+            .map_or(BodyLimits::default().json_depth, |config| config.body_limits.json_depth);
+        if nesting_depth(&body) > max_depth {
+            return reject(request, Rejection::TooDeep { max_depth });
+        }
+
+        match serde_json::from_slice(&body) {
+            Ok(value) => data::Outcome::Success(JsonBody(value)),
+            Err(e) if e.classify() == serde_json::error::Category::Data => {
+                data::Outcome::Error((Status::UnprocessableEntity, BodyError::Parse(e)))
+            }
+            Err(e) => data::Outcome::Error((Status::BadRequest, BodyError::Parse(e))),
+        }
+    }
+}
+
+/// Deepest nesting of arrays and objects in `json`, counted without parsing it
+fn nesting_depth(json: &[u8]) -> usize {
+    let (mut depth, mut deepest) = (0usize, 0usize);
+    let (mut in_string, mut escaped) = (false, false);
+    for &byte in json {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                deepest = deepest.max(depth);
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    deepest
+}
+
+/// JSON body for 413 responses, naming the limit when a [`JsonBody`] hit it
+#[catch(413)]
+pub fn payload_too_large(request: &Request<'_>) -> Json<ErrorResponse> {
+    let response = match request.local_cache(|| None::<Rejection>) {
+        Some(Rejection::TooLarge { limit }) => ErrorResponse::with_details(
+            "Request body too large".to_string(),
+            format!("This endpoint accepts at most {} bytes", limit.as_u64()),
+        )
+        .with_code("payload_too_large"),
+        Some(Rejection::TooDeep { max_depth }) => ErrorResponse::with_details(
+            "Request body nested too deeply".to_string(),
+            format!("JSON may nest arrays and objects at most {} levels deep", max_depth),
+        )
+        .with_code("json_too_deep"),
+        None => ErrorResponse::new("Request body too large".to_string()).with_code("payload_too_large"),
+    };
+    Json(response)
+}
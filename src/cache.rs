@@ -0,0 +1,230 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use moka::Expiry;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::config::{AppConfig, CacheBackend};
+
+/// Prepended to every key, so a shared Redis can hold other applications' keys too
+const KEY_PREFIX: &str = "rocket-auth:";
+
+#[derive(Debug)]
+pub enum CacheError {
+    /// The backend couldn't be reached or refused the command
+    Backend(String),
+    /// A value couldn't be encoded or decoded
+    Encoding(serde_json::Error),
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheError::Backend(message) => write!(f, "Cache backend error: {}", message),
+            CacheError::Encoding(e) => write!(f, "Cache encoding error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CacheError {}
+
+/// Key-value store for lookups that would otherwise go to the database
+///
+/// Implement this to keep cached lookups somewhere else; values are opaque
+/// bytes that expire after their time to live.
+#[rocket::async_trait]
+pub trait Cache: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, CacheError>;
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Duration) -> Result<(), CacheError>;
+
+    async fn delete(&self, key: &str) -> Result<(), CacheError>;
+}
+
+/// The configured cache, shared by everything that caches lookups
+///
+/// Managed as Rocket state. Values are stored as JSON. A failing cache is
+/// never fatal: errors are logged and treated as misses, so lookups fall
+/// back to the database.
+#[derive(Clone)]
+pub struct SharedCache(Arc<dyn Cache>);
+
+impl SharedCache {
+    pub fn new(cache: impl Cache + 'static) -> Self {
+        SharedCache(Arc::new(cache))
+    }
+
+    /// An in-memory cache holding up to 10,000 entries
+    pub fn memory() -> Self {
+        SharedCache::new(MemoryCache::new(10_000))
+    }
+
+    /// Build the cache selected in configuration
+    pub fn from_config(config: &AppConfig) -> Result<Self, CacheError> {
+        match &config.cache {
+            CacheBackend::Memory { capacity } => Ok(SharedCache::new(MemoryCache::new(*capacity))),
+            #[cfg(feature = "redis")]
+            CacheBackend::Redis { url } => Ok(SharedCache::new(RedisCache::new(url)?)),
+            #[cfg(not(feature = "redis"))]
+            CacheBackend::Redis { .. } => Err(CacheError::Backend(
+                "ROCKET_CACHE=redis requires building with the `redis` feature".to_string(),
+            )),
+        }
+    }
+
+    /// The value cached under `key`, if any
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let result = match self.0.get(&prefixed(key)).await {
+            Ok(Some(bytes)) => serde_json::from_slice(&bytes).map(Some).map_err(CacheError::Encoding),
+            Ok(None) => Ok(None),
+            Err(e) => Err(e),
+        };
+        result.unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            None
+        })
+    }
+
+    /// Cache `value` under `key` for `ttl`; a zero `ttl` caches nothing
+    pub async fn set<T: Serialize>(&self, key: &str, value: &T, ttl: Duration) {
+        if ttl.is_zero() {
+            return;
+        }
+        let result = match serde_json::to_vec(value) {
+            Ok(bytes) => self.0.set(&prefixed(key), bytes, ttl).await,
+            Err(e) => Err(CacheError::Encoding(e)),
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+        }
+    }
+
+    pub async fn delete(&self, key: &str) {
+        if let Err(e) = self.0.delete(&prefixed(key)).await {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+fn prefixed(key: &str) -> String {
+    format!("{}{}", KEY_PREFIX, key)
+}
+
+/// Entries expire after the time to live they were stored with
+struct EntryTtl;
+
+impl Expiry<String, (Vec<u8>, Duration)> for EntryTtl {
+    fn expire_after_create(&self, _key: &String, value: &(Vec<u8>, Duration), _created_at: Instant) -> Option<Duration> {
+        Some(value.1)
+    }
+
+    fn expire_after_update(
+        &self,
+        _key: &String,
+        value: &(Vec<u8>, Duration),
+        _updated_at: Instant,
+        _duration_until_expiry: Option<Duration>,
+    ) -> Option<Duration> {
+        Some(value.1)
+    }
+}
+
+/// Cache in this process (moka), for single-node deployments
+///
+/// Each replica caches on its own, so changes made elsewhere reach it only
+/// once its entries expire.
+pub struct MemoryCache {
+    entries: moka::future::Cache<String, (Vec<u8>, Duration)>,
+}
+
+impl MemoryCache {
+    /// A cache holding at most `capacity` entries, evicting the least used
+    pub fn new(capacity: u64) -> Self {
+        MemoryCache {
+            entries: moka::future::Cache::builder()
+                .max_capacity(capacity)
+                .expire_after(EntryTtl)
+                .build(),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Cache for MemoryCache {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, CacheError> {
+        Ok(self.entries.get(key).await.map(|(value, _)| value))
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Duration) -> Result<(), CacheError> {
+        self.entries.insert(key.to_string(), (value, ttl)).await;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), CacheError> {
+        self.entries.invalidate(key).await;
+        Ok(())
+    }
+}
+
+/// Cache on a Redis server shared by every replica, for clustered deployments
+///
+/// Connects on first use and reconnects by itself after connection errors.
+#[cfg(feature = "redis")]
+pub struct RedisCache {
+    client: redis::Client,
+    connection: tokio::sync::OnceCell<redis::aio::ConnectionManager>,
+}
+
+#[cfg(feature = "redis")]
+impl RedisCache {
+    pub fn new(url: &str) -> Result<Self, CacheError> {
+        Ok(RedisCache {
+            client: redis::Client::open(url).map_err(|e| CacheError::Backend(e.to_string()))?,
+            connection: tokio::sync::OnceCell::new(),
+        })
+    }
+
+    async fn connection(&self) -> Result<redis::aio::ConnectionManager, CacheError> {
+        self.connection
+            .get_or_try_init(|| redis::aio::ConnectionManager::new(self.client.clone()))
+            .await
+            .cloned()
+            .map_err(|e| CacheError::Backend(e.to_string()))
+    }
+}
+
+#[cfg(feature = "redis")]
+#[rocket::async_trait]
+impl Cache for RedisCache {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, CacheError> {
+        let mut conn = self.connection().await?;
+        redis::cmd("GET")
+            .arg(key)
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| CacheError::Backend(e.to_string()))
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Duration) -> Result<(), CacheError> {
+        let mut conn = self.connection().await?;
+        redis::cmd("SET")
+            .arg(key)
+            .arg(value)
+            .arg("PX")
+            .arg(ttl.as_millis().max(1) as u64)
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| CacheError::Backend(e.to_string()))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), CacheError> {
+        let mut conn = self.connection().await?;
+        redis::cmd("DEL")
+            .arg(key)
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| CacheError::Backend(e.to_string()))
+    }
+}
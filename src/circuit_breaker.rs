@@ -0,0 +1,117 @@
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+/// Whether calls are let through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// Calls go through
+    Closed,
+    /// Too many calls failed; calls fail fast until the open period is over
+    Open,
+    /// The open period is over; one probe call decides whether to close again
+    HalfOpen,
+}
+
+impl fmt::Display for CircuitState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "half_open",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    /// When the half-open probe was let through, while it hasn't reported back
+    probe_started: Option<Instant>,
+}
+
+/// Stops calling a dependency that keeps failing, then probes until it recovers
+///
+/// After `failure_threshold` failures in a row the breaker opens and
+/// [`allow`](Self::allow) refuses calls for `open_for`. Then a single probe
+/// is let through: success closes the breaker, failure opens it again. A
+/// probe that never reports back is replaced after another `open_for`.
+pub struct CircuitBreaker {
+    name: &'static str,
+    failure_threshold: u32,
+    open_for: Duration,
+    inner: Mutex<Inner>,
+}
+
+impl CircuitBreaker {
+    /// A `failure_threshold` of 0 never opens
+    pub fn new(name: &'static str, failure_threshold: u32, open_for: Duration) -> Self {
+        CircuitBreaker {
+            name,
+            failure_threshold,
+            open_for,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        match self.inner.lock().unwrap().opened_at {
+            None => CircuitState::Closed,
+            Some(opened_at) if opened_at.elapsed() < self.open_for => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    pub fn consecutive_failures(&self) -> u32 {
+        self.inner.lock().unwrap().consecutive_failures
+    }
+
+    /// Whether a call may go ahead; callers that get `true` must report back
+    pub fn allow(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(opened_at) = inner.opened_at else {
+            return true;
+        };
+        if opened_at.elapsed() < self.open_for {
+            return false;
+        }
+        match inner.probe_started {
+            Some(started) if started.elapsed() < self.open_for => false,
+            _ => {
+                inner.probe_started = Some(Instant::now());
+                true
+            }
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.opened_at.is_some() {
+            println!("✓ {} circuit breaker closed", self.name);
+        }
+        *inner = Inner::default();
+    }
+
+    pub fn record_failure(&self) {
+        if self.failure_threshold == 0 {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        inner.consecutive_failures += 1;
+        let probe_failed = inner.probe_started.take().is_some();
+        if probe_failed || (inner.opened_at.is_none() && inner.consecutive_failures >= self.failure_threshold) {
+            if !probe_failed {
+                eprintln!(
+                    "⚠ {} circuit breaker opened after {} consecutive failures",
+                    self.name, inner.consecutive_failures
+                );
+            }
+            inner.opened_at = Some(Instant::now());
+        }
+    }
+}
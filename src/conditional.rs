@@ -0,0 +1,91 @@
+use std::convert::Infallible;
+
+use chrono::{DateTime, Utc};
+use rocket::http::{Header, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{self, Responder, Response};
+
+/// Weak ETag for a resource, derived from when it last changed
+pub fn weak_etag(updated_at: DateTime<Utc>) -> String {
+    format!("W/\"{}\"", updated_at.timestamp_micros())
+}
+
+/// The quoted part of an ETag, without the weak `W/` prefix
+fn opaque(etag: &str) -> &str {
+    let etag = etag.trim();
+    etag.strip_prefix("W/").unwrap_or(etag).trim_matches('"')
+}
+
+/// Whether a comma-separated `If-None-Match`/`If-Match` value lists this ETag
+///
+/// Comparison is weak (the `W/` prefix is ignored) for both headers. Our
+/// ETags are weak because the JSON body isn't byte-for-byte stable, but they
+/// change on every write, which is all `If-Match` needs here.
+fn lists(header: &str, etag: &str) -> bool {
+    header
+        .split(',')
+        .any(|candidate| candidate.trim() == "*" || opaque(candidate) == opaque(etag))
+}
+
+/// The conditional request headers sent with a request
+pub struct Preconditions {
+    pub if_none_match: Option<String>,
+    pub if_match: Option<String>,
+}
+
+impl Preconditions {
+    /// The client's cached copy is current, so a GET can answer 304
+    pub fn is_not_modified(&self, etag: &str) -> bool {
+        self.if_none_match.as_deref().is_some_and(|header| lists(header, etag))
+    }
+
+    /// The client sent `If-Match` and it doesn't match, so a write must answer 412
+    pub fn if_match_fails(&self, etag: &str) -> bool {
+        self.if_match.as_deref().is_some_and(|header| !lists(header, etag))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Preconditions {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let headers = request.headers();
+        Outcome::Success(Preconditions {
+            if_none_match: headers.get_one("If-None-Match").map(str::to_string),
+            if_match: headers.get_one("If-Match").map(str::to_string),
+        })
+    }
+}
+
+/// A response with an `ETag` header, or a bodiless 304 when the client's copy is current
+pub enum Tagged<R> {
+    Fresh { body: R, etag: String },
+    NotModified { etag: String },
+}
+
+impl<R> Tagged<R> {
+    pub fn new(body: R, etag: String) -> Self {
+        Tagged::Fresh { body, etag }
+    }
+
+    pub fn not_modified(etag: String) -> Self {
+        Tagged::NotModified { etag }
+    }
+}
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for Tagged<R> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'o> {
+        match self {
+            Tagged::Fresh { body, etag } => {
+                let mut response = body.respond_to(request)?;
+                response.set_header(Header::new("ETag", etag));
+                Ok(response)
+            }
+            Tagged::NotModified { etag } => Response::build()
+                .status(Status::NotModified)
+                .header(Header::new("ETag", etag))
+                .ok(),
+        }
+    }
+}
@@ -0,0 +1,1621 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use rocket::data::ByteUnit;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::auth::password::DEFAULT_HASH_QUEUE;
+use crate::auth::scopes;
+use crate::redirects;
+
+/// Errors raised while loading configuration at startup
+#[derive(Debug)]
+pub enum ConfigError {
+    Missing(&'static str),
+    Invalid { key: &'static str, message: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Missing(key) => write!(f, "{} must be set in .env file", key),
+            ConfigError::Invalid { key, message } => write!(f, "{} is invalid: {}", key, message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Which backend signs and verifies tokens
+#[derive(Debug, Clone)]
+pub enum SignerConfig {
+    /// HMAC SHA-256 with a shared secret held in memory
+    Hmac { secret: String },
+    /// Asymmetric signing delegated to an AWS KMS key (requires the `aws-kms` feature)
+    Kms { key_id: String },
+    /// PASETO v4 tokens instead of JWTs, with a hex-encoded 32-byte key
+    Paseto { purpose: PasetoPurpose, key: String },
+}
+
+/// Which kind of PASETO v4 token is issued
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasetoPurpose {
+    /// Encrypted with a shared key (`v4.local.`)
+    Local,
+    /// Signed with Ed25519 (`v4.public.`); the key is the private seed
+    Public,
+}
+
+/// What kind of access token a new session gets
+///
+/// Either kind is accepted whatever is configured, so switching strategies
+/// doesn't sign anyone out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenStrategy {
+    /// Signed JWTs carrying the session id
+    Jwt,
+    /// Random strings stored hashed with their session; lookups are cached for `cache_seconds`
+    Opaque { cache_seconds: u64 },
+}
+
+/// Where cached lookups (token versions, lockouts, account standing) are kept
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheBackend {
+    /// In this process, bounded to `capacity` entries; for single-node deployments
+    Memory { capacity: u64 },
+    /// A Redis server shared by every replica; needs the `redis` feature
+    Redis { url: String },
+}
+
+/// How the auth guard verifies session JWTs
+///
+/// API keys, opaque tokens and external issuers' tokens are looked up the
+/// same way in either mode; so are the roles and permissions behind
+/// `AdminUser` and `HasPermission`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerificationMode {
+    /// Every request reads the session, the user's token version and their
+    /// account standing, so revocations apply right away
+    Stateful,
+    /// The guard checks only the signature and claims and touches no
+    /// database, so replicas scale out for read-heavy APIs
+    ///
+    /// Tokens carry the user's token version and account standing and expire
+    /// after `token_minutes`; clients renew them at `POST /auth/renew-token`,
+    /// which checks everything. Sign-outs, password changes and role changes
+    /// reach other tokens only when they expire or are renewed. Needs the
+    /// `jwt` token strategy and no session idle timeout. Tokens from before
+    /// the switch carry no standing and are still checked in full.
+    Stateless { token_minutes: u64 },
+}
+
+/// A hosted identity provider (Auth0, Cognito, Firebase, …) whose tokens are accepted too
+#[derive(Debug, Clone)]
+pub struct ExternalJwtConfig {
+    /// Expected `iss` claim; tokens are routed to this issuer by it
+    pub issuer: String,
+    /// Expected `aud` claim
+    pub audience: String,
+    /// Where the issuer publishes its signing keys
+    pub jwks_url: String,
+}
+
+/// A Microsoft Entra ID app registration used for sign-in
+#[derive(Debug, Clone)]
+pub struct MicrosoftConfig {
+    pub client_id: String,
+    pub client_secret: String,
+    /// Directory id of a single-tenant app, or `organizations`, `common` or `consumers`
+    pub tenant: String,
+}
+
+impl MicrosoftConfig {
+    /// Whether accounts from any directory can sign in
+    pub fn is_multi_tenant(&self) -> bool {
+        matches!(self.tenant.as_str(), "organizations" | "common" | "consumers")
+    }
+}
+
+/// A Discord application used for sign-in
+#[derive(Debug, Clone)]
+pub struct DiscordConfig {
+    pub client_id: String,
+    pub client_secret: String,
+    /// Guild ids and the role their members get, in order of precedence
+    pub guild_roles: Vec<(String, String)>,
+}
+
+/// A Facebook app used for sign-in
+#[derive(Debug, Clone)]
+pub struct FacebookConfig {
+    pub app_id: String,
+    pub app_secret: String,
+}
+
+/// Stripe account customers are created in for new users
+#[derive(Debug, Clone)]
+pub struct StripeConfig {
+    pub secret_key: String,
+    /// Signs the webhooks sent to `/billing/stripe/webhook`; the endpoint is off without it
+    pub webhook_secret: Option<String>,
+    /// Base URL of the API, `https://api.stripe.com` unless pointed at a mock
+    pub api_url: String,
+}
+
+/// An OAuth 2.0 provider defined entirely in configuration (GitLab, Slack, …)
+#[derive(Debug, Clone)]
+pub struct GenericOAuthConfig {
+    /// Path segment of its routes, `/oauth/<name>/...`
+    pub name: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub authorize_url: String,
+    pub token_url: String,
+    /// Fetched with the access token to learn who signed in
+    pub userinfo_url: String,
+    /// Space-separated scopes to ask for
+    pub scopes: String,
+    /// Dotted path of the user's stable id in the userinfo response
+    pub subject_field: String,
+    pub email_field: String,
+    /// Field that's true (or set at all) when the email is verified; without one, emails are unverified
+    pub email_verified_field: Option<String>,
+    /// Authenticate at the token endpoint with HTTP Basic instead of form fields
+    pub basic_auth: bool,
+    /// Send a PKCE challenge
+    pub pkce: bool,
+}
+
+/// Who may exchange user tokens for tokens meant for one internal service (RFC 8693)
+#[derive(Debug, Clone)]
+pub struct TokenExchangePolicy {
+    /// The service's name, sent as `audience` and put in the token's `aud`
+    pub audience: String,
+    /// `client_id`s of the OAuth clients allowed to exchange tokens for this audience
+    pub clients: Vec<String>,
+    /// The most an exchanged token can carry; it never gets more than the subject token had
+    pub scopes: Vec<String>,
+    pub lifetime_seconds: i64,
+}
+
+/// Where outgoing emails go
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailTransport {
+    /// Print emails to stdout
+    Log,
+    /// Keep emails in memory and expose them at `GET /_dev/mailbox` (development only)
+    Memory,
+    /// Deliver through the SMTP relay in `AppConfig::smtp`
+    Smtp,
+}
+
+/// An SMTP relay emails are delivered through
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub security: SmtpSecurity,
+}
+
+/// How the connection to the SMTP relay is protected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Upgrade a plain connection with `STARTTLS`, failing if the relay doesn't offer it
+    StartTls,
+    /// TLS from the start, usually on port 465
+    Tls,
+    /// No encryption; only for relays on the local network
+    None,
+}
+
+/// Where security events are forwarded besides the audit log table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditSinkConfig {
+    /// One JSON object per line on stdout
+    Stdout,
+    /// RFC 5424 messages to a syslog daemon, at a Unix socket path or a UDP `host:port`
+    Syslog { address: String },
+    /// POSTs each event to a collector
+    Http {
+        url: String,
+        /// Sent as the `Authorization` header, e.g. `Splunk <token>`
+        authorization: Option<String>,
+        format: HttpSinkFormat,
+    },
+}
+
+/// Request body sent by the HTTP audit sink
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpSinkFormat {
+    /// The event as is
+    Json,
+    /// The event wrapped for the Splunk HTTP Event Collector
+    SplunkHec,
+}
+
+/// How links in password reset and verification emails are built
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmailLinkStyle {
+    /// Links open the web frontend (reset) or this API (verification)
+    Web,
+    /// Links use the mobile app's URL scheme, e.g. `myapp://reset?token=…`
+    App { scheme: String },
+    /// Links point at `/l/<action>` here, which redirects phones to the app and everything else to the web
+    Universal { scheme: String },
+}
+
+/// How password reset and verification tokens are kept
+///
+/// Stored tokens are accepted whatever is configured, so switching to
+/// signed tokens doesn't break links already sent.
+#[derive(Debug, Clone)]
+pub enum EmailTokenMode {
+    /// Random tokens, stored hashed until used
+    Stored,
+    /// Self-expiring tokens signed with HMAC SHA-256; only redemption is recorded
+    Signed { secret: String },
+}
+
+/// How `Owns<T>` answers when a resource belongs to someone else
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OwnershipDenial {
+    /// 404, as if the resource didn't exist, so ids can't be probed
+    NotFound,
+    /// 403 with code `not_owner`
+    Forbidden,
+}
+
+/// How tenants' (organizations') data is kept apart, see `tenancy`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tenancy {
+    /// Shared tables with an `organization_id` column every query filters on
+    Rows,
+    /// A `tenant_<id>` schema per organization, first on each scoped transaction's `search_path`
+    Schemas,
+}
+
+/// What the request log records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestLog {
+    Off,
+    /// One line per request: method, path, status, latency and user
+    Summary,
+    /// The summary plus JSON request and response bodies, with credentials redacted
+    Bodies,
+}
+
+/// Algorithm and work factor for new password hashes
+///
+/// Stored hashes made with other settings keep verifying, and are re-hashed
+/// on login when the configured work factor is higher.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordHashing {
+    Bcrypt { cost: u32 },
+    /// Argon2id, with memory in KiB
+    Argon2 { memory_kib: u32, iterations: u32, parallelism: u32 },
+}
+
+/// Where a password pepper's secret comes from
+#[derive(Debug, Clone)]
+pub enum PepperSecret {
+    Plain(String),
+    /// Encrypted with AWS KMS, base64 encoded; decrypted on first use (requires the `aws-kms` feature)
+    Kms { ciphertext: String },
+}
+
+/// A server-side secret mixed into passwords before hashing
+///
+/// The id is stored with each hash so the pepper can be rotated.
+#[derive(Debug, Clone)]
+pub struct PepperConfig {
+    pub id: String,
+    pub secret: PepperSecret,
+}
+
+/// Who may create an account through `/api/v1/auth/register`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RegistrationMode {
+    /// Anyone can register
+    Open,
+    /// Registration requires an invitation code created by an admin
+    InviteOnly,
+    /// Nobody can register; accounts are created by admins
+    Closed,
+}
+
+/// Whether newly registered users get a welcome email, sent from the `user_registered` event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WelcomeEmail {
+    Off,
+    /// A welcome email, in addition to the verification email
+    On,
+    /// One welcome email that also carries the verification link
+    WithVerification,
+}
+
+/// Which engine scores the risk of password logins
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginRiskEngine {
+    Off,
+    /// The built-in `risk::HeuristicRiskAssessor`
+    Heuristic,
+}
+
+/// What happens when a session is used from a different country too soon after its last use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImpossibleTravel {
+    Off,
+    /// Email the user and record a `suspicious_session_use` event
+    Notify,
+    /// Also revoke the session
+    Revoke,
+}
+
+/// What the inactivity policy does to accounts nobody has logged in to for too long
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InactiveAccountAction {
+    /// Mark the account for review; the user can still log in, which clears the flag
+    Flag,
+    /// Also refuse logins and end the user's sessions until an admin reactivates the account
+    Disable,
+}
+
+/// Database connection pool settings, applied to the server's and the migration pools
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DbPoolConfig {
+    pub max_connections: u32,
+    /// Connections kept open even when idle
+    pub min_connections: u32,
+    /// Seconds to wait for a free connection before the request fails
+    pub acquire_timeout_seconds: u64,
+    /// Seconds an unused connection stays open; 0 keeps it open
+    pub idle_timeout_seconds: u64,
+    /// Seconds before a connection is closed and replaced; 0 keeps it forever
+    pub max_lifetime_seconds: u64,
+    /// Milliseconds a statement may run before Postgres cancels it; 0 lets it run
+    pub statement_timeout_ms: u64,
+    /// Open no connection until the first one is needed, e.g. for serverless cold starts
+    #[serde(default)]
+    pub lazy_connect: bool,
+}
+
+impl Default for DbPoolConfig {
+    /// sqlx's own defaults, without a statement timeout
+    fn default() -> Self {
+        DbPoolConfig {
+            max_connections: 10,
+            min_connections: 0,
+            acquire_timeout_seconds: 30,
+            idle_timeout_seconds: 600,
+            max_lifetime_seconds: 1800,
+            statement_timeout_ms: 0,
+            lazy_connect: false,
+        }
+    }
+}
+
+/// When the server stops trying to get database connections
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DbCircuitBreakerConfig {
+    /// Failed connection attempts in a row that open the breaker; 0 never opens it
+    pub failure_threshold: u32,
+    /// Seconds requests fail fast before a probe is let through
+    pub open_seconds: u64,
+}
+
+impl Default for DbCircuitBreakerConfig {
+    fn default() -> Self {
+        DbCircuitBreakerConfig {
+            failure_threshold: 5,
+            open_seconds: 10,
+        }
+    }
+}
+
+/// Largest request bodies accepted, applied through Rocket's `limits`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BodyLimits {
+    /// JSON bodies of endpoints without a limit of their own
+    pub json: ByteUnit,
+    /// JSON bodies of endpoints taking credentials, e.g. login and register
+    pub credentials: ByteUnit,
+    /// JSON bodies of bulk endpoints, e.g. audit replay
+    pub bulk: ByteUnit,
+    /// Deepest nesting of arrays and objects in a JSON body
+    pub json_depth: usize,
+}
+
+impl Default for BodyLimits {
+    fn default() -> Self {
+        BodyLimits {
+            json: ByteUnit::Kibibyte(64),
+            credentials: ByteUnit::Kibibyte(8),
+            bulk: ByteUnit::Mebibyte(1),
+            json_depth: 32,
+        }
+    }
+}
+
+/// Certificate and key for terminating TLS in the server itself
+///
+/// Read again on SIGHUP, so renewed certificates are picked up without a restart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// PEM certificate chain, leaf first
+    pub cert_path: PathBuf,
+    /// PEM private key (PKCS#8, PKCS#1 or SEC1)
+    pub key_path: PathBuf,
+    /// PEM CA certificates; when set, clients must present a certificate they issued (mutual TLS)
+    pub client_ca_path: Option<PathBuf>,
+}
+
+/// Application configuration, read from the environment at startup
+///
+/// Every setting can also be supplied as a file path via `<NAME>_FILE`.
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    pub database_url: String,
+    pub db_pool: DbPoolConfig,
+    pub db_circuit_breaker: DbCircuitBreakerConfig,
+    pub jwt_signer: SignerConfig,
+    pub token_strategy: TokenStrategy,
+    pub verification_mode: VerificationMode,
+    pub cache: CacheBackend,
+    /// How long the guard trusts a user's token version before reading it again
+    pub token_version_cache_seconds: u64,
+    /// How long the guard trusts a user's account standing before reading it again; 0 reads it on every request
+    pub user_cache_seconds: u64,
+    /// Absolute lifetime of a login session, however active it is
+    pub session_max_age_hours: u64,
+    /// Sessions unused for this long expire; 0 disables the idle timeout
+    pub session_idle_timeout_minutes: u64,
+    /// Failed logins for an address before it's locked out; 0 disables the lockout
+    pub login_lockout_attempts: u32,
+    /// How long failed logins count towards the lockout
+    pub login_lockout_minutes: u64,
+    /// How long a lockout may be cached instead of re-read; 0 reads it on every attempt
+    pub login_lockout_cache_seconds: u64,
+    /// Delay added to a login per recent failure from the same address and IP; 0 disables delays
+    pub login_delay_ms: u64,
+    /// Longest delay a login is held for
+    pub login_delay_max_ms: u64,
+    /// Distinct emails one IP may fail to log in to within the window before it's blocked; 0 disables blocking
+    pub stuffing_distinct_emails: u32,
+    /// How far back failures count towards blocking an IP
+    pub stuffing_window_minutes: u64,
+    /// How long a blocked IP is refused logins
+    pub stuffing_block_minutes: u64,
+    /// Risk engine consulted after a correct password, unless a custom one is managed
+    pub login_risk: LoginRiskEngine,
+    /// Heuristic risk score from which a login needs a second factor
+    pub login_risk_step_up_score: u32,
+    /// Heuristic risk score from which a login is refused
+    pub login_risk_block_score: u32,
+    /// Header a proxy or CDN sets to the client's country, e.g. `CF-IPCountry`
+    pub geo_country_header: Option<String>,
+    /// Header a proxy or CDN sets to the client's city, e.g. `CF-IPCity`; shown with sessions
+    pub geo_city_header: Option<String>,
+    /// Response to a session used from two countries within `impossible_travel_minutes`
+    pub impossible_travel: ImpossibleTravel,
+    /// How soon after a session's last use a different country counts as impossible travel
+    pub impossible_travel_minutes: u64,
+    /// Requests one API key may make per UTC day; 0 is unlimited
+    pub api_key_daily_quota: u32,
+    /// Requests one API key may make per UTC calendar month; 0 is unlimited
+    pub api_key_monthly_quota: u32,
+    /// How long a rotated API key keeps working next to its replacement
+    pub api_key_rotation_overlap_minutes: u64,
+    /// How close to expiring an API key is flagged in key listings
+    pub api_key_expiry_warning_days: u64,
+    /// The audience this deployment answers to; tokens and API keys bound to it work here, others bound elsewhere don't
+    pub service_audience: Option<String>,
+    /// Let organization owners and admins give their organization its own token signing keys
+    pub org_signing_keys: bool,
+    pub tenancy: Tenancy,
+    /// DNS-over-HTTPS endpoint (JSON API) organizations' domain challenges are looked up with
+    pub dns_over_https_url: String,
+    /// Addresses security anomaly alerts are emailed to; empty disables alerts
+    pub admin_alert_emails: Vec<String>,
+    /// Minimum time between two alerts about the same thing; 0 sends every one
+    pub admin_alert_cooldown_minutes: u64,
+    /// Addresses locked out at once that count as a spike; 0 disables the alert
+    pub admin_alert_lockout_spike: u32,
+    /// Dead letters for one audit sink within an hour that raise an alert; 0 disables the alert
+    pub admin_alert_sink_failures: u32,
+    /// Roles whose users must enroll in MFA
+    pub mfa_required_roles: Vec<String>,
+    /// Days users keep full access after MFA becomes required, before they must enroll
+    pub mfa_grace_days: u64,
+    /// Name authenticator apps show next to the account
+    pub mfa_issuer: String,
+    /// Hours between confirming an MFA recovery by email and MFA being removed
+    pub mfa_recovery_delay_hours: u64,
+    /// Days without a login before the inactivity policy applies; 0 disables it
+    pub inactive_account_days: u64,
+    pub inactive_account_action: InactiveAccountAction,
+    /// Days before the policy applies that the user is warned by email; 0 sends no warning
+    pub inactive_account_warning_days: u64,
+    /// Days audit events are kept; 0 keeps them forever
+    pub audit_retention_days: u64,
+    /// Days login attempts are kept; 0 keeps them forever
+    pub login_history_retention_days: u64,
+    /// Days sessions are kept after they end; 0 keeps them forever
+    pub session_retention_days: u64,
+    /// Only report what the retention cleanup would delete
+    pub retention_dry_run: bool,
+    /// External sinks every security event is forwarded to
+    pub audit_sinks: Vec<AuditSinkConfig>,
+    /// Deliveries tried per event and sink before it goes to the dead-letter table
+    pub audit_sink_max_attempts: u32,
+    /// Wait before the first retry; doubles with each further one
+    pub audit_sink_retry_seconds: u64,
+    /// Milliseconds between flushes of batched session touches, logins and security events; 0 writes them right away
+    pub telemetry_flush_ms: u64,
+    /// Password-hashing requests handled at once before more are refused with 503; 0 is unlimited
+    pub load_shed_credentials_limit: usize,
+    /// Other requests handled at once before more are refused with 503; 0 is unlimited
+    pub load_shed_limit: usize,
+    /// `Retry-After` sent with refused requests
+    pub load_shed_retry_after_seconds: u64,
+    /// Also accept tokens from this issuer, provisioning local users on first sight
+    pub external_jwt: Option<ExternalJwtConfig>,
+    /// OAuth client ids whose Google ID tokens can sign in at `/oauth/google/id-token`
+    pub google_client_ids: Vec<String>,
+    /// Sign-in with Microsoft Entra ID at `/oauth/microsoft/authorize`
+    pub microsoft: Option<MicrosoftConfig>,
+    /// Sign-in with Discord at `/oauth/discord/authorize`
+    pub discord: Option<DiscordConfig>,
+    /// Sign-in with Facebook at `/oauth/facebook/authorize`
+    pub facebook: Option<FacebookConfig>,
+    /// Create a Stripe customer for each new user and take subscription updates from Stripe's webhooks
+    pub stripe: Option<StripeConfig>,
+    /// More sign-in providers from `ROCKET_OAUTH_PROVIDERS`
+    pub oauth_providers: Vec<GenericOAuthConfig>,
+    /// Initial access token for dynamic client registration; registration is off without one
+    pub client_registration_token: Option<String>,
+    /// Audiences OAuth clients may exchange user tokens for, from `ROCKET_TOKEN_EXCHANGE_AUDIENCES`
+    pub token_exchange: Vec<TokenExchangePolicy>,
+    /// Start in read-only maintenance mode
+    pub maintenance_mode: bool,
+    /// Let admins override settings at runtime through the `settings` table
+    pub runtime_settings: bool,
+    /// Serve on a Unix socket at this path instead of Rocket's TCP address and port
+    pub unix_socket: Option<PathBuf>,
+    /// Terminate TLS in the server instead of at a reverse proxy
+    pub tls: Option<TlsConfig>,
+    pub body_limits: BodyLimits,
+    /// Externally reachable base URL of this API, used in email links
+    pub public_url: String,
+    /// Base URL of the frontend that hosts pages like the password reset form
+    pub frontend_url: String,
+    /// URLs and patterns besides the frontend that `redirect_to` parameters may name, see `redirects`
+    pub redirect_allowlist: Vec<String>,
+    pub email_transport: EmailTransport,
+    /// The relay used with `EmailTransport::Smtp`; admins can replace it at runtime
+    pub smtp: Option<SmtpConfig>,
+    /// Sender address for outgoing emails
+    pub email_from: String,
+    pub email_links: EmailLinkStyle,
+    pub email_tokens: EmailTokenMode,
+    /// Key for signed download and upload URLs; they're unavailable without one
+    pub signed_url_secret: Option<String>,
+    /// How long a signed URL works
+    pub signed_url_ttl_seconds: u64,
+    pub registration_mode: RegistrationMode,
+    /// New registrations wait for an admin's approval before they can log in
+    pub signup_approval: bool,
+    /// `/register` answers the same for taken and new emails, emailing existing users instead
+    pub registration_uniform_response: bool,
+    pub welcome_email: WelcomeEmail,
+    /// Embed the user's `app_metadata` in issued tokens
+    pub app_metadata_claim: bool,
+    pub ownership_denial: OwnershipDenial,
+    /// Also serve the API at the unversioned `/api/...` paths (deprecated)
+    pub legacy_api: bool,
+    /// Announced end of the unversioned paths, sent as the `Sunset` header
+    pub legacy_api_sunset: Option<DateTime<Utc>>,
+    /// Compress JSON responses when the client accepts gzip or brotli
+    pub compression: bool,
+    /// Smallest response body worth compressing, in bytes
+    pub compression_min_bytes: usize,
+    pub request_log: RequestLog,
+    pub password_hashing: PasswordHashing,
+    /// Time a password hash at startup and warn when it's too fast to slow down guessing
+    pub password_hash_calibration: bool,
+    /// Password hashes computed at once; 0 means one per CPU
+    pub password_hash_threads: usize,
+    /// Password hashes waiting for a thread before more are refused with 503
+    pub password_hash_queue: usize,
+    /// Password peppers; the first hashes new passwords, the rest only verify old ones
+    pub password_peppers: Vec<PepperConfig>,
+}
+
+impl AppConfig {
+    /// Configuration with defaults for everything but the database and signer
+    pub fn new(database_url: String, jwt_signer: SignerConfig) -> Self {
+        AppConfig {
+            database_url,
+            db_pool: DbPoolConfig::default(),
+            db_circuit_breaker: DbCircuitBreakerConfig::default(),
+            jwt_signer,
+            token_strategy: TokenStrategy::Jwt,
+            verification_mode: VerificationMode::Stateful,
+            cache: CacheBackend::Memory { capacity: 100_000 },
+            token_version_cache_seconds: 5,
+            user_cache_seconds: 0,
+            session_max_age_hours: 24,
+            session_idle_timeout_minutes: 0,
+            login_lockout_attempts: 10,
+            login_lockout_minutes: 15,
+            login_lockout_cache_seconds: 0,
+            login_delay_ms: 250,
+            login_delay_max_ms: 3000,
+            stuffing_distinct_emails: 20,
+            stuffing_window_minutes: 10,
+            stuffing_block_minutes: 60,
+            login_risk: LoginRiskEngine::Off,
+            login_risk_step_up_score: 50,
+            login_risk_block_score: 100,
+            geo_country_header: None,
+            geo_city_header: None,
+            impossible_travel: ImpossibleTravel::Off,
+            impossible_travel_minutes: 60,
+            api_key_daily_quota: 0,
+            api_key_monthly_quota: 0,
+            api_key_rotation_overlap_minutes: 1440,
+            api_key_expiry_warning_days: 7,
+            service_audience: None,
+            org_signing_keys: false,
+            tenancy: Tenancy::Rows,
+            dns_over_https_url: "https://cloudflare-dns.com/dns-query".to_string(),
+            admin_alert_emails: Vec::new(),
+            admin_alert_cooldown_minutes: 60,
+            admin_alert_lockout_spike: 10,
+            admin_alert_sink_failures: 3,
+            mfa_required_roles: Vec::new(),
+            mfa_grace_days: 7,
+            mfa_issuer: "Rocket Auth".to_string(),
+            mfa_recovery_delay_hours: 72,
+            inactive_account_days: 0,
+            inactive_account_action: InactiveAccountAction::Flag,
+            inactive_account_warning_days: 0,
+            audit_retention_days: 0,
+            login_history_retention_days: 0,
+            session_retention_days: 0,
+            retention_dry_run: false,
+            audit_sinks: Vec::new(),
+            audit_sink_max_attempts: 4,
+            audit_sink_retry_seconds: 5,
+            telemetry_flush_ms: 0,
+            load_shed_credentials_limit: 0,
+            load_shed_limit: 0,
+            load_shed_retry_after_seconds: 1,
+            external_jwt: None,
+            google_client_ids: Vec::new(),
+            microsoft: None,
+            discord: None,
+            facebook: None,
+            stripe: None,
+            oauth_providers: Vec::new(),
+            client_registration_token: None,
+            token_exchange: Vec::new(),
+            maintenance_mode: false,
+            runtime_settings: true,
+            unix_socket: None,
+            tls: None,
+            body_limits: BodyLimits::default(),
+            public_url: "http://localhost:8000".to_string(),
+            frontend_url: "http://localhost:8000".to_string(),
+            redirect_allowlist: Vec::new(),
+            email_transport: EmailTransport::Log,
+            smtp: None,
+            email_from: "no-reply@localhost".to_string(),
+            email_links: EmailLinkStyle::Web,
+            email_tokens: EmailTokenMode::Stored,
+            signed_url_secret: None,
+            signed_url_ttl_seconds: 300,
+            registration_mode: RegistrationMode::Open,
+            signup_approval: false,
+            registration_uniform_response: false,
+            welcome_email: WelcomeEmail::Off,
+            app_metadata_claim: false,
+            ownership_denial: OwnershipDenial::NotFound,
+            legacy_api: true,
+            legacy_api_sunset: None,
+            compression: true,
+            compression_min_bytes: 1024,
+            request_log: RequestLog::Off,
+            password_hashing: PasswordHashing::Bcrypt { cost: bcrypt::DEFAULT_COST },
+            password_hash_calibration: false,
+            password_hash_threads: 0,
+            password_hash_queue: DEFAULT_HASH_QUEUE,
+            password_peppers: Vec::new(),
+        }
+    }
+
+    /// Load configuration from environment variables
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let database_url = required("ROCKET_DATABASE_URL")?;
+
+        let jwt_signer = match optional("ROCKET_TOKEN_FORMAT")?.as_deref() {
+            None | Some("jwt") => jwt_signer()?,
+            Some("paseto") => SignerConfig::Paseto {
+                purpose: match optional("ROCKET_PASETO_PURPOSE")?.as_deref() {
+                    None | Some("local") => PasetoPurpose::Local,
+                    Some("public") => PasetoPurpose::Public,
+                    Some(other) => {
+                        return Err(ConfigError::Invalid {
+                            key: "ROCKET_PASETO_PURPOSE",
+                            message: format!("unknown purpose '{}', expected 'local' or 'public'", other),
+                        });
+                    }
+                },
+                key: required("ROCKET_PASETO_KEY")?,
+            },
+            Some(other) => {
+                return Err(ConfigError::Invalid {
+                    key: "ROCKET_TOKEN_FORMAT",
+                    message: format!("unknown format '{}', expected 'jwt' or 'paseto'", other),
+                });
+            }
+        };
+
+        let mut config = AppConfig::new(database_url, jwt_signer);
+
+        let defaults = DbPoolConfig::default();
+        config.db_pool = DbPoolConfig {
+            max_connections: number("ROCKET_DB_MAX_CONNECTIONS", defaults.max_connections)?,
+            min_connections: number("ROCKET_DB_MIN_CONNECTIONS", defaults.min_connections)?,
+            acquire_timeout_seconds: number("ROCKET_DB_ACQUIRE_TIMEOUT_SECONDS", defaults.acquire_timeout_seconds)?,
+            idle_timeout_seconds: number("ROCKET_DB_IDLE_TIMEOUT_SECONDS", defaults.idle_timeout_seconds)?,
+            max_lifetime_seconds: number("ROCKET_DB_MAX_LIFETIME_SECONDS", defaults.max_lifetime_seconds)?,
+            statement_timeout_ms: number("ROCKET_DB_STATEMENT_TIMEOUT_MS", defaults.statement_timeout_ms)?,
+            lazy_connect: flag("ROCKET_DB_LAZY_CONNECT")?,
+        };
+        if config.db_pool.max_connections == 0 {
+            return Err(ConfigError::Invalid {
+                key: "ROCKET_DB_MAX_CONNECTIONS",
+                message: "the pool needs at least one connection".to_string(),
+            });
+        }
+        if config.db_pool.min_connections > config.db_pool.max_connections {
+            return Err(ConfigError::Invalid {
+                key: "ROCKET_DB_MIN_CONNECTIONS",
+                message: "must not be more than ROCKET_DB_MAX_CONNECTIONS".to_string(),
+            });
+        }
+        config.db_circuit_breaker = DbCircuitBreakerConfig {
+            failure_threshold: number("ROCKET_DB_BREAKER_FAILURES", 5)?,
+            open_seconds: number("ROCKET_DB_BREAKER_OPEN_SECONDS", 10)?,
+        };
+
+        config.token_strategy = match optional("ROCKET_TOKEN_STRATEGY")?.as_deref() {
+            None | Some("jwt") => TokenStrategy::Jwt,
+            Some("opaque") => TokenStrategy::Opaque {
+                cache_seconds: number("ROCKET_OPAQUE_TOKEN_CACHE_SECONDS", 5)?,
+            },
+            Some(other) => {
+                return Err(ConfigError::Invalid {
+                    key: "ROCKET_TOKEN_STRATEGY",
+                    message: format!("unknown strategy '{}', expected 'jwt' or 'opaque'", other),
+                });
+            }
+        };
+
+        config.cache = match optional("ROCKET_CACHE")?.as_deref() {
+            None | Some("memory") => CacheBackend::Memory {
+                capacity: number("ROCKET_CACHE_CAPACITY", 100_000)?,
+            },
+            Some("redis") => CacheBackend::Redis {
+                url: required("ROCKET_REDIS_URL")?,
+            },
+            Some(other) => {
+                return Err(ConfigError::Invalid {
+                    key: "ROCKET_CACHE",
+                    message: format!("unknown backend '{}', expected 'memory' or 'redis'", other),
+                });
+            }
+        };
+        config.token_version_cache_seconds = number("ROCKET_TOKEN_VERSION_CACHE_SECONDS", 5)?;
+        config.user_cache_seconds = number("ROCKET_USER_CACHE_SECONDS", 0)?;
+        config.session_max_age_hours = number("ROCKET_SESSION_MAX_AGE_HOURS", 24)?;
+        if config.session_max_age_hours == 0 {
+            return Err(ConfigError::Invalid {
+                key: "ROCKET_SESSION_MAX_AGE_HOURS",
+                message: "sessions need a lifetime of at least one hour".to_string(),
+            });
+        }
+        config.session_idle_timeout_minutes = number("ROCKET_SESSION_IDLE_TIMEOUT_MINUTES", 0)?;
+
+        config.verification_mode = match optional("ROCKET_VERIFICATION_MODE")?.as_deref() {
+            None | Some("stateful") => VerificationMode::Stateful,
+            Some("stateless") => VerificationMode::Stateless {
+                token_minutes: number("ROCKET_STATELESS_TOKEN_MINUTES", 5)?,
+            },
+            Some(other) => {
+                return Err(ConfigError::Invalid {
+                    key: "ROCKET_VERIFICATION_MODE",
+                    message: format!("unknown mode '{}', expected 'stateful' or 'stateless'", other),
+                });
+            }
+        };
+        if let VerificationMode::Stateless { token_minutes } = config.verification_mode {
+            if token_minutes == 0 || token_minutes > 60 {
+                return Err(ConfigError::Invalid {
+                    key: "ROCKET_STATELESS_TOKEN_MINUTES",
+                    message: "must be between 1 and 60 minutes".to_string(),
+                });
+            }
+            if config.token_strategy != TokenStrategy::Jwt {
+                return Err(ConfigError::Invalid {
+                    key: "ROCKET_VERIFICATION_MODE",
+                    message: "stateless verification needs ROCKET_TOKEN_STRATEGY=jwt".to_string(),
+                });
+            }
+            if config.session_idle_timeout_minutes > 0 {
+                return Err(ConfigError::Invalid {
+                    key: "ROCKET_VERIFICATION_MODE",
+                    message: "stateless verification can't enforce ROCKET_SESSION_IDLE_TIMEOUT_MINUTES".to_string(),
+                });
+            }
+        }
+
+        config.login_lockout_attempts = number("ROCKET_LOGIN_LOCKOUT_ATTEMPTS", 10)?;
+        config.login_lockout_minutes = number("ROCKET_LOGIN_LOCKOUT_MINUTES", 15)?;
+        config.login_lockout_cache_seconds = number("ROCKET_LOGIN_LOCKOUT_CACHE_SECONDS", 0)?;
+        if config.login_lockout_attempts > 0 && config.login_lockout_minutes == 0 {
+            return Err(ConfigError::Invalid {
+                key: "ROCKET_LOGIN_LOCKOUT_MINUTES",
+                message: "the lockout window must be at least one minute".to_string(),
+            });
+        }
+
+        config.login_delay_ms = number("ROCKET_LOGIN_DELAY_MS", 250)?;
+        config.login_delay_max_ms = number("ROCKET_LOGIN_DELAY_MAX_MS", 3000)?;
+
+        config.stuffing_distinct_emails = number("ROCKET_STUFFING_DISTINCT_EMAILS", 20)?;
+        config.stuffing_window_minutes = number("ROCKET_STUFFING_WINDOW_MINUTES", 10)?;
+        config.stuffing_block_minutes = number("ROCKET_STUFFING_BLOCK_MINUTES", 60)?;
+        if config.stuffing_distinct_emails > 0 {
+            for (key, minutes) in [
+                ("ROCKET_STUFFING_WINDOW_MINUTES", config.stuffing_window_minutes),
+                ("ROCKET_STUFFING_BLOCK_MINUTES", config.stuffing_block_minutes),
+            ] {
+                if minutes == 0 {
+                    return Err(ConfigError::Invalid {
+                        key,
+                        message: "must be at least one minute".to_string(),
+                    });
+                }
+            }
+        }
+
+        config.login_risk = match optional("ROCKET_LOGIN_RISK")?.as_deref() {
+            None | Some("off") => LoginRiskEngine::Off,
+            Some("heuristic") => LoginRiskEngine::Heuristic,
+            Some(other) => {
+                return Err(ConfigError::Invalid {
+                    key: "ROCKET_LOGIN_RISK",
+                    message: format!("unknown value '{}', expected 'off' or 'heuristic'", other),
+                });
+            }
+        };
+        config.login_risk_step_up_score = number("ROCKET_LOGIN_RISK_STEP_UP_SCORE", 50)?;
+        config.login_risk_block_score = number("ROCKET_LOGIN_RISK_BLOCK_SCORE", 100)?;
+        if config.login_risk_step_up_score > config.login_risk_block_score {
+            return Err(ConfigError::Invalid {
+                key: "ROCKET_LOGIN_RISK_STEP_UP_SCORE",
+                message: "must not be above ROCKET_LOGIN_RISK_BLOCK_SCORE".to_string(),
+            });
+        }
+        config.geo_country_header = optional("ROCKET_GEO_COUNTRY_HEADER")?;
+        config.geo_city_header = optional("ROCKET_GEO_CITY_HEADER")?;
+        config.impossible_travel = match optional("ROCKET_IMPOSSIBLE_TRAVEL")?.as_deref() {
+            None | Some("off") => ImpossibleTravel::Off,
+            Some("notify") => ImpossibleTravel::Notify,
+            Some("revoke") => ImpossibleTravel::Revoke,
+            Some(other) => {
+                return Err(ConfigError::Invalid {
+                    key: "ROCKET_IMPOSSIBLE_TRAVEL",
+                    message: format!("unknown value '{}', expected 'off', 'notify' or 'revoke'", other),
+                });
+            }
+        };
+        if config.impossible_travel != ImpossibleTravel::Off && config.geo_country_header.is_none() {
+            return Err(ConfigError::Invalid {
+                key: "ROCKET_IMPOSSIBLE_TRAVEL",
+                message: "needs ROCKET_GEO_COUNTRY_HEADER to tell where sessions are used from".to_string(),
+            });
+        }
+        config.impossible_travel_minutes = number("ROCKET_IMPOSSIBLE_TRAVEL_MINUTES", 60)?;
+
+        config.api_key_daily_quota = number("ROCKET_API_KEY_DAILY_QUOTA", 0)?;
+        config.api_key_monthly_quota = number("ROCKET_API_KEY_MONTHLY_QUOTA", 0)?;
+        config.api_key_rotation_overlap_minutes = number("ROCKET_API_KEY_ROTATION_OVERLAP_MINUTES", 1440)?;
+        config.api_key_expiry_warning_days = number("ROCKET_API_KEY_EXPIRY_WARNING_DAYS", 7)?;
+        config.service_audience = optional("ROCKET_SERVICE_AUDIENCE")?;
+        config.org_signing_keys = flag("ROCKET_ORG_SIGNING_KEYS")?;
+        config.tenancy = match optional("ROCKET_TENANCY")?.as_deref() {
+            None | Some("rows") => Tenancy::Rows,
+            Some("schemas") => Tenancy::Schemas,
+            Some(other) => {
+                return Err(ConfigError::Invalid {
+                    key: "ROCKET_TENANCY",
+                    message: format!("unknown value '{}', expected 'rows' or 'schemas'", other),
+                });
+            }
+        };
+        if let Some(url) = optional("ROCKET_DNS_OVER_HTTPS_URL")? {
+            config.dns_over_https_url = url;
+        }
+
+        if let Some(emails) = optional("ROCKET_ADMIN_ALERT_EMAILS")? {
+            config.admin_alert_emails = emails
+                .split(',')
+                .map(str::trim)
+                .filter(|email| !email.is_empty())
+                .map(String::from)
+                .collect();
+        }
+        config.admin_alert_cooldown_minutes = number("ROCKET_ADMIN_ALERT_COOLDOWN_MINUTES", 60)?;
+        config.admin_alert_lockout_spike = number("ROCKET_ADMIN_ALERT_LOCKOUT_SPIKE", 10)?;
+        config.admin_alert_sink_failures = number("ROCKET_ADMIN_ALERT_SINK_FAILURES", 3)?;
+
+        if let Some(roles) = optional("ROCKET_MFA_REQUIRED_ROLES")? {
+            config.mfa_required_roles = roles
+                .split(',')
+                .map(str::trim)
+                .filter(|role| !role.is_empty())
+                .map(String::from)
+                .collect();
+        }
+        config.mfa_grace_days = number("ROCKET_MFA_GRACE_DAYS", 7)?;
+        if let Some(issuer) = optional("ROCKET_MFA_ISSUER")? {
+            config.mfa_issuer = issuer;
+        }
+        config.mfa_recovery_delay_hours = number("ROCKET_MFA_RECOVERY_DELAY_HOURS", 72)?;
+        if config.mfa_recovery_delay_hours == 0 {
+            return Err(ConfigError::Invalid {
+                key: "ROCKET_MFA_RECOVERY_DELAY_HOURS",
+                message: "MFA recovery needs a delay of at least one hour".to_string(),
+            });
+        }
+
+        config.inactive_account_days = number("ROCKET_INACTIVE_ACCOUNT_DAYS", 0)?;
+        config.inactive_account_action = match optional("ROCKET_INACTIVE_ACCOUNT_ACTION")?.as_deref() {
+            None | Some("flag") => InactiveAccountAction::Flag,
+            Some("disable") => InactiveAccountAction::Disable,
+            Some(other) => {
+                return Err(ConfigError::Invalid {
+                    key: "ROCKET_INACTIVE_ACCOUNT_ACTION",
+                    message: format!("unknown action '{}', expected 'flag' or 'disable'", other),
+                });
+            }
+        };
+        config.inactive_account_warning_days = number("ROCKET_INACTIVE_ACCOUNT_WARNING_DAYS", 0)?;
+        if config.inactive_account_days > 0 && config.inactive_account_warning_days >= config.inactive_account_days {
+            return Err(ConfigError::Invalid {
+                key: "ROCKET_INACTIVE_ACCOUNT_WARNING_DAYS",
+                message: "warnings must go out fewer days ahead than ROCKET_INACTIVE_ACCOUNT_DAYS".to_string(),
+            });
+        }
+
+        config.audit_retention_days = number("ROCKET_AUDIT_RETENTION_DAYS", 0)?;
+        config.login_history_retention_days = number("ROCKET_LOGIN_HISTORY_RETENTION_DAYS", 0)?;
+        // The lockout counts failures from login_attempts, so they must outlive its window
+        if config.login_history_retention_days > 0 && config.login_history_retention_days * 24 * 60 < config.login_lockout_minutes {
+            return Err(ConfigError::Invalid {
+                key: "ROCKET_LOGIN_HISTORY_RETENTION_DAYS",
+                message: "login attempts must be kept at least as long as ROCKET_LOGIN_LOCKOUT_MINUTES".to_string(),
+            });
+        }
+        config.session_retention_days = number("ROCKET_SESSION_RETENTION_DAYS", 0)?;
+        config.retention_dry_run = flag("ROCKET_RETENTION_DRY_RUN")?;
+
+        for name in optional("ROCKET_AUDIT_SINKS")?.unwrap_or_default().split(',') {
+            let sink = match name.trim() {
+                "" => continue,
+                "stdout" => AuditSinkConfig::Stdout,
+                "syslog" => AuditSinkConfig::Syslog {
+                    address: optional("ROCKET_AUDIT_SYSLOG_ADDRESS")?.unwrap_or_else(|| "/dev/log".to_string()),
+                },
+                "http" => AuditSinkConfig::Http {
+                    url: required("ROCKET_AUDIT_HTTP_URL")?,
+                    authorization: optional("ROCKET_AUDIT_HTTP_AUTHORIZATION")?,
+                    format: match optional("ROCKET_AUDIT_HTTP_FORMAT")?.as_deref() {
+                        None | Some("json") => HttpSinkFormat::Json,
+                        Some("splunk-hec") => HttpSinkFormat::SplunkHec,
+                        Some(other) => {
+                            return Err(ConfigError::Invalid {
+                                key: "ROCKET_AUDIT_HTTP_FORMAT",
+                                message: format!("unknown format '{}', expected 'json' or 'splunk-hec'", other),
+                            });
+                        }
+                    },
+                },
+                other => {
+                    return Err(ConfigError::Invalid {
+                        key: "ROCKET_AUDIT_SINKS",
+                        message: format!("unknown sink '{}', expected 'stdout', 'syslog' or 'http'", other),
+                    });
+                }
+            };
+            config.audit_sinks.push(sink);
+        }
+        config.audit_sink_max_attempts = number("ROCKET_AUDIT_SINK_MAX_ATTEMPTS", 4)?;
+        if config.audit_sink_max_attempts == 0 {
+            return Err(ConfigError::Invalid {
+                key: "ROCKET_AUDIT_SINK_MAX_ATTEMPTS",
+                message: "events need at least one delivery attempt".to_string(),
+            });
+        }
+        config.audit_sink_retry_seconds = number("ROCKET_AUDIT_SINK_RETRY_SECONDS", 5)?;
+        config.telemetry_flush_ms = number("ROCKET_TELEMETRY_FLUSH_MS", 0)?;
+        config.load_shed_credentials_limit = number("ROCKET_LOAD_SHED_CREDENTIALS_LIMIT", 0)?;
+        config.load_shed_limit = number("ROCKET_LOAD_SHED_LIMIT", 0)?;
+        config.load_shed_retry_after_seconds = number("ROCKET_LOAD_SHED_RETRY_AFTER_SECONDS", 1)?;
+
+        if let Some(issuer) = optional("ROCKET_EXTERNAL_JWT_ISSUER")? {
+            // Auth0 and Cognito publish keys here; Firebase needs ROCKET_EXTERNAL_JWT_JWKS_URL
+            let jwks_url = match optional("ROCKET_EXTERNAL_JWT_JWKS_URL")? {
+                Some(url) => url,
+                None => format!("{}/.well-known/jwks.json", issuer.trim_end_matches('/')),
+            };
+            config.external_jwt = Some(ExternalJwtConfig {
+                audience: required("ROCKET_EXTERNAL_JWT_AUDIENCE")?,
+                issuer,
+                jwks_url,
+            });
+        }
+
+        if let Some(ids) = optional("ROCKET_GOOGLE_CLIENT_IDS")? {
+            config.google_client_ids = ids
+                .split(',')
+                .map(str::trim)
+                .filter(|id| !id.is_empty())
+                .map(String::from)
+                .collect();
+        }
+
+        if let Some(client_id) = optional("ROCKET_MICROSOFT_CLIENT_ID")? {
+            let tenant = optional("ROCKET_MICROSOFT_TENANT")?.unwrap_or_else(|| "organizations".to_string());
+            let microsoft = MicrosoftConfig {
+                client_id,
+                client_secret: required("ROCKET_MICROSOFT_CLIENT_SECRET")?,
+                tenant,
+            };
+            if !microsoft.is_multi_tenant() && Uuid::parse_str(&microsoft.tenant).is_err() {
+                return Err(ConfigError::Invalid {
+                    key: "ROCKET_MICROSOFT_TENANT",
+                    message: "expected a directory id, 'organizations', 'common' or 'consumers'".to_string(),
+                });
+            }
+            config.microsoft = Some(microsoft);
+        }
+
+        if let Some(client_id) = optional("ROCKET_DISCORD_CLIENT_ID")? {
+            let mut guild_roles = Vec::new();
+            for pair in optional("ROCKET_DISCORD_GUILD_ROLES")?.unwrap_or_default().split(',') {
+                let pair = pair.trim();
+                if pair.is_empty() {
+                    continue;
+                }
+                match pair.split_once('=') {
+                    Some((guild_id, role)) if !guild_id.trim().is_empty() && !role.trim().is_empty() => {
+                        guild_roles.push((guild_id.trim().to_string(), role.trim().to_string()));
+                    }
+                    _ => {
+                        return Err(ConfigError::Invalid {
+                            key: "ROCKET_DISCORD_GUILD_ROLES",
+                            message: format!("expected '<guild id>=<role>', got '{}'", pair),
+                        });
+                    }
+                }
+            }
+            config.discord = Some(DiscordConfig {
+                client_id,
+                client_secret: required("ROCKET_DISCORD_CLIENT_SECRET")?,
+                guild_roles,
+            });
+        }
+
+        if let Some(app_id) = optional("ROCKET_FACEBOOK_APP_ID")? {
+            config.facebook = Some(FacebookConfig {
+                app_id,
+                app_secret: required("ROCKET_FACEBOOK_APP_SECRET")?,
+            });
+        }
+
+        if let Some(secret_key) = optional("ROCKET_STRIPE_SECRET_KEY")? {
+            config.stripe = Some(StripeConfig {
+                secret_key,
+                webhook_secret: optional("ROCKET_STRIPE_WEBHOOK_SECRET")?,
+                api_url: optional("ROCKET_STRIPE_API_URL")?.unwrap_or_else(|| "https://api.stripe.com".to_string()),
+            });
+        }
+
+        for name in optional("ROCKET_OAUTH_PROVIDERS")?.unwrap_or_default().split(',') {
+            let name = name.trim();
+            if !name.is_empty() {
+                config.oauth_providers.push(generic_oauth_provider(name)?);
+            }
+        }
+
+        config.client_registration_token = optional("ROCKET_CLIENT_REGISTRATION_TOKEN")?;
+
+        for audience in optional("ROCKET_TOKEN_EXCHANGE_AUDIENCES")?.unwrap_or_default().split(',') {
+            let audience = audience.trim();
+            if !audience.is_empty() {
+                config.token_exchange.push(token_exchange_policy(audience)?);
+            }
+        }
+
+        config.maintenance_mode = flag("ROCKET_MAINTENANCE_MODE")?;
+        config.runtime_settings = flag_or("ROCKET_RUNTIME_SETTINGS", true)?;
+        config.unix_socket = optional("ROCKET_UNIX_SOCKET")?.map(PathBuf::from);
+        config.tls = match (optional("ROCKET_TLS_CERT")?, optional("ROCKET_TLS_KEY")?) {
+            (Some(cert), Some(key)) => Some(TlsConfig {
+                cert_path: PathBuf::from(cert),
+                key_path: PathBuf::from(key),
+                client_ca_path: optional("ROCKET_TLS_CLIENT_CA")?.map(PathBuf::from),
+            }),
+            (None, None) => None,
+            (Some(_), None) => return Err(ConfigError::Missing("ROCKET_TLS_KEY")),
+            (None, Some(_)) => return Err(ConfigError::Missing("ROCKET_TLS_CERT")),
+        };
+        if config.tls.is_none() && optional("ROCKET_TLS_CLIENT_CA")?.is_some() {
+            return Err(ConfigError::Invalid {
+                key: "ROCKET_TLS_CLIENT_CA",
+                message: "mutual TLS needs ROCKET_TLS_CERT and ROCKET_TLS_KEY".to_string(),
+            });
+        }
+        config.body_limits = BodyLimits {
+            json: byte_size("ROCKET_JSON_LIMIT", BodyLimits::default().json)?,
+            credentials: byte_size("ROCKET_CREDENTIALS_JSON_LIMIT", BodyLimits::default().credentials)?,
+            bulk: byte_size("ROCKET_BULK_JSON_LIMIT", BodyLimits::default().bulk)?,
+            json_depth: number("ROCKET_JSON_MAX_DEPTH", BodyLimits::default().json_depth)?,
+        };
+        if config.body_limits.json_depth == 0 {
+            return Err(ConfigError::Invalid {
+                key: "ROCKET_JSON_MAX_DEPTH",
+                message: "must be at least 1".to_string(),
+            });
+        }
+
+        if config.tls.is_some() && config.unix_socket.is_some() {
+            return Err(ConfigError::Invalid {
+                key: "ROCKET_TLS_CERT",
+                message: "TLS isn't served on ROCKET_UNIX_SOCKET; terminate it at the proxy".to_string(),
+            });
+        }
+
+        if let Some(url) = optional("ROCKET_PUBLIC_URL")? {
+            config.public_url = url.trim_end_matches('/').to_string();
+        }
+        config.frontend_url = match optional("ROCKET_FRONTEND_URL")? {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None => config.public_url.clone(),
+        };
+        if let Some(allowlist) = optional("ROCKET_REDIRECT_ALLOWLIST")? {
+            for entry in allowlist.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+                if !redirects::valid_pattern(entry) {
+                    return Err(ConfigError::Invalid {
+                        key: "ROCKET_REDIRECT_ALLOWLIST",
+                        message: format!("'{}' isn't an http(s) URL or pattern like https://*.example.com/app/*", entry),
+                    });
+                }
+                config.redirect_allowlist.push(entry.to_string());
+            }
+        }
+
+        config.email_transport = match optional("ROCKET_EMAIL_TRANSPORT")?.as_deref() {
+            None | Some("log") => EmailTransport::Log,
+            Some("memory") => EmailTransport::Memory,
+            Some("smtp") => EmailTransport::Smtp,
+            Some(other) => {
+                return Err(ConfigError::Invalid {
+                    key: "ROCKET_EMAIL_TRANSPORT",
+                    message: format!("unknown transport '{}', expected 'log', 'memory' or 'smtp'", other),
+                });
+            }
+        };
+        if let Some(host) = optional("ROCKET_SMTP_HOST")? {
+            let security = match optional("ROCKET_SMTP_SECURITY")?.as_deref() {
+                None | Some("starttls") => SmtpSecurity::StartTls,
+                Some("tls") => SmtpSecurity::Tls,
+                Some("none") => SmtpSecurity::None,
+                Some(other) => {
+                    return Err(ConfigError::Invalid {
+                        key: "ROCKET_SMTP_SECURITY",
+                        message: format!("unknown security '{}', expected 'starttls', 'tls' or 'none'", other),
+                    });
+                }
+            };
+            let default_port = if security == SmtpSecurity::Tls { 465 } else { 587 };
+            config.smtp = Some(SmtpConfig {
+                host,
+                port: number("ROCKET_SMTP_PORT", default_port)?,
+                username: optional("ROCKET_SMTP_USERNAME")?,
+                password: optional("ROCKET_SMTP_PASSWORD")?,
+                security,
+            });
+        }
+        if config.email_transport == EmailTransport::Smtp && config.smtp.is_none() {
+            return Err(ConfigError::Missing("ROCKET_SMTP_HOST"));
+        }
+        if let Some(from) = optional("ROCKET_EMAIL_FROM")? {
+            config.email_from = from;
+        }
+
+        config.email_links = match optional("ROCKET_EMAIL_LINKS")?.as_deref() {
+            None | Some("web") => EmailLinkStyle::Web,
+            Some("app") => EmailLinkStyle::App {
+                scheme: app_url_scheme(required("ROCKET_APP_URL_SCHEME")?),
+            },
+            Some("universal") => EmailLinkStyle::Universal {
+                scheme: app_url_scheme(required("ROCKET_APP_URL_SCHEME")?),
+            },
+            Some(other) => {
+                return Err(ConfigError::Invalid {
+                    key: "ROCKET_EMAIL_LINKS",
+                    message: format!("unknown link style '{}', expected 'web', 'app' or 'universal'", other),
+                });
+            }
+        };
+
+        config.email_tokens = match optional("ROCKET_EMAIL_TOKENS")?.as_deref() {
+            None | Some("stored") => EmailTokenMode::Stored,
+            Some("signed") => EmailTokenMode::Signed {
+                secret: required("ROCKET_EMAIL_TOKEN_SECRET")?,
+            },
+            Some(other) => {
+                return Err(ConfigError::Invalid {
+                    key: "ROCKET_EMAIL_TOKENS",
+                    message: format!("unknown value '{}', expected 'stored' or 'signed'", other),
+                });
+            }
+        };
+
+        config.signed_url_secret = optional("ROCKET_SIGNED_URL_SECRET")?;
+        config.signed_url_ttl_seconds = number("ROCKET_SIGNED_URL_TTL_SECONDS", 300)?;
+
+        config.registration_mode = match optional("ROCKET_REGISTRATION_MODE")?.as_deref() {
+            None | Some("open") => RegistrationMode::Open,
+            Some("invite-only") => RegistrationMode::InviteOnly,
+            Some("closed") => RegistrationMode::Closed,
+            Some(other) => {
+                return Err(ConfigError::Invalid {
+                    key: "ROCKET_REGISTRATION_MODE",
+                    message: format!("unknown mode '{}', expected 'open', 'invite-only' or 'closed'", other),
+                });
+            }
+        };
+
+        config.signup_approval = flag("ROCKET_SIGNUP_APPROVAL")?;
+        config.registration_uniform_response = flag("ROCKET_REGISTRATION_UNIFORM_RESPONSE")?;
+        config.welcome_email = match optional("ROCKET_WELCOME_EMAIL")?.as_deref() {
+            None | Some("off") => WelcomeEmail::Off,
+            Some("on") => WelcomeEmail::On,
+            Some("with-verification") => WelcomeEmail::WithVerification,
+            Some(other) => {
+                return Err(ConfigError::Invalid {
+                    key: "ROCKET_WELCOME_EMAIL",
+                    message: format!("unknown value '{}', expected 'off', 'on' or 'with-verification'", other),
+                });
+            }
+        };
+        config.app_metadata_claim = flag("ROCKET_JWT_APP_METADATA")?;
+
+        config.ownership_denial = match optional("ROCKET_OWNERSHIP_DENIAL")?.as_deref() {
+            None | Some("not-found") => OwnershipDenial::NotFound,
+            Some("forbidden") => OwnershipDenial::Forbidden,
+            Some(other) => {
+                return Err(ConfigError::Invalid {
+                    key: "ROCKET_OWNERSHIP_DENIAL",
+                    message: format!("unknown value '{}', expected 'not-found' or 'forbidden'", other),
+                });
+            }
+        };
+
+        config.legacy_api = flag_or("ROCKET_LEGACY_API", true)?;
+        config.legacy_api_sunset = match optional("ROCKET_LEGACY_API_SUNSET")? {
+            Some(value) => Some(parse_date(&value).ok_or(ConfigError::Invalid {
+                key: "ROCKET_LEGACY_API_SUNSET",
+                message: format!("expected a date like 2027-01-31 or an RFC 3339 timestamp, got '{}'", value),
+            })?),
+            None => None,
+        };
+
+        config.compression = flag_or("ROCKET_COMPRESSION", true)?;
+        config.compression_min_bytes = number("ROCKET_COMPRESSION_MIN_BYTES", config.compression_min_bytes)?;
+
+        config.request_log = match optional("ROCKET_REQUEST_LOG")?.as_deref() {
+            None | Some("off") => RequestLog::Off,
+            Some("summary") => RequestLog::Summary,
+            Some("bodies") => RequestLog::Bodies,
+            Some(other) => {
+                return Err(ConfigError::Invalid {
+                    key: "ROCKET_REQUEST_LOG",
+                    message: format!("unknown value '{}', expected 'off', 'summary' or 'bodies'", other),
+                });
+            }
+        };
+
+        config.password_hashing = match optional("ROCKET_PASSWORD_HASH")?.as_deref() {
+            None | Some("bcrypt") => {
+                let cost = number("ROCKET_BCRYPT_COST", bcrypt::DEFAULT_COST)?;
+                if !(4..=31).contains(&cost) {
+                    return Err(ConfigError::Invalid {
+                        key: "ROCKET_BCRYPT_COST",
+                        message: format!("expected a cost between 4 and 31, got {}", cost),
+                    });
+                }
+                PasswordHashing::Bcrypt { cost }
+            }
+            Some("argon2") => {
+                let memory_kib = number("ROCKET_ARGON2_MEMORY_KIB", argon2::Params::DEFAULT_M_COST)?;
+                let iterations = number("ROCKET_ARGON2_ITERATIONS", argon2::Params::DEFAULT_T_COST)?;
+                let parallelism = number("ROCKET_ARGON2_PARALLELISM", argon2::Params::DEFAULT_P_COST)?;
+                argon2::Params::new(memory_kib, iterations, parallelism, None).map_err(|e| ConfigError::Invalid {
+                    key: "ROCKET_ARGON2_MEMORY_KIB",
+                    message: format!("invalid Argon2 parameters: {}", e),
+                })?;
+                PasswordHashing::Argon2 {
+                    memory_kib,
+                    iterations,
+                    parallelism,
+                }
+            }
+            Some(other) => {
+                return Err(ConfigError::Invalid {
+                    key: "ROCKET_PASSWORD_HASH",
+                    message: format!("unknown algorithm '{}', expected 'bcrypt' or 'argon2'", other),
+                });
+            }
+        };
+        config.password_hash_calibration = flag("ROCKET_PASSWORD_HASH_CALIBRATION")?;
+        config.password_hash_threads = number("ROCKET_PASSWORD_HASH_THREADS", 0)?;
+        config.password_hash_queue = number("ROCKET_PASSWORD_HASH_QUEUE", DEFAULT_HASH_QUEUE)?;
+
+        if let Some(value) = optional("ROCKET_PASSWORD_PEPPERS")? {
+            config.password_peppers = parse_peppers(&value)?;
+        }
+
+        Ok(config)
+    }
+}
+
+/// The JWT signer selected by `ROCKET_JWT_SIGNER`
+fn jwt_signer() -> Result<SignerConfig, ConfigError> {
+    match optional("ROCKET_JWT_SIGNER")?.as_deref() {
+        None | Some("hmac") => Ok(SignerConfig::Hmac {
+            secret: required("ROCKET_JWT_SECRET")?,
+        }),
+        Some("kms") => Ok(SignerConfig::Kms {
+            key_id: required("ROCKET_JWT_KMS_KEY_ID")?,
+        }),
+        Some(other) => Err(ConfigError::Invalid {
+            key: "ROCKET_JWT_SIGNER",
+            message: format!("unknown signer '{}', expected 'hmac' or 'kms'", other),
+        }),
+    }
+}
+
+/// Parse `v2:secret,v1:kms:<ciphertext>` into peppers, current first
+fn parse_peppers(value: &str) -> Result<Vec<PepperConfig>, ConfigError> {
+    let invalid = |message: String| ConfigError::Invalid {
+        key: "ROCKET_PASSWORD_PEPPERS",
+        message,
+    };
+
+    let mut peppers: Vec<PepperConfig> = Vec::new();
+    for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let (id, secret) = entry
+            .split_once(':')
+            .filter(|(id, secret)| !id.is_empty() && !secret.is_empty())
+            .ok_or_else(|| invalid(format!("expected entries like 'v1:<secret>', got '{}'", entry)))?;
+        if peppers.iter().any(|pepper| pepper.id == id) {
+            return Err(invalid(format!("pepper id '{}' is listed twice", id)));
+        }
+
+        let secret = match secret.strip_prefix("kms:") {
+            Some(ciphertext) if cfg!(feature = "aws-kms") => PepperSecret::Kms {
+                ciphertext: ciphertext.to_string(),
+            },
+            Some(_) => return Err(invalid("KMS-encrypted peppers require the `aws-kms` feature".to_string())),
+            None => PepperSecret::Plain(secret.to_string()),
+        };
+        peppers.push(PepperConfig {
+            id: id.to_string(),
+            secret,
+        });
+    }
+    Ok(peppers)
+}
+
+/// Parse `2027-01-31` (midnight UTC) or an RFC 3339 timestamp
+pub(crate) fn parse_date(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Some(timestamp.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?;
+    Some(date.and_hms_opt(0, 0, 0)?.and_utc())
+}
+
+/// Normalize an app URL prefix so paths can be appended: `myapp` -> `myapp://`
+fn app_url_scheme(value: String) -> String {
+    if !value.contains("://") {
+        format!("{}://", value)
+    } else if value.ends_with('/') {
+        value
+    } else {
+        format!("{}/", value)
+    }
+}
+
+/// Names taken by the built-in providers
+const BUILT_IN_OAUTH_PROVIDERS: [&str; 4] = ["google", "microsoft", "discord", "facebook"];
+
+/// Read `ROCKET_OAUTH_<NAME>_*` for a provider listed in `ROCKET_OAUTH_PROVIDERS`
+fn generic_oauth_provider(name: &str) -> Result<GenericOAuthConfig, ConfigError> {
+    if BUILT_IN_OAUTH_PROVIDERS.contains(&name)
+        || !name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        return Err(ConfigError::Invalid {
+            key: "ROCKET_OAUTH_PROVIDERS",
+            message: format!(
+                "'{}' must be lowercase letters, digits and dashes, and not one of {}",
+                name,
+                BUILT_IN_OAUTH_PROVIDERS.join(", ")
+            ),
+        });
+    }
+
+    // Keys are built from the provider name; they're read once at startup, so leaking them is fine
+    let prefix = format!("ROCKET_OAUTH_{}_", name.to_ascii_uppercase().replace('-', "_"));
+    let key = |suffix: &str| -> &'static str { Box::leak(format!("{}{}", prefix, suffix).into_boxed_str()) };
+    let url = |suffix: &str| -> Result<String, ConfigError> {
+        let key = key(suffix);
+        let value = required(key)?;
+        reqwest::Url::parse(&value).map_err(|e| ConfigError::Invalid {
+            key,
+            message: e.to_string(),
+        })?;
+        Ok(value)
+    };
+
+    Ok(GenericOAuthConfig {
+        name: name.to_string(),
+        client_id: required(key("CLIENT_ID"))?,
+        client_secret: required(key("CLIENT_SECRET"))?,
+        authorize_url: url("AUTHORIZE_URL")?,
+        token_url: url("TOKEN_URL")?,
+        userinfo_url: url("USERINFO_URL")?,
+        scopes: optional(key("SCOPES"))?.unwrap_or_default(),
+        subject_field: optional(key("SUBJECT_FIELD"))?.unwrap_or_else(|| "id".to_string()),
+        email_field: optional(key("EMAIL_FIELD"))?.unwrap_or_else(|| "email".to_string()),
+        email_verified_field: optional(key("EMAIL_VERIFIED_FIELD"))?,
+        basic_auth: match optional(key("TOKEN_AUTH"))?.as_deref() {
+            None | Some("post") => false,
+            Some("basic") => true,
+            Some(other) => {
+                return Err(ConfigError::Invalid {
+                    key: key("TOKEN_AUTH"),
+                    message: format!("unknown method '{}', expected 'post' or 'basic'", other),
+                });
+            }
+        },
+        pkce: flag_or(key("PKCE"), true)?,
+    })
+}
+
+fn token_exchange_policy(audience: &str) -> Result<TokenExchangePolicy, ConfigError> {
+    if !audience.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-') {
+        return Err(ConfigError::Invalid {
+            key: "ROCKET_TOKEN_EXCHANGE_AUDIENCES",
+            message: format!("'{}' must be lowercase letters, digits and dashes", audience),
+        });
+    }
+
+    // Keys are built from the audience; they're read once at startup, so leaking them is fine
+    let prefix = format!("ROCKET_TOKEN_EXCHANGE_{}_", audience.to_ascii_uppercase().replace('-', "_"));
+    let key = |suffix: &str| -> &'static str { Box::leak(format!("{}{}", prefix, suffix).into_boxed_str()) };
+    let list = |value: String| -> Vec<String> {
+        value
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|item| !item.is_empty())
+            .map(str::to_string)
+            .collect()
+    };
+
+    let scopes = list(required(key("SCOPES"))?);
+    if let Some(unknown) = scopes.iter().find(|scope| !scopes::is_known(scope)) {
+        return Err(ConfigError::Invalid {
+            key: key("SCOPES"),
+            message: format!("unknown scope '{}'", unknown),
+        });
+    }
+
+    Ok(TokenExchangePolicy {
+        audience: audience.to_string(),
+        clients: list(required(key("CLIENTS"))?),
+        scopes,
+        lifetime_seconds: number(key("LIFETIME_SECONDS"), 300)?,
+    })
+}
+
+fn required(key: &'static str) -> Result<String, ConfigError> {
+    optional(key)?.ok_or(ConfigError::Missing(key))
+}
+
+fn flag(key: &'static str) -> Result<bool, ConfigError> {
+    flag_or(key, false)
+}
+
+fn flag_or(key: &'static str, default: bool) -> Result<bool, ConfigError> {
+    match optional(key)?.as_deref() {
+        None => Ok(default),
+        Some("false") | Some("0") => Ok(false),
+        Some("true") | Some("1") => Ok(true),
+        Some(other) => Err(ConfigError::Invalid {
+            key,
+            message: format!("expected true or false, got '{}'", other),
+        }),
+    }
+}
+
+/// A size like `64KiB`, `1 MB` or a plain number of bytes
+fn byte_size(key: &'static str, default: ByteUnit) -> Result<ByteUnit, ConfigError> {
+    match optional(key)? {
+        None => Ok(default),
+        Some(value) => value.parse().map_err(|_| ConfigError::Invalid {
+            key,
+            message: format!("expected a size like 64KiB, got '{}'", value),
+        }),
+    }
+}
+
+fn number<T: std::str::FromStr>(key: &'static str, default: T) -> Result<T, ConfigError> {
+    match optional(key)? {
+        None => Ok(default),
+        Some(value) => value.parse().map_err(|_| ConfigError::Invalid {
+            key,
+            message: format!("expected a non-negative number, got '{}'", value),
+        }),
+    }
+}
+
+/// Read a setting from `KEY`, or from the file named by `KEY_FILE`
+///
+/// The `_FILE` form lets secrets be mounted as files (Docker/Kubernetes
+/// secrets) instead of being passed through the environment. Setting both
+/// is rejected so it's never ambiguous which value is in effect.
+fn optional(key: &'static str) -> Result<Option<String>, ConfigError> {
+    let value = std::env::var(key).ok().filter(|value| !value.is_empty());
+    let file_key = format!("{}_FILE", key);
+    let path = std::env::var(&file_key).ok().filter(|path| !path.is_empty());
+
+    match (value, path) {
+        (Some(_), Some(_)) => Err(ConfigError::Invalid {
+            key,
+            message: format!("set either {} or {}, not both", key, file_key),
+        }),
+        (Some(value), None) => Ok(Some(value)),
+        (None, Some(path)) => {
+            let contents = std::fs::read_to_string(&path).map_err(|e| ConfigError::Invalid {
+                key,
+                message: format!("failed to read {} ({}): {}", file_key, path, e),
+            })?;
+            // Secret files usually end with a newline
+            let contents = contents.trim_end_matches(['\r', '\n']).to_string();
+            Ok(Some(contents).filter(|value| !value.is_empty()))
+        }
+        (None, None) => Ok(None),
+    }
+}
@@ -0,0 +1,165 @@
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+use std::time::Duration;
+
+use rocket::figment::Figment;
+use serde::Deserialize;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::pool::PoolConnection;
+use sqlx::{ConnectOptions, PgPool};
+
+use crate::circuit_breaker::CircuitBreaker;
+use crate::config::{DbCircuitBreakerConfig, DbPoolConfig};
+
+/// Connect to `url` with the pool settings from `config`
+///
+/// Used for the migration pool; the server's pool is built the same way by [`Pool`].
+pub async fn connect(url: &str, config: &DbPoolConfig) -> Result<PgPool, sqlx::Error> {
+    let options = connect_options(url, config)?;
+    if config.lazy_connect {
+        return Ok(self::options(config).connect_lazy_with(options));
+    }
+    self::options(config).connect_with(options).await
+}
+
+fn options(config: &DbPoolConfig) -> PgPoolOptions {
+    let seconds = |seconds: u64| (seconds > 0).then(|| Duration::from_secs(seconds));
+    PgPoolOptions::new()
+        .max_connections(config.max_connections)
+        .min_connections(config.min_connections)
+        .acquire_timeout(Duration::from_secs(config.acquire_timeout_seconds))
+        .idle_timeout(seconds(config.idle_timeout_seconds))
+        .max_lifetime(seconds(config.max_lifetime_seconds))
+}
+
+fn connect_options(url: &str, config: &DbPoolConfig) -> Result<PgConnectOptions, sqlx::Error> {
+    let mut options = PgConnectOptions::from_str(url)?.disable_statement_logging();
+    if config.statement_timeout_ms > 0 {
+        options = options.options([("statement_timeout", config.statement_timeout_ms.to_string())]);
+    }
+    Ok(options)
+}
+
+/// One line describing the effective pool settings, for the startup log
+pub fn describe(config: &DbPoolConfig) -> String {
+    let seconds = |seconds: u64| match seconds {
+        0 => "none".to_string(),
+        seconds => format!("{}s", seconds),
+    };
+    format!(
+        "{}-{} connections{}, acquire timeout {}s, idle timeout {}, max lifetime {}, statement timeout {}",
+        config.min_connections,
+        config.max_connections,
+        if config.lazy_connect { " (connected on first use)" } else { "" },
+        config.acquire_timeout_seconds,
+        seconds(config.idle_timeout_seconds),
+        seconds(config.max_lifetime_seconds),
+        match config.statement_timeout_ms {
+            0 => "none".to_string(),
+            ms => format!("{}ms", ms),
+        }
+    )
+}
+
+/// Add the pool settings to the `databases.postgres` section Rocket reads the server's pool from
+pub fn configure(figment: Figment, url: &str, config: &DbPoolConfig, breaker: &DbCircuitBreakerConfig) -> Figment {
+    figment
+        .merge(("databases.postgres.url", url))
+        .merge(("databases.postgres.pool", config))
+        .merge(("databases.postgres.breaker", breaker))
+}
+
+#[derive(Deserialize)]
+struct Settings {
+    url: String,
+    #[serde(default)]
+    pool: DbPoolConfig,
+    #[serde(default)]
+    breaker: DbCircuitBreakerConfig,
+}
+
+/// Why a request couldn't get a database connection
+#[derive(Debug)]
+pub enum GetError {
+    /// Recent attempts failed; not trying again until the breaker lets a probe through
+    CircuitOpen,
+    Sqlx(sqlx::Error),
+}
+
+impl fmt::Display for GetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GetError::CircuitOpen => write!(f, "database circuit breaker is open"),
+            GetError::Sqlx(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for GetError {}
+
+/// The server's connection pool, built from [`DbPoolConfig`] rather than Rocket's pool settings
+///
+/// Connections for requests go through a [`CircuitBreaker`]: once getting
+/// one keeps failing (Postgres is down or unreachable), requests fail with
+/// 503 right away instead of each waiting out the acquire timeout.
+/// Background jobs use the pool directly.
+pub struct Pool {
+    pool: PgPool,
+    breaker: CircuitBreaker,
+}
+
+impl Pool {
+    pub fn breaker(&self) -> &CircuitBreaker {
+        &self.breaker
+    }
+}
+
+impl Deref for Pool {
+    type Target = PgPool;
+
+    fn deref(&self) -> &PgPool {
+        &self.pool
+    }
+}
+
+#[rocket::async_trait]
+impl rocket_db_pools::Pool for Pool {
+    type Error = rocket_db_pools::Error<sqlx::Error, GetError>;
+
+    type Connection = PoolConnection<sqlx::Postgres>;
+
+    async fn init(figment: &Figment) -> Result<Self, Self::Error> {
+        let settings = figment.extract::<Settings>()?;
+        let pool = connect(&settings.url, &settings.pool)
+            .await
+            .map_err(rocket_db_pools::Error::Init)?;
+        println!("✓ Database pool: {}", describe(&settings.pool));
+        let breaker = CircuitBreaker::new(
+            "Database",
+            settings.breaker.failure_threshold,
+            Duration::from_secs(settings.breaker.open_seconds),
+        );
+        Ok(Pool { pool, breaker })
+    }
+
+    async fn get(&self) -> Result<Self::Connection, Self::Error> {
+        if !self.breaker.allow() {
+            return Err(rocket_db_pools::Error::Get(GetError::CircuitOpen));
+        }
+        match self.pool.acquire().await {
+            Ok(conn) => {
+                self.breaker.record_success();
+                Ok(conn)
+            }
+            Err(e) => {
+                self.breaker.record_failure();
+                Err(rocket_db_pools::Error::Get(GetError::Sqlx(e)))
+            }
+        }
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+}
@@ -0,0 +1,96 @@
+use std::convert::Infallible;
+
+use rocket::request::{FromRequest, Outcome, Request};
+
+use crate::models::session::SessionDevice;
+use crate::risk;
+
+/// Longest user agent kept (`sessions.user_agent` is VARCHAR(512)); longer ones are cut
+const MAX_USER_AGENT_LENGTH: usize = 512;
+
+/// Longest city kept (`sessions.city` is VARCHAR(100))
+const MAX_CITY_LENGTH: usize = 100;
+
+/// Longest country kept (`sessions.country` is VARCHAR(8))
+const MAX_COUNTRY_LENGTH: usize = 8;
+
+/// The client details for a session started with these request headers
+///
+/// The browser, OS and kind of device are parsed from the user agent; any of
+/// them is `None` when it can't be told.
+pub fn from_headers(user_agent: Option<&str>, city: Option<&str>, country: Option<&str>) -> SessionDevice {
+    let user_agent = user_agent.map(str::trim).filter(|user_agent| !user_agent.is_empty());
+    let parsed = user_agent.and_then(|user_agent| woothee::parser::Parser::new().parse(user_agent));
+    let known = |value: &str| (value != woothee::woothee::VALUE_UNKNOWN).then(|| value.to_string());
+
+    SessionDevice {
+        user_agent: user_agent.map(|user_agent| truncate(user_agent, MAX_USER_AGENT_LENGTH)),
+        browser: parsed.as_ref().and_then(|parsed| known(parsed.name)),
+        os: parsed.as_ref().and_then(|parsed| known(parsed.os)).map(|os| os_name(&os)),
+        device_type: parsed.as_ref().and_then(|parsed| device_type(parsed.category, parsed.os)),
+        city: city.map(|city| truncate(city, MAX_CITY_LENGTH)),
+        country: country.and_then(country_code),
+    }
+}
+
+/// A country header's value as stored, upper case; `None` if it doesn't look like a country code
+pub fn country_code(country: &str) -> Option<String> {
+    (country.len() <= MAX_COUNTRY_LENGTH && country.chars().all(|c| c.is_ascii_alphanumeric()))
+        .then(|| country.to_uppercase())
+}
+
+/// A short description for people, like `Chrome on macOS, Berlin`; `None` if nothing is known
+pub fn describe(device: &SessionDevice) -> Option<String> {
+    let client = match (&device.browser, &device.os) {
+        (Some(browser), Some(os)) => Some(format!("{} on {}", browser, os)),
+        (Some(only), None) | (None, Some(only)) => Some(only.clone()),
+        (None, None) => None,
+    };
+    let place = device.city.clone().or_else(|| device.country.clone());
+    match (client, place) {
+        (Some(client), Some(place)) => Some(format!("{}, {}", client, place)),
+        (client, place) => client.or(place),
+    }
+}
+
+/// The usual name of an OS where the parser's differs
+fn os_name(os: &str) -> String {
+    match os {
+        "Mac OSX" => "macOS",
+        "iPhone" | "iPod" => "iOS",
+        "iPad" => "iPadOS",
+        os => os,
+    }
+    .to_string()
+}
+
+/// `desktop`, `mobile`, `tablet`, `bot` or `other`, from the parser's category
+fn device_type(category: &str, os: &str) -> Option<String> {
+    let kind = match category {
+        "pc" => "desktop",
+        "smartphone" if os == "iPad" => "tablet",
+        "smartphone" | "mobilephone" => "mobile",
+        "crawler" => "bot",
+        "appliance" | "misc" => "other",
+        _ => return None,
+    };
+    Some(kind.to_string())
+}
+
+fn truncate(value: &str, max: usize) -> String {
+    value.chars().take(max).collect()
+}
+
+/// Request guard with the details of the client a session is started for
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SessionDevice {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(from_headers(
+            request.headers().get_one("User-Agent"),
+            risk::geo_header(request, |config| config.geo_city_header.as_deref()),
+            risk::geo_header(request, |config| config.geo_country_header.as_deref()),
+        ))
+    }
+}
@@ -0,0 +1,272 @@
+use std::fmt;
+use std::time::Duration;
+
+use chrono::Utc;
+use rocket::fairing::AdHoc;
+use rocket_db_pools::Database;
+use serde::Deserialize;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::email::sender::Mailer;
+use crate::email::templates;
+use crate::models::organization::{DomainJoinMode, OrgDomain};
+use crate::models::user::User;
+use crate::repositories::{invitations, organizations, users};
+use crate::Postgres;
+
+/// Label the TXT record proving ownership of a domain goes under, as in `_auth-verification.example.com`
+pub const CHALLENGE_LABEL: &str = "_auth-verification";
+
+/// Prefix of the TXT record's value, followed by the domain's verification token
+pub const CHALLENGE_PREFIX: &str = "auth-verification=";
+
+/// How often each server instance looks for the records of unverified domains
+const CHECK_INTERVAL_MINUTES: u64 = 10;
+
+/// Days after being claimed that a domain is still checked in the background
+const CHECK_WINDOW_DAYS: i64 = 7;
+
+/// Most domains checked per round; the rest wait for the next one
+const CHECK_BATCH: i64 = 100;
+
+/// Days invitations sent for `invite` domains are valid
+const INVITATION_DAYS: i64 = 7;
+
+/// A DNS lookup that failed, as opposed to finding nothing
+#[derive(Debug)]
+pub struct DnsError(pub String);
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DNS error: {}", self.0)
+    }
+}
+
+impl std::error::Error for DnsError {}
+
+/// Looks up TXT records through a DNS-over-HTTPS resolver's JSON API
+///
+/// Managed as Rocket state. Cloudflare (the default) and Google both answer
+/// `?name=<name>&type=TXT` with `application/dns-json`.
+pub struct DnsClient {
+    client: reqwest::Client,
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct DnsAnswer {
+    #[serde(rename = "Status")]
+    status: u32,
+    #[serde(rename = "Answer", default)]
+    answer: Vec<DnsRecord>,
+}
+
+#[derive(Deserialize)]
+struct DnsRecord {
+    #[serde(rename = "type")]
+    kind: u16,
+    data: String,
+}
+
+/// DNS record type of TXT records
+const TXT: u16 = 16;
+
+/// DNS response code for names that don't exist
+const NXDOMAIN: u32 = 3;
+
+impl DnsClient {
+    pub fn new(url: &str) -> Self {
+        DnsClient {
+            client: reqwest::Client::new(),
+            url: url.to_string(),
+        }
+    }
+
+    /// The TXT records of `name`, each with its strings joined; empty if the name doesn't exist
+    pub async fn txt_records(&self, name: &str) -> Result<Vec<String>, DnsError> {
+        let answer: DnsAnswer = self
+            .client
+            .get(&self.url)
+            .query(&[("name", name), ("type", "TXT")])
+            .header(reqwest::header::ACCEPT, "application/dns-json")
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| DnsError(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| DnsError(e.to_string()))?;
+
+        match answer.status {
+            0 => Ok(answer
+                .answer
+                .into_iter()
+                .filter(|record| record.kind == TXT)
+                .map(|record| txt_value(&record.data))
+                .collect()),
+            NXDOMAIN => Ok(Vec::new()),
+            status => Err(DnsError(format!("resolver answered with status {}", status))),
+        }
+    }
+}
+
+/// A TXT record's data as resolvers present it (`"part" "part"`), as one string
+fn txt_value(data: &str) -> String {
+    if !data.starts_with('"') {
+        return data.to_string();
+    }
+    data.split('"').skip(1).step_by(2).collect()
+}
+
+/// A domain as stored: lowercase and without a trailing dot; `None` if it isn't a valid domain name
+pub fn normalize(domain: &str) -> Option<String> {
+    let domain = domain.trim().trim_end_matches('.').to_ascii_lowercase();
+    let valid_label = |label: &str| {
+        (1..=63).contains(&label.len())
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+    };
+    (domain.len() <= 253 && domain.contains('.') && domain.split('.').all(valid_label)).then_some(domain)
+}
+
+/// Where the domain's TXT record goes
+pub fn challenge_name(domain: &OrgDomain) -> String {
+    format!("{}.{}", CHALLENGE_LABEL, domain.domain)
+}
+
+/// What the domain's TXT record must say
+pub fn challenge_value(domain: &OrgDomain) -> String {
+    format!("{}{}", CHALLENGE_PREFIX, domain.verification_token)
+}
+
+/// Why a domain couldn't be checked
+#[derive(Debug)]
+pub enum CheckError {
+    Dns(DnsError),
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for CheckError {
+    fn from(e: sqlx::Error) -> Self {
+        CheckError::Database(e)
+    }
+}
+
+impl fmt::Display for CheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckError::Dns(e) => write!(f, "{}", e),
+            CheckError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+/// Look for the domain's TXT record, verifying the domain if it's there
+///
+/// Returns the domain as stored afterwards. It stays unverified if another
+/// organization verified it first.
+pub async fn check(conn: &mut PgConnection, dns: &DnsClient, domain: &OrgDomain) -> Result<OrgDomain, CheckError> {
+    let expected = challenge_value(domain);
+    let records = dns.txt_records(&challenge_name(domain)).await.map_err(CheckError::Dns)?;
+    let found = records.iter().any(|record| record.trim() == expected);
+    Ok(organizations::record_domain_check(conn, domain.id, found).await?)
+}
+
+/// Check every domain claimed in the last `CHECK_WINDOW_DAYS` that isn't verified yet, once
+///
+/// Returns how many were verified. Safe to run from several instances at once.
+pub async fn check_unverified(conn: &mut PgConnection, dns: &DnsClient) -> Result<usize, sqlx::Error> {
+    let now = Utc::now();
+    let checked_before = now - chrono::Duration::minutes(CHECK_INTERVAL_MINUTES as i64);
+    let pending = organizations::unverified_domains(conn, now - chrono::Duration::days(CHECK_WINDOW_DAYS), checked_before, CHECK_BATCH).await?;
+
+    let mut verified = 0;
+    for domain in &pending {
+        match check(conn, dns, domain).await {
+            Ok(checked) if checked.verified_at.is_some() => {
+                println!("✓ Domain {} verified for organization {}", checked.domain, checked.organization_id);
+                verified += 1;
+            }
+            Ok(_) => {}
+            Err(CheckError::Dns(e)) => eprintln!("Couldn't check domain {}: {}", domain.domain, e),
+            Err(CheckError::Database(e)) => return Err(e),
+        }
+    }
+    Ok(verified)
+}
+
+/// Bring a user who confirmed their address into the organization that verified its domain
+///
+/// With the domain's `auto` join mode they become a member; with `invite`
+/// they're emailed an invitation to accept. Users in an organization already
+/// are left where they are.
+pub async fn join_by_email(conn: &mut PgConnection, config: &AppConfig, mailer: &Mailer, user: &User) -> Result<(), sqlx::Error> {
+    let Some((_, domain)) = user.email.rsplit_once('@') else {
+        return Ok(());
+    };
+    if user.organization_id.is_some() {
+        return Ok(());
+    }
+    let Some(domain) = organizations::find_verified_domain(conn, &domain.to_ascii_lowercase()).await? else {
+        return Ok(());
+    };
+
+    match DomainJoinMode::parse(&domain.join_mode) {
+        Some(DomainJoinMode::Auto) => {
+            if users::join_organization_if_unset(conn, user.id, domain.organization_id).await? {
+                println!("✓ User {} joined organization {} through {}", user.id, domain.organization_id, domain.domain);
+            }
+        }
+        Some(DomainJoinMode::Invite) => {
+            let code = Uuid::new_v4().to_string();
+            let expires_at = Utc::now() + chrono::Duration::days(INVITATION_DAYS);
+            let invitation = invitations::create_for_organization(
+                conn,
+                &code,
+                &user.email,
+                None,
+                expires_at,
+                domain.organization_id,
+                "member",
+            )
+            .await?;
+            let link = format!("{}/organization/join?invitation={}", config.frontend_url, invitation.code);
+            let message = templates::domain_invitation(&user.email, &domain.domain, &invitation.code, &link);
+            if let Err(e) = mailer.send(message).await {
+                eprintln!("{}", e);
+            }
+        }
+        None => eprintln!("Domain {} has an unknown join mode '{}'", domain.domain, domain.join_mode),
+    }
+    Ok(())
+}
+
+/// Fairing that checks unverified domains every `CHECK_INTERVAL_MINUTES` while the server runs
+pub fn fairing() -> AdHoc {
+    AdHoc::on_liftoff("Organization Domains", |rocket| {
+        Box::pin(async move {
+            let (Some(config), Some(db)) = (rocket.state::<AppConfig>(), Postgres::fetch(rocket)) else {
+                eprintln!("Organization domain checks not started: missing AppConfig or database");
+                return;
+            };
+
+            let (dns, pool) = (DnsClient::new(&config.dns_over_https_url), PgPool::clone(db));
+            rocket::tokio::spawn(async move {
+                loop {
+                    rocket::tokio::time::sleep(Duration::from_secs(CHECK_INTERVAL_MINUTES * 60)).await;
+                    let result = match pool.acquire().await {
+                        Ok(mut conn) => check_unverified(&mut conn, &dns).await,
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = result {
+                        eprintln!("Organization domain check failed: {}", e);
+                    }
+                }
+            });
+        })
+    })
+}
@@ -0,0 +1,69 @@
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::email::sender::{EmailError, EmailMessage, EmailSender};
+
+/// A message captured by `MemoryEmailSender`
+#[derive(Debug, Clone, Serialize)]
+pub struct CapturedEmail {
+    pub from: String,
+    #[serde(flatten)]
+    pub message: EmailMessage,
+    pub sent_at: DateTime<Utc>,
+}
+
+/// Records emails in memory instead of delivering them
+///
+/// Used by tests and by the development mailbox (`GET /_dev/mailbox`).
+#[derive(Default)]
+pub struct MemoryEmailSender {
+    messages: Mutex<Vec<CapturedEmail>>,
+}
+
+impl MemoryEmailSender {
+    pub fn new() -> Self {
+        MemoryEmailSender::default()
+    }
+
+    /// All captured messages, oldest first
+    pub fn messages(&self) -> Vec<CapturedEmail> {
+        self.messages.lock().map(|messages| messages.clone()).unwrap_or_default()
+    }
+
+    /// Captured messages sent to one address, oldest first
+    pub fn messages_to(&self, to: &str) -> Vec<CapturedEmail> {
+        self.messages()
+            .into_iter()
+            .filter(|captured| captured.message.to == to)
+            .collect()
+    }
+
+    /// The most recent message sent to an address
+    pub fn last_to(&self, to: &str) -> Option<CapturedEmail> {
+        self.messages_to(to).pop()
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut messages) = self.messages.lock() {
+            messages.clear();
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl EmailSender for MemoryEmailSender {
+    async fn send(&self, from: &str, message: EmailMessage) -> Result<(), EmailError> {
+        let mut messages = self
+            .messages
+            .lock()
+            .map_err(|_| EmailError("mailbox lock poisoned".to_string()))?;
+        messages.push(CapturedEmail {
+            from: from.to_string(),
+            message,
+            sent_at: Utc::now(),
+        });
+        Ok(())
+    }
+}
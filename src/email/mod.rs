@@ -0,0 +1,6 @@
+pub mod sender;
+pub mod memory;
+pub mod settings;
+pub mod smtp;
+pub mod templates;
+pub mod welcome;
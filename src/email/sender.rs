@@ -0,0 +1,108 @@
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use serde::Serialize;
+
+/// An outgoing plain-text email
+#[derive(Debug, Clone, Serialize)]
+pub struct EmailMessage {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Errors returned by email transports
+#[derive(Debug)]
+pub struct EmailError(pub String);
+
+impl fmt::Display for EmailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Email error: {}", self.0)
+    }
+}
+
+impl std::error::Error for EmailError {}
+
+/// Delivers emails
+///
+/// Implement this to plug in a real transport (SMTP, SES, Postmark, ...).
+#[rocket::async_trait]
+pub trait EmailSender: Send + Sync {
+    async fn send(&self, from: &str, message: EmailMessage) -> Result<(), EmailError>;
+}
+
+/// Prints emails to stdout instead of sending them
+pub struct LogEmailSender;
+
+#[rocket::async_trait]
+impl EmailSender for LogEmailSender {
+    async fn send(&self, from: &str, message: EmailMessage) -> Result<(), EmailError> {
+        println!(
+            "✉ Email from {} to {}\n  Subject: {}\n  {}",
+            from,
+            message.to,
+            message.subject,
+            message.body.replace('\n', "\n  ")
+        );
+        Ok(())
+    }
+}
+
+/// A transport and the address it sends from
+#[derive(Clone)]
+struct Transport {
+    sender: Arc<dyn EmailSender>,
+    from: String,
+}
+
+/// Sends emails from a fixed address through the configured transport
+///
+/// Managed as Rocket state; use `&State<Mailer>` in handlers. Clones share
+/// the transport, including an override set at runtime (see
+/// `email::settings`).
+#[derive(Clone)]
+pub struct Mailer {
+    configured: Transport,
+    overridden: Arc<RwLock<Option<Transport>>>,
+}
+
+impl Mailer {
+    pub fn new(sender: Arc<dyn EmailSender>, from: String) -> Self {
+        Mailer {
+            configured: Transport { sender, from },
+            overridden: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    pub async fn send(&self, message: EmailMessage) -> Result<(), EmailError> {
+        let transport = self.transport();
+        transport.sender.send(&transport.from, message).await
+    }
+
+    /// The address emails are currently sent from
+    pub fn from_address(&self) -> String {
+        self.transport().from
+    }
+
+    /// Send through `sender` from `from` instead of the configured transport
+    pub fn set_override(&self, sender: Arc<dyn EmailSender>, from: String) {
+        if let Ok(mut overridden) = self.overridden.write() {
+            *overridden = Some(Transport { sender, from });
+        }
+    }
+
+    /// Go back to the configured transport
+    pub fn clear_override(&self) {
+        if let Ok(mut overridden) = self.overridden.write() {
+            *overridden = None;
+        }
+    }
+
+    fn transport(&self) -> Transport {
+        self.overridden
+            .read()
+            .ok()
+            .and_then(|overridden| overridden.clone())
+            .unwrap_or_else(|| self.configured.clone())
+    }
+}
@@ -0,0 +1,135 @@
+use std::sync::Arc;
+
+use rocket::fairing::AdHoc;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{AppConfig, EmailTransport, SmtpConfig, SmtpSecurity};
+use crate::email::sender::Mailer;
+use crate::email::smtp::SmtpEmailSender;
+use crate::models::setting::StoredSetting;
+use crate::settings::Settings;
+
+/// Key the mailer settings are stored under in `settings`
+pub const SETTINGS_KEY: &str = "email.smtp";
+
+/// SMTP relay and sender address set by an admin, replacing `ROCKET_EMAIL_TRANSPORT`'s
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EmailSettings {
+    #[serde(flatten)]
+    pub smtp: SmtpConfig,
+    pub from: String,
+}
+
+/// Body for changing the stored settings
+///
+/// `password` may be left out to keep the stored one, so settings read
+/// back without it can be saved again.
+#[derive(Debug, Deserialize)]
+pub struct EmailSettingsUpdate {
+    pub host: String,
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub security: Option<SmtpSecurity>,
+    pub from: String,
+}
+
+impl EmailSettingsUpdate {
+    /// Check the update and merge it with the settings it replaces
+    pub fn into_settings(self, current: Option<&EmailSettings>) -> Result<EmailSettings, String> {
+        let host = self.host.trim().to_string();
+        if host.is_empty() || host.contains(char::is_whitespace) {
+            return Err("host must be a host name or IP address".to_string());
+        }
+        let from = self.from.trim().to_string();
+        if !from.contains('@') || from.contains(['\r', '\n', '<', '>']) {
+            return Err("from must be an email address".to_string());
+        }
+
+        let security = self.security.unwrap_or(SmtpSecurity::StartTls);
+        let port = match self.port {
+            Some(0) => return Err("port must be between 1 and 65535".to_string()),
+            Some(port) => port,
+            None if security == SmtpSecurity::Tls => 465,
+            None => 587,
+        };
+        let username = self.username.filter(|username| !username.is_empty());
+        let password = match (&username, self.password) {
+            (None, _) => None,
+            (Some(_), Some(password)) => Some(password),
+            (Some(_), None) => current.and_then(|current| current.smtp.password.clone()),
+        };
+
+        Ok(EmailSettings {
+            smtp: SmtpConfig {
+                host,
+                port,
+                username,
+                password,
+                security,
+            },
+            from,
+        })
+    }
+}
+
+/// The stored settings; unreadable ones are logged and ignored
+pub fn load(settings: &Settings) -> Option<(EmailSettings, StoredSetting)> {
+    let setting = settings.stored(SETTINGS_KEY)?;
+    match serde_json::from_value(setting.value.clone()) {
+        Ok(stored) => Some((stored, setting)),
+        Err(e) => {
+            eprintln!("Ignoring stored email settings: {}", e);
+            None
+        }
+    }
+}
+
+/// Send through `settings`, or through the configured transport without any
+///
+/// The memory transport is never overridden, so the development mailbox
+/// (and the tests' one) keeps every email.
+pub fn apply(config: &AppConfig, mailer: &Mailer, settings: Option<&EmailSettings>) {
+    match settings {
+        Some(settings) if config.email_transport != EmailTransport::Memory => {
+            mailer.set_override(Arc::new(SmtpEmailSender::new(settings.smtp.clone())), settings.from.clone())
+        }
+        _ => mailer.clear_override(),
+    }
+}
+
+/// Fairing that applies the stored settings at liftoff and whenever they change
+///
+/// Changes reach every instance through `Settings`, which reloads when
+/// another instance announces one.
+pub fn fairing() -> AdHoc {
+    AdHoc::on_liftoff("Email Settings", |rocket| {
+        Box::pin(async move {
+            let (Some(config), Some(mailer), Some(settings)) =
+                (rocket.state::<AppConfig>(), rocket.state::<Mailer>(), rocket.state::<Settings>())
+            else {
+                eprintln!("Email settings not loaded: missing AppConfig, Mailer or Settings");
+                return;
+            };
+            if config.email_transport == EmailTransport::Memory || !settings.is_enabled() {
+                return;
+            }
+
+            let (config, mailer, settings) = (config.clone(), mailer.clone(), settings.clone());
+            let mut changes = settings.subscribe();
+            rocket::tokio::spawn(async move {
+                let mut applied: Option<Option<EmailSettings>> = None;
+                loop {
+                    let stored = load(&settings).map(|(stored, _)| stored);
+                    if applied.as_ref() != Some(&stored) {
+                        apply(&config, &mailer, stored.as_ref());
+                        applied = Some(stored);
+                    }
+                    if changes.changed().await.is_err() {
+                        return;
+                    }
+                }
+            });
+        })
+    })
+}
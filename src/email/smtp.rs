@@ -0,0 +1,193 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::Utc;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, RootCertStore};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use uuid::Uuid;
+
+use crate::config::{SmtpConfig, SmtpSecurity};
+use crate::email::sender::{EmailError, EmailMessage, EmailSender};
+
+/// How long one delivery may take, connecting included
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Name this server gives itself in `EHLO`
+const EHLO_NAME: &str = "localhost";
+
+/// Delivers emails through an SMTP relay, one connection per message
+///
+/// Relays are verified against the Mozilla root certificates bundled with
+/// `webpki-roots`. Credentials are sent with `AUTH PLAIN`, and only once
+/// the connection is encrypted unless the relay is configured without
+/// security.
+pub struct SmtpEmailSender {
+    config: SmtpConfig,
+    tls: TlsConnector,
+}
+
+impl SmtpEmailSender {
+    pub fn new(config: SmtpConfig) -> Self {
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let tls = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .expect("ring supports the default protocol versions")
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        SmtpEmailSender {
+            config,
+            tls: TlsConnector::from(Arc::new(tls)),
+        }
+    }
+
+    async fn deliver(&self, from: &str, message: &EmailMessage) -> Result<(), EmailError> {
+        let tcp = TcpStream::connect((self.config.host.as_str(), self.config.port))
+            .await
+            .map_err(|e| EmailError(format!("couldn't connect to {}:{}: {}", self.config.host, self.config.port, e)))?;
+        let server_name = ServerName::try_from(self.config.host.clone())
+            .map_err(|_| EmailError(format!("'{}' isn't a valid host name", self.config.host)))?;
+
+        match self.config.security {
+            SmtpSecurity::None => {
+                let mut conn = BufReader::new(tcp);
+                expect(&mut conn, 220).await?;
+                transaction(&mut conn, &self.config, from, message).await
+            }
+            SmtpSecurity::Tls => {
+                let tls = self.tls.connect(server_name, tcp).await.map_err(tls_error)?;
+                let mut conn = BufReader::new(tls);
+                expect(&mut conn, 220).await?;
+                transaction(&mut conn, &self.config, from, message).await
+            }
+            SmtpSecurity::StartTls => {
+                let mut conn = BufReader::new(tcp);
+                expect(&mut conn, 220).await?;
+                let extensions = command(&mut conn, &format!("EHLO {}", EHLO_NAME), 250).await?;
+                if !extensions.iter().any(|extension| extension.eq_ignore_ascii_case("STARTTLS")) {
+                    return Err(EmailError("the relay doesn't offer STARTTLS".to_string()));
+                }
+                command(&mut conn, "STARTTLS", 220).await?;
+                let tls = self.tls.connect(server_name, conn.into_inner()).await.map_err(tls_error)?;
+                transaction(&mut BufReader::new(tls), &self.config, from, message).await
+            }
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl EmailSender for SmtpEmailSender {
+    async fn send(&self, from: &str, message: EmailMessage) -> Result<(), EmailError> {
+        tokio::time::timeout(SEND_TIMEOUT, self.deliver(from, &message))
+            .await
+            .map_err(|_| EmailError(format!("{}:{} didn't answer in time", self.config.host, self.config.port)))?
+    }
+}
+
+/// Greet, authenticate and hand over one message
+async fn transaction<S: AsyncRead + AsyncWrite + Unpin>(
+    conn: &mut BufReader<S>,
+    config: &SmtpConfig,
+    from: &str,
+    message: &EmailMessage,
+) -> Result<(), EmailError> {
+    for value in [from, message.to.as_str(), message.subject.as_str()] {
+        if value.contains(['\r', '\n']) {
+            return Err(EmailError("line breaks aren't allowed in addresses or subjects".to_string()));
+        }
+    }
+
+    command(conn, &format!("EHLO {}", EHLO_NAME), 250).await?;
+    if let Some(username) = &config.username {
+        let password = config.password.as_deref().unwrap_or_default();
+        let credentials = STANDARD.encode(format!("\0{}\0{}", username, password));
+        command(conn, &format!("AUTH PLAIN {}", credentials), 235).await?;
+    }
+    command(conn, &format!("MAIL FROM:<{}>", from), 250).await?;
+    command(conn, &format!("RCPT TO:<{}>", message.to), 250).await?;
+    command(conn, "DATA", 354).await?;
+    write(conn, &format!("{}\r\n.", format_message(from, message))).await?;
+    expect(conn, 250).await?;
+    // The message is accepted; a relay that drops the connection here doesn't matter
+    let _ = command(conn, "QUIT", 221).await;
+    Ok(())
+}
+
+/// The message as sent after `DATA`: headers, then the body in base64 so any text survives
+fn format_message(from: &str, message: &EmailMessage) -> String {
+    let domain = from.rsplit_once('@').map(|(_, domain)| domain).unwrap_or(EHLO_NAME);
+    let subject = if message.subject.is_ascii() {
+        message.subject.clone()
+    } else {
+        format!("=?utf-8?B?{}?=", STANDARD.encode(&message.subject))
+    };
+    let body = STANDARD.encode(message.body.replace('\n', "\r\n"));
+    let lines: Vec<&str> = body
+        .as_bytes()
+        .chunks(76)
+        .map(|line| std::str::from_utf8(line).expect("base64 is ASCII"))
+        .collect();
+
+    format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMessage-ID: <{}@{}>\r\nMIME-Version: 1.0\r\n\
+         Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: base64\r\n\r\n{}",
+        from,
+        message.to,
+        subject,
+        Utc::now().to_rfc2822(),
+        Uuid::new_v4().simple(),
+        domain,
+        lines.join("\r\n")
+    )
+}
+
+/// Send a command and check the reply's code, returning its lines
+async fn command<S: AsyncRead + AsyncWrite + Unpin>(
+    conn: &mut BufReader<S>,
+    line: &str,
+    code: u16,
+) -> Result<Vec<String>, EmailError> {
+    write(conn, line).await?;
+    expect(conn, code).await
+}
+
+async fn write<S: AsyncRead + AsyncWrite + Unpin>(conn: &mut BufReader<S>, line: &str) -> Result<(), EmailError> {
+    let stream = conn.get_mut();
+    stream.write_all(format!("{}\r\n", line).as_bytes()).await.map_err(io_error)?;
+    stream.flush().await.map_err(io_error)
+}
+
+/// Read a reply, which may span several `<code>-` lines, and check its code
+async fn expect<S: AsyncRead + AsyncWrite + Unpin>(conn: &mut BufReader<S>, code: u16) -> Result<Vec<String>, EmailError> {
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        if conn.read_line(&mut line).await.map_err(io_error)? == 0 {
+            return Err(EmailError("the relay closed the connection".to_string()));
+        }
+        let line = line.trim_end();
+        let (reply_code, rest) = (line.get(..3).unwrap_or(line), line.get(3..).unwrap_or_default());
+        if reply_code != code.to_string() {
+            return Err(EmailError(format!("the relay answered '{}'", line)));
+        }
+        lines.push(rest.get(1..).unwrap_or_default().to_string());
+        if !rest.starts_with('-') {
+            return Ok(lines);
+        }
+    }
+}
+
+fn io_error(e: std::io::Error) -> EmailError {
+    EmailError(format!("connection to the relay failed: {}", e))
+}
+
+fn tls_error(e: std::io::Error) -> EmailError {
+    EmailError(format!("TLS handshake with the relay failed: {}", e))
+}
@@ -0,0 +1,278 @@
+use chrono::{DateTime, Utc};
+
+use crate::email::sender::EmailMessage;
+
+/// A time as shown in emails, in the recipient's time zone when they set one
+pub fn local_time(at: DateTime<Utc>, timezone: Option<&str>) -> String {
+    match timezone.and_then(|tz| tz.parse::<chrono_tz::Tz>().ok()) {
+        Some(tz) => format!("{} ({})", at.with_timezone(&tz).format("%Y-%m-%d %H:%M"), tz),
+        None => format!("{} (UTC)", at.format("%Y-%m-%d %H:%M")),
+    }
+}
+
+/// Email with a link to reset the user's password
+pub fn password_reset(to: &str, link: &str) -> EmailMessage {
+    EmailMessage {
+        to: to.to_string(),
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "We received a request to reset your password.\n\n\
+             Reset it here (the link expires in 1 hour):\n{}\n\n\
+             If you didn't request this, you can ignore this email.",
+            link
+        ),
+    }
+}
+
+/// Email with a link to confirm the user's address
+pub fn email_verification(to: &str, link: &str) -> EmailMessage {
+    EmailMessage {
+        to: to.to_string(),
+        subject: "Verify your email address".to_string(),
+        body: format!(
+            "Please confirm your email address by opening this link \
+             (it expires in 24 hours):\n{}",
+            link
+        ),
+    }
+}
+
+/// Email to a new address, with a link that makes it the account's email
+pub fn email_change_confirmation(to: &str, link: &str) -> EmailMessage {
+    EmailMessage {
+        to: to.to_string(),
+        subject: "Confirm your new email address".to_string(),
+        body: format!(
+            "Confirm that this address should become your account's email \
+             (the link expires in 1 hour and works once):\n{}\n\n\
+             If you didn't request this, you can ignore this email.",
+            link
+        ),
+    }
+}
+
+/// Email with a link that deletes the account
+pub fn account_deletion_confirmation(to: &str, link: &str) -> EmailMessage {
+    EmailMessage {
+        to: to.to_string(),
+        subject: "Confirm account deletion".to_string(),
+        body: format!(
+            "We received a request to delete your account.\n\n\
+             Confirm it here (the link expires in 1 hour and works once):\n{}\n\n\
+             Deletion can't be undone. If you didn't request this, change your password.",
+            link
+        ),
+    }
+}
+
+/// Email inviting someone to register
+pub fn invitation(to: &str, code: &str, link: &str) -> EmailMessage {
+    EmailMessage {
+        to: to.to_string(),
+        subject: "You're invited".to_string(),
+        body: format!(
+            "You have been invited to create an account.\n\n\
+             Sign up here:\n{}\n\n\
+             Or enter this invitation code when registering: {}",
+            link, code
+        ),
+    }
+}
+
+/// Email inviting someone who confirmed an address at an organization's verified domain to join it
+pub fn domain_invitation(to: &str, domain: &str, code: &str, link: &str) -> EmailMessage {
+    EmailMessage {
+        to: to.to_string(),
+        subject: "Join your organization".to_string(),
+        body: format!(
+            "The organization that owns {} invites you to join it.\n\n\
+             Accept here:\n{}\n\n\
+             Or accept with this invitation code: {}",
+            domain, link, code
+        ),
+    }
+}
+
+/// Email sent once an admin has approved a signup
+pub fn welcome(to: &str, link: &str) -> EmailMessage {
+    EmailMessage {
+        to: to.to_string(),
+        subject: "Welcome! Your account is ready".to_string(),
+        body: format!(
+            "Your account has been approved and you can now log in:\n{}",
+            link
+        ),
+    }
+}
+
+/// Email sent once a user has registered, with their verification link if given
+pub fn registration_welcome(to: &str, login_link: &str, verification_link: Option<&str>) -> EmailMessage {
+    let body = match verification_link {
+        Some(verification_link) => format!(
+            "Thanks for signing up! Your account is ready.\n\n\
+             Please confirm your email address by opening this link \
+             (it expires in 24 hours):\n{}\n\n\
+             You can log in here:\n{}",
+            verification_link, login_link
+        ),
+        None => format!("Thanks for signing up! Your account is ready.\n\nYou can log in here:\n{}", login_link),
+    };
+    EmailMessage {
+        to: to.to_string(),
+        subject: "Welcome!".to_string(),
+        body,
+    }
+}
+
+/// Email with a link that schedules removal of the user's MFA
+pub fn mfa_recovery_confirmation(to: &str, link: &str, delay_hours: u64) -> EmailMessage {
+    EmailMessage {
+        to: to.to_string(),
+        subject: "Confirm removing two-factor authentication".to_string(),
+        body: format!(
+            "We received a request to remove two-factor authentication from your account, \
+             because the authenticator app was lost.\n\n\
+             Confirm it here (the link expires in 1 hour and works once):\n{}\n\n\
+             For your security, MFA is only removed {} hours after you confirm. \
+             If you didn't request this, change your password.",
+            link, delay_hours
+        ),
+    }
+}
+
+/// Notice that MFA removal was confirmed and when it takes effect
+pub fn mfa_recovery_scheduled(to: &str, available_at: &str) -> EmailMessage {
+    EmailMessage {
+        to: to.to_string(),
+        subject: "Two-factor authentication will be removed".to_string(),
+        body: format!(
+            "Two-factor authentication will be removed from your account at your \
+             first login after {}.\n\n\
+             If you didn't ask for this, sign in with your authenticator app before then \
+             to cancel it, and change your password.",
+            available_at
+        ),
+    }
+}
+
+/// Notice that the user's MFA was removed
+pub fn mfa_removed(to: &str) -> EmailMessage {
+    EmailMessage {
+        to: to.to_string(),
+        subject: "Two-factor authentication was removed".to_string(),
+        body: "Two-factor authentication was removed from your account, and your password \
+               alone now signs you in. Set up an authenticator again from your account settings.\n\n\
+               If you didn't expect this, change your password and contact support."
+            .to_string(),
+    }
+}
+
+/// Email to an existing user when someone tries to register with their address
+pub fn account_exists(to: &str, link: &str) -> EmailMessage {
+    EmailMessage {
+        to: to.to_string(),
+        subject: "You already have an account".to_string(),
+        body: format!(
+            "Someone tried to sign up with this email address, but you already have an account.\n\n\
+             Sign in here:\n{}\n\n\
+             If you forgot your password, you can reset it from the sign-in page. \
+             If this wasn't you, you can ignore this email.",
+            link
+        ),
+    }
+}
+
+/// Warning that the inactivity policy applies to the user's account unless they log in
+pub fn inactive_account_warning(to: &str, link: &str, deadline: &str, disable: bool) -> EmailMessage {
+    let consequence = if disable {
+        "your account will be disabled"
+    } else {
+        "your account will be marked for closure"
+    };
+    EmailMessage {
+        to: to.to_string(),
+        subject: "We'll close your account soon".to_string(),
+        body: format!(
+            "You haven't signed in for a while. Unless you sign in before {}, {}.\n\n\
+             To keep your account, sign in here:\n{}",
+            deadline, consequence, link
+        ),
+    }
+}
+
+/// Notice that the user's password was changed, with a link to lock the account if it wasn't them
+pub fn password_changed(to: &str, changed_at: &str, ip: Option<&str>, link: &str) -> EmailMessage {
+    let origin = match ip {
+        Some(ip) => format!(" from {}", ip),
+        None => String::new(),
+    };
+    EmailMessage {
+        to: to.to_string(),
+        subject: "Your password was changed".to_string(),
+        body: format!(
+            "The password for your account was changed at {}{}.\n\n\
+             If this was you, there's nothing else to do.\n\n\
+             If this wasn't you, open this link to sign out of every session and \
+             get an email to reset your password (it expires in 7 days and works once):\n{}",
+            changed_at, origin, link
+        ),
+    }
+}
+
+/// Code confirming a login that looked unusual, for users without an authenticator app
+pub fn login_code(to: &str, code: &str, ip: Option<&str>, expires_in_minutes: i64) -> EmailMessage {
+    let origin = match ip {
+        Some(ip) => format!(" from {}", ip),
+        None => String::new(),
+    };
+    EmailMessage {
+        to: to.to_string(),
+        subject: "Your login code".to_string(),
+        body: format!(
+            "Someone signed in to your account{} with your password, and we'd like to be sure it's you.\n\n\
+             Enter this code to finish signing in (it expires in {} minutes):\n{}\n\n\
+             If this wasn't you, change your password now.",
+            origin, expires_in_minutes, code
+        ),
+    }
+}
+
+/// Notice that a session was used from two countries too close together to have travelled between them
+pub fn suspicious_session_use(to: &str, previous_country: &str, country: &str, revoked: bool) -> EmailMessage {
+    let outcome = match revoked {
+        true => "We've signed that session out to be safe. If it was you, just sign in again.",
+        false => "If it was you, for example over a VPN, there's nothing else to do.",
+    };
+    EmailMessage {
+        to: to.to_string(),
+        subject: "Unusual activity on your account".to_string(),
+        body: format!(
+            "One of your sessions was used from {} shortly after being used from {}.\n\n\
+             {}\n\n\
+             If this wasn't you, change your password now and sign out of all sessions.",
+            country, previous_country, outcome
+        ),
+    }
+}
+
+/// Security anomaly alert for the addresses in `ROCKET_ADMIN_ALERT_EMAILS`
+pub fn admin_alert(to: &str, summary: &str, details: &str) -> EmailMessage {
+    EmailMessage {
+        to: to.to_string(),
+        subject: format!("Security alert: {}", summary),
+        body: format!("{}\n\nSee the audit log for the events around it.", details),
+    }
+}
+
+/// Email sent from the admin endpoint that checks the mail settings
+pub fn test_email(to: &str, transport: &str) -> EmailMessage {
+    EmailMessage {
+        to: to.to_string(),
+        subject: "Test email".to_string(),
+        body: format!(
+            "This is a test email, sent through {}.\n\n\
+             If you can read it, outgoing email works.",
+            transport
+        ),
+    }
+}
@@ -0,0 +1,86 @@
+use rocket::fairing::AdHoc;
+use rocket_db_pools::Database;
+use sqlx::{PgConnection, PgPool};
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+use crate::config::{AppConfig, WelcomeEmail};
+use crate::email::sender::Mailer;
+use crate::email::templates;
+use crate::events::{EventBus, SecurityEventKind};
+use crate::repositories::users;
+use crate::routes::auth::verification_link;
+use crate::Postgres;
+
+/// Send the welcome email to a newly registered user, unless it was already sent
+///
+/// With `WelcomeEmail::WithVerification`, unverified users get a fresh
+/// verification link in it. Users waiting for signup approval are skipped.
+pub async fn send(conn: &mut PgConnection, config: &AppConfig, mailer: &Mailer, user_id: Uuid) -> Result<(), sqlx::Error> {
+    let Some(user) = users::claim_welcome_email(conn, user_id).await? else {
+        return Ok(());
+    };
+
+    let verification = match config.welcome_email {
+        WelcomeEmail::WithVerification if user.email_verified_at.is_none() => {
+            Some(verification_link(conn, config, user.id, None).await?)
+        }
+        _ => None,
+    };
+    let login_link = format!("{}/login", config.frontend_url);
+    let message = templates::registration_welcome(&user.email, &login_link, verification.as_deref());
+    if let Err(e) = mailer.send(message).await {
+        eprintln!("{}", e);
+    }
+    Ok(())
+}
+
+/// Fairing that sends welcome emails for `user_registered` events from the managed `EventBus`
+///
+/// Only started when `ROCKET_WELCOME_EMAIL` is on. Every instance receives
+/// every event, so the user is claimed first and only one instance sends.
+/// Users who register while no instance is running don't get one.
+pub fn fairing() -> AdHoc {
+    AdHoc::on_liftoff("Welcome Email", |rocket| {
+        Box::pin(async move {
+            let (Some(config), Some(mailer), Some(bus), Some(db)) = (
+                rocket.state::<AppConfig>(),
+                rocket.state::<Mailer>(),
+                rocket.state::<EventBus>(),
+                Postgres::fetch(rocket),
+            ) else {
+                eprintln!("Welcome email not started: missing AppConfig, Mailer, EventBus or database");
+                return;
+            };
+            if config.welcome_email == WelcomeEmail::Off {
+                return;
+            }
+
+            let (config, mailer, pool) = (config.clone(), mailer.clone(), PgPool::clone(db));
+            let mut events = bus.subscribe();
+            rocket::tokio::spawn(async move {
+                loop {
+                    let event = match events.recv().await {
+                        Ok(event) => event,
+                        Err(RecvError::Lagged(missed)) => {
+                            eprintln!("Welcome email fell behind; {} event(s) skipped", missed);
+                            continue;
+                        }
+                        Err(RecvError::Closed) => return,
+                    };
+                    if !matches!(event.kind, SecurityEventKind::UserRegistered) {
+                        continue;
+                    }
+
+                    let sent = match pool.acquire().await {
+                        Ok(mut conn) => send(&mut conn, &config, &mailer, event.user_id).await,
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = sent {
+                        eprintln!("Database error: {}", e);
+                    }
+                }
+            });
+        })
+    })
+}
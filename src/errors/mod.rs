@@ -7,6 +7,9 @@ pub struct ErrorResponse {
     pub error: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub details: Option<String>,
+    /// Machine-readable error code for clients to branch on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
 }
 
 impl ErrorResponse {
@@ -14,6 +17,7 @@ impl ErrorResponse {
         ErrorResponse {
             error,
             details: None,
+            code: None,
         }
     }
 
@@ -21,8 +25,20 @@ impl ErrorResponse {
         ErrorResponse {
             error,
             details: Some(details),
+            code: None,
         }
     }
+
+    pub fn with_code(mut self, code: &str) -> Self {
+        self.code = Some(code.to_string());
+        self
+    }
+}
+
+/// JSON body for 404 responses, e.g. unknown routes or `Owns<T>` misses
+#[catch(404)]
+pub fn not_found() -> Json<ErrorResponse> {
+    Json(ErrorResponse::new("Not found".to_string()))
 }
 
 /// Helper function to create error responses
@@ -0,0 +1,266 @@
+use std::net::IpAddr;
+
+use chrono::{DateTime, Utc};
+use rocket::fairing::AdHoc;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgListener;
+use sqlx::PgConnection;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::models::audit::AuditEvent;
+use crate::risk::RiskVerdict;
+
+/// Postgres NOTIFY channel carrying security events between processes
+pub const CHANNEL: &str = "security_events";
+
+/// What happened to an account
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SecurityEventKind {
+    SessionCreated { session_id: Uuid },
+    AllSessionsRevoked { count: u64 },
+    PasswordChanged,
+    EmailChanged,
+    MfaEnabled,
+    MfaDisabled,
+    MfaRecoveryRequested { recovery_id: Uuid },
+    MfaRecoveryScheduled { recovery_id: Uuid, available_at: DateTime<Utc> },
+    MfaRecoveryCancelled,
+    MfaReset { recovery_id: Uuid, admin_id: Option<Uuid> },
+    AccountDisabled,
+    UserRegistered,
+    /// Published with the nil user id and the blocked address as `ip`
+    CredentialStuffingDetected { distinct_emails: u32, blocked_until: DateTime<Utc> },
+    /// A login whose risk assessment asked for a second factor or refused it
+    RiskyLogin { verdict: RiskVerdict, score: u32, reasons: Vec<String> },
+    /// A session used from `country` too soon after being used from `previous_country`
+    SuspiciousSessionUse { session_id: Uuid, previous_country: String, country: String, revoked: bool },
+}
+
+impl SecurityEventKind {
+    /// Event name, as used for the SSE `event:` field
+    pub fn name(&self) -> &'static str {
+        match self {
+            SecurityEventKind::SessionCreated { .. } => "session_created",
+            SecurityEventKind::AllSessionsRevoked { .. } => "all_sessions_revoked",
+            SecurityEventKind::PasswordChanged => "password_changed",
+            SecurityEventKind::EmailChanged => "email_changed",
+            SecurityEventKind::MfaEnabled => "mfa_enabled",
+            SecurityEventKind::MfaDisabled => "mfa_disabled",
+            SecurityEventKind::MfaRecoveryRequested { .. } => "mfa_recovery_requested",
+            SecurityEventKind::MfaRecoveryScheduled { .. } => "mfa_recovery_scheduled",
+            SecurityEventKind::MfaRecoveryCancelled => "mfa_recovery_cancelled",
+            SecurityEventKind::MfaReset { .. } => "mfa_reset",
+            SecurityEventKind::AccountDisabled => "account_disabled",
+            SecurityEventKind::UserRegistered => "user_registered",
+            SecurityEventKind::CredentialStuffingDetected { .. } => "credential_stuffing_detected",
+            SecurityEventKind::RiskyLogin { .. } => "risky_login",
+            SecurityEventKind::SuspiciousSessionUse { .. } => "suspicious_session_use",
+        }
+    }
+}
+
+/// A security-relevant change to a user's account
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecurityEvent {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    #[serde(flatten)]
+    pub kind: SecurityEventKind,
+    /// Who caused the event; `None` for the system and the admin CLI
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actor_id: Option<Uuid>,
+    /// Client address of the request that caused the event, where known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<IpAddr>,
+    /// Organization the user belongs to, tagged when the event is recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub organization_id: Option<Uuid>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+impl SecurityEvent {
+    pub fn new(user_id: Uuid, kind: SecurityEventKind) -> Self {
+        SecurityEvent {
+            id: Uuid::new_v4(),
+            user_id,
+            kind,
+            actor_id: None,
+            ip: None,
+            organization_id: None,
+            occurred_at: Utc::now(),
+        }
+    }
+
+    pub fn by(mut self, actor_id: Uuid) -> Self {
+        self.actor_id = Some(actor_id);
+        self
+    }
+
+    pub fn from_ip(mut self, ip: Option<IpAddr>) -> Self {
+        self.ip = ip;
+        self
+    }
+
+    /// The event as it was published, from its audit log row
+    pub fn from_audit(event: AuditEvent) -> Result<Self, serde_json::Error> {
+        let mut kind = event.details;
+        if let Some(fields) = kind.as_object_mut() {
+            fields.insert("type".to_string(), serde_json::Value::String(event.event_type));
+        }
+        Ok(SecurityEvent {
+            id: event.id,
+            user_id: event.user_id,
+            kind: serde_json::from_value(kind)?,
+            actor_id: event.actor_id,
+            ip: event.ip.and_then(|ip| ip.parse().ok()),
+            organization_id: event.organization_id,
+            occurred_at: event.occurred_at,
+        })
+    }
+}
+
+/// Record an event in the audit log and publish it to every server instance via Postgres NOTIFY
+///
+/// Works from any process connected to the database (including the admin CLI).
+/// Events about a member of an organization are tagged with its id, both in
+/// `audit_events` and in what's published.
+/// Delivery is best-effort: instances that aren't listening miss the event,
+/// but it stays in `audit_events`.
+pub async fn publish(conn: &mut PgConnection, event: &SecurityEvent) -> Result<(), sqlx::Error> {
+    publish_all(conn, std::slice::from_ref(event)).await
+}
+
+/// Record and publish several events in one statement, in order
+pub async fn publish_all(conn: &mut PgConnection, events: &[SecurityEvent]) -> Result<(), sqlx::Error> {
+    let mut details = Vec::with_capacity(events.len());
+    let mut payloads = Vec::with_capacity(events.len());
+    for event in events {
+        payloads.push(serde_json::to_string(event).map_err(|e| sqlx::Error::Protocol(e.to_string()))?);
+        let mut fields = serde_json::to_value(&event.kind).map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+        if let Some(fields) = fields.as_object_mut() {
+            fields.remove("type");
+        }
+        details.push(fields);
+    }
+
+    sqlx::query(
+        r#"
+        WITH batch AS (
+            SELECT * FROM unnest($1::uuid[], $2::timestamptz[], $3::text[], $4::uuid[], $5::uuid[], $6::text[], $7::jsonb[], $8::text[])
+                WITH ORDINALITY AS e(id, occurred_at, event_type, user_id, actor_id, ip, details, payload, position)
+        ),
+        tagged AS (
+            SELECT batch.*, users.organization_id FROM batch LEFT JOIN users ON users.id = batch.user_id
+        ),
+        recorded AS (
+            INSERT INTO audit_events (id, occurred_at, event_type, user_id, actor_id, ip, details, organization_id)
+            SELECT id, occurred_at, event_type, user_id, actor_id, ip, details, organization_id FROM tagged
+        )
+        SELECT pg_notify($9, CASE
+            WHEN organization_id IS NULL THEN payload
+            ELSE (payload::jsonb || jsonb_build_object('organization_id', organization_id))::text
+        END)
+        FROM tagged ORDER BY position
+        "#,
+    )
+    .bind(events.iter().map(|event| event.id).collect::<Vec<_>>())
+    .bind(events.iter().map(|event| event.occurred_at).collect::<Vec<_>>())
+    .bind(events.iter().map(|event| event.kind.name()).collect::<Vec<_>>())
+    .bind(events.iter().map(|event| event.user_id).collect::<Vec<_>>())
+    .bind(events.iter().map(|event| event.actor_id).collect::<Vec<_>>())
+    .bind(events.iter().map(|event| event.ip.map(|ip| ip.to_string())).collect::<Vec<_>>())
+    .bind(details)
+    .bind(payloads)
+    .bind(CHANNEL)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Publish an event the user caused themselves, logging instead of failing the caller
+pub async fn emit(conn: &mut PgConnection, user_id: Uuid, kind: SecurityEventKind) {
+    emit_event(conn, SecurityEvent::new(user_id, kind).by(user_id)).await;
+}
+
+/// Publish an event, logging instead of failing the caller
+pub async fn emit_event(conn: &mut PgConnection, event: SecurityEvent) {
+    if let Err(e) = publish(conn, &event).await {
+        eprintln!("Failed to publish security event: {}", e);
+    }
+}
+
+/// In-process fan-out of security events received from Postgres
+///
+/// Managed as Rocket state; subscribers get every event published after they subscribe.
+/// Clones share the same channel.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<SecurityEvent>,
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        EventBus { sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<SecurityEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Deliver an event to local subscribers
+    pub fn dispatch(&self, event: SecurityEvent) {
+        // Sending only fails when nobody is subscribed
+        let _ = self.sender.send(event);
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        EventBus::new(256)
+    }
+}
+
+/// Fairing that forwards NOTIFY payloads into the managed `EventBus`
+pub fn listener() -> AdHoc {
+    AdHoc::on_liftoff("Security Event Listener", |rocket| {
+        Box::pin(async move {
+            let (Some(config), Some(bus)) = (rocket.state::<AppConfig>(), rocket.state::<EventBus>()) else {
+                eprintln!("Security event listener not started: missing AppConfig or EventBus");
+                return;
+            };
+
+            let mut listener = match PgListener::connect(&config.database_url).await {
+                Ok(listener) => listener,
+                Err(e) => {
+                    eprintln!("Failed to start security event listener: {}", e);
+                    return;
+                }
+            };
+            if let Err(e) = listener.listen(CHANNEL).await {
+                eprintln!("Failed to listen on {}: {}", CHANNEL, e);
+                return;
+            }
+
+            let bus = bus.clone();
+            rocket::tokio::spawn(async move {
+                loop {
+                    // The listener reconnects by itself after connection loss
+                    match listener.recv().await {
+                        Ok(notification) => match serde_json::from_str::<SecurityEvent>(notification.payload()) {
+                            Ok(event) => bus.dispatch(event),
+                            Err(e) => eprintln!("Ignoring malformed security event: {}", e),
+                        },
+                        Err(e) => {
+                            eprintln!("Security event listener error: {}", e);
+                            rocket::tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                        }
+                    }
+                }
+            });
+        })
+    })
+}
@@ -0,0 +1,113 @@
+use chrono::{Duration, Utc};
+use rocket::fairing::AdHoc;
+use rocket_db_pools::Database;
+use sqlx::{PgConnection, PgPool};
+
+use crate::config::{AppConfig, InactiveAccountAction};
+use crate::email::sender::Mailer;
+use crate::email::templates;
+use crate::events::{self, SecurityEvent, SecurityEventKind};
+use crate::repositories::{sessions, users};
+use crate::Postgres;
+
+/// How often each server instance applies the inactivity policy
+const SWEEP_INTERVAL_MINUTES: u64 = 60;
+
+/// Most warnings sent per sweep; the rest go out on the next one
+const WARNING_BATCH: i64 = 500;
+
+/// What one sweep of the inactivity policy did
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SweepReport {
+    pub warned: usize,
+    pub flagged: usize,
+    pub disabled: usize,
+}
+
+/// Apply the inactivity policy once
+///
+/// Users who haven't logged in for `ROCKET_INACTIVE_ACCOUNT_DAYS` (counting
+/// from signup or their last reactivation if later) are flagged, and with the
+/// `disable` action also disabled and signed out everywhere. With warnings on,
+/// users are emailed `ROCKET_INACTIVE_ACCOUNT_WARNING_DAYS` ahead, and nobody
+/// is flagged before their warning is that old. Admins and guests are exempt.
+/// Safe to run from several instances at once.
+pub async fn sweep(conn: &mut PgConnection, config: &AppConfig, mailer: &Mailer) -> Result<SweepReport, sqlx::Error> {
+    let mut report = SweepReport::default();
+    if config.inactive_account_days == 0 {
+        return Ok(report);
+    }
+
+    let now = Utc::now();
+    let inactive_days = Duration::days(config.inactive_account_days as i64);
+    let warning_days = Duration::days(config.inactive_account_warning_days as i64);
+    let disable = config.inactive_account_action == InactiveAccountAction::Disable;
+
+    if config.inactive_account_warning_days > 0 {
+        let warned = users::claim_inactivity_warnings(conn, now - (inactive_days - warning_days), WARNING_BATCH).await?;
+        // Nobody is flagged until their warning is a full warning period old
+        let deadline = now + warning_days;
+        for user in &warned {
+            let message = templates::inactive_account_warning(
+                &user.email,
+                &format!("{}/login", config.frontend_url),
+                &templates::local_time(deadline, user.timezone.as_deref()),
+                disable,
+            );
+            if let Err(e) = mailer.send(message).await {
+                eprintln!("Failed to send inactivity warning: {}", e);
+            }
+        }
+        report.warned = warned.len();
+    }
+
+    let warned_before = (config.inactive_account_warning_days > 0).then(|| now - warning_days);
+    let flagged = users::flag_inactive(conn, now - inactive_days, warned_before, disable).await?;
+    report.flagged = flagged.len();
+
+    if disable {
+        for user_id in &flagged {
+            sessions::revoke_all_for_user(conn, *user_id).await?;
+            events::emit_event(conn, SecurityEvent::new(*user_id, SecurityEventKind::AccountDisabled)).await;
+        }
+        report.disabled = flagged.len();
+    }
+
+    Ok(report)
+}
+
+/// Fairing that sweeps every hour while the server runs, if the policy is on
+pub fn fairing() -> AdHoc {
+    AdHoc::on_liftoff("Inactive Account Policy", |rocket| {
+        Box::pin(async move {
+            let (Some(config), Some(mailer), Some(db)) =
+                (rocket.state::<AppConfig>(), rocket.state::<Mailer>(), Postgres::fetch(rocket))
+            else {
+                eprintln!("Inactive account policy not started: missing AppConfig, Mailer or database");
+                return;
+            };
+            if config.inactive_account_days == 0 {
+                return;
+            }
+
+            let (config, mailer, pool) = (config.clone(), mailer.clone(), PgPool::clone(db));
+            rocket::tokio::spawn(async move {
+                loop {
+                    let result = match pool.acquire().await {
+                        Ok(mut conn) => sweep(&mut conn, &config, &mailer).await,
+                        Err(e) => Err(e),
+                    };
+                    match result {
+                        Ok(report) if report != SweepReport::default() => println!(
+                            "✓ Inactive accounts: {} warned, {} flagged, {} disabled",
+                            report.warned, report.flagged, report.disabled
+                        ),
+                        Ok(_) => {}
+                        Err(e) => eprintln!("Inactive account sweep failed: {}", e),
+                    }
+                    rocket::tokio::time::sleep(std::time::Duration::from_secs(SWEEP_INTERVAL_MINUTES * 60)).await;
+                }
+            });
+        })
+    })
+}
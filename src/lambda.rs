@@ -0,0 +1,155 @@
+use std::net::{IpAddr, SocketAddr};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use lambda_runtime::{service_fn, Error, LambdaEvent};
+use rocket::http::{Header, Method};
+use rocket::local::asynchronous::Client;
+use rocket::serde::json::{json, Value};
+use rocket::{Build, Rocket};
+use serde_json::Map;
+
+/// Serve `rocket` on AWS Lambda until the runtime shuts the function down
+///
+/// Requests come from API Gateway (REST or HTTP APIs) or a function URL and
+/// are dispatched to the application in-process, without a listening socket.
+pub async fn run(rocket: Rocket<Build>) -> Result<(), Error> {
+    // Lives as long as the function's process does
+    let client: &'static Client = Box::leak(Box::new(Client::untracked(rocket).await?));
+    lambda_runtime::run(service_fn(move |event: LambdaEvent<Value>| handle(client, event.payload))).await
+}
+
+/// Answer one API Gateway or function URL event with `client`'s application
+///
+/// Takes payload format 2.0 (HTTP APIs, function URLs) and 1.0 (REST APIs)
+/// and answers in the same format.
+pub async fn handle(client: &Client, event: Value) -> Result<Value, Error> {
+    let v2 = event["version"] == "2.0";
+    let method = if v2 { &event["requestContext"]["http"]["method"] } else { &event["httpMethod"] };
+    let method: Method = method.as_str().and_then(|method| method.parse().ok()).ok_or("Event has no HTTP method")?;
+    let path = event[if v2 { "rawPath" } else { "path" }].as_str().ok_or("Event has no path")?;
+
+    let query = match v2 {
+        true => event["rawQueryString"].as_str().unwrap_or_default().to_string(),
+        false => v1_query(&event),
+    };
+    let uri = match query.is_empty() {
+        true => path.to_string(),
+        false => format!("{}?{}", path, query),
+    };
+
+    let mut request = client.req(method, uri);
+    for (name, value) in headers(&event, v2) {
+        request.add_header(Header::new(name, value));
+    }
+    let source_ip = match v2 {
+        true => &event["requestContext"]["http"]["sourceIp"],
+        false => &event["requestContext"]["identity"]["sourceIp"],
+    };
+    if let Some(ip) = source_ip.as_str().and_then(|ip| ip.parse::<IpAddr>().ok()) {
+        request = request.remote(SocketAddr::new(ip, 0));
+    }
+    if let Some(body) = event["body"].as_str() {
+        let body = match event["isBase64Encoded"].as_bool().unwrap_or(false) {
+            true => STANDARD.decode(body)?,
+            false => body.as_bytes().to_vec(),
+        };
+        request = request.body(body);
+    }
+
+    let response = request.dispatch().await;
+    let status = response.status().code;
+    let mut cookies = Vec::new();
+    let mut response_headers: Vec<(String, Vec<String>)> = Vec::new();
+    for header in response.headers().iter() {
+        let (name, value) = (header.name().as_str().to_string(), header.value().to_string());
+        if v2 && name.eq_ignore_ascii_case("Set-Cookie") {
+            cookies.push(value);
+        } else if let Some((_, values)) = response_headers.iter_mut().find(|(existing, _)| *existing == name) {
+            values.push(value);
+        } else {
+            response_headers.push((name, vec![value]));
+        }
+    }
+    let (body, base64) = match response.into_bytes().await.map(String::from_utf8) {
+        Some(Ok(text)) => (text, false),
+        Some(Err(binary)) => (STANDARD.encode(binary.into_bytes()), true),
+        None => (String::new(), false),
+    };
+
+    if v2 {
+        let headers: Map<String, Value> = response_headers
+            .into_iter()
+            .map(|(name, values)| (name, json!(values.join(", "))))
+            .collect();
+        Ok(json!({
+            "statusCode": status,
+            "headers": headers,
+            "cookies": cookies,
+            "body": body,
+            "isBase64Encoded": base64
+        }))
+    } else {
+        let headers: Map<String, Value> = response_headers
+            .into_iter()
+            .map(|(name, values)| (name, json!(values)))
+            .collect();
+        Ok(json!({
+            "statusCode": status,
+            "multiValueHeaders": headers,
+            "body": body,
+            "isBase64Encoded": base64
+        }))
+    }
+}
+
+/// Request headers, with 2.0's separate cookies folded back into `Cookie`
+fn headers(event: &Value, v2: bool) -> Vec<(String, String)> {
+    let mut headers = Vec::new();
+    match event["multiValueHeaders"].as_object().filter(|_| !v2) {
+        Some(multi) => {
+            for (name, values) in multi {
+                for value in values.as_array().into_iter().flatten().filter_map(Value::as_str) {
+                    headers.push((name.clone(), value.to_string()));
+                }
+            }
+        }
+        None => {
+            for (name, value) in event["headers"].as_object().into_iter().flatten() {
+                if let Some(value) = value.as_str() {
+                    headers.push((name.clone(), value.to_string()));
+                }
+            }
+        }
+    }
+    if let Some(cookies) = event["cookies"].as_array().filter(|cookies| !cookies.is_empty()) {
+        let cookies: Vec<&str> = cookies.iter().filter_map(Value::as_str).collect();
+        headers.push(("Cookie".to_string(), cookies.join("; ")));
+    }
+    headers
+}
+
+/// 1.0 events pass the query string decoded; encode it again
+fn v1_query(event: &Value) -> String {
+    let mut url = reqwest::Url::parse("http://lambda/").expect("valid URL");
+    {
+        let mut pairs = url.query_pairs_mut();
+        match event["multiValueQueryStringParameters"].as_object() {
+            Some(multi) => {
+                for (name, values) in multi {
+                    for value in values.as_array().into_iter().flatten().filter_map(Value::as_str) {
+                        pairs.append_pair(name, value);
+                    }
+                }
+            }
+            None => {
+                for (name, value) in event["queryStringParameters"].as_object().into_iter().flatten() {
+                    if let Some(value) = value.as_str() {
+                        pairs.append_pair(name, value);
+                    }
+                }
+            }
+        }
+    }
+    url.query().unwrap_or_default().to_string()
+}
@@ -0,0 +1,420 @@
+#[macro_use] extern crate rocket;
+
+pub mod alerts;
+pub mod audit;
+pub mod auth;
+pub mod authz;
+pub mod billing;
+pub mod body_limits;
+pub mod cache;
+pub mod circuit_breaker;
+pub mod compression;
+pub mod conditional;
+pub mod config;
+pub mod db;
+pub mod devices;
+pub mod domains;
+pub mod email;
+pub mod errors;
+pub mod events;
+pub mod inactivity;
+#[cfg(feature = "lambda")]
+pub mod lambda;
+pub mod links;
+pub mod load_shed;
+pub mod maintenance;
+pub mod migrations;
+pub mod models;
+pub mod pages;
+pub mod rate_limit;
+pub mod redirects;
+pub mod repositories;
+pub mod request_log;
+pub mod retention;
+pub mod risk;
+pub mod routes;
+mod server;
+pub mod settings;
+pub mod telemetry;
+pub mod tenancy;
+pub mod tls;
+pub mod travel;
+pub mod unix_socket;
+pub mod versioning;
+#[cfg(feature = "test-support")]
+pub mod test_support;
+
+use std::sync::Arc;
+
+use rocket::{Build, Rocket};
+use rocket_db_pools::Database;
+use rocket_cors::CorsOptions;
+
+use audit::AuditSinks;
+use cache::SharedCache;
+use auth::access_tokens::AccessTokens;
+use auth::token_versions::TokenVersions;
+use auth::external::ExternalIssuer;
+use auth::google::GoogleIdTokens;
+use auth::oauth::OAuthProviders;
+use auth::jwt::JwtService;
+use auth::password::PasswordHasher;
+use authz::Policy;
+use config::{AppConfig, EmailTransport, RequestLog};
+use domains::DnsClient;
+use email::memory::MemoryEmailSender;
+use email::sender::{LogEmailSender, Mailer};
+use email::smtp::SmtpEmailSender;
+use events::EventBus;
+use load_shed::LoadShedder;
+use maintenance::MaintenanceMode;
+use pages::Pages;
+use rate_limit::{ApiKeyQuota, LoginDelay, LoginLockout, StuffingDetector};
+use settings::Settings;
+use telemetry::TelemetryWriter;
+use routes::account as account_routes;
+use routes::admin as admin_routes;
+use routes::api_keys as api_key_routes;
+use routes::audit as audit_routes;
+use routes::auth as auth_routes;
+use routes::dev as dev_routes;
+use routes::emails as email_routes;
+use routes::mfa as mfa_routes;
+use routes::guest as guest_routes;
+use routes::health as health_routes;
+use routes::links as link_routes;
+use routes::oauth as oauth_routes;
+use routes::oidc as oidc_routes;
+use routes::authorization as authorization_routes;
+use routes::connections as connection_routes;
+use routes::email_settings as email_settings_routes;
+use routes::settings as settings_routes;
+use routes::sso as sso_routes;
+use routes::oauth_clients as oauth_client_routes;
+use routes::client_registration as client_registration_routes;
+use routes::permissions as permission_routes;
+use routes::plans as plan_routes;
+use routes::qr_login as qr_login_routes;
+use routes::organizations as organization_routes;
+use routes::billing as billing_routes;
+
+#[derive(Database)]
+#[database("postgres")]
+pub struct Postgres(db::Pool);
+
+#[get("/")]
+fn index() -> &'static str {
+    "Hello, world!"
+}
+
+/// Assemble the Rocket instance: database pool, CORS, managed state and routes
+///
+/// Migrations are not run here; call `migrations::run_migrations` first.
+pub fn build_rocket(config: AppConfig, jwt: JwtService) -> Rocket<Build> {
+    build_rocket_with_policy(config, jwt, Policy::default())
+}
+
+/// Like `build_rocket`, with a custom authorization policy engine
+pub fn build_rocket_with_policy(config: AppConfig, jwt: JwtService, policy: Policy) -> Rocket<Build> {
+    // Configure Rocket with the database URL from .env and the pool settings
+    let figment = db::configure(
+        rocket::Config::figment(),
+        &config.database_url,
+        &config.db_pool,
+        &config.db_circuit_breaker,
+    );
+    let figment = body_limits::configure(figment, &config.body_limits);
+
+    // Configure CORS
+    let cors = CorsOptions::default()
+        .allowed_origins(rocket_cors::AllowedOrigins::all())
+        .allowed_methods(
+            vec![
+                rocket::http::Method::Get,
+                rocket::http::Method::Post,
+                rocket::http::Method::Put,
+                rocket::http::Method::Patch,
+                rocket::http::Method::Delete,
+            ]
+                .into_iter()
+                .map(From::from)
+                .collect(),
+        )
+        .allowed_headers(rocket_cors::AllowedHeaders::some(&[
+            "Authorization",
+            "Accept",
+            "Content-Type",
+            "If-Match",
+            "If-None-Match",
+        ]))
+        .expose_headers(
+            ["ETag", "Deprecation", "Sunset", "Link"]
+                .into_iter()
+                .chain(rate_limit::HEADERS)
+                .map(String::from)
+                .collect(),
+        )
+        .allow_credentials(true)
+        .to_cors()
+        .expect("Failed to create CORS fairing");
+
+    let maintenance = MaintenanceMode::new(config.maintenance_mode);
+    let password_hasher = PasswordHasher::from_config(&config);
+    let audit_sinks = AuditSinks::from_config(&config);
+    let telemetry = TelemetryWriter::from_config(&config);
+    let cache = SharedCache::from_config(&config).expect("Failed to set up the cache");
+    let access_tokens = AccessTokens::from_config(&config).with_telemetry(telemetry.clone());
+    let token_versions = TokenVersions::from_config(&config).with_cache(cache.clone());
+    let settings = Settings::new(&config);
+    let login_lockout = LoginLockout::from_config(&config)
+        .with_cache(cache.clone(), chrono::Duration::seconds(config.login_lockout_cache_seconds as i64))
+        .with_settings(settings.clone());
+    let login_delay = LoginDelay::from_config(&config).with_settings(settings.clone());
+    let stuffing_detector = StuffingDetector::from_config(&config);
+    let api_key_quota = ApiKeyQuota::from_config(&config);
+    let dns = DnsClient::new(&config.dns_over_https_url);
+    let external_issuer = config.external_jwt.clone().map(ExternalIssuer::new);
+    let google_id_tokens = GoogleIdTokens::from_config(&config);
+    let oauth_providers = OAuthProviders::from_config(&config);
+    let legacy_api = config.legacy_api;
+    let deprecation_headers = versioning::deprecation_headers(config.legacy_api_sunset);
+    let load_shedder = LoadShedder::from_config(&config);
+    let compression = config.compression.then(|| compression::fairing(config.compression_min_bytes));
+    let request_logger = match config.request_log {
+        RequestLog::Off => None,
+        RequestLog::Summary => Some(request_log::RequestLogger { bodies: false }),
+        RequestLog::Bodies => Some(request_log::RequestLogger { bodies: true }),
+    };
+
+    // Set up outgoing email; the memory transport also gets a dev mailbox route
+    let mut dev_mailbox = None;
+    let mailer = match config.email_transport {
+        EmailTransport::Log => Mailer::new(Arc::new(LogEmailSender), config.email_from.clone()),
+        EmailTransport::Memory => {
+            let mailbox = Arc::new(MemoryEmailSender::new());
+            dev_mailbox = Some(mailbox.clone());
+            Mailer::new(mailbox, config.email_from.clone())
+        }
+        EmailTransport::Smtp => {
+            let smtp = config.smtp.clone().expect("ROCKET_EMAIL_TRANSPORT=smtp requires ROCKET_SMTP_HOST");
+            Mailer::new(Arc::new(SmtpEmailSender::new(smtp)), config.email_from.clone())
+        }
+    };
+
+    let rocket = rocket::custom(figment)
+        .attach(Postgres::init())
+        .attach(cors)
+        .attach(events::listener())
+        .attach(audit::forwarder())
+        .attach(email::welcome::fairing())
+        .attach(settings::fairing())
+        .attach(email::settings::fairing())
+        .attach(billing::fairing())
+        .attach(inactivity::fairing())
+        .attach(domains::fairing())
+        .attach(alerts::fairing())
+        .attach(retention::fairing())
+        .attach(telemetry::fairing())
+        .attach(telemetry::shutdown_flush())
+        .attach(deprecation_headers)
+        .manage(config)
+        .manage(jwt)
+        .manage(access_tokens)
+        .manage(telemetry)
+        .manage(cache)
+        .manage(token_versions)
+        .manage(login_lockout)
+        .manage(login_delay)
+        .manage(stuffing_detector)
+        .manage(api_key_quota)
+        .manage(maintenance)
+        .manage(mailer)
+        .manage(settings)
+        .manage(dns)
+        .manage(EventBus::default())
+        .manage(audit_sinks)
+        .manage(policy)
+        .manage(password_hasher)
+        .manage(oauth_providers)
+        .manage(Pages::new())
+        .register("/", catchers![
+            maintenance::service_unavailable,
+            body_limits::payload_too_large,
+            auth::guard::forbidden,
+            rate_limit::too_many_requests,
+            errors::not_found
+        ])
+        .mount("/", routes![index, link_routes::open_link, oidc_routes::openid_configuration])
+        .mount("/health", routes![health_routes::ready]);
+
+    let rocket = match external_issuer {
+        Some(issuer) => rocket.manage(issuer),
+        None => rocket,
+    };
+    let rocket = match google_id_tokens {
+        Some(google) => rocket.manage(google),
+        None => rocket,
+    };
+
+    // Attached before compression so logged response bodies are still readable
+    let rocket = match request_logger {
+        Some(fairing) => rocket.attach(fairing),
+        None => rocket,
+    };
+    let rocket = match load_shedder {
+        Some(fairing) => rocket.attach(fairing).mount("/", routes![load_shed::shed]),
+        None => rocket,
+    };
+    let rocket = match compression {
+        Some(fairing) => rocket.attach(fairing),
+        None => rocket,
+    };
+
+    let rocket = versioning::mount(rocket, "auth", routes![
+        auth_routes::register,
+        auth_routes::login,
+        mfa_routes::login_mfa,
+        mfa_routes::request_recovery,
+        oauth_routes::google_id_token,
+        oauth_routes::oauth_authorize,
+        oauth_routes::oauth_callback,
+        oauth_routes::oauth_complete,
+        oidc_routes::userinfo,
+        oidc_routes::userinfo_post,
+        authorization_routes::describe_authorization,
+        authorization_routes::decide_authorization,
+        authorization_routes::token,
+        authorization_routes::revoke,
+        authorization_routes::list_applications,
+        authorization_routes::revoke_application,
+        connection_routes::list_connections,
+        connection_routes::revoke_connection,
+        client_registration_routes::register_client,
+        client_registration_routes::get_registration,
+        client_registration_routes::delete_registration,
+        auth_routes::forgot_password,
+        auth_routes::reset_password,
+        auth_routes::change_password,
+        auth_routes::renew_token,
+        auth_routes::logout_all,
+        auth_routes::list_sessions,
+        auth_routes::get_current_user,
+        auth_routes::update_current_user,
+        auth_routes::security_events,
+        mfa_routes::mfa_status,
+        mfa_routes::enroll_totp,
+        mfa_routes::totp_qr,
+        mfa_routes::confirm_totp,
+        mfa_routes::disable_totp,
+        mfa_routes::confirm_recovery,
+        mfa_routes::cancel_recovery,
+        auth_routes::verify_email,
+        auth_routes::resend_verification,
+        email_routes::list_emails,
+        email_routes::add_email,
+        email_routes::remove_email,
+        email_routes::promote_email,
+        account_routes::request_email_change,
+        account_routes::confirm_email_change,
+        account_routes::request_account_deletion,
+        account_routes::confirm_account_deletion,
+        account_routes::secure_account,
+        account_routes::request_data_export,
+        account_routes::download_data_export,
+        api_key_routes::create_api_key,
+        api_key_routes::list_api_keys,
+        api_key_routes::revoke_api_key,
+        api_key_routes::rotate_api_key,
+        api_key_routes::api_key_usage,
+        qr_login_routes::start_qr_login,
+        qr_login_routes::approve_qr_login,
+        qr_login_routes::poll_qr_login,
+        guest_routes::create_guest,
+        guest_routes::upgrade_guest
+    ], legacy_api);
+    let rocket = versioning::mount(rocket, "admin", routes![
+        admin_routes::get_maintenance,
+        admin_routes::set_maintenance,
+        email_settings_routes::get_email_settings,
+        email_settings_routes::update_email_settings,
+        email_settings_routes::delete_email_settings,
+        email_settings_routes::send_test_email,
+        settings_routes::list_settings,
+        settings_routes::get_setting,
+        settings_routes::update_setting,
+        settings_routes::reset_setting,
+        sso_routes::list_mappings,
+        sso_routes::create_mapping,
+        sso_routes::delete_mapping,
+        admin_routes::create_invitation,
+        admin_routes::list_invitations,
+        admin_routes::list_pending_signups,
+        admin_routes::list_users,
+        admin_routes::approve_signup,
+        admin_routes::reject_signup,
+        admin_routes::send_password_reset,
+        admin_routes::set_must_change_password,
+        admin_routes::reactivate_user,
+        admin_routes::reset_user_mfa,
+        admin_routes::list_user_mfa_recoveries,
+        admin_routes::list_ip_blocks,
+        admin_routes::lift_ip_block,
+        admin_routes::patch_user_metadata,
+        admin_routes::delete_user,
+        permission_routes::list_permissions,
+        permission_routes::create_permission,
+        permission_routes::delete_permission,
+        permission_routes::grant_permission,
+        permission_routes::revoke_permission,
+        plan_routes::list_plans,
+        plan_routes::create_plan,
+        plan_routes::list_entitlements,
+        plan_routes::grant_entitlement,
+        plan_routes::revoke_entitlement,
+        oauth_client_routes::list_clients,
+        oauth_client_routes::create_client,
+        oauth_client_routes::get_client,
+        oauth_client_routes::update_client,
+        oauth_client_routes::delete_client,
+        admin_routes::get_stats,
+        admin_routes::get_signup_stats,
+        admin_routes::get_login_stats,
+        admin_routes::get_session_stats,
+        audit_routes::query_audit_log,
+        audit_routes::replay_audit_events,
+        audit_routes::list_dead_letters
+    ], legacy_api);
+    let rocket = versioning::mount(rocket, "orgs", routes![
+        organization_routes::list_signing_keys,
+        organization_routes::create_signing_key,
+        organization_routes::revoke_signing_key,
+        organization_routes::org_audit_log,
+        organization_routes::list_roles,
+        organization_routes::create_role,
+        organization_routes::update_role,
+        organization_routes::delete_role,
+        organization_routes::set_member_role,
+        organization_routes::invite_member,
+        organization_routes::billing,
+        organization_routes::list_domains,
+        organization_routes::claim_domain,
+        organization_routes::verify_domain,
+        organization_routes::delete_domain,
+        organization_routes::accept_invitation,
+        organization_routes::get_sso,
+        organization_routes::update_sso
+    ], legacy_api);
+    let rocket = versioning::mount(rocket, "billing", routes![
+        billing_routes::stripe_webhook
+    ], legacy_api);
+
+    match dev_mailbox {
+        Some(mailbox) => {
+            println!("⚠ Email capture enabled: messages are kept in memory at /_dev/mailbox");
+            rocket
+                .manage(mailbox)
+                .mount("/_dev", routes![dev_routes::mailbox])
+        }
+        None => rocket,
+    }
+}
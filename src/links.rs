@@ -0,0 +1,123 @@
+use std::convert::Infallible;
+
+use rocket::http::RawStr;
+use rocket::request::{FromRequest, Outcome, Request};
+
+use crate::config::{AppConfig, EmailLinkStyle};
+
+/// What an emailed link lets the recipient do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkAction {
+    ResetPassword,
+    VerifyEmail,
+    ConfirmEmailChange,
+    ConfirmAccountDeletion,
+    ConfirmMfaRecovery,
+    SecureAccount,
+}
+
+impl LinkAction {
+    /// Path used in app links and in `/l/<action>`
+    pub fn path(self) -> &'static str {
+        match self {
+            LinkAction::ResetPassword => "reset",
+            LinkAction::VerifyEmail => "verify-email",
+            LinkAction::ConfirmEmailChange => "confirm-email-change",
+            LinkAction::ConfirmAccountDeletion => "confirm-account-deletion",
+            LinkAction::ConfirmMfaRecovery => "confirm-mfa-recovery",
+            LinkAction::SecureAccount => "secure-account",
+        }
+    }
+
+    pub fn from_path(path: &str) -> Option<Self> {
+        match path {
+            "reset" => Some(LinkAction::ResetPassword),
+            "verify-email" => Some(LinkAction::VerifyEmail),
+            "confirm-email-change" => Some(LinkAction::ConfirmEmailChange),
+            "confirm-account-deletion" => Some(LinkAction::ConfirmAccountDeletion),
+            "confirm-mfa-recovery" => Some(LinkAction::ConfirmMfaRecovery),
+            "secure-account" => Some(LinkAction::SecureAccount),
+            _ => None,
+        }
+    }
+}
+
+/// The link to put in an email, according to `ROCKET_EMAIL_LINKS`
+pub fn email_link(config: &AppConfig, action: LinkAction, token: &str) -> String {
+    match &config.email_links {
+        EmailLinkStyle::Web => web_link(config, action, token),
+        EmailLinkStyle::App { scheme } => app_link(scheme, action, token),
+        EmailLinkStyle::Universal { .. } => format!(
+            "{}/l/{}?token={}",
+            config.public_url,
+            action.path(),
+            RawStr::new(token).percent_encode()
+        ),
+    }
+}
+
+/// Link to the web frontend (reset and confirmation forms) or this API (verification)
+pub fn web_link(config: &AppConfig, action: LinkAction, token: &str) -> String {
+    let token = RawStr::new(token).percent_encode();
+    match action {
+        LinkAction::ResetPassword => format!("{}/reset-password?token={}", config.frontend_url, token),
+        LinkAction::VerifyEmail => format!("{}/api/v1/auth/verify-email?token={}", config.public_url, token),
+        LinkAction::ConfirmEmailChange => format!("{}/confirm-email-change?token={}", config.frontend_url, token),
+        LinkAction::ConfirmAccountDeletion => {
+            format!("{}/confirm-account-deletion?token={}", config.frontend_url, token)
+        }
+        LinkAction::ConfirmMfaRecovery => format!("{}/confirm-mfa-recovery?token={}", config.frontend_url, token),
+        LinkAction::SecureAccount => format!("{}/secure-account?token={}", config.frontend_url, token),
+    }
+}
+
+/// Link into the mobile app, e.g. `myapp://reset?token=…`
+pub fn app_link(scheme: &str, action: LinkAction, token: &str) -> String {
+    format!("{}{}?token={}", scheme, action.path(), RawStr::new(token).percent_encode())
+}
+
+/// `link` carrying where to send the browser once it's been followed, if anywhere
+pub fn with_redirect(link: String, redirect_to: Option<&str>) -> String {
+    match redirect_to {
+        Some(redirect_to) => format!("{}&redirect_to={}", link, RawStr::new(redirect_to).percent_encode()),
+        None => link,
+    }
+}
+
+/// Client platform, detected from the `User-Agent` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    Ios,
+    Android,
+    Other,
+}
+
+impl Platform {
+    pub fn from_user_agent(user_agent: &str) -> Self {
+        if ["iPhone", "iPad", "iPod"].iter().any(|device| user_agent.contains(device)) {
+            Platform::Ios
+        } else if user_agent.contains("Android") {
+            Platform::Android
+        } else {
+            Platform::Other
+        }
+    }
+
+    pub fn is_mobile(self) -> bool {
+        self != Platform::Other
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Platform {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let platform = request
+            .headers()
+            .get_one("User-Agent")
+            .map(Platform::from_user_agent)
+            .unwrap_or(Platform::Other);
+        Outcome::Success(platform)
+    }
+}
@@ -0,0 +1,160 @@
+use std::sync::{Arc, Mutex};
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::{Header, Method, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::response::status;
+use rocket::serde::json::{json, Json, Value};
+use rocket::{Data, Request, Response};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::config::AppConfig;
+use crate::versioning::ApiVersion;
+
+/// Where shed requests are re-routed to; only reachable that way
+const SHED_PATH: &str = "/_load-shed";
+
+/// Paths, below the API version prefix, of endpoints that hash or verify a password
+const CREDENTIAL_PATHS: &[&str] = &[
+    "/auth/login",
+    "/auth/login/mfa",
+    "/auth/register",
+    "/auth/change-password",
+    "/auth/reset-password",
+    "/auth/guest",
+    "/auth/guest/upgrade",
+    "/auth/me/email-change",
+    "/auth/me/delete",
+];
+
+/// Requests sharing an in-flight limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteGroup {
+    /// Password hashing and verification, bounded by CPU
+    Credentials,
+    /// Everything else, mostly cheap database reads
+    Other,
+}
+
+impl RouteGroup {
+    pub fn of(method: Method, path: &str) -> RouteGroup {
+        let path = match ApiVersion::from_path(path) {
+            Some(version) => &path[version.prefix().len()..],
+            None => path,
+        };
+        if method == Method::Post && CREDENTIAL_PATHS.contains(&path) {
+            RouteGroup::Credentials
+        } else {
+            RouteGroup::Other
+        }
+    }
+}
+
+/// The permit a request holds while its handler runs
+struct InFlight(Mutex<Option<OwnedSemaphorePermit>>);
+
+/// Marks a request that was turned away
+struct Shed(bool);
+
+/// Fairing that caps the requests being handled at once per [`RouteGroup`]
+///
+/// Requests over a group's limit aren't queued: they're answered right away
+/// with 503 and `Retry-After`, so a traffic spike can't exhaust the database
+/// pool or pile up behind password hashing. A request's slot is freed once
+/// its handler returns, before a streamed body is sent.
+pub struct LoadShedder {
+    credentials: Option<Arc<Semaphore>>,
+    other: Option<Arc<Semaphore>>,
+    retry_after_seconds: u64,
+}
+
+impl LoadShedder {
+    /// `None` for a limit means that group is never shed
+    pub fn new(credentials: Option<usize>, other: Option<usize>, retry_after_seconds: u64) -> Self {
+        LoadShedder {
+            credentials: credentials.map(|limit| Arc::new(Semaphore::new(limit))),
+            other: other.map(|limit| Arc::new(Semaphore::new(limit))),
+            retry_after_seconds,
+        }
+    }
+
+    /// The configured shedder, or `None` when no limit is set
+    pub fn from_config(config: &AppConfig) -> Option<Self> {
+        let limit = |limit: usize| (limit > 0).then_some(limit);
+        let (credentials, other) = (limit(config.load_shed_credentials_limit), limit(config.load_shed_limit));
+        (credentials.is_some() || other.is_some())
+            .then(|| LoadShedder::new(credentials, other, config.load_shed_retry_after_seconds))
+    }
+
+    fn semaphore(&self, group: RouteGroup) -> Option<&Arc<Semaphore>> {
+        match group {
+            RouteGroup::Credentials => self.credentials.as_ref(),
+            RouteGroup::Other => self.other.as_ref(),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for LoadShedder {
+    fn info(&self) -> Info {
+        Info {
+            name: "Load Shedder",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let group = RouteGroup::of(request.method(), request.uri().path().as_str());
+        let Some(semaphore) = self.semaphore(group) else {
+            return;
+        };
+
+        match semaphore.clone().try_acquire_owned() {
+            Ok(permit) => {
+                request.local_cache(|| InFlight(Mutex::new(Some(permit))));
+            }
+            Err(_) => {
+                eprintln!("⚠ Load shed: {} {} ({:?} limit reached)", request.method(), request.uri().path(), group);
+                request.local_cache(|| Shed(true));
+                // Fairings can't answer a request, so hand it to a route that only refuses it
+                request.set_method(Method::Get);
+                request.set_uri(Origin::parse(SHED_PATH).expect("valid shed path"));
+            }
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        // Dropping the permit frees the slot
+        request.local_cache(|| InFlight(Mutex::new(None))).0.lock().unwrap().take();
+        if request.local_cache(|| Shed(false)).0 {
+            response.set_header(Header::new("Retry-After", self.retry_after_seconds.to_string()));
+        }
+    }
+}
+
+/// Request guard matching only requests the [`LoadShedder`] turned away
+pub struct ShedRequest;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ShedRequest {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        if request.local_cache(|| Shed(false)).0 {
+            Outcome::Success(ShedRequest)
+        } else {
+            Outcome::Forward(Status::NotFound)
+        }
+    }
+}
+
+#[get("/_load-shed")]
+pub fn shed(_shed: ShedRequest) -> status::Custom<Json<Value>> {
+    status::Custom(
+        Status::ServiceUnavailable,
+        Json(json!({
+            "error": "Server is busy, please retry shortly"
+        })),
+    )
+}
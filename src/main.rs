@@ -1,75 +1,57 @@
-#[macro_use] extern crate rocket;
-
-mod models;
-mod migrations;
-mod routes;
-mod auth;
-mod errors;
-
-use rocket_db_pools::Database;
-use sqlx;
-use rocket_cors::CorsOptions;
-
-use routes::auth as auth_routes;
-
-#[derive(Database)]
-#[database("postgres")]
-struct Postgres(sqlx::PgPool);
-
-#[get("/")]
-fn index() -> &'static str {
-    "Hello, world!"
-}
+use rocket_auth_boilerplate::auth::jwt::JwtService;
+use rocket_auth_boilerplate::auth::password::{PasswordHasher, MIN_HASH_DURATION};
+use rocket_auth_boilerplate::config::AppConfig;
+use rocket_auth_boilerplate::{build_rocket, db, migrations, tls, unix_socket};
 
 #[rocket::main]
+#[allow(clippy::result_large_err)]
 async fn main() -> Result<(), rocket::Error> {
     // Load environment variables from .env file BEFORE Rocket reads config
     dotenv::dotenv().ok();
-    
-    // Get DATABASE_URL from environment
-    let database_url = std::env::var("ROCKET_DATABASE_URL")
-        .expect("ROCKET_DATABASE_URL must be set in .env file");
-    
-    // Configure Rocket with the database URL from .env
-    let figment = rocket::Config::figment()
-        .merge(("databases.postgres.url", database_url.clone()));
-    
+
+    let config = AppConfig::from_env().expect("Invalid configuration");
+
+    // Set up token signing (HMAC secret or remote KMS key)
+    let jwt = JwtService::from_config(&config).await
+        .expect("Failed to initialize JWT signer");
+
+    // Check that password hashing is slow enough on this host
+    if config.password_hash_calibration {
+        match PasswordHasher::from_config(&config).calibrate().await {
+            Ok(elapsed) if elapsed < MIN_HASH_DURATION => eprintln!(
+                "⚠ Password hashing took {}ms, under {}ms; raise ROCKET_BCRYPT_COST or the Argon2 parameters",
+                elapsed.as_millis(),
+                MIN_HASH_DURATION.as_millis()
+            ),
+            Ok(elapsed) => println!("Password hashing takes {}ms", elapsed.as_millis()),
+            Err(e) => eprintln!("⚠ Password hashing calibration failed: {}", e),
+        }
+    }
+
     // Run migrations before starting the server
-    let pool = sqlx::PgPool::connect(&database_url).await
+    let pool = db::connect(&config.database_url, &config.db_pool).await
         .expect("Failed to connect to database");
+    println!("✓ Migration pool: {}", db::describe(&config.db_pool));
     migrations::run_migrations(&pool).await
         .expect("Failed to run migrations");
-    
-    // Configure CORS
-    let cors = CorsOptions::default()
-        .allowed_origins(rocket_cors::AllowedOrigins::all())
-        .allowed_methods(
-            vec![rocket::http::Method::Get, rocket::http::Method::Post]
-                .into_iter()
-                .map(From::from)
-                .collect(),
-        )
-        .allowed_headers(rocket_cors::AllowedHeaders::some(&[
-            "Authorization",
-            "Accept",
-            "Content-Type",
-        ]))
-        .allow_credentials(true)
-        .to_cors()
-        .expect("Failed to create CORS fairing");
 
-    let _rocket = rocket::custom(figment)
-        .attach(Postgres::init())
-        .attach(cors)
-        .mount("/", routes![index])
-        .mount("/api/auth", routes![
-            auth_routes::register,
-            auth_routes::login,
-            auth_routes::forgot_password,
-            auth_routes::reset_password
-        ])
+    // Behind a reverse proxy on the same host, listen on a Unix socket instead of TCP
+    if let Some(path) = config.unix_socket.clone() {
+        unix_socket::serve(build_rocket(config, jwt), &path).await
+            .expect("Failed to serve on the Unix socket");
+        return Ok(());
+    }
+
+    // Without a proxy in front, terminate TLS here
+    if let Some(tls_config) = config.tls.clone() {
+        tls::serve(build_rocket(config, jwt), &tls_config).await
+            .expect("Failed to serve over TLS");
+        return Ok(());
+    }
+
+    let _rocket = build_rocket(config, jwt)
         .launch()
         .await?;
-    
+
     Ok(())
 }
@@ -0,0 +1,94 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::Json;
+use rocket_db_pools::Database;
+use serde::Deserialize;
+
+use crate::circuit_breaker::CircuitState;
+use crate::errors::ErrorResponse;
+use crate::Postgres;
+
+/// Runtime read-only switch for use during migrations or incidents
+///
+/// Managed as Rocket state. While enabled, every route that takes a
+/// `WriteAccess` guard fails with 503; read paths keep working.
+pub struct MaintenanceMode {
+    enabled: AtomicBool,
+    message: RwLock<Option<String>>,
+}
+
+impl MaintenanceMode {
+    pub fn new(enabled: bool) -> Self {
+        MaintenanceMode {
+            enabled: AtomicBool::new(enabled),
+            message: RwLock::new(None),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Operator-supplied explanation shown to clients, if any
+    pub fn message(&self) -> Option<String> {
+        self.message.read().ok().and_then(|message| message.clone())
+    }
+
+    /// Turn maintenance mode on or off
+    pub fn set(&self, enabled: bool, message: Option<String>) {
+        if let Ok(mut current) = self.message.write() {
+            *current = if enabled { message } else { None };
+        }
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+}
+
+/// Body for toggling maintenance mode
+#[derive(Debug, Deserialize)]
+pub struct MaintenanceUpdate {
+    pub enabled: bool,
+    pub message: Option<String>,
+}
+
+/// Request guard for endpoints that modify data
+///
+/// Add it to any mutating handler; it fails with 503 while maintenance
+/// mode is enabled and the `service_unavailable` catcher renders the body.
+pub struct WriteAccess;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for WriteAccess {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.rocket().state::<MaintenanceMode>() {
+            Some(mode) if mode.is_enabled() => Outcome::Error((Status::ServiceUnavailable, ())),
+            _ => Outcome::Success(WriteAccess),
+        }
+    }
+}
+
+/// Structured 503 body, explaining maintenance mode or an open database circuit breaker when it's the cause
+#[catch(503)]
+pub fn service_unavailable(request: &Request) -> Json<ErrorResponse> {
+    let database_down = Postgres::fetch(request.rocket()).is_some_and(|db| db.0.breaker().state() != CircuitState::Closed);
+    match request.rocket().state::<MaintenanceMode>() {
+        Some(mode) if mode.is_enabled() => {
+            let details = mode.message().unwrap_or_else(|| {
+                "The service is in read-only mode. Please try again later.".to_string()
+            });
+            Json(ErrorResponse::with_details(
+                "Service is under maintenance".to_string(),
+                details,
+            ))
+        }
+        _ if database_down => Json(ErrorResponse::with_details(
+            "Service unavailable".to_string(),
+            "The database is unreachable. Please try again later.".to_string(),
+        )),
+        _ => Json(ErrorResponse::new("Service unavailable".to_string())),
+    }
+}
@@ -1,4 +1,9 @@
-use sqlx::PgPool;
+use chrono::{DateTime, Months, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::{PgConnection, PgPool};
+
+use crate::auth::{permissions, plans};
+use crate::repositories::audit;
 
 /// Run database migrations
 pub async fn run_migrations(pool: &PgPool) -> Result<(), sqlx::Error> {
@@ -27,6 +32,1086 @@ pub async fn run_migrations(pool: &PgPool) -> Result<(), sqlx::Error> {
     .execute(pool)
     .await?;
     
+    // Add role column for admin access
+    sqlx::query(
+        "ALTER TABLE users ADD COLUMN IF NOT EXISTS role VARCHAR(20) NOT NULL DEFAULT 'user'"
+    )
+    .execute(pool)
+    .await?;
+
+    // Create password reset tokens table
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS password_reset_tokens (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            token VARCHAR(255) UNIQUE NOT NULL,
+            expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            used BOOLEAN NOT NULL DEFAULT FALSE,
+            created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Create sessions table
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS sessions (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            revoked_at TIMESTAMP WITH TIME ZONE
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS idx_sessions_user_id ON sessions(user_id)"
+    )
+    .execute(pool)
+    .await?;
+
+    // Track email verification
+    sqlx::query(
+        "ALTER TABLE users ADD COLUMN IF NOT EXISTS email_verified_at TIMESTAMP WITH TIME ZONE"
+    )
+    .execute(pool)
+    .await?;
+
+    // Create email verification tokens table
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS email_verification_tokens (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            token VARCHAR(255) UNIQUE NOT NULL,
+            expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            used BOOLEAN NOT NULL DEFAULT FALSE,
+            created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Create QR login requests table
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS qr_login_requests (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            code VARCHAR(255) UNIQUE NOT NULL,
+            poll_token VARCHAR(255) UNIQUE NOT NULL,
+            approved_user_id UUID REFERENCES users(id) ON DELETE CASCADE,
+            approved_at TIMESTAMP WITH TIME ZONE,
+            consumed_at TIMESTAMP WITH TIME ZONE,
+            expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Create login attempts table (feeds admin statistics)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS login_attempts (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+            email VARCHAR(255) NOT NULL,
+            succeeded BOOLEAN NOT NULL,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS idx_login_attempts_created_at ON login_attempts(created_at)"
+    )
+    .execute(pool)
+    .await?;
+
+    // Create invitations table (invite-only registration)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS invitations (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            code VARCHAR(255) UNIQUE NOT NULL,
+            email VARCHAR(255),
+            created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+            expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            used_at TIMESTAMP WITH TIME ZONE,
+            used_by UUID REFERENCES users(id) ON DELETE SET NULL,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Track signup approval (existing accounts count as approved)
+    sqlx::query(
+        "ALTER TABLE users ADD COLUMN IF NOT EXISTS approval_status VARCHAR(20) NOT NULL DEFAULT 'approved'"
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS approval_reason TEXT")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        "ALTER TABLE users ADD COLUMN IF NOT EXISTS approval_decided_at TIMESTAMP WITH TIME ZONE"
+    )
+    .execute(pool)
+    .await?;
+
+    // Let admins force a password change on next use
+    sqlx::query(
+        "ALTER TABLE users ADD COLUMN IF NOT EXISTS must_change_password BOOLEAN NOT NULL DEFAULT FALSE"
+    )
+    .execute(pool)
+    .await?;
+
+    // Create user_emails table (secondary addresses; users.email stays the primary)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS user_emails (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            email VARCHAR(255) NOT NULL,
+            verified_at TIMESTAMP WITH TIME ZONE,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE (user_id, email)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // An address can be added by several users but verified by only one
+    sqlx::query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_user_emails_verified_email ON user_emails(email) WHERE verified_at IS NOT NULL"
+    )
+    .execute(pool)
+    .await?;
+
+    // Verification tokens for a secondary address point at it
+    sqlx::query(
+        "ALTER TABLE email_verification_tokens ADD COLUMN IF NOT EXISTS email_id UUID REFERENCES user_emails(id) ON DELETE CASCADE"
+    )
+    .execute(pool)
+    .await?;
+
+    // Free-form metadata: user_metadata is editable by the user, app_metadata only by admins
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS user_metadata JSONB NOT NULL DEFAULT '{}'")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS app_metadata JSONB NOT NULL DEFAULT '{}'")
+        .execute(pool)
+        .await?;
+
+    // Create api_keys table (only a hash of each key is stored)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS api_keys (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            name VARCHAR(100) NOT NULL,
+            key_prefix VARCHAR(16) NOT NULL,
+            key_hash VARCHAR(64) UNIQUE NOT NULL,
+            scopes TEXT[] NOT NULL DEFAULT '{}',
+            expires_at TIMESTAMP WITH TIME ZONE,
+            last_used_at TIMESTAMP WITH TIME ZONE,
+            revoked_at TIMESTAMP WITH TIME ZONE,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Create permissions and role_permissions tables (authorization beneath roles)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS permissions (
+            name VARCHAR(100) PRIMARY KEY,
+            description TEXT,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS role_permissions (
+            role VARCHAR(20) NOT NULL,
+            permission VARCHAR(100) NOT NULL REFERENCES permissions(name) ON DELETE CASCADE,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (role, permission)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Built-in permissions go to the admin role when first created; later
+    // changes to the mappings are left alone
+    for (name, description) in permissions::BUILT_IN {
+        sqlx::query(
+            r#"
+            WITH created AS (
+                INSERT INTO permissions (name, description) VALUES ($1, $2)
+                ON CONFLICT (name) DO NOTHING
+                RETURNING name
+            )
+            INSERT INTO role_permissions (role, permission) SELECT 'admin', name FROM created
+            "#,
+        )
+        .bind(name)
+        .bind(description)
+        .execute(pool)
+        .await?;
+    }
+
+    // Reset, verification and QR poll tokens are stored as SHA-256 hashes; hash
+    // any plaintext tokens left from before (runs once, when the column is renamed)
+    let hashed_columns = [
+        ("password_reset_tokens", "token"),
+        ("email_verification_tokens", "token"),
+        ("qr_login_requests", "poll_token"),
+    ];
+    for (table, column) in hashed_columns {
+        sqlx::query(&format!(
+            r#"
+            DO $$
+            BEGIN
+                IF EXISTS (
+                    SELECT 1 FROM information_schema.columns
+                    WHERE table_schema = current_schema() AND table_name = '{table}' AND column_name = '{column}'
+                ) THEN
+                    ALTER TABLE {table} RENAME COLUMN {column} TO {column}_hash;
+                    UPDATE {table} SET {column}_hash = encode(sha256(convert_to({column}_hash, 'UTF8')), 'hex');
+                END IF;
+            END
+            $$
+            "#,
+            table = table,
+            column = column
+        ))
+        .execute(pool)
+        .await?;
+    }
+
+    // Create token_nonces table (ids of single-use tokens that have been redeemed)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS token_nonces (
+            jti VARCHAR(64) PRIMARY KEY,
+            expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            used_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Create external_identities table (users provisioned from an external token issuer)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS external_identities (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            issuer VARCHAR(255) NOT NULL,
+            subject VARCHAR(255) NOT NULL,
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE (issuer, subject)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Opaque access tokens are stored hashed with their session
+    sqlx::query(
+        "ALTER TABLE sessions ADD COLUMN IF NOT EXISTS token_hash VARCHAR(64) UNIQUE"
+    )
+    .execute(pool)
+    .await?;
+
+    // Create oauth_states table (pending sign-ins at an OAuth provider)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS oauth_states (
+            state_hash VARCHAR(64) PRIMARY KEY,
+            provider VARCHAR(64) NOT NULL,
+            code_verifier VARCHAR(128) NOT NULL,
+            nonce VARCHAR(128) NOT NULL,
+            expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Create oauth_completions table (OAuth sign-ins waiting for the user to supply an email)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS oauth_completions (
+            token_hash VARCHAR(64) PRIMARY KEY,
+            provider VARCHAR(64) NOT NULL,
+            issuer VARCHAR(255) NOT NULL,
+            subject VARCHAR(255) NOT NULL,
+            expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Create organizations table (one per Microsoft Entra tenant that signed in)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS organizations (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            name VARCHAR(255) NOT NULL,
+            microsoft_tenant_id VARCHAR(64) UNIQUE,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        "ALTER TABLE users ADD COLUMN IF NOT EXISTS organization_id UUID REFERENCES organizations(id) ON DELETE SET NULL"
+    )
+    .execute(pool)
+    .await?;
+
+    // Create oauth_clients table (applications that act for users through this authorization server)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS oauth_clients (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            client_id VARCHAR(64) UNIQUE NOT NULL,
+            client_secret_hash VARCHAR(64),
+            name VARCHAR(255) NOT NULL,
+            redirect_uris TEXT[] NOT NULL DEFAULT '{}',
+            scopes TEXT[] NOT NULL DEFAULT '{}',
+            logo_url TEXT,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Create oauth_consents table (which scopes each user granted each client)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS oauth_consents (
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            client_id UUID NOT NULL REFERENCES oauth_clients(id) ON DELETE CASCADE,
+            scopes TEXT[] NOT NULL,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (user_id, client_id)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Create oauth_authorization_codes table (codes waiting to be exchanged at the token endpoint)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS oauth_authorization_codes (
+            code_hash VARCHAR(64) PRIMARY KEY,
+            client_id UUID NOT NULL REFERENCES oauth_clients(id) ON DELETE CASCADE,
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            redirect_uri TEXT NOT NULL,
+            scopes TEXT[] NOT NULL,
+            code_challenge VARCHAR(128),
+            expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Sessions granted to an OAuth client are limited to the consented scopes
+    sqlx::query(
+        "ALTER TABLE sessions ADD COLUMN IF NOT EXISTS oauth_client_id UUID REFERENCES oauth_clients(id) ON DELETE CASCADE"
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("ALTER TABLE sessions ADD COLUMN IF NOT EXISTS scopes TEXT[]")
+        .execute(pool)
+        .await?;
+
+    // Dynamically registered clients manage their registration with this token (RFC 7592)
+    sqlx::query("ALTER TABLE oauth_clients ADD COLUMN IF NOT EXISTS registration_token_hash VARCHAR(64) UNIQUE")
+        .execute(pool)
+        .await?;
+
+    // Tokens exchanged for an internal service (RFC 8693) name it and are refused by this API
+    sqlx::query("ALTER TABLE sessions ADD COLUMN IF NOT EXISTS audience TEXT")
+        .execute(pool)
+        .await?;
+
+    // JWTs carry the version they were issued at; bumping it signs the user out everywhere
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS token_version INTEGER NOT NULL DEFAULT 0")
+        .execute(pool)
+        .await?;
+
+    // Sessions expire after a period of inactivity as well as at their absolute end
+    sqlx::query(
+        "ALTER TABLE sessions ADD COLUMN IF NOT EXISTS last_used_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP"
+    )
+    .execute(pool)
+    .await?;
+
+    // TOTP second factor; the secret is kept until a first code confirms it
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS totp_secret TEXT")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS totp_enabled_at TIMESTAMP WITH TIME ZONE")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS totp_last_step BIGINT")
+        .execute(pool)
+        .await?;
+
+    // Start of the grace period for users whose role requires MFA
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS mfa_required_since TIMESTAMP WITH TIME ZONE")
+        .execute(pool)
+        .await?;
+
+    // Password logins of users with MFA wait here for a code
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS mfa_challenges (
+            token_hash VARCHAR(64) PRIMARY KEY,
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // MFA removals for users who lost their authenticator, kept as an audit trail
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS mfa_recoveries (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            token_hash VARCHAR(64) UNIQUE,
+            admin_id UUID REFERENCES users(id) ON DELETE SET NULL,
+            reason TEXT,
+            requested_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            link_expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            confirmed_at TIMESTAMP WITH TIME ZONE,
+            available_at TIMESTAMP WITH TIME ZONE,
+            completed_at TIMESTAMP WITH TIME ZONE,
+            cancelled_at TIMESTAMP WITH TIME ZONE
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS idx_mfa_recoveries_user_id ON mfa_recoveries(user_id)"
+    )
+    .execute(pool)
+    .await?;
+
+    // Recent attempts per address, for the login lockout
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS idx_login_attempts_email ON login_attempts(LOWER(email), created_at)"
+    )
+    .execute(pool)
+    .await?;
+
+    // Preferences for emails and clients; a BCP 47 language tag and an IANA time zone
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS locale VARCHAR(35)")
+        .execute(pool)
+        .await?;
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS timezone VARCHAR(64)")
+        .execute(pool)
+        .await?;
+
+    // Last successful login, for activity reporting
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS last_login_at TIMESTAMP WITH TIME ZONE")
+        .execute(pool)
+        .await?;
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS last_login_ip VARCHAR(45)")
+        .execute(pool)
+        .await?;
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS login_count INTEGER NOT NULL DEFAULT 0")
+        .execute(pool)
+        .await?;
+
+    // Inactivity policy: warning sent, account flagged or disabled, and reactivation by an admin
+    for column in ["inactivity_warned_at", "flagged_inactive_at", "disabled_at", "reactivated_at"] {
+        sqlx::query(&format!("ALTER TABLE users ADD COLUMN IF NOT EXISTS {} TIMESTAMP WITH TIME ZONE", column))
+            .execute(pool)
+            .await?;
+    }
+
+    // Every published security event, for the admin audit log; rows outlive
+    // the users they're about, so there are no foreign keys. The table is
+    // partitioned by month so retention can drop whole partitions. Tables
+    // created before partitioning are moved aside and copied over.
+    let unpartitioned = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS (SELECT 1 FROM pg_class WHERE oid = to_regclass('audit_events') AND relkind = 'r')"
+    )
+    .fetch_one(pool)
+    .await?;
+    if unpartitioned {
+        sqlx::query("ALTER TABLE audit_events RENAME TO audit_events_unpartitioned")
+            .execute(pool)
+            .await?;
+        sqlx::query("ALTER TABLE audit_events_unpartitioned RENAME CONSTRAINT audit_events_pkey TO audit_events_unpartitioned_pkey")
+            .execute(pool)
+            .await?;
+        for index in ["idx_audit_events_occurred_at", "idx_audit_events_user_id", "idx_audit_events_actor_id"] {
+            sqlx::query(&format!("DROP INDEX IF EXISTS {}", index))
+                .execute(pool)
+                .await?;
+        }
+    }
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS audit_events (
+            id UUID NOT NULL,
+            occurred_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            event_type VARCHAR(64) NOT NULL,
+            user_id UUID NOT NULL,
+            actor_id UUID,
+            ip VARCHAR(45),
+            details JSONB NOT NULL DEFAULT '{}',
+            PRIMARY KEY (id, occurred_at)
+        ) PARTITION BY RANGE (occurred_at)
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("CREATE TABLE IF NOT EXISTS audit_events_default PARTITION OF audit_events DEFAULT")
+        .execute(pool)
+        .await?;
+
+    for (index, columns) in [
+        ("idx_audit_events_occurred_at", "occurred_at"),
+        ("idx_audit_events_user_id", "user_id, occurred_at"),
+        ("idx_audit_events_actor_id", "actor_id, occurred_at"),
+    ] {
+        sqlx::query(&format!("CREATE INDEX IF NOT EXISTS {} ON audit_events({})", index, columns))
+            .execute(pool)
+            .await?;
+    }
+
+    let mut conn = pool.acquire().await?;
+    let now = Utc::now();
+    let until = now + Months::new(audit::PARTITION_MONTHS_AHEAD);
+    let converting = sqlx::query_scalar::<_, bool>("SELECT to_regclass('audit_events_unpartitioned') IS NOT NULL")
+        .fetch_one(&mut *conn)
+        .await?;
+    if converting {
+        let oldest = sqlx::query_scalar::<_, Option<DateTime<Utc>>>("SELECT MIN(occurred_at) FROM audit_events_unpartitioned")
+            .fetch_one(&mut *conn)
+            .await?;
+        audit::ensure_partitions(&mut conn, oldest.unwrap_or(now).min(now), until).await?;
+        sqlx::query("INSERT INTO audit_events SELECT * FROM audit_events_unpartitioned ON CONFLICT DO NOTHING")
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query("DROP TABLE audit_events_unpartitioned")
+            .execute(&mut *conn)
+            .await?;
+    } else {
+        audit::ensure_partitions(&mut conn, now, until).await?;
+    }
+
+    // Set by the instance that forwarded the event to the audit sinks
+    sqlx::query("ALTER TABLE audit_events ADD COLUMN IF NOT EXISTS forwarded_at TIMESTAMP WITH TIME ZONE")
+        .execute(pool)
+        .await?;
+
+    // Events an audit sink still failed to take after every retry
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS audit_dead_letters (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            event_id UUID NOT NULL,
+            occurred_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            sink VARCHAR(32) NOT NULL,
+            attempts INTEGER NOT NULL,
+            last_error TEXT NOT NULL,
+            failed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_audit_dead_letters_event ON audit_dead_letters(event_id, sink)")
+        .execute(pool)
+        .await?;
+
+    // Client address of each login attempt, for progressive delays
+    sqlx::query("ALTER TABLE login_attempts ADD COLUMN IF NOT EXISTS ip VARCHAR(45)")
+        .execute(pool)
+        .await?;
+
+    // Addresses blocked from logging in after failing logins for many accounts;
+    // one row per address, kept after the block ends
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS ip_blocks (
+            ip VARCHAR(45) PRIMARY KEY,
+            blocked_until TIMESTAMP WITH TIME ZONE NOT NULL,
+            distinct_emails INTEGER NOT NULL,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_login_attempts_ip ON login_attempts(ip, created_at) WHERE NOT succeeded")
+        .execute(pool)
+        .await?;
+
+    // Set by the instance that sent the user's welcome email
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS welcome_email_sent_at TIMESTAMP WITH TIME ZONE")
+        .execute(pool)
+        .await?;
+
+    // Login challenges stepped up by a risk assessment, for users without an
+    // authenticator, are answered with an emailed code instead
+    sqlx::query("ALTER TABLE mfa_challenges ADD COLUMN IF NOT EXISTS email_code_hash VARCHAR(64)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS idx_login_attempts_user_succeeded ON login_attempts(user_id, created_at) WHERE succeeded",
+    )
+    .execute(pool)
+    .await?;
+
+    // Last time each kind of admin alert was sent, shared by all instances
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS admin_alerts (
+            key VARCHAR(255) PRIMARY KEY,
+            sent_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Countries admins have logged in from, for new-country alerts
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS login_countries (
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            country VARCHAR(8) NOT NULL,
+            first_seen_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (user_id, country)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_audit_dead_letters_failed_at ON audit_dead_letters(failed_at)")
+        .execute(pool)
+        .await?;
+
+    // Requests made with each API key, per UTC day, for usage reports and quotas
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS api_key_usage (
+            api_key_id UUID NOT NULL REFERENCES api_keys(id) ON DELETE CASCADE,
+            day DATE NOT NULL,
+            requests BIGINT NOT NULL DEFAULT 0,
+            PRIMARY KEY (api_key_id, day)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // The key that replaced a rotated API key
+    sqlx::query("ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS replaced_by UUID REFERENCES api_keys(id) ON DELETE SET NULL")
+        .execute(pool)
+        .await?;
+
+    // API keys bound to client address ranges (CIDR) or to another service's audience
+    sqlx::query("ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS allowed_ips TEXT[] NOT NULL DEFAULT '{}'")
+        .execute(pool)
+        .await?;
+    sqlx::query("ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS audience TEXT")
+        .execute(pool)
+        .await?;
+
+    // Roles within an organization: owner, admin or member
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS organization_role VARCHAR(20) NOT NULL DEFAULT 'member'")
+        .execute(pool)
+        .await?;
+
+    // Organizations from before roles existed are owned by their first member
+    sqlx::query(
+        r#"
+        UPDATE users SET organization_role = 'owner'
+        WHERE id IN (
+            SELECT DISTINCT ON (organization_id) id FROM users
+            WHERE organization_id IS NOT NULL ORDER BY organization_id, created_at
+        )
+        AND NOT EXISTS (
+            SELECT 1 FROM users owners
+            WHERE owners.organization_id = users.organization_id AND owners.organization_role = 'owner'
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Keys signing the tokens of one organization's members, so a leaked key only affects that organization
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS organization_signing_keys (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+            kid VARCHAR(64) UNIQUE NOT NULL,
+            secret VARCHAR(128) NOT NULL,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            revoked_at TIMESTAMP WITH TIME ZONE
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS idx_organization_signing_keys_org ON organization_signing_keys(organization_id, created_at)",
+    )
+    .execute(pool)
+    .await?;
+
+    // Billing: the user's Stripe customer and the status of their subscription, from Stripe's webhooks
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS stripe_customer_id VARCHAR(255)")
+        .execute(pool)
+        .await?;
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS subscription_status VARCHAR(32)")
+        .execute(pool)
+        .await?;
+    // Time of the webhook event the status came from, so late deliveries of older events are ignored
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS subscription_updated_at TIMESTAMP WITH TIME ZONE")
+        .execute(pool)
+        .await?;
+    sqlx::query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_users_stripe_customer ON users(stripe_customer_id) WHERE stripe_customer_id IS NOT NULL",
+    )
+    .execute(pool)
+    .await?;
+
+    // Subscription tiers, and the plans granted to users or to every member of an organization
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS plans (
+            name VARCHAR(50) PRIMARY KEY,
+            description TEXT,
+            rank INTEGER NOT NULL,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    for (name, rank, description) in plans::BUILT_IN {
+        sqlx::query("INSERT INTO plans (name, description, rank) VALUES ($1, $2, $3) ON CONFLICT (name) DO NOTHING")
+            .bind(name)
+            .bind(description)
+            .bind(rank)
+            .execute(pool)
+            .await?;
+    }
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS entitlements (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            plan VARCHAR(50) NOT NULL REFERENCES plans(name) ON DELETE CASCADE,
+            user_id UUID REFERENCES users(id) ON DELETE CASCADE,
+            organization_id UUID REFERENCES organizations(id) ON DELETE CASCADE,
+            starts_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            ends_at TIMESTAMP WITH TIME ZONE,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            CHECK ((user_id IS NULL) <> (organization_id IS NULL))
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_entitlements_user ON entitlements(user_id) WHERE user_id IS NOT NULL")
+        .execute(pool)
+        .await?;
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS idx_entitlements_organization ON entitlements(organization_id) WHERE organization_id IS NOT NULL",
+    )
+    .execute(pool)
+    .await?;
+
+    // Runtime settings admins can change without a redeploy, as JSON under a key
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS settings (
+            key VARCHAR(100) PRIMARY KEY,
+            value JSONB NOT NULL,
+            updated_by UUID REFERENCES users(id) ON DELETE SET NULL,
+            updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Organization the event's user belonged to when it happened, for organization audit logs.
+    // Events recorded before the column existed are tagged once with their user's current organization.
+    let tagged = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name = 'audit_events' AND column_name = 'organization_id')",
+    )
+    .fetch_one(pool)
+    .await?;
+    if !tagged {
+        sqlx::query("ALTER TABLE audit_events ADD COLUMN organization_id UUID")
+            .execute(pool)
+            .await?;
+        sqlx::query(
+            r#"
+            UPDATE audit_events SET organization_id = users.organization_id
+            FROM users WHERE users.id = audit_events.user_id AND users.organization_id IS NOT NULL
+            "#,
+        )
+        .execute(pool)
+        .await?;
+    }
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_audit_events_organization_id ON audit_events(organization_id, occurred_at)")
+        .execute(pool)
+        .await?;
+
+    // Roles organizations define beyond owner, admin and member, each allowed a set of org permissions
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS organization_roles (
+            organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+            name VARCHAR(50) NOT NULL,
+            permissions TEXT[] NOT NULL DEFAULT '{}',
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (organization_id, name)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    sqlx::query("ALTER TABLE users ALTER COLUMN organization_role TYPE VARCHAR(50)")
+        .execute(pool)
+        .await?;
+
+    // Invitations sent by an organization make the new user a member
+    sqlx::query("ALTER TABLE invitations ADD COLUMN IF NOT EXISTS organization_id UUID REFERENCES organizations(id) ON DELETE CASCADE")
+        .execute(pool)
+        .await?;
+    sqlx::query("ALTER TABLE invitations ADD COLUMN IF NOT EXISTS organization_role VARCHAR(50)")
+        .execute(pool)
+        .await?;
+
+    // Email domains organizations claim; once verified through DNS, people confirming an address there join
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS organization_domains (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+            domain VARCHAR(253) NOT NULL,
+            join_mode VARCHAR(20) NOT NULL DEFAULT 'auto',
+            verification_token VARCHAR(64) NOT NULL,
+            verified_at TIMESTAMP WITH TIME ZONE,
+            last_checked_at TIMESTAMP WITH TIME ZONE,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE (organization_id, domain)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    // Any number of organizations may claim a domain, but only one can verify it
+    sqlx::query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_organization_domains_verified ON organization_domains(domain) WHERE verified_at IS NOT NULL"
+    )
+    .execute(pool)
+    .await?;
+
+    // The sign-in provider an organization uses, and whether its members must sign in with it
+    sqlx::query("ALTER TABLE organizations ADD COLUMN IF NOT EXISTS sso_provider VARCHAR(50)")
+        .execute(pool)
+        .await?;
+    sqlx::query("ALTER TABLE organizations ADD COLUMN IF NOT EXISTS sso_enforced BOOLEAN NOT NULL DEFAULT FALSE")
+        .execute(pool)
+        .await?;
+
+    // Rules turning what a sign-in provider says about someone into roles and memberships
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS sso_mappings (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            provider VARCHAR(50) NOT NULL,
+            claim VARCHAR(255) NOT NULL,
+            value VARCHAR(255) NOT NULL,
+            role VARCHAR(20),
+            organization_id UUID REFERENCES organizations(id) ON DELETE CASCADE,
+            organization_role VARCHAR(50),
+            priority INTEGER NOT NULL DEFAULT 0,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_sso_mappings_provider ON sso_mappings(provider)")
+        .execute(pool)
+        .await?;
+
+    // The client a session was started from, parsed from its user agent, and where it was
+    for column in [
+        "user_agent VARCHAR(512)",
+        "browser VARCHAR(50)",
+        "os VARCHAR(50)",
+        "device_type VARCHAR(20)",
+        "city VARCHAR(100)",
+        "country VARCHAR(8)",
+    ] {
+        sqlx::query(&format!("ALTER TABLE sessions ADD COLUMN IF NOT EXISTS {}", column))
+            .execute(pool)
+            .await?;
+    }
+
+    // The country a session was last used from, for impossible travel detection
+    for column in [
+        "seen_country VARCHAR(8)",
+        "seen_at TIMESTAMP WITH TIME ZONE",
+        "travel_flagged_at TIMESTAMP WITH TIME ZONE",
+    ] {
+        sqlx::query(&format!("ALTER TABLE sessions ADD COLUMN IF NOT EXISTS {}", column))
+            .execute(pool)
+            .await?;
+    }
+
+    // Where the browser goes once an OAuth sign-in finishes
+    sqlx::query("ALTER TABLE oauth_states ADD COLUMN IF NOT EXISTS redirect_to TEXT")
+        .execute(pool)
+        .await?;
+
+    // Migration sets already applied, so cold starts can skip them (see `run_if_needed`)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS schema_fingerprints (
+            fingerprint CHAR(64) PRIMARY KEY,
+            applied_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
     println!("✓ Database migrations completed successfully");
     Ok(())
 }
+
+/// Serializes `run_if_needed` between instances starting at the same time
+const MIGRATION_LOCK: &str = "SELECT pg_advisory_lock(hashtext('schema_migrations'))";
+const MIGRATION_UNLOCK: &str = "SELECT pg_advisory_unlock(hashtext('schema_migrations'))";
+
+/// SHA-256 of this file, identifying the set of migrations built into the binary
+fn fingerprint() -> String {
+    hex::encode(Sha256::digest(include_str!("migrations.rs")))
+}
+
+async fn applied(conn: &mut PgConnection, fingerprint: &str) -> Result<bool, sqlx::Error> {
+    let recorded = sqlx::query_scalar::<_, bool>("SELECT to_regclass('schema_fingerprints') IS NOT NULL")
+        .fetch_one(&mut *conn)
+        .await?;
+    if !recorded {
+        return Ok(false);
+    }
+    sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM schema_fingerprints WHERE fingerprint = $1)")
+        .bind(fingerprint)
+        .fetch_one(&mut *conn)
+        .await
+}
+
+/// Check again under the lock, since another instance may have just migrated
+async fn migrate_unless_applied(pool: &PgPool, conn: &mut PgConnection, fingerprint: &str) -> Result<bool, sqlx::Error> {
+    if applied(conn, fingerprint).await? {
+        return Ok(false);
+    }
+    run_migrations(pool).await?;
+    sqlx::query("INSERT INTO schema_fingerprints (fingerprint) VALUES ($1) ON CONFLICT DO NOTHING")
+        .bind(fingerprint)
+        .execute(conn)
+        .await?;
+    Ok(true)
+}
+
+/// Run the migrations unless this build's have already been applied; returns whether they ran
+///
+/// For cold starts (serverless), where running every statement on each
+/// start is too slow: a fingerprint of the migrations is recorded once they
+/// ran, and later starts of the same build only check it. Concurrent starts
+/// wait for each other, so `pool` needs room for two connections. Audit log
+/// partitions for the coming months are still ensured, since the retention
+/// job may not get to run.
+pub async fn run_if_needed(pool: &PgPool) -> Result<bool, sqlx::Error> {
+    let fingerprint = fingerprint();
+    let mut conn = pool.acquire().await?;
+
+    let ran = if applied(&mut conn, &fingerprint).await? {
+        false
+    } else {
+        sqlx::query(MIGRATION_LOCK).execute(&mut *conn).await?;
+        let result = migrate_unless_applied(pool, &mut conn, &fingerprint).await;
+        sqlx::query(MIGRATION_UNLOCK).execute(&mut *conn).await?;
+        result?
+    };
+
+    let now = Utc::now();
+    audit::ensure_partitions(&mut conn, now, now + Months::new(audit::PARTITION_MONTHS_AHEAD)).await?;
+    Ok(ran)
+}
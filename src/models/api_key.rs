@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+use chrono::{DateTime, NaiveDate, Utc};
+
+/// A long-lived key acting for its user, limited to its scopes
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    /// First characters of the key, to tell keys apart
+    pub key_prefix: String,
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+    pub scopes: Vec<String>,
+    /// CIDR ranges the key may be used from; empty allows any address
+    pub allowed_ips: Vec<String>,
+    /// The service (`ROCKET_SERVICE_AUDIENCE`) the key only works at; `None` for the default deployment
+    pub audience: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    /// The key this one was rotated to; it works until `expires_at`
+    pub replaced_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ApiKey {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewApiKey {
+    pub name: String,
+    pub scopes: Vec<String>,
+    /// Days until the key expires; keys without one don't expire
+    pub expires_in_days: Option<i64>,
+    /// CIDR ranges (or single addresses) the key may be used from
+    #[serde(default)]
+    pub allowed_ips: Vec<String>,
+    /// Bind the key to the service with this `ROCKET_SERVICE_AUDIENCE`
+    pub audience: Option<String>,
+}
+
+/// Requests made with an API key on one UTC day
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ApiKeyUsage {
+    pub day: NaiveDate,
+    pub requests: i64,
+}
@@ -0,0 +1,89 @@
+use chrono::{DateTime, Utc};
+use rocket::form::FromForm;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// A security event as recorded in the audit log
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct AuditEvent {
+    pub id: Uuid,
+    pub occurred_at: DateTime<Utc>,
+    pub event_type: String,
+    /// Whose account the event is about
+    pub user_id: Uuid,
+    /// Who caused it; `None` for the system and the admin CLI
+    pub actor_id: Option<Uuid>,
+    pub ip: Option<String>,
+    /// The event's own fields, e.g. `session_id`
+    pub details: serde_json::Value,
+    /// Organization the user belonged to when it happened
+    pub organization_id: Option<Uuid>,
+}
+
+/// A delivery to an audit sink that failed every attempt
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct AuditDeadLetter {
+    pub id: Uuid,
+    pub event_id: Uuid,
+    /// When the event itself occurred
+    pub occurred_at: DateTime<Utc>,
+    pub sink: String,
+    pub attempts: i32,
+    pub last_error: String,
+    pub failed_at: DateTime<Utc>,
+}
+
+/// Dead letters waiting on one sink
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct DeadLetterCount {
+    pub sink: String,
+    pub count: i64,
+}
+
+/// Which audit events to return; unset fields match every event
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub actor_id: Option<Uuid>,
+    pub user_id: Option<Uuid>,
+    pub event_type: Option<String>,
+    pub ip: Option<String>,
+    pub organization_id: Option<Uuid>,
+    /// Inclusive
+    pub from: Option<DateTime<Utc>>,
+    /// Exclusive
+    pub to: Option<DateTime<Utc>>,
+}
+
+/// Query string of the audit log endpoints, as sent
+#[derive(Debug, FromForm)]
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    pub user: Option<String>,
+    /// Organization id; only the admin audit log takes it
+    pub org: Option<String>,
+    #[field(name = "type")]
+    pub event_type: Option<String>,
+    pub ip: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    /// `json` (default) for a page, `ndjson` to export every match
+    pub format: Option<String>,
+}
+
+/// Request to re-deliver stored events to an audit sink
+///
+/// Either `ids`, or a time range starting at `from`, selects the events.
+#[derive(Debug, Deserialize)]
+pub struct AuditReplayRequest {
+    /// Name of a configured sink: `stdout`, `syslog` or `http`
+    pub sink: String,
+    #[serde(default)]
+    pub ids: Vec<Uuid>,
+    /// Inclusive; a date or RFC 3339 timestamp
+    pub from: Option<String>,
+    /// Exclusive; a date or RFC 3339 timestamp
+    pub to: Option<String>,
+}
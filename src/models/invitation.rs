@@ -0,0 +1,31 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+
+/// A code that allows one registration in invite-only mode
+///
+/// Issued by admins, or by an organization to bring someone into it.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Invitation {
+    pub id: Uuid,
+    pub code: String,
+    /// When set, only this address can use the invitation
+    pub email: Option<String>,
+    pub created_by: Option<Uuid>,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+    pub used_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    /// The organization the invited user joins
+    pub organization_id: Option<Uuid>,
+    /// Their role there: `admin`, `member` or a custom role
+    pub organization_role: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewInvitation {
+    pub email: Option<String>,
+    /// Days until the invitation expires (default 7)
+    pub expires_in_days: Option<i64>,
+}
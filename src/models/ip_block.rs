@@ -0,0 +1,19 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+/// A client address refused logins after failing them for many different accounts
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct IpBlock {
+    pub ip: String,
+    pub blocked_until: DateTime<Utc>,
+    /// Distinct emails the address failed to log in to when it was blocked
+    pub distinct_emails: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+impl IpBlock {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.blocked_until > now
+    }
+}
@@ -0,0 +1,84 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use uuid::Uuid;
+use sqlx::FromRow;
+
+/// A user's TOTP secret; `enabled_at` stays unset until a first code confirms enrollment
+#[derive(Debug, Clone, FromRow)]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub enabled_at: Option<DateTime<Utc>>,
+    /// Time step of the last accepted code; codes from it or earlier are refused
+    pub last_step: Option<i64>,
+}
+
+/// A pending second login step
+#[derive(Debug, Clone, FromRow)]
+pub struct LoginChallenge {
+    pub user_id: Uuid,
+    /// Set when the code was emailed, for users without an authenticator
+    pub email_code_hash: Option<String>,
+}
+
+/// A code from the user's authenticator app
+#[derive(Debug, Deserialize)]
+pub struct TotpCode {
+    pub code: String,
+}
+
+/// Second step of a login for users with MFA
+#[derive(Debug, Deserialize)]
+pub struct MfaLogin {
+    /// From the password step's response
+    pub mfa_token: String,
+    pub code: String,
+}
+
+/// Start MFA recovery from a password login, for users who lost their authenticator
+#[derive(Debug, Deserialize)]
+pub struct MfaRecoveryRequest {
+    /// From the password step's response
+    pub mfa_token: String,
+}
+
+/// Admin removal of a user's MFA; the reason is kept in the audit trail
+#[derive(Debug, Deserialize)]
+pub struct MfaReset {
+    pub reason: String,
+}
+
+/// A request to remove a user's MFA, requested by email or done by an admin
+///
+/// Email requests are confirmed through a link, then wait until `available_at`
+/// before MFA is removed at the next password login. Rows are never deleted.
+#[derive(Debug, Clone, FromRow)]
+pub struct MfaRecovery {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    /// Set when an admin removed MFA directly
+    pub admin_id: Option<Uuid>,
+    pub reason: Option<String>,
+    pub requested_at: DateTime<Utc>,
+    pub link_expires_at: DateTime<Utc>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+    pub available_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub cancelled_at: Option<DateTime<Utc>>,
+}
+
+impl MfaRecovery {
+    /// `completed`, `cancelled`, `scheduled`, `pending_confirmation` or `expired`
+    pub fn status(&self) -> &'static str {
+        if self.completed_at.is_some() {
+            "completed"
+        } else if self.cancelled_at.is_some() {
+            "cancelled"
+        } else if self.confirmed_at.is_some() {
+            "scheduled"
+        } else if self.link_expires_at > Utc::now() {
+            "pending_confirmation"
+        } else {
+            "expired"
+        }
+    }
+}
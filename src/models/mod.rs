@@ -1,2 +1,19 @@
 pub mod user;
 pub mod password_reset;
+pub mod session;
+pub mod email_verification;
+pub mod qr_login;
+pub mod stats;
+pub mod invitation;
+pub mod user_email;
+pub mod api_key;
+pub mod permission;
+pub mod oauth;
+pub mod oauth_client;
+pub mod mfa;
+pub mod audit;
+pub mod ip_block;
+pub mod organization;
+pub mod plan;
+pub mod setting;
+pub mod sso_mapping;
@@ -0,0 +1,40 @@
+use serde::Deserialize;
+use sqlx::FromRow;
+
+#[derive(Debug, Deserialize)]
+pub struct GoogleIdTokenLogin {
+    /// The `credential` from One Tap or the ID token from a mobile SDK
+    pub id_token: String,
+}
+
+/// What the provider redirected the browser back to the frontend with
+#[derive(Debug, Deserialize)]
+pub struct OAuthCallback {
+    pub code: String,
+    pub state: String,
+}
+
+/// A pending sign-in at an OAuth provider, kept under the hash of its `state`
+#[derive(Debug, Clone, FromRow)]
+pub struct OAuthState {
+    /// PKCE verifier whose challenge went to the provider
+    pub code_verifier: String,
+    /// Expected `nonce` claim of the returned ID token
+    pub nonce: String,
+    /// Allowed URL the sign-in was started with, for the frontend to go to afterwards
+    pub redirect_to: Option<String>,
+}
+
+/// An OAuth sign-in of a new account the provider gave no email for
+#[derive(Debug, Clone, FromRow)]
+pub struct OAuthCompletion {
+    pub issuer: String,
+    pub subject: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CompleteOAuthSignIn {
+    /// From the callback's `email_required` response
+    pub completion_token: String,
+    pub email: String,
+}
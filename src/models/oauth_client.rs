@@ -0,0 +1,149 @@
+use rocket::form::FromForm;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+
+/// An application that can act for users who consent to it
+///
+/// Confidential clients authenticate at the token endpoint with their
+/// secret, which is only stored hashed. Public clients (SPAs, mobile apps)
+/// have none and must use PKCE.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct OAuthClient {
+    pub id: Uuid,
+    /// Public identifier used in authorization requests
+    pub client_id: String,
+    #[serde(skip_serializing)]
+    pub client_secret_hash: Option<String>,
+    pub name: String,
+    /// Exact URIs codes may be sent back to
+    pub redirect_uris: Vec<String>,
+    /// Scopes the client may ask users for
+    pub scopes: Vec<String>,
+    pub logo_url: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl OAuthClient {
+    pub fn is_confidential(&self) -> bool {
+        self.client_secret_hash.is_some()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewOAuthClient {
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub scopes: Vec<String>,
+    #[serde(default)]
+    pub logo_url: Option<String>,
+    /// Public clients get no secret; defaults to confidential
+    #[serde(default = "confidential_by_default")]
+    pub confidential: bool,
+}
+
+fn confidential_by_default() -> bool {
+    true
+}
+
+/// Fields to change; omitted ones are kept and an empty `logo_url` removes the logo
+#[derive(Debug, Deserialize)]
+pub struct OAuthClientUpdate {
+    pub name: Option<String>,
+    pub redirect_uris: Option<Vec<String>>,
+    pub scopes: Option<Vec<String>>,
+    pub logo_url: Option<String>,
+}
+
+/// Client metadata sent to the dynamic registration endpoint (RFC 7591)
+#[derive(Debug, Deserialize)]
+pub struct ClientRegistration {
+    pub redirect_uris: Vec<String>,
+    pub client_name: Option<String>,
+    pub logo_uri: Option<String>,
+    /// Space-separated; defaults to `openid`
+    pub scope: Option<String>,
+    /// `client_secret_basic` (default), `client_secret_post`, or `none` for public clients
+    pub token_endpoint_auth_method: Option<String>,
+    pub grant_types: Option<Vec<String>>,
+    pub response_types: Option<Vec<String>>,
+}
+
+/// An application a user has authorized, as listed to that user
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct AuthorizedApplication {
+    pub client_id: String,
+    pub name: String,
+    pub logo_url: Option<String>,
+    /// Scopes the user granted
+    pub scopes: Vec<String>,
+    /// When the user first authorized it
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// An authorization code waiting to be exchanged for an access token
+#[derive(Debug, Clone, FromRow)]
+pub struct AuthorizationCode {
+    pub user_id: Uuid,
+    pub redirect_uri: String,
+    pub scopes: Vec<String>,
+    pub code_challenge: Option<String>,
+}
+
+/// The OAuth 2.0 authorization request, as the client sent it to the consent page
+#[derive(Debug, Clone, Deserialize, FromForm)]
+pub struct AuthorizationParams {
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub response_type: String,
+    /// Space-separated; defaults to every scope the client may ask for
+    #[serde(default)]
+    pub scope: Option<String>,
+    #[serde(default)]
+    pub state: Option<String>,
+    #[serde(default)]
+    pub code_challenge: Option<String>,
+    #[serde(default)]
+    pub code_challenge_method: Option<String>,
+}
+
+/// The user's answer on the consent page
+#[derive(Debug, Deserialize)]
+pub struct AuthorizationDecision {
+    #[serde(flatten)]
+    pub request: AuthorizationParams,
+    pub approve: bool,
+}
+
+/// A token endpoint request (`application/x-www-form-urlencoded`)
+#[derive(Debug, FromForm)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub code_verifier: Option<String>,
+    /// Client credentials, for clients that don't use HTTP Basic
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    /// Token exchange (RFC 8693): the user's access token and what it is
+    pub subject_token: Option<String>,
+    pub subject_token_type: Option<String>,
+    /// Token exchange: the internal service the new token is for
+    pub audience: Option<String>,
+    /// Token exchange: space-separated scopes to narrow the new token to
+    pub scope: Option<String>,
+    pub requested_token_type: Option<String>,
+}
+
+/// A token revocation request (RFC 7009, `application/x-www-form-urlencoded`)
+#[derive(Debug, FromForm)]
+pub struct RevocationRequest {
+    pub token: String,
+    /// `access_token` or `refresh_token`; only a hint, every kind is looked up
+    pub token_type_hint: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
@@ -0,0 +1,166 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// What a member may do in their organization
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrgRole {
+    Owner,
+    Admin,
+    Member,
+}
+
+impl OrgRole {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "owner" => Some(OrgRole::Owner),
+            "admin" => Some(OrgRole::Admin),
+            "member" => Some(OrgRole::Member),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrgRole::Owner => "owner",
+            OrgRole::Admin => "admin",
+            OrgRole::Member => "member",
+        }
+    }
+
+    /// Owners and admins manage the organization
+    pub fn is_admin(&self) -> bool {
+        matches!(self, OrgRole::Owner | OrgRole::Admin)
+    }
+}
+
+/// A role an organization defined, allowing its members some of the org permissions
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct CustomOrgRole {
+    #[serde(skip_serializing)]
+    pub organization_id: Uuid,
+    pub name: String,
+    /// Names from `org_permissions::ALL`
+    pub permissions: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Body for defining a custom role
+#[derive(Debug, Deserialize)]
+pub struct NewOrgRole {
+    pub name: String,
+    pub permissions: Vec<String>,
+}
+
+/// Body for changing what a custom role allows
+#[derive(Debug, Deserialize)]
+pub struct OrgRoleUpdate {
+    pub permissions: Vec<String>,
+}
+
+/// Body for giving a member another role
+#[derive(Debug, Deserialize)]
+pub struct MemberRoleUpdate {
+    pub role: String,
+}
+
+/// Body for inviting someone to an organization
+#[derive(Debug, Deserialize)]
+pub struct NewOrgInvitation {
+    pub email: String,
+    /// `admin`, `member` (default) or a custom role
+    pub role: Option<String>,
+    /// Days until the invitation expires (default 7)
+    pub expires_in_days: Option<i64>,
+}
+
+/// What happens to people confirming an address at an organization's verified domain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DomainJoinMode {
+    /// They become members straight away
+    Auto,
+    /// They're emailed an invitation to accept
+    Invite,
+}
+
+impl DomainJoinMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "auto" => Some(DomainJoinMode::Auto),
+            "invite" => Some(DomainJoinMode::Invite),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DomainJoinMode::Auto => "auto",
+            DomainJoinMode::Invite => "invite",
+        }
+    }
+}
+
+/// An email domain an organization claims, verified with a DNS TXT record
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct OrgDomain {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    /// Lowercase, without a trailing dot
+    pub domain: String,
+    /// `auto` or `invite`, see `DomainJoinMode`
+    pub join_mode: String,
+    /// Published as `auth-verification=<token>` to prove ownership
+    pub verification_token: String,
+    pub verified_at: Option<DateTime<Utc>>,
+    pub last_checked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Body for claiming a domain
+#[derive(Debug, Deserialize)]
+pub struct NewOrgDomain {
+    pub domain: String,
+    /// Default `auto`
+    pub join_mode: Option<DomainJoinMode>,
+}
+
+/// The sign-in provider an organization uses
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct OrgSso {
+    /// A configured provider: `google`, `microsoft`, `discord`, `facebook` or one from `ROCKET_OAUTH_PROVIDERS`
+    pub sso_provider: Option<String>,
+    /// Members may only sign in with `sso_provider`
+    pub sso_enforced: bool,
+}
+
+/// Body for setting an organization's sign-in provider
+#[derive(Debug, Deserialize)]
+pub struct OrgSsoUpdate {
+    pub provider: Option<String>,
+    #[serde(default)]
+    pub enforced: bool,
+}
+
+/// An organization whose members must sign in with `provider`
+#[derive(Debug, Clone, FromRow)]
+pub struct SsoRequirement {
+    pub organization_id: Uuid,
+    pub provider: String,
+}
+
+/// A key signing the access tokens of one organization's members
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct OrgSigningKey {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    /// Sent as the token header's `kid`
+    pub kid: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
@@ -7,7 +7,8 @@ use chrono::{DateTime, Utc};
 pub struct PasswordResetToken {
     pub id: Uuid,
     pub user_id: Uuid,
-    pub token: String,
+    /// SHA-256 of the token; the token itself is only in the email
+    pub token_hash: String,
     pub expires_at: DateTime<Utc>,
     pub used: bool,
     pub created_at: DateTime<Utc>,
@@ -0,0 +1,48 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+
+/// A subscription tier; higher ranks include lower ones
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Plan {
+    pub name: String,
+    pub description: Option<String>,
+    pub rank: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewPlan {
+    pub name: String,
+    pub description: Option<String>,
+    pub rank: i32,
+}
+
+/// A plan granted to a user or to every member of an organization, for a period
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Entitlement {
+    pub id: Uuid,
+    pub plan: String,
+    pub user_id: Option<Uuid>,
+    pub organization_id: Option<Uuid>,
+    pub starts_at: DateTime<Utc>,
+    /// `None` for entitlements that don't end
+    pub ends_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Grant of a plan to exactly one of a user or an organization
+#[derive(Debug, Deserialize)]
+pub struct NewEntitlement {
+    pub plan: String,
+    #[serde(default)]
+    pub user_id: Option<Uuid>,
+    #[serde(default)]
+    pub organization_id: Option<Uuid>,
+    /// Defaults to now
+    #[serde(default)]
+    pub starts_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub ends_at: Option<DateTime<Utc>>,
+}
@@ -0,0 +1,31 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+
+/// A pending cross-device login, shown as a QR code on the device signing in
+///
+/// `code` goes into the QR code and is approved by an already signed-in device;
+/// `poll_token` stays on the requesting device and is exchanged for the JWT.
+/// Only its hash is stored.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct QrLoginRequest {
+    pub id: Uuid,
+    pub code: String,
+    pub poll_token_hash: String,
+    pub approved_user_id: Option<Uuid>,
+    pub approved_at: Option<DateTime<Utc>>,
+    pub consumed_at: Option<DateTime<Utc>>,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ApproveQrLogin {
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PollQrLogin {
+    pub poll_token: String,
+}
@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+
+/// A login session; every access token is tied to one via its `sid` claim
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Session {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    /// The OAuth client the session was granted to; `None` for the user's own logins
+    pub oauth_client_id: Option<Uuid>,
+    /// Scopes an OAuth client's session is limited to
+    pub scopes: Option<Vec<String>>,
+    /// Internal service a token exchanged for it is meant for; such sessions can't call this API
+    pub audience: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// Updated at most once a minute; sessions idle for longer than the idle timeout expire
+    pub last_used_at: DateTime<Utc>,
+    /// Absolute end of the session, however active it is
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub device: SessionDevice,
+}
+
+/// What the request starting a session told about the client, see `devices`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, FromRow)]
+pub struct SessionDevice {
+    pub user_agent: Option<String>,
+    /// Parsed from `user_agent`, e.g. `Chrome`
+    pub browser: Option<String>,
+    /// Parsed from `user_agent`, e.g. `macOS`
+    pub os: Option<String>,
+    /// `desktop`, `mobile`, `tablet`, `bot` or `other`
+    pub device_type: Option<String>,
+    /// From the `ROCKET_GEO_CITY_HEADER` header
+    pub city: Option<String>,
+    /// From the `ROCKET_GEO_COUNTRY_HEADER` header
+    pub country: Option<String>,
+}
+
+/// Where a session was used from before the current request
+#[derive(Debug, Clone, FromRow)]
+pub struct CountrySighting {
+    /// `None` if the country was never known
+    pub country: Option<String>,
+    pub seen_at: DateTime<Utc>,
+    /// Set once the session has been flagged for impossible travel
+    pub flagged_at: Option<DateTime<Utc>>,
+}
+
+/// What an OAuth client's session is limited to
+pub struct ClientGrant<'a> {
+    pub oauth_client_id: Uuid,
+    pub scopes: &'a [String],
+    /// Set for tokens exchanged for another service (RFC 8693)
+    pub audience: Option<&'a str>,
+    pub expires_at: DateTime<Utc>,
+}
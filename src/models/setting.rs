@@ -0,0 +1,14 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+
+/// A runtime setting stored by an admin, overriding the deployment's configuration
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct StoredSetting {
+    pub key: String,
+    pub value: serde_json::Value,
+    /// The admin who last changed it; `None` once their account is deleted
+    pub updated_by: Option<Uuid>,
+    pub updated_at: DateTime<Utc>,
+}
@@ -0,0 +1,38 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// A rule giving people a role or membership when a sign-in provider says they have some claim
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct SsoMapping {
+    pub id: Uuid,
+    /// A configured provider, as in `OrgSso::sso_provider`
+    pub provider: String,
+    /// Dotted path into the provider's profile, such as `groups` or `org.team`
+    pub claim: String,
+    /// Matches the claim when equal to it, or to one of its items when it's a list
+    pub value: String,
+    /// Platform role (`users.role`) to give
+    pub role: Option<String>,
+    /// Organization to put the user in
+    pub organization_id: Option<Uuid>,
+    /// Their role there: `admin`, `member` or one the organization defined
+    pub organization_role: Option<String>,
+    /// Higher wins when several rules match
+    pub priority: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Body for adding a mapping rule
+#[derive(Debug, Deserialize)]
+pub struct NewSsoMapping {
+    pub claim: String,
+    pub value: String,
+    pub role: Option<String>,
+    pub organization_id: Option<Uuid>,
+    /// Default `member`
+    pub organization_role: Option<String>,
+    #[serde(default)]
+    pub priority: i32,
+}
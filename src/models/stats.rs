@@ -0,0 +1,35 @@
+use serde::Serialize;
+use sqlx::FromRow;
+use chrono::NaiveDate;
+
+/// Account counts across the whole user base
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct UserTotals {
+    pub total: i64,
+    pub verified: i64,
+    pub admins: i64,
+    pub guests: i64,
+    pub pending_approval: i64,
+}
+
+/// Number of events on one day (UTC)
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct DailyCount {
+    pub day: NaiveDate,
+    pub count: i64,
+}
+
+/// Password login outcomes on one day (UTC)
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct DailyLogins {
+    pub day: NaiveDate,
+    pub succeeded: i64,
+    pub failed: i64,
+}
+
+/// Sessions that are neither revoked nor expired
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct SessionStats {
+    pub active_sessions: i64,
+    pub users_with_active_sessions: i64,
+}
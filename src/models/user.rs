@@ -8,14 +8,125 @@ pub struct User {
     pub id: Uuid,
     pub email: String,
     pub password_hash: String,
+    pub role: String,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    /// `approved`, `pending_approval` or `rejected`
+    pub approval_status: String,
+    /// Admin's reason for the approval decision
+    pub approval_reason: Option<String>,
+    pub approval_decided_at: Option<DateTime<Utc>>,
+    /// Restricts the user to changing their password until they do
+    pub must_change_password: bool,
+    /// Profile data the user can edit themselves
+    pub user_metadata: serde_json::Value,
+    /// Data only admins can edit (plan, feature flags, …); optionally embedded in tokens
+    pub app_metadata: serde_json::Value,
+    /// Set for users who signed in through a Microsoft Entra directory
+    pub organization_id: Option<Uuid>,
+    /// `owner`, `admin`, `member` or a custom role of the organization; only meaningful with `organization_id`
+    pub organization_role: String,
+    /// The user's customer in Stripe, when billing is on
+    pub stripe_customer_id: Option<String>,
+    /// Status of the user's Stripe subscription (`active`, `trialing`, `past_due`, `canceled`, …); `None` without one
+    pub subscription_status: Option<String>,
+    /// Embedded in the user's JWTs; bumping it invalidates every token issued before
+    pub token_version: i32,
+    /// Set once the user confirmed a TOTP authenticator; logins then need a code
+    pub totp_enabled_at: Option<DateTime<Utc>>,
+    /// When MFA became required for the user's role; enrollment is due a grace period later
+    pub mfa_required_since: Option<DateTime<Utc>>,
+    /// Preferred language as a BCP 47 tag, e.g. `pt-BR`
+    pub locale: Option<String>,
+    /// IANA time zone, e.g. `Europe/Berlin`; times in emails are shown in it
+    pub timezone: Option<String>,
+    pub last_login_at: Option<DateTime<Utc>>,
+    /// Client address of the last successful login
+    pub last_login_ip: Option<String>,
+    /// Successful logins so far
+    pub login_count: i32,
+    /// When the user was warned that the inactivity policy is about to apply
+    pub inactivity_warned_at: Option<DateTime<Utc>>,
+    /// Set by the inactivity policy; cleared when the user logs in again
+    pub flagged_inactive_at: Option<DateTime<Utc>>,
+    /// Set by the inactivity policy; logins are refused until an admin reactivates the account
+    pub disabled_at: Option<DateTime<Utc>>,
+    /// Last reactivation by an admin, which restarts the inactivity clock
+    pub reactivated_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// An external account (Google, Microsoft, …) linked to a user for signing in
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct LinkedAccount {
+    pub id: Uuid,
+    /// Issuer of the external account, e.g. `https://accounts.google.com`
+    pub issuer: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// What the auth guard checks about a user on every request
+///
+/// Carried in tokens as the `standing` claim under stateless verification.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct AccountStanding {
+    pub role: String,
+    pub must_change_password: bool,
+    #[serde(with = "chrono::serde::ts_seconds_option")]
+    pub totp_enabled_at: Option<DateTime<Utc>>,
+    #[serde(with = "chrono::serde::ts_seconds_option")]
+    pub mfa_required_since: Option<DateTime<Utc>>,
+}
+
+impl From<&User> for AccountStanding {
+    fn from(user: &User) -> Self {
+        AccountStanding {
+            role: user.role.clone(),
+            must_change_password: user.must_change_password,
+            totp_enabled_at: user.totp_enabled_at,
+            mfa_required_since: user.mfa_required_since,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct NewUser {
     pub email: String,
     pub password: String,
+    /// Required when registration is invite-only; an organization's invitation joins it
+    #[serde(default)]
+    pub invitation_code: Option<String>,
+    #[serde(default)]
+    pub locale: Option<String>,
+    #[serde(default)]
+    pub timezone: Option<String>,
+    /// Where the verification link sends the browser once followed, see `redirects`
+    #[serde(default)]
+    pub redirect_to: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangeEmail {
+    pub new_email: String,
+    /// Current password, re-checked before a confirmation is sent
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmPassword {
+    pub password: String,
+}
+
+/// A single-use action token from a confirmation email
+#[derive(Debug, Deserialize)]
+pub struct ConfirmAction {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePassword {
+    pub current_password: String,
+    pub new_password: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -25,13 +136,47 @@ pub struct LoginUser {
 }
 
 impl User {
-    /// Hash a password using bcrypt
-    pub fn hash_password(password: &str) -> Result<String, bcrypt::BcryptError> {
-        bcrypt::hash(password, bcrypt::DEFAULT_COST)
+    /// Signup is waiting for an admin's decision
+    pub fn is_pending_approval(&self) -> bool {
+        self.approval_status == "pending_approval"
     }
 
-    /// Verify a password against a hash
-    pub fn verify_password(password: &str, hash: &str) -> Result<bool, bcrypt::BcryptError> {
-        bcrypt::verify(password, hash)
+    /// Anonymous guest account that hasn't been upgraded yet
+    pub fn is_guest(&self) -> bool {
+        self.role == "guest"
     }
 }
+
+/// Admin decision on a pending signup
+#[derive(Debug, Deserialize)]
+pub struct SignupDecision {
+    pub reason: Option<String>,
+}
+
+/// Admin toggle for `must_change_password`
+#[derive(Debug, Deserialize)]
+pub struct MustChangePasswordUpdate {
+    pub enabled: bool,
+}
+
+/// Top-level merge into `user_metadata`; a `null` value removes the key
+///
+/// `locale` and `timezone` replace the stored preference when present.
+#[derive(Debug, Deserialize)]
+pub struct UserMetadataPatch {
+    #[serde(default)]
+    pub user_metadata: Option<serde_json::Value>,
+    #[serde(default)]
+    pub locale: Option<String>,
+    #[serde(default)]
+    pub timezone: Option<String>,
+}
+
+/// Admin patch for either kind of metadata, with the same merge rules
+#[derive(Debug, Deserialize)]
+pub struct MetadataPatch {
+    #[serde(default)]
+    pub user_metadata: Option<serde_json::Value>,
+    #[serde(default)]
+    pub app_metadata: Option<serde_json::Value>,
+}
@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+
+/// A secondary email address; the primary one lives in `users.email`
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct UserEmail {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub email: String,
+    pub verified_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewUserEmail {
+    pub email: String,
+}
@@ -0,0 +1,98 @@
+use std::convert::Infallible;
+
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::content::RawHtml;
+use rocket::response::status;
+use rocket::serde::json::{Json, Value};
+use tera::{Context, Tera};
+
+use crate::config::AppConfig;
+
+/// Server-rendered pages for links opened in a browser, such as email verification
+///
+/// The templates live in `templates/` and are built into the binary, so the
+/// server doesn't need them on disk. Names end in `.html` to get autoescaping.
+pub struct Pages {
+    tera: Tera,
+}
+
+impl Pages {
+    pub fn new() -> Self {
+        let mut tera = Tera::default();
+        tera.add_raw_templates([
+            ("base.html", include_str!("../templates/base.html.tera")),
+            ("success.html", include_str!("../templates/success.html.tera")),
+            ("error.html", include_str!("../templates/error.html.tera")),
+        ])
+        .expect("Failed to load page templates");
+        Pages { tera }
+    }
+
+    /// Answer with `outcome` as JSON, or as a page for browsers, titled `title` on success
+    ///
+    /// Pages keep the status code and show the JSON's `message`, or its `error`
+    /// for failures, with a link on to the frontend.
+    pub fn answer(
+        &self,
+        config: &AppConfig,
+        format: Format,
+        title: &str,
+        outcome: Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>>,
+    ) -> Answer {
+        let (template, status::Custom(status, Json(body))) = match outcome {
+            Ok(response) => ("success.html", response),
+            Err(response) => ("error.html", response),
+        };
+        if format == Format::Json {
+            return Answer::Json(status::Custom(status, Json(body)));
+        }
+
+        let message = body["message"].as_str().or(body["error"].as_str()).unwrap_or_default();
+        let title = match status.code {
+            500.. => "Something went wrong",
+            400.. => "This link didn't work",
+            _ => title,
+        };
+        let mut context = Context::new();
+        context.insert("title", title);
+        context.insert("message", message);
+        context.insert("frontend_url", &config.frontend_url);
+        match self.tera.render(template, &context) {
+            Ok(html) => Answer::Page(status::Custom(status, RawHtml(html))),
+            Err(e) => {
+                eprintln!("Failed to render {}: {}", template, e);
+                Answer::Json(status::Custom(status, Json(body)))
+            }
+        }
+    }
+}
+
+impl Default for Pages {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How the client wants an answer: a page when a browser prefers HTML, JSON otherwise
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Html,
+    Json,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Format {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let html = request.accept().is_some_and(|accept| accept.preferred().media_type().is_html());
+        Outcome::Success(if html { Format::Html } else { Format::Json })
+    }
+}
+
+/// A JSON body or a rendered page, with the same status
+#[derive(Responder)]
+pub enum Answer {
+    Json(status::Custom<Json<Value>>),
+    Page(status::Custom<RawHtml<String>>),
+}
@@ -0,0 +1,448 @@
+use std::net::IpAddr;
+
+use chrono::{DateTime, Datelike, Duration, Months, NaiveDate, Utc};
+use rocket::http::{Header, Status};
+use rocket::request::{Outcome, Request};
+use rocket::response::{self, status, Responder};
+use rocket::serde::json::{Json, Value, json};
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::cache::SharedCache;
+use crate::config::AppConfig;
+use crate::events::{self, SecurityEvent, SecurityEventKind};
+use crate::models::ip_block::IpBlock;
+use crate::repositories::{api_keys, ip_blocks, login_attempts};
+use crate::settings::Settings;
+
+/// Response headers describing a limit, exposed to browser clients through CORS
+pub const HEADERS: [&str; 4] = ["Retry-After", "X-RateLimit-Limit", "X-RateLimit-Remaining", "X-RateLimit-Reset"];
+
+/// Where a client stands against a limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub limit: u32,
+    pub remaining: u32,
+    /// When `remaining` goes back up
+    pub reset_at: DateTime<Utc>,
+}
+
+impl RateLimit {
+    pub fn is_exceeded(&self) -> bool {
+        self.remaining == 0
+    }
+
+    /// Whole seconds until `reset_at`, at least 1
+    pub fn retry_after_seconds(&self) -> i64 {
+        let millis = (self.reset_at - Utc::now()).num_milliseconds();
+        ((millis + 999) / 1000).max(1)
+    }
+}
+
+/// A response that may carry rate limit headers
+///
+/// Every throttled response goes through here, so clients always get
+/// `X-RateLimit-Limit`, `X-RateLimit-Remaining` and `X-RateLimit-Reset` (Unix
+/// seconds), plus `Retry-After` (seconds) on `429`. Handlers returning
+/// `status::Custom<Json<Value>>` errors can use `?` on them as usual.
+pub struct RateLimited<R> {
+    response: R,
+    limit: Option<RateLimit>,
+}
+
+impl<R> RateLimited<R> {
+    pub fn new(response: R, limit: RateLimit) -> Self {
+        RateLimited {
+            response,
+            limit: Some(limit),
+        }
+    }
+}
+
+impl<R> From<R> for RateLimited<R> {
+    fn from(response: R) -> Self {
+        RateLimited { response, limit: None }
+    }
+}
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for RateLimited<R> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'o> {
+        let mut response = self.response.respond_to(request)?;
+        if let Some(limit) = self.limit {
+            if response.status() == Status::TooManyRequests {
+                response.set_header(Header::new("Retry-After", limit.retry_after_seconds().to_string()));
+            }
+            response.set_header(Header::new("X-RateLimit-Limit", limit.limit.to_string()));
+            response.set_header(Header::new("X-RateLimit-Remaining", limit.remaining.to_string()));
+            response.set_header(Header::new("X-RateLimit-Reset", limit.reset_at.timestamp().to_string()));
+        }
+        Ok(response)
+    }
+}
+
+/// `429 Too Many Requests` for an exceeded limit, with its headers
+pub fn throttled(limit: RateLimit, message: &str, code: &str) -> RateLimited<status::Custom<Json<Value>>> {
+    RateLimited::new(
+        status::Custom(
+            Status::TooManyRequests,
+            Json(json!({
+                "error": message,
+                "code": code,
+                "retry_after": limit.retry_after_seconds()
+            })),
+        ),
+        limit,
+    )
+}
+
+/// A limit a guard refused a request for, cached on the request for the catcher
+#[derive(Debug, Clone, Copy)]
+struct Refusal {
+    limit: RateLimit,
+    message: &'static str,
+    code: &'static str,
+}
+
+/// Refuse a request from a guard with `429 Too Many Requests`, answered by [`too_many_requests`]
+pub(crate) fn refuse<T>(request: &Request<'_>, limit: RateLimit, message: &'static str, code: &'static str) -> Outcome<T, ()> {
+    request.local_cache(|| Some(Refusal { limit, message, code }));
+    Outcome::Error((Status::TooManyRequests, ()))
+}
+
+/// JSON body and headers for 429 responses from guards
+#[catch(429)]
+pub fn too_many_requests(request: &Request) -> Result<RateLimited<status::Custom<Json<Value>>>, status::Custom<Json<Value>>> {
+    match *request.local_cache(|| None::<Refusal>) {
+        Some(refusal) => Ok(throttled(refusal.limit, refusal.message, refusal.code)),
+        None => Err(status::Custom(
+            Status::TooManyRequests,
+            Json(json!({
+                "error": "Too many requests"
+            })),
+        )),
+    }
+}
+
+/// Lockout of an email address after repeated failed logins
+///
+/// Managed as Rocket state. Failures are read from `login_attempts`, so the
+/// lockout holds across instances and restarts. Only failures since the last
+/// successful login and within the window count; once `max_failures` are
+/// reached, logins for the address are refused until the oldest of them
+/// leaves the window. Unknown addresses are locked out the same way, so the
+/// lockout doesn't reveal which accounts exist.
+///
+/// With a cache time set, a lockout is cached for up to that long, so
+/// repeated attempts against a locked address don't reach the database. A
+/// cached lockout holds even if the failures are cleared or the user signs in
+/// another way (OAuth, QR code) meanwhile.
+///
+/// With runtime settings, the limit and window follow the
+/// `login.lockout_attempts` and `login.lockout_minutes` overrides.
+pub struct LoginLockout {
+    max_failures: u32,
+    window: Duration,
+    cache: SharedCache,
+    cache_for: Duration,
+    settings: Option<Settings>,
+}
+
+impl LoginLockout {
+    /// A lockout after `max_failures` within `window`; 0 disables it
+    pub fn new(max_failures: u32, window: Duration) -> Self {
+        LoginLockout {
+            max_failures,
+            window,
+            cache: SharedCache::memory(),
+            cache_for: Duration::zero(),
+            settings: None,
+        }
+    }
+
+    /// Take the limit and window from `settings` as admins change them
+    pub fn with_settings(mut self, settings: Settings) -> Self {
+        self.settings = Some(settings);
+        self
+    }
+
+    /// Cache lockouts in `cache` for up to `cache_for`; zero doesn't cache them
+    pub fn with_cache(mut self, cache: SharedCache, cache_for: Duration) -> Self {
+        self.cache = cache;
+        self.cache_for = cache_for;
+        self
+    }
+
+    pub fn from_config(config: &AppConfig) -> Self {
+        LoginLockout::new(
+            config.login_lockout_attempts,
+            Duration::minutes(config.login_lockout_minutes as i64),
+        )
+    }
+
+    /// Where logins for `email` stand, or `None` when the lockout is disabled
+    pub async fn check(&self, conn: &mut PgConnection, email: &str) -> Result<Option<RateLimit>, sqlx::Error> {
+        let (max_failures, window) = match &self.settings {
+            Some(settings) => {
+                let config = settings.current();
+                (config.login_lockout_attempts, Duration::minutes(config.login_lockout_minutes as i64))
+            }
+            None => (self.max_failures, self.window),
+        };
+        if max_failures == 0 {
+            return Ok(None);
+        }
+
+        let key = format!("login_lockout:{}", email.to_lowercase());
+        let now = Utc::now();
+        if self.cache_for > Duration::zero()
+            && let Some(limit) = self.cache.get::<RateLimit>(&key).await
+            && limit.reset_at > now
+        {
+            return Ok(Some(limit));
+        }
+
+        let failures = login_attempts::recent_failures(conn, email, now - window, max_failures as i64).await?;
+        let remaining = max_failures.saturating_sub(failures.len() as u32);
+        // The limit is back up once the oldest counted failure leaves the window
+        let reset_at = failures.last().map_or(now + window, |oldest| *oldest + window);
+
+        let limit = RateLimit {
+            limit: max_failures,
+            remaining,
+            reset_at,
+        };
+        if limit.is_exceeded()
+            && let Ok(cache_for) = (reset_at - now).min(self.cache_for).to_std()
+        {
+            self.cache.set(&key, &limit, cache_for).await;
+        }
+        Ok(Some(limit))
+    }
+}
+
+/// Progressive delay of logins after recent failures, short of a lockout
+///
+/// Managed as Rocket state. Each failed login for an address from a client
+/// address, within the lockout window and since the last successful login,
+/// holds the next attempts from that pair for `per_failure` longer, up to
+/// `max`. Failures are read from `login_attempts`, like the lockout's, so
+/// delays hold across instances. Other clients signing in to the same account
+/// aren't slowed down. With runtime settings, the delays follow the
+/// `login.delay_ms`, `login.delay_max_ms` and `login.lockout_minutes`
+/// overrides.
+pub struct LoginDelay {
+    per_failure: std::time::Duration,
+    max: std::time::Duration,
+    window: Duration,
+    settings: Option<Settings>,
+}
+
+impl LoginDelay {
+    /// `per_failure` more per failure within `window`, capped at `max`; a zero `per_failure` disables delays
+    pub fn new(per_failure: std::time::Duration, max: std::time::Duration, window: Duration) -> Self {
+        LoginDelay {
+            per_failure,
+            max,
+            window,
+            settings: None,
+        }
+    }
+
+    /// Take the delays and window from `settings` as admins change them
+    pub fn with_settings(mut self, settings: Settings) -> Self {
+        self.settings = Some(settings);
+        self
+    }
+
+    pub fn from_config(config: &AppConfig) -> Self {
+        LoginDelay::new(
+            std::time::Duration::from_millis(config.login_delay_ms),
+            std::time::Duration::from_millis(config.login_delay_max_ms),
+            Duration::minutes(config.login_lockout_minutes as i64),
+        )
+    }
+
+    /// The delay after `failures` recent failures
+    pub fn delay_for(&self, failures: u32) -> std::time::Duration {
+        let (per_failure, max, _) = self.limits();
+        per_failure.saturating_mul(failures).min(max)
+    }
+
+    fn limits(&self) -> (std::time::Duration, std::time::Duration, Duration) {
+        match &self.settings {
+            Some(settings) => {
+                let config = settings.current();
+                (
+                    std::time::Duration::from_millis(config.login_delay_ms),
+                    std::time::Duration::from_millis(config.login_delay_max_ms),
+                    Duration::minutes(config.login_lockout_minutes as i64),
+                )
+            }
+            None => (self.per_failure, self.max, self.window),
+        }
+    }
+
+    /// Wait out the delay for logins to `email` from `ip`
+    pub async fn wait(&self, conn: &mut PgConnection, email: &str, ip: Option<IpAddr>) -> Result<(), sqlx::Error> {
+        let (per_failure, max, window) = self.limits();
+        if per_failure.is_zero() || max.is_zero() {
+            return Ok(());
+        }
+
+        let since = Utc::now() - window;
+        let failures = login_attempts::count_recent_failures_from(conn, email, ip, since).await?;
+        let delay = self.delay_for(u32::try_from(failures).unwrap_or(u32::MAX));
+        if !delay.is_zero() {
+            rocket::tokio::time::sleep(delay).await;
+        }
+        Ok(())
+    }
+}
+
+/// Temporary blocks of client addresses that fail logins for many different accounts
+///
+/// Managed as Rocket state. When an address has failed to log in to
+/// `distinct_emails` different emails within `window`, its next login attempt
+/// blocks it for `block_for`, publishing a `credential_stuffing_detected`
+/// security event, and every login from it is refused until the block ends.
+/// Failures from before a block ended don't count again. Requests without a
+/// known client address are never blocked.
+pub struct StuffingDetector {
+    distinct_emails: u32,
+    window: Duration,
+    block_for: Duration,
+}
+
+impl StuffingDetector {
+    /// Block for `block_for` after `distinct_emails` within `window`; 0 disables blocking
+    pub fn new(distinct_emails: u32, window: Duration, block_for: Duration) -> Self {
+        StuffingDetector {
+            distinct_emails,
+            window,
+            block_for,
+        }
+    }
+
+    pub fn from_config(config: &AppConfig) -> Self {
+        StuffingDetector::new(
+            config.stuffing_distinct_emails,
+            Duration::minutes(config.stuffing_window_minutes as i64),
+            Duration::minutes(config.stuffing_block_minutes as i64),
+        )
+    }
+
+    /// The block on `ip`, if it's blocked now or its recent failures get it blocked
+    pub async fn check(&self, conn: &mut PgConnection, ip: Option<IpAddr>) -> Result<Option<IpBlock>, sqlx::Error> {
+        let Some(ip) = ip.filter(|_| self.distinct_emails > 0) else {
+            return Ok(None);
+        };
+
+        let now = Utc::now();
+        let mut since = now - self.window;
+        if let Some(block) = ip_blocks::find(conn, ip).await? {
+            if block.is_active(now) {
+                return Ok(Some(block));
+            }
+            since = since.max(block.blocked_until);
+        }
+
+        let failed = login_attempts::count_failed_emails_from(conn, ip, since).await?;
+        if failed < self.distinct_emails as i64 {
+            return Ok(None);
+        }
+
+        let blocked_until = now + self.block_for;
+        match ip_blocks::block(conn, ip, blocked_until, failed as i32).await? {
+            Some(block) => {
+                let kind = SecurityEventKind::CredentialStuffingDetected {
+                    distinct_emails: failed as u32,
+                    blocked_until,
+                };
+                eprintln!("⚠ Blocked {} after failed logins for {} accounts", ip, failed);
+                events::emit_event(conn, SecurityEvent::new(Uuid::nil(), kind).from_ip(Some(ip))).await;
+                Ok(Some(block))
+            }
+            // Blocked concurrently by another request
+            None => ip_blocks::find(conn, ip).await,
+        }
+    }
+
+    /// Where an address stands, for the headers of a refused login
+    pub fn limit(&self, block: &IpBlock) -> RateLimit {
+        RateLimit {
+            limit: self.distinct_emails,
+            remaining: 0,
+            reset_at: block.blocked_until,
+        }
+    }
+}
+
+/// Daily and monthly request quotas for API keys
+///
+/// Managed as Rocket state. Every request authenticated with an API key is
+/// counted in `api_key_usage` under its UTC day, quotas or not, so owners can
+/// see their usage. Once a key has made `daily` requests today or `monthly`
+/// this calendar month, further requests are refused with `429` until the
+/// period ends; refused requests aren't counted. A quota of 0 is unlimited.
+pub struct ApiKeyQuota {
+    daily: u32,
+    monthly: u32,
+}
+
+/// Where a key stands for one quota period
+#[derive(Debug, Clone, Copy)]
+pub struct QuotaUsage {
+    pub requests: i64,
+    /// `None` when the period has no quota
+    pub limit: Option<RateLimit>,
+}
+
+impl ApiKeyQuota {
+    pub fn new(daily: u32, monthly: u32) -> Self {
+        ApiKeyQuota { daily, monthly }
+    }
+
+    pub fn from_config(config: &AppConfig) -> Self {
+        ApiKeyQuota::new(config.api_key_daily_quota, config.api_key_monthly_quota)
+    }
+
+    /// Count a request made with key `id`, or return the quota it would exceed
+    pub async fn consume(&self, conn: &mut PgConnection, id: Uuid) -> Result<Option<RateLimit>, sqlx::Error> {
+        let today = Utc::now().date_naive();
+        if self.daily > 0 || self.monthly > 0 {
+            let [day, month] = self.usage_on(conn, id, today).await?;
+            if let Some(exceeded) = [day, month].into_iter().filter_map(|usage| usage.limit).find(RateLimit::is_exceeded) {
+                return Ok(Some(exceeded));
+            }
+        }
+        api_keys::record_usage(conn, id, today).await?;
+        Ok(None)
+    }
+
+    /// Where key `id` stands today and this month
+    pub async fn usage(&self, conn: &mut PgConnection, id: Uuid) -> Result<[QuotaUsage; 2], sqlx::Error> {
+        self.usage_on(conn, id, Utc::now().date_naive()).await
+    }
+
+    async fn usage_on(&self, conn: &mut PgConnection, id: Uuid, today: NaiveDate) -> Result<[QuotaUsage; 2], sqlx::Error> {
+        let month_start = today.with_day(1).unwrap_or(today);
+        let (day_requests, month_requests) = api_keys::count_usage(conn, id, today, month_start).await?;
+
+        let midnight = |day: NaiveDate| day.and_time(chrono::NaiveTime::MIN).and_utc();
+        let next_day = midnight(today + Duration::days(1));
+        let next_month = month_start.checked_add_months(Months::new(1)).map_or(next_day, midnight);
+        let period = |requests: i64, quota: u32, reset_at: DateTime<Utc>| QuotaUsage {
+            requests,
+            limit: (quota > 0).then(|| RateLimit {
+                limit: quota,
+                remaining: u32::try_from((quota as i64 - requests).max(0)).unwrap_or(0),
+                reset_at,
+            }),
+        };
+        Ok([
+            period(day_requests, self.daily, next_day),
+            period(month_requests, self.monthly, next_month),
+        ])
+    }
+}
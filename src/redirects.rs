@@ -0,0 +1,88 @@
+use reqwest::Url;
+
+use crate::config::AppConfig;
+
+/// An entry of `ROCKET_REDIRECT_ALLOWLIST`, split into its parts
+struct Pattern<'a> {
+    scheme: &'a str,
+    /// `host[:port]`, or `*.domain[:port]` for any subdomain
+    host: &'a str,
+    /// Empty for any path; a trailing `*` allows any path under it
+    path: &'a str,
+}
+
+/// Where a `redirect_to` parameter may send the browser after a sign-in, sign-out or confirmation
+///
+/// Paths starting with `/` are on the frontend. Full URLs must be on the
+/// frontend's origin or match an entry of `ROCKET_REDIRECT_ALLOWLIST`.
+/// Returns the absolute URL to redirect to, or `None` if it isn't allowed.
+pub fn allowed(config: &AppConfig, redirect_to: &str) -> Option<String> {
+    if redirect_to.is_empty() || redirect_to.chars().any(|c| c.is_control() || c == '\\') {
+        return None;
+    }
+    let candidate = match redirect_to.strip_prefix('/') {
+        // `//host` is a full URL to browsers
+        Some(rest) if rest.starts_with('/') => return None,
+        Some(_) => format!("{}{}", config.frontend_url, redirect_to),
+        None => redirect_to.to_string(),
+    };
+
+    let url = Url::parse(&candidate).ok()?;
+    if !matches!(url.scheme(), "http" | "https") || !url.username().is_empty() || url.password().is_some() {
+        return None;
+    }
+    let on_frontend = Url::parse(&config.frontend_url).is_ok_and(|frontend| frontend.origin() == url.origin());
+    let listed = config
+        .redirect_allowlist
+        .iter()
+        .filter_map(|entry| parse(entry))
+        .any(|pattern| matches(&pattern, &url));
+    (on_frontend || listed).then(|| url.to_string())
+}
+
+/// Whether `entry` can be used in `ROCKET_REDIRECT_ALLOWLIST`
+///
+/// Entries are `http(s)` URLs. The host may start with `*.` to allow any
+/// subdomain, and the path may end with `*` to allow any path under it.
+pub fn valid_pattern(entry: &str) -> bool {
+    parse(entry).is_some()
+}
+
+fn parse(entry: &str) -> Option<Pattern<'_>> {
+    let (scheme, rest) = entry.split_once("://")?;
+    if !matches!(scheme, "http" | "https") {
+        return None;
+    }
+    let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    let domain = host.strip_prefix("*.").unwrap_or(host);
+    if domain.is_empty() || domain.contains(['*', '@', '?', '#']) {
+        return None;
+    }
+    if path.contains(['?', '#']) || path.trim_end_matches('*').contains('*') {
+        return None;
+    }
+    Some(Pattern { scheme, host, path })
+}
+
+fn matches(pattern: &Pattern<'_>, url: &Url) -> bool {
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    let authority = match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    };
+    let pattern_host = pattern.host.to_ascii_lowercase();
+    let host_matches = match pattern_host.strip_prefix('*') {
+        Some(domain) => authority.strip_suffix(domain).is_some_and(|subdomain| !subdomain.is_empty()),
+        None => authority == pattern_host,
+    };
+    let path_matches = match pattern.path {
+        "" | "/" => true,
+        path => match path.strip_suffix('*') {
+            Some(prefix) => url.path().starts_with(prefix),
+            None => url.path() == path,
+        },
+    };
+    url.scheme() == pattern.scheme && host_matches && path_matches
+}
@@ -0,0 +1,45 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+/// Claim the right to send the alert `key`, unless it was last sent after `quiet_since`
+///
+/// Only one of several instances checking at once gets `true`.
+pub async fn claim(conn: &mut PgConnection, key: &str, quiet_since: DateTime<Utc>) -> Result<bool, sqlx::Error> {
+    let claimed = sqlx::query_scalar::<_, String>(
+        r#"
+        INSERT INTO admin_alerts (key, sent_at) VALUES ($1, NOW())
+        ON CONFLICT (key) DO UPDATE SET sent_at = NOW() WHERE admin_alerts.sent_at <= $2
+        RETURNING key
+        "#,
+    )
+    .bind(key)
+    .bind(quiet_since)
+    .fetch_optional(conn)
+    .await?;
+    Ok(claimed.is_some())
+}
+
+/// Remember that the user logged in from `country`
+///
+/// Returns whether that's a country they hadn't logged in from before, not
+/// counting the first one ever recorded.
+pub async fn record_login_country(conn: &mut PgConnection, user_id: Uuid, country: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar::<_, bool>(
+        r#"
+        WITH known AS (
+            SELECT COUNT(*) AS count FROM login_countries WHERE user_id = $1
+        ),
+        inserted AS (
+            INSERT INTO login_countries (user_id, country) VALUES ($1, $2)
+            ON CONFLICT (user_id, country) DO NOTHING
+            RETURNING country
+        )
+        SELECT EXISTS (SELECT 1 FROM inserted) AND (SELECT count FROM known) > 0
+        "#,
+    )
+    .bind(user_id)
+    .bind(country)
+    .fetch_one(conn)
+    .await
+}
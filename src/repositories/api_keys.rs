@@ -0,0 +1,176 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use sqlx::{Connection, PgConnection};
+use uuid::Uuid;
+
+use crate::models::api_key::{ApiKey, ApiKeyUsage};
+
+const API_KEY_COLUMNS: &str =
+    "id, user_id, name, key_prefix, key_hash, scopes, allowed_ips, audience, expires_at, last_used_at, revoked_at, replaced_by, created_at";
+
+/// Store a new API key by its hash
+#[allow(clippy::too_many_arguments)]
+pub async fn create(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    name: &str,
+    key_prefix: &str,
+    key_hash: &str,
+    scopes: &[String],
+    allowed_ips: &[String],
+    audience: Option<&str>,
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<ApiKey, sqlx::Error> {
+    sqlx::query_as::<_, ApiKey>(&format!(
+        "INSERT INTO api_keys (user_id, name, key_prefix, key_hash, scopes, allowed_ips, audience, expires_at) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING {}",
+        API_KEY_COLUMNS
+    ))
+    .bind(user_id)
+    .bind(name)
+    .bind(key_prefix)
+    .bind(key_hash)
+    .bind(scopes)
+    .bind(allowed_ips)
+    .bind(audience)
+    .bind(expires_at)
+    .fetch_one(conn)
+    .await
+}
+
+/// A user's unrevoked API keys, newest first
+pub async fn list_for_user(conn: &mut PgConnection, user_id: Uuid) -> Result<Vec<ApiKey>, sqlx::Error> {
+    sqlx::query_as::<_, ApiKey>(&format!(
+        "SELECT {} FROM api_keys WHERE user_id = $1 AND revoked_at IS NULL ORDER BY created_at DESC",
+        API_KEY_COLUMNS
+    ))
+    .bind(user_id)
+    .fetch_all(conn)
+    .await
+}
+
+/// Find an unrevoked key by id
+pub async fn find(conn: &mut PgConnection, id: Uuid) -> Result<Option<ApiKey>, sqlx::Error> {
+    sqlx::query_as::<_, ApiKey>(&format!(
+        "SELECT {} FROM api_keys WHERE id = $1 AND revoked_at IS NULL",
+        API_KEY_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Find an unrevoked, unexpired key by its hash and record that it was used
+pub async fn use_key(conn: &mut PgConnection, key_hash: &str) -> Result<Option<ApiKey>, sqlx::Error> {
+    sqlx::query_as::<_, ApiKey>(&format!(
+        "UPDATE api_keys SET last_used_at = CURRENT_TIMESTAMP \
+         WHERE key_hash = $1 AND revoked_at IS NULL \
+           AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP) \
+         RETURNING {}",
+        API_KEY_COLUMNS
+    ))
+    .bind(key_hash)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Replace key `id` with a new key of the same name, scopes and bindings, cutting the old one's life short
+///
+/// The old key keeps working until `old_expires_at` (or its own expiry, if
+/// sooner). Returns `None` if it was revoked or already replaced.
+pub async fn rotate(
+    conn: &mut PgConnection,
+    id: Uuid,
+    key_prefix: &str,
+    key_hash: &str,
+    expires_at: Option<DateTime<Utc>>,
+    old_expires_at: DateTime<Utc>,
+) -> Result<Option<ApiKey>, sqlx::Error> {
+    let mut tx = conn.begin().await?;
+    let replacement = sqlx::query_as::<_, ApiKey>(&format!(
+        "INSERT INTO api_keys (user_id, name, key_prefix, key_hash, scopes, allowed_ips, audience, expires_at) \
+         SELECT user_id, name, $2, $3, scopes, allowed_ips, audience, $4 FROM api_keys \
+         WHERE id = $1 AND revoked_at IS NULL AND replaced_by IS NULL \
+         RETURNING {}",
+        API_KEY_COLUMNS
+    ))
+    .bind(id)
+    .bind(key_prefix)
+    .bind(key_hash)
+    .bind(expires_at)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(replacement) = replacement else {
+        return Ok(None);
+    };
+
+    // Rotated concurrently: the row lock makes the other rotation wait, then this finds it replaced
+    let result = sqlx::query(
+        "UPDATE api_keys SET replaced_by = $2, expires_at = LEAST(COALESCE(expires_at, $3), $3) \
+         WHERE id = $1 AND revoked_at IS NULL AND replaced_by IS NULL",
+    )
+    .bind(id)
+    .bind(replacement.id)
+    .bind(old_expires_at)
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() != 1 {
+        return Ok(None);
+    }
+
+    tx.commit().await?;
+    Ok(Some(replacement))
+}
+
+/// Revoke one of a user's keys; returns false if there's no such active key
+pub async fn revoke(conn: &mut PgConnection, user_id: Uuid, id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE api_keys SET revoked_at = CURRENT_TIMESTAMP WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL"
+    )
+    .bind(id)
+    .bind(user_id)
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Count a request made with a key on `day`
+pub async fn record_usage(conn: &mut PgConnection, id: Uuid, day: NaiveDate) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO api_key_usage (api_key_id, day, requests) VALUES ($1, $2, 1) \
+         ON CONFLICT (api_key_id, day) DO UPDATE SET requests = api_key_usage.requests + 1",
+    )
+    .bind(id)
+    .bind(day)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Requests made with a key on `day`, and from `month_start` up to and including `day`
+pub async fn count_usage(
+    conn: &mut PgConnection,
+    id: Uuid,
+    day: NaiveDate,
+    month_start: NaiveDate,
+) -> Result<(i64, i64), sqlx::Error> {
+    sqlx::query_as(
+        "SELECT COALESCE(SUM(requests) FILTER (WHERE day = $2), 0)::BIGINT, COALESCE(SUM(requests), 0)::BIGINT \
+         FROM api_key_usage WHERE api_key_id = $1 AND day BETWEEN $3 AND $2",
+    )
+    .bind(id)
+    .bind(day)
+    .bind(month_start)
+    .fetch_one(conn)
+    .await
+}
+
+/// A key's requests per day since `since`, newest first; days without requests are left out
+pub async fn list_usage(conn: &mut PgConnection, id: Uuid, since: NaiveDate) -> Result<Vec<ApiKeyUsage>, sqlx::Error> {
+    sqlx::query_as::<_, ApiKeyUsage>(
+        "SELECT day, requests FROM api_key_usage WHERE api_key_id = $1 AND day >= $2 ORDER BY day DESC",
+    )
+    .bind(id)
+    .bind(since)
+    .fetch_all(conn)
+    .await
+}
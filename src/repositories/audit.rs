@@ -0,0 +1,290 @@
+use chrono::{DateTime, Datelike, Months, NaiveDate, NaiveTime, Utc};
+use rocket::futures::stream::BoxStream;
+use sqlx::{Connection, PgConnection};
+use uuid::Uuid;
+
+use crate::models::audit::{AuditDeadLetter, AuditEvent, AuditFilter, DeadLetterCount};
+
+/// Matching audit events, newest first; `$1`–`$7` are the filter fields
+const SELECT_FILTERED: &str = r#"
+    SELECT id, occurred_at, event_type, user_id, actor_id, ip, details, organization_id FROM audit_events
+    WHERE ($1::uuid IS NULL OR actor_id = $1)
+      AND ($2::uuid IS NULL OR user_id = $2)
+      AND ($3::text IS NULL OR event_type = $3)
+      AND ($4::text IS NULL OR ip = $4)
+      AND ($5::timestamptz IS NULL OR occurred_at >= $5)
+      AND ($6::timestamptz IS NULL OR occurred_at < $6)
+      AND ($7::uuid IS NULL OR organization_id = $7)
+    ORDER BY occurred_at DESC, id DESC
+"#;
+
+/// A page of audit events matching `filter`, newest first
+pub async fn list(
+    conn: &mut PgConnection,
+    filter: &AuditFilter,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<AuditEvent>, sqlx::Error> {
+    sqlx::query_as::<_, AuditEvent>(&format!("{} LIMIT $8 OFFSET $9", SELECT_FILTERED))
+        .bind(filter.actor_id)
+        .bind(filter.user_id)
+        .bind(filter.event_type.as_deref())
+        .bind(filter.ip.as_deref())
+        .bind(filter.from)
+        .bind(filter.to)
+        .bind(filter.organization_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(conn)
+        .await
+}
+
+/// Every audit event matching `filter`, newest first, read as it's consumed
+pub fn export<'c>(conn: &'c mut PgConnection, filter: &'c AuditFilter) -> BoxStream<'c, Result<AuditEvent, sqlx::Error>> {
+    sqlx::query_as::<_, AuditEvent>(SELECT_FILTERED)
+        .bind(filter.actor_id)
+        .bind(filter.user_id)
+        .bind(filter.event_type.as_deref())
+        .bind(filter.ip.as_deref())
+        .bind(filter.from)
+        .bind(filter.to)
+        .bind(filter.organization_id)
+        .fetch(conn)
+}
+
+/// Audit events to replay, oldest first: those in `ids`, or else those from `from` until `to`
+///
+/// At most `limit` are returned.
+pub async fn list_for_replay(
+    conn: &mut PgConnection,
+    ids: &[Uuid],
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    limit: i64,
+) -> Result<Vec<AuditEvent>, sqlx::Error> {
+    sqlx::query_as::<_, AuditEvent>(
+        r#"
+        SELECT id, occurred_at, event_type, user_id, actor_id, ip, details, organization_id FROM audit_events
+        WHERE (cardinality($1::uuid[]) = 0 OR id = ANY($1))
+          AND ($2::timestamptz IS NULL OR occurred_at >= $2)
+          AND ($3::timestamptz IS NULL OR occurred_at < $3)
+        ORDER BY occurred_at, id
+        LIMIT $4
+        "#,
+    )
+    .bind(ids)
+    .bind(from)
+    .bind(to)
+    .bind(limit)
+    .fetch_all(conn)
+    .await
+}
+
+/// Mark an event as forwarded to the audit sinks, returning false if another instance already did
+pub async fn claim_forwarding(conn: &mut PgConnection, id: Uuid, occurred_at: DateTime<Utc>) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE audit_events SET forwarded_at = CURRENT_TIMESTAMP \
+         WHERE id = $1 AND occurred_at = $2 AND forwarded_at IS NULL",
+    )
+    .bind(id)
+    .bind(occurred_at)
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Record that `sink` failed to take an event after `attempts` tries
+pub async fn record_dead_letter(
+    conn: &mut PgConnection,
+    event_id: Uuid,
+    occurred_at: DateTime<Utc>,
+    sink: &str,
+    attempts: i32,
+    last_error: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO audit_dead_letters (event_id, occurred_at, sink, attempts, last_error) VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(event_id)
+    .bind(occurred_at)
+    .bind(sink)
+    .bind(attempts)
+    .bind(last_error)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Dead letters per sink since `since`, for sinks with at least `min_count`
+pub async fn count_dead_letters_by_sink(
+    conn: &mut PgConnection,
+    since: DateTime<Utc>,
+    min_count: i64,
+) -> Result<Vec<(String, i64)>, sqlx::Error> {
+    sqlx::query_as::<_, (String, i64)>(
+        "SELECT sink, COUNT(*) FROM audit_dead_letters WHERE failed_at > $1 GROUP BY sink HAVING COUNT(*) >= $2 ORDER BY sink",
+    )
+    .bind(since)
+    .bind(min_count)
+    .fetch_all(conn)
+    .await
+}
+
+/// A page of dead letters, most recent failures first
+pub async fn list_dead_letters(conn: &mut PgConnection, limit: i64, offset: i64) -> Result<Vec<AuditDeadLetter>, sqlx::Error> {
+    sqlx::query_as::<_, AuditDeadLetter>(
+        r#"
+        SELECT id, event_id, occurred_at, sink, attempts, last_error, failed_at FROM audit_dead_letters
+        ORDER BY failed_at DESC, id
+        LIMIT $1 OFFSET $2
+        "#,
+    )
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(conn)
+    .await
+}
+
+/// Dead letters per sink
+pub async fn count_dead_letters(conn: &mut PgConnection) -> Result<Vec<DeadLetterCount>, sqlx::Error> {
+    sqlx::query_as::<_, DeadLetterCount>(
+        "SELECT sink, COUNT(*) AS count FROM audit_dead_letters GROUP BY sink ORDER BY sink",
+    )
+    .fetch_all(conn)
+    .await
+}
+
+/// Drop the dead letters of an event once `sink` has taken it after all
+pub async fn clear_dead_letters(conn: &mut PgConnection, event_id: Uuid, sink: &str) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM audit_dead_letters WHERE event_id = $1 AND sink = $2")
+        .bind(event_id)
+        .bind(sink)
+        .execute(conn)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// Count audit events that occurred before `before`
+pub async fn count_before(conn: &mut PgConnection, before: DateTime<Utc>) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM audit_events WHERE occurred_at < $1")
+        .bind(before)
+        .fetch_one(conn)
+        .await
+}
+
+/// Name prefix of the monthly `audit_events` partitions, followed by `YYYY_MM`
+const PARTITION_PREFIX: &str = "audit_events_";
+
+/// Months ahead of the current one that partitions are created for
+pub const PARTITION_MONTHS_AHEAD: u32 = 2;
+
+/// Serializes partition changes between instances
+const PARTITION_LOCK: &str = "SELECT pg_advisory_xact_lock(hashtext('audit_events_partitions'))";
+
+/// Create the monthly `audit_events` partitions covering `from` through `until`
+///
+/// Events outside every monthly partition land in `audit_events_default`
+/// and are moved over when their month's partition is created. To keep that
+/// rare, the migrations and the retention cleanup create partitions for the
+/// next [`PARTITION_MONTHS_AHEAD`] months.
+pub async fn ensure_partitions(conn: &mut PgConnection, from: DateTime<Utc>, until: DateTime<Utc>) -> Result<(), sqlx::Error> {
+    let mut tx = conn.begin().await?;
+    sqlx::query(PARTITION_LOCK).execute(&mut *tx).await?;
+
+    let mut month = month_start(from.date_naive());
+    while month <= until.date_naive() {
+        let next = month + Months::new(1);
+        let partition = format!("{}{}", PARTITION_PREFIX, month.format("%Y_%m"));
+        let exists = sqlx::query_scalar::<_, bool>("SELECT to_regclass($1) IS NOT NULL")
+            .bind(&partition)
+            .fetch_one(&mut *tx)
+            .await?;
+        if !exists {
+            // Events already in the default partition for this month move over first,
+            // since a partition can't be attached while the default holds its rows
+            sqlx::query(&format!("CREATE TABLE {} (LIKE audit_events INCLUDING DEFAULTS)", partition))
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query(&format!(
+                "WITH moved AS (DELETE FROM audit_events_default WHERE occurred_at >= $1 AND occurred_at < $2 RETURNING *) \
+                 INSERT INTO {} SELECT * FROM moved",
+                partition
+            ))
+            .bind(month_bound(month))
+            .bind(month_bound(next))
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query(&format!(
+                "ALTER TABLE audit_events ATTACH PARTITION {} FOR VALUES FROM ('{}') TO ('{}')",
+                partition,
+                month_bound(month).to_rfc3339(),
+                month_bound(next).to_rfc3339()
+            ))
+            .execute(&mut *tx)
+            .await?;
+        }
+        month = next;
+    }
+
+    tx.commit().await
+}
+
+/// Delete audit events that occurred before `before`, returning how many were deleted
+///
+/// Monthly partitions that lie entirely before `before` are dropped rather
+/// than emptied row by row; only the rest is deleted.
+pub async fn delete_before(conn: &mut PgConnection, before: DateTime<Utc>) -> Result<u64, sqlx::Error> {
+    let mut deleted = 0;
+
+    let mut tx = conn.begin().await?;
+    sqlx::query(PARTITION_LOCK).execute(&mut *tx).await?;
+    let partitions = sqlx::query_scalar::<_, String>(
+        r#"
+        SELECT child.relname::text FROM pg_inherits
+        JOIN pg_class child ON child.oid = pg_inherits.inhrelid
+        WHERE pg_inherits.inhparent = 'audit_events'::regclass
+        "#,
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    for partition in partitions {
+        let Some(month) = partition_month(&partition) else {
+            continue;
+        };
+        if month_bound(month + Months::new(1)) > before {
+            continue;
+        }
+        let rows = sqlx::query_scalar::<_, i64>(&format!("SELECT COUNT(*) FROM {}", partition))
+            .fetch_one(&mut *tx)
+            .await?;
+        sqlx::query(&format!("DROP TABLE {}", partition)).execute(&mut *tx).await?;
+        deleted += rows as u64;
+    }
+    tx.commit().await?;
+
+    let result = sqlx::query("DELETE FROM audit_events WHERE occurred_at < $1")
+        .bind(before)
+        .execute(&mut *conn)
+        .await?;
+    // Dead letters can't be replayed once their event is gone
+    sqlx::query("DELETE FROM audit_dead_letters WHERE occurred_at < $1")
+        .bind(before)
+        .execute(conn)
+        .await?;
+    Ok(deleted + result.rows_affected())
+}
+
+/// The month a partition holds, from its name; `None` for the default partition
+fn partition_month(name: &str) -> Option<NaiveDate> {
+    let month = name.strip_prefix(PARTITION_PREFIX)?;
+    NaiveDate::parse_from_str(&format!("{}_01", month), "%Y_%m_%d").ok()
+}
+
+fn month_start(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).expect("every month has a first day")
+}
+
+/// Midnight UTC on `date`; partition bounds don't depend on the session time zone
+fn month_bound(date: NaiveDate) -> DateTime<Utc> {
+    date.and_time(NaiveTime::MIN).and_utc()
+}
@@ -0,0 +1,64 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::auth::tokens;
+use crate::models::email_verification::EmailVerificationToken;
+
+/// Store a new email verification token for a user (only its hash is kept)
+pub async fn create(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    token: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO email_verification_tokens (user_id, token_hash, expires_at) VALUES ($1, $2, $3)")
+        .bind(user_id)
+        .bind(tokens::hash(token))
+        .bind(expires_at)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Store a verification token for one of a user's secondary addresses
+pub async fn create_for_address(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    email_id: Uuid,
+    token: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO email_verification_tokens (user_id, email_id, token_hash, expires_at) VALUES ($1, $2, $3, $4)"
+    )
+    .bind(user_id)
+    .bind(email_id)
+    .bind(tokens::hash(token))
+    .bind(expires_at)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Look up an email verification token by its plaintext value
+pub async fn find_by_token(
+    conn: &mut PgConnection,
+    token: &str,
+) -> Result<Option<EmailVerificationToken>, sqlx::Error> {
+    sqlx::query_as::<_, EmailVerificationToken>(
+        "SELECT id, user_id, email_id, token_hash, expires_at, used, created_at FROM email_verification_tokens WHERE token_hash = $1"
+    )
+    .bind(tokens::hash(token))
+    .fetch_optional(conn)
+    .await
+}
+
+/// Mark a verification token as used; false if it already was
+pub async fn mark_used(conn: &mut PgConnection, token: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("UPDATE email_verification_tokens SET used = TRUE WHERE token_hash = $1 AND used = FALSE")
+        .bind(tokens::hash(token))
+        .execute(conn)
+        .await?;
+    Ok(result.rows_affected() == 1)
+}
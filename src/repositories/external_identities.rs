@@ -0,0 +1,58 @@
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::models::user::LinkedAccount;
+
+/// The local user behind an external issuer's subject, if it has been seen before
+pub async fn find_user_id(conn: &mut PgConnection, issuer: &str, subject: &str) -> Result<Option<Uuid>, sqlx::Error> {
+    sqlx::query_scalar::<_, Uuid>("SELECT user_id FROM external_identities WHERE issuer = $1 AND subject = $2")
+        .bind(issuer)
+        .bind(subject)
+        .fetch_optional(conn)
+        .await
+}
+
+/// Remember which local user an external subject maps to
+///
+/// If the subject was linked concurrently, the existing link wins and its
+/// user id is returned.
+pub async fn link(conn: &mut PgConnection, issuer: &str, subject: &str, user_id: Uuid) -> Result<Uuid, sqlx::Error> {
+    sqlx::query_scalar::<_, Uuid>(
+        r#"
+        WITH inserted AS (
+            INSERT INTO external_identities (issuer, subject, user_id) VALUES ($1, $2, $3)
+            ON CONFLICT (issuer, subject) DO NOTHING
+            RETURNING user_id
+        )
+        SELECT user_id FROM inserted
+        UNION ALL
+        SELECT user_id FROM external_identities WHERE issuer = $1 AND subject = $2
+        LIMIT 1
+        "#,
+    )
+    .bind(issuer)
+    .bind(subject)
+    .bind(user_id)
+    .fetch_one(conn)
+    .await
+}
+
+/// The external accounts a user signs in with, most recently linked first
+pub async fn list_for_user(conn: &mut PgConnection, user_id: Uuid) -> Result<Vec<LinkedAccount>, sqlx::Error> {
+    sqlx::query_as::<_, LinkedAccount>(
+        "SELECT id, issuer, created_at FROM external_identities WHERE user_id = $1 ORDER BY created_at DESC",
+    )
+    .bind(user_id)
+    .fetch_all(conn)
+    .await
+}
+
+/// Remove one of the user's links; returns false if they have none with this id
+pub async fn unlink(conn: &mut PgConnection, user_id: Uuid, id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM external_identities WHERE id = $1 AND user_id = $2")
+        .bind(id)
+        .bind(user_id)
+        .execute(conn)
+        .await?;
+    Ok(result.rows_affected() == 1)
+}
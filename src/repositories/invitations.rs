@@ -0,0 +1,154 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::models::invitation::Invitation;
+
+const INVITATION_COLUMNS: &str =
+    "id, code, email, created_by, expires_at, used_at, used_by, created_at, organization_id, organization_role";
+
+/// Store a new invitation
+pub async fn create(
+    conn: &mut PgConnection,
+    code: &str,
+    email: Option<&str>,
+    created_by: Option<Uuid>,
+    expires_at: DateTime<Utc>,
+) -> Result<Invitation, sqlx::Error> {
+    sqlx::query_as::<_, Invitation>(&format!(
+        "INSERT INTO invitations (code, email, created_by, expires_at) VALUES ($1, $2, $3, $4) RETURNING {}",
+        INVITATION_COLUMNS
+    ))
+    .bind(code)
+    .bind(email)
+    .bind(created_by)
+    .bind(expires_at)
+    .fetch_one(conn)
+    .await
+}
+
+/// Store an invitation into an organization, with the role the invited user gets there
+pub async fn create_for_organization(
+    conn: &mut PgConnection,
+    code: &str,
+    email: &str,
+    created_by: Option<Uuid>,
+    expires_at: DateTime<Utc>,
+    organization_id: Uuid,
+    role: &str,
+) -> Result<Invitation, sqlx::Error> {
+    sqlx::query_as::<_, Invitation>(&format!(
+        "INSERT INTO invitations (code, email, created_by, expires_at, organization_id, organization_role) \
+         VALUES ($1, $2, $3, $4, $5, $6) RETURNING {}",
+        INVITATION_COLUMNS
+    ))
+    .bind(code)
+    .bind(email)
+    .bind(created_by)
+    .bind(expires_at)
+    .bind(organization_id)
+    .bind(role)
+    .fetch_one(conn)
+    .await
+}
+
+/// List invitations that are unused and unexpired, newest first
+pub async fn list_pending(
+    conn: &mut PgConnection,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<Invitation>, sqlx::Error> {
+    sqlx::query_as::<_, Invitation>(&format!(
+        "SELECT {} FROM invitations WHERE used_at IS NULL AND expires_at > CURRENT_TIMESTAMP \
+         ORDER BY created_at DESC LIMIT $1 OFFSET $2",
+        INVITATION_COLUMNS
+    ))
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(conn)
+    .await
+}
+
+/// Reserve an unused, unexpired invitation for an email address
+///
+/// Returns the invitation id, or `None` if the code can't be used by this
+/// address. Only one caller can claim a code.
+pub async fn claim(conn: &mut PgConnection, code: &str, email: &str) -> Result<Option<Uuid>, sqlx::Error> {
+    sqlx::query_scalar::<_, Uuid>(
+        r#"
+        UPDATE invitations SET used_at = CURRENT_TIMESTAMP
+        WHERE code = $1 AND used_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+          AND (email IS NULL OR LOWER(email) = LOWER($2))
+        RETURNING id
+        "#,
+    )
+    .bind(code)
+    .bind(email)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Reserve an organization's invitation for an existing account with this address
+///
+/// Like `claim`, for invitations into an organization only.
+pub async fn claim_for_organization(conn: &mut PgConnection, code: &str, email: &str) -> Result<Option<Uuid>, sqlx::Error> {
+    sqlx::query_scalar::<_, Uuid>(
+        r#"
+        UPDATE invitations SET used_at = CURRENT_TIMESTAMP
+        WHERE code = $1 AND used_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+          AND organization_id IS NOT NULL AND LOWER(email) = LOWER($2)
+        RETURNING id
+        "#,
+    )
+    .bind(code)
+    .bind(email)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Record which user a claimed invitation created
+pub async fn set_used_by(conn: &mut PgConnection, id: Uuid, user_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE invitations SET used_by = $1 WHERE id = $2")
+        .bind(user_id)
+        .bind(id)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Put the user who claimed an invitation into the invitation's organization
+///
+/// A custom role deleted since the invitation was sent becomes `member`.
+/// Does nothing for invitations that aren't into an organization.
+pub async fn join_organization(conn: &mut PgConnection, id: Uuid, user_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE users u SET organization_id = i.organization_id,
+            organization_role = CASE
+                WHEN i.organization_role IN ('admin', 'member') THEN i.organization_role
+                WHEN EXISTS (
+                    SELECT 1 FROM organization_roles r
+                    WHERE r.organization_id = i.organization_id AND r.name = i.organization_role
+                ) THEN i.organization_role
+                ELSE 'member'
+            END,
+            updated_at = CURRENT_TIMESTAMP
+        FROM invitations i
+        WHERE i.id = $1 AND i.organization_id IS NOT NULL AND u.id = $2
+        "#,
+    )
+    .bind(id)
+    .bind(user_id)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Make a claimed invitation usable again after registration failed
+pub async fn release(conn: &mut PgConnection, id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE invitations SET used_at = NULL WHERE id = $1 AND used_by IS NULL")
+        .bind(id)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use std::net::IpAddr;
+
+use chrono::{DateTime, Utc};
+use sqlx::PgConnection;
+
+use crate::models::ip_block::IpBlock;
+
+const IP_BLOCK_COLUMNS: &str = "ip, blocked_until, distinct_emails, created_at";
+
+/// The latest block of an address, whether or not it has ended
+pub async fn find(conn: &mut PgConnection, ip: IpAddr) -> Result<Option<IpBlock>, sqlx::Error> {
+    sqlx::query_as::<_, IpBlock>(&format!("SELECT {} FROM ip_blocks WHERE ip = $1", IP_BLOCK_COLUMNS))
+        .bind(ip.to_string())
+        .fetch_optional(conn)
+        .await
+}
+
+/// Block an address until `blocked_until`, returning the block if it's new
+///
+/// Returns `None` when the address is already blocked, so only one of several
+/// instances detecting it at once reports it.
+pub async fn block(
+    conn: &mut PgConnection,
+    ip: IpAddr,
+    blocked_until: DateTime<Utc>,
+    distinct_emails: i32,
+) -> Result<Option<IpBlock>, sqlx::Error> {
+    sqlx::query_as::<_, IpBlock>(&format!(
+        r#"
+        INSERT INTO ip_blocks (ip, blocked_until, distinct_emails) VALUES ($1, $2, $3)
+        ON CONFLICT (ip) DO UPDATE SET
+            blocked_until = EXCLUDED.blocked_until,
+            distinct_emails = EXCLUDED.distinct_emails,
+            created_at = CURRENT_TIMESTAMP
+        WHERE ip_blocks.blocked_until <= CURRENT_TIMESTAMP
+        RETURNING {}
+        "#,
+        IP_BLOCK_COLUMNS
+    ))
+    .bind(ip.to_string())
+    .bind(blocked_until)
+    .bind(distinct_emails)
+    .fetch_optional(conn)
+    .await
+}
+
+/// List blocks that haven't ended, newest first
+pub async fn list_active(conn: &mut PgConnection, limit: i64, offset: i64) -> Result<Vec<IpBlock>, sqlx::Error> {
+    sqlx::query_as::<_, IpBlock>(&format!(
+        "SELECT {} FROM ip_blocks WHERE blocked_until > CURRENT_TIMESTAMP \
+         ORDER BY created_at DESC LIMIT $1 OFFSET $2",
+        IP_BLOCK_COLUMNS
+    ))
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(conn)
+    .await
+}
+
+/// End an address's block now, returning whether it was blocked
+///
+/// The block is kept as ended rather than deleted, so failures from before
+/// it was lifted don't block the address again.
+pub async fn lift(conn: &mut PgConnection, ip: IpAddr) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE ip_blocks SET blocked_until = CURRENT_TIMESTAMP WHERE ip = $1 AND blocked_until > CURRENT_TIMESTAMP",
+    )
+    .bind(ip.to_string())
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
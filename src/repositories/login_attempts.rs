@@ -0,0 +1,228 @@
+use std::net::IpAddr;
+
+use chrono::{DateTime, Utc};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+/// Record the outcome of a password login
+///
+/// `user_id` is `None` when no account matched the email.
+pub async fn record(
+    conn: &mut PgConnection,
+    user_id: Option<Uuid>,
+    email: &str,
+    ip: Option<IpAddr>,
+    succeeded: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO login_attempts (user_id, email, ip, succeeded) VALUES ($1, $2, $3, $4)")
+        .bind(user_id)
+        .bind(email)
+        .bind(ip.map(|ip| ip.to_string()))
+        .bind(succeeded)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// A successful login, as written by [`record_successes`]
+#[derive(Debug, Clone)]
+pub struct LoginSuccess {
+    pub user_id: Uuid,
+    pub email: String,
+    pub ip: Option<IpAddr>,
+    pub at: DateTime<Utc>,
+}
+
+/// Record a successful login and update the user's login statistics in one statement
+///
+/// Also clears any inactivity warning or flag.
+pub async fn record_success(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    email: &str,
+    ip: Option<IpAddr>,
+) -> Result<(), sqlx::Error> {
+    let login = LoginSuccess {
+        user_id,
+        email: email.to_string(),
+        ip,
+        at: Utc::now(),
+    };
+    record_successes(conn, &[login]).await
+}
+
+/// Record several successful logins at once, as [`record_success`] does for one
+pub async fn record_successes(conn: &mut PgConnection, logins: &[LoginSuccess]) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        WITH logins AS (
+            SELECT * FROM unnest($1::uuid[], $2::text[], $3::text[], $4::timestamptz[]) AS l(user_id, email, ip, at)
+        ),
+        attempts AS (
+            INSERT INTO login_attempts (user_id, email, ip, succeeded, created_at)
+            SELECT user_id, email, ip, TRUE, at FROM logins
+        ),
+        latest AS (
+            SELECT DISTINCT ON (user_id) user_id, ip, at, COUNT(*) OVER (PARTITION BY user_id) AS count
+            FROM logins
+            ORDER BY user_id, at DESC
+        )
+        UPDATE users SET
+            last_login_at = latest.at,
+            last_login_ip = latest.ip,
+            login_count = login_count + latest.count,
+            inactivity_warned_at = NULL,
+            flagged_inactive_at = NULL,
+            updated_at = CURRENT_TIMESTAMP
+        FROM latest
+        WHERE users.id = latest.user_id
+        "#,
+    )
+    .bind(logins.iter().map(|login| login.user_id).collect::<Vec<_>>())
+    .bind(logins.iter().map(|login| login.email.clone()).collect::<Vec<_>>())
+    .bind(logins.iter().map(|login| login.ip.map(|ip| ip.to_string())).collect::<Vec<_>>())
+    .bind(logins.iter().map(|login| login.at).collect::<Vec<_>>())
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Times of the latest failed logins for an email since `since`, newest first
+///
+/// Failures before the address's last successful login don't count. At most
+/// `limit` are returned.
+pub async fn recent_failures(
+    conn: &mut PgConnection,
+    email: &str,
+    since: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<DateTime<Utc>>, sqlx::Error> {
+    sqlx::query_scalar::<_, DateTime<Utc>>(
+        r#"
+        SELECT created_at FROM login_attempts
+        WHERE LOWER(email) = LOWER($1) AND NOT succeeded AND created_at > $2
+          AND created_at > COALESCE(
+              (SELECT MAX(created_at) FROM login_attempts WHERE LOWER(email) = LOWER($1) AND succeeded),
+              '-infinity'
+          )
+        ORDER BY created_at DESC
+        LIMIT $3
+        "#,
+    )
+    .bind(email)
+    .bind(since)
+    .bind(limit)
+    .fetch_all(conn)
+    .await
+}
+
+/// Count failed logins for an email from one client address since `since`
+///
+/// Like [`recent_failures`], failures before the address's last successful
+/// login don't count. A `None` address only matches failures recorded without one.
+pub async fn count_recent_failures_from(
+    conn: &mut PgConnection,
+    email: &str,
+    ip: Option<IpAddr>,
+    since: DateTime<Utc>,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar::<_, i64>(
+        r#"
+        SELECT COUNT(*) FROM login_attempts
+        WHERE LOWER(email) = LOWER($1) AND ip IS NOT DISTINCT FROM $2 AND NOT succeeded AND created_at > $3
+          AND created_at > COALESCE(
+              (SELECT MAX(created_at) FROM login_attempts WHERE LOWER(email) = LOWER($1) AND succeeded),
+              '-infinity'
+          )
+        "#,
+    )
+    .bind(email)
+    .bind(ip.map(|ip| ip.to_string()))
+    .bind(since)
+    .fetch_one(conn)
+    .await
+}
+
+/// Count the distinct emails a client address failed to log in to since `since`
+pub async fn count_failed_emails_from(
+    conn: &mut PgConnection,
+    ip: IpAddr,
+    since: DateTime<Utc>,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(DISTINCT LOWER(email)) FROM login_attempts WHERE ip = $1 AND NOT succeeded AND created_at > $2",
+    )
+    .bind(ip.to_string())
+    .bind(since)
+    .fetch_one(conn)
+    .await
+}
+
+/// Whether the user logged in successfully from `ip` since `since`
+pub async fn has_succeeded_from(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    ip: IpAddr,
+    since: DateTime<Utc>,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS (SELECT 1 FROM login_attempts WHERE user_id = $1 AND ip = $2 AND succeeded AND created_at > $3)",
+    )
+    .bind(user_id)
+    .bind(ip.to_string())
+    .bind(since)
+    .fetch_one(conn)
+    .await
+}
+
+/// Count the distinct client addresses the user logged in from since `since`
+pub async fn count_success_ips(conn: &mut PgConnection, user_id: Uuid, since: DateTime<Utc>) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(DISTINCT ip) FROM login_attempts WHERE user_id = $1 AND succeeded AND created_at > $2",
+    )
+    .bind(user_id)
+    .bind(since)
+    .fetch_one(conn)
+    .await
+}
+
+/// Count the addresses with at least `max_failures` failed logins since `since`
+///
+/// As for the lockout, failures before the address's last successful login don't count.
+pub async fn count_locked_out(conn: &mut PgConnection, max_failures: u32, since: DateTime<Utc>) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar::<_, i64>(
+        r#"
+        SELECT COUNT(*) FROM (
+            SELECT LOWER(failed.email) FROM login_attempts failed
+            WHERE NOT failed.succeeded AND failed.created_at > $1
+              AND NOT EXISTS (
+                  SELECT 1 FROM login_attempts later
+                  WHERE LOWER(later.email) = LOWER(failed.email) AND later.succeeded AND later.created_at > failed.created_at
+              )
+            GROUP BY LOWER(failed.email)
+            HAVING COUNT(*) >= $2
+        ) locked_out
+        "#,
+    )
+    .bind(since)
+    .bind(max_failures as i64)
+    .fetch_one(conn)
+    .await
+}
+
+/// Count login attempts made before `before`
+pub async fn count_before(conn: &mut PgConnection, before: DateTime<Utc>) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM login_attempts WHERE created_at < $1")
+        .bind(before)
+        .fetch_one(conn)
+        .await
+}
+
+/// Delete login attempts made before `before`, returning how many were deleted
+pub async fn delete_before(conn: &mut PgConnection, before: DateTime<Utc>) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM login_attempts WHERE created_at < $1")
+        .bind(before)
+        .execute(conn)
+        .await?;
+    Ok(result.rows_affected())
+}
@@ -0,0 +1,283 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::models::mfa::{LoginChallenge, MfaRecovery, TotpEnrollment};
+
+/// The user's TOTP secret, confirmed or not
+pub async fn find_totp(conn: &mut PgConnection, user_id: Uuid) -> Result<Option<TotpEnrollment>, sqlx::Error> {
+    sqlx::query_as::<_, TotpEnrollment>(
+        "SELECT totp_secret AS secret, totp_enabled_at AS enabled_at, totp_last_step AS last_step \
+         FROM users WHERE id = $1 AND totp_secret IS NOT NULL",
+    )
+    .bind(user_id)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Store a new secret awaiting confirmation; returns false if the user already has TOTP enabled
+pub async fn start_totp_enrollment(conn: &mut PgConnection, user_id: Uuid, secret: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE users SET totp_secret = $2, totp_enabled_at = NULL, totp_last_step = NULL, updated_at = CURRENT_TIMESTAMP \
+         WHERE id = $1 AND totp_enabled_at IS NULL",
+    )
+    .bind(user_id)
+    .bind(secret)
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Accept a code for `step`, enabling TOTP if this confirms enrollment
+///
+/// Returns false if a code for this step (or a later one) was already
+/// accepted, so every code works once.
+pub async fn accept_totp_step(conn: &mut PgConnection, user_id: Uuid, step: i64) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE users SET totp_last_step = $2, totp_enabled_at = COALESCE(totp_enabled_at, CURRENT_TIMESTAMP), \
+         updated_at = CASE WHEN totp_enabled_at IS NULL THEN CURRENT_TIMESTAMP ELSE updated_at END \
+         WHERE id = $1 AND totp_secret IS NOT NULL AND (totp_last_step IS NULL OR totp_last_step < $2)",
+    )
+    .bind(user_id)
+    .bind(step)
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Remove the user's TOTP secret
+pub async fn remove_totp(conn: &mut PgConnection, user_id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE users SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_step = NULL, updated_at = CURRENT_TIMESTAMP \
+         WHERE id = $1 AND totp_secret IS NOT NULL",
+    )
+    .bind(user_id)
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Record when MFA became required for the user, keeping the first time; returns it
+pub async fn mark_required(conn: &mut PgConnection, user_id: Uuid) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    sqlx::query_scalar::<_, DateTime<Utc>>(
+        "UPDATE users SET mfa_required_since = COALESCE(mfa_required_since, CURRENT_TIMESTAMP), \
+         updated_at = CASE WHEN mfa_required_since IS NULL THEN CURRENT_TIMESTAMP ELSE updated_at END \
+         WHERE id = $1 RETURNING mfa_required_since",
+    )
+    .bind(user_id)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Store a login challenge (hashed) awaiting a code; expired challenges are purged on the way
+pub async fn create_challenge(
+    conn: &mut PgConnection,
+    token_hash: &str,
+    user_id: Uuid,
+    expires_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        WITH purged AS (
+            DELETE FROM mfa_challenges WHERE expires_at < NOW()
+        )
+        INSERT INTO mfa_challenges (token_hash, user_id, expires_at) VALUES ($1, $2, $3)
+        "#,
+    )
+    .bind(token_hash)
+    .bind(user_id)
+    .bind(expires_at)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Store a login challenge (hashed) answered with an emailed code rather than TOTP
+pub async fn create_email_challenge(
+    conn: &mut PgConnection,
+    token_hash: &str,
+    user_id: Uuid,
+    code_hash: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        WITH purged AS (
+            DELETE FROM mfa_challenges WHERE expires_at < NOW()
+        )
+        INSERT INTO mfa_challenges (token_hash, user_id, email_code_hash, expires_at) VALUES ($1, $2, $3, $4)
+        "#,
+    )
+    .bind(token_hash)
+    .bind(user_id)
+    .bind(code_hash)
+    .bind(expires_at)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// A pending, unexpired challenge
+pub async fn find_challenge(conn: &mut PgConnection, token_hash: &str) -> Result<Option<LoginChallenge>, sqlx::Error> {
+    sqlx::query_as::<_, LoginChallenge>(
+        "SELECT user_id, email_code_hash FROM mfa_challenges WHERE token_hash = $1 AND expires_at > NOW()",
+    )
+    .bind(token_hash)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Count a wrong code against a challenge, dropping it after `max_attempts`
+pub async fn fail_challenge(conn: &mut PgConnection, token_hash: &str, max_attempts: i32) -> Result<(), sqlx::Error> {
+    let attempts = sqlx::query_scalar::<_, i32>(
+        "UPDATE mfa_challenges SET attempts = attempts + 1 WHERE token_hash = $1 RETURNING attempts",
+    )
+    .bind(token_hash)
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    if attempts.is_some_and(|attempts| attempts >= max_attempts) {
+        delete_challenge(conn, token_hash).await?;
+    }
+    Ok(())
+}
+
+/// Consume a challenge once its code was accepted
+pub async fn delete_challenge(conn: &mut PgConnection, token_hash: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM mfa_challenges WHERE token_hash = $1")
+        .bind(token_hash)
+        .execute(conn)
+        .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+const RECOVERY_COLUMNS: &str = "id, user_id, admin_id, reason, requested_at, link_expires_at, \
+    confirmed_at, available_at, completed_at, cancelled_at";
+
+/// Recoveries that neither completed nor were cancelled
+const OPEN_RECOVERY: &str = "completed_at IS NULL AND cancelled_at IS NULL";
+
+/// Record a recovery request awaiting email confirmation, replacing unconfirmed earlier ones
+pub async fn create_recovery(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    token_hash: &str,
+    link_expires_at: DateTime<Utc>,
+) -> Result<MfaRecovery, sqlx::Error> {
+    sqlx::query_as::<_, MfaRecovery>(&format!(
+        "WITH replaced AS ( \
+             UPDATE mfa_recoveries SET cancelled_at = NOW() \
+             WHERE user_id = $1 AND confirmed_at IS NULL AND {OPEN_RECOVERY} \
+         ) \
+         INSERT INTO mfa_recoveries (user_id, token_hash, link_expires_at) VALUES ($1, $2, $3) \
+         RETURNING {RECOVERY_COLUMNS}"
+    ))
+    .bind(user_id)
+    .bind(token_hash)
+    .bind(link_expires_at)
+    .fetch_one(conn)
+    .await
+}
+
+/// The user's confirmed recovery that is waiting out its delay (or is due)
+pub async fn find_scheduled_recovery(conn: &mut PgConnection, user_id: Uuid) -> Result<Option<MfaRecovery>, sqlx::Error> {
+    sqlx::query_as::<_, MfaRecovery>(&format!(
+        "SELECT {RECOVERY_COLUMNS} FROM mfa_recoveries \
+         WHERE user_id = $1 AND confirmed_at IS NOT NULL AND {OPEN_RECOVERY} \
+         ORDER BY available_at LIMIT 1"
+    ))
+    .bind(user_id)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Confirm a recovery by its link's token, scheduling MFA removal `delay_hours` from now
+///
+/// Returns `None` for unknown, expired, already confirmed or cancelled requests.
+pub async fn confirm_recovery(
+    conn: &mut PgConnection,
+    token_hash: &str,
+    delay_hours: u64,
+) -> Result<Option<MfaRecovery>, sqlx::Error> {
+    sqlx::query_as::<_, MfaRecovery>(&format!(
+        "UPDATE mfa_recoveries \
+         SET confirmed_at = NOW(), available_at = NOW() + make_interval(hours => $2) \
+         WHERE token_hash = $1 AND confirmed_at IS NULL AND link_expires_at > NOW() AND {OPEN_RECOVERY} \
+         RETURNING {RECOVERY_COLUMNS}"
+    ))
+    .bind(token_hash)
+    .bind(delay_hours as i32)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Complete the user's recovery if its delay has passed, removing their TOTP secret
+pub async fn complete_due_recovery(conn: &mut PgConnection, user_id: Uuid) -> Result<Option<MfaRecovery>, sqlx::Error> {
+    sqlx::query_as::<_, MfaRecovery>(&format!(
+        "WITH due AS ( \
+             UPDATE mfa_recoveries SET completed_at = NOW() \
+             WHERE user_id = $1 AND confirmed_at IS NOT NULL AND available_at <= NOW() AND {OPEN_RECOVERY} \
+             RETURNING {RECOVERY_COLUMNS} \
+         ), removed AS ( \
+             UPDATE users SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_step = NULL, \
+             updated_at = CURRENT_TIMESTAMP \
+             WHERE id = $1 AND EXISTS (SELECT 1 FROM due) \
+         ) \
+         SELECT {RECOVERY_COLUMNS} FROM due"
+    ))
+    .bind(user_id)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Cancel the user's open recovery requests, returning how many there were
+pub async fn cancel_recoveries(conn: &mut PgConnection, user_id: Uuid) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(&format!(
+        "UPDATE mfa_recoveries SET cancelled_at = NOW() WHERE user_id = $1 AND {OPEN_RECOVERY}"
+    ))
+    .bind(user_id)
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Remove the user's TOTP secret on an admin's behalf, recording it as a completed recovery
+///
+/// Open requests are cancelled. Returns `None` if the user had no TOTP secret.
+pub async fn admin_reset(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    admin_id: Uuid,
+    reason: &str,
+) -> Result<Option<MfaRecovery>, sqlx::Error> {
+    sqlx::query_as::<_, MfaRecovery>(&format!(
+        "WITH removed AS ( \
+             UPDATE users SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_step = NULL, \
+             updated_at = CURRENT_TIMESTAMP \
+             WHERE id = $1 AND totp_secret IS NOT NULL \
+             RETURNING id \
+         ), superseded AS ( \
+             UPDATE mfa_recoveries SET cancelled_at = NOW() \
+             WHERE user_id IN (SELECT id FROM removed) AND {OPEN_RECOVERY} \
+         ) \
+         INSERT INTO mfa_recoveries \
+             (user_id, admin_id, reason, link_expires_at, confirmed_at, available_at, completed_at) \
+         SELECT id, $2, $3, NOW(), NOW(), NOW(), NOW() FROM removed \
+         RETURNING {RECOVERY_COLUMNS}"
+    ))
+    .bind(user_id)
+    .bind(admin_id)
+    .bind(reason)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Every recovery of the user, newest first
+pub async fn list_recoveries(conn: &mut PgConnection, user_id: Uuid) -> Result<Vec<MfaRecovery>, sqlx::Error> {
+    sqlx::query_as::<_, MfaRecovery>(&format!(
+        "SELECT {RECOVERY_COLUMNS} FROM mfa_recoveries WHERE user_id = $1 ORDER BY requested_at DESC"
+    ))
+    .bind(user_id)
+    .fetch_all(conn)
+    .await
+}
@@ -0,0 +1,26 @@
+pub mod users;
+pub mod sessions;
+pub mod password_resets;
+pub mod email_verifications;
+pub mod qr_logins;
+pub mod login_attempts;
+pub mod stats;
+pub mod invitations;
+pub mod user_emails;
+pub mod api_keys;
+pub mod permissions;
+pub mod nonces;
+pub mod external_identities;
+pub mod oauth_states;
+pub mod oauth_completions;
+pub mod organizations;
+pub mod oauth_clients;
+pub mod oauth_consents;
+pub mod oauth_codes;
+pub mod mfa;
+pub mod audit;
+pub mod ip_blocks;
+pub mod admin_alerts;
+pub mod plans;
+pub mod settings;
+pub mod sso_mappings;
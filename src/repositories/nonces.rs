@@ -0,0 +1,32 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgConnection;
+
+/// Record that the single-use token `jti` has been redeemed
+///
+/// Returns false if it was redeemed before, so each token works once even
+/// under concurrent requests. Entries are only needed until the token would
+/// have expired anyway; expired ones are purged on the way.
+pub async fn consume(conn: &mut PgConnection, jti: &str, expires_at: DateTime<Utc>) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        WITH purged AS (
+            DELETE FROM token_nonces WHERE expires_at < NOW()
+        )
+        INSERT INTO token_nonces (jti, expires_at) VALUES ($1, $2)
+        ON CONFLICT (jti) DO NOTHING
+        "#,
+    )
+    .bind(jti)
+    .bind(expires_at)
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Whether the single-use token `jti` has been redeemed
+pub async fn is_consumed(conn: &mut PgConnection, jti: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM token_nonces WHERE jti = $1)")
+        .bind(jti)
+        .fetch_one(conn)
+        .await
+}
@@ -0,0 +1,121 @@
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::models::oauth_client::OAuthClient;
+
+const CLIENT_COLUMNS: &str =
+    "id, client_id, client_secret_hash, name, redirect_uris, scopes, logo_url, created_at, updated_at";
+
+/// Register a client; `client_secret_hash` is `None` for public clients
+pub async fn create(
+    conn: &mut PgConnection,
+    client_id: &str,
+    client_secret_hash: Option<&str>,
+    name: &str,
+    redirect_uris: &[String],
+    scopes: &[String],
+    logo_url: Option<&str>,
+) -> Result<OAuthClient, sqlx::Error> {
+    sqlx::query_as::<_, OAuthClient>(&format!(
+        "INSERT INTO oauth_clients (client_id, client_secret_hash, name, redirect_uris, scopes, logo_url) \
+         VALUES ($1, $2, $3, $4, $5, $6) RETURNING {}",
+        CLIENT_COLUMNS
+    ))
+    .bind(client_id)
+    .bind(client_secret_hash)
+    .bind(name)
+    .bind(redirect_uris)
+    .bind(scopes)
+    .bind(logo_url)
+    .fetch_one(conn)
+    .await
+}
+
+/// Every client, oldest first
+pub async fn list(conn: &mut PgConnection) -> Result<Vec<OAuthClient>, sqlx::Error> {
+    sqlx::query_as::<_, OAuthClient>(&format!("SELECT {} FROM oauth_clients ORDER BY created_at", CLIENT_COLUMNS))
+        .fetch_all(conn)
+        .await
+}
+
+pub async fn find_by_id(conn: &mut PgConnection, id: Uuid) -> Result<Option<OAuthClient>, sqlx::Error> {
+    sqlx::query_as::<_, OAuthClient>(&format!("SELECT {} FROM oauth_clients WHERE id = $1", CLIENT_COLUMNS))
+        .bind(id)
+        .fetch_optional(conn)
+        .await
+}
+
+/// Find a client by the public `client_id` it uses in requests
+pub async fn find_by_client_id(conn: &mut PgConnection, client_id: &str) -> Result<Option<OAuthClient>, sqlx::Error> {
+    sqlx::query_as::<_, OAuthClient>(&format!("SELECT {} FROM oauth_clients WHERE client_id = $1", CLIENT_COLUMNS))
+        .bind(client_id)
+        .fetch_optional(conn)
+        .await
+}
+
+/// Change a client's details; `None` keeps a field. Returns `None` if the client doesn't exist
+pub async fn update(
+    conn: &mut PgConnection,
+    id: Uuid,
+    name: Option<&str>,
+    redirect_uris: Option<&[String]>,
+    scopes: Option<&[String]>,
+    logo_url: Option<Option<&str>>,
+) -> Result<Option<OAuthClient>, sqlx::Error> {
+    sqlx::query_as::<_, OAuthClient>(&format!(
+        r#"
+        UPDATE oauth_clients SET
+            name = COALESCE($2, name),
+            redirect_uris = COALESCE($3, redirect_uris),
+            scopes = COALESCE($4, scopes),
+            logo_url = CASE WHEN $5 THEN $6 ELSE logo_url END,
+            updated_at = CURRENT_TIMESTAMP
+        WHERE id = $1
+        RETURNING {}
+        "#,
+        CLIENT_COLUMNS
+    ))
+    .bind(id)
+    .bind(name)
+    .bind(redirect_uris)
+    .bind(scopes)
+    .bind(logo_url.is_some())
+    .bind(logo_url.flatten())
+    .fetch_optional(conn)
+    .await
+}
+
+/// Let a dynamically registered client manage its registration with a token (stored hashed)
+pub async fn set_registration_token(conn: &mut PgConnection, id: Uuid, token_hash: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE oauth_clients SET registration_token_hash = $2 WHERE id = $1")
+        .bind(id)
+        .bind(token_hash)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Find a dynamically registered client by its `client_id` and registration access token hash
+pub async fn find_by_registration_token(
+    conn: &mut PgConnection,
+    client_id: &str,
+    token_hash: &str,
+) -> Result<Option<OAuthClient>, sqlx::Error> {
+    sqlx::query_as::<_, OAuthClient>(&format!(
+        "SELECT {} FROM oauth_clients WHERE client_id = $1 AND registration_token_hash = $2",
+        CLIENT_COLUMNS
+    ))
+    .bind(client_id)
+    .bind(token_hash)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Delete a client with its consents, codes and sessions; returns false if it doesn't exist
+pub async fn delete(conn: &mut PgConnection, id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM oauth_clients WHERE id = $1")
+        .bind(id)
+        .execute(conn)
+        .await?;
+    Ok(result.rows_affected() == 1)
+}
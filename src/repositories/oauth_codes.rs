@@ -0,0 +1,51 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::auth::tokens;
+use crate::models::oauth_client::AuthorizationCode;
+
+/// Store an authorization code (hashed) for the token endpoint; expired codes are purged on the way
+#[allow(clippy::too_many_arguments)]
+pub async fn create(
+    conn: &mut PgConnection,
+    code: &str,
+    client_id: Uuid,
+    user_id: Uuid,
+    redirect_uri: &str,
+    scopes: &[String],
+    code_challenge: Option<&str>,
+    expires_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        WITH purged AS (
+            DELETE FROM oauth_authorization_codes WHERE expires_at < NOW()
+        )
+        INSERT INTO oauth_authorization_codes (code_hash, client_id, user_id, redirect_uri, scopes, code_challenge, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+    )
+    .bind(tokens::hash(code))
+    .bind(client_id)
+    .bind(user_id)
+    .bind(redirect_uri)
+    .bind(scopes)
+    .bind(code_challenge)
+    .bind(expires_at)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Consume a code issued to the client; `None` if it's unknown, expired, already used or another client's
+pub async fn take(conn: &mut PgConnection, code: &str, client_id: Uuid) -> Result<Option<AuthorizationCode>, sqlx::Error> {
+    sqlx::query_as::<_, AuthorizationCode>(
+        "DELETE FROM oauth_authorization_codes WHERE code_hash = $1 AND client_id = $2 AND expires_at > NOW() \
+         RETURNING user_id, redirect_uri, scopes, code_challenge",
+    )
+    .bind(tokens::hash(code))
+    .bind(client_id)
+    .fetch_optional(conn)
+    .await
+}
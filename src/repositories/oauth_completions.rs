@@ -0,0 +1,55 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgConnection;
+
+use crate::auth::tokens;
+use crate::models::oauth::OAuthCompletion;
+
+/// Hold on to a verified identity until the user supplies an email for it
+///
+/// Only the token's hash is stored; expired entries are purged on the way.
+pub async fn create(
+    conn: &mut PgConnection,
+    token: &str,
+    provider: &str,
+    issuer: &str,
+    subject: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        WITH purged AS (
+            DELETE FROM oauth_completions WHERE expires_at < NOW()
+        )
+        INSERT INTO oauth_completions (token_hash, provider, issuer, subject, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+    )
+    .bind(tokens::hash(token))
+    .bind(provider)
+    .bind(issuer)
+    .bind(subject)
+    .bind(expires_at)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// A pending completion; `None` if the token is unknown, expired or from another provider
+pub async fn find(conn: &mut PgConnection, token: &str, provider: &str) -> Result<Option<OAuthCompletion>, sqlx::Error> {
+    sqlx::query_as::<_, OAuthCompletion>(
+        "SELECT issuer, subject FROM oauth_completions WHERE token_hash = $1 AND provider = $2 AND expires_at > NOW()",
+    )
+    .bind(tokens::hash(token))
+    .bind(provider)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Drop a completion once its identity is linked
+pub async fn delete(conn: &mut PgConnection, token: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM oauth_completions WHERE token_hash = $1")
+        .bind(tokens::hash(token))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
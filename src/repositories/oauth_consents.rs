@@ -0,0 +1,61 @@
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::models::oauth_client::AuthorizedApplication;
+
+/// The scopes a user has granted a client, if any
+pub async fn granted_scopes(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    client_id: Uuid,
+) -> Result<Option<Vec<String>>, sqlx::Error> {
+    sqlx::query_scalar::<_, Vec<String>>("SELECT scopes FROM oauth_consents WHERE user_id = $1 AND client_id = $2")
+        .bind(user_id)
+        .bind(client_id)
+        .fetch_optional(conn)
+        .await
+}
+
+/// Record that a user granted a client these scopes, on top of any granted before
+pub async fn grant(conn: &mut PgConnection, user_id: Uuid, client_id: Uuid, scopes: &[String]) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO oauth_consents (user_id, client_id, scopes) VALUES ($1, $2, $3)
+        ON CONFLICT (user_id, client_id) DO UPDATE SET
+            scopes = ARRAY(SELECT DISTINCT unnest(oauth_consents.scopes || EXCLUDED.scopes) ORDER BY 1),
+            updated_at = CURRENT_TIMESTAMP
+        "#,
+    )
+    .bind(user_id)
+    .bind(client_id)
+    .bind(scopes)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// The applications a user has authorized, most recently changed first
+pub async fn list_for_user(conn: &mut PgConnection, user_id: Uuid) -> Result<Vec<AuthorizedApplication>, sqlx::Error> {
+    sqlx::query_as::<_, AuthorizedApplication>(
+        r#"
+        SELECT c.client_id, c.name, c.logo_url, oc.scopes, oc.created_at, oc.updated_at
+        FROM oauth_consents oc
+        JOIN oauth_clients c ON c.id = oc.client_id
+        WHERE oc.user_id = $1
+        ORDER BY oc.updated_at DESC
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(conn)
+    .await
+}
+
+/// Withdraw a user's consent; returns false if they hadn't authorized the client
+pub async fn revoke(conn: &mut PgConnection, user_id: Uuid, client_id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM oauth_consents WHERE user_id = $1 AND client_id = $2")
+        .bind(user_id)
+        .bind(client_id)
+        .execute(conn)
+        .await?;
+    Ok(result.rows_affected() == 1)
+}
@@ -0,0 +1,49 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgConnection;
+
+use crate::auth::tokens;
+use crate::models::oauth::OAuthState;
+
+/// Remember a sign-in started at `provider` until the browser comes back with its state
+///
+/// Only the state's hash is stored; expired entries are purged on the way.
+pub async fn create(
+    conn: &mut PgConnection,
+    state: &str,
+    provider: &str,
+    code_verifier: &str,
+    nonce: &str,
+    redirect_to: Option<&str>,
+    expires_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        WITH purged AS (
+            DELETE FROM oauth_states WHERE expires_at < NOW()
+        )
+        INSERT INTO oauth_states (state_hash, provider, code_verifier, nonce, redirect_to, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+    )
+    .bind(tokens::hash(state))
+    .bind(provider)
+    .bind(code_verifier)
+    .bind(nonce)
+    .bind(redirect_to)
+    .bind(expires_at)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Consume a pending sign-in; `None` if the state is unknown, expired, already used or from another provider
+pub async fn take(conn: &mut PgConnection, state: &str, provider: &str) -> Result<Option<OAuthState>, sqlx::Error> {
+    sqlx::query_as::<_, OAuthState>(
+        "DELETE FROM oauth_states WHERE state_hash = $1 AND provider = $2 AND expires_at > NOW() \
+         RETURNING code_verifier, nonce, redirect_to",
+    )
+    .bind(tokens::hash(state))
+    .bind(provider)
+    .fetch_optional(conn)
+    .await
+}
@@ -0,0 +1,354 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::models::organization::{CustomOrgRole, OrgDomain, OrgSigningKey, OrgSso, SsoRequirement};
+
+const DOMAIN_COLUMNS: &str =
+    "id, organization_id, domain, join_mode, verification_token, verified_at, last_checked_at, created_at";
+
+const ROLE_COLUMNS: &str = "organization_id, name, permissions, created_at, updated_at";
+
+const SIGNING_KEY_COLUMNS: &str = "id, organization_id, kid, secret, created_at, revoked_at";
+
+/// `SIGNING_KEY_COLUMNS` of the key aliased `k`, for queries joining other tables
+const SIGNING_KEY_COLUMNS_K: &str = "k.id, k.organization_id, k.kid, k.secret, k.created_at, k.revoked_at";
+
+/// The organization of a Microsoft Entra tenant, created the first time someone from it signs in
+pub async fn find_or_create_for_tenant(conn: &mut PgConnection, tenant_id: &str) -> Result<Uuid, sqlx::Error> {
+    sqlx::query_scalar::<_, Uuid>(
+        r#"
+        WITH inserted AS (
+            INSERT INTO organizations (name, microsoft_tenant_id) VALUES ($1, $1)
+            ON CONFLICT (microsoft_tenant_id) DO NOTHING
+            RETURNING id
+        )
+        SELECT id FROM inserted
+        UNION ALL
+        SELECT id FROM organizations WHERE microsoft_tenant_id = $1
+        LIMIT 1
+        "#,
+    )
+    .bind(tenant_id)
+    .fetch_one(conn)
+    .await
+}
+
+/// Create an organization that isn't tied to a directory
+pub async fn create(conn: &mut PgConnection, name: &str) -> Result<Uuid, sqlx::Error> {
+    sqlx::query_scalar::<_, Uuid>("INSERT INTO organizations (name) VALUES ($1) RETURNING id")
+        .bind(name)
+        .fetch_one(conn)
+        .await
+}
+
+/// Store a new signing key for an organization; the newest unrevoked key signs its members' tokens
+pub async fn create_signing_key(
+    conn: &mut PgConnection,
+    organization_id: Uuid,
+    kid: &str,
+    secret: &str,
+) -> Result<OrgSigningKey, sqlx::Error> {
+    sqlx::query_as::<_, OrgSigningKey>(&format!(
+        "INSERT INTO organization_signing_keys (organization_id, kid, secret) VALUES ($1, $2, $3) RETURNING {}",
+        SIGNING_KEY_COLUMNS
+    ))
+    .bind(organization_id)
+    .bind(kid)
+    .bind(secret)
+    .fetch_one(conn)
+    .await
+}
+
+/// An organization's signing keys, revoked ones included, newest first
+pub async fn list_signing_keys(conn: &mut PgConnection, organization_id: Uuid) -> Result<Vec<OrgSigningKey>, sqlx::Error> {
+    sqlx::query_as::<_, OrgSigningKey>(&format!(
+        "SELECT {} FROM organization_signing_keys WHERE organization_id = $1 ORDER BY created_at DESC",
+        SIGNING_KEY_COLUMNS
+    ))
+    .bind(organization_id)
+    .fetch_all(conn)
+    .await
+}
+
+/// Revoke one of an organization's signing keys; returns false if there's no such unrevoked key
+pub async fn revoke_signing_key(conn: &mut PgConnection, organization_id: Uuid, id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE organization_signing_keys SET revoked_at = CURRENT_TIMESTAMP \
+         WHERE id = $1 AND organization_id = $2 AND revoked_at IS NULL",
+    )
+    .bind(id)
+    .bind(organization_id)
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// The key signing `user_id`'s tokens: the newest unrevoked key of their organization
+pub async fn signing_key_for_user(conn: &mut PgConnection, user_id: Uuid) -> Result<Option<OrgSigningKey>, sqlx::Error> {
+    sqlx::query_as::<_, OrgSigningKey>(&format!(
+        "SELECT {} FROM organization_signing_keys k \
+         WHERE k.organization_id = (SELECT organization_id FROM users WHERE id = $1) AND k.revoked_at IS NULL \
+         ORDER BY k.created_at DESC LIMIT 1",
+        SIGNING_KEY_COLUMNS_K
+    ))
+    .bind(user_id)
+    .fetch_optional(conn)
+    .await
+}
+
+/// The unrevoked key `kid`, if it belongs to `user_id`'s organization
+///
+/// A key only verifies tokens of its own organization's members, so one
+/// organization's leaked key can't forge tokens for anyone else.
+pub async fn verifying_key(conn: &mut PgConnection, kid: &str, user_id: Uuid) -> Result<Option<OrgSigningKey>, sqlx::Error> {
+    sqlx::query_as::<_, OrgSigningKey>(&format!(
+        "SELECT {} FROM organization_signing_keys k JOIN users u ON u.organization_id = k.organization_id \
+         WHERE k.kid = $1 AND u.id = $2 AND k.revoked_at IS NULL",
+        SIGNING_KEY_COLUMNS_K
+    ))
+    .bind(kid)
+    .bind(user_id)
+    .fetch_optional(conn)
+    .await
+}
+
+pub async fn exists(conn: &mut PgConnection, id: Uuid) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM organizations WHERE id = $1)")
+        .bind(id)
+        .fetch_one(conn)
+        .await
+}
+
+/// The roles an organization defined, by name
+pub async fn list_roles(conn: &mut PgConnection, organization_id: Uuid) -> Result<Vec<CustomOrgRole>, sqlx::Error> {
+    sqlx::query_as::<_, CustomOrgRole>(&format!(
+        "SELECT {} FROM organization_roles WHERE organization_id = $1 ORDER BY name",
+        ROLE_COLUMNS
+    ))
+    .bind(organization_id)
+    .fetch_all(conn)
+    .await
+}
+
+pub async fn find_role(conn: &mut PgConnection, organization_id: Uuid, name: &str) -> Result<Option<CustomOrgRole>, sqlx::Error> {
+    sqlx::query_as::<_, CustomOrgRole>(&format!(
+        "SELECT {} FROM organization_roles WHERE organization_id = $1 AND name = $2",
+        ROLE_COLUMNS
+    ))
+    .bind(organization_id)
+    .bind(name)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Define a role; `None` if the organization has one by that name already
+pub async fn create_role(
+    conn: &mut PgConnection,
+    organization_id: Uuid,
+    name: &str,
+    permissions: &[String],
+) -> Result<Option<CustomOrgRole>, sqlx::Error> {
+    sqlx::query_as::<_, CustomOrgRole>(&format!(
+        "INSERT INTO organization_roles (organization_id, name, permissions) VALUES ($1, $2, $3) \
+         ON CONFLICT DO NOTHING RETURNING {}",
+        ROLE_COLUMNS
+    ))
+    .bind(organization_id)
+    .bind(name)
+    .bind(permissions)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Replace what a role allows; `None` if there's no such role
+pub async fn update_role(
+    conn: &mut PgConnection,
+    organization_id: Uuid,
+    name: &str,
+    permissions: &[String],
+) -> Result<Option<CustomOrgRole>, sqlx::Error> {
+    sqlx::query_as::<_, CustomOrgRole>(&format!(
+        "UPDATE organization_roles SET permissions = $3, updated_at = CURRENT_TIMESTAMP \
+         WHERE organization_id = $1 AND name = $2 RETURNING {}",
+        ROLE_COLUMNS
+    ))
+    .bind(organization_id)
+    .bind(name)
+    .bind(permissions)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Delete a role nobody has; returns false if there's no such role
+pub async fn delete_role(conn: &mut PgConnection, organization_id: Uuid, name: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM organization_roles WHERE organization_id = $1 AND name = $2")
+        .bind(organization_id)
+        .bind(name)
+        .execute(conn)
+        .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Members of the organization who have the role `name`
+pub async fn count_with_role(conn: &mut PgConnection, organization_id: Uuid, name: &str) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE organization_id = $1 AND organization_role = $2")
+        .bind(organization_id)
+        .bind(name)
+        .fetch_one(conn)
+        .await
+}
+
+/// The domains an organization claimed, verified or not
+pub async fn list_domains(conn: &mut PgConnection, organization_id: Uuid) -> Result<Vec<OrgDomain>, sqlx::Error> {
+    sqlx::query_as::<_, OrgDomain>(&format!(
+        "SELECT {} FROM organization_domains WHERE organization_id = $1 ORDER BY domain",
+        DOMAIN_COLUMNS
+    ))
+    .bind(organization_id)
+    .fetch_all(conn)
+    .await
+}
+
+pub async fn find_domain(conn: &mut PgConnection, organization_id: Uuid, id: Uuid) -> Result<Option<OrgDomain>, sqlx::Error> {
+    sqlx::query_as::<_, OrgDomain>(&format!(
+        "SELECT {} FROM organization_domains WHERE organization_id = $1 AND id = $2",
+        DOMAIN_COLUMNS
+    ))
+    .bind(organization_id)
+    .bind(id)
+    .fetch_optional(conn)
+    .await
+}
+
+/// The organization that verified `domain`, if one did
+pub async fn find_verified_domain(conn: &mut PgConnection, domain: &str) -> Result<Option<OrgDomain>, sqlx::Error> {
+    sqlx::query_as::<_, OrgDomain>(&format!(
+        "SELECT {} FROM organization_domains WHERE domain = $1 AND verified_at IS NOT NULL",
+        DOMAIN_COLUMNS
+    ))
+    .bind(domain)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Claim a domain, unverified; `None` if the organization claimed it already
+pub async fn create_domain(
+    conn: &mut PgConnection,
+    organization_id: Uuid,
+    domain: &str,
+    join_mode: &str,
+    verification_token: &str,
+) -> Result<Option<OrgDomain>, sqlx::Error> {
+    sqlx::query_as::<_, OrgDomain>(&format!(
+        "INSERT INTO organization_domains (organization_id, domain, join_mode, verification_token) VALUES ($1, $2, $3, $4) \
+         ON CONFLICT (organization_id, domain) DO NOTHING RETURNING {}",
+        DOMAIN_COLUMNS
+    ))
+    .bind(organization_id)
+    .bind(domain)
+    .bind(join_mode)
+    .bind(verification_token)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Drop a claimed domain; returns false if there's no such domain
+pub async fn delete_domain(conn: &mut PgConnection, organization_id: Uuid, id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM organization_domains WHERE organization_id = $1 AND id = $2")
+        .bind(organization_id)
+        .bind(id)
+        .execute(conn)
+        .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Record a look for the domain's challenge, and whether it was found
+///
+/// A domain another organization verified first stays unverified; returns
+/// the domain as stored afterwards.
+pub async fn record_domain_check(conn: &mut PgConnection, id: Uuid, found: bool) -> Result<OrgDomain, sqlx::Error> {
+    sqlx::query_as::<_, OrgDomain>(&format!(
+        "UPDATE organization_domains d SET last_checked_at = CURRENT_TIMESTAMP, \
+         verified_at = CASE WHEN $2 AND NOT EXISTS ( \
+             SELECT 1 FROM organization_domains o WHERE o.domain = d.domain AND o.id <> d.id AND o.verified_at IS NOT NULL \
+         ) THEN COALESCE(d.verified_at, CURRENT_TIMESTAMP) ELSE d.verified_at END \
+         WHERE id = $1 RETURNING {}",
+        DOMAIN_COLUMNS
+    ))
+    .bind(id)
+    .bind(found)
+    .fetch_one(conn)
+    .await
+}
+
+/// Unverified domains claimed since `claimed_after` and not checked since `checked_before`, oldest check first
+pub async fn unverified_domains(
+    conn: &mut PgConnection,
+    claimed_after: DateTime<Utc>,
+    checked_before: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<OrgDomain>, sqlx::Error> {
+    sqlx::query_as::<_, OrgDomain>(&format!(
+        "SELECT {} FROM organization_domains \
+         WHERE verified_at IS NULL AND created_at > $1 AND (last_checked_at IS NULL OR last_checked_at < $2) \
+         ORDER BY last_checked_at NULLS FIRST LIMIT $3",
+        DOMAIN_COLUMNS
+    ))
+    .bind(claimed_after)
+    .bind(checked_before)
+    .bind(limit)
+    .fetch_all(conn)
+    .await
+}
+
+/// The organization's sign-in provider; `None` if there's no such organization
+pub async fn sso(conn: &mut PgConnection, organization_id: Uuid) -> Result<Option<OrgSso>, sqlx::Error> {
+    sqlx::query_as::<_, OrgSso>("SELECT sso_provider, sso_enforced FROM organizations WHERE id = $1")
+        .bind(organization_id)
+        .fetch_optional(conn)
+        .await
+}
+
+pub async fn set_sso(
+    conn: &mut PgConnection,
+    organization_id: Uuid,
+    provider: Option<&str>,
+    enforced: bool,
+) -> Result<OrgSso, sqlx::Error> {
+    sqlx::query_as::<_, OrgSso>(
+        "UPDATE organizations SET sso_provider = $2, sso_enforced = $3 WHERE id = $1 RETURNING sso_provider, sso_enforced"
+    )
+    .bind(organization_id)
+    .bind(provider)
+    .bind(enforced)
+    .fetch_one(conn)
+    .await
+}
+
+/// The organization enforcing single sign-on for a user, if any
+///
+/// That's the user's own organization, or else the one that verified the
+/// domain of their address.
+pub async fn sso_requirement(
+    conn: &mut PgConnection,
+    organization_id: Option<Uuid>,
+    domain: &str,
+) -> Result<Option<SsoRequirement>, sqlx::Error> {
+    sqlx::query_as::<_, SsoRequirement>(
+        r#"
+        SELECT o.id AS organization_id, o.sso_provider AS provider
+        FROM organizations o
+        WHERE o.sso_enforced AND o.sso_provider IS NOT NULL
+          AND (o.id = $1 OR o.id IN (
+              SELECT organization_id FROM organization_domains WHERE domain = $2 AND verified_at IS NOT NULL
+          ))
+        ORDER BY o.id = $1 DESC NULLS LAST
+        LIMIT 1
+        "#,
+    )
+    .bind(organization_id)
+    .bind(domain)
+    .fetch_optional(conn)
+    .await
+}
@@ -0,0 +1,44 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::auth::tokens;
+use crate::models::password_reset::PasswordResetToken;
+
+/// Store a new reset token for a user (only its hash is kept)
+pub async fn create(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    token: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO password_reset_tokens (user_id, token_hash, expires_at) VALUES ($1, $2, $3)")
+        .bind(user_id)
+        .bind(tokens::hash(token))
+        .bind(expires_at)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Look up a reset token by its plaintext value
+pub async fn find_by_token(
+    conn: &mut PgConnection,
+    token: &str,
+) -> Result<Option<PasswordResetToken>, sqlx::Error> {
+    sqlx::query_as::<_, PasswordResetToken>(
+        "SELECT id, user_id, token_hash, expires_at, used, created_at FROM password_reset_tokens WHERE token_hash = $1"
+    )
+    .bind(tokens::hash(token))
+    .fetch_optional(conn)
+    .await
+}
+
+/// Mark a reset token as used so it can't be replayed; false if it already was
+pub async fn mark_used(conn: &mut PgConnection, token: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("UPDATE password_reset_tokens SET used = TRUE WHERE token_hash = $1 AND used = FALSE")
+        .bind(tokens::hash(token))
+        .execute(conn)
+        .await?;
+    Ok(result.rows_affected() == 1)
+}
@@ -0,0 +1,104 @@
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::models::permission::Permission;
+
+/// Every permission with the roles granted it, by name
+pub async fn list(conn: &mut PgConnection) -> Result<Vec<Permission>, sqlx::Error> {
+    sqlx::query_as::<_, Permission>(
+        r#"
+        SELECT p.name, p.description, p.created_at,
+               COALESCE(array_agg(rp.role ORDER BY rp.role) FILTER (WHERE rp.role IS NOT NULL), '{}') AS roles
+        FROM permissions p
+        LEFT JOIN role_permissions rp ON rp.permission = p.name
+        GROUP BY p.name
+        ORDER BY p.name
+        "#,
+    )
+    .fetch_all(conn)
+    .await
+}
+
+/// Create a permission; returns false if one with this name exists
+pub async fn create(conn: &mut PgConnection, name: &str, description: Option<&str>) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "INSERT INTO permissions (name, description) VALUES ($1, $2) ON CONFLICT (name) DO NOTHING"
+    )
+    .bind(name)
+    .bind(description)
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Delete a permission and its role assignments; returns false if it doesn't exist
+pub async fn delete(conn: &mut PgConnection, name: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM permissions WHERE name = $1")
+        .bind(name)
+        .execute(conn)
+        .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Check whether a permission exists
+pub async fn exists(conn: &mut PgConnection, name: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM permissions WHERE name = $1)")
+        .bind(name)
+        .fetch_one(conn)
+        .await
+}
+
+/// Grant a permission to a role (no-op if already granted)
+pub async fn grant(conn: &mut PgConnection, role: &str, permission: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO role_permissions (role, permission) VALUES ($1, $2) ON CONFLICT DO NOTHING"
+    )
+    .bind(role)
+    .bind(permission)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Take a permission away from a role; returns false if the role didn't have it
+pub async fn revoke(conn: &mut PgConnection, role: &str, permission: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM role_permissions WHERE role = $1 AND permission = $2")
+        .bind(role)
+        .bind(permission)
+        .execute(conn)
+        .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Check whether a user's role has a permission
+pub async fn user_has(conn: &mut PgConnection, user_id: Uuid, permission: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS (SELECT 1 FROM users u JOIN role_permissions rp ON rp.role = u.role \
+         WHERE u.id = $1 AND rp.permission = $2)"
+    )
+    .bind(user_id)
+    .bind(permission)
+    .fetch_one(conn)
+    .await
+}
+
+/// A user's role, the permissions granted to it and their subscription status; `None` if the user doesn't exist
+pub async fn role_and_permissions(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+) -> Result<Option<(String, Vec<String>, Option<String>)>, sqlx::Error> {
+    sqlx::query_as::<_, (String, Vec<String>, Option<String>)>(
+        r#"
+        SELECT u.role,
+               COALESCE(array_agg(rp.permission ORDER BY rp.permission) FILTER (WHERE rp.permission IS NOT NULL), '{}'),
+               u.subscription_status
+        FROM users u
+        LEFT JOIN role_permissions rp ON rp.role = u.role
+        WHERE u.id = $1
+        GROUP BY u.id, u.role
+        "#,
+    )
+    .bind(user_id)
+    .fetch_optional(conn)
+    .await
+}
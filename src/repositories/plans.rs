@@ -0,0 +1,101 @@
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::models::plan::{Entitlement, NewEntitlement, Plan};
+
+const ENTITLEMENT_COLUMNS: &str = "id, plan, user_id, organization_id, starts_at, ends_at, created_at";
+
+/// Entitlements in effect now for the user, directly or through their organization, aliased `e`
+const ACTIVE_FOR_USER: &str = "(e.user_id = $1 OR e.organization_id = (SELECT organization_id FROM users WHERE id = $1)) \
+     AND e.starts_at <= NOW() AND (e.ends_at IS NULL OR e.ends_at > NOW())";
+
+/// Every plan, lowest first
+pub async fn list(conn: &mut PgConnection) -> Result<Vec<Plan>, sqlx::Error> {
+    sqlx::query_as::<_, Plan>("SELECT name, description, rank, created_at FROM plans ORDER BY rank, name")
+        .fetch_all(conn)
+        .await
+}
+
+pub async fn exists(conn: &mut PgConnection, name: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM plans WHERE name = $1)")
+        .bind(name)
+        .fetch_one(conn)
+        .await
+}
+
+/// Create a plan; returns false if one with this name exists
+pub async fn create(conn: &mut PgConnection, name: &str, description: Option<&str>, rank: i32) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("INSERT INTO plans (name, description, rank) VALUES ($1, $2, $3) ON CONFLICT (name) DO NOTHING")
+        .bind(name)
+        .bind(description)
+        .bind(rank)
+        .execute(conn)
+        .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// The highest plan the user has now, directly or through their organization
+pub async fn active_plan(conn: &mut PgConnection, user_id: Uuid) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar(&format!(
+        "SELECT p.name FROM entitlements e JOIN plans p ON p.name = e.plan WHERE {} ORDER BY p.rank DESC LIMIT 1",
+        ACTIVE_FOR_USER
+    ))
+    .bind(user_id)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Whether the user has `plan` or a higher one now; false for plans that don't exist
+pub async fn has_plan(conn: &mut PgConnection, user_id: Uuid, plan: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar(&format!(
+        "SELECT EXISTS (SELECT 1 FROM entitlements e JOIN plans p ON p.name = e.plan \
+         WHERE {} AND p.rank >= (SELECT rank FROM plans WHERE name = $2))",
+        ACTIVE_FOR_USER
+    ))
+    .bind(user_id)
+    .bind(plan)
+    .fetch_one(conn)
+    .await
+}
+
+pub async fn grant(conn: &mut PgConnection, entitlement: &NewEntitlement) -> Result<Entitlement, sqlx::Error> {
+    sqlx::query_as::<_, Entitlement>(&format!(
+        "INSERT INTO entitlements (plan, user_id, organization_id, starts_at, ends_at) \
+         VALUES ($1, $2, $3, COALESCE($4, NOW()), $5) RETURNING {}",
+        ENTITLEMENT_COLUMNS
+    ))
+    .bind(&entitlement.plan)
+    .bind(entitlement.user_id)
+    .bind(entitlement.organization_id)
+    .bind(entitlement.starts_at)
+    .bind(entitlement.ends_at)
+    .fetch_one(conn)
+    .await
+}
+
+/// Entitlements of a user or an organization, newest first
+pub async fn list_entitlements(
+    conn: &mut PgConnection,
+    user_id: Option<Uuid>,
+    organization_id: Option<Uuid>,
+) -> Result<Vec<Entitlement>, sqlx::Error> {
+    sqlx::query_as::<_, Entitlement>(&format!(
+        "SELECT {} FROM entitlements \
+         WHERE ($1::uuid IS NULL OR user_id = $1) AND ($2::uuid IS NULL OR organization_id = $2) \
+         ORDER BY created_at DESC",
+        ENTITLEMENT_COLUMNS
+    ))
+    .bind(user_id)
+    .bind(organization_id)
+    .fetch_all(conn)
+    .await
+}
+
+/// Delete an entitlement; returns false if it doesn't exist
+pub async fn revoke(conn: &mut PgConnection, id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM entitlements WHERE id = $1")
+        .bind(id)
+        .execute(conn)
+        .await?;
+    Ok(result.rows_affected() == 1)
+}
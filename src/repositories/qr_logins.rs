@@ -0,0 +1,74 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::auth::tokens;
+use crate::models::qr_login::QrLoginRequest;
+
+const QR_LOGIN_COLUMNS: &str =
+    "id, code, poll_token_hash, approved_user_id, approved_at, consumed_at, expires_at, created_at";
+
+/// Store a new pending QR login
+pub async fn create(
+    conn: &mut PgConnection,
+    code: &str,
+    poll_token: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<QrLoginRequest, sqlx::Error> {
+    sqlx::query_as::<_, QrLoginRequest>(&format!(
+        "INSERT INTO qr_login_requests (code, poll_token_hash, expires_at) VALUES ($1, $2, $3) RETURNING {}",
+        QR_LOGIN_COLUMNS
+    ))
+    .bind(code)
+    .bind(tokens::hash(poll_token))
+    .bind(expires_at)
+    .fetch_one(conn)
+    .await
+}
+
+/// Approve a pending, unexpired QR login on behalf of a user
+///
+/// Returns false if the code is unknown, expired, or already approved.
+pub async fn approve(conn: &mut PgConnection, code: &str, user_id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        UPDATE qr_login_requests SET approved_user_id = $2, approved_at = NOW()
+        WHERE code = $1 AND approved_user_id IS NULL AND expires_at > NOW()
+        "#,
+    )
+    .bind(code)
+    .bind(user_id)
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Look up a QR login by the requesting device's poll token
+pub async fn find_by_poll_token(
+    conn: &mut PgConnection,
+    poll_token: &str,
+) -> Result<Option<QrLoginRequest>, sqlx::Error> {
+    sqlx::query_as::<_, QrLoginRequest>(&format!(
+        "SELECT {} FROM qr_login_requests WHERE poll_token_hash = $1",
+        QR_LOGIN_COLUMNS
+    ))
+    .bind(tokens::hash(poll_token))
+    .fetch_optional(conn)
+    .await
+}
+
+/// Mark an approved QR login as consumed, returning the approving user
+///
+/// Only one caller can consume a request, so the JWT is issued at most once.
+pub async fn consume(conn: &mut PgConnection, poll_token: &str) -> Result<Option<Uuid>, sqlx::Error> {
+    sqlx::query_scalar::<_, Uuid>(
+        r#"
+        UPDATE qr_login_requests SET consumed_at = NOW()
+        WHERE poll_token_hash = $1 AND approved_user_id IS NOT NULL AND consumed_at IS NULL AND expires_at > NOW()
+        RETURNING approved_user_id
+        "#,
+    )
+    .bind(tokens::hash(poll_token))
+    .fetch_optional(conn)
+    .await
+}
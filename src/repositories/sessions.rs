@@ -0,0 +1,321 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::models::session::{ClientGrant, CountrySighting, Session, SessionDevice};
+
+const SESSION_COLUMNS: &str = "id, user_id, oauth_client_id, scopes, audience, created_at, last_used_at, expires_at, revoked_at, \
+     user_agent, browser, os, device_type, city, country";
+
+/// Start a new session for a user
+pub async fn create(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    expires_at: DateTime<Utc>,
+    device: &SessionDevice,
+) -> Result<Session, sqlx::Error> {
+    sqlx::query_as::<_, Session>(&format!(
+        "INSERT INTO sessions (user_id, expires_at, user_agent, browser, os, device_type, city, country) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING {}",
+        SESSION_COLUMNS
+    ))
+    .bind(user_id)
+    .bind(expires_at)
+    .bind(&device.user_agent)
+    .bind(&device.browser)
+    .bind(&device.os)
+    .bind(&device.device_type)
+    .bind(&device.city)
+    .bind(&device.country)
+    .fetch_one(conn)
+    .await
+}
+
+/// Start a new session whose opaque access token has this hash
+pub async fn create_with_token(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    expires_at: DateTime<Utc>,
+    device: &SessionDevice,
+    token_hash: &str,
+) -> Result<Session, sqlx::Error> {
+    sqlx::query_as::<_, Session>(&format!(
+        "INSERT INTO sessions (user_id, expires_at, user_agent, browser, os, device_type, city, country, token_hash) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING {}",
+        SESSION_COLUMNS
+    ))
+    .bind(user_id)
+    .bind(expires_at)
+    .bind(&device.user_agent)
+    .bind(&device.browser)
+    .bind(&device.os)
+    .bind(&device.device_type)
+    .bind(&device.city)
+    .bind(&device.country)
+    .bind(token_hash)
+    .fetch_one(conn)
+    .await
+}
+
+/// Start a session an OAuth client holds for a user, limited to the granted scopes
+///
+/// `token_hash` is set when the client's access token is opaque.
+pub async fn create_for_client(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    grant: &ClientGrant<'_>,
+    token_hash: Option<&str>,
+) -> Result<Session, sqlx::Error> {
+    sqlx::query_as::<_, Session>(&format!(
+        "INSERT INTO sessions (user_id, oauth_client_id, scopes, audience, expires_at, token_hash) \
+         VALUES ($1, $2, $3, $4, $5, $6) RETURNING {}",
+        SESSION_COLUMNS
+    ))
+    .bind(user_id)
+    .bind(grant.oauth_client_id)
+    .bind(grant.scopes)
+    .bind(grant.audience)
+    .bind(grant.expires_at)
+    .bind(token_hash)
+    .fetch_one(conn)
+    .await
+}
+
+/// Find the active session behind an opaque access token, with whether its user is a guest
+///
+/// Sessions idle since before `active_since` count as expired.
+pub async fn find_active_by_token_hash(
+    conn: &mut PgConnection,
+    token_hash: &str,
+    active_since: Option<DateTime<Utc>>,
+) -> Result<Option<(Session, bool)>, sqlx::Error> {
+    #[allow(clippy::type_complexity)]
+    let row = sqlx::query_as::<_, (Uuid, Uuid, Option<Uuid>, Option<Vec<String>>, Option<String>, DateTime<Utc>, DateTime<Utc>, DateTime<Utc>, Option<DateTime<Utc>>, bool)>(
+        r#"
+        SELECT s.id, s.user_id, s.oauth_client_id, s.scopes, s.audience, s.created_at, s.last_used_at, s.expires_at, s.revoked_at, u.role = 'guest'
+        FROM sessions s
+        JOIN users u ON u.id = s.user_id
+        WHERE s.token_hash = $1 AND s.revoked_at IS NULL AND s.expires_at > CURRENT_TIMESTAMP
+          AND ($2::TIMESTAMPTZ IS NULL OR s.last_used_at > $2)
+        "#,
+    )
+    .bind(token_hash)
+    .bind(active_since)
+    .fetch_optional(conn)
+    .await?;
+
+    Ok(row.map(|(id, user_id, oauth_client_id, scopes, audience, created_at, last_used_at, expires_at, revoked_at, guest)| {
+        let session = Session {
+            id,
+            user_id,
+            oauth_client_id,
+            scopes,
+            audience,
+            created_at,
+            last_used_at,
+            expires_at,
+            revoked_at,
+            // Resolving a token doesn't need the client it was issued to
+            device: SessionDevice::default(),
+        };
+        (session, guest)
+    }))
+}
+
+/// Find a session of the user that's neither revoked nor expired
+///
+/// Sessions idle since before `active_since` count as expired.
+pub async fn find_active(
+    conn: &mut PgConnection,
+    id: Uuid,
+    user_id: Uuid,
+    active_since: Option<DateTime<Utc>>,
+) -> Result<Option<Session>, sqlx::Error> {
+    sqlx::query_as::<_, Session>(&format!(
+        "SELECT {} FROM sessions WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL AND expires_at > CURRENT_TIMESTAMP \
+         AND ($3::TIMESTAMPTZ IS NULL OR last_used_at > $3)",
+        SESSION_COLUMNS
+    ))
+    .bind(id)
+    .bind(user_id)
+    .bind(active_since)
+    .fetch_optional(conn)
+    .await
+}
+
+/// A user's active sessions, most recently used first
+pub async fn list_active_for_user(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    active_since: Option<DateTime<Utc>>,
+) -> Result<Vec<Session>, sqlx::Error> {
+    sqlx::query_as::<_, Session>(&format!(
+        "SELECT {} FROM sessions WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > CURRENT_TIMESTAMP \
+         AND ($2::TIMESTAMPTZ IS NULL OR last_used_at > $2) ORDER BY last_used_at DESC",
+        SESSION_COLUMNS
+    ))
+    .bind(user_id)
+    .bind(active_since)
+    .fetch_all(conn)
+    .await
+}
+
+/// Record that a session was just used
+///
+/// Only written once a minute per session, so busy sessions don't turn every request into a write.
+pub async fn touch(conn: &mut PgConnection, id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE sessions SET last_used_at = CURRENT_TIMESTAMP \
+         WHERE id = $1 AND last_used_at < CURRENT_TIMESTAMP - INTERVAL '1 minute'"
+    )
+    .bind(id)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Record that several sessions were used, as [`touch`] does for one
+pub async fn touch_many(conn: &mut PgConnection, ids: &[Uuid]) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE sessions SET last_used_at = CURRENT_TIMESTAMP \
+         WHERE id = ANY($1) AND last_used_at < CURRENT_TIMESTAMP - INTERVAL '1 minute'"
+    )
+    .bind(ids)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Record that a session was just used from `country`, returning where it was used from before
+///
+/// Sessions not yet seen with a country count as last seen where and when they
+/// started. Like [`touch`], only written once a minute unless the country changed;
+/// `None` when nothing was written.
+pub async fn record_country(
+    conn: &mut PgConnection,
+    id: Uuid,
+    country: &str,
+) -> Result<Option<CountrySighting>, sqlx::Error> {
+    sqlx::query_as::<_, CountrySighting>(
+        r#"
+        WITH previous AS (
+            SELECT id, COALESCE(seen_country, country) AS country, COALESCE(seen_at, created_at) AS seen_at,
+                   travel_flagged_at AS flagged_at
+            FROM sessions WHERE id = $1 FOR UPDATE
+        )
+        UPDATE sessions SET seen_country = $2, seen_at = CURRENT_TIMESTAMP
+        FROM previous
+        WHERE sessions.id = previous.id
+          AND (previous.country IS DISTINCT FROM $2 OR previous.seen_at < CURRENT_TIMESTAMP - INTERVAL '1 minute')
+        RETURNING previous.country, previous.seen_at, previous.flagged_at
+        "#,
+    )
+    .bind(id)
+    .bind(country)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Flag a session for impossible travel, revoking it too if `revoke`
+///
+/// Returns false if it was flagged before, so each session is only reported once.
+pub async fn flag_travel(conn: &mut PgConnection, id: Uuid, revoke: bool) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE sessions SET travel_flagged_at = CURRENT_TIMESTAMP, \
+         revoked_at = CASE WHEN $2 THEN COALESCE(revoked_at, CURRENT_TIMESTAMP) ELSE revoked_at END \
+         WHERE id = $1 AND travel_flagged_at IS NULL"
+    )
+    .bind(id)
+    .bind(revoke)
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Revoke every active session of a user, returning how many were revoked
+pub async fn revoke_all_for_user(conn: &mut PgConnection, user_id: Uuid) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE sessions SET revoked_at = CURRENT_TIMESTAMP WHERE user_id = $1 AND revoked_at IS NULL"
+    )
+    .bind(user_id)
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Revoke the sessions an OAuth client holds for a user, returning how many were revoked
+pub async fn revoke_for_client(conn: &mut PgConnection, user_id: Uuid, oauth_client_id: Uuid) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE sessions SET revoked_at = CURRENT_TIMESTAMP \
+         WHERE user_id = $1 AND oauth_client_id = $2 AND revoked_at IS NULL"
+    )
+    .bind(user_id)
+    .bind(oauth_client_id)
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Revoke one session issued to an OAuth client, found by id or by opaque token hash
+///
+/// Returns the session's user, or `None` if it wasn't the client's or was already revoked.
+pub async fn revoke_client_session(
+    conn: &mut PgConnection,
+    session: SessionRef<'_>,
+    oauth_client_id: Uuid,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let query = match session {
+        SessionRef::Id(id) => sqlx::query_scalar::<_, Uuid>(
+            "UPDATE sessions SET revoked_at = CURRENT_TIMESTAMP \
+             WHERE id = $1 AND oauth_client_id = $2 AND revoked_at IS NULL RETURNING user_id",
+        )
+        .bind(id),
+        SessionRef::TokenHash(hash) => sqlx::query_scalar::<_, Uuid>(
+            "UPDATE sessions SET revoked_at = CURRENT_TIMESTAMP \
+             WHERE token_hash = $1 AND oauth_client_id = $2 AND revoked_at IS NULL RETURNING user_id",
+        )
+        .bind(hash),
+    };
+    query.bind(oauth_client_id).fetch_optional(conn).await
+}
+
+/// Sessions that ended before `$1`: revoked, expired, or idle since `$2` when an idle timeout applies
+const ENDED_BEFORE: &str = r#"
+    FROM sessions
+    WHERE revoked_at < $1 OR expires_at < $1 OR ($2::timestamptz IS NOT NULL AND last_used_at < $2)
+"#;
+
+/// Count sessions that ended before `before`
+///
+/// With an idle timeout, sessions last used before `idle_before` count as ended too.
+pub async fn count_ended_before(
+    conn: &mut PgConnection,
+    before: DateTime<Utc>,
+    idle_before: Option<DateTime<Utc>>,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar::<_, i64>(&format!("SELECT COUNT(*) {}", ENDED_BEFORE))
+        .bind(before)
+        .bind(idle_before)
+        .fetch_one(conn)
+        .await
+}
+
+/// Delete sessions that ended before `before`, returning how many were deleted
+pub async fn delete_ended_before(
+    conn: &mut PgConnection,
+    before: DateTime<Utc>,
+    idle_before: Option<DateTime<Utc>>,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(&format!("DELETE {}", ENDED_BEFORE))
+        .bind(before)
+        .bind(idle_before)
+        .execute(conn)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// How a session is identified: JWTs carry its id, opaque tokens are stored by hash
+pub enum SessionRef<'a> {
+    Id(Uuid),
+    TokenHash(&'a str),
+}
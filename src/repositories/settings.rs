@@ -0,0 +1,59 @@
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::models::setting::StoredSetting;
+
+/// The stored value of a setting, if an admin set one
+pub async fn find(conn: &mut PgConnection, key: &str) -> Result<Option<StoredSetting>, sqlx::Error> {
+    sqlx::query_as::<_, StoredSetting>("SELECT key, value, updated_by, updated_at FROM settings WHERE key = $1")
+        .bind(key)
+        .fetch_optional(conn)
+        .await
+}
+
+/// Store a setting, replacing its previous value
+pub async fn upsert(
+    conn: &mut PgConnection,
+    key: &str,
+    value: &serde_json::Value,
+    updated_by: Uuid,
+) -> Result<StoredSetting, sqlx::Error> {
+    sqlx::query_as::<_, StoredSetting>(
+        r#"
+        INSERT INTO settings (key, value, updated_by) VALUES ($1, $2, $3)
+        ON CONFLICT (key) DO UPDATE SET value = $2, updated_by = $3, updated_at = CURRENT_TIMESTAMP
+        RETURNING key, value, updated_by, updated_at
+        "#,
+    )
+    .bind(key)
+    .bind(value)
+    .bind(updated_by)
+    .fetch_one(conn)
+    .await
+}
+
+/// Remove a stored setting, going back to the configured value; false if none was stored
+pub async fn delete(conn: &mut PgConnection, key: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM settings WHERE key = $1")
+        .bind(key)
+        .execute(conn)
+        .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Every stored setting
+pub async fn list(conn: &mut PgConnection) -> Result<Vec<StoredSetting>, sqlx::Error> {
+    sqlx::query_as::<_, StoredSetting>("SELECT key, value, updated_by, updated_at FROM settings ORDER BY key")
+        .fetch_all(conn)
+        .await
+}
+
+/// Tell every instance listening on `channel` that `key` changed
+pub async fn notify_changed(conn: &mut PgConnection, channel: &str, key: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT pg_notify($1, $2)")
+        .bind(channel)
+        .bind(key)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
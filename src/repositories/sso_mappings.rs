@@ -0,0 +1,44 @@
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::models::sso_mapping::{NewSsoMapping, SsoMapping};
+
+const COLUMNS: &str = "id, provider, claim, value, role, organization_id, organization_role, priority, created_at";
+
+/// A provider's rules, the ones that win first
+pub async fn list(conn: &mut PgConnection, provider: &str) -> Result<Vec<SsoMapping>, sqlx::Error> {
+    sqlx::query_as::<_, SsoMapping>(&format!(
+        "SELECT {} FROM sso_mappings WHERE provider = $1 ORDER BY priority DESC, created_at",
+        COLUMNS
+    ))
+    .bind(provider)
+    .fetch_all(conn)
+    .await
+}
+
+pub async fn create(conn: &mut PgConnection, provider: &str, mapping: &NewSsoMapping) -> Result<SsoMapping, sqlx::Error> {
+    sqlx::query_as::<_, SsoMapping>(&format!(
+        "INSERT INTO sso_mappings (provider, claim, value, role, organization_id, organization_role, priority) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING {}",
+        COLUMNS
+    ))
+    .bind(provider)
+    .bind(&mapping.claim)
+    .bind(&mapping.value)
+    .bind(&mapping.role)
+    .bind(mapping.organization_id)
+    .bind(&mapping.organization_role)
+    .bind(mapping.priority)
+    .fetch_one(conn)
+    .await
+}
+
+/// False if the provider has no rule with this id
+pub async fn delete(conn: &mut PgConnection, provider: &str, id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM sso_mappings WHERE provider = $1 AND id = $2")
+        .bind(provider)
+        .bind(id)
+        .execute(conn)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
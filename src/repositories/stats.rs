@@ -0,0 +1,77 @@
+use sqlx::PgConnection;
+
+use crate::models::stats::{DailyCount, DailyLogins, SessionStats, UserTotals};
+
+/// Count users by kind
+pub async fn user_totals(conn: &mut PgConnection) -> Result<UserTotals, sqlx::Error> {
+    sqlx::query_as::<_, UserTotals>(
+        r#"
+        SELECT
+            COUNT(*) AS total,
+            COUNT(*) FILTER (WHERE email_verified_at IS NOT NULL) AS verified,
+            COUNT(*) FILTER (WHERE role = 'admin') AS admins,
+            COUNT(*) FILTER (WHERE role = 'guest') AS guests,
+            COUNT(*) FILTER (WHERE approval_status = 'pending_approval') AS pending_approval
+        FROM users
+        "#,
+    )
+    .fetch_one(conn)
+    .await
+}
+
+/// New accounts per day over the last `days` days, including days without signups
+pub async fn signups_per_day(conn: &mut PgConnection, days: i32) -> Result<Vec<DailyCount>, sqlx::Error> {
+    sqlx::query_as::<_, DailyCount>(
+        r#"
+        SELECT series.day::date AS day, COUNT(users.id) AS count
+        FROM generate_series(
+            (CURRENT_TIMESTAMP AT TIME ZONE 'UTC')::date - ($1 - 1),
+            (CURRENT_TIMESTAMP AT TIME ZONE 'UTC')::date,
+            INTERVAL '1 day'
+        ) AS series(day)
+        LEFT JOIN users ON (users.created_at AT TIME ZONE 'UTC')::date = series.day::date
+        GROUP BY series.day
+        ORDER BY series.day
+        "#,
+    )
+    .bind(days)
+    .fetch_all(conn)
+    .await
+}
+
+/// Successful and failed password logins per day over the last `days` days
+pub async fn logins_per_day(conn: &mut PgConnection, days: i32) -> Result<Vec<DailyLogins>, sqlx::Error> {
+    sqlx::query_as::<_, DailyLogins>(
+        r#"
+        SELECT
+            series.day::date AS day,
+            COUNT(login_attempts.id) FILTER (WHERE login_attempts.succeeded) AS succeeded,
+            COUNT(login_attempts.id) FILTER (WHERE NOT login_attempts.succeeded) AS failed
+        FROM generate_series(
+            (CURRENT_TIMESTAMP AT TIME ZONE 'UTC')::date - ($1 - 1),
+            (CURRENT_TIMESTAMP AT TIME ZONE 'UTC')::date,
+            INTERVAL '1 day'
+        ) AS series(day)
+        LEFT JOIN login_attempts
+            ON (login_attempts.created_at AT TIME ZONE 'UTC')::date = series.day::date
+        GROUP BY series.day
+        ORDER BY series.day
+        "#,
+    )
+    .bind(days)
+    .fetch_all(conn)
+    .await
+}
+
+/// Count active sessions and the users holding them
+pub async fn session_stats(conn: &mut PgConnection) -> Result<SessionStats, sqlx::Error> {
+    sqlx::query_as::<_, SessionStats>(
+        r#"
+        SELECT COUNT(*) AS active_sessions, COUNT(DISTINCT user_id) AS users_with_active_sessions
+        FROM sessions
+        WHERE revoked_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+        "#,
+    )
+    .fetch_one(conn)
+    .await
+}
@@ -0,0 +1,127 @@
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::models::user::User;
+use crate::models::user_email::UserEmail;
+use crate::repositories::users::USER_COLUMNS;
+
+const USER_EMAIL_COLUMNS: &str = "id, user_id, email, verified_at, created_at";
+
+/// Add an unverified secondary address for a user
+pub async fn create(conn: &mut PgConnection, user_id: Uuid, email: &str) -> Result<UserEmail, sqlx::Error> {
+    sqlx::query_as::<_, UserEmail>(&format!(
+        "INSERT INTO user_emails (user_id, email) VALUES ($1, $2) RETURNING {}",
+        USER_EMAIL_COLUMNS
+    ))
+    .bind(user_id)
+    .bind(email)
+    .fetch_one(conn)
+    .await
+}
+
+/// A user's secondary addresses, oldest first
+pub async fn list_for_user(conn: &mut PgConnection, user_id: Uuid) -> Result<Vec<UserEmail>, sqlx::Error> {
+    sqlx::query_as::<_, UserEmail>(&format!(
+        "SELECT {} FROM user_emails WHERE user_id = $1 ORDER BY created_at, id",
+        USER_EMAIL_COLUMNS
+    ))
+    .bind(user_id)
+    .fetch_all(conn)
+    .await
+}
+
+/// Find one of a user's secondary addresses by id
+pub async fn find(conn: &mut PgConnection, user_id: Uuid, id: Uuid) -> Result<Option<UserEmail>, sqlx::Error> {
+    sqlx::query_as::<_, UserEmail>(&format!(
+        "SELECT {} FROM user_emails WHERE id = $1 AND user_id = $2",
+        USER_EMAIL_COLUMNS
+    ))
+    .bind(id)
+    .bind(user_id)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Find a secondary address by id, whoever it belongs to
+pub async fn find_by_id(conn: &mut PgConnection, id: Uuid) -> Result<Option<UserEmail>, sqlx::Error> {
+    sqlx::query_as::<_, UserEmail>(&format!("SELECT {} FROM user_emails WHERE id = $1", USER_EMAIL_COLUMNS))
+        .bind(id)
+        .fetch_optional(conn)
+        .await
+}
+
+/// Check whether the user already has this secondary address
+pub async fn exists_for_user(conn: &mut PgConnection, user_id: Uuid, email: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM user_emails WHERE user_id = $1 AND email = $2)")
+        .bind(user_id)
+        .bind(email)
+        .fetch_one(conn)
+        .await
+}
+
+/// Number of secondary addresses a user has
+pub async fn count_for_user(conn: &mut PgConnection, user_id: Uuid) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM user_emails WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_one(conn)
+        .await
+}
+
+/// Mark a secondary address as verified
+pub async fn mark_verified(conn: &mut PgConnection, id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE user_emails SET verified_at = COALESCE(verified_at, CURRENT_TIMESTAMP) WHERE id = $1")
+        .bind(id)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Remove one of a user's secondary addresses; returns false if it doesn't exist
+pub async fn delete(conn: &mut PgConnection, user_id: Uuid, id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM user_emails WHERE id = $1 AND user_id = $2")
+        .bind(id)
+        .bind(user_id)
+        .execute(conn)
+        .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Find the user who verified this address as a secondary one (used for account recovery)
+pub async fn find_user_by_verified_email(conn: &mut PgConnection, email: &str) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as::<_, User>(&format!(
+        "SELECT {} FROM users WHERE id = \
+         (SELECT user_id FROM user_emails WHERE email = $1 AND verified_at IS NOT NULL)",
+        USER_COLUMNS
+    ))
+    .bind(email)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Make a verified secondary address the primary one
+///
+/// The old primary address becomes a secondary address, keeping its
+/// verification state. Returns `None` unless the address belongs to the
+/// user and is verified.
+pub async fn promote(conn: &mut PgConnection, user_id: Uuid, id: Uuid) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as::<_, User>(&format!(
+        r#"
+        WITH promoted AS (
+            DELETE FROM user_emails WHERE id = $1 AND user_id = $2 AND verified_at IS NOT NULL
+            RETURNING email AS new_email, verified_at AS new_verified_at
+        ), demoted AS (
+            INSERT INTO user_emails (user_id, email, verified_at)
+            SELECT users.id, users.email, users.email_verified_at FROM users, promoted WHERE users.id = $2
+        )
+        UPDATE users SET email = promoted.new_email, email_verified_at = promoted.new_verified_at,
+            updated_at = CURRENT_TIMESTAMP
+        FROM promoted WHERE users.id = $2
+        RETURNING {}
+        "#,
+        USER_COLUMNS
+    ))
+    .bind(id)
+    .bind(user_id)
+    .fetch_optional(conn)
+    .await
+}
@@ -0,0 +1,565 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::models::organization::OrgRole;
+use crate::models::user::{AccountStanding, User};
+
+/// Columns selected into `User`
+pub(crate) const USER_COLUMNS: &str = "id, email, password_hash, role, email_verified_at, \
+    approval_status, approval_reason, approval_decided_at, must_change_password, user_metadata, app_metadata, organization_id, organization_role, stripe_customer_id, subscription_status, token_version, totp_enabled_at, mfa_required_since, locale, timezone, \
+    last_login_at, last_login_ip, login_count, inactivity_warned_at, flagged_inactive_at, disabled_at, reactivated_at, \
+    created_at, updated_at";
+
+/// Find a user by email
+pub async fn find_by_email(conn: &mut PgConnection, email: &str) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as::<_, User>(&format!("SELECT {} FROM users WHERE email = $1", USER_COLUMNS))
+        .bind(email)
+        .fetch_optional(conn)
+        .await
+}
+
+/// Find a user by id
+pub async fn find_by_id(conn: &mut PgConnection, id: Uuid) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as::<_, User>(&format!("SELECT {} FROM users WHERE id = $1", USER_COLUMNS))
+        .bind(id)
+        .fetch_optional(conn)
+        .await
+}
+
+/// Check whether the email is taken, as a primary address or a verified secondary one
+pub async fn email_exists(conn: &mut PgConnection, email: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS (SELECT 1 FROM users WHERE email = $1) \
+         OR EXISTS (SELECT 1 FROM user_emails WHERE email = $1 AND verified_at IS NOT NULL)"
+    )
+    .bind(email)
+    .fetch_one(conn)
+    .await
+}
+
+/// Insert a new user with an already-hashed password
+pub async fn create(
+    conn: &mut PgConnection,
+    email: &str,
+    password_hash: &str,
+    role: &str,
+) -> Result<User, sqlx::Error> {
+    sqlx::query_as::<_, User>(&format!(
+        "INSERT INTO users (email, password_hash, role) VALUES ($1, $2, $3) RETURNING {}",
+        USER_COLUMNS
+    ))
+    .bind(email)
+    .bind(password_hash)
+    .bind(role)
+    .fetch_one(conn)
+    .await
+}
+
+/// Insert a new user whose signup must be approved by an admin before they can log in
+pub async fn create_pending_approval(
+    conn: &mut PgConnection,
+    email: &str,
+    password_hash: &str,
+) -> Result<User, sqlx::Error> {
+    sqlx::query_as::<_, User>(&format!(
+        "INSERT INTO users (email, password_hash, role, approval_status) \
+         VALUES ($1, $2, 'user', 'pending_approval') RETURNING {}",
+        USER_COLUMNS
+    ))
+    .bind(email)
+    .bind(password_hash)
+    .fetch_one(conn)
+    .await
+}
+
+/// Signups waiting for approval, oldest first
+pub async fn list_pending_approval(
+    conn: &mut PgConnection,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<User>, sqlx::Error> {
+    sqlx::query_as::<_, User>(&format!(
+        "SELECT {} FROM users WHERE approval_status = 'pending_approval' ORDER BY created_at LIMIT $1 OFFSET $2",
+        USER_COLUMNS
+    ))
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(conn)
+    .await
+}
+
+/// Approve or reject a pending signup
+///
+/// `status` is `approved` or `rejected`. Returns `None` if the user doesn't
+/// exist or was already decided.
+pub async fn decide_approval(
+    conn: &mut PgConnection,
+    id: Uuid,
+    status: &str,
+    reason: Option<&str>,
+) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as::<_, User>(&format!(
+        "UPDATE users SET approval_status = $1, approval_reason = $2, \
+         approval_decided_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP \
+         WHERE id = $3 AND approval_status = 'pending_approval' RETURNING {}",
+        USER_COLUMNS
+    ))
+    .bind(status)
+    .bind(reason)
+    .bind(id)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Replace a user's password hash, which also satisfies `must_change_password`
+pub async fn update_password(
+    conn: &mut PgConnection,
+    id: Uuid,
+    password_hash: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE users SET password_hash = $1, must_change_password = FALSE, updated_at = CURRENT_TIMESTAMP WHERE id = $2"
+    )
+    .bind(password_hash)
+    .bind(id)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Replace the stored hash of an unchanged password, e.g. after a pepper rotation
+///
+/// Unlike `update_password` this isn't a profile change: `updated_at` and
+/// `must_change_password` are left alone.
+pub async fn set_password_hash(conn: &mut PgConnection, id: Uuid, password_hash: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET password_hash = $1 WHERE id = $2")
+        .bind(password_hash)
+        .bind(id)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Require (or stop requiring) a user to change their password; returns false if the user doesn't exist
+pub async fn set_must_change_password(conn: &mut PgConnection, id: Uuid, enabled: bool) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE users SET must_change_password = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2"
+    )
+    .bind(enabled)
+    .bind(id)
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// What the auth guard checks about a user on every request
+pub async fn standing(conn: &mut PgConnection, id: Uuid) -> Result<Option<AccountStanding>, sqlx::Error> {
+    sqlx::query_as::<_, AccountStanding>(
+        "SELECT role, must_change_password, totp_enabled_at, mfa_required_since FROM users WHERE id = $1"
+    )
+    .bind(id)
+    .fetch_optional(conn)
+    .await
+}
+
+/// The user's current token version, or `None` if the user doesn't exist
+pub async fn token_version(conn: &mut PgConnection, id: Uuid) -> Result<Option<i32>, sqlx::Error> {
+    sqlx::query_scalar::<_, i32>("SELECT token_version FROM users WHERE id = $1")
+        .bind(id)
+        .fetch_optional(conn)
+        .await
+}
+
+/// Invalidate every JWT issued to a user so far, returning the new token version
+pub async fn bump_token_version(conn: &mut PgConnection, id: Uuid) -> Result<Option<i32>, sqlx::Error> {
+    sqlx::query_scalar::<_, i32>("UPDATE users SET token_version = token_version + 1 WHERE id = $1 RETURNING token_version")
+        .bind(id)
+        .fetch_optional(conn)
+        .await
+}
+
+/// Merge patches into a user's metadata, returning the updated user
+///
+/// Keys in a patch replace existing ones and `null` values remove them; a
+/// `None` patch leaves that metadata alone. With `expected_updated_at`, the
+/// user is only updated if it hasn't changed since. Returns `None` if no user
+/// was updated.
+pub async fn patch_metadata(
+    conn: &mut PgConnection,
+    id: Uuid,
+    user_metadata: Option<&serde_json::Value>,
+    app_metadata: Option<&serde_json::Value>,
+    locale: Option<&str>,
+    timezone: Option<&str>,
+    expected_updated_at: Option<DateTime<Utc>>,
+) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as::<_, User>(&format!(
+        r#"
+        UPDATE users SET
+            user_metadata = COALESCE(
+                (user_metadata || $1) - ARRAY(SELECT key FROM jsonb_each($1) WHERE jsonb_typeof(value) = 'null'),
+                user_metadata),
+            app_metadata = COALESCE(
+                (app_metadata || $2) - ARRAY(SELECT key FROM jsonb_each($2) WHERE jsonb_typeof(value) = 'null'),
+                app_metadata),
+            locale = COALESCE($5, locale),
+            timezone = COALESCE($6, timezone),
+            updated_at = CURRENT_TIMESTAMP
+        WHERE id = $3 AND ($4::timestamptz IS NULL OR updated_at = $4)
+        RETURNING {}
+        "#,
+        USER_COLUMNS
+    ))
+    .bind(user_metadata)
+    .bind(app_metadata)
+    .bind(id)
+    .bind(expected_updated_at)
+    .bind(locale)
+    .bind(timezone)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Store a user's locale and time zone; `None` clears them
+pub async fn set_preferences(
+    conn: &mut PgConnection,
+    id: Uuid,
+    locale: Option<&str>,
+    timezone: Option<&str>,
+) -> Result<User, sqlx::Error> {
+    sqlx::query_as::<_, User>(&format!(
+        "UPDATE users SET locale = $1, timezone = $2, updated_at = CURRENT_TIMESTAMP WHERE id = $3 RETURNING {}",
+        USER_COLUMNS
+    ))
+    .bind(locale)
+    .bind(timezone)
+    .bind(id)
+    .fetch_one(conn)
+    .await
+}
+
+/// Claim sending the welcome email to a user, returning them if nobody has yet
+///
+/// Users still waiting for signup approval aren't claimed; approval sends its own welcome.
+pub async fn claim_welcome_email(conn: &mut PgConnection, id: Uuid) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as::<_, User>(&format!(
+        r#"
+        UPDATE users SET welcome_email_sent_at = CURRENT_TIMESTAMP
+        WHERE id = $1 AND welcome_email_sent_at IS NULL AND approval_status <> 'pending_approval'
+        RETURNING {}
+        "#,
+        USER_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Users the inactivity policy leaves alone
+const INACTIVITY_EXEMPT_ROLES: &str = "('admin', 'guest')";
+
+/// Claim up to `limit` users inactive since before `inactive_before` who haven't been warned yet
+///
+/// Claimed users are marked as warned, so concurrent sweeps don't warn anyone twice.
+pub async fn claim_inactivity_warnings(
+    conn: &mut PgConnection,
+    inactive_before: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<User>, sqlx::Error> {
+    sqlx::query_as::<_, User>(&format!(
+        r#"
+        UPDATE users SET inactivity_warned_at = CURRENT_TIMESTAMP
+        WHERE id IN (
+            SELECT id FROM users
+            WHERE role NOT IN {}
+              AND inactivity_warned_at IS NULL AND flagged_inactive_at IS NULL
+              AND GREATEST(created_at, last_login_at, reactivated_at) < $1
+            ORDER BY id
+            LIMIT $2
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING {}
+        "#,
+        INACTIVITY_EXEMPT_ROLES, USER_COLUMNS
+    ))
+    .bind(inactive_before)
+    .bind(limit)
+    .fetch_all(conn)
+    .await
+}
+
+/// Flag users inactive since before `inactive_before`, returning their ids
+///
+/// With `warned_before`, only users warned before then are flagged. With
+/// `disable`, flagged users are disabled too and their tokens invalidated;
+/// this includes users flagged earlier while the policy only flagged.
+pub async fn flag_inactive(
+    conn: &mut PgConnection,
+    inactive_before: DateTime<Utc>,
+    warned_before: Option<DateTime<Utc>>,
+    disable: bool,
+) -> Result<Vec<Uuid>, sqlx::Error> {
+    sqlx::query_scalar::<_, Uuid>(&format!(
+        r#"
+        UPDATE users SET
+            flagged_inactive_at = COALESCE(flagged_inactive_at, CURRENT_TIMESTAMP),
+            disabled_at = CASE WHEN $3 THEN CURRENT_TIMESTAMP END,
+            token_version = token_version + CASE WHEN $3 THEN 1 ELSE 0 END,
+            updated_at = CURRENT_TIMESTAMP
+        WHERE role NOT IN {}
+          AND (flagged_inactive_at IS NULL OR ($3 AND disabled_at IS NULL))
+          AND GREATEST(created_at, last_login_at, reactivated_at) < $1
+          AND ($2::timestamptz IS NULL OR inactivity_warned_at < $2)
+        RETURNING id
+        "#,
+        INACTIVITY_EXEMPT_ROLES
+    ))
+    .bind(inactive_before)
+    .bind(warned_before)
+    .bind(disable)
+    .fetch_all(conn)
+    .await
+}
+
+/// Clear a user's inactivity flags and restart the inactivity clock
+///
+/// Returns `None` if the user doesn't exist.
+pub async fn reactivate(conn: &mut PgConnection, id: Uuid) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as::<_, User>(&format!(
+        "UPDATE users SET disabled_at = NULL, flagged_inactive_at = NULL, inactivity_warned_at = NULL, \
+         reactivated_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP WHERE id = $1 RETURNING {}",
+        USER_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Mark a user's email address as verified
+pub async fn mark_email_verified(conn: &mut PgConnection, id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE users SET email_verified_at = COALESCE(email_verified_at, CURRENT_TIMESTAMP), updated_at = CURRENT_TIMESTAMP WHERE id = $1"
+    )
+    .bind(id)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Put a user in an organization unless they already belong to one; its first member becomes the owner
+pub async fn set_organization_if_unset(conn: &mut PgConnection, id: Uuid, organization_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE users SET organization_id = $2, \
+         organization_role = CASE WHEN EXISTS (SELECT 1 FROM users WHERE organization_id = $2) THEN 'member' ELSE 'owner' END, \
+         updated_at = CURRENT_TIMESTAMP \
+         WHERE id = $1 AND organization_id IS NULL"
+    )
+    .bind(id)
+    .bind(organization_id)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Record the user's Stripe customer unless one is recorded already; false if one was
+pub async fn set_stripe_customer_if_unset(conn: &mut PgConnection, id: Uuid, customer_id: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE users SET stripe_customer_id = $2, updated_at = CURRENT_TIMESTAMP WHERE id = $1 AND stripe_customer_id IS NULL"
+    )
+    .bind(id)
+    .bind(customer_id)
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Set the subscription status of the Stripe customer's user, as of `at`
+///
+/// Statuses older than the one recorded are ignored, as Stripe doesn't
+/// deliver webhooks in order. False if no user is that customer or the
+/// status was older.
+pub async fn set_subscription_status(
+    conn: &mut PgConnection,
+    customer_id: &str,
+    status: &str,
+    at: DateTime<Utc>,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE users SET subscription_status = $2, subscription_updated_at = $3, updated_at = CURRENT_TIMESTAMP \
+         WHERE stripe_customer_id = $1 AND (subscription_updated_at IS NULL OR subscription_updated_at <= $3)"
+    )
+    .bind(customer_id)
+    .bind(status)
+    .bind(at)
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Put a user in an organization with `role`, leaving any other organization
+pub async fn set_organization(
+    conn: &mut PgConnection,
+    id: Uuid,
+    organization_id: Uuid,
+    role: OrgRole,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE users SET organization_id = $2, organization_role = $3, updated_at = CURRENT_TIMESTAMP WHERE id = $1"
+    )
+    .bind(id)
+    .bind(organization_id)
+    .bind(role.as_str())
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Make a user a member of an organization unless they already belong to one; false if they did
+pub async fn join_organization_if_unset(conn: &mut PgConnection, id: Uuid, organization_id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE users SET organization_id = $2, organization_role = 'member', updated_at = CURRENT_TIMESTAMP \
+         WHERE id = $1 AND organization_id IS NULL"
+    )
+    .bind(id)
+    .bind(organization_id)
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Give a member of the organization another role, built-in or custom
+///
+/// Owners keep their role. False if the user isn't a member, or owns it.
+pub async fn set_organization_role(
+    conn: &mut PgConnection,
+    organization_id: Uuid,
+    id: Uuid,
+    role: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE users SET organization_role = $3, updated_at = CURRENT_TIMESTAMP \
+         WHERE id = $1 AND organization_id = $2 AND organization_role <> 'owner'"
+    )
+    .bind(id)
+    .bind(organization_id)
+    .bind(role)
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Set the role an external membership mapping grants, falling back to `user`
+///
+/// Only users whose role is `user` or one of `managed` are changed, so roles
+/// given by other means (like `admin` from the CLI) stay put.
+pub async fn sync_managed_role(
+    conn: &mut PgConnection,
+    id: Uuid,
+    granted: Option<&str>,
+    managed: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE users SET role = COALESCE($2, 'user'), updated_at = CURRENT_TIMESTAMP \
+         WHERE id = $1 AND (role = 'user' OR role = ANY($3)) AND role <> COALESCE($2, 'user')"
+    )
+    .bind(id)
+    .bind(granted)
+    .bind(managed)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Set the membership an external mapping grants, leaving the organization when it grants none
+///
+/// Only users outside any organization or in one of `managed` are changed,
+/// and owners keep their organization and role.
+pub async fn sync_managed_organization(
+    conn: &mut PgConnection,
+    id: Uuid,
+    granted: Option<(Uuid, &str)>,
+    managed: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    let (organization_id, role) = granted.unzip();
+    sqlx::query(
+        "UPDATE users SET organization_id = $2, organization_role = COALESCE($3, 'member'), updated_at = CURRENT_TIMESTAMP \
+         WHERE id = $1 AND (organization_id IS NULL OR organization_id = ANY($4)) AND organization_role <> 'owner' \
+         AND (organization_id IS DISTINCT FROM $2 OR organization_role <> COALESCE($3, 'member'))"
+    )
+    .bind(id)
+    .bind(organization_id)
+    .bind(role)
+    .bind(managed)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Replace a user's primary email with a new address they have confirmed
+///
+/// Returns `None` if the user doesn't exist.
+pub async fn change_email(conn: &mut PgConnection, id: Uuid, email: &str) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as::<_, User>(&format!(
+        "UPDATE users SET email = $1, email_verified_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP \
+         WHERE id = $2 RETURNING {}",
+        USER_COLUMNS
+    ))
+    .bind(email)
+    .bind(id)
+    .fetch_optional(conn)
+    .await
+}
+
+/// Give a guest account an email and password, keeping its id
+///
+/// Returns `None` if the user doesn't exist or isn't a guest.
+pub async fn upgrade_guest(
+    conn: &mut PgConnection,
+    id: Uuid,
+    email: &str,
+    password_hash: &str,
+) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as::<_, User>(&format!(
+        "UPDATE users SET email = $1, password_hash = $2, role = 'user', updated_at = CURRENT_TIMESTAMP \
+         WHERE id = $3 AND role = 'guest' RETURNING {}",
+        USER_COLUMNS
+    ))
+    .bind(email)
+    .bind(password_hash)
+    .bind(id)
+    .fetch_optional(conn)
+    .await
+}
+
+/// List users, oldest first
+///
+/// With `inactive_since`, only users who haven't logged in since then (or
+/// ever, if they were created before it) are listed.
+pub async fn list(
+    conn: &mut PgConnection,
+    limit: i64,
+    offset: i64,
+    inactive_since: Option<DateTime<Utc>>,
+) -> Result<Vec<User>, sqlx::Error> {
+    sqlx::query_as::<_, User>(&format!(
+        "SELECT {} FROM users \
+         WHERE $3::timestamptz IS NULL OR COALESCE(last_login_at, created_at) < $3 \
+         ORDER BY created_at, id LIMIT $1 OFFSET $2",
+        USER_COLUMNS
+    ))
+    .bind(limit)
+    .bind(offset)
+    .bind(inactive_since)
+    .fetch_all(conn)
+    .await
+}
+
+/// Delete a user; sessions, tokens and keys go with it. Returns false if the user doesn't exist
+pub async fn delete(conn: &mut PgConnection, id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM users WHERE id = $1")
+        .bind(id)
+        .execute(conn)
+        .await?;
+    Ok(result.rows_affected() == 1)
+}
@@ -0,0 +1,154 @@
+use std::time::Instant;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::ContentType;
+use rocket::serde::json::Value;
+use rocket::{Data, Request, Response};
+
+/// Placeholder logged instead of sensitive values
+const REDACTED: &str = "[REDACTED]";
+
+/// Most of a request body logged; longer bodies are skipped rather than cut
+/// (Rocket can only peek this far without consuming the body)
+const MAX_REQUEST_BODY: usize = 512;
+
+/// Most of a response body logged
+const MAX_RESPONSE_BODY: usize = 16 * 1024;
+
+/// Whether a JSON key or query parameter holds a credential
+///
+/// Matches `password`, `token` and `secret` anywhere in the name (so
+/// `new_password` and `refresh_token` are covered), plus `authorization`
+/// and `key` (API keys are returned as `key`).
+pub fn is_sensitive(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name.contains("password")
+        || name.contains("token")
+        || name.contains("secret")
+        || name == "authorization"
+        || name == "key"
+}
+
+/// Replace sensitive values in a JSON document, at any depth
+///
+/// Flags like `must_change_password` are kept; only values that could hold
+/// a credential are replaced.
+pub fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if is_sensitive(key) && !value.is_boolean() && !value.is_null() {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+/// A query string with sensitive parameter values replaced
+pub fn redact_query(query: &str) -> String {
+    query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((name, _)) if is_sensitive(name) => format!("{}={}", name, REDACTED),
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// A JSON body, redacted, for the log; `None` if it isn't JSON
+fn redacted_body(body: &[u8]) -> Option<String> {
+    let mut value = serde_json::from_slice::<Value>(body).ok()?;
+    redact(&mut value);
+    Some(value.to_string())
+}
+
+/// Who made the request, recorded by the authentication guards
+pub(crate) struct LoggedUser(pub Option<String>);
+
+/// Note the authenticated user for the request log
+pub(crate) fn record_user(request: &Request<'_>, user_id: &str) {
+    request.local_cache(|| LoggedUser(Some(user_id.to_string())));
+}
+
+struct RequestStart(Instant);
+
+struct RequestBody(Option<String>);
+
+/// Fairing that logs one line per request: method, path, status, latency and user
+///
+/// With `bodies`, JSON request and response bodies are logged as well.
+/// Credentials are redacted from bodies and query strings, and headers
+/// (including `Authorization`) are never logged.
+pub struct RequestLogger {
+    pub bodies: bool,
+}
+
+#[rocket::async_trait]
+impl Fairing for RequestLogger {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request Logger",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, data: &mut Data<'_>) {
+        request.local_cache(|| RequestStart(Instant::now()));
+
+        if self.bodies && request.content_type() == Some(&ContentType::JSON) {
+            let peeked = data.peek(MAX_REQUEST_BODY).await.to_vec();
+            let body = if data.peek_complete() {
+                redacted_body(&peeked)
+            } else {
+                Some(format!("<more than {} bytes, not logged>", MAX_REQUEST_BODY))
+            };
+            request.local_cache(|| RequestBody(body));
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let elapsed = request.local_cache(|| RequestStart(Instant::now())).0.elapsed();
+        let user = request.local_cache(|| LoggedUser(None));
+        let uri = request.uri();
+        let query = uri.query().map(|query| format!("?{}", redact_query(query.as_str())));
+
+        println!(
+            "→ {} {}{} {} {:.1}ms user={}",
+            request.method(),
+            uri.path(),
+            query.unwrap_or_default(),
+            response.status().code,
+            elapsed.as_secs_f64() * 1000.0,
+            user.0.as_deref().unwrap_or("-")
+        );
+
+        if !self.bodies {
+            return;
+        }
+
+        if let Some(body) = &request.local_cache(|| RequestBody(None)).0 {
+            println!("  request: {}", body);
+        }
+
+        // Only buffered JSON bodies; streams are left alone
+        let loggable = response.content_type() == Some(ContentType::JSON)
+            && response.body().preset_size().is_some_and(|size| size <= MAX_RESPONSE_BODY);
+        if loggable {
+            match response.body_mut().to_bytes().await {
+                Ok(body) => {
+                    if let Some(logged) = redacted_body(&body) {
+                        println!("  response: {}", logged);
+                    }
+                    response.set_sized_body(body.len(), std::io::Cursor::new(body));
+                }
+                Err(e) => eprintln!("Failed to read response body for logging: {}", e),
+            }
+        }
+    }
+}
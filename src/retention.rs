@@ -0,0 +1,115 @@
+use chrono::{Duration, Months, Utc};
+use rocket::fairing::AdHoc;
+use rocket_db_pools::Database;
+use sqlx::{PgConnection, PgPool};
+
+use crate::config::AppConfig;
+use crate::repositories::{audit, login_attempts, sessions};
+use crate::Postgres;
+
+/// How often each server instance applies the retention settings
+const CLEANUP_INTERVAL_MINUTES: u64 = 60;
+
+/// Rows one cleanup deleted, or would delete in a dry run
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionReport {
+    pub audit_events: u64,
+    pub login_attempts: u64,
+    pub sessions: u64,
+}
+
+/// Whether any retention period is set
+pub fn is_enabled(config: &AppConfig) -> bool {
+    config.audit_retention_days > 0 || config.login_history_retention_days > 0 || config.session_retention_days > 0
+}
+
+/// Delete data older than its retention period once
+///
+/// Audit events and login attempts are kept for `ROCKET_AUDIT_RETENTION_DAYS`
+/// and `ROCKET_LOGIN_HISTORY_RETENTION_DAYS`; sessions for
+/// `ROCKET_SESSION_RETENTION_DAYS` after they were revoked, expired or went
+/// idle. A period of 0 keeps that data forever. With `dry_run`, nothing is
+/// deleted and the report counts what would be.
+pub async fn cleanup(conn: &mut PgConnection, config: &AppConfig, dry_run: bool) -> Result<RetentionReport, sqlx::Error> {
+    let mut report = RetentionReport::default();
+    let now = Utc::now();
+    let cutoff = |days: u64| now - Duration::days(days as i64);
+
+    if config.audit_retention_days > 0 {
+        let before = cutoff(config.audit_retention_days);
+        report.audit_events = if dry_run {
+            audit::count_before(conn, before).await? as u64
+        } else {
+            audit::delete_before(conn, before).await?
+        };
+    }
+
+    if config.login_history_retention_days > 0 {
+        let before = cutoff(config.login_history_retention_days);
+        report.login_attempts = if dry_run {
+            login_attempts::count_before(conn, before).await? as u64
+        } else {
+            login_attempts::delete_before(conn, before).await?
+        };
+    }
+
+    if config.session_retention_days > 0 {
+        let before = cutoff(config.session_retention_days);
+        let idle_before = (config.session_idle_timeout_minutes > 0)
+            .then(|| before - Duration::minutes(config.session_idle_timeout_minutes as i64));
+        report.sessions = if dry_run {
+            sessions::count_ended_before(conn, before, idle_before).await? as u64
+        } else {
+            sessions::delete_ended_before(conn, before, idle_before).await?
+        };
+    }
+
+    Ok(report)
+}
+
+/// Create upcoming audit log partitions, then clean up
+async fn run(conn: &mut PgConnection, config: &AppConfig) -> Result<RetentionReport, sqlx::Error> {
+    let now = Utc::now();
+    audit::ensure_partitions(conn, now, now + Months::new(audit::PARTITION_MONTHS_AHEAD)).await?;
+    if !is_enabled(config) {
+        return Ok(RetentionReport::default());
+    }
+    cleanup(conn, config, config.retention_dry_run).await
+}
+
+/// Fairing that maintains audit log partitions and cleans up every hour while the server runs
+///
+/// With `ROCKET_RETENTION_DRY_RUN`, it only logs what it would delete.
+pub fn fairing() -> AdHoc {
+    AdHoc::on_liftoff("Data Retention", |rocket| {
+        Box::pin(async move {
+            let (Some(config), Some(db)) = (rocket.state::<AppConfig>(), Postgres::fetch(rocket)) else {
+                eprintln!("Data retention not started: missing AppConfig or database");
+                return;
+            };
+
+            let (config, pool) = (config.clone(), PgPool::clone(db));
+            rocket::tokio::spawn(async move {
+                loop {
+                    let result = match pool.acquire().await {
+                        Ok(mut conn) => run(&mut conn, &config).await,
+                        Err(e) => Err(e),
+                    };
+                    match result {
+                        Ok(report) if report != RetentionReport::default() => println!(
+                            "✓ Retention{}: {} audit event(s), {} login attempt(s), {} session(s) {}",
+                            if config.retention_dry_run { " (dry run)" } else { "" },
+                            report.audit_events,
+                            report.login_attempts,
+                            report.sessions,
+                            if config.retention_dry_run { "would be deleted" } else { "deleted" }
+                        ),
+                        Ok(_) => {}
+                        Err(e) => eprintln!("Retention cleanup failed: {}", e),
+                    }
+                    rocket::tokio::time::sleep(std::time::Duration::from_secs(CLEANUP_INTERVAL_MINUTES * 60)).await;
+                }
+            });
+        })
+    })
+}
@@ -0,0 +1,240 @@
+use std::convert::Infallible;
+use std::net::IpAddr;
+
+use chrono::{Duration, Utc};
+use rocket::request::{FromRequest, Outcome, Request};
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+
+use crate::config::{AppConfig, LoginRiskEngine};
+use crate::models::user::User;
+use crate::repositories::login_attempts;
+use crate::settings::Settings;
+
+/// How long a successful login keeps its address familiar
+pub const FAMILIAR_IP_DAYS: i64 = 90;
+
+/// Window for counting the addresses a user recently logged in from
+pub const VELOCITY_WINDOW_MINUTES: i64 = 60;
+
+/// What is known about a login once its password checked out
+#[derive(Debug)]
+pub struct LoginSignals<'a> {
+    pub user: &'a User,
+    pub ip: Option<IpAddr>,
+    /// From the `ROCKET_GEO_COUNTRY_HEADER` header, when one is configured and sent
+    pub country: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+    /// The user logged in before, but not from this address in the last `FAMILIAR_IP_DAYS`
+    pub new_ip: bool,
+    /// Failed logins for the user's email within the lockout window
+    pub recent_failures: u32,
+    /// Distinct addresses the user logged in from in the last `VELOCITY_WINDOW_MINUTES`
+    pub recent_login_ips: u32,
+}
+
+/// What to do with a login
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RiskVerdict {
+    Allow,
+    /// Ask for a second factor: the user's authenticator, or else an emailed code
+    StepUp,
+    Block,
+}
+
+#[derive(Debug, Clone)]
+pub struct RiskAssessment {
+    pub score: u32,
+    pub verdict: RiskVerdict,
+    /// Short machine-readable names of what raised the score, kept in the `risky_login` event
+    pub reasons: Vec<String>,
+}
+
+/// Judges how likely a login is to be an account takeover
+///
+/// Implement this to plug in a fraud service or app-specific rules, and
+/// manage it as [`LoginRisk`]; it's called after the password was verified,
+/// before any session exists.
+#[rocket::async_trait]
+pub trait RiskAssessor: Send + Sync {
+    async fn assess(&self, signals: &LoginSignals<'_>) -> RiskAssessment;
+}
+
+/// Built-in engine adding up points for each suspicious signal
+///
+/// A new address scores 40; three or more recent failures, and three or
+/// more addresses within the velocity window, 30 each; a missing
+/// `User-Agent`, 20. The country isn't scored.
+#[derive(Debug, Clone, Copy)]
+pub struct HeuristicRiskAssessor {
+    pub step_up_score: u32,
+    pub block_score: u32,
+}
+
+impl HeuristicRiskAssessor {
+    pub fn from_config(config: &AppConfig) -> Self {
+        HeuristicRiskAssessor {
+            step_up_score: config.login_risk_step_up_score,
+            block_score: config.login_risk_block_score,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl RiskAssessor for HeuristicRiskAssessor {
+    async fn assess(&self, signals: &LoginSignals<'_>) -> RiskAssessment {
+        let checks = [
+            (signals.new_ip, 40, "new_ip"),
+            (signals.recent_failures >= 3, 30, "recent_failures"),
+            (signals.recent_login_ips >= 3, 30, "login_velocity"),
+            (signals.user_agent.is_none(), 20, "no_user_agent"),
+        ];
+        let (score, reasons) = checks
+            .into_iter()
+            .filter(|(hit, _, _)| *hit)
+            .fold((0, Vec::new()), |(score, mut reasons), (_, points, reason)| {
+                reasons.push(reason.to_string());
+                (score + points, reasons)
+            });
+
+        let verdict = if score >= self.block_score {
+            RiskVerdict::Block
+        } else if score >= self.step_up_score {
+            RiskVerdict::StepUp
+        } else {
+            RiskVerdict::Allow
+        };
+        RiskAssessment { score, verdict, reasons }
+    }
+}
+
+/// A custom risk engine; managing one replaces `ROCKET_LOGIN_RISK`
+///
+/// `build_rocket(config, jwt).manage(LoginRisk::new(MyAssessor))`
+pub struct LoginRisk {
+    engine: Box<dyn RiskAssessor>,
+}
+
+impl LoginRisk {
+    pub fn new(engine: impl RiskAssessor + 'static) -> Self {
+        LoginRisk {
+            engine: Box::new(engine),
+        }
+    }
+}
+
+/// The client's country, from the header named by `ROCKET_GEO_COUNTRY_HEADER`
+///
+/// `None` when no header is configured or the request didn't carry it.
+#[derive(Debug, Clone, Copy)]
+pub struct GeoCountry<'r>(pub Option<&'r str>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for GeoCountry<'r> {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(GeoCountry(geo_country(request)))
+    }
+}
+
+fn geo_country<'r>(request: &'r Request<'_>) -> Option<&'r str> {
+    geo_header(request, |config| config.geo_country_header.as_deref())
+}
+
+/// The value of the geolocation header the configuration names, if it names one and the request carries it
+pub(crate) fn geo_header<'r>(request: &'r Request<'_>, name: fn(&AppConfig) -> Option<&str>) -> Option<&'r str> {
+    request
+        .rocket()
+        .state::<AppConfig>()
+        .and_then(name)
+        .and_then(|header| request.headers().get_one(header))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+enum Engine<'r> {
+    Custom(&'r dyn RiskAssessor),
+    Heuristic(HeuristicRiskAssessor),
+}
+
+/// Request guard with the client details and engine a login is assessed with
+pub struct RiskCheck<'r> {
+    engine: Option<Engine<'r>>,
+    failure_window: Duration,
+    user_agent: Option<&'r str>,
+    country: Option<&'r str>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RiskCheck<'r> {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let config = request.rocket().state::<AppConfig>();
+        let engine = match (request.rocket().state::<LoginRisk>(), config) {
+            (Some(custom), _) => Some(Engine::Custom(custom.engine.as_ref())),
+            (None, Some(config)) if config.login_risk == LoginRiskEngine::Heuristic => {
+                Some(Engine::Heuristic(HeuristicRiskAssessor::from_config(config)))
+            }
+            _ => None,
+        };
+        // Failures count within the lockout window, which admins may change at runtime
+        let lockout_minutes = match request.rocket().state::<Settings>() {
+            Some(settings) => settings.current().login_lockout_minutes,
+            None => config.map_or(15, |config| config.login_lockout_minutes),
+        };
+        Outcome::Success(RiskCheck {
+            engine,
+            failure_window: Duration::minutes(lockout_minutes as i64),
+            user_agent: request.headers().get_one("User-Agent"),
+            country: geo_country(request),
+        })
+    }
+}
+
+impl RiskCheck<'_> {
+    /// The client's country, if known
+    pub fn country(&self) -> Option<&str> {
+        self.country
+    }
+
+    /// Gather the signals for `user` logging in from `ip` and assess them; `None` without an engine
+    pub async fn assess(
+        &self,
+        conn: &mut PgConnection,
+        user: &User,
+        ip: Option<IpAddr>,
+    ) -> Result<Option<RiskAssessment>, sqlx::Error> {
+        let Some(engine) = &self.engine else {
+            return Ok(None);
+        };
+
+        let now = Utc::now();
+        let new_ip = match ip {
+            Some(ip) if user.last_login_at.is_some() => {
+                !login_attempts::has_succeeded_from(conn, user.id, ip, now - Duration::days(FAMILIAR_IP_DAYS)).await?
+            }
+            _ => false,
+        };
+        let recent_failures = login_attempts::recent_failures(conn, &user.email, now - self.failure_window, 100).await?;
+        let recent_login_ips =
+            login_attempts::count_success_ips(conn, user.id, now - Duration::minutes(VELOCITY_WINDOW_MINUTES)).await?;
+
+        let signals = LoginSignals {
+            user,
+            ip,
+            country: self.country,
+            user_agent: self.user_agent,
+            new_ip,
+            recent_failures: recent_failures.len() as u32,
+            recent_login_ips: recent_login_ips as u32,
+        };
+        let assessment = match engine {
+            Engine::Custom(engine) => engine.assess(&signals).await,
+            Engine::Heuristic(engine) => engine.assess(&signals).await,
+        };
+        Ok(Some(assessment))
+    }
+}
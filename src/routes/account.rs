@@ -0,0 +1,485 @@
+use std::net::IpAddr;
+
+use chrono::{DateTime, Duration, Utc};
+use rocket::futures::StreamExt;
+use rocket::http::{ContentType, Header, Status};
+use rocket::response::status;
+use rocket::response::stream::TextStream;
+use rocket::serde::json::{Json, Value, json};
+use rocket::State;
+use rocket_db_pools::Connection;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::auth::access_tokens::AccessTokens;
+use crate::auth::guard::RegisteredUser;
+use crate::auth::jwt::{JwtService, TokenAction};
+use crate::auth::password::PasswordHasher;
+use crate::auth::signed_urls::{self, DataExport, SignedUrl};
+use crate::auth::token_versions::TokenVersions;
+use crate::body_limits::JsonBody;
+use crate::config::AppConfig;
+use crate::email::sender::Mailer;
+use crate::email::templates;
+use crate::events::{self, SecurityEventKind};
+use crate::links::{self, LinkAction};
+use crate::maintenance::WriteAccess;
+use crate::models::audit::AuditFilter;
+use crate::models::user::{ChangeEmail, ConfirmAction, ConfirmPassword, User};
+use crate::repositories::{audit, nonces, sessions, user_emails, users};
+use crate::routes::auth::send_password_reset_email;
+use crate::Postgres;
+
+/// Ask to move the account to a new email address
+///
+/// Emails a single-use confirmation link to the new address; nothing changes
+/// until it's opened.
+#[post("/me/email-change", data = "<change>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn request_email_change(
+    _write: WriteAccess,
+    user: RegisteredUser,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    jwt: &State<JwtService>,
+    mailer: &State<Mailer>,
+    passwords: &State<PasswordHasher>,
+    change: JsonBody<ChangeEmail>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let account = current_user(&mut db, &user).await?;
+    check_password(passwords, &change.password, &account).await?;
+
+    let email = change.new_email.trim();
+    if !email.contains('@') {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "Invalid email format"
+            })),
+        ));
+    }
+    if users::email_exists(&mut db, email).await.map_err(database_error)? {
+        return Err(email_taken());
+    }
+
+    let action = TokenAction::ChangeEmail {
+        email: email.to_string(),
+    };
+    let token = action_token(jwt, &user, action).await?;
+    let link = links::email_link(config, LinkAction::ConfirmEmailChange, &token);
+    if let Err(e) = mailer.send(templates::email_change_confirmation(email, &link)).await {
+        eprintln!("{}", e);
+    }
+
+    Ok(status::Custom(
+        Status::Accepted,
+        Json(json!({
+            "message": "Check the new address for a confirmation link"
+        })),
+    ))
+}
+
+/// Confirm an email change with the token from the confirmation link
+#[post("/email-change/confirm", data = "<confirm>")]
+pub async fn confirm_email_change(
+    _write: WriteAccess,
+    mut db: Connection<Postgres>,
+    jwt: &State<JwtService>,
+    confirm: JsonBody<ConfirmAction>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let redeemed = redeem(&mut db, jwt, &confirm.token, |action| matches!(action, TokenAction::ChangeEmail { .. })).await?;
+    let (user_id, email) = match redeemed {
+        (user_id, TokenAction::ChangeEmail { email }) => (user_id, email),
+        _ => return Err(invalid_token()),
+    };
+
+    // The address may have been registered since the link was sent
+    if users::email_exists(&mut db, &email).await.map_err(database_error)? {
+        return Err(email_taken());
+    }
+
+    let user = match users::change_email(&mut db, user_id, &email).await.map_err(database_error)? {
+        Some(user) => user,
+        None => return Err(invalid_token()),
+    };
+    events::emit(&mut db, user.id, SecurityEventKind::EmailChanged).await;
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "Email address changed",
+            "user": {
+                "id": user.id.to_string(),
+                "email": user.email,
+                "email_verified": true
+            }
+        })),
+    ))
+}
+
+/// Ask to delete the account
+///
+/// Emails a single-use confirmation link to the account's address; the
+/// account is only deleted once it's opened.
+#[post("/me/delete", data = "<confirm>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn request_account_deletion(
+    _write: WriteAccess,
+    user: RegisteredUser,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    jwt: &State<JwtService>,
+    mailer: &State<Mailer>,
+    passwords: &State<PasswordHasher>,
+    confirm: JsonBody<ConfirmPassword>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let account = current_user(&mut db, &user).await?;
+    check_password(passwords, &confirm.password, &account).await?;
+
+    let token = action_token(jwt, &user, TokenAction::DeleteAccount).await?;
+    let link = links::email_link(config, LinkAction::ConfirmAccountDeletion, &token);
+    if let Err(e) = mailer.send(templates::account_deletion_confirmation(&account.email, &link)).await {
+        eprintln!("{}", e);
+    }
+
+    Ok(status::Custom(
+        Status::Accepted,
+        Json(json!({
+            "message": "Check your email for a confirmation link"
+        })),
+    ))
+}
+
+/// Delete the account with the token from the confirmation link
+#[post("/delete/confirm", data = "<confirm>")]
+pub async fn confirm_account_deletion(
+    _write: WriteAccess,
+    mut db: Connection<Postgres>,
+    jwt: &State<JwtService>,
+    access_tokens: &State<AccessTokens>,
+    confirm: JsonBody<ConfirmAction>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let (user_id, _) = redeem(&mut db, jwt, &confirm.token, |action| *action == TokenAction::DeleteAccount).await?;
+
+    match users::delete(&mut db, user_id).await.map_err(database_error)? {
+        true => {
+            access_tokens.forget_user(user_id);
+            Ok(status::Custom(
+                Status::Ok,
+                Json(json!({
+                    "message": "Account deleted"
+                })),
+            ))
+        }
+        false => Err(invalid_token()),
+    }
+}
+
+/// Sign out everywhere and send a password reset, with the token from a password change notification
+///
+/// For users whose password was changed by someone else; works without
+/// signing in, since they may no longer be able to.
+#[post("/secure-account", data = "<confirm>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn secure_account(
+    _write: WriteAccess,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    jwt: &State<JwtService>,
+    mailer: &State<Mailer>,
+    access_tokens: &State<AccessTokens>,
+    token_versions: &State<TokenVersions>,
+    confirm: JsonBody<ConfirmAction>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let (user_id, _) = redeem(&mut db, jwt, &confirm.token, |action| *action == TokenAction::SecureAccount).await?;
+    let user = users::find_by_id(&mut db, user_id)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(invalid_token)?;
+
+    let revoked = sessions::revoke_all_for_user(&mut db, user_id).await.map_err(database_error)?;
+    token_versions.bump(&mut db, user_id).await.map_err(database_error)?;
+    access_tokens.forget_user(user_id);
+    events::emit(&mut db, user_id, SecurityEventKind::AllSessionsRevoked { count: revoked }).await;
+
+    send_password_reset_email(&mut db, config, mailer, &user, &user.email)
+        .await
+        .map_err(database_error)?;
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "Signed out of all sessions; check your email to reset your password",
+            "revoked_sessions": revoked
+        })),
+    ))
+}
+
+/// Get a link to download everything stored about the current user
+///
+/// The link works once, for `ROCKET_SIGNED_URL_TTL_SECONDS`, and needs no
+/// `Authorization` header, so it can be handed to the browser.
+#[post("/me/export-link")]
+pub async fn request_data_export(
+    user: RegisteredUser,
+    config: &State<AppConfig>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let Some(secret) = &config.signed_url_secret else {
+        return Err(status::Custom(
+            Status::NotFound,
+            Json(json!({
+                "error": "Data export links are disabled"
+            })),
+        ));
+    };
+    let user_id = Uuid::parse_str(&user.user_id).map_err(|_| invalid_token())?;
+
+    let expires_at = Utc::now() + Duration::seconds(config.signed_url_ttl_seconds as i64);
+    let url = format!("{}/api/v1/auth/me/export", config.public_url);
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "url": signed_urls::sign::<DataExport>(secret, &url, user_id, expires_at),
+            "expires_at": expires_at.to_rfc3339()
+        })),
+    ))
+}
+
+/// A download the browser saves to a file
+#[derive(Responder)]
+pub struct Attachment<R> {
+    body: R,
+    disposition: Header<'static>,
+}
+
+/// Everything stored about a user, as newline-delimited JSON, from a link made by [`request_data_export`]
+///
+/// The first line is the profile, followed by secondary emails, active
+/// sessions and the user's audit events, each with a `type`. Audit events
+/// are streamed, so large histories don't have to fit in memory.
+#[get("/me/export")]
+pub async fn download_data_export(
+    link: SignedUrl<DataExport>,
+    mut db: Connection<Postgres>,
+    access_tokens: &State<AccessTokens>,
+) -> Result<Attachment<(ContentType, TextStream![String])>, status::Custom<Json<Value>>> {
+    let user = users::find_by_id(&mut db, link.user_id)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(|| status::Custom(Status::NotFound, Json(json!({ "error": "User not found" }))))?;
+    let emails = user_emails::list_for_user(&mut db, user.id).await.map_err(database_error)?;
+    let active = sessions::list_active_for_user(&mut db, user.id, access_tokens.active_since())
+        .await
+        .map_err(database_error)?;
+
+    let mut records = vec![json!({
+        "type": "user",
+        "id": user.id.to_string(),
+        "email": user.email,
+        "email_verified": user.email_verified_at.is_some(),
+        "role": user.role,
+        "user_metadata": user.user_metadata,
+        "app_metadata": user.app_metadata,
+        "locale": user.locale,
+        "timezone": user.timezone,
+        "last_login_at": user.last_login_at.map(|at| at.to_rfc3339()),
+        "last_login_ip": user.last_login_ip,
+        "login_count": user.login_count,
+        "created_at": user.created_at.to_rfc3339()
+    })];
+    records.extend(emails.iter().map(|email| {
+        json!({
+            "type": "email",
+            "email": email.email,
+            "verified_at": email.verified_at.map(|at| at.to_rfc3339()),
+            "created_at": email.created_at.to_rfc3339()
+        })
+    }));
+    records.extend(active.iter().map(|session| {
+        json!({
+            "type": "session",
+            "id": session.id.to_string(),
+            "oauth_client_id": session.oauth_client_id.map(|id| id.to_string()),
+            "created_at": session.created_at.to_rfc3339(),
+            "last_used_at": session.last_used_at.to_rfc3339(),
+            "expires_at": session.expires_at.to_rfc3339()
+        })
+    }));
+
+    let filter = AuditFilter {
+        user_id: Some(user.id),
+        ..AuditFilter::default()
+    };
+    let export = TextStream! {
+        for record in records {
+            yield format!("{}\n", record);
+        }
+        let mut events = audit::export(&mut db, &filter);
+        while let Some(event) = events.next().await {
+            match event {
+                Ok(event) => yield format!("{}\n", json!({ "type": "audit_event", "event": event })),
+                Err(e) => {
+                    // Too late for an error status; the export just ends early
+                    eprintln!("Database error: {}", e);
+                    break;
+                }
+            }
+        }
+    };
+    Ok(Attachment {
+        body: (ContentType::new("application", "x-ndjson"), export),
+        disposition: Header::new("Content-Disposition", "attachment; filename=\"data-export.ndjson\""),
+    })
+}
+
+/// Tell the user their password was changed, with a link to [`secure_account`] if it wasn't them
+///
+/// Failures are logged; the change stands either way.
+pub(crate) async fn notify_password_changed(
+    config: &AppConfig,
+    jwt: &JwtService,
+    mailer: &Mailer,
+    user: &User,
+    session_id: &str,
+    ip: Option<IpAddr>,
+) {
+    let token = match jwt
+        .generate_action_token(user.id.to_string(), session_id.to_string(), TokenAction::SecureAccount)
+        .await
+    {
+        Ok(token) => token,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let link = links::email_link(config, LinkAction::SecureAccount, &token);
+    let changed_at = templates::local_time(Utc::now(), user.timezone.as_deref());
+    let ip = ip.map(|ip| ip.to_string());
+    if let Err(e) = mailer.send(templates::password_changed(&user.email, &changed_at, ip.as_deref(), &link)).await {
+        eprintln!("{}", e);
+    }
+}
+
+/// Sign an action token for the current session
+async fn action_token(
+    jwt: &JwtService,
+    user: &RegisteredUser,
+    action: TokenAction,
+) -> Result<String, status::Custom<Json<Value>>> {
+    jwt.generate_action_token(user.user_id.clone(), user.session_id.clone(), action)
+        .await
+        .map_err(|e| {
+            eprintln!("{}", e);
+            status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Failed to create confirmation token"
+                })),
+            )
+        })
+}
+
+/// Verify an action token for an action `accepts` and use it up
+///
+/// Fails with 400 for invalid or expired tokens, tokens for another action,
+/// and once a token has been redeemed before.
+async fn redeem(
+    conn: &mut PgConnection,
+    jwt: &JwtService,
+    token: &str,
+    accepts: impl FnOnce(&TokenAction) -> bool,
+) -> Result<(Uuid, TokenAction), status::Custom<Json<Value>>> {
+    let claims = jwt.verify_token(token).await.map_err(|_| invalid_token())?;
+    let action = claims.act.filter(accepts).ok_or_else(invalid_token)?;
+    let user_id = Uuid::parse_str(&claims.sub).map_err(|_| invalid_token())?;
+    let expires_at = DateTime::<Utc>::from_timestamp(claims.exp as i64, 0).ok_or_else(invalid_token)?;
+    if claims.jti.is_empty() {
+        return Err(invalid_token());
+    }
+
+    if !nonces::consume(conn, &claims.jti, expires_at).await.map_err(database_error)? {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "This confirmation link has already been used",
+                "code": "token_used"
+            })),
+        ));
+    }
+    Ok((user_id, action))
+}
+
+/// Re-check the current password before a sensitive change
+async fn check_password(
+    passwords: &PasswordHasher,
+    password: &str,
+    user: &User,
+) -> Result<(), status::Custom<Json<Value>>> {
+    match passwords.verify(password, &user.password_hash).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "Current password is incorrect"
+            })),
+        )),
+        Err(e) => {
+            eprintln!("{}", e);
+            Err(status::Custom(
+                e.status(),
+                Json(json!({
+                    "error": "Failed to verify password"
+                })),
+            ))
+        }
+    }
+}
+
+/// Load the signed-in user
+async fn current_user(conn: &mut PgConnection, user: &RegisteredUser) -> Result<User, status::Custom<Json<Value>>> {
+    let not_found = || {
+        status::Custom(
+            Status::NotFound,
+            Json(json!({
+                "error": "User not found"
+            })),
+        )
+    };
+
+    let user_id = Uuid::parse_str(&user.user_id).map_err(|_| not_found())?;
+    users::find_by_id(conn, user_id)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(not_found)
+}
+
+fn invalid_token() -> status::Custom<Json<Value>> {
+    status::Custom(
+        Status::BadRequest,
+        Json(json!({
+            "error": "Invalid or expired confirmation link",
+            "code": "token_invalid"
+        })),
+    )
+}
+
+fn email_taken() -> status::Custom<Json<Value>> {
+    status::Custom(
+        Status::Conflict,
+        Json(json!({
+            "error": "User with this email already exists"
+        })),
+    )
+}
+
+fn database_error(e: sqlx::Error) -> status::Custom<Json<Value>> {
+    eprintln!("Database error: {}", e);
+    status::Custom(
+        Status::InternalServerError,
+        Json(json!({
+            "error": "Database error occurred"
+        })),
+    )
+}
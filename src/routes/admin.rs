@@ -0,0 +1,906 @@
+use std::net::IpAddr;
+
+use chrono::{Duration, Utc};
+use rocket::serde::json::{Json, Value, json};
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::State;
+use rocket_db_pools::Connection;
+use sqlx::PgConnection;
+
+use crate::auth::access_tokens::AccessTokens;
+use crate::auth::guard::AdminUser;
+use crate::auth::permissions::USERS_DELETE;
+use crate::authz::{Authz, Resource};
+use crate::body_limits::JsonBody;
+use crate::config::AppConfig;
+use crate::email::sender::Mailer;
+use crate::email::templates;
+use crate::events::{self, SecurityEvent, SecurityEventKind};
+use crate::maintenance::{MaintenanceMode, MaintenanceUpdate, WriteAccess};
+use crate::models::invitation::NewInvitation;
+use crate::models::mfa::MfaReset;
+use crate::models::user::{MetadataPatch, MustChangePasswordUpdate, SignupDecision, User};
+use crate::repositories::{invitations, ip_blocks, mfa, stats, users};
+use crate::routes::auth as auth_routes;
+use crate::Postgres;
+
+/// Default lifetime of invitation codes
+const DEFAULT_INVITATION_DAYS: i64 = 7;
+
+/// Default and maximum number of days covered by the daily statistics
+const DEFAULT_STATS_DAYS: i32 = 30;
+const MAX_STATS_DAYS: i32 = 365;
+
+/// Get the current maintenance mode status
+#[get("/maintenance")]
+pub async fn get_maintenance(
+    _admin: AdminUser,
+    mode: &State<MaintenanceMode>,
+) -> status::Custom<Json<Value>> {
+    status::Custom(
+        Status::Ok,
+        Json(json!({
+            "enabled": mode.is_enabled(),
+            "message": mode.message()
+        })),
+    )
+}
+
+/// Enable or disable maintenance (read-only) mode
+#[post("/maintenance", data = "<update>")]
+pub async fn set_maintenance(
+    admin: AdminUser,
+    mode: &State<MaintenanceMode>,
+    update: JsonBody<MaintenanceUpdate>,
+) -> status::Custom<Json<Value>> {
+    let update = update.into_inner();
+    mode.set(update.enabled, update.message);
+
+    if update.enabled {
+        println!("⚠ Maintenance mode enabled by admin {}", admin.user_id);
+    } else {
+        println!("✓ Maintenance mode disabled by admin {}", admin.user_id);
+    }
+
+    status::Custom(
+        Status::Ok,
+        Json(json!({
+            "enabled": mode.is_enabled(),
+            "message": mode.message()
+        })),
+    )
+}
+
+/// Create an invitation code; emails it when an address is given
+#[post("/invitations", data = "<invitation>")]
+pub async fn create_invitation(
+    _write: WriteAccess,
+    admin: AdminUser,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    mailer: &State<Mailer>,
+    invitation: JsonBody<NewInvitation>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let days = invitation.expires_in_days.unwrap_or(DEFAULT_INVITATION_DAYS);
+    if !(1..=365).contains(&days) {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "expires_in_days must be between 1 and 365"
+            })),
+        ));
+    }
+    if let Some(email) = &invitation.email
+        && !email.contains('@')
+    {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "Invalid email format"
+            })),
+        ));
+    }
+
+    let admin_id = uuid::Uuid::parse_str(&admin.user_id).ok();
+    let code = uuid::Uuid::new_v4().to_string();
+    let expires_at = Utc::now() + Duration::days(days);
+
+    let created = match invitations::create(&mut db, &code, invitation.email.as_deref(), admin_id, expires_at).await {
+        Ok(created) => created,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Failed to create invitation"
+                })),
+            ));
+        }
+    };
+
+    if let Some(email) = &created.email {
+        let link = format!("{}/register?invitation={}", config.frontend_url, created.code);
+        if let Err(e) = mailer.send(templates::invitation(email, &created.code, &link)).await {
+            eprintln!("{}", e);
+        }
+    }
+
+    Ok(status::Custom(
+        Status::Created,
+        Json(json!({
+            "code": created.code,
+            "email": created.email,
+            "expires_at": created.expires_at.to_rfc3339()
+        })),
+    ))
+}
+
+/// List invitations that can still be used, newest first
+#[get("/invitations?<limit>&<offset>")]
+pub async fn list_invitations(
+    _admin: AdminUser,
+    mut db: Connection<Postgres>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let limit = limit.unwrap_or(50).clamp(1, 200);
+    let offset = offset.unwrap_or(0).max(0);
+
+    match invitations::list_pending(&mut db, limit, offset).await {
+        Ok(pending) => Ok(status::Custom(
+            Status::Ok,
+            Json(json!({
+                "invitations": pending
+            })),
+        )),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ))
+        }
+    }
+}
+
+/// Signups waiting for approval, oldest first
+#[get("/signups?<limit>&<offset>")]
+pub async fn list_pending_signups(
+    _admin: AdminUser,
+    mut db: Connection<Postgres>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let limit = limit.unwrap_or(50).clamp(1, 200);
+    let offset = offset.unwrap_or(0).max(0);
+
+    match users::list_pending_approval(&mut db, limit, offset).await {
+        Ok(pending) => {
+            let signups: Vec<Value> = pending
+                .iter()
+                .map(|user| {
+                    json!({
+                        "id": user.id.to_string(),
+                        "email": user.email,
+                        "email_verified": user.email_verified_at.is_some(),
+                        "created_at": user.created_at.to_rfc3339()
+                    })
+                })
+                .collect();
+
+            Ok(status::Custom(
+                Status::Ok,
+                Json(json!({
+                    "signups": signups
+                })),
+            ))
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ))
+        }
+    }
+}
+
+/// Approve a pending signup and send the welcome email
+#[post("/signups/<id>/approve", data = "<decision>")]
+pub async fn approve_signup(
+    _write: WriteAccess,
+    admin: AdminUser,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    mailer: &State<Mailer>,
+    id: &str,
+    decision: JsonBody<SignupDecision>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let user = decide_signup(&mut db, id, "approved", decision.reason.as_deref()).await?;
+    println!("✓ Signup {} approved by admin {}", user.email, admin.user_id);
+
+    let link = format!("{}/login", config.frontend_url);
+    if let Err(e) = mailer.send(templates::welcome(&user.email, &link)).await {
+        eprintln!("{}", e);
+    }
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "id": user.id.to_string(),
+            "email": user.email,
+            "approval_status": user.approval_status,
+            "approval_reason": user.approval_reason
+        })),
+    ))
+}
+
+/// Reject a pending signup; the reason is shown to the user when they try to log in
+#[post("/signups/<id>/reject", data = "<decision>")]
+pub async fn reject_signup(
+    _write: WriteAccess,
+    admin: AdminUser,
+    mut db: Connection<Postgres>,
+    id: &str,
+    decision: JsonBody<SignupDecision>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let reason = match decision.reason.as_deref().map(str::trim) {
+        Some(reason) if !reason.is_empty() => reason,
+        _ => {
+            return Err(status::Custom(
+                Status::BadRequest,
+                Json(json!({
+                    "error": "A reason is required to reject a signup"
+                })),
+            ));
+        }
+    };
+
+    let user = decide_signup(&mut db, id, "rejected", Some(reason)).await?;
+    println!("✓ Signup {} rejected by admin {}", user.email, admin.user_id);
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "id": user.id.to_string(),
+            "email": user.email,
+            "approval_status": user.approval_status,
+            "approval_reason": user.approval_reason
+        })),
+    ))
+}
+
+/// Record an approval decision, failing with 404 unless the signup is pending
+async fn decide_signup(
+    conn: &mut PgConnection,
+    id: &str,
+    status: &str,
+    reason: Option<&str>,
+) -> Result<User, status::Custom<Json<Value>>> {
+    let not_found = || {
+        status::Custom(
+            Status::NotFound,
+            Json(json!({
+                "error": "No pending signup with this id"
+            })),
+        )
+    };
+
+    let id = uuid::Uuid::parse_str(id).map_err(|_| not_found())?;
+    match users::decide_approval(conn, id, status, reason).await {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err(not_found()),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ))
+        }
+    }
+}
+
+/// List users, oldest first, with their login activity
+///
+/// With `inactive_days`, only users who haven't logged in for that many days
+/// (counting from signup for those who never did) are listed.
+#[get("/users?<limit>&<offset>&<inactive_days>")]
+pub async fn list_users(
+    _admin: AdminUser,
+    mut db: Connection<Postgres>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+    inactive_days: Option<u32>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let limit = limit.unwrap_or(50).clamp(1, 200);
+    let offset = offset.unwrap_or(0).max(0);
+    let inactive_since = inactive_days.map(|days| Utc::now() - Duration::days(days as i64));
+
+    match users::list(&mut db, limit, offset, inactive_since).await {
+        Ok(listed) => Ok(status::Custom(
+            Status::Ok,
+            Json(json!({
+                "users": listed.iter().map(|user| json!({
+                    "id": user.id.to_string(),
+                    "email": user.email,
+                    "role": user.role,
+                    "email_verified": user.email_verified_at.is_some(),
+                    "approval_status": user.approval_status,
+                    "last_login_at": user.last_login_at.map(|at| at.to_rfc3339()),
+                    "last_login_ip": user.last_login_ip,
+                    "login_count": user.login_count,
+                    "inactivity_warned_at": user.inactivity_warned_at.map(|at| at.to_rfc3339()),
+                    "flagged_inactive_at": user.flagged_inactive_at.map(|at| at.to_rfc3339()),
+                    "disabled_at": user.disabled_at.map(|at| at.to_rfc3339()),
+                    "created_at": user.created_at.to_rfc3339()
+                })).collect::<Vec<_>>()
+            })),
+        )),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ))
+        }
+    }
+}
+
+/// Email a password reset link to a user on their behalf
+#[post("/users/<id>/send-password-reset")]
+pub async fn send_password_reset(
+    _write: WriteAccess,
+    admin: AdminUser,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    mailer: &State<Mailer>,
+    id: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let user = find_user(&mut db, id).await?;
+    if let Err(e) = auth_routes::send_password_reset_email(&mut db, config, mailer, &user, &user.email).await {
+        eprintln!("Database error: {}", e);
+        return Err(status::Custom(
+            Status::InternalServerError,
+            Json(json!({
+                "error": "Failed to create reset token"
+            })),
+        ));
+    }
+    println!("✓ Password reset for {} sent by admin {}", user.email, admin.user_id);
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "Password reset email sent",
+            "email": user.email
+        })),
+    ))
+}
+
+/// Require a user to change their password before they can use any other endpoint
+#[post("/users/<id>/must-change-password", data = "<update>")]
+pub async fn set_must_change_password(
+    _write: WriteAccess,
+    admin: AdminUser,
+    mut db: Connection<Postgres>,
+    id: &str,
+    update: JsonBody<MustChangePasswordUpdate>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let user = find_user(&mut db, id).await?;
+    match users::set_must_change_password(&mut db, user.id, update.enabled).await {
+        Ok(_) => {
+            println!(
+                "✓ must_change_password {} for {} by admin {}",
+                if update.enabled { "set" } else { "cleared" },
+                user.email,
+                admin.user_id
+            );
+            Ok(status::Custom(
+                Status::Ok,
+                Json(json!({
+                    "id": user.id.to_string(),
+                    "email": user.email,
+                    "must_change_password": update.enabled
+                })),
+            ))
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ))
+        }
+    }
+}
+
+/// Clear the inactivity policy's flags on a user, re-enabling a disabled account
+///
+/// The user counts as active from now on, so the policy won't apply again
+/// for another full period.
+#[post("/users/<id>/reactivate")]
+pub async fn reactivate_user(
+    _write: WriteAccess,
+    admin: AdminUser,
+    mut db: Connection<Postgres>,
+    id: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let user = find_user(&mut db, id).await?;
+    match users::reactivate(&mut db, user.id).await {
+        Ok(Some(reactivated)) => {
+            println!("✓ {} reactivated by admin {}", reactivated.email, admin.user_id);
+            Ok(status::Custom(
+                Status::Ok,
+                Json(json!({
+                    "id": reactivated.id.to_string(),
+                    "email": reactivated.email,
+                    "reactivated_at": reactivated.reactivated_at.map(|at| at.to_rfc3339())
+                })),
+            ))
+        }
+        Ok(None) => Err(status::Custom(
+            Status::NotFound,
+            Json(json!({
+                "error": "User not found"
+            })),
+        )),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ))
+        }
+    }
+}
+
+/// Patch a user's `user_metadata` and/or `app_metadata`
+///
+/// Same merge rules as `PATCH /api/v1/auth/me`. Tokens embedding `app_metadata`
+/// only pick up changes when they are next issued.
+#[patch("/users/<id>/metadata", data = "<patch>")]
+pub async fn patch_user_metadata(
+    _write: WriteAccess,
+    admin: AdminUser,
+    mut db: Connection<Postgres>,
+    id: &str,
+    patch: JsonBody<MetadataPatch>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    for metadata in [&patch.user_metadata, &patch.app_metadata].into_iter().flatten() {
+        auth_routes::validate_metadata(metadata)?;
+    }
+
+    let user = find_user(&mut db, id).await?;
+    match users::patch_metadata(&mut db, user.id, patch.user_metadata.as_ref(), patch.app_metadata.as_ref(), None, None, None).await {
+        Ok(Some(user)) => {
+            println!("✓ Metadata for {} updated by admin {}", user.email, admin.user_id);
+            Ok(status::Custom(
+                Status::Ok,
+                Json(json!({
+                    "id": user.id.to_string(),
+                    "email": user.email,
+                    "user_metadata": user.user_metadata,
+                    "app_metadata": user.app_metadata
+                })),
+            ))
+        }
+        Ok(None) => Err(status::Custom(
+            Status::NotFound,
+            Json(json!({
+                "error": "User not found"
+            })),
+        )),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ))
+        }
+    }
+}
+
+/// Delete a user account along with its sessions, tokens and keys
+///
+/// Needs the `users.delete` action on the user rather than the admin role.
+#[delete("/users/<id>")]
+pub async fn delete_user(
+    _write: WriteAccess,
+    authz: Authz<'_>,
+    mut db: Connection<Postgres>,
+    access_tokens: &State<AccessTokens>,
+    id: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    authz.require(USERS_DELETE, &Resource::new("user", id)).await?;
+
+    let user = find_user(&mut db, id).await?;
+    if user.id == authz.subject.user_id {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "You can't delete your own account here"
+            })),
+        ));
+    }
+
+    match users::delete(&mut db, user.id).await {
+        Ok(true) => {
+            access_tokens.forget_user(user.id);
+            println!("✓ User {} deleted by {}", user.email, authz.subject.user_id);
+            Ok(status::Custom(
+                Status::Ok,
+                Json(json!({
+                    "message": "User deleted"
+                })),
+            ))
+        }
+        Ok(false) => Err(status::Custom(
+            Status::NotFound,
+            Json(json!({
+                "error": "User not found"
+            })),
+        )),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ))
+        }
+    }
+}
+
+/// Remove a user's MFA right away, for users support has verified some other way
+///
+/// Needs a `reason`, which is kept with the admin's id in the user's recovery
+/// history. Pending recoveries are cancelled and the user is notified by email.
+#[post("/users/<id>/mfa/reset", data = "<reset>")]
+pub async fn reset_user_mfa(
+    _write: WriteAccess,
+    admin: AdminUser,
+    mut db: Connection<Postgres>,
+    mailer: &State<Mailer>,
+    id: &str,
+    reset: JsonBody<MfaReset>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let reason = reset.reason.trim();
+    if reason.is_empty() {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "A reason is required"
+            })),
+        ));
+    }
+
+    let user = find_user(&mut db, id).await?;
+    let admin_id = uuid::Uuid::parse_str(&admin.user_id).map_err(|_| {
+        status::Custom(
+            Status::Unauthorized,
+            Json(json!({
+                "error": "Invalid token subject"
+            })),
+        )
+    })?;
+    let recovery = match mfa::admin_reset(&mut db, user.id, admin_id, reason).await {
+        Ok(Some(recovery)) => recovery,
+        Ok(None) => {
+            return Err(status::Custom(
+                Status::NotFound,
+                Json(json!({
+                    "error": "This user has no MFA set up"
+                })),
+            ));
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ));
+        }
+    };
+
+    if let Err(e) = mailer.send(templates::mfa_removed(&user.email)).await {
+        eprintln!("{}", e);
+    }
+    let event = SecurityEvent::new(
+        user.id,
+        SecurityEventKind::MfaReset {
+            recovery_id: recovery.id,
+            admin_id: Some(admin_id),
+        },
+    );
+    events::emit_event(&mut db, event.by(admin_id)).await;
+    println!("✓ MFA reset for {} by admin {}: {}", user.email, admin.user_id, reason);
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "MFA removed",
+            "id": user.id.to_string(),
+            "email": user.email,
+            "recovery_id": recovery.id.to_string()
+        })),
+    ))
+}
+
+/// A user's MFA recoveries and admin resets, newest first
+#[get("/users/<id>/mfa/recoveries")]
+pub async fn list_user_mfa_recoveries(
+    _admin: AdminUser,
+    mut db: Connection<Postgres>,
+    id: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let user = find_user(&mut db, id).await?;
+    match mfa::list_recoveries(&mut db, user.id).await {
+        Ok(recoveries) => Ok(status::Custom(
+            Status::Ok,
+            Json(json!({
+                "recoveries": recoveries.iter().map(|recovery| json!({
+                    "id": recovery.id.to_string(),
+                    "status": recovery.status(),
+                    "admin_id": recovery.admin_id.map(|id| id.to_string()),
+                    "reason": recovery.reason,
+                    "requested_at": recovery.requested_at.to_rfc3339(),
+                    "confirmed_at": recovery.confirmed_at.map(|at| at.to_rfc3339()),
+                    "available_at": recovery.available_at.map(|at| at.to_rfc3339()),
+                    "completed_at": recovery.completed_at.map(|at| at.to_rfc3339()),
+                    "cancelled_at": recovery.cancelled_at.map(|at| at.to_rfc3339())
+                })).collect::<Vec<_>>()
+            })),
+        )),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ))
+        }
+    }
+}
+
+/// Look up a user by id from the path, failing with 404
+async fn find_user(conn: &mut PgConnection, id: &str) -> Result<User, status::Custom<Json<Value>>> {
+    let not_found = || {
+        status::Custom(
+            Status::NotFound,
+            Json(json!({
+                "error": "User not found"
+            })),
+        )
+    };
+
+    let id = uuid::Uuid::parse_str(id).map_err(|_| not_found())?;
+    match users::find_by_id(conn, id).await {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err(not_found()),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ))
+        }
+    }
+}
+
+/// Client addresses currently blocked for credential stuffing, newest first
+#[get("/ip-blocks?<limit>&<offset>")]
+pub async fn list_ip_blocks(
+    _admin: AdminUser,
+    mut db: Connection<Postgres>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let limit = limit.unwrap_or(50).clamp(1, 200);
+    let offset = offset.unwrap_or(0).max(0);
+
+    match ip_blocks::list_active(&mut db, limit, offset).await {
+        Ok(blocks) => Ok(status::Custom(
+            Status::Ok,
+            Json(json!({
+                "ip_blocks": blocks
+            })),
+        )),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ))
+        }
+    }
+}
+
+/// Lift a credential stuffing block early, e.g. for a shared office network
+#[delete("/ip-blocks/<ip>")]
+pub async fn lift_ip_block(
+    _write: WriteAccess,
+    admin: AdminUser,
+    mut db: Connection<Postgres>,
+    ip: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let not_found = || {
+        status::Custom(
+            Status::NotFound,
+            Json(json!({
+                "error": "Address is not blocked"
+            })),
+        )
+    };
+    let address: IpAddr = ip.parse().map_err(|_| not_found())?;
+
+    match ip_blocks::lift(&mut db, address).await {
+        Ok(true) => {
+            println!("✓ Login block on {} lifted by admin {}", address, admin.user_id);
+            Ok(status::Custom(
+                Status::Ok,
+                Json(json!({
+                    "message": "Block lifted"
+                })),
+            ))
+        }
+        Ok(false) => Err(not_found()),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ))
+        }
+    }
+}
+
+/// Overview for an admin dashboard: user counts, active sessions, and today's logins
+#[get("/stats")]
+pub async fn get_stats(
+    _admin: AdminUser,
+    mut db: Connection<Postgres>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let users = stats::user_totals(&mut db).await.map_err(stats_error)?;
+    let sessions = stats::session_stats(&mut db).await.map_err(stats_error)?;
+    let logins = stats::logins_per_day(&mut db, 1).await.map_err(stats_error)?;
+    let signups = stats::signups_per_day(&mut db, 1).await.map_err(stats_error)?;
+
+    let (succeeded, failed) = logins
+        .first()
+        .map(|day| (day.succeeded, day.failed))
+        .unwrap_or((0, 0));
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "users": users,
+            "sessions": sessions,
+            "today": {
+                "signups": signups.first().map(|day| day.count).unwrap_or(0),
+                "logins_succeeded": succeeded,
+                "logins_failed": failed,
+                "login_success_ratio": success_ratio(succeeded, failed)
+            }
+        })),
+    ))
+}
+
+/// New accounts per day (UTC) over the last `days` days (default 30, max 365)
+#[get("/stats/signups?<days>")]
+pub async fn get_signup_stats(
+    _admin: AdminUser,
+    mut db: Connection<Postgres>,
+    days: Option<i32>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let days = stats_days(days)?;
+    let signups = stats::signups_per_day(&mut db, days).await.map_err(stats_error)?;
+    let total: i64 = signups.iter().map(|day| day.count).sum();
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "days": days,
+            "total": total,
+            "signups": signups
+        })),
+    ))
+}
+
+/// Password login outcomes per day (UTC) over the last `days` days (default 30, max 365)
+#[get("/stats/logins?<days>")]
+pub async fn get_login_stats(
+    _admin: AdminUser,
+    mut db: Connection<Postgres>,
+    days: Option<i32>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let days = stats_days(days)?;
+    let logins = stats::logins_per_day(&mut db, days).await.map_err(stats_error)?;
+    let succeeded: i64 = logins.iter().map(|day| day.succeeded).sum();
+    let failed: i64 = logins.iter().map(|day| day.failed).sum();
+
+    let daily: Vec<Value> = logins
+        .iter()
+        .map(|day| {
+            json!({
+                "day": day.day,
+                "succeeded": day.succeeded,
+                "failed": day.failed,
+                "success_ratio": success_ratio(day.succeeded, day.failed)
+            })
+        })
+        .collect();
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "days": days,
+            "succeeded": succeeded,
+            "failed": failed,
+            "success_ratio": success_ratio(succeeded, failed),
+            "logins": daily
+        })),
+    ))
+}
+
+/// Active (unrevoked, unexpired) sessions
+#[get("/stats/sessions")]
+pub async fn get_session_stats(
+    _admin: AdminUser,
+    mut db: Connection<Postgres>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let sessions = stats::session_stats(&mut db).await.map_err(stats_error)?;
+    Ok(status::Custom(Status::Ok, Json(json!(sessions))))
+}
+
+/// Share of successful logins, or null when there were none
+fn success_ratio(succeeded: i64, failed: i64) -> Option<f64> {
+    let attempts = succeeded + failed;
+    (attempts > 0).then(|| succeeded as f64 / attempts as f64)
+}
+
+fn stats_days(days: Option<i32>) -> Result<i32, status::Custom<Json<Value>>> {
+    match days.unwrap_or(DEFAULT_STATS_DAYS) {
+        days @ 1..=MAX_STATS_DAYS => Ok(days),
+        _ => Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": format!("days must be between 1 and {}", MAX_STATS_DAYS)
+            })),
+        )),
+    }
+}
+
+fn stats_error(e: sqlx::Error) -> status::Custom<Json<Value>> {
+    eprintln!("Database error: {}", e);
+    status::Custom(
+        Status::InternalServerError,
+        Json(json!({
+            "error": "Database error occurred"
+        })),
+    )
+}
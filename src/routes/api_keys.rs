@@ -0,0 +1,306 @@
+use chrono::{Duration, Utc};
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::State;
+use serde::Serialize;
+use rocket::serde::json::{Json, Value, json};
+use rocket_db_pools::Connection;
+use uuid::Uuid;
+
+use crate::auth::api_key;
+use crate::auth::ip_range::IpRange;
+use crate::auth::guard::RegisteredUser;
+use crate::auth::scopes;
+use crate::authz::Owns;
+use crate::body_limits::JsonBody;
+use crate::config::AppConfig;
+use crate::maintenance::WriteAccess;
+use crate::models::api_key::{ApiKey, NewApiKey};
+use crate::rate_limit::{ApiKeyQuota, QuotaUsage};
+use crate::repositories::api_keys;
+use crate::Postgres;
+
+/// Keys a user may have at once
+const MAX_API_KEYS: usize = 20;
+
+/// Days of per-day usage an API key's usage report covers
+const USAGE_HISTORY_DAYS: i64 = 31;
+
+/// Create an API key limited to the given scopes
+///
+/// The key is only returned in this response; it is stored as a hash. API
+/// keys can't manage API keys, so a leaked key can't mint more. Keys can be
+/// bound to client address ranges and to the audience of one deployment.
+#[post("/api-keys", data = "<new_key>")]
+pub async fn create_api_key(
+    _write: WriteAccess,
+    user: RegisteredUser,
+    mut db: Connection<Postgres>,
+    new_key: JsonBody<NewApiKey>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let user_id = parse_user_id(&user)?;
+    let name = new_key.name.trim();
+
+    if name.is_empty() || name.len() > 100 {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "Name must be between 1 and 100 characters"
+            })),
+        ));
+    }
+
+    if new_key.scopes.is_empty() {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "At least one scope is required",
+                "details": format!("Available scopes: {}", scopes::ALL.join(", "))
+            })),
+        ));
+    }
+
+    if let Some(unknown) = new_key.scopes.iter().find(|scope| !scopes::is_known(scope)) {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": format!("Unknown scope '{}'", unknown),
+                "details": format!("Available scopes: {}", scopes::ALL.join(", "))
+            })),
+        ));
+    }
+
+    let expires_at = match new_key.expires_in_days {
+        Some(days) if !(1..=365).contains(&days) => {
+            return Err(status::Custom(
+                Status::BadRequest,
+                Json(json!({
+                    "error": "expires_in_days must be between 1 and 365"
+                })),
+            ));
+        }
+        Some(days) => Some(Utc::now() + Duration::days(days)),
+        None => None,
+    };
+
+    let mut allowed_ips = Vec::with_capacity(new_key.allowed_ips.len());
+    for range in &new_key.allowed_ips {
+        let range: IpRange = range.parse().map_err(|message: String| {
+            status::Custom(
+                Status::BadRequest,
+                Json(json!({
+                    "error": message
+                })),
+            )
+        })?;
+        allowed_ips.push(range.to_string());
+    }
+
+    let audience = new_key.audience.as_deref().map(str::trim);
+    if audience.is_some_and(|audience| audience.is_empty() || audience.len() > 100) {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "audience must be between 1 and 100 characters"
+            })),
+        ));
+    }
+
+    let existing = api_keys::list_for_user(&mut db, user_id).await.map_err(database_error)?;
+    if existing.len() >= MAX_API_KEYS {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": format!("You can have at most {} API keys", MAX_API_KEYS)
+            })),
+        ));
+    }
+
+    let mut granted = new_key.scopes.clone();
+    granted.sort();
+    granted.dedup();
+
+    let generated = api_key::generate();
+    let key = api_keys::create(
+        &mut db,
+        user_id,
+        name,
+        &generated.prefix,
+        &generated.hash,
+        &granted,
+        &allowed_ips,
+        audience,
+        expires_at,
+    )
+    .await
+    .map_err(database_error)?;
+
+    Ok(status::Custom(
+        Status::Created,
+        Json(json!({
+            "message": "API key created. Store it now; it won't be shown again.",
+            "key": generated.key,
+            "api_key": key
+        })),
+    ))
+}
+
+/// A key in a listing, flagged when it expires within `ROCKET_API_KEY_EXPIRY_WARNING_DAYS`
+#[derive(Serialize)]
+struct ListedKey {
+    #[serde(flatten)]
+    key: ApiKey,
+    expires_soon: bool,
+}
+
+/// List the current user's active API keys (without the keys themselves)
+#[get("/api-keys")]
+pub async fn list_api_keys(
+    user: RegisteredUser,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let user_id = parse_user_id(&user)?;
+    let keys = api_keys::list_for_user(&mut db, user_id).await.map_err(database_error)?;
+
+    let now = Utc::now();
+    let warn_from = now + Duration::days(config.api_key_expiry_warning_days as i64);
+    let keys: Vec<ListedKey> = keys
+        .into_iter()
+        .map(|key| ListedKey {
+            expires_soon: key.expires_at.is_some_and(|expires_at| expires_at > now && expires_at <= warn_from),
+            key,
+        })
+        .collect();
+
+    Ok(status::Custom(Status::Ok, Json(json!({ "api_keys": keys }))))
+}
+
+/// Replace an API key with a new one of the same name, scopes and bindings
+///
+/// The new key is only returned in this response. The old one keeps working
+/// for `ROCKET_API_KEY_ROTATION_OVERLAP_MINUTES`, so it can be swapped out
+/// without downtime. A key that expires lasts as long as the old one did.
+#[post("/api-keys/<_id>/rotate")]
+pub async fn rotate_api_key(
+    _write: WriteAccess,
+    key: Owns<ApiKey>,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    _id: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let now = Utc::now();
+    if key.is_expired(now) {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "This API key has expired; create a new one instead"
+            })),
+        ));
+    }
+
+    let generated = api_key::generate();
+    let expires_at = key.expires_at.map(|expires_at| now + (expires_at - key.created_at));
+    let old_expires_at = now + Duration::minutes(config.api_key_rotation_overlap_minutes as i64);
+    let rotated = api_keys::rotate(&mut db, key.id, &generated.prefix, &generated.hash, expires_at, old_expires_at)
+        .await
+        .map_err(database_error)?;
+    let Some(replacement) = rotated else {
+        return Err(status::Custom(
+            Status::Conflict,
+            Json(json!({
+                "error": "This API key was already rotated"
+            })),
+        ));
+    };
+
+    Ok(status::Custom(
+        Status::Created,
+        Json(json!({
+            "message": "API key rotated. Store the new key now; it won't be shown again.",
+            "key": generated.key,
+            "api_key": replacement,
+            "old_key_expires_at": old_expires_at.min(key.expires_at.unwrap_or(old_expires_at)).to_rfc3339()
+        })),
+    ))
+}
+
+/// Revoke an API key
+#[delete("/api-keys/<_id>")]
+pub async fn revoke_api_key(
+    _write: WriteAccess,
+    key: Owns<ApiKey>,
+    mut db: Connection<Postgres>,
+    _id: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    api_keys::revoke(&mut db, key.user_id, key.id).await.map_err(database_error)?;
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "API key revoked"
+        })),
+    ))
+}
+
+/// Requests made with one of the current user's API keys, against its quotas
+///
+/// Covers today and this calendar month (UTC), with the number of requests
+/// for each of the last 31 days that had any.
+#[get("/api-keys/<_id>/usage")]
+pub async fn api_key_usage(
+    key: Owns<ApiKey>,
+    mut db: Connection<Postgres>,
+    quota: &State<ApiKeyQuota>,
+    _id: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let [today, month] = quota.usage(&mut db, key.id).await.map_err(database_error)?;
+    let since = Utc::now().date_naive() - Duration::days(USAGE_HISTORY_DAYS - 1);
+    let days = api_keys::list_usage(&mut db, key.id, since).await.map_err(database_error)?;
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "api_key_id": key.id,
+            "today": period_json(today),
+            "this_month": period_json(month),
+            "days": days
+        })),
+    ))
+}
+
+fn period_json(usage: QuotaUsage) -> Value {
+    match usage.limit {
+        Some(limit) => json!({
+            "requests": usage.requests,
+            "quota": limit.limit,
+            "remaining": limit.remaining,
+            "resets_at": limit.reset_at.to_rfc3339()
+        }),
+        None => json!({
+            "requests": usage.requests,
+            "quota": null
+        }),
+    }
+}
+
+fn parse_user_id(user: &RegisteredUser) -> Result<Uuid, status::Custom<Json<Value>>> {
+    Uuid::parse_str(&user.user_id).map_err(|_| {
+        status::Custom(
+            Status::Unauthorized,
+            Json(json!({
+                "error": "Invalid token subject"
+            })),
+        )
+    })
+}
+
+fn database_error(e: sqlx::Error) -> status::Custom<Json<Value>> {
+    eprintln!("Database error: {}", e);
+    status::Custom(
+        Status::InternalServerError,
+        Json(json!({
+            "error": "Database error occurred"
+        })),
+    )
+}
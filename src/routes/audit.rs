@@ -0,0 +1,238 @@
+use rocket::Either;
+use rocket::futures::StreamExt;
+use rocket::http::{ContentType, Status};
+use rocket::response::status;
+use rocket::response::stream::TextStream;
+use rocket::State;
+use rocket::serde::json::{Json, Value, json};
+use rocket_db_pools::Connection;
+use uuid::Uuid;
+
+use crate::auth::guard::HasPermission;
+use crate::body_limits::JsonBody;
+use crate::audit::AuditSinks;
+use crate::auth::permissions::{AuditRead, AuditReplay};
+use crate::config::parse_date;
+use crate::events::SecurityEvent;
+use crate::models::audit::{AuditFilter, AuditQuery, AuditReplayRequest};
+use crate::repositories::audit;
+use crate::Postgres;
+
+/// Query the audit log, newest first
+///
+/// Filters: `actor` and `user` (user ids), `org` (organization id), `type`
+/// (event type, e.g. `session_created`), `ip`, and `from` (inclusive) / `to`
+/// (exclusive) as dates or RFC 3339 timestamps. Returns a page of `limit`
+/// (default 50, at most 200) events after `offset`; with `format=ndjson`,
+/// every matching event is streamed instead, one JSON object per line.
+#[get("/audit?<query..>")]
+pub async fn query_audit_log(
+    _user: HasPermission<AuditRead>,
+    db: Connection<Postgres>,
+    query: AuditQuery,
+) -> Result<Either<status::Custom<Json<Value>>, (ContentType, TextStream![String])>, status::Custom<Json<Value>>> {
+    let organization_id = query
+        .org
+        .as_deref()
+        .map(|org| Uuid::parse_str(org).map_err(|_| bad_request("org must be an organization id")))
+        .transpose()?;
+    let filter = audit_filter(&query, organization_id)?;
+    audit_events(db, filter, &query).await
+}
+
+/// The audit log filters in `query`, for events tagged with `organization_id` if set
+pub(crate) fn audit_filter(query: &AuditQuery, organization_id: Option<Uuid>) -> Result<AuditFilter, status::Custom<Json<Value>>> {
+    Ok(AuditFilter {
+        actor_id: parse_id("actor", query.actor.as_deref())?,
+        user_id: parse_id("user", query.user.as_deref())?,
+        event_type: query.event_type.clone(),
+        ip: query.ip.clone(),
+        organization_id,
+        from: parse_time("from", query.from.as_deref())?,
+        to: parse_time("to", query.to.as_deref())?,
+    })
+}
+
+/// A page of events matching `filter`, or all of them as NDJSON, as `query` asks
+pub(crate) async fn audit_events(
+    mut db: Connection<Postgres>,
+    filter: AuditFilter,
+    query: &AuditQuery,
+) -> Result<Either<status::Custom<Json<Value>>, (ContentType, TextStream![String])>, status::Custom<Json<Value>>> {
+    match query.format.as_deref() {
+        None | Some("json") => {}
+        Some("ndjson") => {
+            let export = TextStream! {
+                let mut events = audit::export(&mut db, &filter);
+                while let Some(event) = events.next().await {
+                    match event {
+                        Ok(event) => yield format!("{}\n", json!(event)),
+                        Err(e) => {
+                            // Too late for an error status; the export just ends early
+                            eprintln!("Database error: {}", e);
+                            break;
+                        }
+                    }
+                }
+            };
+            return Ok(Either::Right((ContentType::new("application", "x-ndjson"), export)));
+        }
+        Some(_) => return Err(bad_request("format must be json or ndjson")),
+    }
+
+    let limit = query.limit.unwrap_or(50).clamp(1, 200);
+    let offset = query.offset.unwrap_or(0).max(0);
+    match audit::list(&mut db, &filter, limit, offset).await {
+        Ok(events) => Ok(Either::Left(status::Custom(
+            Status::Ok,
+            Json(json!({
+                "events": events
+            })),
+        ))),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ))
+        }
+    }
+}
+
+/// Deliveries the audit sinks gave up on, most recent first, with a count per sink
+///
+/// Replaying an event to its sink successfully clears its dead letters.
+#[get("/audit/dead-letters?<limit>&<offset>")]
+pub async fn list_dead_letters(
+    _user: HasPermission<AuditRead>,
+    mut db: Connection<Postgres>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let limit = limit.unwrap_or(50).clamp(1, 200);
+    let offset = offset.unwrap_or(0).max(0);
+    let result = match audit::list_dead_letters(&mut db, limit, offset).await {
+        Ok(dead_letters) => audit::count_dead_letters(&mut db).await.map(|counts| (dead_letters, counts)),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok((dead_letters, counts)) => Ok(status::Custom(
+            Status::Ok,
+            Json(json!({
+                "dead_letters": dead_letters,
+                "counts": counts
+            })),
+        )),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ))
+        }
+    }
+}
+
+/// Most events one replay request re-delivers
+const MAX_REPLAY_EVENTS: i64 = 10_000;
+
+/// Re-deliver stored events to a configured audit sink, oldest first
+///
+/// For consumers that missed events while they were down. Select events by
+/// `ids`, or by a time range starting at `from`; at most 10,000 per request.
+/// Events keep their ids, so consumers can drop ones they already have.
+/// Delivery stops at the first failure, reporting how many events got through
+/// and the first one that didn't, so the replay can be resumed from there.
+#[post("/audit/replay", data = "<request>")]
+pub async fn replay_audit_events(
+    admin: HasPermission<AuditReplay>,
+    mut db: Connection<Postgres>,
+    sinks: &State<AuditSinks>,
+    request: JsonBody<AuditReplayRequest>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let Some(sink) = sinks.get(&request.sink) else {
+        return Err(bad_request(&format!("No audit sink named '{}' is configured", request.sink)));
+    };
+    let from = parse_time("from", request.from.as_deref())?;
+    let to = parse_time("to", request.to.as_deref())?;
+    if request.ids.is_empty() && from.is_none() {
+        return Err(bad_request("Select events with ids or a from time"));
+    }
+
+    let events = match audit::list_for_replay(&mut db, &request.ids, from, to, MAX_REPLAY_EVENTS + 1).await {
+        Ok(events) => events,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ));
+        }
+    };
+    if events.len() as i64 > MAX_REPLAY_EVENTS {
+        return Err(bad_request(&format!(
+            "More than {} events match; narrow the time range",
+            MAX_REPLAY_EVENTS
+        )));
+    }
+
+    let mut replayed = 0;
+    for event in events {
+        let id = event.id;
+        let result = match SecurityEvent::from_audit(event) {
+            Ok(event) => sink.send(&event).await.map_err(|e| e.to_string()),
+            Err(e) => Err(format!("Stored event can't be read: {}", e)),
+        };
+        if let Err(error) = result {
+            return Err(status::Custom(
+                Status::BadGateway,
+                Json(json!({
+                    "error": error,
+                    "replayed": replayed,
+                    "failed_id": id
+                })),
+            ));
+        }
+        if let Err(e) = audit::clear_dead_letters(&mut db, id, &request.sink).await {
+            eprintln!("Database error: {}", e);
+        }
+        replayed += 1;
+    }
+
+    println!("✓ Replayed {} audit event(s) to {} by admin {}", replayed, request.sink, admin.user_id);
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "replayed": replayed
+        })),
+    ))
+}
+
+fn parse_id(name: &str, value: Option<&str>) -> Result<Option<Uuid>, status::Custom<Json<Value>>> {
+    value
+        .map(|value| Uuid::parse_str(value).map_err(|_| bad_request(&format!("{} must be a user id", name))))
+        .transpose()
+}
+
+fn parse_time(name: &str, value: Option<&str>) -> Result<Option<chrono::DateTime<chrono::Utc>>, status::Custom<Json<Value>>> {
+    value
+        .map(|value| {
+            parse_date(value).ok_or_else(|| bad_request(&format!("{} must be a date like 2024-01-31 or an RFC 3339 timestamp", name)))
+        })
+        .transpose()
+}
+
+fn bad_request(message: &str) -> status::Custom<Json<Value>> {
+    status::Custom(
+        Status::BadRequest,
+        Json(json!({
+            "error": message
+        })),
+    )
+}
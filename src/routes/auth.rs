@@ -1,49 +1,88 @@
 use rocket::serde::json::{Json, Value, json};
 use rocket::http::Status;
-use rocket::response::status;
+use rocket::response::{status, Redirect};
+use rocket::response::stream::{Event, EventStream};
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::error::RecvError;
+use rocket::{Either, Shutdown, State};
 use rocket_db_pools::Connection;
+use sqlx::PgConnection;
 
-use crate::models::user::{User, NewUser, LoginUser};
-use crate::models::password_reset::{RequestPasswordReset, ResetPassword, PasswordResetToken};
+use crate::models::session::SessionDevice;
+use crate::models::user::{User, NewUser, LoginUser, ChangePassword, UserMetadataPatch};
+use crate::models::password_reset::{RequestPasswordReset, ResetPassword};
+use crate::repositories::{invitations, login_attempts, organizations, plans, sessions, user_emails, users};
+use crate::settings::Settings;
 use crate::Postgres;
+use crate::alerts;
+use crate::auth::access_tokens::{AccessTokenError, AccessTokens};
+use crate::auth::token_versions::TokenVersions;
+use crate::auth::email_tokens::{self, EmailTokenPurpose};
 use crate::auth::jwt::JwtService;
-use crate::auth::guard::AuthenticatedUser;
+use crate::auth::oauth_clients;
+use crate::auth::password::PasswordHasher;
+use crate::auth::sso;
+use crate::auth::guard::{AuthenticatedUser, PasswordChangeUser, RegisteredUser, Scoped, VerifiedSession};
+use crate::auth::scopes::{UsersRead, UsersWrite};
+use crate::body_limits::JsonBody;
+use crate::conditional::{weak_etag, Preconditions, Tagged};
+use crate::devices;
+use crate::domains;
+use crate::config::{AppConfig, RegistrationMode, WelcomeEmail};
+use crate::email::sender::Mailer;
+use crate::email::templates;
+use crate::events::{self, EventBus, SecurityEvent, SecurityEventKind};
+use crate::links::{self, LinkAction};
+use crate::maintenance::WriteAccess;
+use crate::pages::{Answer, Format, Pages};
+use crate::rate_limit::{self, LoginDelay, LoginLockout, RateLimited, StuffingDetector};
+use crate::redirects;
+use crate::risk::{RiskCheck, RiskVerdict};
+use crate::telemetry::TelemetryWriter;
+use crate::routes::account as account_routes;
+use crate::routes::mfa as mfa_routes;
 use chrono::{Duration, Utc};
+use std::net::IpAddr;
+
+/// Largest accepted metadata patch, serialized
+const MAX_METADATA_BYTES: usize = 16 * 1024;
 
 /// Register a new user
+///
+/// With `ROCKET_REGISTRATION_UNIFORM_RESPONSE`, taken and new emails get the
+/// same `202` answer; the owner of a taken email is told by email instead.
 #[post("/register", data = "<new_user>")]
 pub async fn register(
+    _write: WriteAccess,
     mut db: Connection<Postgres>,
-    new_user: Json<NewUser>,
+    settings: &State<Settings>,
+    mailer: &State<Mailer>,
+    passwords: &State<PasswordHasher>,
+    new_user: JsonBody<NewUser>,
 ) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
-    // Validate email format (basic validation)
-    if !new_user.email.contains('@') {
+    let config: &AppConfig = &settings.current();
+    if config.registration_mode == RegistrationMode::Closed {
         return Err(status::Custom(
-            Status::BadRequest,
+            Status::Forbidden,
             Json(json!({
-                "error": "Invalid email format"
+                "error": "Registration is closed",
+                "code": "registration_closed"
             })),
         ));
     }
 
-    // Validate password length
-    if new_user.password.len() < 6 {
-        return Err(status::Custom(
-            Status::BadRequest,
-            Json(json!({
-                "error": "Password must be at least 6 characters long"
-            })),
-        ));
-    }
+    validate_credentials(&new_user)?;
+    validate_preferences(new_user.locale.as_deref(), new_user.timezone.as_deref())?;
+    let redirect_to = redirect_target(config, new_user.redirect_to.as_deref())?;
 
     // Check if user already exists
-    let existing_user = sqlx::query_scalar::<_, Option<uuid::Uuid>>("SELECT id FROM users WHERE email = $1")
-        .bind(&new_user.email)
-        .fetch_optional(&mut **db)
-        .await;
+    let existing_user = users::email_exists(&mut db, &new_user.email).await;
 
     match existing_user {
-        Ok(Some(_)) => {
+        Ok(true) if config.registration_uniform_response => {
+            return conceal_existing_account(&mut db, config, mailer, passwords, &new_user).await;
+        }
+        Ok(true) => {
             return Err(status::Custom(
                 Status::Conflict,
                 Json(json!({
@@ -51,7 +90,7 @@ pub async fn register(
                 })),
             ));
         }
-        Ok(None) => {}
+        Ok(false) => {}
         Err(e) => {
             eprintln!("Database error: {}", e);
             return Err(status::Custom(
@@ -63,12 +102,17 @@ pub async fn register(
         }
     }
 
+    // Invite-only: reserve the invitation before creating the account
+    let invitation_id = claim_invitation(&mut db, config, &new_user).await?;
+
     // Hash the password
-    let password_hash = match User::hash_password(&new_user.password) {
+    let password_hash = match passwords.hash(&new_user.password).await {
         Ok(hash) => hash,
-        Err(_) => {
+        Err(e) => {
+            eprintln!("{}", e);
+            release_invitation(&mut db, invitation_id).await;
             return Err(status::Custom(
-                Status::InternalServerError,
+                e.status(),
                 Json(json!({
                     "error": "Failed to hash password"
                 })),
@@ -76,25 +120,59 @@ pub async fn register(
         }
     };
 
-    // Insert new user into database
-    let result = sqlx::query_as::<_, User>(
-        "INSERT INTO users (email, password_hash) VALUES ($1, $2) RETURNING id, email, password_hash, created_at, updated_at"
-    )
-    .bind(&new_user.email)
-    .bind(&password_hash)
-    .fetch_one(&mut **db)
-    .await;
+    // Insert new user into database; with signup approval it waits for an admin
+    let result = if config.signup_approval {
+        users::create_pending_approval(&mut db, &new_user.email, &password_hash).await
+    } else {
+        users::create(&mut db, &new_user.email, &password_hash, "user").await
+    };
 
     match result {
-        Ok(user) => {
+        Ok(mut user) => {
+            if let Some(id) = invitation_id {
+                if let Err(e) = invitations::set_used_by(&mut db, id, user.id).await {
+                    eprintln!("Database error: {}", e);
+                }
+                if let Err(e) = invitations::join_organization(&mut db, id, user.id).await {
+                    eprintln!("Database error: {}", e);
+                }
+            }
+
+            if new_user.locale.is_some() || new_user.timezone.is_some() {
+                match users::set_preferences(&mut db, user.id, new_user.locale.as_deref(), new_user.timezone.as_deref()).await {
+                    Ok(updated) => user = updated,
+                    Err(e) => eprintln!("Database error: {}", e),
+                }
+            }
+
+            // Ask the user to confirm their address; registration succeeds either way.
+            // A welcome email with the verification link replaces this one.
+            let welcome_verifies = config.welcome_email == WelcomeEmail::WithVerification && !user.is_pending_approval();
+            if !welcome_verifies {
+                send_verification_email(&mut db, config, mailer, &user, redirect_to.as_deref()).await;
+            }
+            events::emit(&mut db, user.id, SecurityEventKind::UserRegistered).await;
+            if config.registration_uniform_response {
+                return Ok(registration_accepted());
+            }
+
+            let message = if user.is_pending_approval() {
+                "Registration received; an administrator will review your account"
+            } else {
+                "User registered successfully"
+            };
 
             Ok(status::Custom(
                 Status::Created,
                 Json(json!({
-                    "message": "User registered successfully",
+                    "message": message,
                     "user": {
                         "id": user.id.to_string(),
                         "email": user.email,
+                        "email_verified": false,
+                        "approval_status": user.approval_status,
+                        "locale": user.locale,
+                        "timezone": user.timezone,
                         "created_at": user.created_at.to_rfc3339()
                     }
                 })),
@@ -102,6 +180,7 @@ pub async fn register(
         }
         Err(e) => {
             eprintln!("Database error: {}", e);
+            release_invitation(&mut db, invitation_id).await;
             Err(status::Custom(
                 Status::InternalServerError,
                 Json(json!({
@@ -113,28 +192,51 @@ pub async fn register(
 }
 
 /// Login endpoint
+///
+/// After `ROCKET_LOGIN_LOCKOUT_ATTEMPTS` failures for an address, further
+/// attempts get `429` until the lockout window has passed. Before that, each
+/// recent failure from the same client delays the response a little more.
+/// Clients failing logins for many accounts are blocked for a while. With a
+/// risk engine, unusual logins need a second factor or are refused.
 #[post("/login", data = "<login_user>")]
+#[allow(clippy::too_many_arguments)]
 pub async fn login(
     mut db: Connection<Postgres>,
-    login_user: Json<LoginUser>,
-) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    jwt: &State<JwtService>,
+    access_tokens: &State<AccessTokens>,
+    passwords: &State<PasswordHasher>,
+    settings: &State<Settings>,
+    mailer: &State<Mailer>,
+    lockout: &State<LoginLockout>,
+    delay: &State<LoginDelay>,
+    stuffing: &State<StuffingDetector>,
+    risk: RiskCheck<'_>,
+    ip: Option<IpAddr>,
+    device: SessionDevice,
+    login_user: JsonBody<LoginUser>,
+) -> Result<status::Custom<Json<Value>>, RateLimited<status::Custom<Json<Value>>>> {
+    let config: &AppConfig = &settings.current();
+    check_ip_block(&mut db, stuffing, ip).await?;
+    check_lockout(&mut db, lockout, &login_user.email).await?;
+    wait_for_login_delay(&mut db, delay, &login_user.email, ip).await?;
+    // Addresses at a domain whose organization requires SSO don't use passwords, whoever they are
+    check_sso(&mut db, config, None, &login_user.email, None).await?;
+
     // Find user by email
-    let result = sqlx::query_as::<_, User>(
-        "SELECT id, email, password_hash, created_at, updated_at FROM users WHERE email = $1"
-    )
-    .bind(&login_user.email)
-    .fetch_optional(&mut **db)
-    .await;
+    let result = users::find_by_email(&mut db, &login_user.email).await;
 
-    let user = match result {
+    let mut user = match result {
         Ok(Some(user)) => user,
         Ok(None) => {
+            passwords.verify_dummy(&login_user.password).await;
+            record_login_attempt(&mut db, None, &login_user.email, ip, false).await;
             return Err(status::Custom(
                 Status::Unauthorized,
                 Json(json!({
                     "error": "Invalid email or password"
                 })),
-            ));
+            )
+            .into());
         }
         Err(e) => {
             eprintln!("Database error: {}", e);
@@ -143,54 +245,84 @@ pub async fn login(
                 Json(json!({
                     "error": "Database error occurred"
                 })),
-            ));
+            )
+            .into());
         }
     };
 
     // Verify password
-    match User::verify_password(&login_user.password, &user.password_hash) {
+    match passwords.verify(&login_user.password, &user.password_hash).await {
         Ok(true) => {
-            // Generate JWT token
-            let token = match JwtService::generate_token(user.id.to_string()) {
-                Ok(t) => t,
-                Err(_) => {
-                    return Err(status::Custom(
-                        Status::InternalServerError,
-                        Json(json!({
-                            "error": "Failed to generate token"
-                        })),
-                    ));
-                }
-            };
+            // Move the hash to the current pepper while the password is at hand
+            if passwords.needs_rehash(&user.password_hash) {
+                rehash_password(&mut db, passwords, user.id, &login_user.password).await;
+            }
+            check_sso(&mut db, config, user.organization_id, &user.email, None).await?;
+
+            if user.disabled_at.is_some() {
+                record_login_attempt(&mut db, Some(user.id), &login_user.email, ip, false).await;
+                return Err(disabled_refusal().into());
+            }
+
+            // Signups awaiting approval (or rejected) can't log in yet
+            if user.approval_status != "approved" {
+                record_login_attempt(&mut db, Some(user.id), &login_user.email, ip, false).await;
+                return Err(approval_refusal(&user).into());
+            }
+
+            let step_up = check_login_risk(&mut db, &risk, &user, ip).await?;
+
+            // Users with MFA get a session only after the second step at /login/mfa,
+            // unless a confirmed recovery has just removed it
+            if user.totp_enabled_at.is_some() && !mfa_routes::complete_due_recovery(&mut db, mailer, &mut user).await? {
+                return Ok(mfa_routes::challenge(&mut db, &user).await?);
+            }
+            // Risky logins by users without MFA confirm an emailed code at the same step
+            if step_up {
+                return Ok(mfa_routes::email_challenge(&mut db, mailer, &user, ip).await?);
+            }
+
+            record_login(&mut db, access_tokens.telemetry(), user.id, &login_user.email, ip).await;
+            alerts::admin_login(&mut db, config, mailer, &user, risk.country(), ip).await;
+            mfa_routes::start_grace_period(&mut db, config, &mut user).await;
+
+            // Start a session for this login
+            let token = start_session(&mut db, jwt, access_tokens, &user, ip, &device).await?;
 
             Ok(status::Custom(
                 Status::Ok,
                 Json(json!({
                     "message": "Login successful",
                     "token": token,
+                    "mfa_enrollment_deadline": mfa_routes::enrollment_deadline(config, &user).map(|deadline| deadline.to_rfc3339()),
                     "user": {
                         "id": user.id.to_string(),
                         "email": user.email,
+                        "must_change_password": user.must_change_password,
                         "created_at": user.created_at.to_rfc3339()
                     }
                 })),
             ))
         }
         Ok(false) => {
+            record_login_attempt(&mut db, Some(user.id), &login_user.email, ip, false).await;
             Err(status::Custom(
                 Status::Unauthorized,
                 Json(json!({
                     "error": "Invalid email or password"
                 })),
-            ))
+            )
+            .into())
         }
-        Err(_) => {
+        Err(e) => {
+            eprintln!("{}", e);
             Err(status::Custom(
-                Status::InternalServerError,
+                e.status(),
                 Json(json!({
                     "error": "Failed to verify password"
                 })),
-            ))
+            )
+            .into())
         }
     }
 }
@@ -198,60 +330,35 @@ pub async fn login(
 /// Request password reset - generates a reset token
 #[post("/forgot-password", data = "<request>")]
 pub async fn forgot_password(
+    _write: WriteAccess,
     mut db: Connection<Postgres>,
-    request: Json<RequestPasswordReset>,
+    config: &State<AppConfig>,
+    mailer: &State<Mailer>,
+    request: JsonBody<RequestPasswordReset>,
 ) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
-    // Find user by email
-    let result = sqlx::query_as::<_, User>(
-        "SELECT id, email, password_hash, created_at, updated_at FROM users WHERE email = $1"
-    )
-    .bind(&request.email)
-    .fetch_optional(&mut **db)
-    .await;
+    // Find user by email, falling back to verified secondary (recovery) addresses
+    let result = match users::find_by_email(&mut db, &request.email).await {
+        Ok(None) => user_emails::find_user_by_verified_email(&mut db, &request.email).await,
+        result => result,
+    };
 
     // Always return success to prevent email enumeration
-    // In production, you would send an email here
     match result {
-        Ok(Some(user)) => {
-            // Generate reset token
-            let reset_token = uuid::Uuid::new_v4().to_string();
-            let expires_at = Utc::now() + Duration::hours(1); // Token expires in 1 hour
-
-            // Store reset token in database
-            let insert_result = sqlx::query(
-                "INSERT INTO password_reset_tokens (user_id, token, expires_at) VALUES ($1, $2, $3)"
-            )
-            .bind(&user.id)
-            .bind(&reset_token)
-            .bind(&expires_at)
-            .execute(&mut **db)
-            .await;
-
-            match insert_result {
-                Ok(_) => {
-                    // In production, send email with reset link
-                    // For now, return token in response (remove this in production!)
-                    Ok(status::Custom(
-                        Status::Ok,
-                        Json(json!({
-                            "message": "Password reset token generated. Check your email.",
-                            "token": reset_token // Remove this in production!
-                        })),
-                    ))
-                }
-                Err(e) => {
-                    eprintln!("Database error: {}", e);
-                    Ok(status::Custom(
-                        Status::Ok,
-                        Json(json!({
-                            "message": "If the email exists, a password reset token has been sent."
-                        })),
-                    ))
-                }
+        Ok(Some(user)) if !user.is_guest() => {
+            // The link goes to the address that was entered
+            if let Err(e) = send_password_reset_email(&mut db, config, mailer, &user, &request.email).await {
+                eprintln!("Database error: {}", e);
             }
+
+            Ok(status::Custom(
+                Status::Ok,
+                Json(json!({
+                    "message": "If the email exists, a password reset token has been sent."
+                })),
+            ))
         }
-        Ok(None) | Err(_) => {
-            // Return success to prevent email enumeration
+        _ => {
+            // Unknown email, guest account or database error: return success to prevent email enumeration
             Ok(status::Custom(
                 Status::Ok,
                 Json(json!({
@@ -264,9 +371,17 @@ pub async fn forgot_password(
 
 /// Reset password using token
 #[post("/reset-password", data = "<reset>")]
+#[allow(clippy::too_many_arguments)]
 pub async fn reset_password(
+    _write: WriteAccess,
     mut db: Connection<Postgres>,
-    reset: Json<ResetPassword>,
+    config: &State<AppConfig>,
+    jwt: &State<JwtService>,
+    mailer: &State<Mailer>,
+    passwords: &State<PasswordHasher>,
+    token_versions: &State<TokenVersions>,
+    ip: Option<IpAddr>,
+    reset: JsonBody<ResetPassword>,
 ) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
     // Validate password length
     if reset.new_password.len() < 6 {
@@ -279,12 +394,7 @@ pub async fn reset_password(
     }
 
     // Find valid reset token
-    let token_result = sqlx::query_as::<_, PasswordResetToken>(
-        "SELECT id, user_id, token, expires_at, used, created_at FROM password_reset_tokens WHERE token = $1"
-    )
-    .bind(&reset.token)
-    .fetch_optional(&mut **db)
-    .await;
+    let token_result = email_tokens::find(&mut db, config, EmailTokenPurpose::ResetPassword, &reset.token).await;
 
     let reset_token = match token_result {
         Ok(Some(token)) => token,
@@ -328,11 +438,12 @@ pub async fn reset_password(
     }
 
     // Hash new password
-    let password_hash = match User::hash_password(&reset.new_password) {
+    let password_hash = match passwords.hash(&reset.new_password).await {
         Ok(hash) => hash,
-        Err(_) => {
+        Err(e) => {
+            eprintln!("{}", e);
             return Err(status::Custom(
-                Status::InternalServerError,
+                e.status(),
                 Json(json!({
                     "error": "Failed to hash password"
                 })),
@@ -340,24 +451,43 @@ pub async fn reset_password(
         }
     };
 
+    // Use the token up first, so a concurrent request with it can't reset the password too
+    match email_tokens::mark_used(&mut db, &reset_token).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return Err(status::Custom(
+                Status::BadRequest,
+                Json(json!({
+                    "error": "Reset token has already been used"
+                })),
+            ));
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ));
+        }
+    }
+
     // Update user password
-    let update_result = sqlx::query(
-        "UPDATE users SET password_hash = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2"
-    )
-    .bind(&password_hash)
-    .bind(&reset_token.user_id)
-    .execute(&mut **db)
-    .await;
+    let update_result = users::update_password(&mut db, reset_token.user_id, &password_hash).await;
 
     match update_result {
         Ok(_) => {
-            // Mark token as used
-            let _ = sqlx::query(
-                "UPDATE password_reset_tokens SET used = TRUE WHERE token = $1"
-            )
-            .bind(&reset.token)
-            .execute(&mut **db)
-            .await;
+            // Tokens issued with the old password stop working
+            if let Err(e) = token_versions.bump(&mut db, reset_token.user_id).await {
+                eprintln!("Database error: {}", e);
+            }
+            events::emit(&mut db, reset_token.user_id, SecurityEventKind::PasswordChanged).await;
+            match users::find_by_id(&mut db, reset_token.user_id).await {
+                Ok(Some(user)) => account_routes::notify_password_changed(config, jwt, mailer, &user, "", ip).await,
+                Ok(None) => {}
+                Err(e) => eprintln!("Database error: {}", e),
+            }
 
             Ok(status::Custom(
                 Status::Ok,
@@ -378,39 +508,118 @@ pub async fn reset_password(
     }
 }
 
-/// Protected route example - requires authentication
-#[get("/me")]
-pub async fn get_current_user(
-    user: AuthenticatedUser,
+/// Change password while signed in; the only route open to users who must change their password
+#[post("/change-password", data = "<change>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn change_password(
+    _write: WriteAccess,
+    user: PasswordChangeUser,
     mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    mailer: &State<Mailer>,
+    passwords: &State<PasswordHasher>,
+    jwt: &State<JwtService>,
+    token_versions: &State<TokenVersions>,
+    ip: Option<IpAddr>,
+    change: JsonBody<ChangePassword>,
 ) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
-    // Find user by ID from token
-    let result = sqlx::query_as::<_, User>(
-        "SELECT id, email, password_hash, created_at, updated_at FROM users WHERE id = $1"
-    )
-    .bind(&user.user_id)
-    .fetch_optional(&mut **db)
-    .await;
+    // Validate password length
+    if change.new_password.len() < 6 {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "Password must be at least 6 characters long"
+            })),
+        ));
+    }
 
-    match result {
-        Ok(Some(user_data)) => {
-            Ok(status::Custom(
-                Status::Ok,
+    let user_id = match uuid::Uuid::parse_str(&user.user_id) {
+        Ok(id) => id,
+        Err(_) => {
+            return Err(status::Custom(
+                Status::Unauthorized,
                 Json(json!({
-                    "user": {
-                        "id": user_data.id.to_string(),
-                        "email": user_data.email,
-                        "created_at": user_data.created_at.to_rfc3339()
-                    }
+                    "error": "Invalid token subject"
                 })),
-            ))
+            ));
         }
+    };
+
+    let mut user_data = match users::find_by_id(&mut db, user_id).await {
+        Ok(Some(user_data)) => user_data,
         Ok(None) => {
-            Err(status::Custom(
+            return Err(status::Custom(
                 Status::NotFound,
                 Json(json!({
                     "error": "User not found"
                 })),
+            ));
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ));
+        }
+    };
+
+    match passwords.verify(&change.current_password, &user_data.password_hash).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return Err(status::Custom(
+                Status::BadRequest,
+                Json(json!({
+                    "error": "Current password is incorrect"
+                })),
+            ));
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            return Err(status::Custom(
+                e.status(),
+                Json(json!({
+                    "error": "Failed to verify password"
+                })),
+            ));
+        }
+    }
+
+    // Hash new password
+    let password_hash = match passwords.hash(&change.new_password).await {
+        Ok(hash) => hash,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Err(status::Custom(
+                e.status(),
+                Json(json!({
+                    "error": "Failed to hash password"
+                })),
+            ));
+        }
+    };
+
+    // Also clears must_change_password
+    match users::update_password(&mut db, user_id, &password_hash).await {
+        Ok(_) => {
+            // Every JWT issued so far stops working; this session gets a fresh one
+            match token_versions.bump(&mut db, user_id).await {
+                Ok(Some(version)) => user_data.token_version = version,
+                Ok(None) => {}
+                Err(e) => eprintln!("Database error: {}", e),
+            }
+            events::emit(&mut db, user_id, SecurityEventKind::PasswordChanged).await;
+            account_routes::notify_password_changed(config, jwt, mailer, &user_data, &user.session_id, ip).await;
+            let token = reissue_token(&mut db, jwt, &user_data, &user.session_id).await;
+
+            Ok(status::Custom(
+                Status::Ok,
+                Json(json!({
+                    "message": "Password changed successfully",
+                    "token": token
+                })),
             ))
         }
         Err(e) => {
@@ -418,9 +627,1144 @@ pub async fn get_current_user(
             Err(status::Custom(
                 Status::InternalServerError,
                 Json(json!({
-                    "error": "Database error occurred"
+                    "error": "Failed to change password"
                 })),
             ))
         }
     }
 }
+
+/// A new JWT for the caller's session after their token version was bumped
+///
+/// `None` when the request wasn't made with a session token (API keys,
+/// external issuers) or the session is gone.
+async fn reissue_token(conn: &mut PgConnection, jwt: &JwtService, user: &User, session_id: &str) -> Option<String> {
+    let session_id = uuid::Uuid::parse_str(session_id).ok()?;
+    let session = match sessions::find_active(conn, session_id, user.id, None).await {
+        Ok(Some(session)) if session.oauth_client_id.is_none() => session,
+        Ok(_) => return None,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return None;
+        }
+    };
+    let plan = match plans::active_plan(conn, user.id).await {
+        Ok(plan) => plan,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return None;
+        }
+    };
+    let claims = jwt.claims_for(user, session.id, session.expires_at).plan(plan);
+    match jwt.sign_session(conn, &claims).await {
+        Ok(token) => Some(token),
+        Err(e) => {
+            eprintln!("Token error: {}", e);
+            None
+        }
+    }
+}
+
+/// Renew the caller's access token
+///
+/// Checks the session, token version and account standing in full and
+/// returns a new token for the same session. Under stateless verification
+/// (`ROCKET_VERIFICATION_MODE=stateless`) tokens are short-lived and clients
+/// renew them here before they expire; no token outlives its session.
+#[post("/renew-token")]
+pub async fn renew_token(
+    session: VerifiedSession,
+    mut db: Connection<Postgres>,
+    jwt: &State<JwtService>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let user_id = match uuid::Uuid::parse_str(&session.user_id) {
+        Ok(id) => id,
+        Err(_) => {
+            return Err(status::Custom(
+                Status::Unauthorized,
+                Json(json!({
+                    "error": "Invalid token subject"
+                })),
+            ));
+        }
+    };
+
+    let user = match users::find_by_id(&mut db, user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return Err(status::Custom(
+                Status::Unauthorized,
+                Json(json!({
+                    "error": "User not found"
+                })),
+            ));
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ));
+        }
+    };
+
+    match reissue_token(&mut db, jwt, &user, &session.session_id).await {
+        Some(token) => Ok(status::Custom(
+            Status::Ok,
+            Json(json!({
+                "token": token
+            })),
+        )),
+        None => Err(status::Custom(
+            Status::Unauthorized,
+            Json(json!({
+                "error": "Session can't be renewed"
+            })),
+        )),
+    }
+}
+
+/// Sign out everywhere
+///
+/// Revokes every session of the user, including this one and those held by
+/// OAuth clients, and bumps their token version so any JWT issued so far is
+/// refused right away. API keys are left alone; revoke them separately.
+/// An allowed `redirect_to` is returned for the frontend to go to next.
+#[post("/logout-all?<redirect_to>")]
+pub async fn logout_all(
+    _write: WriteAccess,
+    user: AuthenticatedUser,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    access_tokens: &State<AccessTokens>,
+    token_versions: &State<TokenVersions>,
+    redirect_to: Option<&str>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let redirect_to = redirect_target(config, redirect_to)?;
+    let user_id = match uuid::Uuid::parse_str(&user.user_id) {
+        Ok(id) => id,
+        Err(_) => {
+            return Err(status::Custom(
+                Status::Unauthorized,
+                Json(json!({
+                    "error": "Invalid token subject"
+                })),
+            ));
+        }
+    };
+
+    let revoked = match sessions::revoke_all_for_user(&mut db, user_id).await {
+        Ok(count) => count,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ));
+        }
+    };
+    if let Err(e) = token_versions.bump(&mut db, user_id).await {
+        eprintln!("Database error: {}", e);
+        return Err(status::Custom(
+            Status::InternalServerError,
+            Json(json!({
+                "error": "Database error occurred"
+            })),
+        ));
+    }
+    access_tokens.forget_user(user_id);
+    events::emit(&mut db, user_id, SecurityEventKind::AllSessionsRevoked { count: revoked }).await;
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "Signed out of all sessions",
+            "revoked_sessions": revoked,
+            "redirect_to": redirect_to
+        })),
+    ))
+}
+
+/// List the current user's active sessions with the limits that end them
+///
+/// A session ends at `expires_at` (the absolute maximum age) or, with an idle
+/// timeout configured, at `idle_expires_at` if it isn't used before then.
+/// `last_used_at` is updated at most once a minute. `device` describes where
+/// the session was started from, like `Chrome on macOS, Berlin`.
+#[get("/me/sessions")]
+pub async fn list_sessions(
+    user: AuthenticatedUser,
+    mut db: Connection<Postgres>,
+    access_tokens: &State<AccessTokens>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let user_id = match uuid::Uuid::parse_str(&user.user_id) {
+        Ok(id) => id,
+        Err(_) => {
+            return Err(status::Custom(
+                Status::Unauthorized,
+                Json(json!({
+                    "error": "Invalid token subject"
+                })),
+            ));
+        }
+    };
+
+    let active = match sessions::list_active_for_user(&mut db, user_id, access_tokens.active_since()).await {
+        Ok(active) => active,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ));
+        }
+    };
+
+    let idle_timeout = access_tokens.idle_timeout();
+    let sessions: Vec<Value> = active
+        .iter()
+        .map(|session| {
+            json!({
+                "id": session.id.to_string(),
+                "current": session.id.to_string() == user.session_id,
+                "oauth_client_id": session.oauth_client_id.map(|id| id.to_string()),
+                "device": devices::describe(&session.device),
+                "browser": session.device.browser,
+                "os": session.device.os,
+                "device_type": session.device.device_type,
+                "city": session.device.city,
+                "country": session.device.country,
+                "created_at": session.created_at.to_rfc3339(),
+                "last_used_at": session.last_used_at.to_rfc3339(),
+                "expires_at": session.expires_at.to_rfc3339(),
+                "idle_expires_at": idle_timeout.map(|idle_timeout| (session.last_used_at + idle_timeout).to_rfc3339())
+            })
+        })
+        .collect();
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "max_age_seconds": access_tokens.max_age().num_seconds(),
+            "idle_timeout_seconds": idle_timeout.map(|idle_timeout| idle_timeout.num_seconds()),
+            "sessions": sessions
+        })),
+    ))
+}
+
+/// Protected route example - requires authentication (API keys need `users:read`)
+///
+/// Returns a weak `ETag`; send it back in `If-None-Match` to get a `304` while
+/// the user hasn't changed.
+#[get("/me")]
+pub async fn get_current_user(
+    user: Scoped<UsersRead>,
+    preconditions: Preconditions,
+    mut db: Connection<Postgres>,
+) -> Result<Tagged<status::Custom<Json<Value>>>, status::Custom<Json<Value>>> {
+    let user_id = match uuid::Uuid::parse_str(&user.user_id) {
+        Ok(id) => id,
+        Err(_) => {
+            return Err(status::Custom(
+                Status::Unauthorized,
+                Json(json!({
+                    "error": "Invalid token subject"
+                })),
+            ));
+        }
+    };
+
+    // Find user by ID from token
+    let user_data = find_profile(&mut db, user_id).await?;
+    let etag = weak_etag(user_data.updated_at);
+    if preconditions.is_not_modified(&etag) {
+        return Ok(Tagged::not_modified(etag));
+    }
+
+    Ok(Tagged::new(
+        status::Custom(
+            Status::Ok,
+            Json(json!({
+                "user": {
+                    "id": user_data.id.to_string(),
+                    "email": user_data.email,
+                    "email_verified": user_data.email_verified_at.is_some(),
+                    "guest": user_data.is_guest(),
+                    "user_metadata": user_data.user_metadata,
+                    "app_metadata": user_data.app_metadata,
+                    "organization_id": user_data.organization_id.map(|id| id.to_string()),
+                    "subscription_status": user_data.subscription_status,
+                    "locale": user_data.locale,
+                    "timezone": user_data.timezone,
+                    "last_login_at": user_data.last_login_at.map(|at| at.to_rfc3339()),
+                    "last_login_ip": user_data.last_login_ip,
+                    "login_count": user_data.login_count,
+                    "created_at": user_data.created_at.to_rfc3339()
+                }
+            })),
+        ),
+        etag,
+    ))
+}
+
+/// Update the current user's `user_metadata`, `locale` and `timezone`
+///
+/// The metadata patch is merged into the stored object: keys replace existing
+/// ones and `null` values remove them. `app_metadata` can only be changed by admins.
+/// With `If-Match`, the update only applies if the user still has that ETag
+/// and fails with `412` otherwise.
+#[patch("/me", data = "<patch>")]
+pub async fn update_current_user(
+    _write: WriteAccess,
+    user: Scoped<UsersWrite>,
+    preconditions: Preconditions,
+    mut db: Connection<Postgres>,
+    patch: JsonBody<UserMetadataPatch>,
+) -> Result<Tagged<status::Custom<Json<Value>>>, status::Custom<Json<Value>>> {
+    let user_id = match uuid::Uuid::parse_str(&user.user_id) {
+        Ok(id) => id,
+        Err(_) => {
+            return Err(status::Custom(
+                Status::Unauthorized,
+                Json(json!({
+                    "error": "Invalid token subject"
+                })),
+            ));
+        }
+    };
+
+    if let Some(user_metadata) = &patch.user_metadata {
+        validate_metadata(user_metadata)?;
+    }
+    validate_preferences(patch.locale.as_deref(), patch.timezone.as_deref())?;
+
+    let expected_updated_at = match preconditions.if_match {
+        Some(_) => {
+            let current = find_profile(&mut db, user_id).await?;
+            if preconditions.if_match_fails(&weak_etag(current.updated_at)) {
+                return Err(precondition_failed());
+            }
+            Some(current.updated_at)
+        }
+        None => None,
+    };
+
+    let result = users::patch_metadata(
+        &mut db,
+        user_id,
+        patch.user_metadata.as_ref(),
+        None,
+        patch.locale.as_deref(),
+        patch.timezone.as_deref(),
+        expected_updated_at,
+    )
+    .await;
+
+    match result {
+        Ok(Some(user_data)) => Ok(Tagged::new(
+            status::Custom(
+                Status::Ok,
+                Json(json!({
+                    "user_metadata": user_data.user_metadata,
+                    "app_metadata": user_data.app_metadata,
+                    "locale": user_data.locale,
+                    "timezone": user_data.timezone
+                })),
+            ),
+            weak_etag(user_data.updated_at),
+        )),
+        // Changed between the check above and the update
+        Ok(None) if expected_updated_at.is_some() => Err(precondition_failed()),
+        Ok(None) => Err(status::Custom(
+            Status::NotFound,
+            Json(json!({
+                "error": "User not found"
+            })),
+        )),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ))
+        }
+    }
+}
+
+/// Load the signed-in user for the profile endpoints
+async fn find_profile(conn: &mut PgConnection, user_id: uuid::Uuid) -> Result<User, status::Custom<Json<Value>>> {
+    match users::find_by_id(conn, user_id).await {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err(status::Custom(
+            Status::NotFound,
+            Json(json!({
+                "error": "User not found"
+            })),
+        )),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ))
+        }
+    }
+}
+
+fn precondition_failed() -> status::Custom<Json<Value>> {
+    status::Custom(
+        Status::PreconditionFailed,
+        Json(json!({
+            "error": "Precondition failed",
+            "details": "The user changed since it was fetched; reload it and try again",
+            "code": "etag_mismatch"
+        })),
+    )
+}
+
+/// Stream security events for the current user as server-sent events
+///
+/// The stream ends when the user's sessions are revoked or the server shuts down.
+#[get("/me/events/stream")]
+pub async fn security_events(
+    user: AuthenticatedUser,
+    bus: &State<EventBus>,
+    mut shutdown: Shutdown,
+) -> EventStream![] {
+    let mut events = bus.subscribe();
+    let user_id = uuid::Uuid::parse_str(&user.user_id).ok();
+
+    EventStream! {
+        loop {
+            let event = select! {
+                received = events.recv() => match received {
+                    Ok(event) => event,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(_)) => continue,
+                },
+                _ = &mut shutdown => break,
+            };
+
+            if Some(event.user_id) != user_id {
+                continue;
+            }
+
+            yield Event::json(&event).event(event.kind.name());
+
+            if let SecurityEventKind::AllSessionsRevoked { .. } = event.kind {
+                break;
+            }
+        }
+    }
+}
+
+/// Confirm an email address using the token from the verification email
+///
+/// The link is opened in a browser; with an allowed `redirect_to` it's sent
+/// there with `email_verified=true`, or `email_verified=false` if the token
+/// didn't work. Otherwise browsers get a page saying how it went, and clients
+/// that don't prefer HTML get JSON.
+#[allow(clippy::too_many_arguments)]
+#[get("/verify-email?<token>&<redirect_to>")]
+pub async fn verify_email(
+    _write: WriteAccess,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    mailer: &State<Mailer>,
+    pages: &State<Pages>,
+    format: Format,
+    token: &str,
+    redirect_to: Option<&str>,
+) -> Either<Redirect, Answer> {
+    let redirect_to = match redirect_target(config, redirect_to) {
+        Ok(redirect_to) => redirect_to,
+        Err(refused) => return Either::Right(pages.answer(config, format, "Email verified", Err(refused))),
+    };
+    let verified = confirm_email(&mut db, config, mailer, token).await;
+    match redirect_to {
+        Some(redirect_to) => {
+            let verified = if verified.is_ok() { "true" } else { "false" };
+            Either::Left(Redirect::to(oauth_clients::redirect_with(&redirect_to, &[("email_verified", verified)])))
+        }
+        None => Either::Right(pages.answer(config, format, "Email verified", verified)),
+    }
+}
+
+/// Verify the address behind a verification token
+async fn confirm_email(
+    conn: &mut PgConnection,
+    config: &AppConfig,
+    mailer: &Mailer,
+    token: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let verification = match email_tokens::find(conn, config, EmailTokenPurpose::VerifyEmail, token).await {
+        Ok(Some(verification)) => verification,
+        Ok(None) => {
+            return Err(status::Custom(
+                Status::BadRequest,
+                Json(json!({
+                    "error": "Invalid or expired verification token"
+                })),
+            ));
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ));
+        }
+    };
+
+    // Check if token is expired or already used
+    if verification.used || verification.expires_at < Utc::now() {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "Invalid or expired verification token"
+            })),
+        ));
+    }
+
+    match verification.email_id {
+        Some(email_id) => verify_secondary_email(conn, email_id).await?,
+        None => {
+            if let Err(e) = users::mark_email_verified(conn, verification.user_id).await {
+                eprintln!("Database error: {}", e);
+                return Err(status::Custom(
+                    Status::InternalServerError,
+                    Json(json!({
+                        "error": "Failed to verify email"
+                    })),
+                ));
+            }
+            // A confirmed address at an organization's verified domain brings the user into it
+            let joined = match users::find_by_id(conn, verification.user_id).await {
+                Ok(Some(user)) => domains::join_by_email(conn, config, mailer, &user).await,
+                Ok(None) => Ok(()),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = joined {
+                eprintln!("Database error: {}", e);
+            }
+        }
+    }
+    let _ = email_tokens::mark_used(conn, &verification).await;
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "Email verified successfully"
+        })),
+    ))
+}
+
+/// Verify a secondary address, unless another account has claimed it meanwhile
+async fn verify_secondary_email(conn: &mut PgConnection, email_id: uuid::Uuid) -> Result<(), status::Custom<Json<Value>>> {
+    let database_error = |e: sqlx::Error| {
+        eprintln!("Database error: {}", e);
+        status::Custom(
+            Status::InternalServerError,
+            Json(json!({
+                "error": "Failed to verify email"
+            })),
+        )
+    };
+
+    let address = match user_emails::find_by_id(conn, email_id).await.map_err(database_error)? {
+        Some(address) => address,
+        None => {
+            return Err(status::Custom(
+                Status::BadRequest,
+                Json(json!({
+                    "error": "Invalid or expired verification token"
+                })),
+            ));
+        }
+    };
+
+    if users::email_exists(conn, &address.email).await.map_err(database_error)? {
+        return Err(status::Custom(
+            Status::Conflict,
+            Json(json!({
+                "error": "Email already in use"
+            })),
+        ));
+    }
+
+    user_emails::mark_verified(conn, email_id).await.map_err(database_error)
+}
+
+/// Send a new verification email to the current user
+///
+/// With `redirect_to`, following the link sends the browser there afterwards.
+#[post("/resend-verification?<redirect_to>")]
+pub async fn resend_verification(
+    _write: WriteAccess,
+    user: RegisteredUser,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    mailer: &State<Mailer>,
+    redirect_to: Option<&str>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let redirect_to = redirect_target(config, redirect_to)?;
+    let user_id = match uuid::Uuid::parse_str(&user.user_id) {
+        Ok(id) => id,
+        Err(_) => {
+            return Err(status::Custom(
+                Status::Unauthorized,
+                Json(json!({
+                    "error": "Invalid token subject"
+                })),
+            ));
+        }
+    };
+
+    let user = match users::find_by_id(&mut db, user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return Err(status::Custom(
+                Status::NotFound,
+                Json(json!({
+                    "error": "User not found"
+                })),
+            ));
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ));
+        }
+    };
+
+    if user.email_verified_at.is_some() {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "Email is already verified"
+            })),
+        ));
+    }
+
+    send_verification_email(&mut db, config, mailer, &user, redirect_to.as_deref()).await;
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "Verification email sent"
+        })),
+    ))
+}
+
+/// Claim the invitation code sent with a registration, required in invite-only mode
+///
+/// Returns the claimed invitation's id, or `None` when registration is open
+/// and no code was sent. Open registrations send one to join the
+/// organization that invited them.
+async fn claim_invitation(
+    conn: &mut PgConnection,
+    config: &AppConfig,
+    new_user: &NewUser,
+) -> Result<Option<uuid::Uuid>, status::Custom<Json<Value>>> {
+    let code = match new_user.invitation_code.as_deref() {
+        Some(code) if !code.is_empty() => code,
+        _ if config.registration_mode != RegistrationMode::InviteOnly => return Ok(None),
+        _ => {
+            return Err(status::Custom(
+                Status::Forbidden,
+                Json(json!({
+                    "error": "An invitation code is required to register",
+                    "code": "invitation_required"
+                })),
+            ));
+        }
+    };
+
+    match invitations::claim(conn, code, &new_user.email).await {
+        Ok(Some(id)) => Ok(Some(id)),
+        Ok(None) => Err(status::Custom(
+            Status::Forbidden,
+            Json(json!({
+                "error": "Invalid or expired invitation code",
+                "code": "invitation_invalid"
+            })),
+        )),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ))
+        }
+    }
+}
+
+/// Give back an invitation claimed by a registration that failed
+async fn release_invitation(conn: &mut PgConnection, invitation_id: Option<uuid::Uuid>) {
+    if let Some(id) = invitation_id
+        && let Err(e) = invitations::release(conn, id).await
+    {
+        eprintln!("Database error: {}", e);
+    }
+}
+
+/// Answer a registration for a taken email like a new one, emailing the account's owner
+///
+/// The invitation is checked and the password hashed as for a new account, so
+/// neither error responses nor timing tell the two apart.
+async fn conceal_existing_account(
+    conn: &mut PgConnection,
+    config: &AppConfig,
+    mailer: &Mailer,
+    passwords: &PasswordHasher,
+    new_user: &NewUser,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let invitation_id = claim_invitation(conn, config, new_user).await?;
+    release_invitation(conn, invitation_id).await;
+    if let Err(e) = passwords.hash(&new_user.password).await {
+        eprintln!("{}", e);
+    }
+
+    let link = format!("{}/login", config.frontend_url);
+    if let Err(e) = mailer.send(templates::account_exists(&new_user.email, &link)).await {
+        eprintln!("{}", e);
+    }
+    Ok(registration_accepted())
+}
+
+/// The registration response that doesn't reveal whether the email was taken
+fn registration_accepted() -> status::Custom<Json<Value>> {
+    status::Custom(
+        Status::Accepted,
+        Json(json!({
+            "message": "Check your email to continue"
+        })),
+    )
+}
+
+/// The `redirect_to` a browser flow was given, as an absolute URL; refused unless `redirects` allows it
+pub(crate) fn redirect_target(
+    config: &AppConfig,
+    redirect_to: Option<&str>,
+) -> Result<Option<String>, status::Custom<Json<Value>>> {
+    let Some(redirect_to) = redirect_to else {
+        return Ok(None);
+    };
+    match redirects::allowed(config, redirect_to) {
+        Some(target) => Ok(Some(target)),
+        None => Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "redirect_to isn't an allowed URL",
+                "code": "redirect_not_allowed"
+            })),
+        )),
+    }
+}
+
+/// Login refusal for an account whose signup isn't approved
+pub(crate) fn approval_refusal(user: &User) -> status::Custom<Json<Value>> {
+    if user.is_pending_approval() {
+        status::Custom(
+            Status::Forbidden,
+            Json(json!({
+                "error": "Account is awaiting approval",
+                "code": "approval_pending"
+            })),
+        )
+    } else {
+        status::Custom(
+            Status::Forbidden,
+            Json(json!({
+                "error": "Account was not approved",
+                "code": "approval_rejected",
+                "details": user.approval_reason
+            })),
+        )
+    }
+}
+
+/// Why a disabled account can't log in
+pub(crate) fn disabled_refusal() -> status::Custom<Json<Value>> {
+    status::Custom(
+        Status::Forbidden,
+        Json(json!({
+            "error": "Account is disabled after a long period of inactivity; contact support to reactivate it",
+            "code": "account_disabled"
+        })),
+    )
+}
+
+/// Refuse signing in other than with the provider an organization requires
+///
+/// The organization is the user's own, or the one that verified the
+/// address's domain. `provider` is `None` for passwords.
+pub(crate) async fn check_sso(
+    conn: &mut PgConnection,
+    config: &AppConfig,
+    organization_id: Option<uuid::Uuid>,
+    email: &str,
+    provider: Option<&str>,
+) -> Result<(), status::Custom<Json<Value>>> {
+    let domain = email.rsplit_once('@').map(|(_, domain)| domain.to_ascii_lowercase()).unwrap_or_default();
+    match organizations::sso_requirement(conn, organization_id, &domain).await {
+        Ok(Some(required)) if provider != Some(required.provider.as_str()) => Err(status::Custom(
+            Status::Forbidden,
+            Json(json!({
+                "error": "Your organization requires signing in with its identity provider",
+                "code": "sso_required",
+                "provider": required.provider,
+                "sign_in_url": sso::sign_in_url(config, &required.provider)
+            })),
+        )),
+        Ok(_) => Ok(()),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ))
+        }
+    }
+}
+
+/// Refuse logins for an address that's locked out after too many failures
+pub(crate) async fn check_lockout(
+    conn: &mut PgConnection,
+    lockout: &LoginLockout,
+    email: &str,
+) -> Result<(), RateLimited<status::Custom<Json<Value>>>> {
+    match lockout.check(conn, email).await {
+        Ok(Some(limit)) if limit.is_exceeded() => Err(rate_limit::throttled(
+            limit,
+            "Too many failed login attempts. Try again later.",
+            "too_many_attempts",
+        )),
+        Ok(_) => Ok(()),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            )
+            .into())
+        }
+    }
+}
+
+/// Refuse logins from a client address blocked for credential stuffing
+pub(crate) async fn check_ip_block(
+    conn: &mut PgConnection,
+    stuffing: &StuffingDetector,
+    ip: Option<IpAddr>,
+) -> Result<(), RateLimited<status::Custom<Json<Value>>>> {
+    match stuffing.check(conn, ip).await {
+        Ok(Some(block)) => Err(rate_limit::throttled(
+            stuffing.limit(&block),
+            "Too many failed logins from this network. Try again later.",
+            "ip_blocked",
+        )),
+        Ok(None) => Ok(()),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            )
+            .into())
+        }
+    }
+}
+
+/// Assess a login whose password checked out, refusing it when the verdict is to block
+///
+/// Returns whether the login needs a second factor. Logins that don't pass
+/// as-is are published as `risky_login` events.
+pub(crate) async fn check_login_risk(
+    conn: &mut PgConnection,
+    risk: &RiskCheck<'_>,
+    user: &User,
+    ip: Option<IpAddr>,
+) -> Result<bool, status::Custom<Json<Value>>> {
+    let assessment = match risk.assess(conn, user, ip).await {
+        Ok(Some(assessment)) if assessment.verdict != RiskVerdict::Allow => assessment,
+        Ok(_) => return Ok(false),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ));
+        }
+    };
+
+    let kind = SecurityEventKind::RiskyLogin {
+        verdict: assessment.verdict,
+        score: assessment.score,
+        reasons: assessment.reasons,
+    };
+    events::emit_event(conn, SecurityEvent::new(user.id, kind).from_ip(ip)).await;
+
+    if assessment.verdict == RiskVerdict::Block {
+        record_login_attempt(conn, Some(user.id), &user.email, ip, false).await;
+        return Err(status::Custom(
+            Status::Forbidden,
+            Json(json!({
+                "error": "This login looks suspicious and was refused",
+                "code": "login_blocked"
+            })),
+        ));
+    }
+    Ok(true)
+}
+
+/// Hold a login for its progressive delay
+pub(crate) async fn wait_for_login_delay(
+    conn: &mut PgConnection,
+    delay: &LoginDelay,
+    email: &str,
+    ip: Option<IpAddr>,
+) -> Result<(), status::Custom<Json<Value>>> {
+    delay.wait(conn, email, ip).await.map_err(|e| {
+        eprintln!("Database error: {}", e);
+        status::Custom(
+            Status::InternalServerError,
+            Json(json!({
+                "error": "Database error occurred"
+            })),
+        )
+    })
+}
+
+/// Store a login outcome for admin statistics, the lockout and login delays; failures are logged, not returned
+pub(crate) async fn record_login_attempt(
+    conn: &mut PgConnection,
+    user_id: Option<uuid::Uuid>,
+    email: &str,
+    ip: Option<IpAddr>,
+    succeeded: bool,
+) {
+    if let Err(e) = login_attempts::record(conn, user_id, email, ip, succeeded).await {
+        eprintln!("Database error: {}", e);
+    }
+}
+
+/// Record a successful login and the user's last-login details; failures are logged, not returned
+pub(crate) async fn record_login(
+    conn: &mut PgConnection,
+    telemetry: &TelemetryWriter,
+    user_id: uuid::Uuid,
+    email: &str,
+    ip: Option<IpAddr>,
+) {
+    if let Err(e) = telemetry.record_login(conn, user_id, email, ip).await {
+        eprintln!("Database error: {}", e);
+    }
+}
+
+/// Re-hash a verified password with the current pepper; failures are logged, not returned
+async fn rehash_password(conn: &mut PgConnection, passwords: &PasswordHasher, user_id: uuid::Uuid, password: &str) {
+    let result = match passwords.hash(password).await {
+        Ok(hash) => users::set_password_hash(conn, user_id, &hash).await.map_err(|e| format!("Database error: {}", e)),
+        Err(e) => Err(e.to_string()),
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to rehash password: {}", e);
+    }
+}
+
+/// Check the email format and password length of new credentials
+pub(crate) fn validate_credentials(new_user: &NewUser) -> Result<(), status::Custom<Json<Value>>> {
+    // Validate email format (basic validation)
+    if !new_user.email.contains('@') {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "Invalid email format"
+            })),
+        ));
+    }
+
+    // Validate password length
+    if new_user.password.len() < 6 {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "Password must be at least 6 characters long"
+            })),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Check a locale is a BCP 47 language tag and a time zone is an IANA zone name
+pub(crate) fn validate_preferences(locale: Option<&str>, timezone: Option<&str>) -> Result<(), status::Custom<Json<Value>>> {
+    if let Some(locale) = locale
+        && !is_language_tag(locale)
+    {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "Locale must be a BCP 47 language tag, like en or pt-BR"
+            })),
+        ));
+    }
+
+    if let Some(timezone) = timezone
+        && timezone.parse::<chrono_tz::Tz>().is_err()
+    {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "Timezone must be an IANA time zone, like Europe/Berlin"
+            })),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Whether `tag` is shaped like a BCP 47 tag: a 2–8 letter language, then 1–8 character alphanumeric subtags
+fn is_language_tag(tag: &str) -> bool {
+    let mut subtags = tag.split('-');
+    let language = subtags.next().unwrap_or_default();
+    tag.len() <= 35
+        && (2..=8).contains(&language.len())
+        && language.bytes().all(|b| b.is_ascii_alphabetic())
+        && subtags.all(|subtag| (1..=8).contains(&subtag.len()) && subtag.bytes().all(|b| b.is_ascii_alphanumeric()))
+}
+
+/// Check a metadata patch is a JSON object of reasonable size
+pub(crate) fn validate_metadata(patch: &Value) -> Result<(), status::Custom<Json<Value>>> {
+    if !patch.is_object() {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "Metadata must be a JSON object"
+            })),
+        ));
+    }
+
+    if patch.to_string().len() > MAX_METADATA_BYTES {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": format!("Metadata patches are limited to {} bytes", MAX_METADATA_BYTES)
+            })),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Issue a verification token and email the link; failures are logged, not returned
+pub(crate) async fn send_verification_email(
+    conn: &mut PgConnection,
+    config: &AppConfig,
+    mailer: &Mailer,
+    user: &User,
+    redirect_to: Option<&str>,
+) {
+    let link = match verification_link(conn, config, user.id, redirect_to).await {
+        Ok(link) => link,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return;
+        }
+    };
+
+    if let Err(e) = mailer.send(templates::email_verification(&user.email, &link)).await {
+        eprintln!("{}", e);
+    }
+}
+
+/// Issue an email verification token for the user, returning the link to put in an email
+///
+/// The link carries `redirect_to`, an allowed URL to send the browser to once verified.
+pub(crate) async fn verification_link(
+    conn: &mut PgConnection,
+    config: &AppConfig,
+    user_id: uuid::Uuid,
+    redirect_to: Option<&str>,
+) -> Result<String, sqlx::Error> {
+    let expires_at = Utc::now() + Duration::hours(24); // Token expires in 24 hours
+    let token = email_tokens::issue(conn, config, EmailTokenPurpose::VerifyEmail, user_id, None, expires_at).await?;
+    Ok(links::with_redirect(links::email_link(config, LinkAction::VerifyEmail, &token), redirect_to))
+}
+
+/// Issue a fresh reset token for a user and email the link to one of their addresses
+pub(crate) async fn send_password_reset_email(
+    conn: &mut PgConnection,
+    config: &AppConfig,
+    mailer: &Mailer,
+    user: &User,
+    to: &str,
+) -> Result<(), sqlx::Error> {
+    let expires_at = Utc::now() + Duration::hours(1); // Token expires in 1 hour
+    let reset_token = email_tokens::issue(conn, config, EmailTokenPurpose::ResetPassword, user.id, None, expires_at).await?;
+
+    let link = links::email_link(config, LinkAction::ResetPassword, &reset_token);
+    if let Err(e) = mailer.send(templates::password_reset(to, &link)).await {
+        eprintln!("{}", e);
+    }
+    Ok(())
+}
+
+/// Create a session for a user and issue an access token bound to it
+pub(crate) async fn start_session(
+    conn: &mut PgConnection,
+    jwt: &JwtService,
+    access_tokens: &AccessTokens,
+    user: &User,
+    ip: Option<IpAddr>,
+    device: &SessionDevice,
+) -> Result<String, status::Custom<Json<Value>>> {
+    let expires_at = access_tokens.session_expiry();
+    let (session, token) = match access_tokens.issue(conn, jwt, user, device, expires_at).await {
+        Ok(issued) => issued,
+        Err(e) => {
+            eprintln!("{}", e);
+            let error = match e {
+                AccessTokenError::Database(_) => "Failed to create session",
+                AccessTokenError::Signer(_) => "Failed to generate token",
+            };
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": error
+                })),
+            ));
+        }
+    };
+    let event = SecurityEvent::new(user.id, SecurityEventKind::SessionCreated { session_id: session.id });
+    access_tokens.telemetry().emit(conn, event.by(user.id).from_ip(ip)).await;
+
+    Ok(token)
+}
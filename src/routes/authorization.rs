@@ -0,0 +1,534 @@
+use chrono::{DateTime, Duration, Utc};
+use rocket::form::Form;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::{Json, Value, json};
+use rocket::State;
+use rocket_db_pools::Connection;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::auth::access_tokens::AccessTokens;
+use crate::auth::guard::RegisteredUser;
+use crate::auth::jwt::JwtService;
+use crate::auth::oauth;
+use crate::auth::oauth_clients::{self, BasicCredentials, ACCESS_TOKEN_TTL_SECONDS, CODE_TTL_SECONDS};
+use crate::auth::token_exchange;
+use crate::body_limits::JsonBody;
+use crate::config::AppConfig;
+use crate::events::{self, SecurityEventKind};
+use crate::maintenance::WriteAccess;
+use crate::models::oauth_client::{AuthorizationDecision, AuthorizationParams, OAuthClient, RevocationRequest, TokenRequest};
+use crate::models::session::ClientGrant;
+use crate::models::user::User;
+use crate::repositories::{oauth_clients as client_repo, oauth_codes, oauth_consents, sessions, users};
+use crate::Postgres;
+
+/// An authorization request that checked out against its client
+struct ValidRequest {
+    client: OAuthClient,
+    scopes: Vec<String>,
+}
+
+/// Describe an authorization request for the consent page
+///
+/// The frontend's `/oauth/authorize` page forwards the client's query here
+/// and shows the user who is asking for what. `consent_required` is false
+/// when the user already granted every requested scope, in which case the
+/// page may approve straight away.
+#[get("/authorize?<params..>")]
+pub async fn describe_authorization(
+    user: RegisteredUser,
+    mut db: Connection<Postgres>,
+    params: AuthorizationParams,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let user_id = parse_user_id(&user)?;
+    let request = validate(&mut db, &params).await?;
+
+    let granted = oauth_consents::granted_scopes(&mut db, user_id, request.client.id)
+        .await
+        .map_err(database_error)?
+        .unwrap_or_default();
+    let consent_required = request.scopes.iter().any(|scope| !granted.contains(scope));
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "client": {
+                "client_id": request.client.client_id,
+                "name": request.client.name,
+                "logo_url": request.client.logo_url
+            },
+            "scopes": request.scopes,
+            "consent_required": consent_required
+        })),
+    ))
+}
+
+/// Approve or deny an authorization request
+///
+/// Returns the `redirect_to` URL the frontend sends the browser to: the
+/// client's redirect URI with a single-use `code` (valid for 10 minutes) or
+/// `error=access_denied`, plus the client's `state`.
+#[post("/authorize", data = "<decision>")]
+pub async fn decide_authorization(
+    _write: WriteAccess,
+    user: RegisteredUser,
+    mut db: Connection<Postgres>,
+    decision: JsonBody<AuthorizationDecision>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let user_id = parse_user_id(&user)?;
+    let params = &decision.request;
+    let request = validate(&mut db, params).await?;
+
+    let mut response = Vec::new();
+    let code = decision.approve.then(oauth_clients::generate_code);
+    if let Some(code) = &code {
+        oauth_consents::grant(&mut db, user_id, request.client.id, &request.scopes)
+            .await
+            .map_err(database_error)?;
+        oauth_codes::create(
+            &mut db,
+            code,
+            request.client.id,
+            user_id,
+            &params.redirect_uri,
+            &request.scopes,
+            params.code_challenge.as_deref(),
+            Utc::now() + Duration::seconds(CODE_TTL_SECONDS),
+        )
+        .await
+        .map_err(database_error)?;
+        response.push(("code", code.as_str()));
+    } else {
+        response.push(("error", "access_denied"));
+    }
+    if let Some(state) = &params.state {
+        response.push(("state", state.as_str()));
+    }
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "redirect_to": oauth_clients::redirect_with(&params.redirect_uri, &response)
+        })),
+    ))
+}
+
+/// OAuth 2.0 token endpoint
+///
+/// Supports the `authorization_code` grant and token exchange (RFC 8693).
+/// Confidential clients authenticate with HTTP Basic or `client_id` and
+/// `client_secret` form fields; public clients send only `client_id` and
+/// prove possession with their PKCE `code_verifier`. Errors use the RFC 6749
+/// format (`error`, `error_description`).
+#[post("/token", data = "<request>")]
+pub async fn token(
+    _write: WriteAccess,
+    basic: BasicCredentials,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    jwt: &State<JwtService>,
+    access_tokens: &State<AccessTokens>,
+    request: Form<TokenRequest>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let exchange = match request.grant_type.as_str() {
+        "authorization_code" => false,
+        token_exchange::GRANT_TYPE => true,
+        _ => {
+            return Err(token_error(
+                Status::BadRequest,
+                "unsupported_grant_type",
+                "Supported grants are authorization_code and token exchange",
+            ));
+        }
+    };
+
+    let client = authenticate_client(&mut db, basic, &request.client_id, &request.client_secret).await?;
+    let (user, grant) = match exchange {
+        false => authorization_code_grant(&mut db, &client, &request).await?,
+        true => token_exchange_grant(&mut db, config, jwt, access_tokens, &client, &request).await?,
+    };
+
+    let client_grant = ClientGrant {
+        oauth_client_id: client.id,
+        scopes: &grant.scopes,
+        audience: grant.audience.as_deref(),
+        expires_at: grant.expires_at,
+    };
+    let (session, access_token) =
+        match access_tokens.issue_for_client(&mut db, jwt, &user, &client_grant).await {
+            Ok(issued) => issued,
+            Err(e) => {
+                eprintln!("{}", e);
+                return Err(token_error(Status::InternalServerError, "server_error", "Failed to issue a token"));
+            }
+        };
+    events::emit(&mut db, user.id, SecurityEventKind::SessionCreated { session_id: session.id }).await;
+
+    let mut response = json!({
+        "access_token": access_token,
+        "token_type": "Bearer",
+        "expires_in": (grant.expires_at - Utc::now()).num_seconds().max(0),
+        "scope": grant.scopes.join(" ")
+    });
+    if exchange {
+        response["issued_token_type"] = json!(token_exchange::ACCESS_TOKEN_TYPE);
+    }
+
+    Ok(status::Custom(Status::Ok, Json(response)))
+}
+
+/// What a token request was granted, before a token is issued for it
+struct TokenGrant {
+    scopes: Vec<String>,
+    audience: Option<String>,
+    expires_at: DateTime<Utc>,
+}
+
+/// Redeem an authorization code issued to the client
+async fn authorization_code_grant(
+    conn: &mut PgConnection,
+    client: &OAuthClient,
+    request: &TokenRequest,
+) -> Result<(User, TokenGrant), status::Custom<Json<Value>>> {
+    let (Some(code), Some(redirect_uri)) = (&request.code, &request.redirect_uri) else {
+        return Err(token_error(Status::BadRequest, "invalid_request", "code and redirect_uri are required"));
+    };
+    let Some(grant) = oauth_codes::take(conn, code, client.id).await.map_err(database_error)? else {
+        return Err(token_error(
+            Status::BadRequest,
+            "invalid_grant",
+            "The code is invalid, expired or already used",
+        ));
+    };
+    if &grant.redirect_uri != redirect_uri {
+        return Err(token_error(Status::BadRequest, "invalid_grant", "redirect_uri doesn't match"));
+    }
+    if let Some(challenge) = &grant.code_challenge {
+        let verified = request
+            .code_verifier
+            .as_deref()
+            .is_some_and(|verifier| &oauth::code_challenge(verifier) == challenge);
+        if !verified {
+            return Err(token_error(Status::BadRequest, "invalid_grant", "code_verifier doesn't match"));
+        }
+    }
+
+    let Some(user) = users::find_by_id(conn, grant.user_id).await.map_err(database_error)? else {
+        return Err(token_error(Status::BadRequest, "invalid_grant", "The user no longer exists"));
+    };
+
+    Ok((
+        user,
+        TokenGrant {
+            scopes: grant.scopes,
+            audience: None,
+            expires_at: Utc::now() + Duration::seconds(ACCESS_TOKEN_TTL_SECONDS),
+        },
+    ))
+}
+
+/// Exchange a user's access token for a narrower one meant for an internal service (RFC 8693)
+///
+/// The client must be confidential and allowed by the audience's policy.
+/// The new token carries at most the policy's scopes and the subject
+/// token's own, and expires with the policy's lifetime or the subject
+/// token's session, whichever comes first.
+async fn token_exchange_grant(
+    conn: &mut PgConnection,
+    config: &AppConfig,
+    jwt: &JwtService,
+    access_tokens: &AccessTokens,
+    client: &OAuthClient,
+    request: &TokenRequest,
+) -> Result<(User, TokenGrant), status::Custom<Json<Value>>> {
+    if !client.is_confidential() {
+        return Err(token_error(
+            Status::BadRequest,
+            "unauthorized_client",
+            "Public clients can't exchange tokens",
+        ));
+    }
+    let Some(subject_token) = &request.subject_token else {
+        return Err(token_error(Status::BadRequest, "invalid_request", "subject_token is required"));
+    };
+    if request.subject_token_type.as_deref() != Some(token_exchange::ACCESS_TOKEN_TYPE)
+        || request
+            .requested_token_type
+            .as_deref()
+            .is_some_and(|requested| requested != token_exchange::ACCESS_TOKEN_TYPE)
+    {
+        return Err(token_error(
+            Status::BadRequest,
+            "invalid_request",
+            "Only access tokens can be exchanged for access tokens",
+        ));
+    }
+
+    let Some(audience) = &request.audience else {
+        return Err(token_error(Status::BadRequest, "invalid_request", "audience is required"));
+    };
+    let Some(policy) = token_exchange::policy(config, audience) else {
+        return Err(token_error(Status::BadRequest, "invalid_target", "Unknown audience"));
+    };
+    if !policy.clients.contains(&client.client_id) {
+        return Err(token_error(
+            Status::BadRequest,
+            "unauthorized_client",
+            "The client may not exchange tokens for this audience",
+        ));
+    }
+
+    let Some(subject) = token_exchange::subject_session(conn, jwt, access_tokens, subject_token)
+        .await
+        .map_err(database_error)?
+    else {
+        return Err(token_error(
+            Status::BadRequest,
+            "invalid_grant",
+            "subject_token is invalid, expired or can't be exchanged",
+        ));
+    };
+
+    let scopes = match token_exchange::narrow_scopes(policy, subject.scopes.as_deref(), request.scope.as_deref()) {
+        Ok(scopes) if scopes.is_empty() => {
+            return Err(token_error(Status::BadRequest, "invalid_scope", "No scope can be granted"));
+        }
+        Ok(scopes) => scopes,
+        Err(denied) => {
+            return Err(token_error(
+                Status::BadRequest,
+                "invalid_scope",
+                &format!("'{}' can't be granted for this audience", denied),
+            ));
+        }
+    };
+
+    let Some(user) = users::find_by_id(conn, subject.user_id).await.map_err(database_error)? else {
+        return Err(token_error(Status::BadRequest, "invalid_grant", "The user no longer exists"));
+    };
+
+    Ok((
+        user,
+        TokenGrant {
+            scopes,
+            audience: Some(policy.audience.clone()),
+            expires_at: subject
+                .expires_at
+                .min(Utc::now() + Duration::seconds(policy.lifetime_seconds)),
+        },
+    ))
+}
+
+/// OAuth 2.0 token revocation (RFC 7009)
+///
+/// Clients authenticate as at the token endpoint and can only revoke tokens
+/// issued to them. The answer is `200` whether or not the token was found,
+/// so it can't be used to probe tokens. No refresh tokens are issued, so a
+/// `refresh_token` hint just means nothing is revoked.
+#[post("/revoke", data = "<request>")]
+pub async fn revoke(
+    _write: WriteAccess,
+    basic: BasicCredentials,
+    mut db: Connection<Postgres>,
+    jwt: &State<JwtService>,
+    access_tokens: &State<AccessTokens>,
+    request: Form<RevocationRequest>,
+) -> Result<Status, status::Custom<Json<Value>>> {
+    let client = authenticate_client(&mut db, basic, &request.client_id, &request.client_secret).await?;
+
+    let revoked = access_tokens
+        .revoke_for_client(&mut db, jwt, &request.token, client.id)
+        .await
+        .map_err(database_error)?;
+    if revoked {
+        println!("✓ OAuth client {} revoked a token", client.client_id);
+    }
+
+    Ok(Status::Ok)
+}
+
+/// List the applications the current user has authorized
+#[get("/me/applications")]
+pub async fn list_applications(
+    user: RegisteredUser,
+    mut db: Connection<Postgres>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let user_id = parse_user_id(&user)?;
+    let applications = oauth_consents::list_for_user(&mut db, user_id).await.map_err(database_error)?;
+
+    Ok(status::Custom(Status::Ok, Json(json!({ "applications": applications }))))
+}
+
+/// Revoke an application's access: its consent and every token it holds for the user
+#[delete("/me/applications/<client_id>")]
+pub async fn revoke_application(
+    _write: WriteAccess,
+    user: RegisteredUser,
+    mut db: Connection<Postgres>,
+    access_tokens: &State<AccessTokens>,
+    client_id: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let user_id = parse_user_id(&user)?;
+    let revoked = revoke_access(&mut db, access_tokens, user_id, client_id)
+        .await
+        .map_err(database_error)?;
+    let Some(sessions_revoked) = revoked else {
+        return Err(application_not_found());
+    };
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "Application access revoked",
+            "sessions_revoked": sessions_revoked
+        })),
+    ))
+}
+
+/// Withdraw the user's consent for a client and end every session it holds for them
+///
+/// Returns the number of sessions ended, or `None` if the user hadn't
+/// authorized the client.
+pub(crate) async fn revoke_access(
+    conn: &mut PgConnection,
+    access_tokens: &AccessTokens,
+    user_id: Uuid,
+    client_id: &str,
+) -> Result<Option<u64>, sqlx::Error> {
+    let Some(client) = client_repo::find_by_client_id(conn, client_id).await? else {
+        return Ok(None);
+    };
+    if !oauth_consents::revoke(conn, user_id, client.id).await? {
+        return Ok(None);
+    }
+
+    let sessions_revoked = sessions::revoke_for_client(conn, user_id, client.id).await?;
+    access_tokens.forget_user(user_id);
+    Ok(Some(sessions_revoked))
+}
+
+/// Check an authorization request against the client's registration
+///
+/// Nothing here redirects: until the redirect URI is known to belong to the
+/// client, sending the browser there would make this an open redirector.
+async fn validate(
+    conn: &mut PgConnection,
+    params: &AuthorizationParams,
+) -> Result<ValidRequest, status::Custom<Json<Value>>> {
+    let Some(client) = client_repo::find_by_client_id(conn, &params.client_id).await.map_err(database_error)? else {
+        return Err(authorization_error("invalid_client", "Unknown client_id"));
+    };
+    if !client.redirect_uris.contains(&params.redirect_uri) {
+        return Err(authorization_error("invalid_request", "redirect_uri isn't registered for this client"));
+    }
+    if params.response_type != "code" {
+        return Err(authorization_error("unsupported_response_type", "Only response_type=code is supported"));
+    }
+
+    match (&params.code_challenge, params.code_challenge_method.as_deref()) {
+        (Some(_), Some("S256")) => {}
+        (Some(_), _) => {
+            return Err(authorization_error("invalid_request", "code_challenge_method must be S256"));
+        }
+        (None, _) if !client.is_confidential() => {
+            return Err(authorization_error("invalid_request", "Public clients must use PKCE"));
+        }
+        (None, _) => {}
+    }
+
+    let mut scopes: Vec<String> = match &params.scope {
+        Some(scope) => scope.split_whitespace().map(str::to_string).collect(),
+        None => client.scopes.clone(),
+    };
+    scopes.sort();
+    scopes.dedup();
+    if scopes.is_empty() {
+        return Err(authorization_error("invalid_scope", "At least one scope is required"));
+    }
+    if let Some(unknown) = scopes.iter().find(|scope| !client.scopes.contains(scope)) {
+        return Err(authorization_error(
+            "invalid_scope",
+            &format!("The client may not request '{}'", unknown),
+        ));
+    }
+
+    Ok(ValidRequest { client, scopes })
+}
+
+/// Identify the client calling the token or revocation endpoint, from HTTP Basic or form fields
+async fn authenticate_client(
+    conn: &mut PgConnection,
+    basic: BasicCredentials,
+    client_id: &Option<String>,
+    client_secret: &Option<String>,
+) -> Result<OAuthClient, status::Custom<Json<Value>>> {
+    let (client_id, secret) = match basic.0 {
+        Some((client_id, secret)) => (client_id, Some(secret)),
+        None => match client_id {
+            Some(client_id) => (client_id.clone(), client_secret.clone()),
+            None => {
+                return Err(token_error(Status::Unauthorized, "invalid_client", "Client authentication is required"));
+            }
+        },
+    };
+
+    let client = client_repo::find_by_client_id(conn, &client_id).await.map_err(database_error)?;
+    let authenticated = client.filter(|client| match &client.client_secret_hash {
+        Some(hash) => secret.as_deref().is_some_and(|secret| oauth_clients::secret_matches(secret, hash)),
+        None => true,
+    });
+
+    authenticated.ok_or_else(|| token_error(Status::Unauthorized, "invalid_client", "Client authentication failed"))
+}
+
+fn authorization_error(code: &str, message: &str) -> status::Custom<Json<Value>> {
+    status::Custom(
+        Status::BadRequest,
+        Json(json!({
+            "error": message,
+            "code": code
+        })),
+    )
+}
+
+fn application_not_found() -> status::Custom<Json<Value>> {
+    status::Custom(
+        Status::NotFound,
+        Json(json!({
+            "error": "Application not found"
+        })),
+    )
+}
+
+fn token_error(status: Status, error: &str, description: &str) -> status::Custom<Json<Value>> {
+    status::Custom(
+        status,
+        Json(json!({
+            "error": error,
+            "error_description": description
+        })),
+    )
+}
+
+fn parse_user_id(user: &RegisteredUser) -> Result<Uuid, status::Custom<Json<Value>>> {
+    Uuid::parse_str(&user.user_id).map_err(|_| {
+        status::Custom(
+            Status::Unauthorized,
+            Json(json!({
+                "error": "Invalid token subject"
+            })),
+        )
+    })
+}
+
+fn database_error(e: sqlx::Error) -> status::Custom<Json<Value>> {
+    eprintln!("Database error: {}", e);
+    status::Custom(
+        Status::InternalServerError,
+        Json(json!({
+            "error": "Database error occurred"
+        })),
+    )
+}
@@ -0,0 +1,105 @@
+use chrono::{DateTime, Utc};
+use rocket::data::{Data, ToByteUnit};
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::{Json, Value, json};
+use rocket::State;
+use rocket_db_pools::Connection;
+use serde::Deserialize;
+
+use crate::billing::{self, StripeSignature};
+use crate::config::AppConfig;
+use crate::maintenance::WriteAccess;
+use crate::repositories::users;
+use crate::Postgres;
+
+/// Largest webhook payload accepted
+const MAX_PAYLOAD_KIB: u64 = 256;
+
+#[derive(Debug, Deserialize)]
+struct StripeEvent {
+    #[serde(rename = "type")]
+    event_type: String,
+    /// Unix time the event happened
+    created: i64,
+    data: StripeEventData,
+}
+
+#[derive(Debug, Deserialize)]
+struct StripeEventData {
+    object: Value,
+}
+
+/// Receive a Stripe webhook, keeping users' `subscription_status` in step with their subscriptions
+///
+/// Handles `customer.subscription.created`, `.updated` and `.deleted`;
+/// other events are acknowledged and ignored. Deliveries without a valid
+/// `Stripe-Signature` are refused with 400.
+#[post("/stripe/webhook", data = "<payload>")]
+pub async fn stripe_webhook(
+    _write: WriteAccess,
+    signature: Option<StripeSignature<'_>>,
+    payload: Data<'_>,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let Some(secret) = config.stripe.as_ref().and_then(|stripe| stripe.webhook_secret.as_deref()) else {
+        return Err(status::Custom(
+            Status::NotFound,
+            Json(json!({ "error": "Stripe webhooks are disabled" })),
+        ));
+    };
+
+    let payload = match payload.open(MAX_PAYLOAD_KIB.kibibytes()).into_string().await {
+        Ok(payload) if payload.is_complete() => payload.into_inner(),
+        Ok(_) => {
+            return Err(status::Custom(
+                Status::PayloadTooLarge,
+                Json(json!({ "error": "Payload too large" })),
+            ));
+        }
+        Err(_) => return Err(invalid_payload()),
+    };
+
+    let authentic = signature.and_then(|signature| billing::verify_webhook(secret, signature.0, &payload, Utc::now()));
+    if authentic.is_none() {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "Invalid webhook signature",
+                "code": "invalid_signature"
+            })),
+        ));
+    }
+
+    let event: StripeEvent = serde_json::from_str(&payload).map_err(|_| invalid_payload())?;
+    if let "customer.subscription.created" | "customer.subscription.updated" | "customer.subscription.deleted" =
+        event.event_type.as_str()
+    {
+        let object = &event.data.object;
+        let (Some(customer), Some(status)) = (object["customer"].as_str(), object["status"].as_str()) else {
+            return Err(invalid_payload());
+        };
+        let at = DateTime::from_timestamp(event.created, 0).ok_or_else(invalid_payload)?;
+        users::set_subscription_status(&mut db, customer, status, at)
+            .await
+            .map_err(database_error)?;
+    }
+
+    Ok(status::Custom(Status::Ok, Json(json!({ "received": true }))))
+}
+
+fn invalid_payload() -> status::Custom<Json<Value>> {
+    status::Custom(
+        Status::BadRequest,
+        Json(json!({ "error": "Invalid webhook payload" })),
+    )
+}
+
+fn database_error(e: sqlx::Error) -> status::Custom<Json<Value>> {
+    eprintln!("Database error: {}", e);
+    status::Custom(
+        Status::InternalServerError,
+        Json(json!({ "error": "Database error occurred" })),
+    )
+}
@@ -0,0 +1,227 @@
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::{Json, Value, json};
+use rocket::State;
+use rocket_db_pools::Connection;
+
+use crate::auth::oauth_clients::{self, BearerToken};
+use crate::auth::{scopes, tokens};
+use crate::body_limits::JsonBody;
+use crate::config::AppConfig;
+use crate::maintenance::WriteAccess;
+use crate::models::oauth_client::{ClientRegistration, OAuthClient};
+use crate::repositories::oauth_clients as client_repo;
+use crate::Postgres;
+
+/// Dynamic client registration (RFC 7591)
+///
+/// Lets applications register themselves without an admin, given the
+/// initial access token from `ROCKET_CLIENT_REGISTRATION_TOKEN`; without
+/// one configured this route is a 404. The response holds the client's
+/// credentials and a registration access token for reading or deleting the
+/// registration at `registration_client_uri` (RFC 7592). Secrets and tokens
+/// are only returned here.
+#[post("/register-client", data = "<registration>")]
+pub async fn register_client(
+    _write: WriteAccess,
+    bearer: BearerToken,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    registration: JsonBody<ClientRegistration>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let Some(initial_token) = &config.client_registration_token else {
+        return Err(status::Custom(
+            Status::NotFound,
+            Json(json!({
+                "error": "Client registration is disabled"
+            })),
+        ));
+    };
+    // Compared by hash so the comparison time says nothing about the token
+    let authorized = bearer
+        .0
+        .is_some_and(|token| tokens::hash(&token) == tokens::hash(initial_token));
+    if !authorized {
+        return Err(invalid_token());
+    }
+
+    let name = match registration.client_name.as_deref().map(str::trim) {
+        Some(name) if !name.is_empty() && name.len() <= 100 => name,
+        _ => {
+            return Err(registration_error(
+                "invalid_client_metadata",
+                "client_name must be between 1 and 100 characters",
+            ));
+        }
+    };
+    if registration.redirect_uris.is_empty() {
+        return Err(registration_error("invalid_redirect_uri", "At least one redirect URI is required"));
+    }
+    if let Some(invalid) = oauth_clients::invalid_redirect_uri(&registration.redirect_uris) {
+        return Err(registration_error(
+            "invalid_redirect_uri",
+            &format!("'{}' isn't an absolute URI without a fragment", invalid),
+        ));
+    }
+    if let Some(logo_uri) = &registration.logo_uri
+        && !oauth_clients::is_valid_logo_url(logo_uri)
+    {
+        return Err(registration_error("invalid_client_metadata", "logo_uri must be an https URL"));
+    }
+
+    let confidential = match registration.token_endpoint_auth_method.as_deref() {
+        None | Some("client_secret_basic") | Some("client_secret_post") => true,
+        Some("none") => false,
+        Some(other) => {
+            return Err(registration_error(
+                "invalid_client_metadata",
+                &format!("Unsupported token_endpoint_auth_method '{}'", other),
+            ));
+        }
+    };
+    if let Some(grant_types) = &registration.grant_types
+        && grant_types.iter().any(|grant| grant != "authorization_code")
+    {
+        return Err(registration_error(
+            "invalid_client_metadata",
+            "Only the authorization_code grant is supported",
+        ));
+    }
+    if let Some(response_types) = &registration.response_types
+        && response_types.iter().any(|response| response != "code")
+    {
+        return Err(registration_error("invalid_client_metadata", "Only the code response type is supported"));
+    }
+
+    let mut granted: Vec<String> = match &registration.scope {
+        Some(scope) => scope.split_whitespace().map(str::to_string).collect(),
+        None => vec![scopes::OPENID.to_string()],
+    };
+    granted.sort();
+    granted.dedup();
+    if granted.is_empty() {
+        return Err(registration_error("invalid_client_metadata", "At least one scope is required"));
+    }
+    if let Some(unknown) = granted.iter().find(|scope| !scopes::is_known(scope)) {
+        return Err(registration_error(
+            "invalid_client_metadata",
+            &format!("Unknown scope '{}'", unknown),
+        ));
+    }
+
+    let secret = confidential.then(oauth_clients::generate_secret);
+    let (registration_token, registration_token_hash) = oauth_clients::generate_registration_token();
+    let client = client_repo::create(
+        &mut db,
+        &oauth_clients::generate_client_id(),
+        secret.as_ref().map(|(_, hash)| hash.as_str()),
+        name,
+        &registration.redirect_uris,
+        &granted,
+        registration.logo_uri.as_deref(),
+    )
+    .await
+    .map_err(database_error)?;
+    client_repo::set_registration_token(&mut db, client.id, &registration_token_hash)
+        .await
+        .map_err(database_error)?;
+    println!("✓ OAuth client {} registered dynamically", client.client_id);
+
+    let mut response = client_metadata(config, &client);
+    response["registration_access_token"] = json!(registration_token);
+    if let Some((secret, _)) = secret {
+        response["client_secret"] = json!(secret);
+        response["client_secret_expires_at"] = json!(0);
+    }
+
+    Ok(status::Custom(Status::Created, Json(response)))
+}
+
+/// Read a dynamically registered client's metadata with its registration access token
+#[get("/register-client/<client_id>")]
+pub async fn get_registration(
+    bearer: BearerToken,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    client_id: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let client = registered_client(&mut db, bearer, client_id).await?;
+
+    Ok(status::Custom(Status::Ok, Json(client_metadata(config, &client))))
+}
+
+/// Delete a dynamically registered client, with every token it was issued
+#[delete("/register-client/<client_id>")]
+pub async fn delete_registration(
+    _write: WriteAccess,
+    bearer: BearerToken,
+    mut db: Connection<Postgres>,
+    client_id: &str,
+) -> Result<Status, status::Custom<Json<Value>>> {
+    let client = registered_client(&mut db, bearer, client_id).await?;
+    client_repo::delete(&mut db, client.id).await.map_err(database_error)?;
+    println!("✓ OAuth client {} deleted its registration", client.client_id);
+
+    Ok(Status::NoContent)
+}
+
+/// The client a registration access token belongs to; unknown clients look like bad tokens
+async fn registered_client(
+    db: &mut Connection<Postgres>,
+    bearer: BearerToken,
+    client_id: &str,
+) -> Result<OAuthClient, status::Custom<Json<Value>>> {
+    let Some(token) = bearer.0 else {
+        return Err(invalid_token());
+    };
+    client_repo::find_by_registration_token(db, client_id, &tokens::hash(&token))
+        .await
+        .map_err(database_error)?
+        .ok_or_else(invalid_token)
+}
+
+/// A client's registered metadata, in RFC 7591 terms
+fn client_metadata(config: &AppConfig, client: &OAuthClient) -> Value {
+    json!({
+        "client_id": client.client_id,
+        "client_id_issued_at": client.created_at.timestamp(),
+        "client_name": client.name,
+        "redirect_uris": client.redirect_uris,
+        "logo_uri": client.logo_url,
+        "scope": client.scopes.join(" "),
+        "token_endpoint_auth_method": if client.is_confidential() { "client_secret_basic" } else { "none" },
+        "grant_types": ["authorization_code"],
+        "response_types": ["code"],
+        "registration_client_uri": format!("{}/api/v1/auth/register-client/{}", config.public_url, client.client_id)
+    })
+}
+
+fn registration_error(error: &str, description: &str) -> status::Custom<Json<Value>> {
+    status::Custom(
+        Status::BadRequest,
+        Json(json!({
+            "error": error,
+            "error_description": description
+        })),
+    )
+}
+
+fn invalid_token() -> status::Custom<Json<Value>> {
+    status::Custom(
+        Status::Unauthorized,
+        Json(json!({
+            "error": "invalid_token",
+            "error_description": "A valid access token is required"
+        })),
+    )
+}
+
+fn database_error(e: sqlx::Error) -> status::Custom<Json<Value>> {
+    eprintln!("Database error: {}", e);
+    status::Custom(
+        Status::InternalServerError,
+        Json(json!({
+            "error": "Database error occurred"
+        })),
+    )
+}
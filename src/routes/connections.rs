@@ -0,0 +1,201 @@
+use chrono::{DateTime, Utc};
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::{Json, Value, json};
+use rocket::State;
+use rocket_db_pools::Connection;
+use uuid::Uuid;
+
+use crate::auth::access_tokens::AccessTokens;
+use crate::auth::guard::RegisteredUser;
+use crate::maintenance::WriteAccess;
+use crate::repositories::{api_keys, external_identities, oauth_consents, users};
+use crate::routes::authorization::revoke_access;
+use crate::Postgres;
+
+/// Domain of the placeholder addresses given to external users who had no email
+const PLACEHOLDER_EMAIL_DOMAIN: &str = "@external.invalid";
+
+/// Everything with access to the current user's account, newest first
+///
+/// Aggregates the OAuth applications they authorized (`application`), their
+/// unexpired API keys (`api_key`) and the external accounts they sign in
+/// with (`linked_account`). Each entry has a `type`, an `id`, a `name`, when
+/// it was connected and the `revoke` path that disconnects it, plus the
+/// details of its kind.
+#[get("/me/connections")]
+pub async fn list_connections(
+    user: RegisteredUser,
+    mut db: Connection<Postgres>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let user_id = parse_user_id(&user)?;
+    let applications = oauth_consents::list_for_user(&mut db, user_id).await.map_err(database_error)?;
+    let keys = api_keys::list_for_user(&mut db, user_id).await.map_err(database_error)?;
+    let accounts = external_identities::list_for_user(&mut db, user_id).await.map_err(database_error)?;
+
+    let now = Utc::now();
+    let mut connections: Vec<(DateTime<Utc>, Value)> = Vec::new();
+    connections.extend(applications.into_iter().map(|application| {
+        (
+            application.created_at,
+            json!({
+                "type": "application",
+                "id": application.client_id,
+                "name": application.name,
+                "connected_at": application.created_at,
+                "revoke": format!("/me/connections/application/{}", application.client_id),
+                "logo_url": application.logo_url,
+                "scopes": application.scopes,
+                "updated_at": application.updated_at
+            }),
+        )
+    }));
+    connections.extend(
+        keys.into_iter()
+            .filter(|key| key.expires_at.is_none_or(|expires_at| expires_at > now))
+            .map(|key| {
+                (
+                    key.created_at,
+                    json!({
+                        "type": "api_key",
+                        "id": key.id,
+                        "name": key.name,
+                        "connected_at": key.created_at,
+                        "revoke": format!("/me/connections/api_key/{}", key.id),
+                        "key_prefix": key.key_prefix,
+                        "scopes": key.scopes,
+                        "last_used_at": key.last_used_at,
+                        "expires_at": key.expires_at
+                    }),
+                )
+            }),
+    );
+    connections.extend(accounts.into_iter().map(|account| {
+        (
+            account.created_at,
+            json!({
+                "type": "linked_account",
+                "id": account.id,
+                "name": account.issuer,
+                "connected_at": account.created_at,
+                "revoke": format!("/me/connections/linked_account/{}", account.id),
+                "issuer": account.issuer
+            }),
+        )
+    }));
+    connections.sort_by_key(|(connected_at, _)| std::cmp::Reverse(*connected_at));
+
+    let connections: Vec<Value> = connections.into_iter().map(|(_, connection)| connection).collect();
+    Ok(status::Custom(Status::Ok, Json(json!({ "connections": connections }))))
+}
+
+/// Disconnect one of the entries of `GET /me/connections`
+///
+/// Revoking an application also ends every session it holds for the user.
+/// Unlinking the only external account of a user provisioned without an
+/// email is refused with `409 last_sign_in_method`: they'd have no way left
+/// to sign in.
+#[delete("/me/connections/<kind>/<id>")]
+pub async fn revoke_connection(
+    _write: WriteAccess,
+    user: RegisteredUser,
+    mut db: Connection<Postgres>,
+    access_tokens: &State<AccessTokens>,
+    kind: &str,
+    id: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let user_id = parse_user_id(&user)?;
+
+    match kind {
+        "application" => {
+            let revoked = revoke_access(&mut db, access_tokens, user_id, id)
+                .await
+                .map_err(database_error)?;
+            let Some(sessions_revoked) = revoked else {
+                return Err(connection_not_found());
+            };
+            Ok(status::Custom(
+                Status::Ok,
+                Json(json!({
+                    "message": "Application access revoked",
+                    "sessions_revoked": sessions_revoked
+                })),
+            ))
+        }
+        "api_key" => {
+            let id = Uuid::parse_str(id).map_err(|_| connection_not_found())?;
+            if !api_keys::revoke(&mut db, user_id, id).await.map_err(database_error)? {
+                return Err(connection_not_found());
+            }
+            Ok(status::Custom(
+                Status::Ok,
+                Json(json!({
+                    "message": "API key revoked"
+                })),
+            ))
+        }
+        "linked_account" => {
+            let id = Uuid::parse_str(id).map_err(|_| connection_not_found())?;
+            let accounts = external_identities::list_for_user(&mut db, user_id)
+                .await
+                .map_err(database_error)?;
+            if !accounts.iter().any(|account| account.id == id) {
+                return Err(connection_not_found());
+            }
+
+            if accounts.len() == 1 {
+                let user = users::find_by_id(&mut db, user_id).await.map_err(database_error)?;
+                if user.is_some_and(|user| user.email.ends_with(PLACEHOLDER_EMAIL_DOMAIN)) {
+                    return Err(status::Custom(
+                        Status::Conflict,
+                        Json(json!({
+                            "error": "This account is your only way to sign in; add an email address first",
+                            "code": "last_sign_in_method"
+                        })),
+                    ));
+                }
+            }
+
+            if !external_identities::unlink(&mut db, user_id, id).await.map_err(database_error)? {
+                return Err(connection_not_found());
+            }
+            Ok(status::Custom(
+                Status::Ok,
+                Json(json!({
+                    "message": "Account unlinked"
+                })),
+            ))
+        }
+        _ => Err(connection_not_found()),
+    }
+}
+
+fn connection_not_found() -> status::Custom<Json<Value>> {
+    status::Custom(
+        Status::NotFound,
+        Json(json!({
+            "error": "Connection not found"
+        })),
+    )
+}
+
+fn parse_user_id(user: &RegisteredUser) -> Result<Uuid, status::Custom<Json<Value>>> {
+    Uuid::parse_str(&user.user_id).map_err(|_| {
+        status::Custom(
+            Status::Unauthorized,
+            Json(json!({
+                "error": "Invalid token subject"
+            })),
+        )
+    })
+}
+
+fn database_error(e: sqlx::Error) -> status::Custom<Json<Value>> {
+    eprintln!("Database error: {}", e);
+    status::Custom(
+        Status::InternalServerError,
+        Json(json!({
+            "error": "Database error occurred"
+        })),
+    )
+}
@@ -0,0 +1,16 @@
+use std::sync::Arc;
+
+use rocket::serde::json::{Json, Value, json};
+use rocket::State;
+
+use crate::email::memory::MemoryEmailSender;
+
+/// List emails captured by the memory transport, newest first
+///
+/// Only mounted when `ROCKET_EMAIL_TRANSPORT=memory`; never enable that in production.
+#[get("/mailbox")]
+pub fn mailbox(mailbox: &State<Arc<MemoryEmailSender>>) -> Json<Value> {
+    let mut messages = mailbox.messages();
+    messages.reverse();
+    Json(json!({ "messages": messages }))
+}
@@ -0,0 +1,251 @@
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::{Json, Value, json};
+use rocket::State;
+use rocket_db_pools::Connection;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::auth::guard::AdminUser;
+use crate::body_limits::JsonBody;
+use crate::config::{AppConfig, EmailTransport, SmtpConfig};
+use crate::email::sender::Mailer;
+use crate::email::settings::{self as email_settings, EmailSettingsUpdate, SETTINGS_KEY};
+use crate::email::templates;
+use crate::maintenance::WriteAccess;
+use crate::repositories::users;
+use crate::settings::Settings;
+use crate::Postgres;
+
+/// Body for sending a test email
+#[derive(Debug, Deserialize)]
+pub struct TestEmailRequest {
+    /// Recipient; the admin's own address by default
+    pub to: Option<String>,
+}
+
+/// The mail settings in effect: stored by an admin, or from the configuration
+///
+/// The SMTP password is never returned, only whether one is set.
+#[get("/email/settings")]
+pub async fn get_email_settings(
+    _admin: AdminUser,
+    config: &State<AppConfig>,
+    settings: &State<Settings>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    ensure_enabled(settings)?;
+
+    let body = match email_settings::load(settings) {
+        Some((stored, setting)) => json!({
+            "source": "stored",
+            "transport": "smtp",
+            "smtp": smtp_view(&stored.smtp),
+            "from": stored.from,
+            "updated_by": setting.updated_by,
+            "updated_at": setting.updated_at
+        }),
+        None => json!({
+            "source": "configured",
+            "transport": transport_name(config.email_transport),
+            "smtp": config.smtp.as_ref().map(smtp_view),
+            "from": config.email_from
+        }),
+    };
+    Ok(status::Custom(Status::Ok, Json(body)))
+}
+
+/// Store SMTP settings and start sending through them
+///
+/// They replace `ROCKET_EMAIL_TRANSPORT` until deleted, on every instance.
+/// Leaving out `password` keeps the stored one.
+#[put("/email/settings", data = "<update>")]
+pub async fn update_email_settings(
+    _write: WriteAccess,
+    admin: AdminUser,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    mailer: &State<Mailer>,
+    settings: &State<Settings>,
+    update: JsonBody<EmailSettingsUpdate>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    ensure_enabled(settings)?;
+    let admin_id = parse_admin_id(&admin)?;
+    let current = email_settings::load(settings);
+    let stored = update
+        .into_inner()
+        .into_settings(current.as_ref().map(|(current, _)| current))
+        .map_err(|message| {
+            status::Custom(
+                Status::BadRequest,
+                Json(json!({
+                    "error": "Invalid email settings",
+                    "details": message
+                })),
+            )
+        })?;
+
+    let value = serde_json::to_value(&stored).expect("email settings serialize to JSON");
+    let setting = settings
+        .store(&mut db, SETTINGS_KEY, &value, admin_id)
+        .await
+        .map_err(database_error)?;
+    email_settings::apply(config, mailer, Some(&stored));
+    println!("✓ Email settings updated by admin {}: {}:{}", admin_id, stored.smtp.host, stored.smtp.port);
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "source": "stored",
+            "transport": "smtp",
+            "smtp": smtp_view(&stored.smtp),
+            "from": stored.from,
+            "updated_by": setting.updated_by,
+            "updated_at": setting.updated_at
+        })),
+    ))
+}
+
+/// Drop the stored settings and go back to the configured transport
+#[delete("/email/settings")]
+pub async fn delete_email_settings(
+    _write: WriteAccess,
+    admin: AdminUser,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    mailer: &State<Mailer>,
+    settings: &State<Settings>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    ensure_enabled(settings)?;
+    if !settings.clear(&mut db, SETTINGS_KEY).await.map_err(database_error)? {
+        return Err(status::Custom(
+            Status::NotFound,
+            Json(json!({
+                "error": "No email settings are stored"
+            })),
+        ));
+    }
+    email_settings::apply(config, mailer, None);
+    println!("✓ Email settings reset to the configuration by admin {}", admin.user_id);
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "Email settings removed; using the configured transport"
+        })),
+    ))
+}
+
+/// Send a test email through the settings in effect
+///
+/// Answers `502` with the transport's error when it can't be sent, so
+/// operators see what the relay said.
+#[post("/email/test", data = "<request>")]
+pub async fn send_test_email(
+    admin: AdminUser,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    mailer: &State<Mailer>,
+    settings: &State<Settings>,
+    request: JsonBody<TestEmailRequest>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let to = match request.into_inner().to {
+        Some(to) if to.contains('@') => to,
+        Some(_) => {
+            return Err(status::Custom(
+                Status::BadRequest,
+                Json(json!({
+                    "error": "Invalid email format"
+                })),
+            ));
+        }
+        None => {
+            let admin_id = parse_admin_id(&admin)?;
+            match users::find_by_id(&mut db, admin_id).await.map_err(database_error)? {
+                Some(user) => user.email,
+                None => return Err(invalid_subject()),
+            }
+        }
+    };
+
+    let transport = match email_settings::load(settings) {
+        Some((stored, _)) if config.email_transport != EmailTransport::Memory => {
+            format!("{}:{}", stored.smtp.host, stored.smtp.port)
+        }
+        _ => match (config.email_transport, &config.smtp) {
+            (EmailTransport::Smtp, Some(smtp)) => format!("{}:{}", smtp.host, smtp.port),
+            (transport, _) => format!("the {} transport", transport_name(transport)),
+        },
+    };
+
+    if let Err(e) = mailer.send(templates::test_email(&to, &transport)).await {
+        return Err(status::Custom(
+            Status::BadGateway,
+            Json(json!({
+                "error": "Failed to send test email",
+                "details": e.to_string()
+            })),
+        ));
+    }
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "Test email sent",
+            "to": to,
+            "from": mailer.from_address()
+        })),
+    ))
+}
+
+fn smtp_view(smtp: &SmtpConfig) -> Value {
+    json!({
+        "host": smtp.host,
+        "port": smtp.port,
+        "username": smtp.username,
+        "password_set": smtp.password.is_some(),
+        "security": smtp.security
+    })
+}
+
+fn transport_name(transport: EmailTransport) -> &'static str {
+    match transport {
+        EmailTransport::Log => "log",
+        EmailTransport::Memory => "memory",
+        EmailTransport::Smtp => "smtp",
+    }
+}
+
+fn ensure_enabled(settings: &Settings) -> Result<(), status::Custom<Json<Value>>> {
+    if settings.is_enabled() {
+        return Ok(());
+    }
+    Err(status::Custom(
+        Status::NotFound,
+        Json(json!({
+            "error": "Runtime settings are disabled"
+        })),
+    ))
+}
+
+fn parse_admin_id(admin: &AdminUser) -> Result<Uuid, status::Custom<Json<Value>>> {
+    Uuid::parse_str(&admin.user_id).map_err(|_| invalid_subject())
+}
+
+fn invalid_subject() -> status::Custom<Json<Value>> {
+    status::Custom(
+        Status::Unauthorized,
+        Json(json!({
+            "error": "Invalid token subject"
+        })),
+    )
+}
+
+fn database_error(e: sqlx::Error) -> status::Custom<Json<Value>> {
+    eprintln!("Database error: {}", e);
+    status::Custom(
+        Status::InternalServerError,
+        Json(json!({
+            "error": "Database error occurred"
+        })),
+    )
+}
@@ -0,0 +1,247 @@
+use chrono::{Duration, Utc};
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::{Json, Value, json};
+use rocket::State;
+use rocket_db_pools::Connection;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::auth::email_tokens::{self, EmailTokenPurpose};
+use crate::auth::guard::RegisteredUser;
+use crate::authz::Owns;
+use crate::body_limits::JsonBody;
+use crate::config::AppConfig;
+use crate::email::sender::Mailer;
+use crate::email::templates;
+use crate::links::{self, LinkAction};
+use crate::maintenance::WriteAccess;
+use crate::models::user::User;
+use crate::models::user_email::{NewUserEmail, UserEmail};
+use crate::repositories::{user_emails, users};
+use crate::Postgres;
+
+/// Secondary addresses a user may have besides the primary one
+const MAX_SECONDARY_EMAILS: i64 = 5;
+
+/// List the current user's addresses, primary first
+#[get("/me/emails")]
+pub async fn list_emails(
+    user: RegisteredUser,
+    mut db: Connection<Postgres>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let user = current_user(&mut db, &user).await?;
+    let emails = email_list(&mut db, &user).await?;
+
+    Ok(status::Custom(Status::Ok, Json(json!({ "emails": emails }))))
+}
+
+/// Add a secondary address and email it a verification link
+///
+/// The address can't be used for recovery or promoted until it's verified.
+#[post("/me/emails", data = "<new_email>")]
+pub async fn add_email(
+    _write: WriteAccess,
+    user: RegisteredUser,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    mailer: &State<Mailer>,
+    new_email: JsonBody<NewUserEmail>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let user = current_user(&mut db, &user).await?;
+    let email = new_email.email.trim();
+
+    // Validate email format (basic validation)
+    if !email.contains('@') {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "Invalid email format"
+            })),
+        ));
+    }
+
+    let in_use = match users::email_exists(&mut db, email).await {
+        Ok(true) => true,
+        Ok(false) => user_emails::exists_for_user(&mut db, user.id, email)
+            .await
+            .map_err(database_error)?,
+        Err(e) => return Err(database_error(e)),
+    };
+    if in_use {
+        return Err(status::Custom(
+            Status::Conflict,
+            Json(json!({
+                "error": "Email already in use"
+            })),
+        ));
+    }
+
+    let count = user_emails::count_for_user(&mut db, user.id).await.map_err(database_error)?;
+    if count >= MAX_SECONDARY_EMAILS {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": format!("You can add at most {} email addresses", MAX_SECONDARY_EMAILS)
+            })),
+        ));
+    }
+
+    let address = user_emails::create(&mut db, user.id, email).await.map_err(database_error)?;
+    send_address_verification_email(&mut db, config, mailer, &address).await;
+
+    Ok(status::Custom(Status::Created, Json(secondary_json(&address))))
+}
+
+/// Remove a secondary address
+#[delete("/me/emails/<_id>")]
+pub async fn remove_email(
+    _write: WriteAccess,
+    address: Owns<UserEmail>,
+    mut db: Connection<Postgres>,
+    _id: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    user_emails::delete(&mut db, address.user_id, address.id).await.map_err(database_error)?;
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "Email address removed"
+        })),
+    ))
+}
+
+/// Make a verified secondary address the primary one (used to log in)
+///
+/// The previous primary address stays on the account as a secondary address.
+#[post("/me/emails/<id>/primary")]
+pub async fn promote_email(
+    _write: WriteAccess,
+    user: RegisteredUser,
+    mut db: Connection<Postgres>,
+    id: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let user = current_user(&mut db, &user).await?;
+    let id = Uuid::parse_str(id).map_err(|_| not_found())?;
+
+    let address = match user_emails::find(&mut db, user.id, id).await.map_err(database_error)? {
+        Some(address) => address,
+        None => return Err(not_found()),
+    };
+    if address.verified_at.is_none() {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "Verify this email address before making it primary"
+            })),
+        ));
+    }
+
+    let user = match user_emails::promote(&mut db, user.id, id).await.map_err(database_error)? {
+        Some(user) => user,
+        None => return Err(not_found()),
+    };
+    let emails = email_list(&mut db, &user).await?;
+
+    Ok(status::Custom(Status::Ok, Json(json!({ "emails": emails }))))
+}
+
+/// Issue a verification token for a secondary address and email the link to it
+async fn send_address_verification_email(
+    conn: &mut PgConnection,
+    config: &AppConfig,
+    mailer: &Mailer,
+    address: &UserEmail,
+) {
+    let expires_at = Utc::now() + Duration::hours(24); // Token expires in 24 hours
+    let issued = email_tokens::issue(
+        conn,
+        config,
+        EmailTokenPurpose::VerifyEmail,
+        address.user_id,
+        Some(address.id),
+        expires_at,
+    )
+    .await;
+
+    let token = match issued {
+        Ok(token) => token,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return;
+        }
+    };
+
+    let link = links::email_link(config, LinkAction::VerifyEmail, &token);
+    if let Err(e) = mailer.send(templates::email_verification(&address.email, &link)).await {
+        eprintln!("{}", e);
+    }
+}
+
+/// Load the signed-in user
+async fn current_user(conn: &mut PgConnection, user: &RegisteredUser) -> Result<User, status::Custom<Json<Value>>> {
+    let user_id = match Uuid::parse_str(&user.user_id) {
+        Ok(id) => id,
+        Err(_) => {
+            return Err(status::Custom(
+                Status::Unauthorized,
+                Json(json!({
+                    "error": "Invalid token subject"
+                })),
+            ));
+        }
+    };
+
+    match users::find_by_id(conn, user_id).await {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err(status::Custom(
+            Status::NotFound,
+            Json(json!({
+                "error": "User not found"
+            })),
+        )),
+        Err(e) => Err(database_error(e)),
+    }
+}
+
+/// The primary address followed by the secondary ones
+async fn email_list(conn: &mut PgConnection, user: &User) -> Result<Vec<Value>, status::Custom<Json<Value>>> {
+    let secondary = user_emails::list_for_user(conn, user.id).await.map_err(database_error)?;
+
+    let mut emails = vec![json!({
+        "id": null,
+        "email": user.email,
+        "verified": user.email_verified_at.is_some(),
+        "primary": true
+    })];
+    emails.extend(secondary.iter().map(secondary_json));
+    Ok(emails)
+}
+
+fn secondary_json(address: &UserEmail) -> Value {
+    json!({
+        "id": address.id.to_string(),
+        "email": address.email,
+        "verified": address.verified_at.is_some(),
+        "primary": false
+    })
+}
+
+fn not_found() -> status::Custom<Json<Value>> {
+    status::Custom(
+        Status::NotFound,
+        Json(json!({
+            "error": "Email address not found"
+        })),
+    )
+}
+
+fn database_error(e: sqlx::Error) -> status::Custom<Json<Value>> {
+    eprintln!("Database error: {}", e);
+    status::Custom(
+        Status::InternalServerError,
+        Json(json!({
+            "error": "Database error occurred"
+        })),
+    )
+}
@@ -0,0 +1,221 @@
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::{Json, Value, json};
+use rocket::State;
+use rocket_db_pools::Connection;
+use std::net::IpAddr;
+
+use crate::auth::access_tokens::AccessTokens;
+use crate::auth::guard::AuthenticatedUser;
+use crate::auth::jwt::JwtService;
+use crate::auth::password::PasswordHasher;
+use crate::body_limits::JsonBody;
+use crate::config::{AppConfig, RegistrationMode};
+use crate::email::sender::Mailer;
+use crate::events::{self, SecurityEventKind};
+use crate::maintenance::WriteAccess;
+use crate::models::session::SessionDevice;
+use crate::models::user::NewUser;
+use crate::repositories::{sessions, users};
+use crate::routes::auth::{send_verification_email, start_session, validate_credentials};
+use crate::settings::Settings;
+use crate::Postgres;
+
+/// Create an anonymous guest account and sign it in
+///
+/// Only available when registration is open without approval. Guests get a
+/// placeholder email and no usable password; they can't log in again once
+/// their token is lost, so clients should upgrade them before that.
+#[post("/guest")]
+#[allow(clippy::too_many_arguments)]
+pub async fn create_guest(
+    _write: WriteAccess,
+    mut db: Connection<Postgres>,
+    jwt: &State<JwtService>,
+    access_tokens: &State<AccessTokens>,
+    settings: &State<Settings>,
+    passwords: &State<PasswordHasher>,
+    ip: Option<IpAddr>,
+    device: SessionDevice,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let config: &AppConfig = &settings.current();
+    // A guest is a signup without credentials; only allow it when anyone may register
+    if config.registration_mode != RegistrationMode::Open || config.signup_approval {
+        return Err(status::Custom(
+            Status::Forbidden,
+            Json(json!({
+                "error": "Guest accounts are not available",
+                "code": "registration_restricted"
+            })),
+        ));
+    }
+
+    let email = format!("guest-{}@guest.invalid", uuid::Uuid::new_v4());
+
+    // Random password nobody knows, so password login never succeeds
+    let password_hash = match passwords.hash(&uuid::Uuid::new_v4().to_string()).await {
+        Ok(hash) => hash,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Err(status::Custom(
+                e.status(),
+                Json(json!({
+                    "error": "Failed to hash password"
+                })),
+            ));
+        }
+    };
+
+    let user = match users::create(&mut db, &email, &password_hash, "guest").await {
+        Ok(user) => user,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Failed to create guest account"
+                })),
+            ));
+        }
+    };
+
+    let token = start_session(&mut db, jwt, access_tokens, &user, ip, &device).await?;
+
+    Ok(status::Custom(
+        Status::Created,
+        Json(json!({
+            "message": "Guest account created",
+            "token": token,
+            "user": {
+                "id": user.id.to_string(),
+                "guest": true,
+                "created_at": user.created_at.to_rfc3339()
+            }
+        })),
+    ))
+}
+
+/// Turn the current guest account into a regular account with email and password
+///
+/// The user id (and everything attached to it) is kept. Guest sessions are
+/// revoked and a new token for a regular session is returned.
+#[post("/guest/upgrade", data = "<credentials>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn upgrade_guest(
+    _write: WriteAccess,
+    user: AuthenticatedUser,
+    mut db: Connection<Postgres>,
+    jwt: &State<JwtService>,
+    access_tokens: &State<AccessTokens>,
+    config: &State<AppConfig>,
+    mailer: &State<Mailer>,
+    passwords: &State<PasswordHasher>,
+    ip: Option<IpAddr>,
+    device: SessionDevice,
+    credentials: JsonBody<NewUser>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    if !user.guest {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "Account is not a guest account"
+            })),
+        ));
+    }
+
+    let user_id = match uuid::Uuid::parse_str(&user.user_id) {
+        Ok(id) => id,
+        Err(_) => {
+            return Err(status::Custom(
+                Status::Unauthorized,
+                Json(json!({
+                    "error": "Invalid token subject"
+                })),
+            ));
+        }
+    };
+
+    validate_credentials(&credentials)?;
+
+    match users::email_exists(&mut db, &credentials.email).await {
+        Ok(true) => {
+            return Err(status::Custom(
+                Status::Conflict,
+                Json(json!({
+                    "error": "User with this email already exists"
+                })),
+            ));
+        }
+        Ok(false) => {}
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ));
+        }
+    }
+
+    let password_hash = match passwords.hash(&credentials.password).await {
+        Ok(hash) => hash,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Err(status::Custom(
+                e.status(),
+                Json(json!({
+                    "error": "Failed to hash password"
+                })),
+            ));
+        }
+    };
+
+    let upgraded = match users::upgrade_guest(&mut db, user_id, &credentials.email, &password_hash).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return Err(status::Custom(
+                Status::BadRequest,
+                Json(json!({
+                    "error": "Account is not a guest account"
+                })),
+            ));
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Failed to upgrade account"
+                })),
+            ));
+        }
+    };
+
+    // Guest tokens carry the guest claim; replace them with a regular session
+    match sessions::revoke_all_for_user(&mut db, upgraded.id).await {
+        Ok(count) => {
+            events::emit(&mut db, upgraded.id, SecurityEventKind::AllSessionsRevoked { count }).await;
+        }
+        Err(e) => eprintln!("Database error: {}", e),
+    }
+    access_tokens.forget_user(upgraded.id);
+    let token = start_session(&mut db, jwt, access_tokens, &upgraded, ip, &device).await?;
+
+    send_verification_email(&mut db, config, mailer, &upgraded, None).await;
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "Account upgraded successfully",
+            "token": token,
+            "user": {
+                "id": upgraded.id.to_string(),
+                "email": upgraded.email,
+                "email_verified": false,
+                "guest": false,
+                "created_at": upgraded.created_at.to_rfc3339()
+            }
+        })),
+    ))
+}
@@ -0,0 +1,45 @@
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::{Json, Value, json};
+use rocket::State;
+use rocket_db_pools::Pool as _;
+
+use crate::Postgres;
+
+/// Readiness probe for load balancers and orchestrators
+///
+/// 200 when a database connection can be had and answers, 503 otherwise.
+/// Reports the database circuit breaker too; while it's open the database
+/// isn't tried, so an outage doesn't make probes wait out the acquire timeout.
+#[get("/ready")]
+pub async fn ready(db: &State<Postgres>) -> status::Custom<Json<Value>> {
+    let breaker = db.0.breaker();
+    let checked = match db.0.get().await {
+        Ok(mut conn) => sqlx::query("SELECT 1").execute(&mut *conn).await.map(|_| ()).map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+
+    let mut database = json!({
+        "status": if checked.is_ok() { "up" } else { "down" },
+        "circuit_breaker": {
+            "state": breaker.state(),
+            "consecutive_failures": breaker.consecutive_failures()
+        }
+    });
+    let status = match checked {
+        Ok(()) => Status::Ok,
+        Err(error) => {
+            database["error"] = json!(error);
+            Status::ServiceUnavailable
+        }
+    };
+    let ready = if status == Status::Ok { "ready" } else { "unavailable" };
+
+    status::Custom(
+        status,
+        Json(json!({
+            "status": ready,
+            "database": database
+        })),
+    )
+}
@@ -0,0 +1,28 @@
+use rocket::response::Redirect;
+use rocket::State;
+
+use crate::config::{AppConfig, EmailLinkStyle};
+use crate::links::{self, LinkAction, Platform};
+
+/// Bounce an emailed link to the mobile app on phones, or to the web everywhere else
+///
+/// Used by `ROCKET_EMAIL_LINKS=universal`; with other styles every client goes to the web.
+#[get("/l/<action>?<token>&<redirect_to>")]
+pub fn open_link(
+    action: &str,
+    token: &str,
+    redirect_to: Option<&str>,
+    platform: Platform,
+    config: &State<AppConfig>,
+) -> Option<Redirect> {
+    let action = LinkAction::from_path(action)?;
+
+    let target = match &config.email_links {
+        EmailLinkStyle::Universal { scheme } if platform.is_mobile() => {
+            links::app_link(scheme, action, token)
+        }
+        _ => links::web_link(config, action, token),
+    };
+
+    Some(Redirect::to(links::with_redirect(target, redirect_to)))
+}
@@ -0,0 +1,557 @@
+use chrono::{DateTime, Duration, Utc};
+use rocket::http::{ContentType, Header, Status};
+use rocket::response::status;
+use rocket::serde::json::{Json, Value, json};
+use rocket::State;
+use rocket_db_pools::Connection;
+use sqlx::PgConnection;
+use std::net::IpAddr;
+use uuid::Uuid;
+
+use crate::alerts;
+use crate::auth::access_tokens::AccessTokens;
+use crate::auth::guard::{AuthenticatedUser, MfaEnrollmentUser};
+use crate::auth::jwt::JwtService;
+use crate::auth::{mfa, tokens};
+use crate::body_limits::JsonBody;
+use crate::config::AppConfig;
+use crate::email::sender::Mailer;
+use crate::email::templates;
+use crate::events::{self, SecurityEventKind};
+use crate::links::{self, LinkAction};
+use crate::maintenance::WriteAccess;
+use crate::risk::GeoCountry;
+use crate::rate_limit::{LoginDelay, LoginLockout, RateLimited, StuffingDetector};
+use crate::models::mfa::{MfaLogin, MfaRecoveryRequest, TotpCode};
+use crate::models::session::SessionDevice;
+use crate::models::user::{ConfirmAction, User};
+use crate::repositories::{mfa as mfa_repo, users};
+use crate::routes::auth::{
+    check_ip_block, check_lockout, disabled_refusal, record_login, record_login_attempt, start_session, wait_for_login_delay,
+};
+use crate::settings::Settings;
+use crate::Postgres;
+
+/// Second login step for users with MFA: trade the `mfa_token` from `/login` and a code for a session
+///
+/// A token takes a few wrong codes before it's dropped and the password
+/// step has to be repeated. Wrong codes count towards the login lockout.
+#[post("/login/mfa", data = "<login>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn login_mfa(
+    mut db: Connection<Postgres>,
+    jwt: &State<JwtService>,
+    access_tokens: &State<AccessTokens>,
+    lockout: &State<LoginLockout>,
+    delay: &State<LoginDelay>,
+    stuffing: &State<StuffingDetector>,
+    config: &State<AppConfig>,
+    mailer: &State<Mailer>,
+    country: GeoCountry<'_>,
+    ip: Option<IpAddr>,
+    device: SessionDevice,
+    login: JsonBody<MfaLogin>,
+) -> Result<status::Custom<Json<Value>>, RateLimited<status::Custom<Json<Value>>>> {
+    check_ip_block(&mut db, stuffing, ip).await?;
+    let challenge = tokens::hash(&login.mfa_token);
+    let pending = mfa_repo::find_challenge(&mut db, &challenge)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(invalid_challenge)?;
+    let user = users::find_by_id(&mut db, pending.user_id)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(invalid_challenge)?;
+    check_lockout(&mut db, lockout, &user.email).await?;
+    wait_for_login_delay(&mut db, delay, &user.email, ip).await?;
+    if user.disabled_at.is_some() {
+        return Err(disabled_refusal().into());
+    }
+
+    let accepted = match &pending.email_code_hash {
+        Some(code_hash) => tokens::hash(login.code.trim()) == *code_hash,
+        None => accept_code(&mut db, user.id, &login.code).await?,
+    };
+    if !accepted {
+        mfa_repo::fail_challenge(&mut db, &challenge, mfa::MAX_CHALLENGE_ATTEMPTS)
+            .await
+            .map_err(database_error)?;
+        record_login_attempt(&mut db, Some(user.id), &user.email, ip, false).await;
+        return Err(invalid_code().into());
+    }
+    // A concurrent request with the same token may have won
+    if !mfa_repo::delete_challenge(&mut db, &challenge).await.map_err(database_error)? {
+        return Err(invalid_challenge().into());
+    }
+
+    // Whoever asked to recover MFA evidently didn't need to
+    if mfa_repo::cancel_recoveries(&mut db, user.id).await.map_err(database_error)? > 0 {
+        events::emit(&mut db, user.id, SecurityEventKind::MfaRecoveryCancelled).await;
+    }
+
+    record_login(&mut db, access_tokens.telemetry(), user.id, &user.email, ip).await;
+    alerts::admin_login(&mut db, config, mailer, &user, country.0, ip).await;
+    let token = start_session(&mut db, jwt, access_tokens, &user, ip, &device).await?;
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "Login successful",
+            "token": token,
+            "user": {
+                "id": user.id.to_string(),
+                "email": user.email,
+                "must_change_password": user.must_change_password,
+                "created_at": user.created_at.to_rfc3339()
+            }
+        })),
+    ))
+}
+
+/// Whether the current user has MFA, and whether and by when they must enroll
+#[get("/mfa")]
+pub async fn mfa_status(
+    user: MfaEnrollmentUser,
+    mut db: Connection<Postgres>,
+    settings: &State<Settings>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let config: &AppConfig = &settings.current();
+    let user = find_user(&mut db, &user.user_id).await?;
+    let recovery = mfa_repo::find_scheduled_recovery(&mut db, user.id)
+        .await
+        .map_err(database_error)?;
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "enabled": user.totp_enabled_at.is_some(),
+            "required": mfa::required_for(config, &user.role),
+            "enrollment_deadline": enrollment_deadline(config, &user).map(|deadline| deadline.to_rfc3339()),
+            "recovery_available_at": recovery.and_then(|recovery| recovery.available_at).map(|at| at.to_rfc3339())
+        })),
+    ))
+}
+
+/// Start TOTP enrollment with a new secret
+///
+/// Returns the secret and its `otpauth://` URI for the authenticator app.
+/// MFA is only enabled once `/mfa/totp/confirm` receives a code from it;
+/// starting again replaces an unconfirmed secret.
+#[post("/mfa/totp/enroll")]
+pub async fn enroll_totp(
+    _write: WriteAccess,
+    user: MfaEnrollmentUser,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    if user.guest {
+        return Err(error(Status::Forbidden, "Guest accounts can't enroll in MFA"));
+    }
+    let user = find_user(&mut db, &user.user_id).await?;
+
+    let secret = mfa::generate_secret();
+    if !mfa_repo::start_totp_enrollment(&mut db, user.id, &secret)
+        .await
+        .map_err(database_error)?
+    {
+        return Err(error(Status::Conflict, "MFA is already enabled"));
+    }
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "Add the secret to your authenticator app, then confirm with a code from it",
+            "secret": secret,
+            "otpauth_uri": mfa::provisioning_uri(&config.mfa_issuer, &user.email, &secret)
+        })),
+    ))
+}
+
+/// A rendered QR code, kept out of caches since it holds the TOTP secret
+#[derive(Responder)]
+pub struct QrImage {
+    image: (ContentType, Vec<u8>),
+    cache_control: Header<'static>,
+}
+
+/// The pending enrollment's `otpauth://` URI as a QR code, for the authenticator app to scan
+///
+/// SVG by default, PNG with `?format=png`. Only served until enrollment is
+/// confirmed.
+#[get("/mfa/totp/qr?<format>")]
+pub async fn totp_qr(
+    user: MfaEnrollmentUser,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    format: Option<&str>,
+) -> Result<QrImage, status::Custom<Json<Value>>> {
+    let user = find_user(&mut db, &user.user_id).await?;
+    let enrollment = mfa_repo::find_totp(&mut db, user.id)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(|| error(Status::NotFound, "Start enrollment at /mfa/totp/enroll first"))?;
+    if enrollment.enabled_at.is_some() {
+        return Err(error(Status::Conflict, "MFA is already enabled"));
+    }
+
+    let uri = mfa::provisioning_uri(&config.mfa_issuer, &user.email, &enrollment.secret);
+    let image = match format.unwrap_or("svg") {
+        "svg" => mfa::qr_svg(&uri)
+            .map(|svg| (ContentType::SVG, svg.into_bytes()))
+            .map_err(|e| e.to_string()),
+        "png" => mfa::qr_png(&uri).map(|png| (ContentType::PNG, png)),
+        _ => return Err(error(Status::BadRequest, "format must be svg or png")),
+    };
+    let image = image.map_err(|e| {
+        eprintln!("Failed to render QR code: {}", e);
+        error(Status::InternalServerError, "Failed to render QR code")
+    })?;
+
+    Ok(QrImage {
+        image,
+        cache_control: Header::new("Cache-Control", "no-store"),
+    })
+}
+
+/// Finish TOTP enrollment with a code from the authenticator app
+#[post("/mfa/totp/confirm", data = "<code>")]
+pub async fn confirm_totp(
+    _write: WriteAccess,
+    user: MfaEnrollmentUser,
+    mut db: Connection<Postgres>,
+    code: JsonBody<TotpCode>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let user_id = parse_user_id(&user.user_id)?;
+    let enrollment = mfa_repo::find_totp(&mut db, user_id)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(|| error(Status::BadRequest, "Start enrollment at /mfa/totp/enroll first"))?;
+    if enrollment.enabled_at.is_some() {
+        return Err(error(Status::Conflict, "MFA is already enabled"));
+    }
+
+    if !accept_code(&mut db, user_id, &code.code).await? {
+        return Err(invalid_code());
+    }
+    events::emit(&mut db, user_id, SecurityEventKind::MfaEnabled).await;
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "MFA enabled. Logins now need a code from your authenticator app."
+        })),
+    ))
+}
+
+/// Turn TOTP off, confirmed with a current code
+///
+/// Refused while MFA is required for the user's role.
+#[delete("/mfa/totp", data = "<code>")]
+pub async fn disable_totp(
+    _write: WriteAccess,
+    user: AuthenticatedUser,
+    mut db: Connection<Postgres>,
+    settings: &State<Settings>,
+    code: JsonBody<TotpCode>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let config: &AppConfig = &settings.current();
+    let user = find_user(&mut db, &user.user_id).await?;
+    if user.totp_enabled_at.is_none() {
+        return Err(error(Status::NotFound, "MFA isn't enabled"));
+    }
+    if mfa::required_for(config, &user.role) {
+        return Err(error(Status::Forbidden, "MFA is required for your account"));
+    }
+
+    if !accept_code(&mut db, user.id, &code.code).await? {
+        return Err(invalid_code());
+    }
+    mfa_repo::remove_totp(&mut db, user.id).await.map_err(database_error)?;
+    events::emit(&mut db, user.id, SecurityEventKind::MfaDisabled).await;
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "MFA disabled"
+        })),
+    ))
+}
+
+/// Ask to remove MFA after losing the authenticator, with the `mfa_token` from a password login
+///
+/// Emails a confirmation link to the account's address. Once it's opened, MFA
+/// is removed at the first password login after `ROCKET_MFA_RECOVERY_DELAY_HOURS`;
+/// logging in with a code before then cancels the recovery.
+#[post("/login/mfa/recover", data = "<request>")]
+pub async fn request_recovery(
+    _write: WriteAccess,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    mailer: &State<Mailer>,
+    request: JsonBody<MfaRecoveryRequest>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    // Only challenges answered from an authenticator can be recovered from
+    let user_id = mfa_repo::find_challenge(&mut db, &tokens::hash(&request.mfa_token))
+        .await
+        .map_err(database_error)?
+        .filter(|pending| pending.email_code_hash.is_none())
+        .ok_or_else(invalid_challenge)?
+        .user_id;
+    let user = users::find_by_id(&mut db, user_id)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(invalid_challenge)?;
+
+    if let Some(recovery) = mfa_repo::find_scheduled_recovery(&mut db, user.id)
+        .await
+        .map_err(database_error)?
+    {
+        return Err(status::Custom(
+            Status::Conflict,
+            Json(json!({
+                "error": "MFA recovery is already scheduled",
+                "available_at": recovery.available_at.map(|at| at.to_rfc3339())
+            })),
+        ));
+    }
+
+    let (token, hash) = mfa::generate_recovery_token();
+    let link_expires_at = Utc::now() + Duration::minutes(mfa::RECOVERY_LINK_TTL_MINUTES);
+    let recovery = mfa_repo::create_recovery(&mut db, user.id, &hash, link_expires_at)
+        .await
+        .map_err(database_error)?;
+
+    let link = links::email_link(config, LinkAction::ConfirmMfaRecovery, &token);
+    let message = templates::mfa_recovery_confirmation(&user.email, &link, config.mfa_recovery_delay_hours);
+    if let Err(e) = mailer.send(message).await {
+        eprintln!("{}", e);
+    }
+    events::emit(&mut db, user.id, SecurityEventKind::MfaRecoveryRequested { recovery_id: recovery.id }).await;
+
+    Ok(status::Custom(
+        Status::Accepted,
+        Json(json!({
+            "message": "Check your email for a confirmation link"
+        })),
+    ))
+}
+
+/// Confirm an MFA recovery with the token from the emailed link, starting its delay
+#[post("/mfa/recovery/confirm", data = "<confirm>")]
+pub async fn confirm_recovery(
+    _write: WriteAccess,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    mailer: &State<Mailer>,
+    confirm: JsonBody<ConfirmAction>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let recovery = mfa_repo::confirm_recovery(&mut db, &tokens::hash(&confirm.token), config.mfa_recovery_delay_hours)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(|| {
+            status::Custom(
+                Status::BadRequest,
+                Json(json!({
+                    "error": "Invalid or expired confirmation link",
+                    "code": "token_invalid"
+                })),
+            )
+        })?;
+    let available_at = recovery.available_at.unwrap_or_else(Utc::now);
+
+    if let Some(user) = users::find_by_id(&mut db, recovery.user_id).await.map_err(database_error)? {
+        let message = templates::mfa_recovery_scheduled(&user.email, &templates::local_time(available_at, user.timezone.as_deref()));
+        if let Err(e) = mailer.send(message).await {
+            eprintln!("{}", e);
+        }
+    }
+    events::emit(
+        &mut db,
+        recovery.user_id,
+        SecurityEventKind::MfaRecoveryScheduled {
+            recovery_id: recovery.id,
+            available_at,
+        },
+    )
+    .await;
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "MFA will be removed at your first login after available_at",
+            "available_at": available_at.to_rfc3339()
+        })),
+    ))
+}
+
+/// Cancel a pending MFA recovery
+#[delete("/mfa/recovery")]
+pub async fn cancel_recovery(
+    _write: WriteAccess,
+    user: AuthenticatedUser,
+    mut db: Connection<Postgres>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let user_id = parse_user_id(&user.user_id)?;
+    if mfa_repo::cancel_recoveries(&mut db, user_id).await.map_err(database_error)? == 0 {
+        return Err(error(Status::NotFound, "No MFA recovery is pending"));
+    }
+    events::emit(&mut db, user_id, SecurityEventKind::MfaRecoveryCancelled).await;
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "MFA recovery cancelled"
+        })),
+    ))
+}
+
+/// When a user who has to use MFA but hasn't enrolled must do so; `None` otherwise
+pub(crate) fn enrollment_deadline(config: &AppConfig, user: &User) -> Option<DateTime<Utc>> {
+    if user.totp_enabled_at.is_some() || !mfa::required_for(config, &user.role) {
+        return None;
+    }
+    // Users who haven't logged in since MFA became required are due a full grace period from now
+    let required_since = user.mfa_required_since.unwrap_or_else(Utc::now);
+    Some(mfa::enrollment_deadline(config, required_since))
+}
+
+/// Start the grace period of a user who has to use MFA, on their first login since it became required
+pub(crate) async fn start_grace_period(conn: &mut PgConnection, config: &AppConfig, user: &mut User) {
+    if user.mfa_required_since.is_some() || enrollment_deadline(config, user).is_none() {
+        return;
+    }
+    match mfa_repo::mark_required(conn, user.id).await {
+        Ok(required_since) => user.mfa_required_since = required_since,
+        Err(e) => eprintln!("Database error: {}", e),
+    }
+}
+
+/// Remove the user's MFA if a confirmed recovery is due, returning whether it was removed
+pub(crate) async fn complete_due_recovery(
+    conn: &mut PgConnection,
+    mailer: &Mailer,
+    user: &mut User,
+) -> Result<bool, status::Custom<Json<Value>>> {
+    let Some(recovery) = mfa_repo::complete_due_recovery(conn, user.id).await.map_err(database_error)? else {
+        return Ok(false);
+    };
+    user.totp_enabled_at = None;
+
+    if let Err(e) = mailer.send(templates::mfa_removed(&user.email)).await {
+        eprintln!("{}", e);
+    }
+    events::emit(
+        conn,
+        user.id,
+        SecurityEventKind::MfaReset {
+            recovery_id: recovery.id,
+            admin_id: None,
+        },
+    )
+    .await;
+    Ok(true)
+}
+
+/// Start the second login step for a user with MFA
+pub(crate) async fn challenge(conn: &mut PgConnection, user: &User) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let (token, hash) = mfa::generate_challenge();
+    let expires_at = Utc::now() + Duration::seconds(mfa::CHALLENGE_TTL_SECONDS);
+    mfa_repo::create_challenge(conn, &hash, user.id, expires_at)
+        .await
+        .map_err(database_error)?;
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "Enter the code from your authenticator app",
+            "mfa_required": true,
+            "mfa_method": "totp",
+            "mfa_token": token,
+            "expires_in": mfa::CHALLENGE_TTL_SECONDS
+        })),
+    ))
+}
+
+/// Start the second login step for a user without MFA, with a code sent to their email
+pub(crate) async fn email_challenge(
+    conn: &mut PgConnection,
+    mailer: &Mailer,
+    user: &User,
+    ip: Option<IpAddr>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let (token, hash) = mfa::generate_challenge();
+    let (code, code_hash) = mfa::generate_email_code();
+    let expires_at = Utc::now() + Duration::seconds(mfa::CHALLENGE_TTL_SECONDS);
+    mfa_repo::create_email_challenge(conn, &hash, user.id, &code_hash, expires_at)
+        .await
+        .map_err(database_error)?;
+
+    let ip = ip.map(|ip| ip.to_string());
+    let message = templates::login_code(&user.email, &code, ip.as_deref(), mfa::CHALLENGE_TTL_SECONDS / 60);
+    if let Err(e) = mailer.send(message).await {
+        eprintln!("{}", e);
+    }
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "Enter the code sent to your email",
+            "mfa_required": true,
+            "mfa_method": "email",
+            "mfa_token": token,
+            "expires_in": mfa::CHALLENGE_TTL_SECONDS
+        })),
+    ))
+}
+
+/// Check a code against the user's TOTP secret, using it up if it matches
+async fn accept_code(conn: &mut PgConnection, user_id: Uuid, code: &str) -> Result<bool, status::Custom<Json<Value>>> {
+    let Some(enrollment) = mfa_repo::find_totp(conn, user_id).await.map_err(database_error)? else {
+        return Ok(false);
+    };
+    match mfa::verify(&enrollment.secret, code, Utc::now()) {
+        Some(step) => mfa_repo::accept_totp_step(conn, user_id, step)
+            .await
+            .map_err(database_error),
+        None => Ok(false),
+    }
+}
+
+async fn find_user(db: &mut Connection<Postgres>, user_id: &str) -> Result<User, status::Custom<Json<Value>>> {
+    let user_id = parse_user_id(user_id)?;
+    users::find_by_id(db, user_id)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(|| error(Status::NotFound, "User not found"))
+}
+
+fn parse_user_id(user_id: &str) -> Result<Uuid, status::Custom<Json<Value>>> {
+    Uuid::parse_str(user_id).map_err(|_| error(Status::Unauthorized, "Invalid token subject"))
+}
+
+fn invalid_challenge() -> status::Custom<Json<Value>> {
+    error(Status::Unauthorized, "Invalid or expired MFA token")
+}
+
+fn invalid_code() -> status::Custom<Json<Value>> {
+    error(Status::BadRequest, "Invalid code")
+}
+
+fn error(status: Status, message: &str) -> status::Custom<Json<Value>> {
+    status::Custom(
+        status,
+        Json(json!({
+            "error": message
+        })),
+    )
+}
+
+fn database_error(e: sqlx::Error) -> status::Custom<Json<Value>> {
+    eprintln!("Database error: {}", e);
+    status::Custom(
+        Status::InternalServerError,
+        Json(json!({
+            "error": "Database error occurred"
+        })),
+    )
+}
@@ -1 +1,25 @@
 pub mod auth;
+pub mod admin;
+pub mod dev;
+pub mod links;
+pub mod qr_login;
+pub mod guest;
+pub mod emails;
+pub mod account;
+pub mod api_keys;
+pub mod permissions;
+pub mod oauth;
+pub mod oidc;
+pub mod authorization;
+pub mod oauth_clients;
+pub mod client_registration;
+pub mod mfa;
+pub mod audit;
+pub mod health;
+pub mod organizations;
+pub mod billing;
+pub mod plans;
+pub mod connections;
+pub mod email_settings;
+pub mod settings;
+pub mod sso;
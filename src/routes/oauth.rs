@@ -0,0 +1,529 @@
+use chrono::{Duration, Utc};
+use rocket::http::Status;
+use rocket::response::{status, Redirect};
+use rocket::serde::json::{Json, Value, json};
+use rocket::State;
+use rocket_db_pools::Connection;
+use sqlx::PgConnection;
+use std::net::IpAddr;
+use uuid::Uuid;
+
+use crate::auth::access_tokens::AccessTokens;
+use crate::auth::external::{self, ExternalClaims, ExternalTokenError, ProvisionError};
+use crate::auth::google::GoogleIdTokens;
+use crate::auth::jwt::JwtService;
+use crate::auth::oauth::{self, AuthorizationRequest, OAuthError, OAuthIdentity, OAuthProvider, OAuthProviders};
+use crate::auth::password::PasswordHasher;
+use crate::auth::sso;
+use crate::body_limits::JsonBody;
+use crate::config::{AppConfig, RegistrationMode};
+use crate::email::sender::Mailer;
+use crate::models::oauth::{CompleteOAuthSignIn, GoogleIdTokenLogin, OAuthCallback};
+use crate::models::session::SessionDevice;
+use crate::models::user::User;
+use crate::repositories::{external_identities, oauth_completions, oauth_states, organizations, users};
+use crate::routes::auth::{
+    approval_refusal, check_sso, disabled_refusal, record_login, record_login_attempt, redirect_target, send_verification_email,
+    start_session,
+};
+use crate::settings::Settings;
+use crate::Postgres;
+
+/// Sign in with a Google ID token, skipping the redirect flow
+///
+/// The token is verified against Google's published keys and must be issued
+/// to one of `ROCKET_GOOGLE_CLIENT_IDS`; without any, this route is a 404. The Google account is linked to the
+/// local user with its (verified) email, or to a new user while registration
+/// is open.
+#[post("/oauth/google/id-token", data = "<login>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn google_id_token(
+    mut db: Connection<Postgres>,
+    settings: &State<Settings>,
+    google: &GoogleIdTokens,
+    jwt: &State<JwtService>,
+    access_tokens: &State<AccessTokens>,
+    passwords: &State<PasswordHasher>,
+    ip: Option<IpAddr>,
+    device: SessionDevice,
+    login: JsonBody<GoogleIdTokenLogin>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let config: &AppConfig = &settings.current();
+    let claims = match google.verify(&login.id_token).await {
+        Ok(claims) => claims,
+        Err(ExternalTokenError::Invalid(_)) => {
+            return Err(status::Custom(
+                Status::Unauthorized,
+                Json(json!({
+                    "error": "Invalid Google ID token"
+                })),
+            ));
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            return Err(status::Custom(
+                Status::BadGateway,
+                Json(json!({
+                    "error": "Failed to verify Google ID token"
+                })),
+            ));
+        }
+    };
+
+    let identity = OAuthIdentity {
+        issuer: google.issuer().to_string(),
+        claims,
+        tenant: None,
+        roles: None,
+        profile: external::all_claims(&login.id_token),
+    };
+    sign_in(&mut db, config, jwt, access_tokens, passwords, sso::GOOGLE, &identity, ip, &device).await
+}
+
+/// Start signing in at an OAuth provider (`microsoft`, `discord`, `facebook` or one from `ROCKET_OAUTH_PROVIDERS`)
+///
+/// Redirects the browser to the provider, which sends it back to
+/// `<frontend>/oauth/<provider>/callback` with a `code` and `state` for the
+/// callback endpoint. The state expires after ten minutes. An allowed
+/// `redirect_to` is kept with it and returned by the callback.
+#[get("/oauth/<provider>/authorize?<redirect_to>")]
+pub async fn oauth_authorize(
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    providers: &State<OAuthProviders>,
+    provider: &str,
+    redirect_to: Option<&str>,
+) -> Result<Redirect, status::Custom<Json<Value>>> {
+    let oauth_provider = find_provider(providers, provider)?;
+    let redirect_to = redirect_target(config, redirect_to)?;
+
+    let state = Uuid::new_v4().simple().to_string();
+    let nonce = Uuid::new_v4().simple().to_string();
+    let code_verifier = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let expires_at = Utc::now() + Duration::minutes(10);
+
+    if let Err(e) = oauth_states::create(&mut db, &state, provider, &code_verifier, &nonce, redirect_to.as_deref(), expires_at)
+        .await {
+        eprintln!("Database error: {}", e);
+        return Err(status::Custom(
+            Status::InternalServerError,
+            Json(json!({
+                "error": "Database error occurred"
+            })),
+        ));
+    }
+
+    let redirect_uri = oauth::redirect_uri(config, provider);
+    let code_challenge = oauth::code_challenge(&code_verifier);
+    Ok(Redirect::to(oauth_provider.authorization_url(&AuthorizationRequest {
+        redirect_uri: &redirect_uri,
+        state: &state,
+        nonce: &nonce,
+        code_challenge: &code_challenge,
+    })))
+}
+
+/// Finish signing in at an OAuth provider with the `code` and `state` it redirected back with
+///
+/// Signs in like `/oauth/google/id-token`. Microsoft accounts from a
+/// multi-tenant app also join their directory's organization. A new account
+/// the provider gave no email for isn't created yet: the response is a `202`
+/// with code `email_required` and a token for `/oauth/<provider>/complete`.
+#[post("/oauth/<provider>/callback", data = "<callback>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn oauth_callback(
+    mut db: Connection<Postgres>,
+    settings: &State<Settings>,
+    providers: &State<OAuthProviders>,
+    jwt: &State<JwtService>,
+    access_tokens: &State<AccessTokens>,
+    passwords: &State<PasswordHasher>,
+    ip: Option<IpAddr>,
+    device: SessionDevice,
+    provider: &str,
+    callback: JsonBody<OAuthCallback>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let config: &AppConfig = &settings.current();
+    let oauth_provider = find_provider(providers, provider)?;
+
+    let pending = match oauth_states::take(&mut db, &callback.state, provider).await {
+        Ok(Some(pending)) => pending,
+        Ok(None) => {
+            return Err(status::Custom(
+                Status::BadRequest,
+                Json(json!({
+                    "error": "Invalid or expired sign-in state",
+                    "code": "invalid_state"
+                })),
+            ));
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ));
+        }
+    };
+
+    let redirect_uri = oauth::redirect_uri(config, provider);
+    let identity = match oauth_provider
+        .exchange(&callback.code, &redirect_uri, &pending.code_verifier, &pending.nonce)
+        .await
+    {
+        Ok(identity) => identity,
+        Err(OAuthError::Invalid(message)) => {
+            eprintln!("OAuth sign-in refused: {}", message);
+            return Err(status::Custom(
+                Status::Unauthorized,
+                Json(json!({
+                    "error": "Sign-in was refused by the provider"
+                })),
+            ));
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            return Err(status::Custom(
+                Status::BadGateway,
+                Json(json!({
+                    "error": "Failed to complete sign-in with the provider"
+                })),
+            ));
+        }
+    };
+
+    // New accounts need an email; ask the user for one before creating it
+    if identity.claims.email.is_none() {
+        match external_identities::find_user_id(&mut db, &identity.issuer, &identity.claims.sub).await {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                let response = request_email(&mut db, config, provider, &identity).await;
+                return response.map(|response| with_redirect(response, pending.redirect_to));
+            }
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                return Err(status::Custom(
+                    Status::InternalServerError,
+                    Json(json!({
+                        "error": "Database error occurred"
+                    })),
+                ));
+            }
+        }
+    }
+
+    let response = sign_in(&mut db, config, jwt, access_tokens, passwords, provider, &identity, ip, &device).await;
+    response.map(|response| with_redirect(response, pending.redirect_to))
+}
+
+/// Add the `redirect_to` a sign-in was started with to its response, for the frontend to go to next
+fn with_redirect(mut response: status::Custom<Json<Value>>, redirect_to: Option<String>) -> status::Custom<Json<Value>> {
+    if let (Some(redirect_to), Some(fields)) = (redirect_to, response.1.0.as_object_mut()) {
+        fields.insert("redirect_to".to_string(), Value::String(redirect_to));
+    }
+    response
+}
+
+/// Finish an OAuth sign-in the provider gave no email for
+///
+/// Takes the `completion_token` from the callback's `202 email_required`
+/// response and the address the user typed. The address isn't trusted: it
+/// can't be one that's already registered (`409 email_taken`), and the new
+/// account gets a verification email. Completion tokens expire after 30
+/// minutes.
+#[post("/oauth/<provider>/complete", data = "<completion>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn oauth_complete(
+    mut db: Connection<Postgres>,
+    settings: &State<Settings>,
+    providers: &State<OAuthProviders>,
+    jwt: &State<JwtService>,
+    access_tokens: &State<AccessTokens>,
+    passwords: &State<PasswordHasher>,
+    mailer: &State<Mailer>,
+    ip: Option<IpAddr>,
+    device: SessionDevice,
+    provider: &str,
+    completion: JsonBody<CompleteOAuthSignIn>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let config: &AppConfig = &settings.current();
+    find_provider(providers, provider)?;
+
+    if !completion.email.contains('@') {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "Invalid email format"
+            })),
+        ));
+    }
+
+    let pending = match oauth_completions::find(&mut db, &completion.completion_token, provider).await {
+        Ok(Some(pending)) => pending,
+        Ok(None) => {
+            return Err(status::Custom(
+                Status::BadRequest,
+                Json(json!({
+                    "error": "Invalid or expired completion token",
+                    "code": "invalid_completion"
+                })),
+            ));
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ));
+        }
+    };
+
+    let identity = OAuthIdentity {
+        issuer: pending.issuer.clone(),
+        claims: ExternalClaims {
+            iss: pending.issuer,
+            sub: pending.subject,
+            email: Some(completion.email.clone()),
+            email_verified: Some(false),
+            tid: None,
+            nonce: None,
+        },
+        tenant: None,
+        roles: None,
+        profile: Value::Null,
+    };
+    let user = provision(&mut db, config, passwords, provider, &identity).await?;
+
+    if let Err(e) = oauth_completions::delete(&mut db, &completion.completion_token).await {
+        eprintln!("Database error: {}", e);
+    }
+    if user.email_verified_at.is_none() {
+        send_verification_email(&mut db, config, mailer, &user, None).await;
+    }
+
+    log_in(&mut db, config, jwt, access_tokens, provider, &user, ip, &device).await
+}
+
+/// Park an identity without an email and hand out a token to finish with one
+async fn request_email(
+    db: &mut PgConnection,
+    config: &AppConfig,
+    provider: &str,
+    identity: &OAuthIdentity,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    if config.registration_mode != RegistrationMode::Open {
+        return Err(status::Custom(
+            Status::Forbidden,
+            Json(json!({
+                "error": "Registration is closed",
+                "code": "registration_closed"
+            })),
+        ));
+    }
+
+    let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let expires_at = Utc::now() + Duration::minutes(30);
+    if let Err(e) =
+        oauth_completions::create(db, &token, provider, &identity.issuer, &identity.claims.sub, expires_at).await
+    {
+        eprintln!("Database error: {}", e);
+        return Err(status::Custom(
+            Status::InternalServerError,
+            Json(json!({
+                "error": "Database error occurred"
+            })),
+        ));
+    }
+
+    Ok(status::Custom(
+        Status::Accepted,
+        Json(json!({
+            "message": "An email address is needed to finish signing in",
+            "code": "email_required",
+            "completion_token": token,
+            "expires_at": expires_at.to_rfc3339()
+        })),
+    ))
+}
+
+fn find_provider<'a>(
+    providers: &'a OAuthProviders,
+    name: &str,
+) -> Result<&'a dyn OAuthProvider, status::Custom<Json<Value>>> {
+    providers.get(name).ok_or_else(|| {
+        status::Custom(
+            Status::NotFound,
+            Json(json!({
+                "error": "Unknown sign-in provider"
+            })),
+        )
+    })
+}
+
+/// Sign in the local user behind a verified external identity, creating it while registration is open
+#[allow(clippy::too_many_arguments)]
+async fn sign_in(
+    db: &mut PgConnection,
+    config: &AppConfig,
+    jwt: &JwtService,
+    access_tokens: &AccessTokens,
+    passwords: &PasswordHasher,
+    provider: &str,
+    identity: &OAuthIdentity,
+    ip: Option<IpAddr>,
+    device: &SessionDevice,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let user = provision(db, config, passwords, provider, identity).await?;
+    log_in(db, config, jwt, access_tokens, provider, &user, ip, device).await
+}
+
+/// The local user behind an external identity, linking or creating it as needed
+///
+/// With a `tenant`, the user also joins that tenant's organization unless
+/// they already belong to one; with `roles`, their role follows the
+/// provider's mapping. Then the `provider`'s SSO mapping rules are applied.
+async fn provision(
+    db: &mut PgConnection,
+    config: &AppConfig,
+    passwords: &PasswordHasher,
+    provider: &str,
+    identity: &OAuthIdentity,
+) -> Result<User, status::Custom<Json<Value>>> {
+    let allow_signup = config.registration_mode == RegistrationMode::Open;
+    let user_id = match external::local_user(db, passwords, &identity.issuer, &identity.claims, allow_signup).await {
+        Ok(user_id) => user_id,
+        Err(ProvisionError::EmailTaken) => {
+            return Err(status::Custom(
+                Status::Conflict,
+                Json(json!({
+                    "error": "An account with this email already exists; sign in with its password",
+                    "code": "email_taken"
+                })),
+            ));
+        }
+        Err(ProvisionError::SignupClosed) => {
+            return Err(status::Custom(
+                Status::Forbidden,
+                Json(json!({
+                    "error": "Registration is closed",
+                    "code": "registration_closed"
+                })),
+            ));
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Failed to sign in"
+                })),
+            ));
+        }
+    };
+
+    if let Some(tenant) = &identity.tenant {
+        let joined = match organizations::find_or_create_for_tenant(db, tenant).await {
+            Ok(organization_id) => users::set_organization_if_unset(db, user_id, organization_id).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = joined {
+            eprintln!("Database error: {}", e);
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ));
+        }
+    }
+
+    if let Some(roles) = &identity.roles
+        && let Err(e) = users::sync_managed_role(db, user_id, roles.granted.as_deref(), &roles.managed).await
+    {
+        eprintln!("Database error: {}", e);
+        return Err(status::Custom(
+            Status::InternalServerError,
+            Json(json!({
+                "error": "Database error occurred"
+            })),
+        ));
+    }
+
+    if let Err(e) = sso::apply_mappings(db, provider, user_id, &identity.profile).await {
+        eprintln!("Database error: {}", e);
+        return Err(status::Custom(
+            Status::InternalServerError,
+            Json(json!({
+                "error": "Database error occurred"
+            })),
+        ));
+    }
+
+    let user = match users::find_by_id(db, user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return Err(status::Custom(
+                Status::Unauthorized,
+                Json(json!({
+                    "error": "Failed to sign in"
+                })),
+            ));
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ));
+        }
+    };
+    Ok(user)
+}
+
+/// Start a session for a user who proved who they are at `provider`
+///
+/// Members of an organization requiring another provider are refused.
+#[allow(clippy::too_many_arguments)]
+async fn log_in(
+    db: &mut PgConnection,
+    config: &AppConfig,
+    jwt: &JwtService,
+    access_tokens: &AccessTokens,
+    provider: &str,
+    user: &User,
+    ip: Option<IpAddr>,
+    device: &SessionDevice,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    check_sso(db, config, user.organization_id, &user.email, Some(provider)).await?;
+    if user.disabled_at.is_some() {
+        record_login_attempt(db, Some(user.id), &user.email, ip, false).await;
+        return Err(disabled_refusal());
+    }
+    if user.approval_status != "approved" {
+        record_login_attempt(db, Some(user.id), &user.email, ip, false).await;
+        return Err(approval_refusal(user));
+    }
+    record_login(db, access_tokens.telemetry(), user.id, &user.email, ip).await;
+
+    let token = start_session(db, jwt, access_tokens, user, ip, device).await?;
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "Login successful",
+            "token": token,
+            "user": {
+                "id": user.id.to_string(),
+                "email": user.email,
+                "must_change_password": user.must_change_password,
+                "created_at": user.created_at.to_rfc3339()
+            }
+        })),
+    ))
+}
@@ -0,0 +1,224 @@
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::{Json, Value, json};
+use rocket_db_pools::Connection;
+use uuid::Uuid;
+
+use crate::auth::guard::HasPermission;
+use crate::auth::oauth_clients;
+use crate::auth::permissions::OAuthClientsManage;
+use crate::auth::scopes;
+use crate::body_limits::JsonBody;
+use crate::maintenance::WriteAccess;
+use crate::models::oauth_client::{NewOAuthClient, OAuthClientUpdate};
+use crate::repositories::oauth_clients as client_repo;
+use crate::Postgres;
+
+/// List registered OAuth clients
+#[get("/oauth-clients")]
+pub async fn list_clients(
+    _user: HasPermission<OAuthClientsManage>,
+    mut db: Connection<Postgres>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let clients = client_repo::list(&mut db).await.map_err(database_error)?;
+
+    Ok(status::Custom(Status::Ok, Json(json!({ "clients": clients }))))
+}
+
+/// Register an OAuth client
+///
+/// Confidential clients get a secret, returned only in this response; it is
+/// stored as a hash.
+#[post("/oauth-clients", data = "<client>")]
+pub async fn create_client(
+    _write: WriteAccess,
+    user: HasPermission<OAuthClientsManage>,
+    mut db: Connection<Postgres>,
+    client: JsonBody<NewOAuthClient>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let name = validate_name(&client.name)?;
+    validate_redirect_uris(&client.redirect_uris)?;
+    let granted = validate_scopes(&client.scopes)?;
+    let logo_url = validate_logo_url(client.logo_url.as_deref())?;
+
+    let client_id = oauth_clients::generate_client_id();
+    let secret = client.confidential.then(oauth_clients::generate_secret);
+    let created = client_repo::create(
+        &mut db,
+        &client_id,
+        secret.as_ref().map(|(_, hash)| hash.as_str()),
+        name,
+        &client.redirect_uris,
+        &granted,
+        logo_url,
+    )
+    .await
+    .map_err(database_error)?;
+    println!("✓ OAuth client {} created by {}", created.client_id, user.user_id);
+
+    Ok(status::Custom(
+        Status::Created,
+        Json(json!({
+            "message": match secret {
+                Some(_) => "Client created. Store the secret now; it won't be shown again.",
+                None => "Public client created. It must use PKCE.",
+            },
+            "client_secret": secret.map(|(secret, _)| secret),
+            "client": created
+        })),
+    ))
+}
+
+/// Show a registered OAuth client
+#[get("/oauth-clients/<id>")]
+pub async fn get_client(
+    _user: HasPermission<OAuthClientsManage>,
+    mut db: Connection<Postgres>,
+    id: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let id = parse_id(id)?;
+    let client = client_repo::find_by_id(&mut db, id)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(client_not_found)?;
+
+    Ok(status::Custom(Status::Ok, Json(json!({ "client": client }))))
+}
+
+/// Change a client's name, redirect URIs, scopes or logo
+///
+/// Narrowing the scopes doesn't touch what users already granted; tokens
+/// keep their scopes until they expire.
+#[patch("/oauth-clients/<id>", data = "<update>")]
+pub async fn update_client(
+    _write: WriteAccess,
+    user: HasPermission<OAuthClientsManage>,
+    mut db: Connection<Postgres>,
+    id: &str,
+    update: JsonBody<OAuthClientUpdate>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let id = parse_id(id)?;
+    let name = update.name.as_deref().map(validate_name).transpose()?;
+    if let Some(redirect_uris) = &update.redirect_uris {
+        validate_redirect_uris(redirect_uris)?;
+    }
+    let granted = update.scopes.as_deref().map(validate_scopes).transpose()?;
+    let logo_url = match update.logo_url.as_deref() {
+        Some("") => Some(None),
+        Some(logo_url) => Some(validate_logo_url(Some(logo_url))?),
+        None => None,
+    };
+
+    let client = client_repo::update(
+        &mut db,
+        id,
+        name,
+        update.redirect_uris.as_deref(),
+        granted.as_deref(),
+        logo_url,
+    )
+    .await
+    .map_err(database_error)?
+    .ok_or_else(client_not_found)?;
+    println!("✓ OAuth client {} updated by {}", client.client_id, user.user_id);
+
+    Ok(status::Custom(Status::Ok, Json(json!({ "client": client }))))
+}
+
+/// Delete a client, with every consent, code and token issued to it
+#[delete("/oauth-clients/<id>")]
+pub async fn delete_client(
+    _write: WriteAccess,
+    user: HasPermission<OAuthClientsManage>,
+    mut db: Connection<Postgres>,
+    id: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let id = parse_id(id)?;
+    if !client_repo::delete(&mut db, id).await.map_err(database_error)? {
+        return Err(client_not_found());
+    }
+    println!("✓ OAuth client {} deleted by {}", id, user.user_id);
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "OAuth client deleted"
+        })),
+    ))
+}
+
+fn validate_name(name: &str) -> Result<&str, status::Custom<Json<Value>>> {
+    let name = name.trim();
+    if name.is_empty() || name.len() > 100 {
+        return Err(bad_request("Name must be between 1 and 100 characters", None));
+    }
+    Ok(name)
+}
+
+fn validate_redirect_uris(redirect_uris: &[String]) -> Result<(), status::Custom<Json<Value>>> {
+    if redirect_uris.is_empty() {
+        return Err(bad_request("At least one redirect URI is required", None));
+    }
+    if let Some(invalid) = oauth_clients::invalid_redirect_uri(redirect_uris) {
+        return Err(bad_request(
+            &format!("Invalid redirect URI '{}'", invalid),
+            Some("Use an absolute URI without a fragment"),
+        ));
+    }
+    Ok(())
+}
+
+fn validate_scopes(requested: &[String]) -> Result<Vec<String>, status::Custom<Json<Value>>> {
+    let details = format!("Available scopes: {}", scopes::ALL.join(", "));
+    if requested.is_empty() {
+        return Err(bad_request("At least one scope is required", Some(&details)));
+    }
+    if let Some(unknown) = requested.iter().find(|scope| !scopes::is_known(scope)) {
+        return Err(bad_request(&format!("Unknown scope '{}'", unknown), Some(&details)));
+    }
+
+    let mut granted = requested.to_vec();
+    granted.sort();
+    granted.dedup();
+    Ok(granted)
+}
+
+fn validate_logo_url(logo_url: Option<&str>) -> Result<Option<&str>, status::Custom<Json<Value>>> {
+    match logo_url {
+        Some(url) if !oauth_clients::is_valid_logo_url(url) => {
+            Err(bad_request("logo_url must be an https URL", None))
+        }
+        other => Ok(other),
+    }
+}
+
+fn parse_id(id: &str) -> Result<Uuid, status::Custom<Json<Value>>> {
+    Uuid::parse_str(id).map_err(|_| client_not_found())
+}
+
+fn bad_request(error: &str, details: Option<&str>) -> status::Custom<Json<Value>> {
+    let body = match details {
+        Some(details) => json!({ "error": error, "details": details }),
+        None => json!({ "error": error }),
+    };
+    status::Custom(Status::BadRequest, Json(body))
+}
+
+fn client_not_found() -> status::Custom<Json<Value>> {
+    status::Custom(
+        Status::NotFound,
+        Json(json!({
+            "error": "OAuth client not found"
+        })),
+    )
+}
+
+fn database_error(e: sqlx::Error) -> status::Custom<Json<Value>> {
+    eprintln!("Database error: {}", e);
+    status::Custom(
+        Status::InternalServerError,
+        Json(json!({
+            "error": "Database error occurred"
+        })),
+    )
+}
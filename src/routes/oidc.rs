@@ -0,0 +1,100 @@
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::{Json, Value, json};
+use rocket::State;
+use rocket_db_pools::Connection;
+
+use crate::auth::guard::Scoped;
+use crate::auth::oidc::{self, PROFILE_CLAIMS};
+use crate::auth::scopes::OpenId;
+use crate::auth::token_exchange;
+use crate::config::AppConfig;
+use crate::repositories::users;
+use crate::Postgres;
+
+/// OpenID Connect discovery document
+///
+/// Lets standard OIDC client libraries find this service's endpoints from
+/// its issuer URL (`ROCKET_PUBLIC_URL`). Authorization requests go to the
+/// frontend's consent page, which talks to `/api/v1/auth/authorize`.
+#[get("/.well-known/openid-configuration")]
+pub fn openid_configuration(config: &State<AppConfig>) -> Json<Value> {
+    let issuer = oidc::issuer(config);
+    let mut claims_supported = vec!["sub", "email", "email_verified", "updated_at"];
+    claims_supported.extend_from_slice(PROFILE_CLAIMS);
+
+    let mut document = json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{}/oauth/authorize", config.frontend_url),
+        "token_endpoint": format!("{}/api/v1/auth/token", config.public_url),
+        "userinfo_endpoint": format!("{}/api/v1/auth/userinfo", config.public_url),
+        "scopes_supported": ["openid", "profile", "email"],
+        "claims_supported": claims_supported,
+        "subject_types_supported": ["public"],
+        "response_types_supported": ["code"],
+        "grant_types_supported": ["authorization_code", token_exchange::GRANT_TYPE],
+        "code_challenge_methods_supported": ["S256"],
+        "token_endpoint_auth_methods_supported": ["client_secret_basic", "client_secret_post", "none"],
+        "revocation_endpoint": format!("{}/api/v1/auth/revoke", config.public_url),
+        "revocation_endpoint_auth_methods_supported": ["client_secret_basic", "client_secret_post", "none"]
+    });
+    if config.client_registration_token.is_some() {
+        document["registration_endpoint"] = json!(format!("{}/api/v1/auth/register-client", config.public_url));
+    }
+
+    Json(document)
+}
+
+/// OpenID Connect userinfo: standard claims about the token's user
+///
+/// Session tokens get every claim. Scoped credentials need `openid`, and
+/// `email` or `profile` for those claims.
+#[get("/userinfo")]
+pub async fn userinfo(
+    user: Scoped<OpenId>,
+    mut db: Connection<Postgres>,
+) -> Result<Json<Value>, status::Custom<Json<Value>>> {
+    let user_id = match uuid::Uuid::parse_str(&user.user_id) {
+        Ok(id) => id,
+        Err(_) => {
+            return Err(status::Custom(
+                Status::Unauthorized,
+                Json(json!({
+                    "error": "Invalid token subject"
+                })),
+            ));
+        }
+    };
+
+    let profile = match users::find_by_id(&mut db, user_id).await {
+        Ok(Some(profile)) => profile,
+        Ok(None) => {
+            return Err(status::Custom(
+                Status::NotFound,
+                Json(json!({
+                    "error": "User not found"
+                })),
+            ));
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ));
+        }
+    };
+
+    Ok(Json(Value::Object(oidc::user_claims(&profile, |scope| user.has_scope(scope)))))
+}
+
+/// Same as `GET /userinfo`; OIDC clients may use either
+#[post("/userinfo")]
+pub async fn userinfo_post(
+    user: Scoped<OpenId>,
+    db: Connection<Postgres>,
+) -> Result<Json<Value>, status::Custom<Json<Value>>> {
+    userinfo(user, db).await
+}
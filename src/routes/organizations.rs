@@ -0,0 +1,784 @@
+use chrono::{Duration, Utc};
+use rocket::Either;
+use rocket::http::{ContentType, Status};
+use rocket::response::status;
+use rocket::response::stream::TextStream;
+use rocket::serde::json::{Json, Value, json};
+use rocket::State;
+use rocket_db_pools::Connection;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::auth::guard::AuthenticatedUser;
+use crate::auth::org_keys;
+use crate::auth::oauth::OAuthProviders;
+use crate::auth::org_permissions::{self, InviteMembers, ManageBilling, ViewAudit};
+use crate::auth::sso;
+use crate::authz::{OrgAdmin, OrgPermitted};
+use crate::body_limits::JsonBody;
+use crate::config::AppConfig;
+use crate::domains::{self, CheckError, DnsClient};
+use crate::email::sender::Mailer;
+use crate::email::templates;
+use crate::maintenance::WriteAccess;
+use crate::models::audit::AuditQuery;
+use crate::models::organization::{
+    DomainJoinMode, MemberRoleUpdate, NewOrgDomain, NewOrgInvitation, NewOrgRole, OrgDomain, OrgRole, OrgRoleUpdate, OrgSso,
+    OrgSsoUpdate,
+};
+use crate::repositories::{invitations, organizations, plans, users};
+use crate::routes::audit as audit_routes;
+use crate::Postgres;
+
+/// Days an invitation is valid when the request doesn't say
+const DEFAULT_INVITATION_DAYS: i64 = 7;
+
+/// Longest custom role name, as stored
+const MAX_ROLE_NAME_LENGTH: usize = 50;
+
+/// List the organization's token signing keys (without their secrets)
+#[get("/<_id>/signing-keys")]
+pub async fn list_signing_keys(
+    org: OrgAdmin,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    _id: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    signing_keys_enabled(config)?;
+    let keys = organizations::list_signing_keys(&mut db, org.organization_id)
+        .await
+        .map_err(database_error)?;
+
+    Ok(status::Custom(Status::Ok, Json(json!({ "signing_keys": keys }))))
+}
+
+/// Create a signing key for the organization's members' tokens
+///
+/// Tokens issued from now on are signed with it; tokens signed with older
+/// keys keep working until those keys are revoked.
+#[post("/<_id>/signing-keys")]
+pub async fn create_signing_key(
+    _write: WriteAccess,
+    org: OrgAdmin,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    _id: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    signing_keys_enabled(config)?;
+    let (kid, secret) = org_keys::generate();
+    let key = organizations::create_signing_key(&mut db, org.organization_id, &kid, &secret)
+        .await
+        .map_err(database_error)?;
+
+    Ok(status::Custom(
+        Status::Created,
+        Json(json!({
+            "message": "Signing key created. New tokens for the organization's members are signed with it.",
+            "signing_key": key
+        })),
+    ))
+}
+
+/// Revoke one of the organization's signing keys
+///
+/// Every token signed with it stops working at once, so members signed in
+/// with those tokens have to sign in again.
+#[delete("/<_id>/signing-keys/<key_id>")]
+pub async fn revoke_signing_key(
+    _write: WriteAccess,
+    org: OrgAdmin,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    _id: &str,
+    key_id: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    signing_keys_enabled(config)?;
+    let not_found = || {
+        status::Custom(
+            Status::NotFound,
+            Json(json!({
+                "error": "Signing key not found"
+            })),
+        )
+    };
+    let key_id = Uuid::parse_str(key_id).map_err(|_| not_found())?;
+    if !organizations::revoke_signing_key(&mut db, org.organization_id, key_id)
+        .await
+        .map_err(database_error)?
+    {
+        return Err(not_found());
+    }
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "Signing key revoked"
+        })),
+    ))
+}
+
+/// Audit events about the organization's members, newest first
+///
+/// For owners, admins and roles with `audit.view`.
+/// Takes the admin audit log's filters (except `org`) and `format=ndjson`.
+/// Events are tagged with the organization the user belonged to when they
+/// happened, so former members' events stay here and new members' earlier
+/// ones don't show up.
+#[get("/<_id>/audit?<query..>")]
+pub async fn org_audit_log(
+    org: OrgPermitted<ViewAudit>,
+    db: Connection<Postgres>,
+    _id: &str,
+    query: AuditQuery,
+) -> Result<Either<status::Custom<Json<Value>>, (ContentType, TextStream![String])>, status::Custom<Json<Value>>> {
+    let filter = audit_routes::audit_filter(&query, Some(org.organization_id))?;
+    audit_routes::audit_events(db, filter, &query).await
+}
+
+/// The roles members can have: the built-in ones and those the organization defined
+#[get("/<_id>/roles")]
+pub async fn list_roles(
+    org: OrgAdmin,
+    mut db: Connection<Postgres>,
+    _id: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let custom = organizations::list_roles(&mut db, org.organization_id)
+        .await
+        .map_err(database_error)?;
+    let everything: Vec<&str> = org_permissions::ALL.iter().map(|(name, _)| *name).collect();
+    let permissions: Vec<Value> = org_permissions::ALL
+        .iter()
+        .map(|(name, description)| json!({ "name": name, "description": description }))
+        .collect();
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "built_in": [
+                { "name": OrgRole::Owner.as_str(), "permissions": everything },
+                { "name": OrgRole::Admin.as_str(), "permissions": everything },
+                { "name": OrgRole::Member.as_str(), "permissions": [] }
+            ],
+            "roles": custom,
+            "permissions": permissions
+        })),
+    ))
+}
+
+/// Define a role allowing its members some of the organization permissions
+#[post("/<_id>/roles", data = "<role>")]
+pub async fn create_role(
+    _write: WriteAccess,
+    org: OrgAdmin,
+    mut db: Connection<Postgres>,
+    _id: &str,
+    role: JsonBody<NewOrgRole>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let role = role.into_inner();
+    let name = role.name.trim();
+    validate_role_name(name)?;
+    validate_permissions(&role.permissions)?;
+    if OrgRole::parse(name).is_some() {
+        return Err(role_exists());
+    }
+
+    let created = organizations::create_role(&mut db, org.organization_id, name, &role.permissions)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(role_exists)?;
+    println!("✓ Role {} defined in organization {} by {}", created.name, org.organization_id, org.user_id);
+
+    Ok(status::Custom(Status::Created, Json(json!({ "role": created }))))
+}
+
+/// Replace what a custom role allows; members with it are affected at once
+#[put("/<_id>/roles/<name>", data = "<update>")]
+pub async fn update_role(
+    _write: WriteAccess,
+    org: OrgAdmin,
+    mut db: Connection<Postgres>,
+    _id: &str,
+    name: &str,
+    update: JsonBody<OrgRoleUpdate>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    validate_permissions(&update.permissions)?;
+    let updated = organizations::update_role(&mut db, org.organization_id, name, &update.permissions)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(role_not_found)?;
+
+    Ok(status::Custom(Status::Ok, Json(json!({ "role": updated }))))
+}
+
+/// Delete a custom role no member has
+#[delete("/<_id>/roles/<name>")]
+pub async fn delete_role(
+    _write: WriteAccess,
+    org: OrgAdmin,
+    mut db: Connection<Postgres>,
+    _id: &str,
+    name: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let members = organizations::count_with_role(&mut db, org.organization_id, name)
+        .await
+        .map_err(database_error)?;
+    if members > 0 {
+        return Err(status::Custom(
+            Status::Conflict,
+            Json(json!({
+                "error": format!("{} member(s) still have this role; give them another one first", members),
+                "code": "role_in_use"
+            })),
+        ));
+    }
+    if !organizations::delete_role(&mut db, org.organization_id, name)
+        .await
+        .map_err(database_error)?
+    {
+        return Err(role_not_found());
+    }
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "Role deleted"
+        })),
+    ))
+}
+
+/// Give a member another role: `admin`, `member` or a custom role
+///
+/// Owners keep their role.
+#[put("/<_id>/members/<user_id>/role", data = "<update>")]
+pub async fn set_member_role(
+    _write: WriteAccess,
+    org: OrgAdmin,
+    mut db: Connection<Postgres>,
+    _id: &str,
+    user_id: &str,
+    update: JsonBody<MemberRoleUpdate>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let not_found = || {
+        status::Custom(
+            Status::NotFound,
+            Json(json!({
+                "error": "No member of the organization with this id, or they own it"
+            })),
+        )
+    };
+    let user_id = Uuid::parse_str(user_id).map_err(|_| not_found())?;
+    let role = assignable_role(&mut db, org.organization_id, &update.role).await?;
+    if !users::set_organization_role(&mut db, org.organization_id, user_id, &role)
+        .await
+        .map_err(database_error)?
+    {
+        return Err(not_found());
+    }
+    println!("✓ Member {} of organization {} given role {} by {}", user_id, org.organization_id, role, org.user_id);
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "Role changed",
+            "user_id": user_id,
+            "role": role
+        })),
+    ))
+}
+
+/// Invite someone to register into the organization, with a role there
+///
+/// For owners, admins and roles with `members.invite`. The invitation is
+/// emailed; registering with its code puts the new account in the
+/// organization, even while registration is invite-only.
+#[post("/<_id>/invitations", data = "<invitation>")]
+pub async fn invite_member(
+    _write: WriteAccess,
+    org: OrgPermitted<InviteMembers>,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    mailer: &State<Mailer>,
+    _id: &str,
+    invitation: JsonBody<NewOrgInvitation>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let invitation = invitation.into_inner();
+    let days = invitation.expires_in_days.unwrap_or(DEFAULT_INVITATION_DAYS);
+    if !(1..=365).contains(&days) {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "expires_in_days must be between 1 and 365"
+            })),
+        ));
+    }
+    let email = invitation.email.trim();
+    if !email.contains('@') {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "Invalid email format"
+            })),
+        ));
+    }
+    let role = assignable_role(&mut db, org.organization_id, invitation.role.as_deref().unwrap_or("member")).await?;
+    if users::email_exists(&mut db, email).await.map_err(database_error)? {
+        return Err(status::Custom(
+            Status::Conflict,
+            Json(json!({
+                "error": "This address already has an account; an admin can add it to the organization"
+            })),
+        ));
+    }
+
+    let code = Uuid::new_v4().to_string();
+    let expires_at = Utc::now() + Duration::days(days);
+    let created = invitations::create_for_organization(
+        &mut db,
+        &code,
+        email,
+        Some(org.user_id),
+        expires_at,
+        org.organization_id,
+        &role,
+    )
+    .await
+    .map_err(database_error)?;
+
+    let link = format!("{}/register?invitation={}", config.frontend_url, created.code);
+    if let Err(e) = mailer.send(templates::invitation(email, &created.code, &link)).await {
+        eprintln!("{}", e);
+    }
+
+    Ok(status::Custom(
+        Status::Created,
+        Json(json!({
+            "code": created.code,
+            "email": created.email,
+            "role": created.organization_role,
+            "expires_at": created.expires_at.to_rfc3339()
+        })),
+    ))
+}
+
+/// The plans the organization is entitled to
+///
+/// For owners, admins and roles with `billing.manage`.
+#[get("/<_id>/billing")]
+pub async fn billing(
+    org: OrgPermitted<ManageBilling>,
+    mut db: Connection<Postgres>,
+    _id: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let entitlements = plans::list_entitlements(&mut db, None, Some(org.organization_id))
+        .await
+        .map_err(database_error)?;
+
+    Ok(status::Custom(Status::Ok, Json(json!({ "entitlements": entitlements }))))
+}
+
+/// The email domains the organization claimed, with the TXT record each needs
+#[get("/<_id>/domains")]
+pub async fn list_domains(
+    org: OrgAdmin,
+    mut db: Connection<Postgres>,
+    _id: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let claimed = organizations::list_domains(&mut db, org.organization_id)
+        .await
+        .map_err(database_error)?;
+    let views: Vec<Value> = claimed.iter().map(domain_view).collect();
+
+    Ok(status::Custom(Status::Ok, Json(json!({ "domains": views }))))
+}
+
+/// Claim an email domain; it's verified once the TXT record in the answer is published
+///
+/// Unverified domains are checked in the background for a week, or at once
+/// with the verify endpoint.
+#[post("/<_id>/domains", data = "<domain>")]
+pub async fn claim_domain(
+    _write: WriteAccess,
+    org: OrgAdmin,
+    mut db: Connection<Postgres>,
+    _id: &str,
+    domain: JsonBody<NewOrgDomain>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let name = domains::normalize(&domain.domain).ok_or_else(|| {
+        status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "Invalid domain name"
+            })),
+        )
+    })?;
+    let join_mode = domain.join_mode.unwrap_or(DomainJoinMode::Auto);
+    if let Some(verified) = organizations::find_verified_domain(&mut db, &name).await.map_err(database_error)?
+        && verified.organization_id != org.organization_id
+    {
+        return Err(domain_taken());
+    }
+
+    let token = Uuid::new_v4().simple().to_string();
+    let claimed = organizations::create_domain(&mut db, org.organization_id, &name, join_mode.as_str(), &token)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(|| {
+            status::Custom(
+                Status::Conflict,
+                Json(json!({
+                    "error": "The organization already claimed this domain"
+                })),
+            )
+        })?;
+
+    Ok(status::Custom(Status::Created, Json(json!({ "domain": domain_view(&claimed) }))))
+}
+
+/// Look for a claimed domain's TXT record now
+#[post("/<_id>/domains/<domain_id>/verify")]
+pub async fn verify_domain(
+    _write: WriteAccess,
+    org: OrgAdmin,
+    mut db: Connection<Postgres>,
+    dns: &State<DnsClient>,
+    _id: &str,
+    domain_id: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let domain_id = Uuid::parse_str(domain_id).map_err(|_| domain_not_found())?;
+    let domain = organizations::find_domain(&mut db, org.organization_id, domain_id)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(domain_not_found)?;
+
+    let checked = match domains::check(&mut db, dns, &domain).await {
+        Ok(checked) => checked,
+        Err(CheckError::Dns(e)) => {
+            eprintln!("{}", e);
+            return Err(status::Custom(
+                Status::BadGateway,
+                Json(json!({
+                    "error": "Couldn't look up the domain's DNS records; try again later"
+                })),
+            ));
+        }
+        Err(CheckError::Database(e)) => return Err(database_error(e)),
+    };
+    if checked.verified_at.is_some() {
+        return Ok(status::Custom(Status::Ok, Json(json!({ "domain": domain_view(&checked) }))));
+    }
+
+    let taken = organizations::find_verified_domain(&mut db, &checked.domain)
+        .await
+        .map_err(database_error)?
+        .is_some();
+    if taken {
+        return Err(domain_taken());
+    }
+    Err(status::Custom(
+        Status::BadRequest,
+        Json(json!({
+            "error": "Verification record not found",
+            "details": format!(
+                "Publish a TXT record at {} with the value {}; DNS changes can take a while to show",
+                domains::challenge_name(&checked),
+                domains::challenge_value(&checked)
+            ),
+            "code": "challenge_not_found"
+        })),
+    ))
+}
+
+/// Give up a claimed domain; people with addresses there no longer join
+#[delete("/<_id>/domains/<domain_id>")]
+pub async fn delete_domain(
+    _write: WriteAccess,
+    org: OrgAdmin,
+    mut db: Connection<Postgres>,
+    _id: &str,
+    domain_id: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let domain_id = Uuid::parse_str(domain_id).map_err(|_| domain_not_found())?;
+    if !organizations::delete_domain(&mut db, org.organization_id, domain_id)
+        .await
+        .map_err(database_error)?
+    {
+        return Err(domain_not_found());
+    }
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "Domain removed"
+        })),
+    ))
+}
+
+/// Join an organization with an invitation sent to the current user's address
+#[post("/invitations/<code>/accept")]
+pub async fn accept_invitation(
+    _write: WriteAccess,
+    user: AuthenticatedUser,
+    mut db: Connection<Postgres>,
+    code: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let unauthorized = || {
+        status::Custom(
+            Status::Unauthorized,
+            Json(json!({
+                "error": "User not found"
+            })),
+        )
+    };
+    let user_id = Uuid::parse_str(&user.user_id).map_err(|_| unauthorized())?;
+    let user = users::find_by_id(&mut db, user_id)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(unauthorized)?;
+    if user.organization_id.is_some() {
+        return Err(status::Custom(
+            Status::Conflict,
+            Json(json!({
+                "error": "You already belong to an organization",
+                "code": "already_in_organization"
+            })),
+        ));
+    }
+
+    let invitation_id = invitations::claim_for_organization(&mut db, code, &user.email)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(|| {
+            status::Custom(
+                Status::Forbidden,
+                Json(json!({
+                    "error": "Invalid or expired invitation code",
+                    "code": "invitation_invalid"
+                })),
+            )
+        })?;
+    invitations::set_used_by(&mut db, invitation_id, user.id).await.map_err(database_error)?;
+    invitations::join_organization(&mut db, invitation_id, user.id).await.map_err(database_error)?;
+    let joined = users::find_by_id(&mut db, user.id).await.map_err(database_error)?.ok_or_else(unauthorized)?;
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "Joined the organization",
+            "organization_id": joined.organization_id,
+            "organization_role": joined.organization_role
+        })),
+    ))
+}
+
+/// The organization's sign-in provider, and whether members must use it
+#[get("/<_id>/sso")]
+pub async fn get_sso(
+    org: OrgAdmin,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    _id: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let current = organizations::sso(&mut db, org.organization_id)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(organization_not_found)?;
+    Ok(status::Custom(Status::Ok, Json(sso_view(config, &current))))
+}
+
+/// Set the organization's sign-in provider; with `enforced`, members can't use anything else
+///
+/// Enforcement also covers anyone with an address at the organization's
+/// verified domains. Passwords and other providers are refused with `403`
+/// and code `sso_required`.
+#[put("/<_id>/sso", data = "<update>")]
+pub async fn update_sso(
+    _write: WriteAccess,
+    org: OrgAdmin,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    providers: &State<OAuthProviders>,
+    _id: &str,
+    update: JsonBody<OrgSsoUpdate>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let provider = update.provider.as_deref().map(str::trim).filter(|provider| !provider.is_empty());
+    match provider {
+        Some(provider) if !sso::is_available(config, providers, provider) => {
+            return Err(status::Custom(
+                Status::BadRequest,
+                Json(json!({
+                    "error": format!("'{}' isn't a configured sign-in provider", provider)
+                })),
+            ));
+        }
+        None if update.enforced => {
+            return Err(status::Custom(
+                Status::BadRequest,
+                Json(json!({
+                    "error": "Enforcing single sign-on needs a provider"
+                })),
+            ));
+        }
+        _ => {}
+    }
+
+    let updated = organizations::set_sso(&mut db, org.organization_id, provider, update.enforced)
+        .await
+        .map_err(database_error)?;
+    println!(
+        "✓ Organization {} sign-in provider set to {:?} (enforced: {}) by {}",
+        org.organization_id, updated.sso_provider, updated.sso_enforced, org.user_id
+    );
+
+    Ok(status::Custom(Status::Ok, Json(sso_view(config, &updated))))
+}
+
+fn sso_view(config: &AppConfig, current: &OrgSso) -> Value {
+    json!({
+        "provider": current.sso_provider,
+        "enforced": current.sso_enforced,
+        "sign_in_url": current.sso_provider.as_deref().map(|provider| sso::sign_in_url(config, provider))
+    })
+}
+
+fn organization_not_found() -> status::Custom<Json<Value>> {
+    status::Custom(
+        Status::NotFound,
+        Json(json!({
+            "error": "Organization not found"
+        })),
+    )
+}
+
+fn domain_view(domain: &OrgDomain) -> Value {
+    json!({
+        "id": domain.id,
+        "domain": domain.domain,
+        "join_mode": domain.join_mode,
+        "verified": domain.verified_at.is_some(),
+        "verified_at": domain.verified_at,
+        "last_checked_at": domain.last_checked_at,
+        "created_at": domain.created_at,
+        "record": {
+            "type": "TXT",
+            "name": domains::challenge_name(domain),
+            "value": domains::challenge_value(domain)
+        }
+    })
+}
+
+fn domain_not_found() -> status::Custom<Json<Value>> {
+    status::Custom(
+        Status::NotFound,
+        Json(json!({
+            "error": "Domain not found"
+        })),
+    )
+}
+
+fn domain_taken() -> status::Custom<Json<Value>> {
+    status::Custom(
+        Status::Conflict,
+        Json(json!({
+            "error": "Another organization verified this domain",
+            "code": "domain_taken"
+        })),
+    )
+}
+
+/// A role that can be given to members: `admin`, `member` or one the organization defined
+pub(crate) async fn assignable_role(
+    conn: &mut PgConnection,
+    organization_id: Uuid,
+    role: &str,
+) -> Result<String, status::Custom<Json<Value>>> {
+    match OrgRole::parse(role) {
+        Some(OrgRole::Owner) => Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "Ownership can't be given through roles"
+            })),
+        )),
+        Some(builtin) => Ok(builtin.as_str().to_string()),
+        None => match organizations::find_role(conn, organization_id, role).await.map_err(database_error)? {
+            Some(custom) => Ok(custom.name),
+            None => Err(status::Custom(
+                Status::BadRequest,
+                Json(json!({
+                    "error": format!("Unknown role '{}'", role)
+                })),
+            )),
+        },
+    }
+}
+
+fn validate_role_name(name: &str) -> Result<(), status::Custom<Json<Value>>> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_ROLE_NAME_LENGTH
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-');
+    if valid {
+        Ok(())
+    } else {
+        Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": format!(
+                    "Role names are 1 to {} lowercase letters, digits, '_' or '-'",
+                    MAX_ROLE_NAME_LENGTH
+                )
+            })),
+        ))
+    }
+}
+
+fn validate_permissions(permissions: &[String]) -> Result<(), status::Custom<Json<Value>>> {
+    match permissions.iter().find(|name| !org_permissions::is_known(name)) {
+        None => Ok(()),
+        Some(unknown) => Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": format!("Unknown organization permission '{}'", unknown)
+            })),
+        )),
+    }
+}
+
+fn role_exists() -> status::Custom<Json<Value>> {
+    status::Custom(
+        Status::Conflict,
+        Json(json!({
+            "error": "The organization already has a role with this name"
+        })),
+    )
+}
+
+fn role_not_found() -> status::Custom<Json<Value>> {
+    status::Custom(
+        Status::NotFound,
+        Json(json!({
+            "error": "Role not found"
+        })),
+    )
+}
+
+fn signing_keys_enabled(config: &AppConfig) -> Result<(), status::Custom<Json<Value>>> {
+    if config.org_signing_keys {
+        Ok(())
+    } else {
+        Err(status::Custom(
+            Status::NotFound,
+            Json(json!({
+                "error": "Organization signing keys are disabled"
+            })),
+        ))
+    }
+}
+
+fn database_error(e: sqlx::Error) -> status::Custom<Json<Value>> {
+    eprintln!("Database error: {}", e);
+    status::Custom(
+        Status::InternalServerError,
+        Json(json!({
+            "error": "Database error occurred"
+        })),
+    )
+}
@@ -0,0 +1,187 @@
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::{Json, Value, json};
+use rocket_db_pools::Connection;
+
+use crate::auth::guard::HasPermission;
+use crate::auth::permissions::{self, PermissionsManage, PERMISSIONS_MANAGE};
+use crate::body_limits::JsonBody;
+use crate::maintenance::WriteAccess;
+use crate::models::permission::NewPermission;
+use crate::repositories::permissions as permission_repo;
+use crate::Postgres;
+
+/// Longest role name (`users.role` is VARCHAR(20))
+const MAX_ROLE_LEN: usize = 20;
+
+/// List permissions with the roles that have them
+#[get("/permissions")]
+pub async fn list_permissions(
+    _user: HasPermission<PermissionsManage>,
+    mut db: Connection<Postgres>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let permissions = permission_repo::list(&mut db).await.map_err(database_error)?;
+
+    Ok(status::Custom(Status::Ok, Json(json!({ "permissions": permissions }))))
+}
+
+/// Create a permission for the app to check with `HasPermission`
+#[post("/permissions", data = "<permission>")]
+pub async fn create_permission(
+    _write: WriteAccess,
+    user: HasPermission<PermissionsManage>,
+    mut db: Connection<Postgres>,
+    permission: JsonBody<NewPermission>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    if !permissions::is_valid_name(&permission.name) {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "Invalid permission name",
+                "details": "Use lowercase words separated by dots, e.g. reports.export"
+            })),
+        ));
+    }
+
+    let created = permission_repo::create(&mut db, &permission.name, permission.description.as_deref())
+        .await
+        .map_err(database_error)?;
+    if !created {
+        return Err(status::Custom(
+            Status::Conflict,
+            Json(json!({
+                "error": "Permission already exists"
+            })),
+        ));
+    }
+    println!("✓ Permission {} created by {}", permission.name, user.user_id);
+
+    Ok(status::Custom(
+        Status::Created,
+        Json(json!({
+            "name": permission.name,
+            "description": permission.description,
+            "roles": []
+        })),
+    ))
+}
+
+/// Delete a permission and its role assignments
+#[delete("/permissions/<name>")]
+pub async fn delete_permission(
+    _write: WriteAccess,
+    user: HasPermission<PermissionsManage>,
+    mut db: Connection<Postgres>,
+    name: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    if permissions::is_built_in(name) {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "Built-in permissions can't be deleted"
+            })),
+        ));
+    }
+
+    if !permission_repo::delete(&mut db, name).await.map_err(database_error)? {
+        return Err(permission_not_found());
+    }
+    println!("✓ Permission {} deleted by {}", name, user.user_id);
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "Permission deleted"
+        })),
+    ))
+}
+
+/// Grant a permission to everyone with a role
+#[put("/roles/<role>/permissions/<name>")]
+pub async fn grant_permission(
+    _write: WriteAccess,
+    user: HasPermission<PermissionsManage>,
+    mut db: Connection<Postgres>,
+    role: &str,
+    name: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    if role.is_empty() || role.len() > MAX_ROLE_LEN {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": format!("Role names must be between 1 and {} characters", MAX_ROLE_LEN)
+            })),
+        ));
+    }
+
+    if !permission_repo::exists(&mut db, name).await.map_err(database_error)? {
+        return Err(permission_not_found());
+    }
+
+    permission_repo::grant(&mut db, role, name).await.map_err(database_error)?;
+    println!("✓ Permission {} granted to role {} by {}", name, role, user.user_id);
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "role": role,
+            "permission": name
+        })),
+    ))
+}
+
+/// Take a permission away from a role
+#[delete("/roles/<role>/permissions/<name>")]
+pub async fn revoke_permission(
+    _write: WriteAccess,
+    user: HasPermission<PermissionsManage>,
+    mut db: Connection<Postgres>,
+    role: &str,
+    name: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    // Keep at least one way back in
+    if role == "admin" && name == PERMISSIONS_MANAGE {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "The admin role can't lose permissions.manage"
+            })),
+        ));
+    }
+
+    if !permission_repo::revoke(&mut db, role, name).await.map_err(database_error)? {
+        return Err(status::Custom(
+            Status::NotFound,
+            Json(json!({
+                "error": "This role doesn't have that permission"
+            })),
+        ));
+    }
+    println!("✓ Permission {} revoked from role {} by {}", name, role, user.user_id);
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "Permission revoked"
+        })),
+    ))
+}
+
+fn permission_not_found() -> status::Custom<Json<Value>> {
+    status::Custom(
+        Status::NotFound,
+        Json(json!({
+            "error": "Permission not found"
+        })),
+    )
+}
+
+fn database_error(e: sqlx::Error) -> status::Custom<Json<Value>> {
+    eprintln!("Database error: {}", e);
+    status::Custom(
+        Status::InternalServerError,
+        Json(json!({
+            "error": "Database error occurred"
+        })),
+    )
+}
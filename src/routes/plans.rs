@@ -0,0 +1,185 @@
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::{Json, Value, json};
+use rocket_db_pools::Connection;
+use uuid::Uuid;
+
+use crate::auth::guard::HasPermission;
+use crate::auth::permissions::PlansManage;
+use crate::auth::plans;
+use crate::body_limits::JsonBody;
+use crate::maintenance::WriteAccess;
+use crate::models::plan::{NewEntitlement, NewPlan};
+use crate::repositories::{organizations, plans as plan_repo, users};
+use crate::Postgres;
+
+/// List plans, lowest first
+#[get("/plans")]
+pub async fn list_plans(
+    _user: HasPermission<PlansManage>,
+    mut db: Connection<Postgres>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let plans = plan_repo::list(&mut db).await.map_err(database_error)?;
+
+    Ok(status::Custom(Status::Ok, Json(json!({ "plans": plans }))))
+}
+
+/// Create a plan for the app to check with `RequiresPlan`
+#[post("/plans", data = "<plan>")]
+pub async fn create_plan(
+    _write: WriteAccess,
+    user: HasPermission<PlansManage>,
+    mut db: Connection<Postgres>,
+    plan: JsonBody<NewPlan>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    if !plans::is_valid_name(&plan.name) {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "Invalid plan name",
+                "details": "Use lowercase letters, digits, dashes and underscores, e.g. team-plus"
+            })),
+        ));
+    }
+
+    let created = plan_repo::create(&mut db, &plan.name, plan.description.as_deref(), plan.rank)
+        .await
+        .map_err(database_error)?;
+    if !created {
+        return Err(status::Custom(
+            Status::Conflict,
+            Json(json!({
+                "error": "Plan already exists"
+            })),
+        ));
+    }
+    println!("✓ Plan {} created by {}", plan.name, user.user_id);
+
+    Ok(status::Custom(
+        Status::Created,
+        Json(json!({
+            "name": plan.name,
+            "description": plan.description,
+            "rank": plan.rank
+        })),
+    ))
+}
+
+/// List the entitlements of a user or an organization, or all of them
+#[get("/entitlements?<user_id>&<organization_id>")]
+pub async fn list_entitlements(
+    _user: HasPermission<PlansManage>,
+    mut db: Connection<Postgres>,
+    user_id: Option<&str>,
+    organization_id: Option<&str>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let (user_id, organization_id) = (parse_filter(user_id)?, parse_filter(organization_id)?);
+    let entitlements = plan_repo::list_entitlements(&mut db, user_id, organization_id)
+        .await
+        .map_err(database_error)?;
+
+    Ok(status::Custom(Status::Ok, Json(json!({ "entitlements": entitlements }))))
+}
+
+/// Grant a plan to a user, or to every member of an organization
+#[post("/entitlements", data = "<entitlement>")]
+pub async fn grant_entitlement(
+    _write: WriteAccess,
+    user: HasPermission<PlansManage>,
+    mut db: Connection<Postgres>,
+    entitlement: JsonBody<NewEntitlement>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let target_exists = match (entitlement.user_id, entitlement.organization_id) {
+        (Some(user_id), None) => users::find_by_id(&mut db, user_id).await.map_err(database_error)?.is_some(),
+        (None, Some(organization_id)) => organizations::exists(&mut db, organization_id).await.map_err(database_error)?,
+        _ => {
+            return Err(status::Custom(
+                Status::BadRequest,
+                Json(json!({
+                    "error": "Give either user_id or organization_id"
+                })),
+            ));
+        }
+    };
+    if !target_exists {
+        return Err(status::Custom(
+            Status::NotFound,
+            Json(json!({
+                "error": "User or organization not found"
+            })),
+        ));
+    }
+    if !plan_repo::exists(&mut db, &entitlement.plan).await.map_err(database_error)? {
+        return Err(status::Custom(
+            Status::NotFound,
+            Json(json!({
+                "error": "Plan not found"
+            })),
+        ));
+    }
+    if let (Some(starts_at), Some(ends_at)) = (entitlement.starts_at, entitlement.ends_at)
+        && ends_at <= starts_at
+    {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "ends_at must be after starts_at"
+            })),
+        ));
+    }
+
+    let granted = plan_repo::grant(&mut db, &entitlement).await.map_err(database_error)?;
+    println!("✓ Plan {} granted by {}", granted.plan, user.user_id);
+
+    Ok(status::Custom(Status::Created, Json(json!({ "entitlement": granted }))))
+}
+
+/// Revoke an entitlement
+#[delete("/entitlements/<id>")]
+pub async fn revoke_entitlement(
+    _write: WriteAccess,
+    user: HasPermission<PlansManage>,
+    mut db: Connection<Postgres>,
+    id: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let id = Uuid::parse_str(id).map_err(|_| entitlement_not_found())?;
+    if !plan_repo::revoke(&mut db, id).await.map_err(database_error)? {
+        return Err(entitlement_not_found());
+    }
+    println!("✓ Entitlement {} revoked by {}", id, user.user_id);
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "Entitlement revoked"
+        })),
+    ))
+}
+
+fn parse_filter(id: Option<&str>) -> Result<Option<Uuid>, status::Custom<Json<Value>>> {
+    id.map(Uuid::parse_str).transpose().map_err(|_| {
+        status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "Invalid id"
+            })),
+        )
+    })
+}
+
+fn entitlement_not_found() -> status::Custom<Json<Value>> {
+    status::Custom(
+        Status::NotFound,
+        Json(json!({
+            "error": "Entitlement not found"
+        })),
+    )
+}
+
+fn database_error(e: sqlx::Error) -> status::Custom<Json<Value>> {
+    eprintln!("Database error: {}", e);
+    status::Custom(
+        Status::InternalServerError,
+        Json(json!({ "error": "Database error occurred" })),
+    )
+}
@@ -0,0 +1,190 @@
+use chrono::{Duration, Utc};
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::{Json, Value, json};
+use rocket::State;
+use rocket_db_pools::Connection;
+use std::net::IpAddr;
+
+use crate::auth::guard::RegisteredUser;
+use crate::auth::access_tokens::AccessTokens;
+use crate::auth::jwt::JwtService;
+use crate::body_limits::JsonBody;
+use crate::models::qr_login::{ApproveQrLogin, PollQrLogin};
+use crate::models::session::SessionDevice;
+use crate::repositories::{qr_logins, users};
+use crate::routes::auth::start_session;
+use crate::Postgres;
+
+/// How long a QR code can be scanned and its login picked up
+pub const QR_LOGIN_LIFETIME_MINUTES: i64 = 5;
+
+/// Start a cross-device login on a signed-out device
+///
+/// Render `code` as a QR code and keep `poll_token` private to this device.
+#[post("/qr-login")]
+pub async fn start_qr_login(
+    mut db: Connection<Postgres>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let code = uuid::Uuid::new_v4().to_string();
+    let poll_token = uuid::Uuid::new_v4().to_string();
+    let expires_at = Utc::now() + Duration::minutes(QR_LOGIN_LIFETIME_MINUTES);
+
+    match qr_logins::create(&mut db, &code, &poll_token, expires_at).await {
+        Ok(request) => Ok(status::Custom(
+            Status::Created,
+            Json(json!({
+                "code": request.code,
+                "poll_token": poll_token,
+                "expires_at": request.expires_at.to_rfc3339()
+            })),
+        )),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Failed to start QR login"
+                })),
+            ))
+        }
+    }
+}
+
+/// Approve a scanned QR code from a device that is already signed in
+#[post("/qr-login/approve", data = "<approval>")]
+pub async fn approve_qr_login(
+    user: RegisteredUser,
+    mut db: Connection<Postgres>,
+    approval: JsonBody<ApproveQrLogin>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let user_id = match uuid::Uuid::parse_str(&user.user_id) {
+        Ok(id) => id,
+        Err(_) => {
+            return Err(status::Custom(
+                Status::Unauthorized,
+                Json(json!({
+                    "error": "Invalid token subject"
+                })),
+            ));
+        }
+    };
+
+    match qr_logins::approve(&mut db, &approval.code, user_id).await {
+        Ok(true) => Ok(status::Custom(
+            Status::Ok,
+            Json(json!({
+                "message": "Login approved"
+            })),
+        )),
+        Ok(false) => Err(status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "Invalid or expired QR code"
+            })),
+        )),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ))
+        }
+    }
+}
+
+/// Poll a QR login from the requesting device; returns a JWT once approved
+///
+/// Responds `202 Accepted` while waiting for approval.
+#[post("/qr-login/poll", data = "<poll>")]
+pub async fn poll_qr_login(
+    mut db: Connection<Postgres>,
+    jwt: &State<JwtService>,
+    access_tokens: &State<AccessTokens>,
+    ip: Option<IpAddr>,
+    device: SessionDevice,
+    poll: JsonBody<PollQrLogin>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let invalid = || {
+        status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": "Invalid or expired login request"
+            })),
+        )
+    };
+
+    let request = match qr_logins::find_by_poll_token(&mut db, &poll.poll_token).await {
+        Ok(Some(request)) => request,
+        Ok(None) => return Err(invalid()),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ));
+        }
+    };
+
+    if request.consumed_at.is_some() || request.expires_at < Utc::now() {
+        return Err(invalid());
+    }
+
+    if request.approved_user_id.is_none() {
+        return Ok(status::Custom(
+            Status::Accepted,
+            Json(json!({
+                "status": "pending",
+                "expires_at": request.expires_at.to_rfc3339()
+            })),
+        ));
+    }
+
+    // Claim the approval so the token is issued only once
+    let user_id = match qr_logins::consume(&mut db, &poll.poll_token).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => return Err(invalid()),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ));
+        }
+    };
+
+    let user = match users::find_by_id(&mut db, user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Err(invalid()),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Json(json!({
+                    "error": "Database error occurred"
+                })),
+            ));
+        }
+    };
+
+    let token = start_session(&mut db, jwt, access_tokens, &user, ip, &device).await?;
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "Login successful",
+            "token": token,
+            "user": {
+                "id": user.id.to_string(),
+                "email": user.email,
+                "created_at": user.created_at.to_rfc3339()
+            }
+        })),
+    ))
+}
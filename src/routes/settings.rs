@@ -0,0 +1,159 @@
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::{Json, Value, json};
+use rocket::State;
+use rocket_db_pools::Connection;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::auth::guard::AdminUser;
+use crate::body_limits::JsonBody;
+use crate::maintenance::WriteAccess;
+use crate::settings::{self, Definition, Settings, SettingsError};
+use crate::Postgres;
+
+/// Body for overriding a setting
+#[derive(Debug, Deserialize)]
+pub struct SettingUpdate {
+    pub value: Value,
+}
+
+/// Every runtime setting, with its value in effect and its configured default
+#[get("/settings")]
+pub async fn list_settings(
+    _admin: AdminUser,
+    settings: &State<Settings>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    ensure_enabled(settings)?;
+    let views: Vec<Value> = settings::BUILT_IN.iter().map(|definition| setting_view(settings, definition)).collect();
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "settings": views
+        })),
+    ))
+}
+
+/// One runtime setting
+#[get("/settings/<key>")]
+pub async fn get_setting(
+    _admin: AdminUser,
+    settings: &State<Settings>,
+    key: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    ensure_enabled(settings)?;
+    let definition = settings::definition(key).ok_or_else(unknown_setting)?;
+    Ok(status::Custom(Status::Ok, Json(setting_view(settings, definition))))
+}
+
+/// Override a setting on every instance
+///
+/// The value is checked like the configuration it replaces; other
+/// instances pick it up as soon as they're notified.
+#[put("/settings/<key>", data = "<update>")]
+pub async fn update_setting(
+    _write: WriteAccess,
+    admin: AdminUser,
+    mut db: Connection<Postgres>,
+    settings: &State<Settings>,
+    key: &str,
+    update: JsonBody<SettingUpdate>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    ensure_enabled(settings)?;
+    let definition = settings::definition(key).ok_or_else(unknown_setting)?;
+    let admin_id = Uuid::parse_str(&admin.user_id).map_err(|_| {
+        status::Custom(
+            Status::Unauthorized,
+            Json(json!({
+                "error": "Invalid token subject"
+            })),
+        )
+    })?;
+
+    match settings.set(&mut db, definition.key, update.into_inner().value, admin_id).await {
+        Ok(_) => {}
+        Err(SettingsError::Unknown) => return Err(unknown_setting()),
+        Err(SettingsError::Invalid(message)) => {
+            return Err(status::Custom(
+                Status::BadRequest,
+                Json(json!({
+                    "error": "Invalid setting value",
+                    "details": message
+                })),
+            ));
+        }
+        Err(SettingsError::Database(e)) => return Err(database_error(e)),
+    }
+    println!("✓ Setting {} changed by admin {}", definition.key, admin_id);
+
+    Ok(status::Custom(Status::Ok, Json(setting_view(settings, definition))))
+}
+
+/// Drop a setting's override, going back to the configured value
+#[delete("/settings/<key>")]
+pub async fn reset_setting(
+    _write: WriteAccess,
+    admin: AdminUser,
+    mut db: Connection<Postgres>,
+    settings: &State<Settings>,
+    key: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    ensure_enabled(settings)?;
+    let definition = settings::definition(key).ok_or_else(unknown_setting)?;
+    if !settings.clear(&mut db, definition.key).await.map_err(database_error)? {
+        return Err(status::Custom(
+            Status::NotFound,
+            Json(json!({
+                "error": "Setting isn't overridden"
+            })),
+        ));
+    }
+    println!("✓ Setting {} reset to the configuration by admin {}", definition.key, admin.user_id);
+
+    Ok(status::Custom(Status::Ok, Json(setting_view(settings, definition))))
+}
+
+fn setting_view(settings: &Settings, definition: &Definition) -> Value {
+    let stored = settings.stored(definition.key);
+    json!({
+        "key": definition.key,
+        "description": definition.description,
+        "value": definition.read(&settings.current()),
+        "default": definition.read(settings.configured()),
+        "overridden": stored.is_some(),
+        "updated_by": stored.as_ref().and_then(|stored| stored.updated_by),
+        "updated_at": stored.as_ref().map(|stored| stored.updated_at)
+    })
+}
+
+fn ensure_enabled(settings: &Settings) -> Result<(), status::Custom<Json<Value>>> {
+    if settings.is_enabled() {
+        return Ok(());
+    }
+    Err(status::Custom(
+        Status::NotFound,
+        Json(json!({
+            "error": "Runtime settings are disabled"
+        })),
+    ))
+}
+
+fn unknown_setting() -> status::Custom<Json<Value>> {
+    status::Custom(
+        Status::NotFound,
+        Json(json!({
+            "error": "Unknown setting"
+        })),
+    )
+}
+
+fn database_error(e: sqlx::Error) -> status::Custom<Json<Value>> {
+    eprintln!("Database error: {}", e);
+    status::Custom(
+        Status::InternalServerError,
+        Json(json!({
+            "error": "Database error occurred"
+        })),
+    )
+}
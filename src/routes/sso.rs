@@ -0,0 +1,165 @@
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::{Json, Value, json};
+use rocket::State;
+use rocket_db_pools::Connection;
+use uuid::Uuid;
+
+use crate::auth::guard::AdminUser;
+use crate::auth::oauth::OAuthProviders;
+use crate::auth::sso;
+use crate::body_limits::JsonBody;
+use crate::config::AppConfig;
+use crate::maintenance::WriteAccess;
+use crate::models::sso_mapping::NewSsoMapping;
+use crate::repositories::{organizations, sso_mappings};
+use crate::routes::organizations::assignable_role;
+use crate::Postgres;
+
+/// Longest claim path or value (the columns are VARCHAR(255))
+const MAX_CLAIM_LENGTH: usize = 255;
+
+/// Longest platform role (`users.role` is VARCHAR(20))
+const MAX_ROLE_LENGTH: usize = 20;
+
+/// A sign-in provider's mapping rules, the ones that win first
+#[get("/sso/<provider>/mappings")]
+pub async fn list_mappings(
+    _admin: AdminUser,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    providers: &State<OAuthProviders>,
+    provider: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    ensure_provider(config, providers, provider)?;
+    let mappings = sso_mappings::list(&mut db, provider).await.map_err(database_error)?;
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "provider": provider,
+            "mappings": mappings
+        })),
+    ))
+}
+
+/// Add a rule giving a platform role, an organization membership or both to people with a claim
+///
+/// Applies from the next time each of them signs in at the provider.
+#[post("/sso/<provider>/mappings", data = "<mapping>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn create_mapping(
+    _write: WriteAccess,
+    admin: AdminUser,
+    mut db: Connection<Postgres>,
+    config: &State<AppConfig>,
+    providers: &State<OAuthProviders>,
+    provider: &str,
+    mapping: JsonBody<NewSsoMapping>,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    ensure_provider(config, providers, provider)?;
+    let mut mapping = mapping.into_inner();
+    mapping.claim = mapping.claim.trim().to_string();
+
+    let bad_request = |message: String| {
+        status::Custom(
+            Status::BadRequest,
+            Json(json!({
+                "error": message
+            })),
+        )
+    };
+    if mapping.claim.is_empty() || mapping.claim.len() > MAX_CLAIM_LENGTH {
+        return Err(bad_request(format!("claim must be between 1 and {} characters", MAX_CLAIM_LENGTH)));
+    }
+    if mapping.value.is_empty() || mapping.value.len() > MAX_CLAIM_LENGTH {
+        return Err(bad_request(format!("value must be between 1 and {} characters", MAX_CLAIM_LENGTH)));
+    }
+    if mapping.role.as_ref().is_some_and(|role| role.is_empty() || role.len() > MAX_ROLE_LENGTH) {
+        return Err(bad_request(format!("role must be between 1 and {} characters", MAX_ROLE_LENGTH)));
+    }
+    match (mapping.organization_id, &mapping.organization_role) {
+        (None, Some(_)) => return Err(bad_request("organization_role needs an organization_id".to_string())),
+        (None, None) if mapping.role.is_none() => {
+            return Err(bad_request("A mapping needs a role, an organization_id or both".to_string()));
+        }
+        (None, None) => {}
+        (Some(organization_id), role) => {
+            if !organizations::exists(&mut db, organization_id).await.map_err(database_error)? {
+                return Err(bad_request("Unknown organization".to_string()));
+            }
+            let role = role.as_deref().unwrap_or("member");
+            mapping.organization_role = Some(assignable_role(&mut db, organization_id, role).await?);
+        }
+    }
+
+    let created = sso_mappings::create(&mut db, provider, &mapping).await.map_err(database_error)?;
+    println!(
+        "✓ SSO mapping {} ({} = {}) added to {} by admin {}",
+        created.id, created.claim, created.value, provider, admin.user_id
+    );
+
+    Ok(status::Custom(
+        Status::Created,
+        Json(json!({
+            "mapping": created
+        })),
+    ))
+}
+
+/// Remove a rule; what it granted is taken away as people next sign in
+#[delete("/sso/<provider>/mappings/<id>")]
+pub async fn delete_mapping(
+    _write: WriteAccess,
+    admin: AdminUser,
+    mut db: Connection<Postgres>,
+    provider: &str,
+    id: &str,
+) -> Result<status::Custom<Json<Value>>, status::Custom<Json<Value>>> {
+    let not_found = || {
+        status::Custom(
+            Status::NotFound,
+            Json(json!({
+                "error": "Mapping not found"
+            })),
+        )
+    };
+    let id = Uuid::parse_str(id).map_err(|_| not_found())?;
+    if !sso_mappings::delete(&mut db, provider, id).await.map_err(database_error)? {
+        return Err(not_found());
+    }
+    println!("✓ SSO mapping {} removed from {} by admin {}", id, provider, admin.user_id);
+
+    Ok(status::Custom(
+        Status::Ok,
+        Json(json!({
+            "message": "Mapping removed"
+        })),
+    ))
+}
+
+fn ensure_provider(
+    config: &AppConfig,
+    providers: &OAuthProviders,
+    provider: &str,
+) -> Result<(), status::Custom<Json<Value>>> {
+    if sso::is_available(config, providers, provider) {
+        return Ok(());
+    }
+    Err(status::Custom(
+        Status::NotFound,
+        Json(json!({
+            "error": "Unknown sign-in provider"
+        })),
+    ))
+}
+
+fn database_error(e: sqlx::Error) -> status::Custom<Json<Value>> {
+    eprintln!("Database error: {}", e);
+    status::Custom(
+        Status::InternalServerError,
+        Json(json!({
+            "error": "Database error occurred"
+        })),
+    )
+}
@@ -0,0 +1,144 @@
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rocket::http::hyper::{self, server::conn::Http, service::service_fn};
+use rocket::http::{Header, Method};
+use rocket::local::asynchronous::Client;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::task::JoinSet;
+
+/// How long a new connection may take to be ready, e.g. to finish a TLS handshake
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Finishes setting up one accepted connection
+pub(crate) type Connecting<S> = Pin<Box<dyn Future<Output = io::Result<S>> + Send>>;
+
+/// Connections for [`serve`] to answer, for listeners Rocket can't bind itself
+#[rocket::async_trait]
+pub(crate) trait Listener: Send {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    /// The next connection and its peer's address, if it has one
+    ///
+    /// Slow setup, like a TLS handshake, belongs in the returned future: it
+    /// runs in the connection's own task, so it doesn't hold up the next accept.
+    async fn accept(&self) -> io::Result<(Connecting<Self::Stream>, Option<SocketAddr>)>;
+}
+
+/// Answer `listener`'s connections with `client`'s application until SIGINT,
+/// SIGTERM or a requested shutdown
+///
+/// Requests are dispatched in-process, and response bodies are sent whole
+/// rather than streamed. At shutdown the listener is dropped first, then
+/// in-flight requests get Rocket's shutdown grace period to finish.
+pub(crate) async fn serve<L: Listener>(client: Client, listener: L) -> io::Result<()> {
+    let grace = Duration::from_secs(client.rocket().config().shutdown.grace.into());
+    let shutdown = client.rocket().shutdown();
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let client = Arc::new(client);
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((connecting, remote)) => {
+                    connections.spawn(serve_connection(client.clone(), connecting, remote));
+                }
+                Err(e) => eprintln!("Accept error: {}", e),
+            },
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            _ = terminate.recv() => break,
+            _ = interrupt.recv() => break,
+            _ = shutdown.clone() => break,
+        }
+    }
+
+    drop(listener);
+    client.rocket().shutdown().notify();
+    let drained = tokio::time::timeout(grace, async {
+        while connections.join_next().await.is_some() {}
+    })
+    .await;
+    if drained.is_err() {
+        eprintln!("⚠ Aborting {} connection(s) still open after the grace period", connections.len());
+        connections.shutdown().await;
+    }
+
+    // Every connection held a clone, so this is the last one
+    if let Ok(client) = Arc::try_unwrap(client) {
+        client.terminate().await;
+    }
+    Ok(())
+}
+
+/// Serve HTTP on one connection, closing it gracefully at shutdown
+async fn serve_connection<S>(client: Arc<Client>, connecting: Connecting<S>, remote: Option<SocketAddr>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let stream = match tokio::time::timeout(CONNECT_TIMEOUT, connecting).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => return eprintln!("Connection setup failed: {}", e),
+        Err(_) => return eprintln!("Connection setup timed out"),
+    };
+
+    let shutdown = client.rocket().shutdown();
+    let service = service_fn(move |request| dispatch(client.clone(), request, remote));
+    let connection = Http::new().serve_connection(stream, service);
+    tokio::pin!(connection);
+
+    let result = tokio::select! {
+        result = &mut connection => result,
+        _ = shutdown => {
+            connection.as_mut().graceful_shutdown();
+            connection.await
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("Connection error: {}", e);
+    }
+}
+
+async fn dispatch(
+    client: Arc<Client>,
+    request: hyper::Request<hyper::Body>,
+    remote: Option<SocketAddr>,
+) -> Result<hyper::Response<hyper::Body>, hyper::Error> {
+    let (parts, body) = request.into_parts();
+    let Ok(method) = parts.method.as_str().parse::<Method>() else {
+        return Ok(empty_response(501));
+    };
+    let uri = parts.uri.path_and_query().map(|uri| uri.as_str()).unwrap_or("/");
+
+    let mut request = client.req(method, uri);
+    for (name, value) in parts.headers.iter() {
+        if let Ok(value) = value.to_str() {
+            request.add_header(Header::new(name.as_str().to_string(), value.to_string()));
+        }
+    }
+    if let Some(remote) = remote {
+        request = request.remote(remote);
+    }
+    let response = request.body(hyper::body::to_bytes(body).await?).dispatch().await;
+
+    let mut builder = hyper::Response::builder().status(response.status().code);
+    for header in response.headers().iter() {
+        builder = builder.header(header.name().as_str(), header.value());
+    }
+    let body = response.into_bytes().await.unwrap_or_default();
+    Ok(builder.body(hyper::Body::from(body)).unwrap_or_else(|e| {
+        eprintln!("Invalid response: {}", e);
+        empty_response(500)
+    }))
+}
+
+fn empty_response(status: u16) -> hyper::Response<hyper::Body> {
+    let mut response = hyper::Response::new(hyper::Body::empty());
+    *response.status_mut() = status.try_into().expect("valid status code");
+    response
+}
@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rocket::fairing::AdHoc;
+use rocket_db_pools::Database;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::postgres::PgListener;
+use sqlx::{PgConnection, PgPool};
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use crate::config::{AppConfig, RegistrationMode};
+use crate::models::setting::StoredSetting;
+use crate::repositories::settings as stored_settings;
+use crate::Postgres;
+
+/// Postgres NOTIFY channel telling every instance that a stored setting changed
+pub const CHANNEL: &str = "settings_changed";
+
+/// How often stored settings are reloaded anyway, in case a notification was missed
+pub const RELOAD_INTERVAL_SECONDS: u64 = 300;
+
+/// A part of the configuration admins can override at runtime
+///
+/// Each setting is one `AppConfig` field. Its default is what the
+/// configuration says; an override stored in the `settings` table replaces
+/// it in `Settings::current`, on every instance. Built-in settings are
+/// declared with `settings!` below.
+pub trait Setting: Send + Sync + 'static {
+    const KEY: &'static str;
+    type Value: Serialize + DeserializeOwned;
+
+    fn read(config: &AppConfig) -> Self::Value;
+    fn write(config: &mut AppConfig, value: Self::Value);
+
+    /// Refuse values that don't make sense with the rest of `config`
+    fn validate(_value: &Self::Value, _config: &AppConfig) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// A setting for the admin endpoints, whatever its type
+pub struct Definition {
+    pub key: &'static str,
+    pub description: &'static str,
+    read: fn(&AppConfig) -> Value,
+    apply: fn(&mut AppConfig, Value) -> Result<(), String>,
+}
+
+impl Definition {
+    pub const fn of<S: Setting>(description: &'static str) -> Self {
+        Definition {
+            key: S::KEY,
+            description,
+            read: read_json::<S>,
+            apply: apply_json::<S>,
+        }
+    }
+
+    /// The setting's value in `config`, as JSON
+    pub fn read(&self, config: &AppConfig) -> Value {
+        (self.read)(config)
+    }
+
+    /// Check `value` and put it into `config`
+    pub fn apply(&self, config: &mut AppConfig, value: Value) -> Result<(), String> {
+        (self.apply)(config, value)
+    }
+}
+
+fn read_json<S: Setting>(config: &AppConfig) -> Value {
+    serde_json::to_value(S::read(config)).expect("settings serialize to JSON")
+}
+
+fn apply_json<S: Setting>(config: &mut AppConfig, value: Value) -> Result<(), String> {
+    let value: S::Value = serde_json::from_value(value).map_err(|e| e.to_string())?;
+    S::validate(&value, config)?;
+    S::write(config, value);
+    Ok(())
+}
+
+/// Declare settings mapped to `AppConfig` fields, and list them in `BUILT_IN`
+macro_rules! settings {
+    ($(
+        #[doc = $description:literal]
+        $marker:ident: $key:literal => $value:ty = $field:ident $(, valid if |$v:ident, $c:ident| $check:expr)?;
+    )*) => {
+        $(
+            #[doc = $description]
+            pub struct $marker;
+
+            impl Setting for $marker {
+                const KEY: &'static str = $key;
+                type Value = $value;
+
+                fn read(config: &AppConfig) -> $value {
+                    config.$field.clone()
+                }
+
+                fn write(config: &mut AppConfig, value: $value) {
+                    config.$field = value;
+                }
+
+                $(
+                    fn validate($v: &$value, $c: &AppConfig) -> Result<(), String> {
+                        $check
+                    }
+                )?
+            }
+        )*
+
+        /// Every setting admins can override through `/admin/settings`
+        pub const BUILT_IN: &[Definition] = &[$(Definition::of::<$marker>($description.trim_ascii())),*];
+    };
+}
+
+settings! {
+    /// Who may register: `open`, `invite-only` or `closed`
+    Registration: "registration.mode" => RegistrationMode = registration_mode;
+    /// Whether new accounts wait for an admin's approval
+    SignupApproval: "registration.signup_approval" => bool = signup_approval;
+    /// Roles that must use MFA
+    MfaRequiredRoles: "mfa.required_roles" => Vec<String> = mfa_required_roles,
+        valid if |roles, _config| match roles.iter().any(|role| role.is_empty() || role.contains(char::is_whitespace)) {
+            true => Err("roles must be non-empty names without spaces".to_string()),
+            false => Ok(()),
+        };
+    /// Days users have to enroll in MFA once their role requires it
+    MfaGraceDays: "mfa.grace_days" => u64 = mfa_grace_days;
+    /// Failed logins within the window that lock an address out; 0 disables the lockout
+    LoginLockoutAttempts: "login.lockout_attempts" => u32 = login_lockout_attempts;
+    /// Minutes failed logins count toward the lockout and delays
+    LoginLockoutMinutes: "login.lockout_minutes" => u64 = login_lockout_minutes,
+        valid if |minutes, config| {
+            let retention = config.login_history_retention_days * 24 * 60;
+            if *minutes == 0 {
+                Err("the lockout window must be at least one minute".to_string())
+            } else if retention > 0 && retention < *minutes {
+                Err("the lockout window can't be longer than login attempts are kept".to_string())
+            } else {
+                Ok(())
+            }
+        };
+    /// Milliseconds each recent failure delays the next login by
+    LoginDelayMs: "login.delay_ms" => u64 = login_delay_ms;
+    /// Longest delay of a login, in milliseconds
+    LoginDelayMaxMs: "login.delay_max_ms" => u64 = login_delay_max_ms;
+}
+
+/// Look up a built-in setting by key
+pub fn definition(key: &str) -> Option<&'static Definition> {
+    BUILT_IN.iter().find(|definition| definition.key == key)
+}
+
+/// Why a setting couldn't be changed
+#[derive(Debug)]
+pub enum SettingsError {
+    Unknown,
+    Invalid(String),
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for SettingsError {
+    fn from(e: sqlx::Error) -> Self {
+        SettingsError::Database(e)
+    }
+}
+
+/// What's stored, and the configuration with it applied
+pub struct Snapshot {
+    stored: HashMap<String, StoredSetting>,
+    current: Arc<AppConfig>,
+}
+
+/// The configuration with the overrides admins stored
+///
+/// Managed as Rocket state; clones share the cache. Stored settings are
+/// loaded at liftoff and kept in memory. Changes are published with Postgres
+/// NOTIFY, and every instance reloads when it hears of one, or every
+/// `RELOAD_INTERVAL_SECONDS` otherwise. With `ROCKET_RUNTIME_SETTINGS=false`
+/// nothing is loaded and the configuration is used as is.
+#[derive(Clone)]
+pub struct Settings {
+    config: Arc<AppConfig>,
+    snapshot: Arc<watch::Sender<Arc<Snapshot>>>,
+}
+
+impl Settings {
+    pub fn new(config: &AppConfig) -> Self {
+        let config = Arc::new(config.clone());
+        let snapshot = Snapshot {
+            stored: HashMap::new(),
+            current: config.clone(),
+        };
+        Settings {
+            config,
+            snapshot: Arc::new(watch::Sender::new(Arc::new(snapshot))),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.runtime_settings
+    }
+
+    /// The configuration as deployed, without overrides
+    pub fn configured(&self) -> &AppConfig {
+        &self.config
+    }
+
+    /// The configuration with every stored override applied
+    pub fn current(&self) -> Arc<AppConfig> {
+        self.snapshot.borrow().current.clone()
+    }
+
+    /// The value of one setting in effect
+    pub fn get<S: Setting>(&self) -> S::Value {
+        S::read(&self.current())
+    }
+
+    /// The override stored under `key`, if any
+    pub fn stored(&self, key: &str) -> Option<StoredSetting> {
+        self.snapshot.borrow().stored.get(key).cloned()
+    }
+
+    /// Be told whenever the stored settings change
+    pub fn subscribe(&self) -> watch::Receiver<Arc<Snapshot>> {
+        self.snapshot.subscribe()
+    }
+
+    /// Load the stored settings again
+    pub async fn reload(&self, conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+
+        let stored: HashMap<String, StoredSetting> = stored_settings::list(conn)
+            .await?
+            .into_iter()
+            .map(|setting| (setting.key.clone(), setting))
+            .collect();
+        let mut current = (*self.config).clone();
+        for definition in BUILT_IN {
+            if let Some(setting) = stored.get(definition.key)
+                && let Err(e) = definition.apply(&mut current, setting.value.clone())
+            {
+                eprintln!("Ignoring stored setting {}: {}", definition.key, e);
+            }
+        }
+
+        self.snapshot.send_replace(Arc::new(Snapshot {
+            stored,
+            current: Arc::new(current),
+        }));
+        Ok(())
+    }
+
+    /// Override a built-in setting, after checking the value against the current configuration
+    pub async fn set(&self, conn: &mut PgConnection, key: &str, value: Value, updated_by: Uuid) -> Result<StoredSetting, SettingsError> {
+        let definition = definition(key).ok_or(SettingsError::Unknown)?;
+        let mut candidate = (*self.current()).clone();
+        definition.apply(&mut candidate, value.clone()).map_err(SettingsError::Invalid)?;
+        Ok(self.store(conn, key, &value, updated_by).await?)
+    }
+
+    /// Store a value under `key` as is, for settings with their own endpoints
+    pub async fn store(&self, conn: &mut PgConnection, key: &str, value: &Value, updated_by: Uuid) -> Result<StoredSetting, sqlx::Error> {
+        let setting = stored_settings::upsert(conn, key, value, updated_by).await?;
+        stored_settings::notify_changed(conn, CHANNEL, key).await?;
+        self.reload(conn).await?;
+        Ok(setting)
+    }
+
+    /// Remove the override under `key`; false if there was none
+    pub async fn clear(&self, conn: &mut PgConnection, key: &str) -> Result<bool, sqlx::Error> {
+        if !stored_settings::delete(conn, key).await? {
+            return Ok(false);
+        }
+        stored_settings::notify_changed(conn, CHANNEL, key).await?;
+        self.reload(conn).await?;
+        Ok(true)
+    }
+}
+
+/// Fairing that loads the stored settings at liftoff and reloads them when told of changes
+pub fn fairing() -> AdHoc {
+    AdHoc::on_liftoff("Runtime Settings", |rocket| {
+        Box::pin(async move {
+            let (Some(settings), Some(db)) = (rocket.state::<Settings>(), Postgres::fetch(rocket)) else {
+                eprintln!("Runtime settings not loaded: missing Settings or database");
+                return;
+            };
+            if !settings.is_enabled() {
+                return;
+            }
+
+            let (settings, pool) = (settings.clone(), PgPool::clone(db));
+            reload(&settings, &pool).await;
+
+            // Without a listener, changes made elsewhere still arrive with the periodic reload
+            let mut listener = match PgListener::connect(&settings.configured().database_url).await {
+                Ok(mut listener) => match listener.listen(CHANNEL).await {
+                    Ok(()) => Some(listener),
+                    Err(e) => {
+                        eprintln!("Failed to listen on {}: {}", CHANNEL, e);
+                        None
+                    }
+                },
+                Err(e) => {
+                    eprintln!("Failed to start settings listener: {}", e);
+                    None
+                }
+            };
+
+            rocket::tokio::spawn(async move {
+                loop {
+                    let interval = rocket::tokio::time::sleep(Duration::from_secs(RELOAD_INTERVAL_SECONDS));
+                    match listener.as_mut() {
+                        // The listener reconnects by itself after connection loss
+                        Some(listener) => {
+                            rocket::tokio::select! {
+                                received = listener.recv() => if let Err(e) = received {
+                                    eprintln!("Settings listener error: {}", e);
+                                    rocket::tokio::time::sleep(Duration::from_secs(1)).await;
+                                },
+                                _ = interval => {}
+                            }
+                        }
+                        None => interval.await,
+                    }
+                    reload(&settings, &pool).await;
+                }
+            });
+        })
+    })
+}
+
+async fn reload(settings: &Settings, pool: &PgPool) {
+    let reloaded = match pool.acquire().await {
+        Ok(mut conn) => settings.reload(&mut conn).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = reloaded {
+        eprintln!("Database error: {}", e);
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::Utc;
+use rocket::fairing::AdHoc;
+use rocket_db_pools::Database;
+use sqlx::{PgConnection, PgPool};
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::events::{self, SecurityEvent};
+use crate::repositories::login_attempts::{self, LoginSuccess};
+use crate::repositories::sessions;
+use crate::Postgres;
+
+/// Writes queued before senders wait for the writer task
+const QUEUE_CAPACITY: usize = 10_000;
+
+/// Queued writes that trigger a flush before the interval is up
+const MAX_BATCH: usize = 500;
+
+enum Write {
+    Touch(Uuid),
+    Login(LoginSuccess),
+    Event(SecurityEvent),
+    /// Flush now and report back
+    Flush(oneshot::Sender<()>),
+}
+
+#[derive(Default)]
+struct Batch {
+    touches: HashSet<Uuid>,
+    logins: Vec<LoginSuccess>,
+    events: Vec<SecurityEvent>,
+}
+
+impl Batch {
+    fn len(&self) -> usize {
+        self.touches.len() + self.logins.len() + self.events.len()
+    }
+
+    async fn flush(&mut self, pool: &PgPool) {
+        if self.len() == 0 {
+            return;
+        }
+        let batch = std::mem::take(self);
+        let mut conn = match pool.acquire().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                eprintln!("Telemetry flush failed, {} write(s) dropped: {}", batch.len(), e);
+                return;
+            }
+        };
+
+        if !batch.touches.is_empty() {
+            let ids: Vec<Uuid> = batch.touches.into_iter().collect();
+            if let Err(e) = sessions::touch_many(&mut conn, &ids).await {
+                eprintln!("Telemetry flush failed, {} session touch(es) dropped: {}", ids.len(), e);
+            }
+        }
+        if !batch.logins.is_empty()
+            && let Err(e) = login_attempts::record_successes(&mut conn, &batch.logins).await
+        {
+            eprintln!("Telemetry flush failed, {} login(s) dropped: {}", batch.logins.len(), e);
+        }
+        if !batch.events.is_empty()
+            && let Err(e) = events::publish_all(&mut conn, &batch.events).await
+        {
+            eprintln!("Telemetry flush failed, {} security event(s) dropped: {}", batch.events.len(), e);
+        }
+    }
+}
+
+/// Writes done on the login and request paths: session touches, successful
+/// logins (with the user's last-login details) and security events
+///
+/// Managed as Rocket state. With `ROCKET_TELEMETRY_FLUSH_MS` set, writes are
+/// queued and a background task writes them in batches on that interval,
+/// when a batch fills up, and at shutdown, so requests don't wait on them.
+/// Queued writes are lost if the process dies before they're flushed, and
+/// become visible (including to the login lockout and the event stream) only
+/// once flushed. Without it, every write happens right away on the caller's
+/// connection. Clones share the queue.
+#[derive(Clone)]
+pub struct TelemetryWriter {
+    sender: Option<mpsc::Sender<Write>>,
+    /// Taken by the writer task at liftoff
+    receiver: Arc<Mutex<Option<mpsc::Receiver<Write>>>>,
+    flush_every: Duration,
+}
+
+impl TelemetryWriter {
+    /// A writer that writes right away
+    pub fn direct() -> Self {
+        TelemetryWriter {
+            sender: None,
+            receiver: Arc::new(Mutex::new(None)),
+            flush_every: Duration::ZERO,
+        }
+    }
+
+    /// A writer that batches, flushing every `flush_every`
+    pub fn batched(flush_every: Duration) -> Self {
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        TelemetryWriter {
+            sender: Some(sender),
+            receiver: Arc::new(Mutex::new(Some(receiver))),
+            flush_every,
+        }
+    }
+
+    pub fn from_config(config: &AppConfig) -> Self {
+        match config.telemetry_flush_ms {
+            0 => TelemetryWriter::direct(),
+            ms => TelemetryWriter::batched(Duration::from_millis(ms)),
+        }
+    }
+
+    /// Record that a session was just used
+    ///
+    /// Batched touches are best-effort: they're dropped while the queue is full.
+    pub async fn touch_session(&self, conn: &mut PgConnection, session_id: Uuid) -> Result<(), sqlx::Error> {
+        match &self.sender {
+            Some(sender) => {
+                let _ = sender.try_send(Write::Touch(session_id));
+                Ok(())
+            }
+            None => sessions::touch(conn, session_id).await,
+        }
+    }
+
+    /// Record a successful login and the user's last-login details
+    pub async fn record_login(
+        &self,
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        email: &str,
+        ip: Option<IpAddr>,
+    ) -> Result<(), sqlx::Error> {
+        match &self.sender {
+            Some(sender) => {
+                let login = LoginSuccess {
+                    user_id,
+                    email: email.to_string(),
+                    ip,
+                    at: Utc::now(),
+                };
+                self.enqueue(sender, Write::Login(login)).await
+            }
+            None => login_attempts::record_success(conn, user_id, email, ip).await,
+        }
+    }
+
+    /// Record a security event in the audit log and publish it, logging instead of failing the caller
+    pub async fn emit(&self, conn: &mut PgConnection, event: SecurityEvent) {
+        let result = match &self.sender {
+            Some(sender) => self.enqueue(sender, Write::Event(event)).await,
+            None => events::publish(conn, &event).await,
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to publish security event: {}", e);
+        }
+    }
+
+    /// Write everything queued so far
+    pub async fn flush(&self) {
+        if let Some(sender) = &self.sender {
+            let (done, flushed) = oneshot::channel();
+            if sender.send(Write::Flush(done)).await.is_ok() {
+                let _ = flushed.await;
+            }
+        }
+    }
+
+    async fn enqueue(&self, sender: &mpsc::Sender<Write>, write: Write) -> Result<(), sqlx::Error> {
+        sender
+            .send(write)
+            .await
+            .map_err(|_| sqlx::Error::Protocol("telemetry writer has stopped".to_string()))
+    }
+
+    async fn run(mut receiver: mpsc::Receiver<Write>, pool: PgPool, flush_every: Duration) {
+        let mut batch = Batch::default();
+        let mut ticker = rocket::tokio::time::interval(flush_every);
+        loop {
+            rocket::tokio::select! {
+                write = receiver.recv() => match write {
+                    Some(Write::Touch(id)) => {
+                        batch.touches.insert(id);
+                    }
+                    Some(Write::Login(login)) => batch.logins.push(login),
+                    Some(Write::Event(event)) => batch.events.push(event),
+                    Some(Write::Flush(done)) => {
+                        batch.flush(&pool).await;
+                        let _ = done.send(());
+                        continue;
+                    }
+                    None => {
+                        batch.flush(&pool).await;
+                        return;
+                    }
+                },
+                _ = ticker.tick() => {
+                    batch.flush(&pool).await;
+                    continue;
+                }
+            }
+            if batch.len() >= MAX_BATCH {
+                batch.flush(&pool).await;
+            }
+        }
+    }
+}
+
+/// Fairing that starts the batching writer task at liftoff and flushes it at shutdown
+pub fn fairing() -> AdHoc {
+    AdHoc::on_liftoff("Telemetry Writer", |rocket| {
+        Box::pin(async move {
+            let (Some(writer), Some(db)) = (rocket.state::<TelemetryWriter>(), Postgres::fetch(rocket)) else {
+                eprintln!("Telemetry writer not started: missing TelemetryWriter or database");
+                return;
+            };
+            let Some(receiver) = writer.receiver.lock().unwrap().take() else {
+                return;
+            };
+            rocket::tokio::spawn(TelemetryWriter::run(receiver, PgPool::clone(db), writer.flush_every));
+        })
+    })
+}
+
+/// Fairing that writes out queued telemetry when the server shuts down
+pub fn shutdown_flush() -> AdHoc {
+    AdHoc::on_shutdown("Telemetry Flush", |rocket| {
+        Box::pin(async move {
+            if let Some(writer) = rocket.state::<TelemetryWriter>() {
+                writer.flush().await;
+            }
+        })
+    })
+}
@@ -0,0 +1,241 @@
+use std::ops::{Deref, DerefMut};
+
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket_db_pools::Connection;
+use sqlx::postgres::PgRow;
+use sqlx::{Connection as _, FromRow, PgConnection, Transaction};
+use uuid::Uuid;
+
+use crate::auth::guard::{forbid, AuthenticatedUser, ForbiddenReason};
+use crate::config::{AppConfig, Tenancy};
+use crate::repositories::users;
+use crate::Postgres;
+
+/// Setting holding the tenant of the current transaction, for row-level security policies
+pub const ORGANIZATION_SETTING: &str = "app.organization_id";
+
+/// The schema holding an organization's tables with `Tenancy::Schemas`
+pub fn schema_name(organization_id: Uuid) -> String {
+    format!("tenant_{}", organization_id.simple())
+}
+
+/// The organization the signed-in user acts for
+///
+/// Fails with 401 when the request isn't authenticated and 403 (code
+/// `no_organization`) for users outside any organization. Tenant data is
+/// only reached through `begin`:
+/// ```rust,ignore
+/// #[get("/notes")]
+/// async fn notes(tenant: Tenant, mut db: Connection<Postgres>) -> ... {
+///     let mut tx = tenant.begin(&mut db).await.map_err(database_error)?;
+///     let notes = tenancy::list::<Note>(&mut tx).await.map_err(database_error)?;
+///     tx.commit().await.map_err(database_error)?;
+/// }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Tenant {
+    pub organization_id: Uuid,
+    pub user_id: Uuid,
+    pub strategy: Tenancy,
+}
+
+impl Tenant {
+    /// The tenant `user_id` acts for; `None` if the user isn't in an organization
+    pub async fn for_user(conn: &mut PgConnection, user_id: Uuid, strategy: Tenancy) -> Result<Option<Tenant>, sqlx::Error> {
+        let user = users::find_by_id(conn, user_id).await?;
+        Ok(user.and_then(|user| user.organization_id).map(|organization_id| Tenant {
+            organization_id,
+            user_id,
+            strategy,
+        }))
+    }
+
+    /// Start a transaction scoped to the tenant
+    ///
+    /// `app.organization_id` is set for its duration, and with
+    /// `Tenancy::Schemas` the tenant's schema (created on first use) comes
+    /// first on the `search_path`, so unqualified table names resolve to it.
+    pub async fn begin<'c>(&self, conn: &'c mut PgConnection) -> Result<TenantTx<'c>, sqlx::Error> {
+        let mut tx = conn.begin().await?;
+        sqlx::query("SELECT set_config($1, $2, true)")
+            .bind(ORGANIZATION_SETTING)
+            .bind(self.organization_id.to_string())
+            .execute(&mut *tx)
+            .await?;
+
+        if self.strategy == Tenancy::Schemas {
+            let schema = schema_name(self.organization_id);
+            let exists: bool = sqlx::query_scalar("SELECT to_regnamespace($1) IS NOT NULL")
+                .bind(&schema)
+                .fetch_one(&mut *tx)
+                .await?;
+            if !exists {
+                sqlx::query(&format!("CREATE SCHEMA IF NOT EXISTS {}", schema))
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            sqlx::query("SELECT set_config('search_path', $1, true)")
+                .bind(format!("{}, public", schema))
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        Ok(TenantTx {
+            organization_id: self.organization_id,
+            strategy: self.strategy,
+            tx,
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Tenant {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let user = match request.guard::<AuthenticatedUser>().await {
+            Outcome::Success(user) => user,
+            Outcome::Error(e) => return Outcome::Error(e),
+            Outcome::Forward(status) => return Outcome::Forward(status),
+        };
+
+        let user_id = match Uuid::parse_str(&user.user_id) {
+            Ok(id) => id,
+            Err(_) => return Outcome::Error((Status::Unauthorized, ())),
+        };
+
+        let mut db = match request.guard::<Connection<Postgres>>().await {
+            Outcome::Success(db) => db,
+            _ => return Outcome::Error((Status::InternalServerError, ())),
+        };
+
+        let strategy = request
+            .rocket()
+            .state::<AppConfig>()
+            .map(|config| config.tenancy)
+            .unwrap_or(Tenancy::Rows);
+
+        match Tenant::for_user(&mut db, user_id, strategy).await {
+            Ok(Some(tenant)) => Outcome::Success(tenant),
+            Ok(None) => forbid(request, ForbiddenReason::NoOrganization),
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                Outcome::Error((Status::InternalServerError, ()))
+            }
+        }
+    }
+}
+
+/// A transaction scoped to one tenant; dereferences to the connection for other queries
+///
+/// Dropping it without `commit` rolls back.
+pub struct TenantTx<'c> {
+    organization_id: Uuid,
+    strategy: Tenancy,
+    tx: Transaction<'c, sqlx::Postgres>,
+}
+
+impl TenantTx<'_> {
+    pub fn organization_id(&self) -> Uuid {
+        self.organization_id
+    }
+
+    pub async fn commit(self) -> Result<(), sqlx::Error> {
+        self.tx.commit().await
+    }
+
+    /// `organization_id = $n` with `Tenancy::Rows`; nothing to add with `Tenancy::Schemas`
+    fn filter(&self, n: usize) -> Option<String> {
+        match self.strategy {
+            Tenancy::Rows => Some(format!("organization_id = ${}", n)),
+            Tenancy::Schemas => None,
+        }
+    }
+}
+
+impl Deref for TenantTx<'_> {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &PgConnection {
+        &self.tx
+    }
+}
+
+impl DerefMut for TenantTx<'_> {
+    fn deref_mut(&mut self) -> &mut PgConnection {
+        &mut self.tx
+    }
+}
+
+/// A table holding tenants' rows, reached with `find`, `list` and `delete`
+///
+/// With `Tenancy::Rows` the table needs an `organization_id` column, which
+/// those helpers always filter on; with `Tenancy::Schemas` it's created in
+/// each tenant's schema and needs none.
+/// ```rust,ignore
+/// impl TenantOwned for Note {
+///     const TABLE: &'static str = "notes";
+///     const COLUMNS: &'static str = "id, organization_id, body, created_at";
+/// }
+/// ```
+pub trait TenantOwned: for<'r> FromRow<'r, PgRow> + Send + Unpin {
+    const TABLE: &'static str;
+    const COLUMNS: &'static str;
+}
+
+/// The tenant's row with `id`; another tenant's is `None`
+pub async fn find<T: TenantOwned>(tx: &mut TenantTx<'_>, id: Uuid) -> Result<Option<T>, sqlx::Error> {
+    let filter = tx.filter(2).map(|filter| format!(" AND {}", filter)).unwrap_or_default();
+    let sql = format!("SELECT {} FROM {} WHERE id = $1{}", T::COLUMNS, T::TABLE, filter);
+    let mut query = sqlx::query_as::<_, T>(&sql).bind(id);
+    if tx.strategy == Tenancy::Rows {
+        query = query.bind(tx.organization_id);
+    }
+    query.fetch_optional(&mut **tx).await
+}
+
+/// All of the tenant's rows
+pub async fn list<T: TenantOwned>(tx: &mut TenantTx<'_>) -> Result<Vec<T>, sqlx::Error> {
+    let filter = tx.filter(1).map(|filter| format!(" WHERE {}", filter)).unwrap_or_default();
+    let sql = format!("SELECT {} FROM {}{}", T::COLUMNS, T::TABLE, filter);
+    let mut query = sqlx::query_as::<_, T>(&sql);
+    if tx.strategy == Tenancy::Rows {
+        query = query.bind(tx.organization_id);
+    }
+    query.fetch_all(&mut **tx).await
+}
+
+/// Delete the tenant's row with `id`; false if the tenant has none
+pub async fn delete<T: TenantOwned>(tx: &mut TenantTx<'_>, id: Uuid) -> Result<bool, sqlx::Error> {
+    let filter = tx.filter(2).map(|filter| format!(" AND {}", filter)).unwrap_or_default();
+    let sql = format!("DELETE FROM {} WHERE id = $1{}", T::TABLE, filter);
+    let mut query = sqlx::query(&sql).bind(id);
+    if tx.strategy == Tenancy::Rows {
+        query = query.bind(tx.organization_id);
+    }
+    Ok(query.execute(&mut **tx).await?.rows_affected() > 0)
+}
+
+/// Also have Postgres refuse other tenants' rows of `table` outside `TenantTx`'s filters
+///
+/// Enables and forces row-level security with a policy matching
+/// `organization_id` to the transaction's tenant, so raw queries can't
+/// cross tenants either and queries outside a `TenantTx` see no rows.
+/// Superusers and roles with `BYPASSRLS` aren't subject to it. Call from a
+/// migration; `table` is put into SQL as is.
+pub async fn enable_row_security(conn: &mut PgConnection, table: &str) -> Result<(), sqlx::Error> {
+    let statements = [
+        format!("ALTER TABLE {} ENABLE ROW LEVEL SECURITY", table),
+        format!("ALTER TABLE {} FORCE ROW LEVEL SECURITY", table),
+        format!("DROP POLICY IF EXISTS tenant_isolation ON {}", table),
+        format!(
+            "CREATE POLICY tenant_isolation ON {} USING (organization_id = NULLIF(current_setting('{}', true), '')::uuid)",
+            table, ORGANIZATION_SETTING
+        ),
+    ];
+    for statement in statements {
+        sqlx::query(&statement).execute(&mut *conn).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,215 @@
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::models::user::User;
+use crate::repositories::{email_verifications, password_resets, users};
+use crate::test_support::unique_email;
+
+/// Password given to factory users unless overridden
+pub const DEFAULT_PASSWORD: &str = "password123";
+
+/// bcrypt's minimum cost keeps fixtures fast; verification doesn't depend on the cost
+pub(crate) const FIXTURE_BCRYPT_COST: u32 = 4;
+
+/// A user inserted by `UserFactory`, with the plaintext password it was given
+#[derive(Debug, Clone)]
+pub struct TestUser {
+    pub user: User,
+    pub password: String,
+}
+
+impl TestUser {
+    pub fn id(&self) -> Uuid {
+        self.user.id
+    }
+
+    pub fn email(&self) -> &str {
+        &self.user.email
+    }
+}
+
+/// Builder for users inserted directly into the database
+///
+/// Example:
+/// ```rust,ignore
+/// let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+/// let token = app.token_for(&admin.user).await;
+/// ```
+#[derive(Debug, Clone)]
+pub struct UserFactory {
+    email: Option<String>,
+    password: String,
+    role: String,
+    verified: bool,
+}
+
+impl Default for UserFactory {
+    fn default() -> Self {
+        UserFactory {
+            email: None,
+            password: DEFAULT_PASSWORD.to_string(),
+            role: "user".to_string(),
+            verified: false,
+        }
+    }
+}
+
+impl UserFactory {
+    /// A regular user with an unverified email
+    pub fn new() -> Self {
+        UserFactory::default()
+    }
+
+    /// A regular user whose email is already verified
+    pub fn verified() -> Self {
+        UserFactory {
+            verified: true,
+            ..UserFactory::default()
+        }
+    }
+
+    /// Use a fixed email instead of a unique generated one
+    pub fn with_email(mut self, email: &str) -> Self {
+        self.email = Some(email.to_string());
+        self
+    }
+
+    pub fn with_password(mut self, password: &str) -> Self {
+        self.password = password.to_string();
+        self
+    }
+
+    pub fn with_role(mut self, role: &str) -> Self {
+        self.role = role.to_string();
+        self
+    }
+
+    /// Insert the user, panicking on failure
+    pub async fn insert(self, pool: &PgPool) -> TestUser {
+        let mut conn = pool.acquire().await.expect("Failed to acquire connection");
+        let email = self.email.unwrap_or_else(unique_email);
+        let password_hash = bcrypt::hash(&self.password, FIXTURE_BCRYPT_COST)
+            .expect("Failed to hash password");
+
+        let mut user = users::create(&mut conn, &email, &password_hash, &self.role)
+            .await
+            .expect("Failed to insert user");
+
+        if self.verified {
+            users::mark_email_verified(&mut conn, user.id)
+                .await
+                .expect("Failed to verify user");
+            user = users::find_by_id(&mut conn, user.id)
+                .await
+                .expect("Failed to reload user")
+                .expect("Inserted user disappeared");
+        }
+
+        TestUser {
+            user,
+            password: self.password,
+        }
+    }
+}
+
+/// Which table an `EmailTokenFactory` writes to
+#[derive(Debug, Clone, Copy)]
+enum EmailTokenKind {
+    PasswordReset,
+    EmailVerification,
+}
+
+/// Builder for password reset and email verification tokens
+///
+/// Example:
+/// ```rust,ignore
+/// let token = EmailTokenFactory::password_reset(user.id()).expired().insert(&app.pool).await;
+/// ```
+#[derive(Debug, Clone)]
+pub struct EmailTokenFactory {
+    kind: EmailTokenKind,
+    user_id: Uuid,
+    token: Option<String>,
+    expires_in: Duration,
+    used: bool,
+}
+
+impl EmailTokenFactory {
+    /// A valid, unused password reset token
+    pub fn password_reset(user_id: Uuid) -> Self {
+        EmailTokenFactory {
+            kind: EmailTokenKind::PasswordReset,
+            user_id,
+            token: None,
+            expires_in: Duration::hours(1),
+            used: false,
+        }
+    }
+
+    /// A valid, unused email verification token
+    pub fn email_verification(user_id: Uuid) -> Self {
+        EmailTokenFactory {
+            kind: EmailTokenKind::EmailVerification,
+            user_id,
+            token: None,
+            expires_in: Duration::hours(24),
+            used: false,
+        }
+    }
+
+    /// Use a fixed token value instead of a random one
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    /// Make the token expire after the given duration (negative for already expired)
+    pub fn expires_in(mut self, duration: Duration) -> Self {
+        self.expires_in = duration;
+        self
+    }
+
+    /// A token that expired an hour ago
+    pub fn expired(self) -> Self {
+        self.expires_in(Duration::hours(-1))
+    }
+
+    /// A token that has already been consumed
+    pub fn used(mut self) -> Self {
+        self.used = true;
+        self
+    }
+
+    /// Insert the token and return its value, panicking on failure
+    pub async fn insert(self, pool: &PgPool) -> String {
+        let mut conn = pool.acquire().await.expect("Failed to acquire connection");
+        let token = self.token.unwrap_or_else(|| Uuid::new_v4().to_string());
+        let expires_at = Utc::now() + self.expires_in;
+
+        match self.kind {
+            EmailTokenKind::PasswordReset => {
+                password_resets::create(&mut conn, self.user_id, &token, expires_at)
+                    .await
+                    .expect("Failed to insert reset token");
+                if self.used {
+                    password_resets::mark_used(&mut conn, &token)
+                        .await
+                        .expect("Failed to mark reset token used");
+                }
+            }
+            EmailTokenKind::EmailVerification => {
+                email_verifications::create(&mut conn, self.user_id, &token, expires_at)
+                    .await
+                    .expect("Failed to insert verification token");
+                if self.used {
+                    email_verifications::mark_used(&mut conn, &token)
+                        .await
+                        .expect("Failed to mark verification token used");
+                }
+            }
+        }
+
+        token
+    }
+}
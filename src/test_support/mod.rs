@@ -0,0 +1,274 @@
+pub mod factories;
+
+use std::sync::Arc;
+
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::asynchronous::{Client, LocalResponse};
+use rocket::serde::json::{json, Value};
+use rocket::Route;
+use sqlx::PgPool;
+use testcontainers::runners::AsyncRunner;
+use testcontainers::ContainerAsync;
+use testcontainers_modules::postgres::Postgres as PostgresImage;
+use tokio::sync::OnceCell;
+
+use crate::auth::access_tokens::AccessTokens;
+use crate::auth::jwt::JwtService;
+use crate::authz::Policy;
+use crate::config::{AppConfig, EmailTransport, PasswordHashing, SignerConfig};
+use crate::email::memory::MemoryEmailSender;
+use crate::models::session::SessionDevice;
+use crate::models::user::User;
+use crate::repositories::users;
+use crate::{build_rocket_with_policy, migrations};
+
+/// Migrations against a shared `TEST_DATABASE_URL` run once per test binary
+static SHARED_DATABASE_MIGRATED: OnceCell<()> = OnceCell::const_new();
+
+/// A migrated Postgres database and a Rocket client wired to it
+///
+/// By default each `TestApp` starts its own throwaway Postgres container via
+/// testcontainers (Docker required). Set `TEST_DATABASE_URL` to run against
+/// an existing database instead; tests then share it, so use
+/// `unique_email` to keep their data apart.
+///
+/// Example:
+/// ```rust,ignore
+/// #[rocket::async_test]
+/// async fn my_test() {
+///     let app = TestApp::spawn().await;
+///     let email = unique_email();
+///     app.register(&email, "password123").await;
+///     let token = app.login_token(&email, "password123").await;
+/// }
+/// ```
+pub struct TestApp {
+    pub client: Client,
+    pub pool: PgPool,
+    pub database_url: String,
+    _container: Option<ContainerAsync<PostgresImage>>,
+}
+
+impl TestApp {
+    /// Start a database, run migrations and boot the application
+    pub async fn spawn() -> TestApp {
+        TestApp::spawn_with(|_| {}).await
+    }
+
+    /// Like `spawn`, but lets the caller adjust the configuration first
+    pub async fn spawn_with(configure: impl FnOnce(&mut AppConfig)) -> TestApp {
+        TestApp::start(configure, Policy::default(), Vec::new()).await
+    }
+
+    /// Like `spawn`, with a custom authorization policy engine
+    pub async fn spawn_with_policy(policy: Policy) -> TestApp {
+        TestApp::start(|_| {}, policy, Vec::new()).await
+    }
+
+    /// Like `spawn`, with the app's own routes mounted at `/`, e.g. to exercise request guards
+    pub async fn spawn_mounting(routes: Vec<Route>) -> TestApp {
+        TestApp::start(|_| {}, Policy::default(), routes).await
+    }
+
+    async fn start(configure: impl FnOnce(&mut AppConfig), policy: Policy, routes: Vec<Route>) -> TestApp {
+        let (database_url, container) = match std::env::var("TEST_DATABASE_URL") {
+            Ok(url) if !url.is_empty() => (url, None),
+            _ => {
+                let container = PostgresImage::default()
+                    .start()
+                    .await
+                    .expect("Failed to start Postgres container (is Docker running?)");
+                let host = container.get_host().await.expect("Failed to get container host");
+                let port = container
+                    .get_host_port_ipv4(5432)
+                    .await
+                    .expect("Failed to get container port");
+                let url = format!("postgres://postgres:postgres@{}:{}/postgres", host, port);
+                (url, Some(container))
+            }
+        };
+
+        let pool = PgPool::connect(&database_url)
+            .await
+            .expect("Failed to connect to test database");
+
+        if container.is_some() {
+            migrations::run_migrations(&pool).await.expect("Failed to run migrations");
+        } else {
+            SHARED_DATABASE_MIGRATED
+                .get_or_init(|| async {
+                    migrations::run_migrations(&pool).await.expect("Failed to run migrations");
+                })
+                .await;
+        }
+
+        let mut config = test_config(database_url.clone());
+        configure(&mut config);
+
+        let jwt = JwtService::from_config(&config)
+            .await
+            .expect("Failed to initialize JWT signer");
+        let client = Client::tracked(build_rocket_with_policy(config, jwt, policy).mount("/", routes))
+            .await
+            .expect("Failed to build Rocket instance");
+
+        TestApp {
+            client,
+            pool,
+            database_url,
+            _container: container,
+        }
+    }
+
+    /// Emails sent by the application, captured in memory
+    pub fn mailbox(&self) -> &MemoryEmailSender {
+        self.client
+            .rocket()
+            .state::<Arc<MemoryEmailSender>>()
+            .expect("email transport is not `memory`")
+    }
+
+    /// Start a session for a user and return its access token, skipping the login endpoint
+    ///
+    /// The user is read again first, so the token carries their current token version.
+    pub async fn token_for(&self, user: &User) -> String {
+        let mut conn = self.pool.acquire().await.expect("Failed to acquire connection");
+        let user = &users::find_by_id(&mut conn, user.id)
+            .await
+            .expect("Failed to load user")
+            .expect("User doesn't exist");
+
+        let rocket = self.client.rocket();
+        let jwt = rocket.state::<JwtService>().expect("JwtService is not managed");
+        let access_tokens = rocket.state::<AccessTokens>().expect("AccessTokens is not managed");
+        let (_, token) = access_tokens
+            .issue(&mut conn, jwt, user, &SessionDevice::default(), access_tokens.session_expiry())
+            .await
+            .expect("Failed to issue token");
+        token
+    }
+
+    /// POST a JSON body
+    pub async fn post_json(&self, uri: &str, body: Value) -> LocalResponse<'_> {
+        self.client
+            .post(uri.to_string())
+            .header(ContentType::JSON)
+            .body(body.to_string())
+            .dispatch()
+            .await
+    }
+
+    /// POST a JSON body with a bearer token
+    pub async fn post_json_authorized(&self, uri: &str, token: &str, body: Value) -> LocalResponse<'_> {
+        self.client
+            .post(uri.to_string())
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", format!("Bearer {}", token)))
+            .body(body.to_string())
+            .dispatch()
+            .await
+    }
+
+    /// PUT a JSON body with a bearer token
+    pub async fn put_json_authorized(&self, uri: &str, token: &str, body: Value) -> LocalResponse<'_> {
+        self.client
+            .put(uri.to_string())
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", format!("Bearer {}", token)))
+            .body(body.to_string())
+            .dispatch()
+            .await
+    }
+
+    /// GET with a bearer token
+    pub async fn get_authorized(&self, uri: &str, token: &str) -> LocalResponse<'_> {
+        self.client
+            .get(uri.to_string())
+            .header(Header::new("Authorization", format!("Bearer {}", token)))
+            .dispatch()
+            .await
+    }
+
+    /// DELETE with a bearer token
+    pub async fn delete_authorized(&self, uri: &str, token: &str) -> LocalResponse<'_> {
+        self.client
+            .delete(uri.to_string())
+            .header(Header::new("Authorization", format!("Bearer {}", token)))
+            .dispatch()
+            .await
+    }
+
+    /// Register a user through the API, returning the response
+    pub async fn register(&self, email: &str, password: &str) -> LocalResponse<'_> {
+        self.post_json(
+            "/api/v1/auth/register",
+            json!({ "email": email, "password": password }),
+        )
+        .await
+    }
+
+    /// Log in through the API, returning the response
+    pub async fn login(&self, email: &str, password: &str) -> LocalResponse<'_> {
+        self.post_json(
+            "/api/v1/auth/login",
+            json!({ "email": email, "password": password }),
+        )
+        .await
+    }
+
+    /// Log in and return the issued token, panicking if login fails
+    pub async fn login_token(&self, email: &str, password: &str) -> String {
+        let response = self.login(email, password).await;
+        assert_eq!(response.status(), Status::Ok, "login failed for {}", email);
+        let body = response_json(response).await;
+        body["token"]
+            .as_str()
+            .expect("login response has no token")
+            .to_string()
+    }
+}
+
+/// Configuration used by `TestApp` before any caller adjustments
+///
+/// Emails are captured in memory; read them with `TestApp::mailbox`.
+/// Passwords use the same minimal bcrypt cost as fixtures, so logins don't
+/// re-hash them. Failed logins don't delay later ones.
+pub fn test_config(database_url: String) -> AppConfig {
+    let mut config = AppConfig::new(
+        database_url,
+        SignerConfig::Hmac {
+            secret: "test-secret-do-not-use-in-production".to_string(),
+        },
+    );
+    config.email_transport = EmailTransport::Memory;
+    config.password_hashing = PasswordHashing::Bcrypt {
+        cost: factories::FIXTURE_BCRYPT_COST,
+    };
+    config.login_delay_ms = 0;
+    // Test apps share one database; stored overrides would leak between them
+    config.runtime_settings = false;
+    config
+}
+
+/// Extract the `token` query parameter from the first link in an email body
+///
+/// Covers random tokens and JWT action tokens (base64url segments joined by dots).
+pub fn token_from_email(body: &str) -> Option<String> {
+    let start = body.find("token=")? + "token=".len();
+    let token: String = body[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_' || *c == '.')
+        .collect();
+    Some(token).filter(|token| !token.is_empty())
+}
+
+/// An email address no other test will use
+pub fn unique_email() -> String {
+    format!("user-{}@example.com", uuid::Uuid::new_v4())
+}
+
+/// Read a response body as JSON
+pub async fn response_json(response: LocalResponse<'_>) -> Value {
+    let body = response.into_string().await.unwrap_or_default();
+    serde_json::from_str(&body).unwrap_or_else(|_| panic!("response is not JSON: {}", body))
+}
@@ -0,0 +1,141 @@
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use rocket::local::asynchronous::Client;
+use rocket::{Build, Rocket};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+use crate::config::TlsConfig;
+use crate::server::{self, Connecting, Listener};
+
+#[derive(Debug)]
+pub enum TlsError {
+    /// A certificate or key file couldn't be read or holds nothing usable
+    File { path: PathBuf, message: String },
+    /// The certificates and key were read but rejected
+    Rustls(rustls::Error),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsError::File { path, message } => write!(f, "{}: {}", path.display(), message),
+            TlsError::Rustls(e) => write!(f, "Invalid TLS configuration: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+impl From<rustls::Error> for TlsError {
+    fn from(e: rustls::Error) -> Self {
+        TlsError::Rustls(e)
+    }
+}
+
+/// Read and check the certificates and key `config` points at
+///
+/// Fails if a file is missing or unparseable, if the key doesn't belong to
+/// the certificate, or if the client CA file holds no certificate.
+pub fn server_config(config: &TlsConfig) -> Result<ServerConfig, TlsError> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone()).with_safe_default_protocol_versions()?;
+    let builder = match &config.client_ca_path {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in certificates(path)? {
+                roots.add(cert)?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .map_err(|e| file_error(path, e))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let key = PrivateKeyDer::from_pem_file(&config.key_path).map_err(|e| file_error(&config.key_path, e))?;
+    let mut server = builder.with_single_cert(certificates(&config.cert_path)?, key)?;
+    server.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(server)
+}
+
+fn certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| file_error(path, e))?;
+    match certs.is_empty() {
+        true => Err(file_error(path, "no certificates found")),
+        false => Ok(certs),
+    }
+}
+
+fn file_error(path: &Path, message: impl fmt::Display) -> TlsError {
+    TlsError::File {
+        path: path.to_path_buf(),
+        message: message.to_string(),
+    }
+}
+
+/// Serve `rocket` over HTTPS on its configured address and port until
+/// SIGINT, SIGTERM or a requested shutdown
+///
+/// The certificates are checked before anything else starts. On SIGHUP they
+/// are read again and used for new connections; if they no longer load, the
+/// error is logged and the previous ones stay in use.
+pub async fn serve(rocket: Rocket<Build>, config: &TlsConfig) -> io::Result<()> {
+    let current = server_config(config).map_err(io::Error::other)?;
+    let current = Arc::new(RwLock::new(Arc::new(current)));
+
+    let client = Client::untracked(rocket).await.map_err(|e| io::Error::other(e.to_string()))?;
+    let address = SocketAddr::new(client.rocket().config().address, client.rocket().config().port);
+    let listener = HttpsListener {
+        tcp: TcpListener::bind(address).await?,
+        current: current.clone(),
+    };
+    println!("🚀 Listening on https://{}", listener.tcp.local_addr()?);
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    let config = config.clone();
+    let reload = tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            match server_config(&config) {
+                Ok(reloaded) => {
+                    *current.write().expect("TLS config lock poisoned") = Arc::new(reloaded);
+                    println!("✓ Reloaded TLS certificates");
+                }
+                Err(e) => eprintln!("⚠ Keeping the current TLS certificates: {}", e),
+            }
+        }
+    });
+
+    let result = server::serve(client, listener).await;
+    reload.abort();
+    result
+}
+
+struct HttpsListener {
+    tcp: TcpListener,
+    current: Arc<RwLock<Arc<ServerConfig>>>,
+}
+
+#[rocket::async_trait]
+impl Listener for HttpsListener {
+    type Stream = TlsStream<TcpStream>;
+
+    async fn accept(&self) -> io::Result<(Connecting<Self::Stream>, Option<SocketAddr>)> {
+        let (stream, remote) = self.tcp.accept().await?;
+        let acceptor = TlsAcceptor::from(self.current.read().expect("TLS config lock poisoned").clone());
+        Ok((Box::pin(acceptor.accept(stream)), Some(remote)))
+    }
+}
@@ -0,0 +1,85 @@
+use chrono::{TimeDelta, Utc};
+use rocket::Request;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::auth::access_tokens::AccessTokens;
+use crate::config::{AppConfig, ImpossibleTravel};
+use crate::devices;
+use crate::email::sender::Mailer;
+use crate::email::templates;
+use crate::events::{self, SecurityEvent, SecurityEventKind};
+use crate::repositories::{sessions, users};
+use crate::risk;
+
+/// Check a session just used by `request` for impossible travel, returning whether it was revoked for it
+///
+/// With `ROCKET_IMPOSSIBLE_TRAVEL` on, each use is compared with the country the
+/// session was last used from (`ROCKET_GEO_COUNTRY_HEADER`). A different country
+/// within `ROCKET_IMPOSSIBLE_TRAVEL_MINUTES` flags the session: the user is
+/// emailed, a `suspicious_session_use` event is published and, with `revoke`,
+/// the session ends. Each session is flagged once.
+pub async fn check(
+    request: &Request<'_>,
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    session_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let Some(config) = request.rocket().state::<AppConfig>() else {
+        return Ok(false);
+    };
+    if config.impossible_travel == ImpossibleTravel::Off {
+        return Ok(false);
+    }
+    let Some(country) = risk::geo_header(request, |config| config.geo_country_header.as_deref())
+        .and_then(devices::country_code)
+    else {
+        return Ok(false);
+    };
+
+    let Some(previous) = sessions::record_country(conn, session_id, &country).await? else {
+        return Ok(false);
+    };
+    let window = TimeDelta::minutes(config.impossible_travel_minutes as i64);
+    let previous_country = match previous.country {
+        Some(previous_country)
+            if previous_country != country && previous.flagged_at.is_none() && previous.seen_at > Utc::now() - window =>
+        {
+            previous_country
+        }
+        _ => return Ok(false),
+    };
+
+    let revoke = config.impossible_travel == ImpossibleTravel::Revoke;
+    if !sessions::flag_travel(conn, session_id, revoke).await? {
+        return Ok(false);
+    }
+    if revoke && let Some(access_tokens) = request.rocket().state::<AccessTokens>() {
+        access_tokens.forget_user(user_id);
+    }
+    println!(
+        "⚠ Session {} used from {} shortly after {}{}",
+        session_id,
+        country,
+        previous_country,
+        if revoke { ", revoked" } else { "" }
+    );
+
+    let kind = SecurityEventKind::SuspiciousSessionUse {
+        session_id,
+        previous_country: previous_country.clone(),
+        country: country.clone(),
+        revoked: revoke,
+    };
+    events::emit_event(conn, SecurityEvent::new(user_id, kind).from_ip(request.client_ip())).await;
+
+    if let (Some(user), Some(mailer)) = (users::find_by_id(conn, user_id).await?, request.rocket().state::<Mailer>())
+        && !user.is_guest()
+    {
+        let message = templates::suspicious_session_use(&user.email, &previous_country, &country, revoke);
+        if let Err(e) = mailer.send(message).await {
+            eprintln!("{}", e);
+        }
+    }
+    Ok(revoke)
+}
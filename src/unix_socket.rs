@@ -0,0 +1,74 @@
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixStream as StdUnixStream;
+use std::path::{Path, PathBuf};
+
+use rocket::local::asynchronous::Client;
+use rocket::{Build, Rocket};
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::server::{self, Connecting, Listener};
+
+/// Serve `rocket` on the Unix socket at `path` until SIGINT, SIGTERM or a
+/// requested shutdown
+///
+/// Meant for running behind a reverse proxy on the same host. A socket left
+/// behind by a server that crashed is replaced; the socket is removed again
+/// once the server stops accepting connections, before in-flight requests get
+/// Rocket's shutdown grace period to finish.
+pub async fn serve(rocket: Rocket<Build>, path: &Path) -> io::Result<()> {
+    let client = Client::untracked(rocket).await.map_err(|e| io::Error::other(e.to_string()))?;
+    let socket = SocketFile::bind(path)?;
+    println!("🚀 Listening on unix:{}", path.display());
+    server::serve(client, socket).await
+}
+
+/// The bound socket; its path is removed when this is dropped
+struct SocketFile {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl SocketFile {
+    fn bind(path: &Path) -> io::Result<Self> {
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and isn't a socket", path.display()),
+                ));
+            }
+            // Only a socket nobody answers on is stale
+            if StdUnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("another server is listening on {}", path.display()),
+                ));
+            }
+            std::fs::remove_file(path)?;
+        }
+        Ok(SocketFile {
+            listener: UnixListener::bind(path)?,
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl Listener for SocketFile {
+    type Stream = UnixStream;
+
+    async fn accept(&self) -> io::Result<(Connecting<UnixStream>, Option<SocketAddr>)> {
+        let (stream, _) = self.listener.accept().await?;
+        Ok((Box::pin(async { Ok(stream) }), None))
+    }
+}
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            eprintln!("Failed to remove {}: {}", self.path.display(), e);
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use std::convert::Infallible;
+
+use chrono::{DateTime, Utc};
+use rocket::fairing::AdHoc;
+use rocket::http::Header;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::route::Route;
+use rocket::{Build, Rocket};
+
+/// A version of the HTTP API, taken from the request path
+///
+/// Handlers are shared between versions; take `ApiVersion` as a guard to
+/// vary a response's shape when a new version changes it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ApiVersion {
+    /// Unversioned `/api/...` paths, kept for existing clients and deprecated
+    Legacy,
+    V1,
+}
+
+impl ApiVersion {
+    /// The version new clients should use
+    pub const CURRENT: ApiVersion = ApiVersion::V1;
+
+    /// The version of an `/api/...` path, or `None` for paths outside the API
+    pub fn from_path(path: &str) -> Option<ApiVersion> {
+        let rest = path.strip_prefix("/api/")?;
+        match rest.split('/').next() {
+            Some("v1") => Some(ApiVersion::V1),
+            _ => Some(ApiVersion::Legacy),
+        }
+    }
+
+    /// Path prefix routes of this version are mounted under
+    pub fn prefix(self) -> &'static str {
+        match self {
+            ApiVersion::Legacy => "/api",
+            ApiVersion::V1 => "/api/v1",
+        }
+    }
+
+    pub fn is_deprecated(self) -> bool {
+        self < ApiVersion::CURRENT
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiVersion {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let version = ApiVersion::from_path(request.uri().path().as_str()).unwrap_or(ApiVersion::CURRENT);
+        Outcome::Success(version)
+    }
+}
+
+/// Mount routes under `/api/v1/<scope>`, and under the legacy `/api/<scope>` when enabled
+pub fn mount(rocket: Rocket<Build>, scope: &str, routes: Vec<Route>, legacy: bool) -> Rocket<Build> {
+    let rocket = if legacy {
+        rocket.mount(format!("{}/{}", ApiVersion::Legacy.prefix(), scope), routes.clone())
+    } else {
+        rocket
+    };
+    rocket.mount(format!("{}/{}", ApiVersion::CURRENT.prefix(), scope), routes)
+}
+
+/// Fairing that marks responses from deprecated API versions
+///
+/// Adds `Deprecation: true`, a `Link` to the same path in the current
+/// version and, when a sunset date is configured, `Sunset`.
+pub fn deprecation_headers(sunset: Option<DateTime<Utc>>) -> AdHoc {
+    AdHoc::on_response("API Deprecation Headers", move |request, response| {
+        Box::pin(async move {
+            let path = request.uri().path();
+            let version = match ApiVersion::from_path(path.as_str()) {
+                Some(version) if version.is_deprecated() => version,
+                _ => return,
+            };
+
+            let successor = format!(
+                "{}{}",
+                ApiVersion::CURRENT.prefix(),
+                &path.as_str()[version.prefix().len()..]
+            );
+            response.set_header(Header::new("Deprecation", "true"));
+            response.set_header(Header::new("Link", format!("<{}>; rel=\"successor-version\"", successor)));
+            if let Some(sunset) = sunset {
+                response.set_header(Header::new("Sunset", sunset.format("%a, %d %b %Y %H:%M:%S GMT").to_string()));
+            }
+        })
+    })
+}
@@ -0,0 +1,136 @@
+use rocket::http::Status;
+use rocket::serde::json::json;
+
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, token_from_email, unique_email, TestApp};
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn email_change_is_confirmed_once_from_the_new_address() {
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+    let new_email = unique_email();
+
+    let response = app
+        .post_json_authorized(
+            "/api/v1/auth/me/email-change",
+            &token,
+            json!({ "new_email": new_email, "password": "wrong-password" }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let response = app
+        .post_json_authorized(
+            "/api/v1/auth/me/email-change",
+            &token,
+            json!({ "new_email": new_email, "password": user.password }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::Accepted);
+    let email = app.mailbox().last_to(&new_email).expect("confirmation sent to the new address");
+    let confirmation = token_from_email(&email.message.body).expect("token in confirmation email");
+
+    // Action tokens don't sign anyone in
+    let response = app.get_authorized("/api/v1/auth/me", &confirmation).await;
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    // Nor do they confirm a different action, and trying doesn't use them up
+    let response = app
+        .post_json("/api/v1/auth/delete/confirm", json!({ "token": confirmation }))
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let response = app
+        .post_json("/api/v1/auth/email-change/confirm", json!({ "token": confirmation }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    assert_eq!(body["user"]["email"], new_email.as_str());
+
+    let response = app
+        .post_json("/api/v1/auth/email-change/confirm", json!({ "token": confirmation }))
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+    assert_eq!(response_json(response).await["code"], "token_used");
+
+    assert_eq!(app.login(&new_email, &user.password).await.status(), Status::Ok);
+    assert_eq!(app.login(user.email(), &user.password).await.status(), Status::Unauthorized);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn account_deletion_requires_the_emailed_confirmation() {
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+
+    let response = app
+        .post_json_authorized("/api/v1/auth/me/delete", &token, json!({ "password": user.password }))
+        .await;
+    assert_eq!(response.status(), Status::Accepted);
+
+    // Nothing happens until the link is used
+    assert_eq!(app.get_authorized("/api/v1/auth/me", &token).await.status(), Status::Ok);
+
+    let email = app.mailbox().last_to(user.email()).expect("confirmation email sent");
+    assert_eq!(email.message.subject, "Confirm account deletion");
+    let confirmation = token_from_email(&email.message.body).expect("token in confirmation email");
+
+    let response = app
+        .post_json("/api/v1/auth/delete/confirm", json!({ "token": confirmation }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    assert_eq!(app.get_authorized("/api/v1/auth/me", &token).await.status(), Status::Unauthorized);
+    assert_eq!(app.login(user.email(), &user.password).await.status(), Status::Unauthorized);
+
+    let response = app
+        .post_json("/api/v1/auth/delete/confirm", json!({ "token": confirmation }))
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn password_change_notification_can_lock_the_account() {
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.login_token(user.email(), &user.password).await;
+
+    let response = app
+        .post_json_authorized(
+            "/api/v1/auth/change-password",
+            &token,
+            json!({ "current_password": user.password, "new_password": "newpassword456" }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let new_token = response_json(response).await["token"].as_str().unwrap().to_string();
+
+    let notice = app.mailbox().last_to(user.email()).expect("notification sent");
+    assert_eq!(notice.message.subject, "Your password was changed");
+    assert!(notice.message.body.contains("(UTC)"));
+    assert!(notice.message.body.contains("/secure-account?token="));
+    let secure = token_from_email(&notice.message.body).unwrap();
+
+    let response = app.post_json("/api/v1/auth/secure-account", json!({ "token": secure })).await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response_json(response).await["revoked_sessions"], 1);
+    assert_eq!(app.get_authorized("/api/v1/auth/me", &new_token).await.status(), Status::Unauthorized);
+
+    // A reset link follows, and resetting sends another notification
+    let reset = app.mailbox().last_to(user.email()).expect("reset email sent");
+    assert!(reset.message.body.contains("/reset-password?token="));
+    let reset = token_from_email(&reset.message.body).unwrap();
+    let response = app
+        .post_json("/api/v1/auth/reset-password", json!({ "token": reset, "new_password": "another789" }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(app.mailbox().last_to(user.email()).unwrap().message.subject, "Your password was changed");
+
+    let response = app.post_json("/api/v1/auth/secure-account", json!({ "token": secure })).await;
+    assert_eq!(response.status(), Status::BadRequest);
+    assert_eq!(response_json(response).await["code"], "token_used");
+}
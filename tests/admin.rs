@@ -0,0 +1,243 @@
+use rocket::http::Status;
+use rocket::serde::json::json;
+
+use rocket_auth_boilerplate::test_support::factories::{EmailTokenFactory, UserFactory};
+use rocket_auth_boilerplate::test_support::{response_json, token_from_email, unique_email, TestApp};
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn admin_endpoints_require_admin_role() {
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+
+    let response = app.get_authorized("/api/v1/admin/maintenance", &token).await;
+    assert_eq!(response.status(), Status::Forbidden);
+
+    let response = app.client.get("/api/v1/admin/maintenance").dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn maintenance_mode_blocks_writes_but_not_reads() {
+    let app = TestApp::spawn().await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let admin_token = app.token_for(&admin.user).await;
+    let user = UserFactory::new().insert(&app.pool).await;
+
+    let response = app
+        .post_json_authorized(
+            "/api/v1/admin/maintenance",
+            &admin_token,
+            json!({ "enabled": true, "message": "Upgrading" }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = app.register(&unique_email(), "password123").await;
+    assert_eq!(response.status(), Status::ServiceUnavailable);
+    let body = response_json(response).await;
+    assert_eq!(body["error"], "Service is under maintenance");
+    assert_eq!(body["details"], "Upgrading");
+
+    // Login and /me keep working
+    let token = app.login_token(user.email(), &user.password).await;
+    let response = app.get_authorized("/api/v1/auth/me", &token).await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = app
+        .post_json_authorized("/api/v1/admin/maintenance", &admin_token, json!({ "enabled": false }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = app.register(&unique_email(), "password123").await;
+    assert_eq!(response.status(), Status::Created);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn expired_and_used_reset_tokens_are_rejected() {
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+
+    let expired = EmailTokenFactory::password_reset(user.id()).expired().insert(&app.pool).await;
+    let response = app
+        .post_json(
+            "/api/v1/auth/reset-password",
+            json!({ "token": expired, "new_password": "new-password456" }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let used = EmailTokenFactory::password_reset(user.id()).used().insert(&app.pool).await;
+    let response = app
+        .post_json(
+            "/api/v1/auth/reset-password",
+            json!({ "token": used, "new_password": "new-password456" }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    // The original password still works
+    app.login_token(user.email(), &user.password).await;
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn stats_aggregate_signups_logins_and_sessions() {
+    let app = TestApp::spawn().await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let admin_token = app.token_for(&admin.user).await;
+
+    let before = response_json(app.get_authorized("/api/v1/admin/stats/logins?days=7", &admin_token).await).await;
+
+    let user = UserFactory::verified().insert(&app.pool).await;
+    app.login_token(user.email(), &user.password).await;
+    app.login(user.email(), "wrong-password").await;
+    app.login(&unique_email(), "password123").await;
+
+    let response = app.get_authorized("/api/v1/admin/stats/logins?days=7", &admin_token).await;
+    assert_eq!(response.status(), Status::Ok);
+    let after = response_json(response).await;
+    assert_eq!(after["logins"].as_array().unwrap().len(), 7);
+    assert!(after["succeeded"].as_i64().unwrap() > before["succeeded"].as_i64().unwrap());
+    assert!(after["failed"].as_i64().unwrap() >= before["failed"].as_i64().unwrap() + 2);
+
+    let body = response_json(app.get_authorized("/api/v1/admin/stats/signups", &admin_token).await).await;
+    assert_eq!(body["days"], 30);
+    assert!(body["signups"][29]["count"].as_i64().unwrap() >= 2);
+
+    let body = response_json(app.get_authorized("/api/v1/admin/stats", &admin_token).await).await;
+    assert!(body["users"]["admins"].as_i64().unwrap() >= 1);
+    assert!(body["sessions"]["active_sessions"].as_i64().unwrap() >= 2);
+
+    let response = app.get_authorized("/api/v1/admin/stats/logins?days=0", &admin_token).await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let user_token = app.token_for(&user.user).await;
+    let response = app.get_authorized("/api/v1/admin/stats", &user_token).await;
+    assert_eq!(response.status(), Status::Forbidden);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn forced_password_change_locks_everything_but_change_password() {
+    let app = TestApp::spawn().await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let admin_token = app.token_for(&admin.user).await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+
+    let uri = format!("/api/v1/admin/users/{}/must-change-password", user.id());
+    let response = app
+        .post_json_authorized(&uri, &admin_token, json!({ "enabled": true }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = app.get_authorized("/api/v1/auth/me", &token).await;
+    assert_eq!(response.status(), Status::Forbidden);
+    let body = response_json(response).await;
+    assert_eq!(body["code"], "password_change_required");
+
+    let body = response_json(app.login(user.email(), &user.password).await).await;
+    assert_eq!(body["user"]["must_change_password"], true);
+
+    let response = app
+        .post_json_authorized(
+            "/api/v1/auth/change-password",
+            &token,
+            json!({ "current_password": "wrong-password", "new_password": "new-password" }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let response = app
+        .post_json_authorized(
+            "/api/v1/auth/change-password",
+            &token,
+            json!({ "current_password": user.password, "new_password": "new-password" }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let fresh_token = response_json(response).await["token"].as_str().unwrap().to_string();
+
+    // Changing the password retires the old token; the session continues with the new one
+    let response = app.get_authorized("/api/v1/auth/me", &token).await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    let response = app.get_authorized("/api/v1/auth/me", &fresh_token).await;
+    assert_eq!(response.status(), Status::Ok);
+    app.login_token(user.email(), "new-password").await;
+
+    let response = app
+        .post_json_authorized(
+            "/api/v1/admin/users/00000000-0000-0000-0000-000000000000/must-change-password",
+            &admin_token,
+            json!({ "enabled": true }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn admin_can_send_a_password_reset_email() {
+    let app = TestApp::spawn().await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let admin_token = app.token_for(&admin.user).await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+
+    let uri = format!("/api/v1/admin/users/{}/send-password-reset", user.id());
+    let response = app.post_json_authorized(&uri, &admin_token, json!({})).await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let email = app.mailbox().last_to(user.email()).expect("reset email sent");
+    let token = token_from_email(&email.message.body).expect("token in reset email");
+    let response = app
+        .post_json("/api/v1/auth/reset-password", json!({ "token": token, "new_password": "new-password" }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let user_token = app.token_for(&user.user).await;
+    let response = app.post_json_authorized(&uri, &user_token, json!({})).await;
+    assert_eq!(response.status(), Status::Forbidden);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn logins_are_tracked_for_inactive_account_reports() {
+    let app = TestApp::spawn().await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let admin_token = app.token_for(&admin.user).await;
+    let active = UserFactory::verified().insert(&app.pool).await;
+    let dormant = UserFactory::verified().insert(&app.pool).await;
+    sqlx::query("UPDATE users SET created_at = NOW() - INTERVAL '90 days' WHERE id = ANY($1)")
+        .bind(vec![active.id(), dormant.id()])
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    app.login_token(active.email(), &active.password).await;
+    let token = app.login_token(active.email(), &active.password).await;
+    let response = app.login(active.email(), "wrong-password").await;
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    let body = response_json(app.get_authorized("/api/v1/auth/me", &token).await).await;
+    assert_eq!(body["user"]["login_count"], 2);
+    assert!(body["user"]["last_login_at"].is_string());
+
+    let body = response_json(app.get_authorized("/api/v1/admin/users?inactive_days=30&limit=200", &admin_token).await).await;
+    let listed: Vec<&str> = body["users"].as_array().unwrap().iter().map(|user| user["id"].as_str().unwrap()).collect();
+    assert!(listed.contains(&dormant.id().to_string().as_str()));
+    assert!(!listed.contains(&active.id().to_string().as_str()));
+
+    let response = app.get_authorized("/api/v1/admin/users", &token).await;
+    assert_eq!(response.status(), Status::Forbidden);
+
+    // Keep later runs against a shared database from listing these first
+    sqlx::query("UPDATE users SET created_at = NOW() WHERE id = ANY($1)")
+        .bind(vec![active.id(), dormant.id()])
+        .execute(&app.pool)
+        .await
+        .unwrap();
+}
@@ -0,0 +1,135 @@
+use chrono::Utc;
+use rocket::http::{ContentType, Header, Status};
+use rocket::serde::json::json;
+use uuid::Uuid;
+
+use rocket_auth_boilerplate::alerts;
+use rocket_auth_boilerplate::config::AppConfig;
+use rocket_auth_boilerplate::email::sender::Mailer;
+use rocket_auth_boilerplate::repositories::{audit, login_attempts};
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{unique_email, TestApp};
+
+const SECURITY_TEAM: &str = "security@example.com";
+
+async fn spawn(configure: impl FnOnce(&mut AppConfig)) -> TestApp {
+    TestApp::spawn_with(|config| {
+        config.admin_alert_emails = vec![SECURITY_TEAM.to_string()];
+        config.admin_alert_cooldown_minutes = 0;
+        configure(config);
+    })
+    .await
+}
+
+async fn check(app: &TestApp) -> usize {
+    let rocket = app.client.rocket();
+    let mut conn = app.pool.acquire().await.unwrap();
+    alerts::check(&mut conn, rocket.state::<AppConfig>().unwrap(), rocket.state::<Mailer>().unwrap())
+        .await
+        .unwrap()
+}
+
+/// Subjects of the alerts sent so far that mention `about`
+///
+/// The background check may also alert on other tests' data in a shared database.
+fn alert_subjects(app: &TestApp, about: &str) -> Vec<String> {
+    app.mailbox()
+        .messages_to(SECURITY_TEAM)
+        .into_iter()
+        .map(|captured| captured.message.subject)
+        .filter(|subject| subject.contains(about))
+        .collect()
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn lockout_spikes_and_failing_sinks_alert_admins() {
+    let app = spawn(|config| {
+        config.login_lockout_attempts = 3;
+        config.admin_alert_lockout_spike = 2;
+        config.admin_alert_sink_failures = 2;
+    })
+    .await;
+    let mut conn = app.pool.acquire().await.unwrap();
+
+    for _ in 0..2 {
+        let email = unique_email();
+        for _ in 0..3 {
+            login_attempts::record(&mut conn, None, &email, None, false).await.unwrap();
+        }
+    }
+    let sink = format!("test-{}", &Uuid::new_v4().simple().to_string()[..8]);
+    for _ in 0..2 {
+        audit::record_dead_letter(&mut conn, Uuid::new_v4(), Utc::now(), &sink, 3, "connection refused")
+            .await
+            .unwrap();
+    }
+
+    assert!(check(&app).await >= 2);
+    assert!(!alert_subjects(&app, "login lockouts spiking").is_empty());
+    // The background check may have sent it as well, racing the one above
+    let subjects = alert_subjects(&app, &sink);
+    assert!(!subjects.is_empty());
+    assert!(subjects.iter().all(|subject| *subject == format!("Security alert: audit sink {} failing", sink)));
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn alerts_about_the_same_thing_wait_for_the_cooldown() {
+    let app = spawn(|config| config.admin_alert_cooldown_minutes = 60).await;
+    let mut conn = app.pool.acquire().await.unwrap();
+    let rocket = app.client.rocket();
+    let (config, mailer) = (rocket.state::<AppConfig>().unwrap(), rocket.state::<Mailer>().unwrap());
+
+    let sink = format!("test-{}", &Uuid::new_v4().simple().to_string()[..8]);
+    let alert = alerts::AdminAlert::SinkFailing {
+        sink: sink.clone(),
+        dead_letters: 5,
+    };
+    assert!(alerts::send(&mut conn, config, mailer, &alert).await.unwrap());
+    assert!(!alerts::send(&mut conn, config, mailer, &alert).await.unwrap());
+    assert_eq!(alert_subjects(&app, &sink).len(), 1);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn admin_logins_from_a_new_country_alert_admins() {
+    let app = spawn(|config| config.geo_country_header = Some("CF-IPCountry".to_string())).await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let login = |country: &'static str| {
+        app.client
+            .post("/api/v1/auth/login")
+            .header(ContentType::JSON)
+            .header(Header::new("CF-IPCountry", country))
+            .body(json!({ "email": admin.email(), "password": admin.password }).to_string())
+            .dispatch()
+    };
+
+    // The first country is only remembered
+    assert_eq!(login("us").await.status(), Status::Ok);
+    assert_eq!(login("US").await.status(), Status::Ok);
+    assert!(alert_subjects(&app, "admin login").is_empty());
+
+    assert_eq!(login("DE").await.status(), Status::Ok);
+    assert_eq!(alert_subjects(&app, "admin login"), ["Security alert: admin login from DE"]);
+    let alert = app
+        .mailbox()
+        .messages_to(SECURITY_TEAM)
+        .into_iter()
+        .find(|captured| captured.message.subject.ends_with("from DE"))
+        .unwrap();
+    assert!(alert.message.body.contains(admin.email()));
+
+    // Other users' logins aren't watched
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let response = app
+        .client
+        .post("/api/v1/auth/login")
+        .header(ContentType::JSON)
+        .header(Header::new("CF-IPCountry", "FR"))
+        .body(json!({ "email": user.email(), "password": user.password }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(alert_subjects(&app, "admin login").len(), 1);
+}
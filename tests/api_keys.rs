@@ -0,0 +1,320 @@
+use rocket::http::{ContentType, Header, Status};
+use rocket::serde::json::json;
+
+use rocket_auth_boilerplate::config::OwnershipDenial;
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, TestApp};
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn api_keys_are_limited_to_their_scopes() {
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+
+    let response = app
+        .post_json_authorized("/api/v1/auth/api-keys", &token, json!({ "name": "ci", "scopes": ["users:read"] }))
+        .await;
+    assert_eq!(response.status(), Status::Created);
+    let body = response_json(response).await;
+    let key = body["key"].as_str().unwrap().to_string();
+    let id = body["api_key"]["id"].as_str().unwrap().to_string();
+    assert!(key.starts_with(body["api_key"]["key_prefix"].as_str().unwrap()));
+    assert!(body["api_key"].get("key_hash").is_none());
+
+    let response = app.get_authorized("/api/v1/auth/me", &key).await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    assert_eq!(body["user"]["email"], user.email());
+
+    // Missing scope is named
+    let response = app
+        .client
+        .patch("/api/v1/auth/me")
+        .header(ContentType::JSON)
+        .header(Header::new("Authorization", format!("Bearer {}", key)))
+        .body(json!({ "user_metadata": { "theme": "dark" } }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Forbidden);
+    let body = response_json(response).await;
+    assert_eq!(body["code"], "insufficient_scope");
+    assert!(body["details"].as_str().unwrap().contains("users:write"));
+
+    // Endpoints without a scope refuse API keys, including key management
+    let response = app.get_authorized("/api/v1/auth/api-keys", &key).await;
+    assert_eq!(response.status(), Status::Forbidden);
+    let response = app.get_authorized("/api/v1/auth/me/emails", &key).await;
+    assert_eq!(response.status(), Status::Forbidden);
+
+    let body = response_json(app.get_authorized("/api/v1/auth/api-keys", &token).await).await;
+    assert_eq!(body["api_keys"].as_array().unwrap().len(), 1);
+
+    let response = app
+        .delete_authorized(&format!("/api/v1/auth/api-keys/{}", id), &token)
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = app.get_authorized("/api/v1/auth/me", &key).await;
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn unknown_scopes_are_rejected() {
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+
+    let response = app
+        .post_json_authorized("/api/v1/auth/api-keys", &token, json!({ "name": "ci", "scopes": ["admin:everything"] }))
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let response = app
+        .post_json_authorized("/api/v1/auth/api-keys", &token, json!({ "name": "ci", "scopes": [] }))
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn only_the_owner_can_revoke_a_key() {
+    for (denial, status) in [
+        (OwnershipDenial::NotFound, Status::NotFound),
+        (OwnershipDenial::Forbidden, Status::Forbidden),
+    ] {
+        let app = TestApp::spawn_with(|config| config.ownership_denial = denial).await;
+        let owner = UserFactory::verified().insert(&app.pool).await;
+        let owner_token = app.token_for(&owner.user).await;
+        let other = UserFactory::verified().insert(&app.pool).await;
+        let other_token = app.token_for(&other.user).await;
+
+        let response = app
+            .post_json_authorized("/api/v1/auth/api-keys", &owner_token, json!({ "name": "ci", "scopes": ["users:read"] }))
+            .await;
+        let body = response_json(response).await;
+        let uri = format!("/api/v1/auth/api-keys/{}", body["api_key"]["id"].as_str().unwrap());
+
+        let response = app.delete_authorized(&uri, &other_token).await;
+        assert_eq!(response.status(), status);
+        if status == Status::Forbidden {
+            assert_eq!(response_json(response).await["code"], "not_owner");
+        }
+
+        let response = app.delete_authorized(&uri, &owner_token).await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = app.delete_authorized(&uri, &owner_token).await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn api_keys_are_refused_over_their_quota() {
+    let app = TestApp::spawn_with(|config| {
+        config.api_key_daily_quota = 2;
+        config.api_key_monthly_quota = 100;
+    })
+    .await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+
+    let response = app
+        .post_json_authorized("/api/v1/auth/api-keys", &token, json!({ "name": "ci", "scopes": ["users:read"] }))
+        .await;
+    let body = response_json(response).await;
+    let key = body["key"].as_str().unwrap().to_string();
+    let id = body["api_key"]["id"].as_str().unwrap().to_string();
+
+    for _ in 0..2 {
+        assert_eq!(app.get_authorized("/api/v1/auth/me", &key).await.status(), Status::Ok);
+    }
+    let response = app.get_authorized("/api/v1/auth/me", &key).await;
+    assert_eq!(response.status(), Status::TooManyRequests);
+    assert_eq!(response.headers().get_one("X-RateLimit-Limit"), Some("2"));
+    assert_eq!(response.headers().get_one("X-RateLimit-Remaining"), Some("0"));
+    assert!(response.headers().get_one("Retry-After").is_some());
+    assert_eq!(response_json(response).await["code"], "quota_exceeded");
+
+    // Refused requests aren't counted
+    let usage_uri = format!("/api/v1/auth/api-keys/{}/usage", id);
+    let response = app.get_authorized(&usage_uri, &token).await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    assert_eq!(body["today"]["requests"], 2);
+    assert_eq!(body["today"]["quota"], 2);
+    assert_eq!(body["today"]["remaining"], 0);
+    assert_eq!(body["this_month"]["requests"], 2);
+    assert_eq!(body["this_month"]["remaining"], 98);
+    assert_eq!(body["days"].as_array().unwrap().len(), 1);
+
+    // Only the key's owner sees its usage
+    let other = UserFactory::verified().insert(&app.pool).await;
+    let other_token = app.token_for(&other.user).await;
+    assert_ne!(app.get_authorized(&usage_uri, &other_token).await.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn api_key_usage_is_tracked_without_quotas() {
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+
+    let response = app
+        .post_json_authorized("/api/v1/auth/api-keys", &token, json!({ "name": "ci", "scopes": ["users:read"] }))
+        .await;
+    let body = response_json(response).await;
+    let key = body["key"].as_str().unwrap().to_string();
+    let id = body["api_key"]["id"].as_str().unwrap().to_string();
+
+    for _ in 0..3 {
+        assert_eq!(app.get_authorized("/api/v1/auth/me", &key).await.status(), Status::Ok);
+    }
+    let body = response_json(app.get_authorized(&format!("/api/v1/auth/api-keys/{}/usage", id), &token).await).await;
+    assert_eq!(body["today"]["requests"], 3);
+    assert!(body["today"]["quota"].is_null());
+    assert!(body["this_month"]["quota"].is_null());
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn rotated_keys_overlap_with_their_replacement() {
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+
+    let response = app
+        .post_json_authorized(
+            "/api/v1/auth/api-keys",
+            &token,
+            json!({ "name": "ci", "scopes": ["users:read"], "expires_in_days": 5 }),
+        )
+        .await;
+    let body = response_json(response).await;
+    let old_key = body["key"].as_str().unwrap().to_string();
+    let id = body["api_key"]["id"].as_str().unwrap().to_string();
+
+    // Expiring within the warning window is flagged
+    let body = response_json(app.get_authorized("/api/v1/auth/api-keys", &token).await).await;
+    assert_eq!(body["api_keys"][0]["expires_soon"], true);
+
+    let rotate_uri = format!("/api/v1/auth/api-keys/{}/rotate", id);
+    let response = app.post_json_authorized(&rotate_uri, &token, json!({})).await;
+    assert_eq!(response.status(), Status::Created);
+    let body = response_json(response).await;
+    let new_key = body["key"].as_str().unwrap().to_string();
+    let new_id = body["api_key"]["id"].as_str().unwrap().to_string();
+    assert_eq!(body["api_key"]["name"], "ci");
+    assert_eq!(body["api_key"]["scopes"], json!(["users:read"]));
+    assert!(body["api_key"]["expires_at"].is_string());
+
+    // Both keys work during the overlap
+    assert_eq!(app.get_authorized("/api/v1/auth/me", &old_key).await.status(), Status::Ok);
+    assert_eq!(app.get_authorized("/api/v1/auth/me", &new_key).await.status(), Status::Ok);
+
+    let body = response_json(app.get_authorized("/api/v1/auth/api-keys", &token).await).await;
+    let old = body["api_keys"].as_array().unwrap().iter().find(|key| key["id"] == id.as_str()).unwrap();
+    assert_eq!(old["replaced_by"], new_id.as_str());
+
+    let response = app.post_json_authorized(&rotate_uri, &token, json!({})).await;
+    assert_eq!(response.status(), Status::Conflict);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn rotation_without_overlap_retires_the_old_key() {
+    let app = TestApp::spawn_with(|config| config.api_key_rotation_overlap_minutes = 0).await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+
+    let response = app
+        .post_json_authorized("/api/v1/auth/api-keys", &token, json!({ "name": "ci", "scopes": ["users:read"] }))
+        .await;
+    let body = response_json(response).await;
+    let old_key = body["key"].as_str().unwrap().to_string();
+    let id = body["api_key"]["id"].as_str().unwrap().to_string();
+
+    let response = app
+        .post_json_authorized(&format!("/api/v1/auth/api-keys/{}/rotate", id), &token, json!({}))
+        .await;
+    let body = response_json(response).await;
+    let new_key = body["key"].as_str().unwrap().to_string();
+    assert!(body["api_key"]["expires_at"].is_null());
+
+    assert_eq!(app.get_authorized("/api/v1/auth/me", &old_key).await.status(), Status::Unauthorized);
+    assert_eq!(app.get_authorized("/api/v1/auth/me", &new_key).await.status(), Status::Ok);
+
+    // Another user can't rotate the key
+    let other = UserFactory::verified().insert(&app.pool).await;
+    let other_token = app.token_for(&other.user).await;
+    let response = app
+        .post_json_authorized(&format!("/api/v1/auth/api-keys/{}/rotate", id), &other_token, json!({}))
+        .await;
+    assert_ne!(response.status(), Status::Created);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn api_keys_bound_to_addresses_refuse_other_clients() {
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+
+    let response = app
+        .post_json_authorized(
+            "/api/v1/auth/api-keys",
+            &token,
+            json!({ "name": "ci", "scopes": ["users:read"], "allowed_ips": ["10.1.0.0/16", "not-a-range"] }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let response = app
+        .post_json_authorized(
+            "/api/v1/auth/api-keys",
+            &token,
+            json!({ "name": "ci", "scopes": ["users:read"], "allowed_ips": ["10.1.0.0/16", "2001:db8::1"] }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::Created);
+    let body = response_json(response).await;
+    assert_eq!(body["api_key"]["allowed_ips"], json!(["10.1.0.0/16", "2001:db8::1/128"]));
+    let key = body["key"].as_str().unwrap().to_string();
+
+    let me_from = |ip: &'static str| {
+        app.client
+            .get("/api/v1/auth/me")
+            .header(Header::new("Authorization", format!("Bearer {}", key)))
+            .header(Header::new("X-Real-IP", ip))
+            .dispatch()
+    };
+    assert_eq!(me_from("10.1.200.7").await.status(), Status::Ok);
+    assert_eq!(me_from("2001:db8::1").await.status(), Status::Ok);
+    let response = me_from("203.0.113.9").await;
+    assert_eq!(response.status(), Status::Forbidden);
+    assert_eq!(response_json(response).await["code"], "ip_not_allowed");
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn api_keys_bound_to_an_audience_only_work_at_that_service() {
+    let app = TestApp::spawn().await;
+    let reports = TestApp::spawn_with(|config| config.service_audience = Some("reports".to_string())).await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+
+    let response = app
+        .post_json_authorized(
+            "/api/v1/auth/api-keys",
+            &token,
+            json!({ "name": "reports", "scopes": ["users:read"], "audience": "reports" }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::Created);
+    let key = response_json(response).await["key"].as_str().unwrap().to_string();
+
+    assert_eq!(app.get_authorized("/api/v1/auth/me", &key).await.status(), Status::Unauthorized);
+    assert_eq!(reports.get_authorized("/api/v1/auth/me", &key).await.status(), Status::Ok);
+}
@@ -0,0 +1,183 @@
+use std::time::Duration;
+
+use rocket::http::{ContentType, Header, Status};
+use rocket::serde::json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::time::timeout;
+
+use rocket_auth_boilerplate::config::{AuditSinkConfig, HttpSinkFormat};
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, TestApp};
+
+/// The next syslog message mentioning `needle`; other tests' events are forwarded here too
+async fn recv_syslog(socket: &UdpSocket, needle: &str) -> String {
+    let mut datagram = [0u8; 4096];
+    loop {
+        let (len, _) = timeout(Duration::from_secs(5), socket.recv_from(&mut datagram))
+            .await
+            .expect("syslog message")
+            .unwrap();
+        let message = String::from_utf8_lossy(&datagram[..len]).to_string();
+        if message.contains(needle) {
+            return message;
+        }
+    }
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn admins_query_and_export_the_audit_log() {
+    let app = TestApp::spawn().await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let admin_token = app.token_for(&admin.user).await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let response = app
+        .client
+        .post("/api/v1/auth/login")
+        .header(ContentType::JSON)
+        .header(Header::new("X-Real-IP", "203.0.113.7"))
+        .body(json!({ "email": user.email(), "password": user.password }).to_string())
+        .dispatch()
+        .await;
+    let token = response_json(response).await["token"].as_str().unwrap().to_string();
+    let response = app.post_json_authorized("/api/v1/auth/logout-all", &token, json!({})).await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let uri = format!("/api/v1/admin/audit?user={}", user.id());
+    let body = response_json(app.get_authorized(&uri, &admin_token).await).await;
+    let events = body["events"].as_array().unwrap();
+    let types: Vec<&str> = events.iter().map(|event| event["event_type"].as_str().unwrap()).collect();
+    assert_eq!(types, ["all_sessions_revoked", "session_created"]);
+    assert_eq!(events[1]["actor_id"], user.id().to_string());
+    assert!(events[1]["details"]["session_id"].is_string());
+
+    let uri = format!("/api/v1/admin/audit?actor={}&type=session_created&ip=203.0.113.7&from=2000-01-01", user.id());
+    let body = response_json(app.get_authorized(&uri, &admin_token).await).await;
+    assert_eq!(body["events"].as_array().unwrap().len(), 1);
+
+    let uri = format!("/api/v1/admin/audit?user={}&to=2000-01-01", user.id());
+    let body = response_json(app.get_authorized(&uri, &admin_token).await).await;
+    assert!(body["events"].as_array().unwrap().is_empty());
+
+    let uri = format!("/api/v1/admin/audit?user={}&format=ndjson", user.id());
+    let response = app.get_authorized(&uri, &admin_token).await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::new("application", "x-ndjson")));
+    let export = response.into_string().await.unwrap();
+    let lines: Vec<Value> = export.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["event_type"], "all_sessions_revoked");
+
+    let response = app.get_authorized("/api/v1/admin/audit?actor=nobody", &admin_token).await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let token = app.token_for(&user.user).await;
+    let response = app.get_authorized("/api/v1/admin/audit", &token).await;
+    assert_eq!(response.status(), Status::Forbidden);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn security_events_are_forwarded_and_replayed_to_audit_sinks() {
+    let syslog = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let collector = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let syslog_address = syslog.local_addr().unwrap().to_string();
+    let collector_url = format!("http://{}/services/collector/event", collector.local_addr().unwrap());
+    let app = TestApp::spawn_with(move |config| {
+        config.audit_sinks = vec![
+            AuditSinkConfig::Syslog { address: syslog_address },
+            AuditSinkConfig::Http {
+                url: collector_url,
+                authorization: Some("Splunk test-token".to_string()),
+                format: HttpSinkFormat::SplunkHec,
+            },
+        ];
+    })
+    .await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let admin_token = app.token_for(&admin.user).await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+
+    // Answers every request, returning the first about `user`
+    let user_id = user.id().to_string();
+    let http = tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = collector.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 4096];
+            loop {
+                let read = stream.read(&mut buffer).await.unwrap();
+                assert!(read > 0, "connection closed before the body was complete");
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request).to_lowercase();
+                if let Some(end) = text.find("\r\n\r\n") {
+                    let length: usize = text
+                        .lines()
+                        .find_map(|line| line.strip_prefix("content-length: "))
+                        .map_or(0, |length| length.trim().parse().unwrap());
+                    if request.len() >= end + 4 + length {
+                        break;
+                    }
+                }
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .await
+                .unwrap();
+            let request = String::from_utf8(request).unwrap();
+            if request.contains(&user_id) {
+                return request;
+            }
+        }
+    });
+
+    app.login(user.email(), &user.password).await;
+
+    let message = recv_syslog(&syslog, &user.id().to_string()).await;
+    assert!(message.starts_with("<85>1 "));
+    assert!(message.contains(" session_created - {"));
+
+    let request = timeout(Duration::from_secs(5), http).await.expect("collector request").unwrap();
+    assert!(request.starts_with("POST /services/collector/event "));
+    assert!(request.to_lowercase().contains("authorization: splunk test-token"));
+    let body = &request[request.find("\r\n\r\n").unwrap() + 4..];
+    let body: Value = serde_json::from_str(body).unwrap();
+    assert_eq!(body["sourcetype"], "_json");
+    assert_eq!(body["event"]["type"], "session_created");
+    assert_eq!(body["event"]["user_id"], user.id().to_string());
+
+    // Forwarded once, whichever instance claimed it
+    let (id, forwarded) = sqlx::query_as::<_, (uuid::Uuid, bool)>(
+        "SELECT id, forwarded_at IS NOT NULL FROM audit_events WHERE user_id = $1 AND event_type = 'session_created'",
+    )
+    .bind(user.id())
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    assert!(forwarded);
+
+    // Replayed to one sink for a consumer that missed it, with the same id
+    let response = app
+        .post_json_authorized("/api/v1/admin/audit/replay", &admin_token, json!({ "sink": "syslog", "ids": [id] }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response_json(response).await["replayed"], 1);
+    let replayed = recv_syslog(&syslog, &id.to_string()).await;
+    assert!(replayed.contains(" session_created - {"));
+
+    let response = app
+        .post_json_authorized("/api/v1/admin/audit/replay", &admin_token, json!({ "sink": "stdout", "ids": [id] }))
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+    let response = app
+        .post_json_authorized("/api/v1/admin/audit/replay", &admin_token, json!({ "sink": "syslog" }))
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let user_token = app.token_for(&user.user).await;
+    let response = app
+        .post_json_authorized("/api/v1/admin/audit/replay", &user_token, json!({ "sink": "syslog", "ids": [id] }))
+        .await;
+    assert_eq!(response.status(), Status::Forbidden);
+}
@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use rocket::http::Status;
+use rocket::serde::json::{json, Value};
+use tokio::net::TcpListener;
+
+use rocket_auth_boilerplate::config::{AuditSinkConfig, HttpSinkFormat};
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, TestApp};
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn failed_sink_deliveries_are_retried_then_dead_lettered() {
+    // Nothing listens here once the listener is dropped
+    let unreachable = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+    let app = TestApp::spawn_with(move |config| {
+        config.audit_sinks = vec![AuditSinkConfig::Http {
+            url: format!("http://{}/events", unreachable),
+            authorization: None,
+            format: HttpSinkFormat::Json,
+        }];
+        config.audit_sink_max_attempts = 3;
+        config.audit_sink_retry_seconds = 0;
+    })
+    .await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let admin_token = app.token_for(&admin.user).await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+
+    app.login(user.email(), &user.password).await;
+    let event_id = sqlx::query_scalar::<_, uuid::Uuid>("SELECT id FROM audit_events WHERE user_id = $1")
+        .bind(user.id())
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+
+    let mut dead_letter = None;
+    for _ in 0..50 {
+        dead_letter = sqlx::query_as::<_, (String, i32)>("SELECT sink, attempts FROM audit_dead_letters WHERE event_id = $1")
+            .bind(event_id)
+            .fetch_optional(&app.pool)
+            .await
+            .unwrap();
+        if dead_letter.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(dead_letter, Some(("http".to_string(), 3)));
+
+    let response = app.get_authorized("/api/v1/admin/audit/dead-letters", &admin_token).await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    let listed: Vec<&Value> = body["dead_letters"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|letter| letter["event_id"] == event_id.to_string())
+        .collect();
+    assert_eq!(listed.len(), 1);
+    assert!(!listed[0]["last_error"].as_str().unwrap().is_empty());
+    assert!(body["counts"].as_array().unwrap().iter().any(|count| count["sink"] == "http" && count["count"].as_i64() >= Some(1)));
+
+    // Replaying to a sink that's still down keeps the dead letter
+    let response = app
+        .post_json_authorized("/api/v1/admin/audit/replay", &admin_token, json!({ "sink": "http", "ids": [event_id] }))
+        .await;
+    assert_eq!(response.status(), Status::BadGateway);
+    let body = response_json(response).await;
+    assert_eq!(body["replayed"], 0);
+    assert_eq!(body["failed_id"], event_id.to_string());
+
+    let user_token = app.token_for(&user.user).await;
+    let response = app.get_authorized("/api/v1/admin/audit/dead-letters", &user_token).await;
+    assert_eq!(response.status(), Status::Forbidden);
+}
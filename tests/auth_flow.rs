@@ -0,0 +1,763 @@
+use std::time::{Duration, Instant};
+
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::asynchronous::LocalResponse;
+use rocket::serde::json::json;
+
+use rocket_auth_boilerplate::auth::tokens;
+use rocket_auth_boilerplate::config::LoginRiskEngine;
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, token_from_email, unique_email, TestApp};
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn register_login_and_fetch_current_user() {
+    let app = TestApp::spawn().await;
+    let email = unique_email();
+
+    let response = app.register(&email, "password123").await;
+    assert_eq!(response.status(), Status::Created);
+    let body = response_json(response).await;
+    assert_eq!(body["user"]["email"], email.as_str());
+
+    let token = app.login_token(&email, "password123").await;
+
+    let response = app.get_authorized("/api/v1/auth/me", &token).await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    assert_eq!(body["user"]["email"], email.as_str());
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn duplicate_registration_is_rejected() {
+    let app = TestApp::spawn().await;
+    let email = unique_email();
+
+    assert_eq!(app.register(&email, "password123").await.status(), Status::Created);
+    assert_eq!(app.register(&email, "password123").await.status(), Status::Conflict);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn login_with_wrong_password_fails() {
+    let app = TestApp::spawn().await;
+    let email = unique_email();
+    app.register(&email, "password123").await;
+
+    let response = app.login(&email, "wrong-password").await;
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn me_requires_a_valid_token() {
+    let app = TestApp::spawn().await;
+
+    let response = app.client.get("/api/v1/auth/me").dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    let response = app.get_authorized("/api/v1/auth/me", "not-a-jwt").await;
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn password_reset_flow() {
+    let app = TestApp::spawn().await;
+    let email = unique_email();
+    app.register(&email, "password123").await;
+    let old_token = app.login_token(&email, "password123").await;
+
+    let response = app
+        .post_json("/api/v1/auth/forgot-password", json!({ "email": email }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    assert!(body.get("token").is_none(), "reset token must only be sent by email");
+
+    let email_message = app.mailbox().last_to(&email).expect("reset email sent");
+    assert_eq!(email_message.message.subject, "Reset your password");
+    let reset_token = token_from_email(&email_message.message.body).expect("token in reset email");
+
+    // Only a hash of the token is stored
+    let stored: Vec<String> = sqlx::query_scalar(
+        "SELECT t.token_hash FROM password_reset_tokens t JOIN users u ON u.id = t.user_id WHERE u.email = $1",
+    )
+    .bind(&email)
+    .fetch_all(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(stored, vec![tokens::hash(&reset_token)]);
+
+    let response = app
+        .post_json(
+            "/api/v1/auth/reset-password",
+            json!({ "token": reset_token, "new_password": "new-password456" }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    // The old password no longer works, the new one does, and tokens from before are refused
+    assert_eq!(app.login(&email, "password123").await.status(), Status::Unauthorized);
+    app.login_token(&email, "new-password456").await;
+    assert_eq!(app.get_authorized("/api/v1/auth/me", &old_token).await.status(), Status::Unauthorized);
+
+    // Reset tokens are single-use
+    let response = app
+        .post_json(
+            "/api/v1/auth/reset-password",
+            json!({ "token": reset_token, "new_password": "another-password789" }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn forgot_password_for_unknown_email_sends_nothing() {
+    let app = TestApp::spawn().await;
+    let email = unique_email();
+
+    let response = app
+        .post_json("/api/v1/auth/forgot-password", json!({ "email": email }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert!(app.mailbox().messages_to(&email).is_empty());
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn email_verification_flow() {
+    let app = TestApp::spawn().await;
+    let email = unique_email();
+    app.register(&email, "password123").await;
+    let token = app.login_token(&email, "password123").await;
+
+    let body = response_json(app.get_authorized("/api/v1/auth/me", &token).await).await;
+    assert_eq!(body["user"]["email_verified"], false);
+
+    let email_message = app.mailbox().last_to(&email).expect("verification email sent");
+    assert_eq!(email_message.message.subject, "Verify your email address");
+    let verification_token = token_from_email(&email_message.message.body).expect("token in email");
+
+    let response = app
+        .client
+        .get(format!("/api/v1/auth/verify-email?token={}", verification_token))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let body = response_json(app.get_authorized("/api/v1/auth/me", &token).await).await;
+    assert_eq!(body["user"]["email_verified"], true);
+
+    // Verification links are single-use
+    let response = app
+        .client
+        .get(format!("/api/v1/auth/verify-email?token={}", verification_token))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn password_peppers_are_applied_and_rotated_on_login() {
+    use rocket_auth_boilerplate::config::{PepperConfig, PepperSecret};
+    use rocket_auth_boilerplate::test_support::factories::UserFactory;
+
+    fn pepper(id: &str, secret: &str) -> PepperConfig {
+        PepperConfig {
+            id: id.to_string(),
+            secret: PepperSecret::Plain(secret.to_string()),
+        }
+    }
+
+    // Hashed before any pepper was configured
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let stored_hash = || async {
+        sqlx::query_scalar::<_, String>("SELECT password_hash FROM users WHERE id = $1")
+            .bind(user.id())
+            .fetch_one(&app.pool)
+            .await
+            .unwrap()
+    };
+
+    let v1 = TestApp::spawn_with(|config| config.password_peppers = vec![pepper("v1", "first-secret")]).await;
+    let response = v1.login(user.email(), &user.password).await;
+    assert_eq!(response.status(), Status::Ok);
+    assert!(stored_hash().await.starts_with("pepper:v1:"));
+
+    // Rotation: v2 hashes from now on, v1 hashes still verify and get upgraded
+    let v2 = TestApp::spawn_with(|config| {
+        config.password_peppers = vec![pepper("v2", "second-secret"), pepper("v1", "first-secret")]
+    })
+    .await;
+    assert_eq!(v2.login(user.email(), "wrong-password").await.status(), Status::Unauthorized);
+    assert!(stored_hash().await.starts_with("pepper:v1:"));
+    assert_eq!(v2.login(user.email(), &user.password).await.status(), Status::Ok);
+    assert!(stored_hash().await.starts_with("pepper:v2:"));
+
+    // Once v1 is retired, upgraded hashes keep working
+    let v2_only = TestApp::spawn_with(|config| config.password_peppers = vec![pepper("v2", "second-secret")]).await;
+    assert_eq!(v2_only.login(user.email(), &user.password).await.status(), Status::Ok);
+
+    // New passwords use the current pepper
+    let email = unique_email();
+    assert_eq!(v2_only.register(&email, "password123").await.status(), Status::Created);
+    let hash: String = sqlx::query_scalar("SELECT password_hash FROM users WHERE email = $1")
+        .bind(&email)
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert!(hash.starts_with("pepper:v2:"));
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn password_hashes_are_upgraded_when_the_work_factor_increases() {
+    use rocket_auth_boilerplate::config::PasswordHashing;
+    use rocket_auth_boilerplate::test_support::factories::UserFactory;
+
+    // Fixtures use bcrypt cost 4
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let stored_hash = || async {
+        sqlx::query_scalar::<_, String>("SELECT password_hash FROM users WHERE id = $1")
+            .bind(user.id())
+            .fetch_one(&app.pool)
+            .await
+            .unwrap()
+    };
+
+    let bcrypt = |cost| TestApp::spawn_with(move |config| config.password_hashing = PasswordHashing::Bcrypt { cost });
+    assert_eq!(bcrypt(5).await.login(user.email(), &user.password).await.status(), Status::Ok);
+    assert!(stored_hash().await.starts_with("$2b$05$"));
+
+    // A lower cost doesn't downgrade existing hashes
+    assert_eq!(bcrypt(4).await.login(user.email(), &user.password).await.status(), Status::Ok);
+    assert!(stored_hash().await.starts_with("$2b$05$"));
+
+    // Switching algorithms re-hashes with the new one
+    let argon2 = TestApp::spawn_with(|config| {
+        config.password_hashing = PasswordHashing::Argon2 {
+            memory_kib: 8192,
+            iterations: 1,
+            parallelism: 1,
+        }
+    })
+    .await;
+    assert_eq!(argon2.login(user.email(), &user.password).await.status(), Status::Ok);
+    assert!(stored_hash().await.starts_with("$argon2id$v=19$m=8192,t=1,p=1$"));
+    assert_eq!(argon2.login(user.email(), "wrong-password").await.status(), Status::Unauthorized);
+
+    // Argon2 hashes still verify after switching back
+    assert_eq!(bcrypt(4).await.login(user.email(), &user.password).await.status(), Status::Ok);
+    assert!(stored_hash().await.starts_with("$2b$04$"));
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn external_issuer_tokens_are_verified_and_local_tokens_still_work() {
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use rocket_auth_boilerplate::config::ExternalJwtConfig;
+
+    let app = TestApp::spawn_with(|config| {
+        config.external_jwt = Some(ExternalJwtConfig {
+            issuer: "https://issuer.example.com/".to_string(),
+            audience: "test-api".to_string(),
+            jwks_url: "http://127.0.0.1:9/.well-known/jwks.json".to_string(),
+        })
+    })
+    .await;
+    let email = unique_email();
+    app.register(&email, "password123").await;
+
+    let token = app.login_token(&email, "password123").await;
+    assert_eq!(app.get_authorized("/api/v1/auth/me", &token).await.status(), Status::Ok);
+
+    // A token naming the external issuer but signed with a shared secret is never trusted
+    let forged = encode(
+        &Header::default(),
+        &json!({
+            "iss": "https://issuer.example.com/",
+            "aud": "test-api",
+            "sub": "attacker",
+            "email": email,
+            "email_verified": true,
+            "exp": chrono::Utc::now().timestamp() + 3600,
+        }),
+        &EncodingKey::from_secret(b"guessed"),
+    )
+    .unwrap();
+    let response = app.get_authorized("/api/v1/auth/me", &forged).await;
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    let linked: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM external_identities WHERE subject = 'attacker'")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(linked, 0);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn opaque_tokens_are_stored_hashed_and_revoked_with_their_session() {
+    use rocket_auth_boilerplate::config::TokenStrategy;
+
+    let opaque = TestApp::spawn_with(|config| config.token_strategy = TokenStrategy::Opaque { cache_seconds: 60 }).await;
+
+    let response = opaque.post_json("/api/v1/auth/guest", json!({})).await;
+    assert_eq!(response.status(), Status::Created);
+    let guest_token = response_json(response).await["token"].as_str().unwrap().to_string();
+    assert!(guest_token.starts_with("at_"));
+
+    let body = response_json(opaque.get_authorized("/api/v1/auth/me", &guest_token).await).await;
+    assert_eq!(body["user"]["guest"], true);
+
+    let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sessions WHERE token_hash = $1")
+        .bind(tokens::hash(&guest_token))
+        .fetch_one(&opaque.pool)
+        .await
+        .unwrap();
+    assert_eq!(stored, 1);
+
+    // Upgrading revokes the guest session; the cached lookup goes with it
+    let email = unique_email();
+    let response = opaque
+        .post_json_authorized(
+            "/api/v1/auth/guest/upgrade",
+            &guest_token,
+            json!({ "email": email, "password": "password123" }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let response = opaque.get_authorized("/api/v1/auth/me", &guest_token).await;
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    // Tokens from either strategy keep working after switching
+    let token = opaque.login_token(&email, "password123").await;
+    let jwt = TestApp::spawn().await;
+    assert_eq!(jwt.get_authorized("/api/v1/auth/me", &token).await.status(), Status::Ok);
+    let token = jwt.login_token(&email, "password123").await;
+    assert!(!token.starts_with("at_"));
+    assert_eq!(opaque.get_authorized("/api/v1/auth/me", &token).await.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn paseto_tokens_authenticate_in_local_and_public_mode() {
+    use rocket_auth_boilerplate::config::{PasetoPurpose, SignerConfig};
+
+    let email = unique_email();
+    let jwt = TestApp::spawn().await;
+    jwt.register(&email, "password123").await;
+    let jwt_token = jwt.login_token(&email, "password123").await;
+
+    for (purpose, header) in [(PasetoPurpose::Local, "v4.local."), (PasetoPurpose::Public, "v4.public.")] {
+        let app = TestApp::spawn_with(|config| {
+            config.jwt_signer = SignerConfig::Paseto {
+                purpose,
+                key: "707172737475767778797a7b7c7d7e7f808182838485868788898a8b8c8d8e8f".to_string(),
+            }
+        })
+        .await;
+
+        let token = app.login_token(&email, "password123").await;
+        assert!(token.starts_with(header), "{} token expected, got {}", header, token);
+        let body = response_json(app.get_authorized("/api/v1/auth/me", &token).await).await;
+        assert_eq!(body["user"]["email"], email.as_str());
+
+        // Tampered tokens and tokens in the other format are refused
+        let mut tampered = token.clone();
+        let last = if tampered.ends_with('A') { 'B' } else { 'A' };
+        tampered.pop();
+        tampered.push(last);
+        assert_eq!(app.get_authorized("/api/v1/auth/me", &tampered).await.status(), Status::Unauthorized);
+        assert_eq!(app.get_authorized("/api/v1/auth/me", &jwt_token).await.status(), Status::Unauthorized);
+        assert_eq!(jwt.get_authorized("/api/v1/auth/me", &token).await.status(), Status::Unauthorized);
+    }
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn signed_email_tokens_are_not_stored_and_work_once() {
+    use rocket_auth_boilerplate::config::EmailTokenMode;
+
+    let signed = TestApp::spawn_with(|config| {
+        config.email_tokens = EmailTokenMode::Signed {
+            secret: "email-token-test-secret".to_string(),
+        }
+    })
+    .await;
+    let email = unique_email();
+    signed.register(&email, "password123").await;
+
+    let message = signed.mailbox().last_to(&email).expect("verification email sent");
+    let verification_token = token_from_email(&message.message.body).expect("token in email");
+    signed.post_json("/api/v1/auth/forgot-password", json!({ "email": email })).await;
+    let message = signed.mailbox().last_to(&email).expect("reset email sent");
+    let reset_token = token_from_email(&message.message.body).expect("token in reset email");
+
+    // Nothing is written when the tokens are issued
+    let stored: i64 = sqlx::query_scalar(
+        "SELECT (SELECT COUNT(*) FROM password_reset_tokens WHERE user_id = u.id) \
+         + (SELECT COUNT(*) FROM email_verification_tokens WHERE user_id = u.id) FROM users u WHERE u.email = $1",
+    )
+    .bind(&email)
+    .fetch_one(&signed.pool)
+    .await
+    .unwrap();
+    assert_eq!(stored, 0);
+
+    // A token is only good for its own purpose, and not when tampered with
+    let response = signed
+        .post_json(
+            "/api/v1/auth/reset-password",
+            json!({ "token": verification_token, "new_password": "new-password456" }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+    let tampered = format!("v{}", &reset_token[1..]);
+    let response = signed
+        .client
+        .get(format!("/api/v1/auth/verify-email?token={}", tampered))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    for expected in [Status::Ok, Status::BadRequest] {
+        let response = signed
+            .client
+            .get(format!("/api/v1/auth/verify-email?token={}", verification_token))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), expected);
+    }
+    for (password, expected) in [("new-password456", Status::Ok), ("another-password789", Status::BadRequest)] {
+        let response = signed
+            .post_json(
+                "/api/v1/auth/reset-password",
+                json!({ "token": reset_token, "new_password": password }),
+            )
+            .await;
+        assert_eq!(response.status(), expected);
+    }
+    signed.login_token(&email, "new-password456").await;
+
+    // Without the secret, signed tokens aren't accepted
+    let stored = TestApp::spawn().await;
+    signed.post_json("/api/v1/auth/forgot-password", json!({ "email": email })).await;
+    let message = signed.mailbox().last_to(&email).expect("reset email sent");
+    let reset_token = token_from_email(&message.message.body).expect("token in reset email");
+    let response = stored
+        .post_json(
+            "/api/v1/auth/reset-password",
+            json!({ "token": reset_token, "new_password": "another-password789" }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn logout_all_revokes_every_session_and_outstanding_token() {
+    // Versions bumped outside the app are only noticed once the cache expires
+    let app = TestApp::spawn_with(|config| config.token_version_cache_seconds = 0).await;
+    let email = unique_email();
+    app.register(&email, "password123").await;
+
+    let first = app.login_token(&email, "password123").await;
+    let second = app.login_token(&email, "password123").await;
+
+    let response = app.post_json_authorized("/api/v1/auth/logout-all", &first, json!({})).await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    assert!(body["revoked_sessions"].as_u64().unwrap() >= 2);
+
+    for token in [&first, &second] {
+        assert_eq!(app.get_authorized("/api/v1/auth/me", token).await.status(), Status::Unauthorized);
+    }
+
+    // Bumping the version alone is enough to refuse a token whose session is still active
+    let token = app.login_token(&email, "password123").await;
+    assert_eq!(app.get_authorized("/api/v1/auth/me", &token).await.status(), Status::Ok);
+    sqlx::query("UPDATE users SET token_version = token_version + 1 WHERE email = $1")
+        .bind(&email)
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(app.get_authorized("/api/v1/auth/me", &token).await.status(), Status::Unauthorized);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn sessions_expire_when_idle_and_list_their_limits() {
+    let app = TestApp::spawn_with(|config| {
+        config.session_max_age_hours = 2;
+        config.session_idle_timeout_minutes = 30;
+    })
+    .await;
+    let email = unique_email();
+    app.register(&email, "password123").await;
+    let token = app.login_token(&email, "password123").await;
+
+    let response = app.get_authorized("/api/v1/auth/me/sessions", &token).await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    assert_eq!(body["max_age_seconds"], 7200);
+    assert_eq!(body["idle_timeout_seconds"], 1800);
+    let sessions = body["sessions"].as_array().unwrap();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0]["current"], true);
+    assert!(sessions[0]["idle_expires_at"].is_string());
+
+    // A session left unused past the idle timeout is over, though its maximum age isn't reached
+    sqlx::query(
+        "UPDATE sessions SET last_used_at = CURRENT_TIMESTAMP - INTERVAL '31 minutes' \
+         WHERE user_id = (SELECT id FROM users WHERE email = $1)",
+    )
+    .bind(&email)
+    .execute(&app.pool)
+    .await
+    .unwrap();
+    let response = app.get_authorized("/api/v1/auth/me", &token).await;
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn repeated_failures_lock_out_the_address_with_rate_limit_headers() {
+    let app = TestApp::spawn_with(|config| {
+        config.login_lockout_attempts = 3;
+        config.login_lockout_minutes = 15;
+    })
+    .await;
+    let email = unique_email();
+    app.register(&email, "password123").await;
+
+    for _ in 0..3 {
+        let response = app.login(&email, "wrong-password").await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    // Even the right password is refused while locked out
+    let response = app.login(&email, "password123").await;
+    assert_eq!(response.status(), Status::TooManyRequests);
+    let headers = response.headers();
+    assert_eq!(headers.get_one("X-RateLimit-Limit"), Some("3"));
+    assert_eq!(headers.get_one("X-RateLimit-Remaining"), Some("0"));
+    let retry_after: i64 = headers.get_one("Retry-After").unwrap().parse().unwrap();
+    assert!(retry_after > 0 && retry_after <= 15 * 60);
+    let reset: i64 = headers.get_one("X-RateLimit-Reset").unwrap().parse().unwrap();
+    assert!(reset > chrono::Utc::now().timestamp());
+    let body = response_json(response).await;
+    assert_eq!(body["code"], "too_many_attempts");
+
+    // Unknown addresses are locked out the same way
+    let unknown = unique_email();
+    for _ in 0..3 {
+        app.login(&unknown, "password123").await;
+    }
+    let response = app.login(&unknown, "password123").await;
+    assert_eq!(response.status(), Status::TooManyRequests);
+
+    sqlx::query("UPDATE login_attempts SET created_at = created_at - INTERVAL '16 minutes' WHERE email = $1")
+        .bind(&email)
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    app.login_token(&email, "password123").await;
+}
+
+/// Log in as if from `ip`, returning the status and how long the response took
+async fn timed_login(app: &TestApp, email: &str, password: &str, ip: &str) -> (Status, Duration) {
+    let started = Instant::now();
+    let response = app
+        .client
+        .post("/api/v1/auth/login")
+        .header(ContentType::JSON)
+        .header(Header::new("X-Real-IP", ip.to_string()))
+        .body(json!({ "email": email, "password": password }).to_string())
+        .dispatch()
+        .await;
+    (response.status(), started.elapsed())
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn failures_delay_later_logins_from_the_same_client() {
+    let app = TestApp::spawn_with(|config| {
+        config.login_delay_ms = 150;
+        config.login_delay_max_ms = 400;
+    })
+    .await;
+    let email = unique_email();
+    app.register(&email, "password123").await;
+
+    for _ in 0..3 {
+        let (status, _) = timed_login(&app, &email, "wrong-password", "203.0.113.7").await;
+        assert_eq!(status, Status::Unauthorized);
+    }
+
+    // Three failures, capped at 400ms
+    let (status, elapsed) = timed_login(&app, &email, "password123", "203.0.113.7").await;
+    assert_eq!(status, Status::Ok);
+    assert!(elapsed >= Duration::from_millis(400), "{:?}", elapsed);
+
+    // The success clears the delay
+    let (_, elapsed) = timed_login(&app, &email, "password123", "203.0.113.7").await;
+    assert!(elapsed < Duration::from_millis(150), "{:?}", elapsed);
+
+    // Other clients aren't slowed by someone else's failures
+    timed_login(&app, &email, "wrong-password", "203.0.113.7").await;
+    let (_, elapsed) = timed_login(&app, &email, "password123", "198.51.100.2").await;
+    assert!(elapsed < Duration::from_millis(150), "{:?}", elapsed);
+}
+
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn failing_logins_for_many_accounts_blocks_the_address() {
+    let app = TestApp::spawn_with(|config| config.stuffing_distinct_emails = 3).await;
+    let email = unique_email();
+    app.register(&email, "password123").await;
+    let id = uuid::Uuid::new_v4().into_bytes();
+    let (ip, other_ip) = (
+        format!("10.{}.{}.{}", id[0], id[1], id[2]),
+        format!("10.{}.{}.{}", id[3], id[4], id[5]),
+    );
+
+    for _ in 0..3 {
+        let (status, _) = timed_login(&app, &unique_email(), "password123", &ip).await;
+        assert_eq!(status, Status::Unauthorized);
+    }
+
+    // Even a valid login is refused from the address now
+    let response = app
+        .client
+        .post("/api/v1/auth/login")
+        .header(ContentType::JSON)
+        .header(Header::new("X-Real-IP", ip.clone()))
+        .body(json!({ "email": email, "password": "password123" }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::TooManyRequests);
+    let retry_after: i64 = response.headers().get_one("Retry-After").unwrap().parse().unwrap();
+    assert!(retry_after > 59 * 60);
+    assert_eq!(response_json(response).await["code"], "ip_blocked");
+    assert_eq!(timed_login(&app, &email, "password123", &other_ip).await.0, Status::Ok);
+
+    let alerts: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM audit_events WHERE event_type = 'credential_stuffing_detected' AND ip = $1",
+    )
+    .bind(&ip)
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(alerts, 1);
+
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let admin_token = app.token_for(&admin.user).await;
+    let response = app.get_authorized("/api/v1/admin/ip-blocks", &admin_token).await;
+    let blocks = response_json(response).await;
+    assert!(blocks["ip_blocks"].as_array().unwrap().iter().any(|block| block["ip"] == ip.as_str()));
+
+    let response = app.delete_authorized(&format!("/api/v1/admin/ip-blocks/{}", ip), &admin_token).await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(timed_login(&app, &email, "password123", &ip).await.0, Status::Ok);
+    let response = app.delete_authorized(&format!("/api/v1/admin/ip-blocks/{}", ip), &admin_token).await;
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+async fn login_from<'a>(
+    app: &'a TestApp,
+    email: &str,
+    password: &str,
+    ip: &str,
+    user_agent: Option<&str>,
+) -> LocalResponse<'a> {
+    let mut request = app
+        .client
+        .post("/api/v1/auth/login")
+        .header(ContentType::JSON)
+        .header(Header::new("X-Real-IP", ip.to_string()))
+        .body(json!({ "email": email, "password": password }).to_string());
+    if let Some(user_agent) = user_agent {
+        request = request.header(Header::new("User-Agent", user_agent.to_string()));
+    }
+    request.dispatch().await
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn logins_from_a_new_address_confirm_an_emailed_code() {
+    let app = TestApp::spawn_with(|config| {
+        config.login_risk = LoginRiskEngine::Heuristic;
+        config.login_risk_step_up_score = 40;
+    })
+    .await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let browser = Some("Mozilla/5.0");
+
+    // The first login has no history to compare against
+    let response = login_from(&app, user.email(), &user.password, "198.51.100.1", browser).await;
+    assert_eq!(response.status(), Status::Ok);
+    assert!(response_json(response).await["token"].is_string());
+    let response = login_from(&app, user.email(), &user.password, "198.51.100.1", browser).await;
+    assert!(response_json(response).await["token"].is_string());
+
+    let response = login_from(&app, user.email(), &user.password, "198.51.100.2", browser).await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    assert!(body["token"].is_null());
+    assert_eq!(body["mfa_method"], "email");
+    let mfa_token = body["mfa_token"].as_str().unwrap();
+
+    let email = app.mailbox().last_to(user.email()).unwrap().message;
+    assert_eq!(email.subject, "Your login code");
+    assert!(email.body.contains("198.51.100.2"));
+    let code = email
+        .body
+        .lines()
+        .find(|line| line.len() == 6 && line.chars().all(|c| c.is_ascii_digit()))
+        .unwrap();
+
+    let wrong = if code == "000000" { "111111" } else { "000000" };
+    let response = app.post_json("/api/v1/auth/login/mfa", json!({ "mfa_token": mfa_token, "code": wrong })).await;
+    assert_eq!(response.status(), Status::BadRequest);
+    let response = app
+        .client
+        .post("/api/v1/auth/login/mfa")
+        .header(ContentType::JSON)
+        .header(Header::new("X-Real-IP", "198.51.100.2"))
+        .body(json!({ "mfa_token": mfa_token, "code": code }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert!(response_json(response).await["token"].is_string());
+
+    // The address is familiar now
+    let response = login_from(&app, user.email(), &user.password, "198.51.100.2", browser).await;
+    assert!(response_json(response).await["token"].is_string());
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn high_risk_logins_are_refused() {
+    let app = TestApp::spawn_with(|config| {
+        config.login_risk = LoginRiskEngine::Heuristic;
+        config.login_risk_block_score = 60;
+    })
+    .await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+
+    let response = login_from(&app, user.email(), &user.password, "198.51.100.1", Some("Mozilla/5.0")).await;
+    assert_eq!(response.status(), Status::Ok);
+
+    // A new address and no User-Agent add up to 60
+    let response = login_from(&app, user.email(), &user.password, "198.51.100.3", None).await;
+    assert_eq!(response.status(), Status::Forbidden);
+    assert_eq!(response_json(response).await["code"], "login_blocked");
+}
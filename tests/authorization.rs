@@ -0,0 +1,501 @@
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use rocket::http::{ContentType, Header, Status};
+use rocket::serde::json::{json, Value};
+use uuid::Uuid;
+
+use rocket_auth_boilerplate::auth::{oauth, oauth_clients};
+use rocket_auth_boilerplate::config::TokenExchangePolicy;
+use rocket_auth_boilerplate::repositories::oauth_clients as client_repo;
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, TestApp};
+
+const REDIRECT_URI: &str = "https://app.example.com/callback";
+
+async fn create_client(app: &TestApp, confidential: bool) -> Value {
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let admin_token = app.token_for(&admin.user).await;
+    let response = app
+        .post_json_authorized(
+            "/api/v1/admin/oauth-clients",
+            &admin_token,
+            json!({
+                "name": "Photo Printer",
+                "redirect_uris": [REDIRECT_URI],
+                "scopes": ["openid", "email", "users:read"],
+                "confidential": confidential
+            }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::Created);
+    response_json(response).await
+}
+
+fn query_param(url: &str, name: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).unwrap();
+    url.query_pairs().find(|(key, _)| key == name).map(|(_, value)| value.into_owned())
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn public_clients_get_scoped_tokens_with_pkce() {
+    let app = TestApp::spawn().await;
+    let created = create_client(&app, false).await;
+    assert!(created["client_secret"].is_null());
+    let client_id = created["client"]["client_id"].as_str().unwrap().to_string();
+
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+    let verifier = "a-sufficiently-long-and-random-code-verifier-value";
+    let challenge = oauth::code_challenge(verifier);
+
+    // Public clients can't skip PKCE
+    let without_pkce = format!(
+        "/api/v1/auth/authorize?client_id={}&redirect_uri={}&response_type=code",
+        client_id, REDIRECT_URI
+    );
+    let response = app.get_authorized(&without_pkce, &token).await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let request = json!({
+        "client_id": client_id,
+        "redirect_uri": REDIRECT_URI,
+        "response_type": "code",
+        "scope": "openid email",
+        "state": "xyz 123",
+        "code_challenge": challenge,
+        "code_challenge_method": "S256"
+    });
+    let describe = format!(
+        "/api/v1/auth/authorize?client_id={}&redirect_uri={}&response_type=code&scope=openid%20email&code_challenge={}&code_challenge_method=S256",
+        client_id, REDIRECT_URI, challenge
+    );
+    let body = response_json(app.get_authorized(&describe, &token).await).await;
+    assert_eq!(body["client"]["name"], "Photo Printer");
+    assert_eq!(body["scopes"], json!(["email", "openid"]));
+    assert_eq!(body["consent_required"], true);
+
+    // Redirect URIs must match a registered one exactly
+    let mut foreign = request.clone();
+    foreign["redirect_uri"] = json!("https://evil.example.com/callback");
+    foreign["approve"] = json!(true);
+    let response = app.post_json_authorized("/api/v1/auth/authorize", &token, foreign).await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let mut approval = request.clone();
+    approval["approve"] = json!(true);
+    let body = response_json(app.post_json_authorized("/api/v1/auth/authorize", &token, approval).await).await;
+    let redirect_to = body["redirect_to"].as_str().unwrap();
+    assert!(redirect_to.starts_with(REDIRECT_URI));
+    assert_eq!(query_param(redirect_to, "state").as_deref(), Some("xyz 123"));
+    let code = query_param(redirect_to, "code").unwrap();
+
+    let exchange = |verifier: &str| {
+        format!(
+            "grant_type=authorization_code&code={}&redirect_uri={}&client_id={}&code_verifier={}",
+            code, REDIRECT_URI, client_id, verifier
+        )
+    };
+    let response = app
+        .client
+        .post("/api/v1/auth/token")
+        .header(ContentType::Form)
+        .body(exchange("the-wrong-verifier"))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+    assert_eq!(response_json(response).await["error"], "invalid_grant");
+
+    // A failed exchange still consumed the code
+    let response = app
+        .client
+        .post("/api/v1/auth/token")
+        .header(ContentType::Form)
+        .body(exchange(verifier))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let mut approval = request.clone();
+    approval["approve"] = json!(true);
+    let body = response_json(app.post_json_authorized("/api/v1/auth/authorize", &token, approval).await).await;
+    let code = query_param(body["redirect_to"].as_str().unwrap(), "code").unwrap();
+    let response = app
+        .client
+        .post("/api/v1/auth/token")
+        .header(ContentType::Form)
+        .body(format!(
+            "grant_type=authorization_code&code={}&redirect_uri={}&client_id={}&code_verifier={}",
+            code, REDIRECT_URI, client_id, verifier
+        ))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    assert_eq!(body["token_type"], "Bearer");
+    assert_eq!(body["scope"], "email openid");
+    let access_token = body["access_token"].as_str().unwrap().to_string();
+
+    // The token carries only the granted scopes
+    let claims = response_json(app.get_authorized("/api/v1/auth/userinfo", &access_token).await).await;
+    assert_eq!(claims["email"], user.email());
+    assert_eq!(app.get_authorized("/api/v1/auth/me", &access_token).await.status(), Status::Forbidden);
+
+    // Granted scopes no longer need consent
+    let body = response_json(app.get_authorized(&describe, &token).await).await;
+    assert_eq!(body["consent_required"], false);
+
+    // Revoking the application ends its tokens
+    let body = response_json(app.get_authorized("/api/v1/auth/me/applications", &token).await).await;
+    assert_eq!(body["applications"][0]["client_id"], client_id);
+    assert_eq!(body["applications"][0]["scopes"], json!(["email", "openid"]));
+
+    let response = app
+        .delete_authorized(&format!("/api/v1/auth/me/applications/{}", client_id), &token)
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(app.get_authorized("/api/v1/auth/userinfo", &access_token).await.status(), Status::Unauthorized);
+    let body = response_json(app.get_authorized("/api/v1/auth/me/applications", &token).await).await;
+    assert_eq!(body["applications"], json!([]));
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn confidential_clients_authenticate_at_the_token_endpoint() {
+    let app = TestApp::spawn().await;
+    let created = create_client(&app, true).await;
+    let client_id = created["client"]["client_id"].as_str().unwrap().to_string();
+    let secret = created["client_secret"].as_str().unwrap().to_string();
+
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+
+    // Denying sends the user back with an error
+    let request = json!({
+        "client_id": client_id,
+        "redirect_uri": REDIRECT_URI,
+        "response_type": "code",
+        "scope": "users:read",
+        "approve": false
+    });
+    let body = response_json(app.post_json_authorized("/api/v1/auth/authorize", &token, request.clone()).await).await;
+    assert_eq!(query_param(body["redirect_to"].as_str().unwrap(), "error").as_deref(), Some("access_denied"));
+
+    // Clients can't ask for scopes they weren't registered with
+    let mut greedy = request.clone();
+    greedy["scope"] = json!("users:write");
+    greedy["approve"] = json!(true);
+    let response = app.post_json_authorized("/api/v1/auth/authorize", &token, greedy).await;
+    assert_eq!(response.status(), Status::BadRequest);
+    assert_eq!(response_json(response).await["code"], "invalid_scope");
+
+    let mut approval = request.clone();
+    approval["approve"] = json!(true);
+    let body = response_json(app.post_json_authorized("/api/v1/auth/authorize", &token, approval).await).await;
+    let code = query_param(body["redirect_to"].as_str().unwrap(), "code").unwrap();
+    let form = format!("grant_type=authorization_code&code={}&redirect_uri={}", code, REDIRECT_URI);
+
+    let wrong = STANDARD.encode(format!("{}:wrong", client_id));
+    let response = app
+        .client
+        .post("/api/v1/auth/token")
+        .header(ContentType::Form)
+        .header(Header::new("Authorization", format!("Basic {}", wrong)))
+        .body(form.clone())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert_eq!(response_json(response).await["error"], "invalid_client");
+
+    let basic = STANDARD.encode(format!("{}:{}", client_id, secret));
+    let response = app
+        .client
+        .post("/api/v1/auth/token")
+        .header(ContentType::Form)
+        .header(Header::new("Authorization", format!("Basic {}", basic)))
+        .body(form)
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let access_token = response_json(response).await["access_token"].as_str().unwrap().to_string();
+
+    let body = response_json(app.get_authorized("/api/v1/auth/me", &access_token).await).await;
+    assert_eq!(body["user"]["email"], user.email());
+    assert_eq!(app.get_authorized("/api/v1/auth/userinfo", &access_token).await.status(), Status::Forbidden);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn admins_manage_oauth_clients() {
+    let app = TestApp::spawn().await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let admin_token = app.token_for(&admin.user).await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let user_token = app.token_for(&user.user).await;
+
+    let response = app.get_authorized("/api/v1/admin/oauth-clients", &user_token).await;
+    assert_eq!(response.status(), Status::Forbidden);
+
+    let response = app
+        .post_json_authorized(
+            "/api/v1/admin/oauth-clients",
+            &admin_token,
+            json!({ "name": "Bad", "redirect_uris": ["not a url"], "scopes": ["openid"] }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let response = app
+        .post_json_authorized(
+            "/api/v1/admin/oauth-clients",
+            &admin_token,
+            json!({ "name": "Bad", "redirect_uris": [REDIRECT_URI], "scopes": ["everything"] }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let created = create_client(&app, true).await;
+    assert!(created["client"].get("client_secret_hash").is_none());
+    let id = created["client"]["id"].as_str().unwrap().to_string();
+    let uri = format!("/api/v1/admin/oauth-clients/{}", id);
+
+    let response = app
+        .client
+        .patch(uri.clone())
+        .header(ContentType::JSON)
+        .header(Header::new("Authorization", format!("Bearer {}", admin_token)))
+        .body(json!({ "name": "Photo Printer Pro", "logo_url": "https://app.example.com/logo.png" }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(app.get_authorized(&uri, &admin_token).await).await;
+    assert_eq!(body["client"]["name"], "Photo Printer Pro");
+    assert_eq!(body["client"]["logo_url"], "https://app.example.com/logo.png");
+    assert_eq!(body["client"]["redirect_uris"], json!([REDIRECT_URI]));
+
+    let body = response_json(app.get_authorized("/api/v1/admin/oauth-clients", &admin_token).await).await;
+    assert!(body["clients"].as_array().unwrap().iter().any(|client| client["id"] == id.as_str()));
+
+    assert_eq!(app.delete_authorized(&uri, &admin_token).await.status(), Status::Ok);
+    assert_eq!(app.get_authorized(&uri, &admin_token).await.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn clients_register_themselves_with_the_initial_access_token() {
+    let disabled = TestApp::spawn().await;
+    let registration = json!({
+        "client_name": "Calendar Sync",
+        "redirect_uris": [REDIRECT_URI],
+        "scope": "openid email"
+    });
+    let response = disabled
+        .post_json_authorized("/api/v1/auth/register-client", "anything", registration.clone())
+        .await;
+    assert_eq!(response.status(), Status::NotFound);
+
+    let app = TestApp::spawn_with(|config| config.client_registration_token = Some("initial-token".to_string())).await;
+    let discovery = response_json(app.client.get("/.well-known/openid-configuration").dispatch().await).await;
+    assert!(discovery["registration_endpoint"].as_str().unwrap().ends_with("/api/v1/auth/register-client"));
+
+    let response = app
+        .post_json_authorized("/api/v1/auth/register-client", "wrong-token", registration.clone())
+        .await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert_eq!(response_json(response).await["error"], "invalid_token");
+
+    let mut fragment = registration.clone();
+    fragment["redirect_uris"] = json!(["https://app.example.com/callback#frag"]);
+    let response = app
+        .post_json_authorized("/api/v1/auth/register-client", "initial-token", fragment)
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+    assert_eq!(response_json(response).await["error"], "invalid_redirect_uri");
+
+    let response = app
+        .post_json_authorized("/api/v1/auth/register-client", "initial-token", registration)
+        .await;
+    assert_eq!(response.status(), Status::Created);
+    let body = response_json(response).await;
+    assert_eq!(body["scope"], "email openid");
+    assert_eq!(body["token_endpoint_auth_method"], "client_secret_basic");
+    assert!(body["client_secret"].as_str().unwrap().starts_with("cs_"));
+    let client_id = body["client_id"].as_str().unwrap().to_string();
+    let registration_token = body["registration_access_token"].as_str().unwrap().to_string();
+    let uri = format!("/api/v1/auth/register-client/{}", client_id);
+    assert!(body["registration_client_uri"].as_str().unwrap().ends_with(&uri));
+
+    // The registration access token reads and deletes only its own registration
+    assert_eq!(app.get_authorized(&uri, "initial-token").await.status(), Status::Unauthorized);
+    let body = response_json(app.get_authorized(&uri, &registration_token).await).await;
+    assert_eq!(body["client_name"], "Calendar Sync");
+    assert!(body.get("client_secret").is_none());
+
+    assert_eq!(app.delete_authorized(&uri, &registration_token).await.status(), Status::NoContent);
+    assert_eq!(app.get_authorized(&uri, &registration_token).await.status(), Status::Unauthorized);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn clients_revoke_only_their_own_tokens() {
+    let app = TestApp::spawn().await;
+    let owner = create_client(&app, true).await;
+    let other = create_client(&app, true).await;
+    let client_id = owner["client"]["client_id"].as_str().unwrap().to_string();
+    let secret = owner["client_secret"].as_str().unwrap().to_string();
+
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+    let approval = json!({
+        "client_id": client_id,
+        "redirect_uri": REDIRECT_URI,
+        "response_type": "code",
+        "scope": "users:read",
+        "approve": true
+    });
+    let body = response_json(app.post_json_authorized("/api/v1/auth/authorize", &token, approval).await).await;
+    let code = query_param(body["redirect_to"].as_str().unwrap(), "code").unwrap();
+    let response = app
+        .client
+        .post("/api/v1/auth/token")
+        .header(ContentType::Form)
+        .body(format!(
+            "grant_type=authorization_code&code={}&redirect_uri={}&client_id={}&client_secret={}",
+            code, REDIRECT_URI, client_id, secret
+        ))
+        .dispatch()
+        .await;
+    let access_token = response_json(response).await["access_token"].as_str().unwrap().to_string();
+
+    let revoke = |client_id: &str, secret: &str| {
+        format!("token={}&token_type_hint=access_token&client_id={}&client_secret={}", access_token, client_id, secret)
+    };
+
+    let response = app
+        .client
+        .post("/api/v1/auth/revoke")
+        .header(ContentType::Form)
+        .body(format!("token={}", access_token))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    // Another client's request is accepted but changes nothing
+    let response = app
+        .client
+        .post("/api/v1/auth/revoke")
+        .header(ContentType::Form)
+        .body(revoke(
+            other["client"]["client_id"].as_str().unwrap(),
+            other["client_secret"].as_str().unwrap(),
+        ))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(app.get_authorized("/api/v1/auth/me", &access_token).await.status(), Status::Ok);
+
+    for _ in 0..2 {
+        let response = app
+            .client
+            .post("/api/v1/auth/revoke")
+            .header(ContentType::Form)
+            .body(revoke(&client_id, &secret))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+    }
+    assert_eq!(app.get_authorized("/api/v1/auth/me", &access_token).await.status(), Status::Unauthorized);
+
+    // The user's own session is untouched
+    assert_eq!(app.get_authorized("/api/v1/auth/me", &token).await.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn services_exchange_user_tokens_for_narrower_audience_tokens() {
+    // The policy names the service's client_id, so register it under a known one
+    let client_id = format!("client_{}", Uuid::new_v4().simple());
+    let policy_app = TestApp::spawn_with(|config| {
+        config.token_exchange = vec![TokenExchangePolicy {
+            audience: "billing".to_string(),
+            clients: vec![client_id.clone()],
+            scopes: vec!["users:read".to_string(), "email".to_string()],
+            lifetime_seconds: 120,
+        }];
+    })
+    .await;
+    let (secret, secret_hash) = oauth_clients::generate_secret();
+    let mut conn = policy_app.pool.acquire().await.unwrap();
+    client_repo::create(
+        &mut conn,
+        &client_id,
+        Some(&secret_hash),
+        "Checkout",
+        &[REDIRECT_URI.to_string()],
+        &["users:read".to_string()],
+        None,
+    )
+    .await
+    .unwrap();
+    let outsider = create_client(&policy_app, true).await;
+
+    let user = UserFactory::verified().insert(&policy_app.pool).await;
+    let user_token = policy_app.token_for(&user.user).await;
+    let exchange = |client_id: &str, secret: &str, extra: &str| {
+        format!(
+            "grant_type=urn:ietf:params:oauth:grant-type:token-exchange\
+             &subject_token={}&subject_token_type=urn:ietf:params:oauth:token-type:access_token\
+             &client_id={}&client_secret={}{}",
+            user_token, client_id, secret, extra
+        )
+    };
+    let post = |body: String| async {
+        policy_app
+            .client
+            .post("/api/v1/auth/token")
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch()
+            .await
+    };
+
+    let response = post(exchange(&client_id, &secret, "&audience=reports")).await;
+    assert_eq!(response_json(response).await["error"], "invalid_target");
+
+    let response = post(exchange(
+        outsider["client"]["client_id"].as_str().unwrap(),
+        outsider["client_secret"].as_str().unwrap(),
+        "&audience=billing",
+    ))
+    .await;
+    assert_eq!(response_json(response).await["error"], "unauthorized_client");
+
+    let response = post(exchange(&client_id, &secret, "&audience=billing&scope=users:write")).await;
+    assert_eq!(response_json(response).await["error"], "invalid_scope");
+
+    let response = post(exchange(&client_id, &secret, "&audience=billing&scope=users:read")).await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    assert_eq!(body["issued_token_type"], "urn:ietf:params:oauth:token-type:access_token");
+    assert_eq!(body["scope"], "users:read");
+    assert!(body["expires_in"].as_i64().unwrap() <= 120);
+    let exchanged = body["access_token"].as_str().unwrap().to_string();
+
+    let payload = exchanged.split('.').nth(1).unwrap();
+    let claims: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap();
+    assert_eq!(claims["aud"], "billing");
+    assert_eq!(claims["sub"], user.id().to_string());
+
+    // Meant for billing, so this API refuses it, and it can't be exchanged again
+    assert_eq!(policy_app.get_authorized("/api/v1/auth/me", &exchanged).await.status(), Status::Unauthorized);
+    let billing_app = TestApp::spawn_with(|config| config.service_audience = Some("billing".to_string())).await;
+    assert_eq!(billing_app.get_authorized("/api/v1/auth/me", &exchanged).await.status(), Status::Ok);
+    let response = post(format!(
+        "grant_type=urn:ietf:params:oauth:grant-type:token-exchange\
+         &subject_token={}&subject_token_type=urn:ietf:params:oauth:token-type:access_token\
+         &client_id={}&client_secret={}&audience=billing",
+        exchanged, client_id, secret
+    ))
+    .await;
+    assert_eq!(response_json(response).await["error"], "invalid_grant");
+}
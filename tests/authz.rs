@@ -0,0 +1,48 @@
+use rocket::http::Status;
+
+use rocket_auth_boilerplate::authz::{Context, Decision, Policy, PolicyEngine, PolicyError, Resource, Subject};
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, TestApp};
+
+/// Lets support staff delete users and nothing else, regardless of stored permissions
+struct SupportDeletesUsers;
+
+#[rocket::async_trait]
+impl PolicyEngine for SupportDeletesUsers {
+    async fn decide(
+        &self,
+        subject: &Subject,
+        action: &str,
+        resource: &Resource,
+        context: &Context,
+    ) -> Result<Decision, PolicyError> {
+        let allowed = subject.role == "support"
+            && action == "users.delete"
+            && resource.kind.as_deref() == Some("user")
+            && context.method == "DELETE";
+        Ok(if allowed { Decision::Allow } else { Decision::Deny })
+    }
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn custom_policy_engine_replaces_role_permissions() {
+    let app = TestApp::spawn_with_policy(Policy::new(SupportDeletesUsers)).await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let admin_token = app.token_for(&admin.user).await;
+    let support = UserFactory::verified().with_role("support").insert(&app.pool).await;
+    let support_token = app.token_for(&support.user).await;
+    let victim = UserFactory::verified().insert(&app.pool).await;
+    let delete_uri = format!("/api/v1/admin/users/{}", victim.id());
+
+    // Admins hold users.delete and permissions.manage, but the engine decides
+    let response = app.delete_authorized(&delete_uri, &admin_token).await;
+    assert_eq!(response.status(), Status::Forbidden);
+    let body = response_json(response).await;
+    assert_eq!(body["code"], "missing_permission");
+    let response = app.get_authorized("/api/v1/admin/permissions", &admin_token).await;
+    assert_eq!(response.status(), Status::Forbidden);
+
+    let response = app.delete_authorized(&delete_uri, &support_token).await;
+    assert_eq!(response.status(), Status::Ok);
+}
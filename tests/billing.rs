@@ -0,0 +1,171 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use rocket::http::{Header, Status};
+use rocket::serde::json::{json, Value};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use rocket_auth_boilerplate::config::StripeConfig;
+use rocket_auth_boilerplate::repositories::users;
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, unique_email, TestApp};
+
+const WEBHOOK_SECRET: &str = "whsec_test";
+
+fn stripe(api_url: &str) -> StripeConfig {
+    StripeConfig {
+        secret_key: "sk_test_123".to_string(),
+        webhook_secret: Some(WEBHOOK_SECRET.to_string()),
+        api_url: api_url.to_string(),
+    }
+}
+
+/// Answers every request with a new customer, like Stripe's `POST /v1/customers`, keeping the requests
+async fn mock_stripe() -> (String, Arc<Mutex<Vec<String>>>) {
+    use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let listener = rocket::tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let received = requests.clone();
+    rocket::tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut request = vec![0; 8192];
+            let read = stream.read(&mut request).await.unwrap_or(0);
+            received.lock().unwrap().push(String::from_utf8_lossy(&request[..read]).to_string());
+            let body = json!({ "id": format!("cus_{}", Uuid::new_v4().simple()), "object": "customer" }).to_string();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        }
+    });
+    (format!("http://{}", address), requests)
+}
+
+/// A `Stripe-Signature` header for `payload`, signed at `timestamp`
+fn signature(payload: &str, timestamp: i64) -> Header<'static> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(WEBHOOK_SECRET.as_bytes()).unwrap();
+    mac.update(format!("{}.{}", timestamp, payload).as_bytes());
+    let value = format!("t={},v1={}", timestamp, hex::encode(mac.finalize().into_bytes()));
+    Header::new("Stripe-Signature", value)
+}
+
+fn subscription_event(customer: &str, status: &str, created: i64) -> String {
+    json!({
+        "id": format!("evt_{}", Uuid::new_v4().simple()),
+        "type": "customer.subscription.updated",
+        "created": created,
+        "data": { "object": { "object": "subscription", "customer": customer, "status": status } }
+    })
+    .to_string()
+}
+
+async fn deliver(app: &TestApp, payload: String, signature: Header<'static>) -> Status {
+    app.client
+        .post("/api/v1/billing/stripe/webhook")
+        .header(signature)
+        .body(payload)
+        .dispatch()
+        .await
+        .status()
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn registering_creates_a_stripe_customer() {
+    let (api_url, requests) = mock_stripe().await;
+    let app = TestApp::spawn_with(|config| config.stripe = Some(stripe(&api_url))).await;
+
+    // The subscriber starts asynchronously, so register until a customer comes through
+    let mut created = None;
+    'register: for _ in 0..10 {
+        let email = unique_email();
+        assert_eq!(app.register(&email, "password123").await.status(), Status::Created);
+        for _ in 0..20 {
+            let mut conn = app.pool.acquire().await.unwrap();
+            let user = users::find_by_email(&mut conn, &email).await.unwrap().unwrap();
+            if user.stripe_customer_id.is_some() {
+                created = Some(user);
+                break 'register;
+            }
+            rocket::tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+    let user = created.expect("a Stripe customer was created");
+    assert!(user.stripe_customer_id.unwrap().starts_with("cus_"));
+
+    let requests = requests.lock().unwrap();
+    let request = requests.last().unwrap().to_lowercase();
+    assert!(request.starts_with("post /v1/customers"));
+    assert!(request.contains(&format!("idempotency-key: customer-{}", user.id)));
+    assert!(request.contains("authorization: bearer sk_test_123"));
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn subscription_webhooks_update_the_users_status() {
+    let app = TestApp::spawn_with(|config| config.stripe = Some(stripe("http://127.0.0.1:9"))).await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let customer = format!("cus_{}", Uuid::new_v4().simple());
+    let mut conn = app.pool.acquire().await.unwrap();
+    assert!(users::set_stripe_customer_if_unset(&mut conn, user.id(), &customer).await.unwrap());
+    let token = app.token_for(&user.user).await;
+
+    let now = Utc::now().timestamp();
+    let payload = subscription_event(&customer, "active", now);
+    assert_eq!(deliver(&app, payload.clone(), signature(&payload, now)).await, Status::Ok);
+    let me = response_json(app.get_authorized("/api/v1/auth/me", &token).await).await;
+    assert_eq!(me["user"]["subscription_status"], "active");
+
+    // Stripe doesn't deliver in order; an older event arriving late changes nothing
+    let payload = subscription_event(&customer, "incomplete", now - 60);
+    assert_eq!(deliver(&app, payload.clone(), signature(&payload, now)).await, Status::Ok);
+    let me = response_json(app.get_authorized("/api/v1/auth/me", &token).await).await;
+    assert_eq!(me["user"]["subscription_status"], "active");
+
+    let payload = subscription_event(&customer, "canceled", now + 1);
+    assert_eq!(deliver(&app, payload.clone(), signature(&payload, now)).await, Status::Ok);
+    let me: Value = response_json(app.get_authorized("/api/v1/auth/me", &token).await).await;
+    assert_eq!(me["user"]["subscription_status"], "canceled");
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn webhooks_need_a_valid_signature() {
+    let app = TestApp::spawn_with(|config| config.stripe = Some(stripe("http://127.0.0.1:9"))).await;
+    let now = Utc::now().timestamp();
+    let payload = subscription_event("cus_unknown", "active", now);
+
+    let tampered = payload.replace("active", "trialing");
+    let response = app
+        .client
+        .post("/api/v1/billing/stripe/webhook")
+        .header(signature(&payload, now))
+        .body(tampered)
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+    assert_eq!(response_json(response).await["code"], "invalid_signature");
+
+    // Replayed long after it was signed
+    let stale = now - 3600;
+    assert_eq!(deliver(&app, payload.clone(), signature(&payload, stale)).await, Status::BadRequest);
+
+    let response = app.client.post("/api/v1/billing/stripe/webhook").body(payload).dispatch().await;
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn webhooks_are_disabled_without_stripe() {
+    let app = TestApp::spawn().await;
+    let now = Utc::now().timestamp();
+    let payload = subscription_event("cus_unknown", "active", now);
+    assert_eq!(deliver(&app, payload.clone(), signature(&payload, now)).await, Status::NotFound);
+}
@@ -0,0 +1,55 @@
+use rocket::data::ByteUnit;
+use rocket::http::Status;
+use rocket::serde::json::json;
+
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, TestApp};
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn credential_endpoints_refuse_bodies_over_their_limit() {
+    let app = TestApp::spawn_with(|config| config.body_limits.credentials = ByteUnit::Kibibyte(1)).await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+
+    let response = app.login(user.email(), &"x".repeat(2048)).await;
+    assert_eq!(response.status(), Status::PayloadTooLarge);
+    let body = response_json(response).await;
+    assert_eq!(body["code"], "payload_too_large");
+    assert!(body["details"].as_str().unwrap().contains("1024 bytes"));
+
+    let response = app.login(user.email(), &user.password).await;
+    assert_eq!(response.status(), Status::Ok);
+
+    // Other endpoints keep the general JSON limit
+    let token = app.login_token(user.email(), &user.password).await;
+    let response = app
+        .post_json_authorized(
+            "/api/v1/auth/me/emails",
+            &token,
+            json!({ "email": format!("{}@example.com", "a".repeat(2048)) }),
+        )
+        .await;
+    assert_ne!(response.status(), Status::PayloadTooLarge);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn deeply_nested_json_is_refused_before_parsing() {
+    let app = TestApp::spawn_with(|config| config.body_limits.json_depth = 4).await;
+
+    let nested = format!("{}1{}", "[".repeat(5), "]".repeat(5));
+    let body = format!(r#"{{"email": {}, "password": "[[[[[[not nesting"}}"#, nested);
+    let response = app
+        .client
+        .post("/api/v1/auth/login")
+        .header(rocket::http::ContentType::JSON)
+        .body(body)
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::PayloadTooLarge);
+    assert_eq!(response_json(response).await["code"], "json_too_deep");
+
+    // Brackets inside strings don't count
+    let response = app.login("nobody@example.com", "[[[[[[{{{{{{").await;
+    assert_eq!(response.status(), Status::Unauthorized);
+}
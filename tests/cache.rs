@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+use rocket::http::Status;
+
+use rocket_auth_boilerplate::auth::token_versions::TokenVersions;
+use rocket_auth_boilerplate::cache::{MemoryCache, SharedCache};
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::TestApp;
+
+#[rocket::async_test]
+async fn memory_cache_entries_expire_after_their_ttl() {
+    let cache = SharedCache::new(MemoryCache::new(100));
+
+    cache.set("short", &1, Duration::from_millis(100)).await;
+    cache.set("long", &2, Duration::from_secs(60)).await;
+    cache.set("never", &3, Duration::ZERO).await;
+    assert_eq!(cache.get::<i32>("short").await, Some(1));
+    assert_eq!(cache.get::<i32>("never").await, None);
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(cache.get::<i32>("short").await, None);
+    assert_eq!(cache.get::<i32>("long").await, Some(2));
+
+    cache.delete("long").await;
+    assert_eq!(cache.get::<i32>("long").await, None);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn token_version_bumps_reach_replicas_sharing_the_cache() {
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let mut conn = app.pool.acquire().await.unwrap();
+
+    let cache = SharedCache::memory();
+    let first = TokenVersions::new(Duration::from_secs(60)).with_cache(cache.clone());
+    let second = TokenVersions::new(Duration::from_secs(60)).with_cache(cache);
+
+    let version = first.current(&mut conn, user.id()).await.unwrap().unwrap();
+    let bumped = second.bump(&mut conn, user.id()).await.unwrap().unwrap();
+    assert_eq!(bumped, version + 1);
+    assert_eq!(first.current(&mut conn, user.id()).await.unwrap(), Some(bumped));
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn lockouts_are_served_from_the_cache_until_they_lift() {
+    let app = TestApp::spawn_with(|config| {
+        config.login_lockout_attempts = 2;
+        config.login_lockout_cache_seconds = 60;
+    })
+    .await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+
+    for _ in 0..2 {
+        let response = app.login(user.email(), "wrong-password").await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+    let response = app.login(user.email(), &user.password).await;
+    assert_eq!(response.status(), Status::TooManyRequests);
+
+    // The failures are gone from the database, but the lockout is cached
+    sqlx::query("DELETE FROM login_attempts WHERE LOWER(email) = LOWER($1)")
+        .bind(user.email())
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    let response = app.login(&user.email().to_uppercase(), &user.password).await;
+    assert_eq!(response.status(), Status::TooManyRequests);
+}
@@ -0,0 +1,61 @@
+use std::io::Read;
+
+use rocket::http::{Header, Status};
+use rocket::serde::json::Value;
+
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::TestApp;
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn json_responses_are_compressed_when_accepted() {
+    let app = TestApp::spawn_with(|config| config.compression_min_bytes = 200).await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let token = app.token_for(&admin.user).await;
+    let authorization = Header::new("Authorization", format!("Bearer {}", token));
+
+    let get = |accept_encoding: &'static str| {
+        app.client
+            .get("/api/v1/admin/permissions")
+            .header(authorization.clone())
+            .header(Header::new("Accept-Encoding", accept_encoding))
+            .dispatch()
+    };
+
+    let response = get("gzip, deflate").await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("Content-Encoding"), Some("gzip"));
+    assert_eq!(response.headers().get_one("Vary"), Some("Accept-Encoding"));
+    let compressed = response.into_bytes().await.unwrap();
+    let mut body = String::new();
+    flate2::read::GzDecoder::new(compressed.as_slice())
+        .read_to_string(&mut body)
+        .unwrap();
+    let body: Value = serde_json::from_str(&body).unwrap();
+    assert!(body["permissions"].is_array());
+
+    let response = get("gzip;q=0.5, br").await;
+    assert_eq!(response.headers().get_one("Content-Encoding"), Some("br"));
+    let compressed = response.into_bytes().await.unwrap();
+    let mut body = String::new();
+    brotli::Decompressor::new(compressed.as_slice(), 4096)
+        .read_to_string(&mut body)
+        .unwrap();
+    assert!(serde_json::from_str::<Value>(&body).unwrap()["permissions"].is_array());
+
+    let response = get("identity").await;
+    assert!(response.headers().get_one("Content-Encoding").is_none());
+    let body: Value = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+    assert!(body["permissions"].is_array());
+
+    // Small bodies aren't worth it
+    let response = app
+        .client
+        .get("/api/v1/auth/me/emails")
+        .header(authorization.clone())
+        .header(Header::new("Accept-Encoding", "gzip"))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert!(response.headers().get_one("Content-Encoding").is_none());
+}
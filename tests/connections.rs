@@ -0,0 +1,106 @@
+use rocket::http::Status;
+use rocket::serde::json::json;
+use uuid::Uuid;
+
+use rocket_auth_boilerplate::repositories::{external_identities, oauth_clients as client_repo, oauth_consents, users};
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, TestApp};
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn connections_list_applications_keys_and_linked_accounts() {
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+    let mut conn = app.pool.acquire().await.unwrap();
+
+    let client_id = format!("client-{}", Uuid::new_v4().simple());
+    let client = client_repo::create(&mut conn, &client_id, None, "Photo Printer", &[], &["email".to_string()], None)
+        .await
+        .unwrap();
+    oauth_consents::grant(&mut conn, user.user.id, client.id, &["email".to_string()]).await.unwrap();
+    let subject = Uuid::new_v4().to_string();
+    external_identities::link(&mut conn, "https://idp.example.com", &subject, user.user.id).await.unwrap();
+
+    let response = app
+        .post_json_authorized("/api/v1/auth/api-keys", &token, json!({ "name": "ci", "scopes": ["users:read"] }))
+        .await;
+    assert_eq!(response.status(), Status::Created);
+    let key = response_json(response).await;
+    let key_id = key["api_key"]["id"].as_str().unwrap().to_string();
+
+    let response = app.get_authorized("/api/v1/auth/me/connections", &token).await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    let connections = body["connections"].as_array().unwrap();
+    assert_eq!(connections.len(), 3);
+    // Newest first
+    assert_eq!(connections[0]["type"], "api_key");
+    assert_eq!(connections[0]["id"], key_id.as_str());
+    assert_eq!(connections[0]["revoke"], format!("/me/connections/api_key/{}", key_id));
+    assert!(connections[0].get("key_hash").is_none());
+
+    let application = connections.iter().find(|c| c["type"] == "application").unwrap();
+    assert_eq!(application["id"], client_id.as_str());
+    assert_eq!(application["name"], "Photo Printer");
+    assert_eq!(application["scopes"], json!(["email"]));
+    let account = connections.iter().find(|c| c["type"] == "linked_account").unwrap();
+    assert_eq!(account["issuer"], "https://idp.example.com");
+
+    // Each one is revoked through its own path
+    for connection in connections {
+        let path = format!("/api/v1/auth{}", connection["revoke"].as_str().unwrap());
+        let response = app.delete_authorized(&path, &token).await;
+        assert_eq!(response.status(), Status::Ok, "revoking {}", path);
+        assert_eq!(app.delete_authorized(&path, &token).await.status(), Status::NotFound);
+    }
+
+    let body = response_json(app.get_authorized("/api/v1/auth/me/connections", &token).await).await;
+    assert_eq!(body["connections"], json!([]));
+    let key = key["key"].as_str().unwrap();
+    assert_eq!(app.get_authorized("/api/v1/auth/me", key).await.status(), Status::Unauthorized);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn connections_of_other_users_cannot_be_revoked() {
+    let app = TestApp::spawn().await;
+    let owner = UserFactory::verified().insert(&app.pool).await;
+    let other = UserFactory::verified().insert(&app.pool).await;
+    let other_token = app.token_for(&other.user).await;
+    let mut conn = app.pool.acquire().await.unwrap();
+
+    let subject = Uuid::new_v4().to_string();
+    external_identities::link(&mut conn, "https://idp.example.com", &subject, owner.user.id).await.unwrap();
+    let account = &external_identities::list_for_user(&mut conn, owner.user.id).await.unwrap()[0];
+
+    let path = format!("/api/v1/auth/me/connections/linked_account/{}", account.id);
+    assert_eq!(app.delete_authorized(&path, &other_token).await.status(), Status::NotFound);
+    let unknown = format!("/api/v1/auth/me/connections/webhook/{}", account.id);
+    assert_eq!(app.delete_authorized(&unknown, &other_token).await.status(), Status::NotFound);
+    let malformed = "/api/v1/auth/me/connections/api_key/not-a-uuid";
+    assert_eq!(app.delete_authorized(malformed, &other_token).await.status(), Status::NotFound);
+
+    assert_eq!(external_identities::list_for_user(&mut conn, owner.user.id).await.unwrap().len(), 1);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn the_last_sign_in_method_cannot_be_unlinked() {
+    let app = TestApp::spawn().await;
+    let mut conn = app.pool.acquire().await.unwrap();
+    let email = format!("external-{}@external.invalid", Uuid::new_v4());
+    let user = users::create(&mut conn, &email, "unusable", "user").await.unwrap();
+    let subject = Uuid::new_v4().to_string();
+    external_identities::link(&mut conn, "https://idp.example.com", &subject, user.id).await.unwrap();
+    let token = app.token_for(&user).await;
+
+    let body = response_json(app.get_authorized("/api/v1/auth/me/connections", &token).await).await;
+    let path = format!("/api/v1/auth{}", body["connections"][0]["revoke"].as_str().unwrap());
+
+    let response = app.delete_authorized(&path, &token).await;
+    assert_eq!(response.status(), Status::Conflict);
+    let body = response_json(response).await;
+    assert_eq!(body["code"], "last_sign_in_method");
+    assert_eq!(external_identities::list_for_user(&mut conn, user.id).await.unwrap().len(), 1);
+}
@@ -0,0 +1,77 @@
+use rocket::http::Status;
+use rocket::serde::json::{json, Value};
+
+use rocket_auth_boilerplate::config::AppConfig;
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, TestApp};
+
+const SECRET: &str = "test-signed-url-secret";
+
+/// Issue an export link and return its path and query, as the local client needs them
+async fn export_link(app: &TestApp, token: &str) -> String {
+    let response = app.post_json_authorized("/api/v1/auth/me/export-link", token, json!({})).await;
+    assert_eq!(response.status(), Status::Ok);
+    let url = response_json(response).await["url"].as_str().unwrap().to_string();
+    let public_url = &app.client.rocket().state::<AppConfig>().unwrap().public_url;
+    url.strip_prefix(public_url.as_str()).unwrap().to_string()
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn export_links_download_the_users_data_once() {
+    let app = TestApp::spawn_with(|config| config.signed_url_secret = Some(SECRET.to_string())).await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+    let link = export_link(&app, &token).await;
+
+    // No Authorization header needed
+    let response = app.client.get(link.clone()).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert!(response
+        .headers()
+        .get_one("Content-Disposition")
+        .unwrap()
+        .starts_with("attachment"));
+    let body = response.into_string().await.unwrap();
+    let records: Vec<Value> = body.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(records[0]["type"], "user");
+    assert_eq!(records[0]["email"], user.email());
+    assert!(records[0].get("password_hash").is_none());
+    assert!(records.iter().any(|record| record["type"] == "session"));
+
+    let response = app.client.get(link).dispatch().await;
+    assert_eq!(response.status(), Status::Forbidden);
+    assert_eq!(response_json(response).await["code"], "invalid_signed_url");
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn tampered_export_links_are_refused() {
+    let app = TestApp::spawn_with(|config| config.signed_url_secret = Some(SECRET.to_string())).await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let other = UserFactory::verified().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+    let link = export_link(&app, &token).await;
+
+    let tampered = link.replace(&user.id().simple().to_string(), &other.id().simple().to_string());
+    let response = app.client.get(tampered).dispatch().await;
+    assert_eq!(response.status(), Status::Forbidden);
+    assert_eq!(response_json(response).await["code"], "invalid_signed_url");
+
+    let response = app.client.get("/api/v1/auth/me/export").dispatch().await;
+    assert_eq!(response.status(), Status::Forbidden);
+
+    // Refusing the tampered copy didn't use up the real one
+    assert_eq!(app.client.get(link).dispatch().await.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn export_links_are_disabled_without_a_secret() {
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+
+    let response = app.post_json_authorized("/api/v1/auth/me/export-link", &token, json!({})).await;
+    assert_eq!(response.status(), Status::NotFound);
+}
@@ -0,0 +1,47 @@
+use rocket_db_pools::Database;
+use sqlx::PgPool;
+
+use rocket_auth_boilerplate::config::DbPoolConfig;
+use rocket_auth_boilerplate::test_support::TestApp;
+use rocket_auth_boilerplate::{db, migrations, Postgres};
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn server_pool_uses_the_configured_settings() {
+    let app = TestApp::spawn_with(|config| {
+        config.db_pool.max_connections = 3;
+        config.db_pool.statement_timeout_ms = 250;
+    })
+    .await;
+    let pool = PgPool::clone(Postgres::fetch(app.client.rocket()).expect("database pool is managed"));
+
+    assert_eq!(pool.options().get_max_connections(), 3);
+    let timeout = sqlx::query_scalar::<_, String>("SHOW statement_timeout").fetch_one(&pool).await.unwrap();
+    assert_eq!(timeout, "250ms");
+
+    let error = sqlx::query("SELECT pg_sleep(1)").execute(&pool).await.unwrap_err();
+    assert!(error.to_string().contains("statement timeout"), "unexpected error: {}", error);
+}
+
+#[rocket::async_test]
+async fn lazy_pools_connect_on_first_use() {
+    let config = DbPoolConfig {
+        lazy_connect: true,
+        acquire_timeout_seconds: 1,
+        ..DbPoolConfig::default()
+    };
+    // Nothing listens there; creating the pool doesn't notice
+    let pool = db::connect("postgres://postgres@127.0.0.1:1/none", &config).await.unwrap();
+    assert_eq!(pool.size(), 0);
+    assert!(pool.acquire().await.is_err());
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn migrations_run_once_per_build() {
+    let app = TestApp::spawn().await;
+    sqlx::query("DELETE FROM schema_fingerprints").execute(&app.pool).await.unwrap();
+
+    assert!(migrations::run_if_needed(&app.pool).await.unwrap());
+    assert!(!migrations::run_if_needed(&app.pool).await.unwrap());
+}
@@ -0,0 +1,152 @@
+use std::sync::{Arc, Mutex};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use rocket::http::Status;
+use rocket::serde::json::json;
+
+use rocket_auth_boilerplate::config::EmailTransport;
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, TestApp};
+
+/// Accepts every message like an SMTP relay without TLS, keeping the lines it receives
+async fn mock_relay() -> (u16, Arc<Mutex<Vec<String>>>) {
+    use rocket::tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let listener = rocket::tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let received = lines.clone();
+    rocket::tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let mut stream = BufReader::new(stream);
+            let _ = stream.get_mut().write_all(b"220 mock ESMTP\r\n").await;
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+                    break;
+                }
+                let line = line.trim_end().to_string();
+                received.lock().unwrap().push(line.clone());
+                let reply: &[u8] = if in_data {
+                    if line != "." {
+                        continue;
+                    }
+                    in_data = false;
+                    b"250 queued\r\n"
+                } else if line.starts_with("EHLO") {
+                    b"250-mock\r\n250 AUTH PLAIN\r\n"
+                } else if line.starts_with("AUTH") {
+                    b"235 authenticated\r\n"
+                } else if line == "DATA" {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                } else if line == "QUIT" {
+                    let _ = stream.get_mut().write_all(b"221 bye\r\n").await;
+                    break;
+                } else {
+                    b"250 ok\r\n"
+                };
+                let _ = stream.get_mut().write_all(reply).await;
+            }
+        }
+    });
+    (port, lines)
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn stored_smtp_settings_replace_the_configured_transport() {
+    let app = TestApp::spawn_with(|config| {
+        config.email_transport = EmailTransport::Log;
+        config.runtime_settings = true;
+    })
+    .await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let token = app.token_for(&admin.user).await;
+    let (port, lines) = mock_relay().await;
+
+    let body = response_json(app.get_authorized("/api/v1/admin/email/settings", &token).await).await;
+    assert_eq!(body["source"], "configured");
+    assert_eq!(body["transport"], "log");
+
+    let settings = json!({
+        "host": "127.0.0.1",
+        "port": port,
+        "username": "mailer",
+        "password": "relay-secret",
+        "security": "none",
+        "from": "alerts@example.com"
+    });
+    let response = app.put_json_authorized("/api/v1/admin/email/settings", &token, settings).await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    assert_eq!(body["source"], "stored");
+    assert_eq!(body["smtp"]["password_set"], true);
+    assert!(body["smtp"].get("password").is_none());
+
+    let response = app.post_json_authorized("/api/v1/admin/email/test", &token, json!({})).await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response_json(response).await["to"], admin.email());
+
+    let credentials = format!("AUTH PLAIN {}", STANDARD.encode("\0mailer\0relay-secret"));
+    let received = lines.lock().unwrap().clone();
+    assert!(received.contains(&credentials));
+    assert!(received.contains(&"MAIL FROM:<alerts@example.com>".to_string()));
+    assert!(received.contains(&format!("RCPT TO:<{}>", admin.email())));
+    assert!(received.contains(&"Subject: Test email".to_string()));
+
+    // Saving without the password keeps it
+    let settings = json!({ "host": "127.0.0.1", "port": port, "username": "mailer", "security": "none", "from": "alerts@example.com" });
+    let body = response_json(app.put_json_authorized("/api/v1/admin/email/settings", &token, settings).await).await;
+    assert_eq!(body["smtp"]["password_set"], true);
+
+    // A relay that can't be reached is reported
+    let closed = rocket::tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let closed_port = closed.local_addr().unwrap().port();
+    drop(closed);
+    let settings = json!({ "host": "127.0.0.1", "port": closed_port, "security": "none", "from": "alerts@example.com" });
+    app.put_json_authorized("/api/v1/admin/email/settings", &token, settings).await;
+    let response = app
+        .post_json_authorized("/api/v1/admin/email/test", &token, json!({ "to": "ops@example.com" }))
+        .await;
+    assert_eq!(response.status(), Status::BadGateway);
+    assert!(response_json(response).await["details"].as_str().unwrap().contains("couldn't connect"));
+
+    let response = app.delete_authorized("/api/v1/admin/email/settings", &token).await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(app.get_authorized("/api/v1/admin/email/settings", &token).await).await;
+    assert_eq!(body["source"], "configured");
+    let response = app.delete_authorized("/api/v1/admin/email/settings", &token).await;
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn email_settings_are_checked_and_admin_only() {
+    let app = TestApp::spawn_with(|config| config.runtime_settings = true).await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let token = app.token_for(&admin.user).await;
+
+    for settings in [
+        json!({ "host": "", "from": "alerts@example.com" }),
+        json!({ "host": "smtp.example.com", "from": "not-an-address" }),
+        json!({ "host": "smtp.example.com", "port": 0, "from": "alerts@example.com" }),
+    ] {
+        let response = app.put_json_authorized("/api/v1/admin/email/settings", &token, settings).await;
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    // The memory transport sends test emails to the mailbox
+    let response = app
+        .post_json_authorized("/api/v1/admin/email/test", &token, json!({ "to": "ops@example.com" }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(app.mailbox().last_to("ops@example.com").unwrap().message.subject, "Test email");
+
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let user_token = app.token_for(&user.user).await;
+    let response = app.get_authorized("/api/v1/admin/email/settings", &user_token).await;
+    assert_eq!(response.status(), Status::Forbidden);
+}
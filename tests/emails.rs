@@ -0,0 +1,93 @@
+use rocket::http::Status;
+use rocket::serde::json::json;
+
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, token_from_email, unique_email, TestApp};
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn secondary_email_can_recover_and_become_primary() {
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+    let backup = unique_email();
+
+    let response = app
+        .post_json_authorized("/api/v1/auth/me/emails", &token, json!({ "email": backup }))
+        .await;
+    assert_eq!(response.status(), Status::Created);
+    let body = response_json(response).await;
+    let id = body["id"].as_str().unwrap().to_string();
+    assert_eq!(body["verified"], false);
+
+    // Unverified addresses can't be promoted or used for recovery
+    let primary_uri = format!("/api/v1/auth/me/emails/{}/primary", id);
+    let response = app.post_json_authorized(&primary_uri, &token, json!({})).await;
+    assert_eq!(response.status(), Status::BadRequest);
+    app.post_json("/api/v1/auth/forgot-password", json!({ "email": backup })).await;
+    let email = app.mailbox().last_to(&backup).expect("verification email sent");
+    assert!(email.message.subject.to_lowercase().contains("verify"));
+
+    let verification = token_from_email(&email.message.body).expect("token in verification email");
+    let response = app
+        .client
+        .get(format!("/api/v1/auth/verify-email?token={}", verification))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    // The address is now taken
+    let response = app.register(&backup, "password123").await;
+    assert_eq!(response.status(), Status::Conflict);
+
+    // Recovery: the reset link goes to the secondary address
+    app.post_json("/api/v1/auth/forgot-password", json!({ "email": backup })).await;
+    let email = app.mailbox().last_to(&backup).expect("reset email sent");
+    let reset = token_from_email(&email.message.body).expect("token in reset email");
+    let response = app
+        .post_json("/api/v1/auth/reset-password", json!({ "token": reset, "new_password": "new-password" }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    // The reset signed the user out
+    assert_eq!(app.get_authorized("/api/v1/auth/me", &token).await.status(), Status::Unauthorized);
+    let token = app.token_for(&user.user).await;
+    let response = app.post_json_authorized(&primary_uri, &token, json!({})).await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    assert_eq!(body["emails"][0]["email"], backup.as_str());
+    assert_eq!(body["emails"][0]["primary"], true);
+    assert_eq!(body["emails"][1]["email"], user.email());
+    assert_eq!(body["emails"][1]["verified"], true);
+
+    app.login_token(&backup, "new-password").await;
+
+    let old_id = body["emails"][1]["id"].as_str().unwrap().to_string();
+    let response = app
+        .delete_authorized(&format!("/api/v1/auth/me/emails/{}", old_id), &token)
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(app.get_authorized("/api/v1/auth/me/emails", &token).await).await;
+    assert_eq!(body["emails"].as_array().unwrap().len(), 1);
+
+    let response = app
+        .delete_authorized(&format!("/api/v1/auth/me/emails/{}", old_id), &token)
+        .await;
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn addresses_in_use_cannot_be_added() {
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let other = UserFactory::verified().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+
+    for email in [user.email(), other.email()] {
+        let response = app
+            .post_json_authorized("/api/v1/auth/me/emails", &token, json!({ "email": email }))
+            .await;
+        assert_eq!(response.status(), Status::Conflict);
+    }
+}
@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use rocket::http::Header;
+use rocket::local::asynchronous::LocalResponse;
+use rocket::tokio::io::AsyncReadExt;
+use rocket::tokio::time::timeout;
+
+use rocket_auth_boilerplate::events::{self, SecurityEventKind};
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::TestApp;
+
+/// Read whatever the stream has produced within the timeout
+async fn read_chunk(stream: &mut LocalResponse<'_>) -> String {
+    let mut buffer = vec![0u8; 4096];
+    match timeout(Duration::from_millis(500), stream.read(&mut buffer)).await {
+        Ok(Ok(read)) => String::from_utf8_lossy(&buffer[..read]).into_owned(),
+        _ => String::new(),
+    }
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn security_event_stream_pushes_own_events_and_ends_on_revocation() {
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let other = UserFactory::verified().insert(&app.pool).await;
+    let token = app.login_token(user.email(), &user.password).await;
+
+    let mut stream = app
+        .client
+        .get("/api/v1/auth/me/events/stream")
+        .header(Header::new("Authorization", format!("Bearer {}", token)))
+        .dispatch()
+        .await;
+
+    // The listener starts asynchronously, so keep logging in until an event comes through
+    let mut received = String::new();
+    for _ in 0..20 {
+        if !received.contains("event:session_created") {
+            app.login_token(other.email(), &other.password).await;
+            app.login_token(user.email(), &user.password).await;
+        } else if received.ends_with("\n\n") {
+            break;
+        }
+        received.push_str(&read_chunk(&mut stream).await);
+    }
+    assert!(received.contains("event:session_created"), "no event received: {received:?}");
+    assert!(received.contains(&user.id().to_string()));
+    assert!(!received.contains(&other.id().to_string()), "saw another user's event");
+
+    let mut conn = app.pool.acquire().await.unwrap();
+    events::emit(&mut conn, user.id(), SecurityEventKind::AllSessionsRevoked { count: 2 }).await;
+
+    let mut rest = String::new();
+    timeout(Duration::from_secs(5), stream.read_to_string(&mut rest))
+        .await
+        .expect("stream should end after revocation")
+        .unwrap();
+    assert!(rest.contains("event:all_sessions_revoked"));
+}
@@ -0,0 +1,68 @@
+use rocket::http::Status;
+use rocket::serde::json::json;
+
+use rocket_auth_boilerplate::test_support::{response_json, unique_email, TestApp};
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn guest_upgrade_keeps_the_user_id() {
+    let app = TestApp::spawn().await;
+
+    let response = app.post_json("/api/v1/auth/guest", json!({})).await;
+    assert_eq!(response.status(), Status::Created);
+    let body = response_json(response).await;
+    let guest_id = body["user"]["id"].as_str().unwrap().to_string();
+    let guest_token = body["token"].as_str().unwrap().to_string();
+
+    let body = response_json(app.get_authorized("/api/v1/auth/me", &guest_token).await).await;
+    assert_eq!(body["user"]["guest"], true);
+
+    // Guests are limited to their own account
+    let response = app
+        .post_json_authorized("/api/v1/auth/resend-verification", &guest_token, json!({}))
+        .await;
+    assert_eq!(response.status(), Status::Forbidden);
+
+    let email = unique_email();
+    let response = app
+        .post_json_authorized(
+            "/api/v1/auth/guest/upgrade",
+            &guest_token,
+            json!({ "email": email, "password": "password123" }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    assert_eq!(body["user"]["id"], guest_id.as_str());
+    let token = body["token"].as_str().unwrap().to_string();
+
+    // The guest token is retired; the new one belongs to a regular account
+    let response = app.get_authorized("/api/v1/auth/me", &guest_token).await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    let body = response_json(app.get_authorized("/api/v1/auth/me", &token).await).await;
+    assert_eq!(body["user"]["id"], guest_id.as_str());
+    assert_eq!(body["user"]["guest"], false);
+    assert_eq!(body["user"]["email"], email.as_str());
+
+    let login_token = app.login_token(&email, "password123").await;
+    assert!(!login_token.is_empty());
+    assert!(app.mailbox().last_to(&email).is_some(), "verification email sent");
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn regular_accounts_cannot_be_upgraded() {
+    let app = TestApp::spawn().await;
+    let email = unique_email();
+    app.register(&email, "password123").await;
+    let token = app.login_token(&email, "password123").await;
+
+    let response = app
+        .post_json_authorized(
+            "/api/v1/auth/guest/upgrade",
+            &token,
+            json!({ "email": unique_email(), "password": "password123" }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+}
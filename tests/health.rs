@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+use rocket::http::Status;
+
+use rocket_auth_boilerplate::test_support::{response_json, TestApp};
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn readiness_reports_the_database_and_its_circuit_breaker() {
+    let app = TestApp::spawn().await;
+
+    let response = app.client.get("/health/ready").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    assert_eq!(body["status"], "ready");
+    assert_eq!(body["database"]["status"], "up");
+    assert_eq!(body["database"]["circuit_breaker"]["state"], "closed");
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn requests_fail_fast_once_the_database_breaker_opens() {
+    // A database of its own, so it can disappear without affecting other tests
+    let name = format!("breaker_{}", uuid::Uuid::new_v4().simple());
+    let setup = TestApp::spawn().await;
+    sqlx::query(&format!("CREATE DATABASE {}", name)).execute(&setup.pool).await.unwrap();
+    let (base, _) = setup.database_url.rsplit_once('/').unwrap();
+    let url = format!("{}/{}", base, name);
+
+    let app = TestApp::spawn_with(move |config| {
+        config.database_url = url;
+        config.db_pool.acquire_timeout_seconds = 1;
+        config.db_circuit_breaker.failure_threshold = 2;
+        config.db_circuit_breaker.open_seconds = 60;
+    })
+    .await;
+    let response = app.client.get("/health/ready").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+
+    sqlx::query(&format!("DROP DATABASE {} WITH (FORCE)", name)).execute(&setup.pool).await.unwrap();
+
+    // Two failed connection attempts open the breaker
+    for _ in 0..2 {
+        let response = app.client.get("/health/ready").dispatch().await;
+        assert_eq!(response.status(), Status::ServiceUnavailable);
+    }
+
+    let started = std::time::Instant::now();
+    let response = app.client.get("/health/ready").dispatch().await;
+    assert_eq!(response.status(), Status::ServiceUnavailable);
+    let body = response_json(response).await;
+    assert_eq!(body["status"], "unavailable");
+    assert_eq!(body["database"]["status"], "down");
+    assert_eq!(body["database"]["circuit_breaker"]["state"], "open");
+    assert!(body["database"]["error"].as_str().unwrap().contains("circuit breaker is open"));
+
+    // Handlers needing a connection are refused without waiting for the pool
+    let response = app.login("nobody@example.com", "password123").await;
+    assert_eq!(response.status(), Status::ServiceUnavailable);
+    let body = response_json(response).await;
+    assert!(body["details"].as_str().unwrap().contains("database is unreachable"));
+    assert!(started.elapsed() < Duration::from_millis(500));
+}
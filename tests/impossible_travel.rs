@@ -0,0 +1,127 @@
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::asynchronous::LocalResponse;
+use rocket::serde::json::{json, Value};
+
+use rocket_auth_boilerplate::config::{ImpossibleTravel, TokenStrategy};
+use rocket_auth_boilerplate::test_support::factories::{TestUser, UserFactory};
+use rocket_auth_boilerplate::test_support::{response_json, TestApp};
+
+async fn spawn(action: ImpossibleTravel, strategy: TokenStrategy) -> TestApp {
+    TestApp::spawn_with(|config| {
+        config.geo_country_header = Some("CF-IPCountry".to_string());
+        config.impossible_travel = action;
+        config.impossible_travel_minutes = 60;
+        config.token_strategy = strategy;
+    })
+    .await
+}
+
+async fn login(app: &TestApp, user: &TestUser, country: &str) -> String {
+    let response = app
+        .client
+        .post("/api/v1/auth/login")
+        .header(ContentType::JSON)
+        .header(Header::new("CF-IPCountry", country.to_string()))
+        .body(json!({ "email": user.email(), "password": user.password }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    response_json(response).await["token"].as_str().unwrap().to_string()
+}
+
+async fn me<'a>(app: &'a TestApp, token: &str, country: &str) -> LocalResponse<'a> {
+    app.client
+        .get("/api/v1/auth/me")
+        .header(Header::new("Authorization", format!("Bearer {}", token)))
+        .header(Header::new("CF-IPCountry", country.to_string()))
+        .dispatch()
+        .await
+}
+
+fn notices(app: &TestApp, user: &TestUser) -> Vec<String> {
+    app.mailbox()
+        .messages_to(user.email())
+        .into_iter()
+        .filter(|captured| captured.message.subject == "Unusual activity on your account")
+        .map(|captured| captured.message.body)
+        .collect()
+}
+
+async fn travel_events(app: &TestApp, user: &TestUser) -> Vec<Value> {
+    sqlx::query_scalar(
+        "SELECT details FROM audit_events WHERE user_id = $1 AND event_type = 'suspicious_session_use'",
+    )
+    .bind(user.id())
+    .fetch_all(&app.pool)
+    .await
+    .unwrap()
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn sessions_used_from_another_country_too_soon_are_revoked() {
+    for strategy in [TokenStrategy::Jwt, TokenStrategy::Opaque { cache_seconds: 5 }] {
+        let app = spawn(ImpossibleTravel::Revoke, strategy).await;
+        let user = UserFactory::verified().insert(&app.pool).await;
+        let token = login(&app, &user, "DE").await;
+
+        assert_eq!(me(&app, &token, "de").await.status(), Status::Ok);
+        assert_eq!(me(&app, &token, "US").await.status(), Status::Unauthorized);
+        assert_eq!(me(&app, &token, "DE").await.status(), Status::Unauthorized);
+
+        let notices = notices(&app, &user);
+        assert_eq!(notices.len(), 1);
+        assert!(notices[0].contains("used from US shortly after being used from DE"));
+        assert!(notices[0].contains("signed that session out"));
+        let events = travel_events(&app, &user).await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["previous_country"], "DE");
+        assert_eq!(events[0]["country"], "US");
+        assert_eq!(events[0]["revoked"], true);
+
+        // Other sessions carry on
+        let token = login(&app, &user, "US").await;
+        assert_eq!(me(&app, &token, "US").await.status(), Status::Ok);
+    }
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn notify_mode_keeps_the_session_and_reports_it_once() {
+    let app = spawn(ImpossibleTravel::Notify, TokenStrategy::Jwt).await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = login(&app, &user, "DE").await;
+
+    // Requests without the header don't count
+    let response = app.get_authorized("/api/v1/auth/me", &token).await;
+    assert_eq!(response.status(), Status::Ok);
+    assert!(notices(&app, &user).is_empty());
+
+    for country in ["FR", "DE", "FR"] {
+        assert_eq!(me(&app, &token, country).await.status(), Status::Ok);
+    }
+    let notices = notices(&app, &user);
+    assert_eq!(notices.len(), 1);
+    assert!(notices[0].contains("nothing else to do"));
+    assert_eq!(travel_events(&app, &user).await[0]["revoked"], false);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn countries_seen_longer_ago_than_the_window_are_fine() {
+    let app = spawn(ImpossibleTravel::Revoke, TokenStrategy::Jwt).await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = login(&app, &user, "DE").await;
+    assert_eq!(me(&app, &token, "DE").await.status(), Status::Ok);
+
+    sqlx::query(
+        "UPDATE sessions SET created_at = created_at - INTERVAL '2 hours', \
+         seen_at = COALESCE(seen_at, created_at) - INTERVAL '2 hours' WHERE user_id = $1",
+    )
+    .bind(user.id())
+    .execute(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(me(&app, &token, "JP").await.status(), Status::Ok);
+    assert!(notices(&app, &user).is_empty());
+}
@@ -0,0 +1,90 @@
+use rocket::http::Status;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use rocket_auth_boilerplate::config::{AppConfig, InactiveAccountAction};
+use rocket_auth_boilerplate::email::sender::Mailer;
+use rocket_auth_boilerplate::inactivity;
+use rocket_auth_boilerplate::models::user::User;
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, TestApp};
+
+async fn sweep(app: &TestApp) {
+    let rocket = app.client.rocket();
+    let mut conn = app.pool.acquire().await.unwrap();
+    inactivity::sweep(&mut conn, rocket.state::<AppConfig>().unwrap(), rocket.state::<Mailer>().unwrap())
+        .await
+        .unwrap();
+}
+
+async fn backdate(pool: &PgPool, column: &str, id: Uuid, days: i32) {
+    sqlx::query(&format!("UPDATE users SET {} = NOW() - make_interval(days => $1) WHERE id = $2", column))
+        .bind(days)
+        .bind(id)
+        .execute(pool)
+        .await
+        .unwrap();
+}
+
+async fn reload(pool: &PgPool, id: Uuid) -> User {
+    sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+        .bind(id)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn inactive_accounts_are_warned_then_disabled_until_reactivated() {
+    let app = TestApp::spawn_with(|config| {
+        config.inactive_account_days = 30;
+        config.inactive_account_warning_days = 7;
+        config.inactive_account_action = InactiveAccountAction::Disable;
+    })
+    .await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let admin_token = app.token_for(&admin.user).await;
+    let dormant = UserFactory::verified().insert(&app.pool).await;
+    let returning = UserFactory::verified().insert(&app.pool).await;
+    let recent = UserFactory::verified().insert(&app.pool).await;
+    for user in [&admin, &dormant, &returning] {
+        backdate(&app.pool, "created_at", user.id(), 40).await;
+    }
+    let dormant_token = app.token_for(&dormant.user).await;
+
+    // Warned first; nothing happens until the warning is a week old
+    sweep(&app).await;
+    let email = app.mailbox().last_to(dormant.email()).expect("warning email");
+    assert!(email.message.body.contains("disabled"));
+    assert!(app.mailbox().last_to(returning.email()).is_some());
+    assert!(app.mailbox().last_to(recent.email()).is_none());
+    assert!(app.mailbox().last_to(admin.email()).is_none());
+    assert!(reload(&app.pool, dormant.id()).await.disabled_at.is_none());
+
+    // Logging in clears the warning
+    app.login_token(returning.email(), &returning.password).await;
+    assert!(reload(&app.pool, returning.id()).await.inactivity_warned_at.is_none());
+
+    backdate(&app.pool, "inactivity_warned_at", dormant.id(), 8).await;
+    sweep(&app).await;
+    let user = reload(&app.pool, dormant.id()).await;
+    assert!(user.flagged_inactive_at.is_some());
+    assert!(user.disabled_at.is_some());
+    assert!(reload(&app.pool, returning.id()).await.flagged_inactive_at.is_none());
+
+    let response = app.login(dormant.email(), &dormant.password).await;
+    assert_eq!(response.status(), Status::Forbidden);
+    assert_eq!(response_json(response).await["code"], "account_disabled");
+    let response = app.get_authorized("/api/v1/auth/me", &dormant_token).await;
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    let uri = format!("/api/v1/admin/users/{}/reactivate", dormant.id());
+    let response = app.post_json_authorized(&uri, &admin_token, rocket::serde::json::json!({})).await;
+    assert_eq!(response.status(), Status::Ok);
+    app.login_token(dormant.email(), &dormant.password).await;
+
+    // A fresh period starts, so the next sweep leaves the account alone
+    sweep(&app).await;
+    assert!(reload(&app.pool, dormant.id()).await.inactivity_warned_at.is_none());
+}
@@ -0,0 +1,46 @@
+#![cfg(feature = "lambda")]
+
+use rocket::serde::json::{json, Value};
+
+use rocket_auth_boilerplate::lambda;
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::TestApp;
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn http_api_events_are_dispatched_to_the_application() {
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+
+    let event = json!({
+        "version": "2.0",
+        "rawPath": "/api/v1/auth/login",
+        "rawQueryString": "",
+        "headers": { "content-type": "application/json" },
+        "requestContext": { "http": { "method": "POST", "sourceIp": "203.0.113.7" } },
+        "body": json!({ "email": user.email(), "password": user.password }).to_string(),
+        "isBase64Encoded": false
+    });
+    let response = lambda::handle(&app.client, event).await.unwrap();
+    assert_eq!(response["statusCode"], 200);
+    assert_eq!(response["isBase64Encoded"], false);
+    assert!(response["headers"]["Content-Type"].as_str().unwrap().contains("json"));
+    let body: Value = serde_json::from_str(response["body"].as_str().unwrap()).unwrap();
+    let token = body["token"].as_str().unwrap();
+
+    // Payload format 1.0 (REST APIs) is answered in kind
+    let event = json!({
+        "httpMethod": "GET",
+        "path": "/api/v1/auth/me",
+        "multiValueHeaders": { "Authorization": [format!("Bearer {}", token)] },
+        "queryStringParameters": null,
+        "requestContext": { "identity": { "sourceIp": "203.0.113.7" } },
+        "body": null,
+        "isBase64Encoded": false
+    });
+    let response = lambda::handle(&app.client, event).await.unwrap();
+    assert_eq!(response["statusCode"], 200);
+    let body: Value = serde_json::from_str(response["body"].as_str().unwrap()).unwrap();
+    assert_eq!(body["user"]["email"], user.email());
+    assert!(response["multiValueHeaders"]["Content-Type"].is_array());
+}
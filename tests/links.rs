@@ -0,0 +1,69 @@
+use rocket::http::{Header, Status};
+use rocket::serde::json::json;
+
+use rocket_auth_boilerplate::config::EmailLinkStyle;
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{token_from_email, TestApp};
+
+const IPHONE: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) AppleWebKit/605.1.15";
+const DESKTOP: &str = "Mozilla/5.0 (X11; Linux x86_64) Gecko/20100101 Firefox/120.0";
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn universal_links_redirect_phones_to_the_app() {
+    let app = TestApp::spawn_with(|config| {
+        config.public_url = "https://api.example.com".to_string();
+        config.frontend_url = "https://example.com".to_string();
+        config.email_links = EmailLinkStyle::Universal {
+            scheme: "myapp://".to_string(),
+        };
+    })
+    .await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+
+    app.post_json("/api/v1/auth/forgot-password", json!({ "email": user.email() }))
+        .await;
+    let email = app.mailbox().last_to(user.email()).expect("reset email sent");
+    let token = token_from_email(&email.message.body).expect("token in reset email");
+    assert!(email
+        .message
+        .body
+        .contains(&format!("https://api.example.com/l/reset?token={}", token)));
+
+    let uri = format!("/l/reset?token={}", token);
+
+    let response = app.client.get(uri.as_str()).header(Header::new("User-Agent", IPHONE)).dispatch().await;
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(
+        response.headers().get_one("Location"),
+        Some(format!("myapp://reset?token={}", token).as_str())
+    );
+
+    let response = app.client.get(uri.as_str()).header(Header::new("User-Agent", DESKTOP)).dispatch().await;
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(
+        response.headers().get_one("Location"),
+        Some(format!("https://example.com/reset-password?token={}", token).as_str())
+    );
+
+    let response = app.client.get("/l/unknown?token=abc").dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn app_links_use_the_custom_scheme() {
+    let app = TestApp::spawn_with(|config| {
+        config.email_links = EmailLinkStyle::App {
+            scheme: "myapp://".to_string(),
+        };
+    })
+    .await;
+    let user = UserFactory::new().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+
+    app.post_json_authorized("/api/v1/auth/resend-verification", &token, json!({}))
+        .await;
+    let email = app.mailbox().last_to(user.email()).expect("verification email sent");
+    assert!(email.message.body.contains("myapp://verify-email?token="));
+}
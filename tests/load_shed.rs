@@ -0,0 +1,42 @@
+use rocket::http::{ContentType, Status};
+use rocket::serde::json::json;
+
+use rocket_auth_boilerplate::config::PasswordHashing;
+use rocket_auth_boilerplate::test_support::{response_json, TestApp};
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn requests_over_the_credentials_limit_are_shed() {
+    let app = TestApp::spawn_with(|config| {
+        // Slow enough that the first login is still hashing when the second arrives
+        config.password_hashing = PasswordHashing::Bcrypt { cost: 13 };
+        config.load_shed_credentials_limit = 1;
+        config.load_shed_retry_after_seconds = 2;
+    })
+    .await;
+
+    let login = |email: &'static str| {
+        app.client
+            .post("/api/v1/auth/login")
+            .header(ContentType::JSON)
+            .body(json!({ "email": email, "password": "password123" }).to_string())
+            .dispatch()
+    };
+    let (first, second) = tokio::join!(login("nobody-1@example.com"), login("nobody-2@example.com"));
+
+    let mut statuses = [first.status(), second.status()];
+    statuses.sort_by_key(|status| status.code);
+    assert_eq!(statuses, [Status::Unauthorized, Status::ServiceUnavailable]);
+    let shed = if first.status() == Status::ServiceUnavailable { first } else { second };
+    assert_eq!(shed.headers().get_one("Retry-After"), Some("2"));
+    let body = response_json(shed).await;
+    assert!(body["error"].as_str().unwrap().contains("busy"));
+
+    // The slot is free again once the handler is done
+    let response = app.login("nobody-3@example.com", "password123").await;
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    // The shed route only answers requests the fairing re-routed
+    let response = app.client.get("/_load-shed").dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+}
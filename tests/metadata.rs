@@ -0,0 +1,169 @@
+use rocket::http::{ContentType, Header, Status};
+use rocket::serde::json::{json, Value};
+
+use rocket_auth_boilerplate::auth::jwt::JwtService;
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, unique_email, TestApp};
+
+async fn patch(app: &TestApp, uri: &str, token: &str, body: Value) -> (Status, Value) {
+    let response = app
+        .client
+        .patch(uri.to_string())
+        .header(ContentType::JSON)
+        .header(Header::new("Authorization", format!("Bearer {}", token)))
+        .body(body.to_string())
+        .dispatch()
+        .await;
+    let status = response.status();
+    (status, response_json(response).await)
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn users_edit_user_metadata_and_admins_edit_app_metadata() {
+    let app = TestApp::spawn_with(|config| config.app_metadata_claim = true).await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let admin_token = app.token_for(&admin.user).await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+
+    let (status, body) = patch(
+        &app,
+        "/api/v1/auth/me",
+        &token,
+        json!({ "user_metadata": { "theme": "dark", "locale": "en" } }),
+    )
+    .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["user_metadata"], json!({ "theme": "dark", "locale": "en" }));
+
+    // Keys merge, null removes
+    let (_, body) = patch(&app, "/api/v1/auth/me", &token, json!({ "user_metadata": { "locale": null, "tz": "UTC" } })).await;
+    assert_eq!(body["user_metadata"], json!({ "theme": "dark", "tz": "UTC" }));
+
+    let (status, _) = patch(&app, "/api/v1/auth/me", &token, json!({ "user_metadata": [1, 2] })).await;
+    assert_eq!(status, Status::BadRequest);
+
+    // Users can't touch app_metadata
+    let (_, body) = patch(
+        &app,
+        "/api/v1/auth/me",
+        &token,
+        json!({ "user_metadata": {}, "app_metadata": { "plan": "pro" } }),
+    )
+    .await;
+    assert_eq!(body["app_metadata"], json!({}));
+
+    let uri = format!("/api/v1/admin/users/{}/metadata", user.id());
+    let (status, _) = patch(&app, &uri, &token, json!({ "app_metadata": { "plan": "pro" } })).await;
+    assert_eq!(status, Status::Forbidden);
+
+    let (status, body) = patch(&app, &uri, &admin_token, json!({ "app_metadata": { "plan": "pro" } })).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["app_metadata"], json!({ "plan": "pro" }));
+    assert_eq!(body["user_metadata"], json!({ "theme": "dark", "tz": "UTC" }));
+
+    let body = response_json(app.get_authorized("/api/v1/auth/me", &token).await).await;
+    assert_eq!(body["user"]["app_metadata"], json!({ "plan": "pro" }));
+
+    // Newly issued tokens carry app_metadata
+    let token = app.login_token(user.email(), &user.password).await;
+    let jwt = app.client.rocket().state::<JwtService>().unwrap();
+    let claims = jwt.verify_token(&token).await.unwrap();
+    assert_eq!(claims.app_metadata, Some(json!({ "plan": "pro" })));
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn profile_etags_support_caching_and_optimistic_updates() {
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+    let authorization = Header::new("Authorization", format!("Bearer {}", token));
+
+    let response = app.get_authorized("/api/v1/auth/me", &token).await;
+    assert_eq!(response.status(), Status::Ok);
+    let etag = response.headers().get_one("ETag").unwrap().to_string();
+    assert!(etag.starts_with("W/\""));
+
+    let response = app
+        .client
+        .get("/api/v1/auth/me")
+        .header(authorization.clone())
+        .header(Header::new("If-None-Match", etag.clone()))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::NotModified);
+    assert_eq!(response.headers().get_one("ETag"), Some(etag.as_str()));
+
+    let update = |if_match: String| {
+        app.client
+            .patch("/api/v1/auth/me")
+            .header(ContentType::JSON)
+            .header(authorization.clone())
+            .header(Header::new("If-Match", if_match))
+            .body(json!({ "user_metadata": { "theme": "dark" } }).to_string())
+            .dispatch()
+    };
+
+    let response = update(etag.clone()).await;
+    assert_eq!(response.status(), Status::Ok);
+    let new_etag = response.headers().get_one("ETag").unwrap().to_string();
+    assert_ne!(new_etag, etag);
+
+    // A stale copy can't overwrite the newer one
+    let response = update(etag.clone()).await;
+    assert_eq!(response.status(), Status::PreconditionFailed);
+    assert_eq!(response_json(response).await["code"], "etag_mismatch");
+
+    let response = app
+        .client
+        .get("/api/v1/auth/me")
+        .header(authorization.clone())
+        .header(Header::new("If-None-Match", etag))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("ETag"), Some(new_etag.as_str()));
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn locale_and_timezone_are_validated_and_returned_from_me() {
+    let app = TestApp::spawn().await;
+    let email = unique_email();
+
+    for body in [
+        json!({ "email": email, "password": "password123", "locale": "english!" }),
+        json!({ "email": email, "password": "password123", "timezone": "Mars/Olympus" }),
+    ] {
+        let response = app.post_json("/api/v1/auth/register", body).await;
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    let response = app
+        .post_json(
+            "/api/v1/auth/register",
+            json!({ "email": email, "password": "password123", "locale": "pt-BR", "timezone": "America/Sao_Paulo" }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::Created);
+    let body = response_json(response).await;
+    assert_eq!(body["user"]["locale"], "pt-BR");
+    assert_eq!(body["user"]["timezone"], "America/Sao_Paulo");
+
+    let token = app.login_token(&email, "password123").await;
+    let (status, _) = patch(&app, "/api/v1/auth/me", &token, json!({ "timezone": "Nowhere" })).await;
+    assert_eq!(status, Status::BadRequest);
+
+    // Preferences change on their own, without touching metadata
+    let (status, body) = patch(&app, "/api/v1/auth/me", &token, json!({ "timezone": "Europe/Berlin" })).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["locale"], "pt-BR");
+    assert_eq!(body["timezone"], "Europe/Berlin");
+    assert_eq!(body["user_metadata"], json!({}));
+
+    let body = response_json(app.get_authorized("/api/v1/auth/me", &token).await).await;
+    assert_eq!(body["user"]["locale"], "pt-BR");
+    assert_eq!(body["user"]["timezone"], "Europe/Berlin");
+}
@@ -0,0 +1,296 @@
+use chrono::{Duration, Utc};
+use rocket::http::{ContentType, Status};
+use rocket::serde::json::json;
+
+use rocket_auth_boilerplate::auth::mfa;
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, token_from_email, TestApp};
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn totp_enrollment_adds_a_second_login_step() {
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.login_token(user.email(), &user.password).await;
+
+    let response = app.post_json_authorized("/api/v1/auth/mfa/totp/enroll", &token, json!({})).await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    let secret = body["secret"].as_str().unwrap().to_string();
+    assert!(body["otpauth_uri"].as_str().unwrap().starts_with("otpauth://totp/"));
+
+    let response = app
+        .post_json_authorized("/api/v1/auth/mfa/totp/confirm", &token, json!({ "code": "000000x" }))
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let now = Utc::now();
+    let code = mfa::code_at(&secret, now).unwrap();
+    let response = app
+        .post_json_authorized("/api/v1/auth/mfa/totp/confirm", &token, json!({ "code": code }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    // The password alone no longer starts a session
+    let response = app.login(user.email(), &user.password).await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    assert_eq!(body["mfa_required"], true);
+    assert!(body["token"].is_null());
+    let mfa_token = body["mfa_token"].as_str().unwrap().to_string();
+
+    // A code can't be used twice
+    let response = app
+        .post_json("/api/v1/auth/login/mfa", json!({ "mfa_token": mfa_token, "code": code }))
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let code = mfa::code_at(&secret, now + Duration::seconds(mfa::STEP_SECONDS)).unwrap();
+    let response = app
+        .post_json("/api/v1/auth/login/mfa", json!({ "mfa_token": mfa_token, "code": code }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    let token = body["token"].as_str().unwrap();
+
+    let response = app.get_authorized("/api/v1/auth/mfa", token).await;
+    let body = response_json(response).await;
+    assert_eq!(body["enabled"], true);
+
+    // The challenge is spent
+    let response = app
+        .post_json("/api/v1/auth/login/mfa", json!({ "mfa_token": mfa_token, "code": code }))
+        .await;
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn required_mfa_blocks_the_account_after_the_grace_period() {
+    let app = TestApp::spawn_with(|config| {
+        config.mfa_required_roles = vec!["admin".to_string()];
+        config.mfa_grace_days = 7;
+    })
+    .await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+
+    let response = app.login(admin.email(), &admin.password).await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    let deadline = body["mfa_enrollment_deadline"].as_str().unwrap().to_string();
+    let token = body["token"].as_str().unwrap().to_string();
+
+    // Within the grace period the account works as usual
+    let response = app.get_authorized("/api/v1/auth/me", &token).await;
+    assert_eq!(response.status(), Status::Ok);
+
+    // Logging in again doesn't restart the grace period
+    let response = app.login(admin.email(), &admin.password).await;
+    let body = response_json(response).await;
+    assert_eq!(body["mfa_enrollment_deadline"], deadline.as_str());
+
+    sqlx::query("UPDATE users SET mfa_required_since = now() - INTERVAL '8 days' WHERE id = $1")
+        .bind(admin.id())
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    let response = app.get_authorized("/api/v1/auth/me", &token).await;
+    assert_eq!(response.status(), Status::Forbidden);
+    let body = response_json(response).await;
+    assert_eq!(body["code"], "mfa_enrollment_required");
+
+    // Enrollment is still open, and lifts the restriction
+    let response = app.post_json_authorized("/api/v1/auth/mfa/totp/enroll", &token, json!({})).await;
+    assert_eq!(response.status(), Status::Ok);
+    let secret = response_json(response).await["secret"].as_str().unwrap().to_string();
+    let code = mfa::code_at(&secret, Utc::now()).unwrap();
+    let response = app
+        .post_json_authorized("/api/v1/auth/mfa/totp/confirm", &token, json!({ "code": code }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = app.get_authorized("/api/v1/auth/me", &token).await;
+    assert_eq!(response.status(), Status::Ok);
+
+    // Users whose role doesn't need MFA get no deadline
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let body = response_json(app.login(user.email(), &user.password).await).await;
+    assert!(body["mfa_enrollment_deadline"].is_null());
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn pending_enrollment_is_served_as_a_qr_code() {
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.login_token(user.email(), &user.password).await;
+
+    let response = app.get_authorized("/api/v1/auth/mfa/totp/qr", &token).await;
+    assert_eq!(response.status(), Status::NotFound);
+
+    let response = app.post_json_authorized("/api/v1/auth/mfa/totp/enroll", &token, json!({})).await;
+    let secret = response_json(response).await["secret"].as_str().unwrap().to_string();
+
+    let response = app.get_authorized("/api/v1/auth/mfa/totp/qr", &token).await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::SVG));
+    assert_eq!(response.headers().get_one("Cache-Control"), Some("no-store"));
+    assert!(response.into_string().await.unwrap().contains("<svg"));
+
+    let response = app.get_authorized("/api/v1/auth/mfa/totp/qr?format=png", &token).await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::PNG));
+    assert!(response.into_bytes().await.unwrap().starts_with(b"\x89PNG"));
+
+    let response = app.get_authorized("/api/v1/auth/mfa/totp/qr?format=gif", &token).await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    // The secret isn't shown again once enrollment is confirmed
+    let code = mfa::code_at(&secret, Utc::now()).unwrap();
+    app.post_json_authorized("/api/v1/auth/mfa/totp/confirm", &token, json!({ "code": code }))
+        .await;
+    let response = app.get_authorized("/api/v1/auth/mfa/totp/qr", &token).await;
+    assert_eq!(response.status(), Status::Conflict);
+}
+
+/// Enroll the token's user in TOTP, returning the secret
+async fn enable_totp(app: &TestApp, token: &str) -> String {
+    let response = app.post_json_authorized("/api/v1/auth/mfa/totp/enroll", token, json!({})).await;
+    let secret = response_json(response).await["secret"].as_str().unwrap().to_string();
+    let code = mfa::code_at(&secret, Utc::now()).unwrap();
+    let response = app
+        .post_json_authorized("/api/v1/auth/mfa/totp/confirm", token, json!({ "code": code }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    secret
+}
+
+/// Log in with a password, returning the `mfa_token` for the second step
+async fn password_step(app: &TestApp, email: &str, password: &str) -> String {
+    let body = response_json(app.login(email, password).await).await;
+    body["mfa_token"].as_str().expect("login asks for a code").to_string()
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn lost_authenticator_is_removed_after_email_confirmation_and_delay() {
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.login_token(user.email(), &user.password).await;
+    enable_totp(&app, &token).await;
+
+    let mfa_token = password_step(&app, user.email(), &user.password).await;
+    let response = app
+        .post_json("/api/v1/auth/login/mfa/recover", json!({ "mfa_token": mfa_token }))
+        .await;
+    assert_eq!(response.status(), Status::Accepted);
+
+    let email = app.mailbox().last_to(user.email()).expect("confirmation email sent");
+    assert_eq!(email.message.subject, "Confirm removing two-factor authentication");
+    let confirmation = token_from_email(&email.message.body).expect("token in confirmation email");
+
+    let response = app
+        .post_json("/api/v1/auth/mfa/recovery/confirm", json!({ "token": confirmation }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    assert!(body["available_at"].is_string());
+    let email = app.mailbox().last_to(user.email()).expect("notification sent");
+    assert_eq!(email.message.subject, "Two-factor authentication will be removed");
+
+    let response = app
+        .post_json("/api/v1/auth/mfa/recovery/confirm", json!({ "token": confirmation }))
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    // MFA stays until the delay has passed
+    let mfa_token = password_step(&app, user.email(), &user.password).await;
+    let response = app
+        .post_json("/api/v1/auth/login/mfa/recover", json!({ "mfa_token": mfa_token }))
+        .await;
+    assert_eq!(response.status(), Status::Conflict);
+
+    sqlx::query("UPDATE mfa_recoveries SET available_at = now() - INTERVAL '1 minute' WHERE user_id = $1")
+        .bind(user.id())
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    let token = app.login_token(user.email(), &user.password).await;
+    let email = app.mailbox().last_to(user.email()).expect("removal notice sent");
+    assert_eq!(email.message.subject, "Two-factor authentication was removed");
+
+    let body = response_json(app.get_authorized("/api/v1/auth/mfa", &token).await).await;
+    assert_eq!(body["enabled"], false);
+    assert!(body["recovery_available_at"].is_null());
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn logging_in_with_a_code_cancels_a_pending_recovery() {
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.login_token(user.email(), &user.password).await;
+    let secret = enable_totp(&app, &token).await;
+
+    let mfa_token = password_step(&app, user.email(), &user.password).await;
+    app.post_json("/api/v1/auth/login/mfa/recover", json!({ "mfa_token": mfa_token }))
+        .await;
+    let email = app.mailbox().last_to(user.email()).unwrap();
+    let confirmation = token_from_email(&email.message.body).unwrap();
+    app.post_json("/api/v1/auth/mfa/recovery/confirm", json!({ "token": confirmation }))
+        .await;
+
+    let body = response_json(app.get_authorized("/api/v1/auth/mfa", &token).await).await;
+    assert!(body["recovery_available_at"].is_string());
+
+    let code = mfa::code_at(&secret, Utc::now() + Duration::seconds(mfa::STEP_SECONDS)).unwrap();
+    let response = app
+        .post_json("/api/v1/auth/login/mfa", json!({ "mfa_token": mfa_token, "code": code }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let body = response_json(app.get_authorized("/api/v1/auth/mfa", &token).await).await;
+    assert!(body["recovery_available_at"].is_null());
+    let response = app.delete_authorized("/api/v1/auth/mfa/recovery", &token).await;
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn admins_can_reset_mfa_with_a_reason() {
+    let app = TestApp::spawn().await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let admin_token = app.token_for(&admin.user).await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.login_token(user.email(), &user.password).await;
+    enable_totp(&app, &token).await;
+
+    let uri = format!("/api/v1/admin/users/{}/mfa/reset", user.id());
+    let response = app.post_json_authorized(&uri, &admin_token, json!({ "reason": " " })).await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let response = app
+        .post_json_authorized(&uri, &admin_token, json!({ "reason": "Identity checked on a support call" }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let email = app.mailbox().last_to(user.email()).expect("user notified");
+    assert_eq!(email.message.subject, "Two-factor authentication was removed");
+
+    // The password alone signs in again
+    app.login_token(user.email(), &user.password).await;
+
+    let response = app
+        .post_json_authorized(&uri, &admin_token, json!({ "reason": "Again" }))
+        .await;
+    assert_eq!(response.status(), Status::NotFound);
+
+    let uri = format!("/api/v1/admin/users/{}/mfa/recoveries", user.id());
+    let body = response_json(app.get_authorized(&uri, &admin_token).await).await;
+    let recoveries = body["recoveries"].as_array().unwrap();
+    assert_eq!(recoveries.len(), 1);
+    assert_eq!(recoveries[0]["status"], "completed");
+    assert_eq!(recoveries[0]["admin_id"], admin.id().to_string().as_str());
+    assert_eq!(recoveries[0]["reason"], "Identity checked on a support call");
+}
@@ -0,0 +1,291 @@
+use jsonwebtoken::{encode, EncodingKey, Header};
+use rocket::http::Status;
+use rocket::serde::json::json;
+
+use rocket_auth_boilerplate::config::{DiscordConfig, FacebookConfig, GenericOAuthConfig, MicrosoftConfig};
+use rocket_auth_boilerplate::repositories::oauth_completions;
+use rocket_auth_boilerplate::test_support::{response_json, unique_email, TestApp};
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn google_id_token_login_requires_a_client_id_and_a_genuine_token() {
+    let unconfigured = TestApp::spawn().await;
+    let response = unconfigured
+        .post_json("/api/v1/auth/oauth/google/id-token", json!({ "id_token": "x" }))
+        .await;
+    assert_eq!(response.status(), Status::NotFound);
+
+    let app = TestApp::spawn_with(|config| config.google_client_ids = vec!["web-client.apps.googleusercontent.com".to_string()]).await;
+
+    let response = app
+        .post_json("/api/v1/auth/oauth/google/id-token", json!({ "id_token": "not-a-token" }))
+        .await;
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    // Signed with a shared secret instead of one of Google's keys
+    let email = unique_email();
+    let forged = encode(
+        &Header::default(),
+        &json!({
+            "iss": "https://accounts.google.com",
+            "aud": "web-client.apps.googleusercontent.com",
+            "sub": "forged-google-subject",
+            "email": email,
+            "email_verified": true,
+            "exp": chrono::Utc::now().timestamp() + 3600,
+        }),
+        &EncodingKey::from_secret(b"guessed"),
+    )
+    .unwrap();
+    let response = app
+        .post_json("/api/v1/auth/oauth/google/id-token", json!({ "id_token": forged }))
+        .await;
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    // Google ID tokens are exchanged for a session, never used as bearer tokens
+    assert_eq!(app.get_authorized("/api/v1/auth/me", &forged).await.status(), Status::Unauthorized);
+
+    let linked: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM external_identities WHERE subject = 'forged-google-subject'")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(linked, 0);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn microsoft_sign_in_redirects_with_pkce_and_checks_the_state() {
+    let unconfigured = TestApp::spawn().await;
+    let response = unconfigured.client.get("/api/v1/auth/oauth/microsoft/authorize").dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+
+    let app = TestApp::spawn_with(|config| {
+        config.microsoft = Some(MicrosoftConfig {
+            client_id: "entra-client".to_string(),
+            client_secret: "entra-secret".to_string(),
+            tenant: "organizations".to_string(),
+        })
+    })
+    .await;
+
+    let response = app.client.get("/api/v1/auth/oauth/nowhere/authorize").dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+
+    let response = app.client.get("/api/v1/auth/oauth/microsoft/authorize").dispatch().await;
+    assert_eq!(response.status(), Status::SeeOther);
+    let location = reqwest::Url::parse(response.headers().get_one("Location").unwrap()).unwrap();
+    assert_eq!(location.host_str(), Some("login.microsoftonline.com"));
+    assert_eq!(location.path(), "/organizations/oauth2/v2.0/authorize");
+    let params: std::collections::HashMap<_, _> = location.query_pairs().into_owned().collect();
+    assert_eq!(params["client_id"], "entra-client");
+    assert_eq!(params["code_challenge_method"], "S256");
+    assert_eq!(params["redirect_uri"], "http://localhost:8000/oauth/microsoft/callback");
+
+    // The state is stored hashed, with the verifier behind the challenge
+    let state = &params["state"];
+    let verifier: String = sqlx::query_scalar("SELECT code_verifier FROM oauth_states WHERE state_hash = $1")
+        .bind(rocket_auth_boilerplate::auth::tokens::hash(state))
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(params["code_challenge"], rocket_auth_boilerplate::auth::oauth::code_challenge(&verifier));
+
+    let response = app
+        .post_json(
+            "/api/v1/auth/oauth/microsoft/callback",
+            json!({ "code": "anything", "state": "made-up-state" }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn discord_sign_in_asks_for_guilds_only_when_they_map_to_roles() {
+    let app = TestApp::spawn_with(|config| {
+        config.discord = Some(DiscordConfig {
+            client_id: "discord-client".to_string(),
+            client_secret: "discord-secret".to_string(),
+            guild_roles: Vec::new(),
+        })
+    })
+    .await;
+    let response = app.client.get("/api/v1/auth/oauth/discord/authorize").dispatch().await;
+    assert_eq!(response.status(), Status::SeeOther);
+    let location = reqwest::Url::parse(response.headers().get_one("Location").unwrap()).unwrap();
+    assert_eq!(location.host_str(), Some("discord.com"));
+    let scope = location.query_pairs().find(|(key, _)| key == "scope").unwrap().1.into_owned();
+    assert_eq!(scope, "identify email");
+
+    let app = TestApp::spawn_with(|config| {
+        config.discord = Some(DiscordConfig {
+            client_id: "discord-client".to_string(),
+            client_secret: "discord-secret".to_string(),
+            guild_roles: vec![("81384788765712384".to_string(), "moderator".to_string())],
+        });
+        config.microsoft = Some(MicrosoftConfig {
+            client_id: "entra-client".to_string(),
+            client_secret: "entra-secret".to_string(),
+            tenant: "organizations".to_string(),
+        });
+    })
+    .await;
+    let response = app.client.get("/api/v1/auth/oauth/discord/authorize").dispatch().await;
+    let location = reqwest::Url::parse(response.headers().get_one("Location").unwrap()).unwrap();
+    let scope = location.query_pairs().find(|(key, _)| key == "scope").unwrap().1.into_owned();
+    assert_eq!(scope, "identify email guilds");
+
+    // A state issued for Discord can't finish a Microsoft sign-in
+    let state = location.query_pairs().find(|(key, _)| key == "state").unwrap().1.into_owned();
+    let response = app
+        .post_json("/api/v1/auth/oauth/microsoft/callback", json!({ "code": "x", "state": state }))
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn sign_in_without_an_email_is_completed_with_an_unverified_one() {
+    let app = TestApp::spawn_with(|config| {
+        config.facebook = Some(FacebookConfig {
+            app_id: "facebook-app".to_string(),
+            app_secret: "facebook-secret".to_string(),
+        })
+    })
+    .await;
+
+    // As left behind by a callback whose Facebook account had no email
+    let subject = uuid::Uuid::new_v4().to_string();
+    let mut conn = app.pool.acquire().await.unwrap();
+    oauth_completions::create(
+        &mut conn,
+        "completion-token",
+        "facebook",
+        "https://www.facebook.com",
+        &subject,
+        chrono::Utc::now() + chrono::Duration::minutes(30),
+    )
+    .await
+    .unwrap();
+
+    let taken = unique_email();
+    app.register(&taken, "password123").await;
+    let response = app
+        .post_json(
+            "/api/v1/auth/oauth/facebook/complete",
+            json!({ "completion_token": "completion-token", "email": taken }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::Conflict);
+
+    // The token survives a refused address
+    let email = unique_email();
+    let response = app
+        .post_json(
+            "/api/v1/auth/oauth/facebook/complete",
+            json!({ "completion_token": "completion-token", "email": email }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    assert_eq!(body["user"]["email"], email);
+    assert!(app.mailbox().last_to(&email).is_some(), "verification email sent");
+
+    let linked: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM external_identities WHERE subject = $1")
+        .bind(&subject)
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(linked, 1);
+
+    let response = app
+        .post_json(
+            "/api/v1/auth/oauth/facebook/complete",
+            json!({ "completion_token": "completion-token", "email": unique_email() }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+/// Answers any request with `userinfo` for GETs and an access token for POSTs, like a provider would
+async fn mock_provider(userinfo: serde_json::Value) -> String {
+    use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = rocket::tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    rocket::tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut request = vec![0; 8192];
+            let read = stream.read(&mut request).await.unwrap_or(0);
+            let body = match request[..read].starts_with(b"POST") {
+                true => json!({ "access_token": "mock-access-token", "token_type": "bearer" }),
+                false => userinfo.clone(),
+            }
+            .to_string();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        }
+    });
+    format!("http://{}", address)
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn configured_provider_signs_in_with_mapped_userinfo_fields() {
+    let email = unique_email();
+    let provider = mock_provider(json!({
+        "id": 4242,
+        "email": email,
+        "confirmed_at": "2024-01-01T00:00:00Z"
+    }))
+    .await;
+    let app = TestApp::spawn_with(|config| {
+        config.oauth_providers = vec![GenericOAuthConfig {
+            name: "gitlab".to_string(),
+            client_id: "gitlab-client".to_string(),
+            client_secret: "gitlab-secret".to_string(),
+            authorize_url: format!("{}/oauth/authorize", provider),
+            token_url: format!("{}/oauth/token", provider),
+            userinfo_url: format!("{}/api/v4/user", provider),
+            scopes: "read_user".to_string(),
+            subject_field: "id".to_string(),
+            email_field: "email".to_string(),
+            email_verified_field: Some("confirmed_at".to_string()),
+            basic_auth: false,
+            pkce: true,
+        }]
+    })
+    .await;
+
+    let response = app.client.get("/api/v1/auth/oauth/gitlab/authorize").dispatch().await;
+    assert_eq!(response.status(), Status::SeeOther);
+    let location = reqwest::Url::parse(response.headers().get_one("Location").unwrap()).unwrap();
+    assert!(location.as_str().starts_with(&format!("{}/oauth/authorize?", provider)));
+    let params: std::collections::HashMap<_, _> = location.query_pairs().into_owned().collect();
+    assert_eq!(params["scope"], "read_user");
+
+    let response = app
+        .post_json(
+            "/api/v1/auth/oauth/gitlab/callback",
+            json!({ "code": "mock-code", "state": params["state"] }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    assert_eq!(body["user"]["email"], email);
+
+    let token = body["token"].as_str().unwrap().to_string();
+    let me = response_json(app.get_authorized("/api/v1/auth/me", &token).await).await;
+    assert_eq!(me["user"]["email_verified"], true);
+
+    let linked: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM external_identities WHERE issuer = $1 AND subject = '4242'")
+        .bind(&provider)
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(linked, 1);
+}
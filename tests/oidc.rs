@@ -0,0 +1,51 @@
+use rocket::http::Status;
+use rocket::serde::json::json;
+
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, TestApp};
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn discovery_points_at_userinfo_which_releases_claims_by_scope() {
+    let app = TestApp::spawn_with(|config| config.public_url = "https://auth.example.com".to_string()).await;
+
+    let discovery = response_json(app.client.get("/.well-known/openid-configuration").dispatch().await).await;
+    assert_eq!(discovery["issuer"], "https://auth.example.com");
+    assert_eq!(discovery["userinfo_endpoint"], "https://auth.example.com/api/v1/auth/userinfo");
+
+    let user = UserFactory::verified().insert(&app.pool).await;
+    sqlx::query("UPDATE users SET user_metadata = $2 WHERE id = $1")
+        .bind(user.id())
+        .bind(json!({ "name": "Ada Lovelace", "theme": "dark" }))
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    let token = app.token_for(&user.user).await;
+
+    // Session tokens see everything, but only standard claims
+    let claims = response_json(app.get_authorized("/api/v1/auth/userinfo", &token).await).await;
+    assert_eq!(claims["sub"], user.id().to_string());
+    assert_eq!(claims["email"], user.email());
+    assert_eq!(claims["email_verified"], true);
+    assert_eq!(claims["name"], "Ada Lovelace");
+    assert!(claims.get("theme").is_none());
+
+    // API keys need `openid`, and get only the claims their scopes cover
+    let body = response_json(
+        app.post_json_authorized("/api/v1/auth/api-keys", &token, json!({ "name": "reader", "scopes": ["users:read"] }))
+            .await,
+    )
+    .await;
+    let reader = body["key"].as_str().unwrap().to_string();
+    assert_eq!(app.get_authorized("/api/v1/auth/userinfo", &reader).await.status(), Status::Forbidden);
+
+    let body = response_json(
+        app.post_json_authorized("/api/v1/auth/api-keys", &token, json!({ "name": "oidc", "scopes": ["openid", "email"] }))
+            .await,
+    )
+    .await;
+    let key = body["key"].as_str().unwrap().to_string();
+    let claims = response_json(app.get_authorized("/api/v1/auth/userinfo", &key).await).await;
+    assert_eq!(claims["email"], user.email());
+    assert!(claims.get("name").is_none());
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use rocket::http::Status;
+use rocket::serde::json::{json, Value};
+use uuid::Uuid;
+
+use rocket_auth_boilerplate::domains::{self, DnsClient};
+use rocket_auth_boilerplate::models::organization::OrgRole;
+use rocket_auth_boilerplate::repositories::{organizations, users};
+use rocket_auth_boilerplate::test_support::factories::{TestUser, UserFactory};
+use rocket_auth_boilerplate::test_support::{response_json, token_from_email, TestApp};
+
+type Records = Arc<Mutex<HashMap<String, Vec<String>>>>;
+
+/// Answers DNS-over-HTTPS JSON queries with the TXT records published in the map
+async fn mock_resolver() -> (String, Records) {
+    use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let records: Records = Arc::new(Mutex::new(HashMap::new()));
+    let listener = rocket::tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let published = records.clone();
+    rocket::tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut request = vec![0; 8192];
+            let read = stream.read(&mut request).await.unwrap_or(0);
+            let request = String::from_utf8_lossy(&request[..read]).to_string();
+            let name = request
+                .split(['?', '&', ' '])
+                .find_map(|part| part.strip_prefix("name="))
+                .unwrap_or_default()
+                .to_string();
+            let answer: Vec<Value> = published
+                .lock()
+                .unwrap()
+                .get(&name)
+                .into_iter()
+                .flatten()
+                .map(|value| json!({ "name": name, "type": 16, "TTL": 300, "data": format!("\"{}\"", value) }))
+                .collect();
+            let status = if answer.is_empty() { 3 } else { 0 };
+            let body = json!({ "Status": status, "Answer": answer }).to_string();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/dns-json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        }
+    });
+    (format!("http://{}/dns-query", address), records)
+}
+
+async fn owner_of_new_org(app: &TestApp, name: &str) -> (Uuid, TestUser) {
+    let mut conn = app.pool.acquire().await.unwrap();
+    let org = organizations::create(&mut conn, name).await.unwrap();
+    let owner = UserFactory::verified().insert(&app.pool).await;
+    users::set_organization(&mut conn, owner.id(), org, OrgRole::Owner).await.unwrap();
+    (org, owner)
+}
+
+fn unique_domain() -> String {
+    format!("corp-{}.example.com", Uuid::new_v4().simple())
+}
+
+/// Register at `email` and confirm the address with the link emailed
+async fn register_and_verify(app: &TestApp, email: &str) {
+    assert_eq!(app.register(email, "password123").await.status(), Status::Created);
+    let verification = app.mailbox().last_to(email).expect("verification email sent");
+    let token = token_from_email(&verification.message.body).expect("token in verification email");
+    let response = app.client.get(format!("/api/v1/auth/verify-email?token={}", token)).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn verified_domains_bring_confirmed_addresses_into_the_organization() {
+    let (resolver, records) = mock_resolver().await;
+    let app = TestApp::spawn_with(|config| config.dns_over_https_url = resolver.clone()).await;
+    let (org, owner) = owner_of_new_org(&app, "Corp").await;
+    let token = app.token_for(&owner.user).await;
+    let domains_uri = format!("/api/v1/orgs/{}/domains", org);
+    let domain = unique_domain();
+
+    let response = app.post_json_authorized(&domains_uri, &token, json!({ "domain": "not a domain" })).await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let response = app
+        .post_json_authorized(&domains_uri, &token, json!({ "domain": format!("{}.", domain.to_uppercase()) }))
+        .await;
+    assert_eq!(response.status(), Status::Created);
+    let claimed = response_json(response).await["domain"].clone();
+    assert_eq!(claimed["domain"], domain.as_str());
+    assert_eq!(claimed["join_mode"], "auto");
+    assert_eq!(claimed["verified"], false);
+    assert_eq!(claimed["record"]["name"], format!("_auth-verification.{}", domain));
+    let response = app.post_json_authorized(&domains_uri, &token, json!({ "domain": domain })).await;
+    assert_eq!(response.status(), Status::Conflict);
+
+    // Until the domain is verified, nobody joins
+    let early = format!("early@{}", domain);
+    register_and_verify(&app, &early).await;
+    let mut conn = app.pool.acquire().await.unwrap();
+    assert_eq!(users::find_by_email(&mut conn, &early).await.unwrap().unwrap().organization_id, None);
+
+    let verify_uri = format!("{}/{}/verify", domains_uri, claimed["id"].as_str().unwrap());
+    let response = app.post_json_authorized(&verify_uri, &token, json!({})).await;
+    assert_eq!(response.status(), Status::BadRequest);
+    assert_eq!(response_json(response).await["code"], "challenge_not_found");
+
+    records.lock().unwrap().insert(
+        claimed["record"]["name"].as_str().unwrap().to_string(),
+        vec!["v=spf1 -all".to_string(), claimed["record"]["value"].as_str().unwrap().to_string()],
+    );
+    let response = app.post_json_authorized(&verify_uri, &token, json!({})).await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response_json(response).await["domain"]["verified"], true);
+
+    let joining = format!("new.hire@{}", domain);
+    register_and_verify(&app, &joining).await;
+    let joined = users::find_by_email(&mut conn, &joining).await.unwrap().unwrap();
+    assert_eq!(joined.organization_id, Some(org));
+    assert_eq!(joined.organization_role, "member");
+
+    // Once verified, the domain is the organization's alone
+    let (other_org, other_owner) = owner_of_new_org(&app, "Impostor").await;
+    let other_uri = format!("/api/v1/orgs/{}/domains", other_org);
+    let response = app
+        .post_json_authorized(&other_uri, &app.token_for(&other_owner.user).await, json!({ "domain": domain }))
+        .await;
+    assert_eq!(response.status(), Status::Conflict);
+    assert_eq!(response_json(response).await["code"], "domain_taken");
+
+    let plain = app.token_for(&joined).await;
+    assert_eq!(app.get_authorized(&domains_uri, &plain).await.status(), Status::Forbidden);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn invite_domains_email_an_invitation_to_accept() {
+    let (resolver, records) = mock_resolver().await;
+    let app = TestApp::spawn_with(|config| config.dns_over_https_url = resolver.clone()).await;
+    let (org, owner) = owner_of_new_org(&app, "Careful").await;
+    let token = app.token_for(&owner.user).await;
+    let domain = unique_domain();
+
+    let response = app
+        .post_json_authorized(
+            &format!("/api/v1/orgs/{}/domains", org),
+            &token,
+            json!({ "domain": domain, "join_mode": "invite" }),
+        )
+        .await;
+    let claimed = response_json(response).await["domain"].clone();
+    records.lock().unwrap().insert(
+        claimed["record"]["name"].as_str().unwrap().to_string(),
+        vec![claimed["record"]["value"].as_str().unwrap().to_string()],
+    );
+
+    // The background check verifies it as well
+    let mut conn = app.pool.acquire().await.unwrap();
+    let verified = domains::check_unverified(&mut conn, &DnsClient::new(&resolver)).await.unwrap();
+    assert!(verified >= 1);
+
+    let email = format!("someone@{}", domain);
+    register_and_verify(&app, &email).await;
+    let user = users::find_by_email(&mut conn, &email).await.unwrap().unwrap();
+    assert_eq!(user.organization_id, None);
+
+    let invitation = app.mailbox().last_to(&email).expect("invitation emailed");
+    assert_eq!(invitation.message.subject, "Join your organization");
+    let code = invitation.message.body.rsplit(' ').next().unwrap().trim().to_string();
+
+    let user_token = app.token_for(&user).await;
+    let outsider = UserFactory::verified().insert(&app.pool).await;
+    let accept_uri = format!("/api/v1/orgs/invitations/{}/accept", code);
+    let response = app.post_json_authorized(&accept_uri, &app.token_for(&outsider.user).await, json!({})).await;
+    assert_eq!(response.status(), Status::Forbidden);
+
+    let response = app.post_json_authorized(&accept_uri, &user_token, json!({})).await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response_json(response).await["organization_id"], org.to_string());
+
+    let response = app.post_json_authorized(&accept_uri, &user_token, json!({})).await;
+    assert_eq!(response.status(), Status::Conflict);
+}
@@ -0,0 +1,148 @@
+use rocket::http::Status;
+use rocket::serde::json::{json, Value};
+use uuid::Uuid;
+
+use rocket_auth_boilerplate::config::GenericOAuthConfig;
+use rocket_auth_boilerplate::models::organization::OrgRole;
+use rocket_auth_boilerplate::repositories::{organizations, users};
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, unique_email, TestApp};
+
+/// Answers any request with `userinfo` for GETs and an access token for POSTs, like a provider would
+async fn mock_provider(userinfo: Value) -> String {
+    use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = rocket::tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    rocket::tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut request = vec![0; 8192];
+            let read = stream.read(&mut request).await.unwrap_or(0);
+            let body = match request[..read].starts_with(b"POST") {
+                true => json!({ "access_token": "mock-access-token", "token_type": "bearer" }),
+                false => userinfo.clone(),
+            }
+            .to_string();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        }
+    });
+    format!("http://{}", address)
+}
+
+fn provider(name: &str, url: &str) -> GenericOAuthConfig {
+    GenericOAuthConfig {
+        name: name.to_string(),
+        client_id: format!("{}-client", name),
+        client_secret: format!("{}-secret", name),
+        authorize_url: format!("{}/oauth/authorize", url),
+        token_url: format!("{}/oauth/token", url),
+        userinfo_url: format!("{}/userinfo", url),
+        scopes: "openid email".to_string(),
+        subject_field: "id".to_string(),
+        email_field: "email".to_string(),
+        email_verified_field: Some("email_verified".to_string()),
+        basic_auth: false,
+        pkce: true,
+    }
+}
+
+/// Sign in at `provider` through the authorize redirect and the callback
+async fn oauth_sign_in(app: &TestApp, provider: &str) -> (Status, Value) {
+    let response = app.client.get(format!("/api/v1/auth/oauth/{}/authorize", provider)).dispatch().await;
+    let location = reqwest::Url::parse(response.headers().get_one("Location").unwrap()).unwrap();
+    let state = location.query_pairs().find(|(key, _)| key == "state").unwrap().1.into_owned();
+    let response = app
+        .post_json(
+            &format!("/api/v1/auth/oauth/{}/callback", provider),
+            json!({ "code": "mock-code", "state": state }),
+        )
+        .await;
+    let status = response.status();
+    (status, response_json(response).await)
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn enforced_sso_turns_members_away_from_passwords_and_other_providers() {
+    let email = unique_email();
+    let userinfo = json!({ "id": Uuid::new_v4().to_string(), "email": email, "email_verified": true });
+    let (corporate, other) = (mock_provider(userinfo.clone()).await, mock_provider(userinfo).await);
+    let app = TestApp::spawn_with(|config| {
+        config.oauth_providers = vec![provider("corporate", &corporate), provider("other", &other)];
+    })
+    .await;
+
+    let mut conn = app.pool.acquire().await.unwrap();
+    let org = organizations::create(&mut conn, "Single Sign-On").await.unwrap();
+    let member = UserFactory::verified().with_email(&email).insert(&app.pool).await;
+    let owner = UserFactory::verified().insert(&app.pool).await;
+    users::set_organization(&mut conn, owner.id(), org, OrgRole::Owner).await.unwrap();
+    users::set_organization(&mut conn, member.id(), org, OrgRole::Member).await.unwrap();
+    let token = app.token_for(&owner.user).await;
+    let sso_uri = format!("/api/v1/orgs/{}/sso", org);
+
+    for update in [json!({ "provider": "nope", "enforced": true }), json!({ "enforced": true })] {
+        let response = app.put_json_authorized(&sso_uri, &token, update.clone()).await;
+        assert_eq!(response.status(), Status::BadRequest, "{}", update);
+    }
+    let response = app
+        .put_json_authorized(&sso_uri, &token, json!({ "provider": "corporate", "enforced": true }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    assert_eq!(body["enforced"], true);
+    assert!(body["sign_in_url"].as_str().unwrap().ends_with("/api/v1/auth/oauth/corporate/authorize"));
+
+    let response = app.login(member.email(), &member.password).await;
+    assert_eq!(response.status(), Status::Forbidden);
+    let body = response_json(response).await;
+    assert_eq!(body["code"], "sso_required");
+    assert_eq!(body["provider"], "corporate");
+
+    let (status, body) = oauth_sign_in(&app, "other").await;
+    assert_eq!(status, Status::Forbidden);
+    assert_eq!(body["code"], "sso_required");
+
+    let (status, body) = oauth_sign_in(&app, "corporate").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["user"]["email"], member.email());
+
+    // Lifting enforcement lets passwords back in
+    let response = app
+        .put_json_authorized(&sso_uri, &token, json!({ "provider": "corporate", "enforced": false }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(app.login(member.email(), &member.password).await.status(), Status::Ok);
+    let body = response_json(app.get_authorized(&sso_uri, &token).await).await;
+    assert_eq!(body["provider"], "corporate");
+    assert_eq!(body["enforced"], false);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn enforced_sso_covers_addresses_at_verified_domains() {
+    let corporate = mock_provider(json!({ "id": "1" })).await;
+    let app = TestApp::spawn_with(|config| config.oauth_providers = vec![provider("corporate", &corporate)]).await;
+    let mut conn = app.pool.acquire().await.unwrap();
+    let org = organizations::create(&mut conn, "Domain SSO").await.unwrap();
+    let domain = format!("sso-{}.example.com", Uuid::new_v4().simple());
+    let claimed = organizations::create_domain(&mut conn, org, &domain, "auto", "token").await.unwrap().unwrap();
+    organizations::record_domain_check(&mut conn, claimed.id, true).await.unwrap();
+    organizations::set_sso(&mut conn, org, Some("corporate"), true).await.unwrap();
+
+    // Outside the organization, and even without an account
+    let outsider = UserFactory::verified().with_email(&format!("someone@{}", domain)).insert(&app.pool).await;
+    for email in [outsider.email().to_string(), format!("nobody@{}", domain.to_uppercase())] {
+        let response = app.login(&email, &outsider.password).await;
+        assert_eq!(response.status(), Status::Forbidden, "{}", email);
+        assert_eq!(response_json(response).await["code"], "sso_required");
+    }
+
+    let elsewhere = UserFactory::verified().insert(&app.pool).await;
+    assert_eq!(app.login(elsewhere.email(), &elsewhere.password).await.status(), Status::Ok);
+}
@@ -0,0 +1,288 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rocket::serde::json::{json, Value};
+use rocket::http::Status;
+use uuid::Uuid;
+
+use rocket_auth_boilerplate::auth::jwt::{Claims, JwtService};
+use rocket_auth_boilerplate::auth::org_keys;
+use rocket_auth_boilerplate::models::organization::OrgRole;
+use rocket_auth_boilerplate::repositories::{organizations, users};
+use rocket_auth_boilerplate::test_support::factories::{TestUser, UserFactory};
+use rocket_auth_boilerplate::test_support::{response_json, unique_email, TestApp};
+
+async fn member(app: &TestApp, organization_id: Uuid, role: OrgRole) -> TestUser {
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let mut conn = app.pool.acquire().await.unwrap();
+    users::set_organization(&mut conn, user.id(), organization_id, role).await.unwrap();
+    user
+}
+
+fn kid(token: &str) -> Option<String> {
+    let header: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(token.split('.').next().unwrap()).unwrap()).unwrap();
+    header["kid"].as_str().map(str::to_string)
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn organization_keys_sign_their_members_tokens_until_revoked() {
+    let app = TestApp::spawn_with(|config| config.org_signing_keys = true).await;
+    let mut conn = app.pool.acquire().await.unwrap();
+    let org = organizations::create(&mut conn, "Acme").await.unwrap();
+    let owner = member(&app, org, OrgRole::Owner).await;
+    let plain = member(&app, org, OrgRole::Member).await;
+    let owner_token = app.token_for(&owner.user).await;
+    let keys_uri = format!("/api/v1/orgs/{}/signing-keys", org);
+
+    let response = app.post_json_authorized(&keys_uri, &app.token_for(&plain.user).await, json!({})).await;
+    assert_eq!(response.status(), Status::Forbidden);
+    assert_eq!(response_json(response).await["code"], "not_org_admin");
+
+    let response = app.post_json_authorized(&keys_uri, &owner_token, json!({})).await;
+    assert_eq!(response.status(), Status::Created);
+    let body = response_json(response).await;
+    let key_kid = body["signing_key"]["kid"].as_str().unwrap().to_string();
+    let key_id = body["signing_key"]["id"].as_str().unwrap().to_string();
+    assert!(key_kid.starts_with(org_keys::KID_PREFIX));
+    assert!(body["signing_key"].get("secret").is_none());
+
+    let token = app.login_token(plain.email(), &plain.password).await;
+    assert_eq!(kid(&token), Some(key_kid));
+    assert_eq!(app.get_authorized("/api/v1/auth/me", &token).await.status(), Status::Ok);
+
+    // Users outside the organization keep the service's own key
+    let outsider = UserFactory::verified().insert(&app.pool).await;
+    let outsider_token = app.login_token(outsider.email(), &outsider.password).await;
+    assert_eq!(kid(&outsider_token), None);
+
+    let body = response_json(app.get_authorized(&keys_uri, &owner_token).await).await;
+    assert_eq!(body["signing_keys"].as_array().unwrap().len(), 1);
+
+    let response = app.delete_authorized(&format!("{}/{}", keys_uri, key_id), &owner_token).await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(app.get_authorized("/api/v1/auth/me", &token).await.status(), Status::Unauthorized);
+    assert_eq!(app.get_authorized("/api/v1/auth/me", &outsider_token).await.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn an_organization_key_cant_sign_for_other_organizations() {
+    let app = TestApp::spawn_with(|config| config.org_signing_keys = true).await;
+    let mut conn = app.pool.acquire().await.unwrap();
+    let (acme, globex) = (
+        organizations::create(&mut conn, "Acme").await.unwrap(),
+        organizations::create(&mut conn, "Globex").await.unwrap(),
+    );
+    let victim = member(&app, acme, OrgRole::Member).await;
+    let (kid, secret) = org_keys::generate();
+    let leaked = organizations::create_signing_key(&mut conn, globex, &kid, &secret).await.unwrap();
+
+    // A real session of the victim's, re-signed with the other organization's key
+    let token = app.token_for(&victim.user).await;
+    let jwt = app.client.rocket().state::<JwtService>().unwrap();
+    let claims: Claims = jwt.verify_token(&token).await.unwrap();
+    let forged = org_keys::sign(&claims, &leaked).unwrap();
+    assert_eq!(app.get_authorized("/api/v1/auth/me", &forged).await.status(), Status::Unauthorized);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn organization_keys_are_disabled_by_default() {
+    let app = TestApp::spawn().await;
+    let mut conn = app.pool.acquire().await.unwrap();
+    let org = organizations::create(&mut conn, "Acme").await.unwrap();
+    let owner = member(&app, org, OrgRole::Owner).await;
+    let token = app.token_for(&owner.user).await;
+
+    let response = app
+        .post_json_authorized(&format!("/api/v1/orgs/{}/signing-keys", org), &token, json!({}))
+        .await;
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn organization_audit_log_shows_events_of_members_at_the_time() {
+    let app = TestApp::spawn().await;
+    let mut conn = app.pool.acquire().await.unwrap();
+    let org = organizations::create(&mut conn, "Audited").await.unwrap();
+    let other_org = organizations::create(&mut conn, "Elsewhere").await.unwrap();
+    let owner = member(&app, org, OrgRole::Owner).await;
+    let plain = member(&app, org, OrgRole::Member).await;
+    let outsider = UserFactory::verified().insert(&app.pool).await;
+    let owner_token = app.token_for(&owner.user).await;
+    let audit_uri = format!("/api/v1/orgs/{}/audit?type=session_created", org);
+
+    app.login_token(plain.email(), &plain.password).await;
+    app.login_token(outsider.email(), &outsider.password).await;
+    // Moving to another organization doesn't take earlier events along
+    users::set_organization(&mut conn, plain.id(), other_org, OrgRole::Member).await.unwrap();
+    app.login_token(plain.email(), &plain.password).await;
+
+    let body = response_json(app.get_authorized(&audit_uri, &owner_token).await).await;
+    let events = body["events"].as_array().unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["user_id"], plain.id().to_string());
+    assert_eq!(events[0]["organization_id"], org.to_string());
+
+    let other_uri = format!("/api/v1/orgs/{}/audit?type=session_created", other_org);
+    let response = app.get_authorized(&other_uri, &owner_token).await;
+    assert_eq!(response.status(), Status::Forbidden);
+
+    // Admins see every organization's events, and can narrow them down to one
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let admin_token = app.token_for(&admin.user).await;
+    let body = response_json(app.get_authorized(&other_uri, &admin_token).await).await;
+    assert_eq!(body["events"].as_array().unwrap().len(), 1);
+    let uri = format!("/api/v1/admin/audit?org={}&user={}", org, plain.id());
+    let body = response_json(app.get_authorized(&uri, &admin_token).await).await;
+    assert_eq!(body["events"].as_array().unwrap().len(), 1);
+
+    let response = app.get_authorized(&audit_uri, &app.token_for(&plain.user).await).await;
+    assert_eq!(response.status(), Status::Forbidden);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn custom_roles_grant_only_their_permissions() {
+    let app = TestApp::spawn().await;
+    let mut conn = app.pool.acquire().await.unwrap();
+    let org = organizations::create(&mut conn, "Delegated").await.unwrap();
+    let owner = member(&app, org, OrgRole::Owner).await;
+    let auditor = member(&app, org, OrgRole::Member).await;
+    let owner_token = app.token_for(&owner.user).await;
+    let auditor_token = app.token_for(&auditor.user).await;
+    let roles_uri = format!("/api/v1/orgs/{}/roles", org);
+    let audit_uri = format!("/api/v1/orgs/{}/audit", org);
+    let billing_uri = format!("/api/v1/orgs/{}/billing", org);
+
+    let response = app.get_authorized(&audit_uri, &auditor_token).await;
+    assert_eq!(response.status(), Status::Forbidden);
+    assert_eq!(response_json(response).await["code"], "missing_org_permission");
+
+    let response = app
+        .post_json_authorized(&roles_uri, &owner_token, json!({ "name": "auditor", "permissions": ["audit.view"] }))
+        .await;
+    assert_eq!(response.status(), Status::Created);
+    assert_eq!(response_json(response).await["role"]["permissions"], json!(["audit.view"]));
+
+    let role_uri = format!("/api/v1/orgs/{}/members/{}/role", org, auditor.id());
+    let response = app.put_json_authorized(&role_uri, &owner_token, json!({ "role": "auditor" })).await;
+    assert_eq!(response.status(), Status::Ok);
+
+    assert_eq!(app.get_authorized(&audit_uri, &auditor_token).await.status(), Status::Ok);
+    assert_eq!(app.get_authorized(&billing_uri, &auditor_token).await.status(), Status::Forbidden);
+    // Managing roles stays with owners and admins
+    let response = app.get_authorized(&roles_uri, &auditor_token).await;
+    assert_eq!(response_json(response).await["code"], "not_org_admin");
+
+    let auditor_role_uri = format!("{}/auditor", roles_uri);
+    let response = app
+        .put_json_authorized(&auditor_role_uri, &owner_token, json!({ "permissions": ["audit.view", "billing.manage"] }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(app.get_authorized(&billing_uri, &auditor_token).await.status(), Status::Ok);
+
+    let body = response_json(app.get_authorized(&roles_uri, &owner_token).await).await;
+    assert_eq!(body["roles"].as_array().unwrap().len(), 1);
+    assert_eq!(body["built_in"][2]["name"], "member");
+
+    let response = app.delete_authorized(&auditor_role_uri, &owner_token).await;
+    assert_eq!(response.status(), Status::Conflict);
+    assert_eq!(response_json(response).await["code"], "role_in_use");
+    app.put_json_authorized(&role_uri, &owner_token, json!({ "role": "member" })).await;
+    assert_eq!(app.delete_authorized(&auditor_role_uri, &owner_token).await.status(), Status::Ok);
+    assert_eq!(app.delete_authorized(&auditor_role_uri, &owner_token).await.status(), Status::NotFound);
+    assert_eq!(app.get_authorized(&audit_uri, &auditor_token).await.status(), Status::Forbidden);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn invalid_roles_are_refused() {
+    let app = TestApp::spawn().await;
+    let mut conn = app.pool.acquire().await.unwrap();
+    let org = organizations::create(&mut conn, "Strict").await.unwrap();
+    let owner = member(&app, org, OrgRole::Owner).await;
+    let plain = member(&app, org, OrgRole::Member).await;
+    let owner_token = app.token_for(&owner.user).await;
+    let roles_uri = format!("/api/v1/orgs/{}/roles", org);
+
+    for (role, status) in [
+        (json!({ "name": "Has Spaces", "permissions": [] }), Status::BadRequest),
+        (json!({ "name": "auditor", "permissions": ["everything"] }), Status::BadRequest),
+        (json!({ "name": "admin", "permissions": [] }), Status::Conflict),
+        (json!({ "name": "billing", "permissions": ["billing.manage"] }), Status::Created),
+        (json!({ "name": "billing", "permissions": [] }), Status::Conflict),
+    ] {
+        let response = app.post_json_authorized(&roles_uri, &owner_token, role.clone()).await;
+        assert_eq!(response.status(), status, "{}", role);
+    }
+
+    let uri = format!("{}/nobody", roles_uri);
+    let response = app.put_json_authorized(&uri, &owner_token, json!({ "permissions": [] })).await;
+    assert_eq!(response.status(), Status::NotFound);
+
+    let role_uri = format!("/api/v1/orgs/{}/members/{}/role", org, plain.id());
+    for role in ["owner", "nobody"] {
+        let response = app.put_json_authorized(&role_uri, &owner_token, json!({ "role": role })).await;
+        assert_eq!(response.status(), Status::BadRequest, "{}", role);
+    }
+    // Owners keep their role, and other organizations' users can't be given one
+    let outsider = UserFactory::verified().insert(&app.pool).await;
+    for user_id in [owner.id(), outsider.id()] {
+        let uri = format!("/api/v1/orgs/{}/members/{}/role", org, user_id);
+        let response = app.put_json_authorized(&uri, &owner_token, json!({ "role": "billing" })).await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn organization_invitations_bring_new_accounts_in_with_their_role() {
+    let app = TestApp::spawn().await;
+    let mut conn = app.pool.acquire().await.unwrap();
+    let org = organizations::create(&mut conn, "Growing").await.unwrap();
+    let owner = member(&app, org, OrgRole::Owner).await;
+    let recruiter = member(&app, org, OrgRole::Member).await;
+    let owner_token = app.token_for(&owner.user).await;
+    let recruiter_token = app.token_for(&recruiter.user).await;
+    let invitations_uri = format!("/api/v1/orgs/{}/invitations", org);
+    let email = unique_email();
+
+    let response = app.post_json_authorized(&invitations_uri, &recruiter_token, json!({ "email": email })).await;
+    assert_eq!(response.status(), Status::Forbidden);
+    assert_eq!(response_json(response).await["code"], "missing_org_permission");
+
+    let roles_uri = format!("/api/v1/orgs/{}/roles", org);
+    app.post_json_authorized(&roles_uri, &owner_token, json!({ "name": "recruiter", "permissions": ["members.invite"] }))
+        .await;
+    let role_uri = format!("/api/v1/orgs/{}/members/{}/role", org, recruiter.id());
+    app.put_json_authorized(&role_uri, &owner_token, json!({ "role": "recruiter" })).await;
+
+    let response = app
+        .post_json_authorized(&invitations_uri, &recruiter_token, json!({ "email": email, "role": "recruiter" }))
+        .await;
+    assert_eq!(response.status(), Status::Created);
+    let code = response_json(response).await["code"].as_str().unwrap().to_string();
+    assert!(app.mailbox().last_to(&email).expect("invitation emailed").message.body.contains(&code));
+
+    // Accounts that exist already aren't invited
+    let response = app
+        .post_json_authorized(&invitations_uri, &recruiter_token, json!({ "email": owner.email() }))
+        .await;
+    assert_eq!(response.status(), Status::Conflict);
+
+    let response = app
+        .post_json("/api/v1/auth/register", json!({ "email": email, "password": "password123", "invitation_code": code }))
+        .await;
+    assert_eq!(response.status(), Status::Created);
+    let joined = users::find_by_email(&mut conn, &email).await.unwrap().unwrap();
+    assert_eq!(joined.organization_id, Some(org));
+    assert_eq!(joined.organization_role, "recruiter");
+
+    let response = app
+        .post_json("/api/v1/auth/register", json!({ "email": unique_email(), "password": "password123", "invitation_code": code }))
+        .await;
+    assert_eq!(response.status(), Status::Forbidden);
+    assert_eq!(response_json(response).await["code"], "invitation_invalid");
+}
@@ -0,0 +1,74 @@
+use rocket::http::{Accept, ContentType, Header, Status};
+use rocket::serde::json::json;
+
+use rocket_auth_boilerplate::test_support::{response_json, unique_email, TestApp};
+
+const BROWSER_ACCEPT: &str = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+
+async fn verification_link(app: &TestApp) -> String {
+    let email = unique_email();
+    let body = json!({ "email": email, "password": "Str0ng!Passw0rd" });
+    let response = app.post_json("/api/v1/auth/register", body).await;
+    assert_eq!(response.status(), Status::Created);
+
+    let message = app.mailbox().messages_to(&email).pop().unwrap().message;
+    let start = message.body.find("/api/v1/auth/verify-email").unwrap();
+    message.body[start..].split_whitespace().next().unwrap().to_string()
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn browsers_get_a_page_from_verification_links() {
+    let app = TestApp::spawn_with(|config| config.frontend_url = "https://app.example.com".to_string()).await;
+    let link = verification_link(&app).await;
+    let open = || {
+        app.client
+            .get(link.clone())
+            .header(Header::new("Accept", BROWSER_ACCEPT))
+            .dispatch()
+    };
+
+    let response = open().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::HTML));
+    let page = response.into_string().await.unwrap();
+    assert!(page.contains("Email verified successfully"));
+    assert!(page.contains("app.example.com"));
+
+    let response = open().await;
+    assert_eq!(response.status(), Status::BadRequest);
+    assert_eq!(response.content_type(), Some(ContentType::HTML));
+    assert!(response.into_string().await.unwrap().contains("Invalid or expired verification token"));
+
+    // Refused redirects are explained on a page too
+    let response = app
+        .client
+        .get("/api/v1/auth/verify-email?token=unused&redirect_to=https%3A%2F%2Fevil.example.org")
+        .header(Accept::HTML)
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+    assert_eq!(response.content_type(), Some(ContentType::HTML));
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn api_clients_still_get_json() {
+    let app = TestApp::spawn().await;
+
+    let link = verification_link(&app).await;
+    let response = app.client.get(link.clone()).header(Accept::JSON).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response_json(response).await["message"], "Email verified successfully");
+
+    // No Accept header, or one that takes anything, means JSON
+    for accept in [None, Some(Accept::Any)] {
+        let mut request = app.client.get(link.clone());
+        if let Some(accept) = accept {
+            request = request.header(accept);
+        }
+        let response = request.dispatch().await;
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(response_json(response).await["error"], "Invalid or expired verification token");
+    }
+}
@@ -0,0 +1,37 @@
+use rocket::http::{ContentType, Status};
+use rocket::serde::json::json;
+
+use rocket_auth_boilerplate::config::PasswordHashing;
+use rocket_auth_boilerplate::test_support::TestApp;
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn hashes_beyond_the_threads_and_queue_are_refused() {
+    let app = TestApp::spawn_with(|config| {
+        // Slow enough that the first registration is still hashing when the second arrives
+        config.password_hashing = PasswordHashing::Bcrypt { cost: 13 };
+        config.password_hash_threads = 1;
+        config.password_hash_queue = 0;
+    })
+    .await;
+
+    let register = |email: String| {
+        app.client
+            .post("/api/v1/auth/register")
+            .header(ContentType::JSON)
+            .body(json!({ "email": email, "password": "password123" }).to_string())
+            .dispatch()
+    };
+    let (first, second) = tokio::join!(
+        register(format!("hash-{}@example.com", uuid::Uuid::new_v4())),
+        register(format!("hash-{}@example.com", uuid::Uuid::new_v4()))
+    );
+
+    let mut statuses = [first.status().code, second.status().code];
+    statuses.sort();
+    assert_eq!(statuses, [Status::Created.code, Status::ServiceUnavailable.code]);
+
+    // The thread is free again once the first hash is done
+    let response = register(format!("hash-{}@example.com", uuid::Uuid::new_v4())).await;
+    assert_eq!(response.status(), Status::Created);
+}
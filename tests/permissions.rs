@@ -0,0 +1,94 @@
+use rocket::http::{Header, Status};
+use rocket::serde::json::json;
+
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, TestApp};
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn permissions_granted_to_a_role_unlock_endpoints() {
+    let app = TestApp::spawn().await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let admin_token = app.token_for(&admin.user).await;
+    let support = UserFactory::verified().with_role("support").insert(&app.pool).await;
+    let support_token = app.token_for(&support.user).await;
+    let victim = UserFactory::verified().insert(&app.pool).await;
+    let delete_uri = format!("/api/v1/admin/users/{}", victim.id());
+
+    let response = app.delete_authorized(&delete_uri, &support_token).await;
+    assert_eq!(response.status(), Status::Forbidden);
+    let body = response_json(response).await;
+    assert_eq!(body["code"], "missing_permission");
+    assert!(body["details"].as_str().unwrap().contains("users.delete"));
+
+    // Admins start with the built-in permissions
+    let body = response_json(app.get_authorized("/api/v1/admin/permissions", &admin_token).await).await;
+    let users_delete = body["permissions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|permission| permission["name"] == "users.delete")
+        .expect("built-in permission listed");
+    assert_eq!(users_delete["roles"], json!(["admin"]));
+
+    let response = app
+        .client
+        .put("/api/v1/admin/roles/support/permissions/users.delete")
+        .header(Header::new("Authorization", format!("Bearer {}", admin_token)))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = app.delete_authorized(&delete_uri, &support_token).await;
+    assert_eq!(response.status(), Status::Ok);
+    let response = app.login(victim.email(), &victim.password).await;
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    let response = app
+        .delete_authorized("/api/v1/admin/roles/support/permissions/users.delete", &admin_token)
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let other = UserFactory::verified().insert(&app.pool).await;
+    let response = app
+        .delete_authorized(&format!("/api/v1/admin/users/{}", other.id()), &support_token)
+        .await;
+    assert_eq!(response.status(), Status::Forbidden);
+
+    // Support staff can't manage permissions themselves
+    let response = app.get_authorized("/api/v1/admin/permissions", &support_token).await;
+    assert_eq!(response.status(), Status::Forbidden);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn custom_permissions_can_be_created_and_deleted() {
+    let app = TestApp::spawn().await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let admin_token = app.token_for(&admin.user).await;
+    let name = format!("reports.export_{}", uuid::Uuid::new_v4().simple());
+
+    let response = app
+        .post_json_authorized("/api/v1/admin/permissions", &admin_token, json!({ "name": name }))
+        .await;
+    assert_eq!(response.status(), Status::Created);
+    let response = app
+        .post_json_authorized("/api/v1/admin/permissions", &admin_token, json!({ "name": name }))
+        .await;
+    assert_eq!(response.status(), Status::Conflict);
+    let response = app
+        .post_json_authorized("/api/v1/admin/permissions", &admin_token, json!({ "name": "Not Valid" }))
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let response = app
+        .delete_authorized(&format!("/api/v1/admin/permissions/{}", name), &admin_token)
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = app.delete_authorized("/api/v1/admin/permissions/users.delete", &admin_token).await;
+    assert_eq!(response.status(), Status::BadRequest);
+    let response = app
+        .delete_authorized("/api/v1/admin/roles/admin/permissions/permissions.manage", &admin_token)
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+}
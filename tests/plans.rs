@@ -0,0 +1,147 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{Duration, Utc};
+use rocket::http::Status;
+use rocket::serde::json::{json, Value};
+use rocket::{get, routes};
+
+use rocket_auth_boilerplate::auth::guard::RequiresPlan;
+use rocket_auth_boilerplate::auth::plans::{Enterprise, Pro};
+use rocket_auth_boilerplate::models::organization::OrgRole;
+use rocket_auth_boilerplate::repositories::{organizations, users};
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, TestApp};
+
+#[get("/pro")]
+fn pro(user: RequiresPlan<Pro>) -> String {
+    user.user_id
+}
+
+#[get("/enterprise")]
+fn enterprise(user: RequiresPlan<Enterprise>) -> String {
+    user.user_id
+}
+
+async fn spawn() -> TestApp {
+    TestApp::spawn_mounting(routes![pro, enterprise]).await
+}
+
+fn plan_claim(token: &str) -> Value {
+    let payload = URL_SAFE_NO_PAD.decode(token.split('.').nth(1).unwrap()).unwrap();
+    serde_json::from_slice::<Value>(&payload).unwrap()["plan"].clone()
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn granted_plans_open_gated_routes_and_appear_in_tokens() {
+    let app = spawn().await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let admin_token = app.token_for(&admin.user).await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.login_token(user.email(), &user.password).await;
+    assert_eq!(plan_claim(&token), Value::Null);
+
+    let response = app.get_authorized("/pro", &token).await;
+    assert_eq!(response.status(), Status::Forbidden);
+    assert_eq!(response_json(response).await["code"], "plan_required");
+
+    let response = app
+        .post_json_authorized(
+            "/api/v1/admin/entitlements",
+            &admin_token,
+            json!({ "plan": "pro", "user_id": user.id() }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::Created);
+    let entitlement_id = response_json(response).await["entitlement"]["id"].as_str().unwrap().to_string();
+
+    // Checked against the database, so the old token works straight away
+    assert_eq!(app.get_authorized("/pro", &token).await.status(), Status::Ok);
+    assert_eq!(app.get_authorized("/enterprise", &token).await.status(), Status::Forbidden);
+    let token = app.login_token(user.email(), &user.password).await;
+    assert_eq!(plan_claim(&token), "pro");
+
+    let response = app
+        .delete_authorized(&format!("/api/v1/admin/entitlements/{}", entitlement_id), &admin_token)
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(app.get_authorized("/pro", &token).await.status(), Status::Forbidden);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn organization_plans_cover_members_while_in_effect() {
+    let app = spawn().await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let admin_token = app.token_for(&admin.user).await;
+    let member = UserFactory::verified().insert(&app.pool).await;
+    let mut conn = app.pool.acquire().await.unwrap();
+    let org = organizations::create(&mut conn, "Acme").await.unwrap();
+    users::set_organization(&mut conn, member.id(), org, OrgRole::Member).await.unwrap();
+    let token = app.token_for(&member.user).await;
+
+    // An entitlement that already ended doesn't count
+    let response = app
+        .post_json_authorized(
+            "/api/v1/admin/entitlements",
+            &admin_token,
+            json!({
+                "plan": "enterprise",
+                "organization_id": org,
+                "starts_at": Utc::now() - Duration::days(30),
+                "ends_at": Utc::now() - Duration::days(1)
+            }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::Created);
+    assert_eq!(app.get_authorized("/pro", &token).await.status(), Status::Forbidden);
+
+    let response = app
+        .post_json_authorized(
+            "/api/v1/admin/entitlements",
+            &admin_token,
+            json!({ "plan": "enterprise", "organization_id": org }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::Created);
+    // A higher plan includes the lower ones
+    assert_eq!(app.get_authorized("/pro", &token).await.status(), Status::Ok);
+    assert_eq!(app.get_authorized("/enterprise", &token).await.status(), Status::Ok);
+
+    let body = response_json(
+        app.get_authorized(&format!("/api/v1/admin/entitlements?organization_id={}", org), &admin_token)
+            .await,
+    )
+    .await;
+    assert_eq!(body["entitlements"].as_array().unwrap().len(), 2);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn plans_are_managed_by_admins() {
+    let app = spawn().await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let admin_token = app.token_for(&admin.user).await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+
+    let response = app.get_authorized("/api/v1/admin/plans", &app.token_for(&user.user).await).await;
+    assert_eq!(response.status(), Status::Forbidden);
+
+    let body = response_json(app.get_authorized("/api/v1/admin/plans", &admin_token).await).await;
+    let names: Vec<&str> = body["plans"].as_array().unwrap().iter().map(|p| p["name"].as_str().unwrap()).collect();
+    assert_eq!(&names[..3], ["free", "pro", "enterprise"]);
+
+    let grant = |body: Value| async { app.post_json_authorized("/api/v1/admin/entitlements", &admin_token, body).await.status() };
+    assert_eq!(grant(json!({ "plan": "pro" })).await, Status::BadRequest);
+    assert_eq!(grant(json!({ "plan": "platinum", "user_id": user.id() })).await, Status::NotFound);
+    assert_eq!(grant(json!({ "plan": "pro", "user_id": uuid::Uuid::new_v4() })).await, Status::NotFound);
+
+    let response = app
+        .post_json_authorized("/api/v1/admin/plans", &admin_token, json!({ "name": "Pro Plus!", "rank": 15 }))
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+    let response = app
+        .post_json_authorized("/api/v1/admin/plans", &admin_token, json!({ "name": "pro", "rank": 15 }))
+        .await;
+    assert_eq!(response.status(), Status::Conflict);
+}
@@ -0,0 +1,76 @@
+use rocket::http::Status;
+use rocket::serde::json::json;
+
+use rocket_auth_boilerplate::auth::tokens;
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, TestApp};
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn qr_login_issues_a_token_once_approved() {
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let phone_token = app.token_for(&user.user).await;
+
+    // Signed-out device asks for a QR code
+    let response = app.post_json("/api/v1/auth/qr-login", json!({})).await;
+    assert_eq!(response.status(), Status::Created);
+    let body = response_json(response).await;
+    let code = body["code"].as_str().unwrap().to_string();
+    let poll_token = body["poll_token"].as_str().unwrap().to_string();
+
+    // Only a hash of the poll token is stored
+    let stored: String = sqlx::query_scalar("SELECT poll_token_hash FROM qr_login_requests WHERE code = $1")
+        .bind(&code)
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(stored, tokens::hash(&poll_token));
+
+    let response = app
+        .post_json("/api/v1/auth/qr-login/poll", json!({ "poll_token": poll_token }))
+        .await;
+    assert_eq!(response.status(), Status::Accepted);
+
+    // The poll token is not a QR code
+    let response = app
+        .post_json_authorized("/api/v1/auth/qr-login/approve", &phone_token, json!({ "code": poll_token }))
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    // Signed-in phone scans and approves
+    let response = app
+        .post_json_authorized("/api/v1/auth/qr-login/approve", &phone_token, json!({ "code": code }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = app
+        .post_json("/api/v1/auth/qr-login/poll", json!({ "poll_token": poll_token }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    let desktop_token = body["token"].as_str().expect("token issued").to_string();
+
+    let response = app.get_authorized("/api/v1/auth/me", &desktop_token).await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    assert_eq!(body["user"]["email"], user.email());
+
+    // The approval can only be picked up once
+    let response = app
+        .post_json("/api/v1/auth/qr-login/poll", json!({ "poll_token": poll_token }))
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn qr_login_approval_requires_authentication() {
+    let app = TestApp::spawn().await;
+
+    let body = response_json(app.post_json("/api/v1/auth/qr-login", json!({})).await).await;
+    let response = app
+        .post_json("/api/v1/auth/qr-login/approve", json!({ "code": body["code"] }))
+        .await;
+    assert_eq!(response.status(), Status::Unauthorized);
+}
@@ -0,0 +1,163 @@
+use rocket::http::{RawStr, Status};
+use rocket::serde::json::{json, Value};
+use uuid::Uuid;
+
+use rocket_auth_boilerplate::config::GenericOAuthConfig;
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, unique_email, TestApp};
+
+const FRONTEND: &str = "https://app.example.com";
+
+async fn spawn(providers: Vec<GenericOAuthConfig>) -> TestApp {
+    TestApp::spawn_with(|config| {
+        config.frontend_url = FRONTEND.to_string();
+        config.redirect_allowlist = vec!["https://*.shop.example.com/checkout/*".to_string()];
+        config.oauth_providers = providers;
+    })
+    .await
+}
+
+/// Answers POSTs with an access token and GETs with `profile`, like a provider would
+async fn mock_provider(profile: Value) -> String {
+    use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = rocket::tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    rocket::tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut request = vec![0; 8192];
+            let read = stream.read(&mut request).await.unwrap_or(0);
+            let body = match request[..read].starts_with(b"POST") {
+                true => json!({ "access_token": "mock-access-token", "token_type": "bearer" }),
+                false => profile.clone(),
+            }
+            .to_string();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        }
+    });
+    format!("http://{}", address)
+}
+
+fn provider(url: &str) -> GenericOAuthConfig {
+    GenericOAuthConfig {
+        name: format!("corp-{}", Uuid::new_v4().simple()),
+        client_id: "corp-client".to_string(),
+        client_secret: "corp-secret".to_string(),
+        authorize_url: format!("{}/oauth/authorize", url),
+        token_url: format!("{}/oauth/token", url),
+        userinfo_url: format!("{}/userinfo", url),
+        scopes: "openid email".to_string(),
+        subject_field: "id".to_string(),
+        email_field: "email".to_string(),
+        email_verified_field: Some("email_verified".to_string()),
+        basic_auth: false,
+        pkce: true,
+    }
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn oauth_sign_ins_return_to_allowed_urls_only() {
+    let profile = json!({ "id": Uuid::new_v4().to_string(), "email": unique_email(), "email_verified": true });
+    let corp = provider(&mock_provider(profile).await);
+    let app = spawn(vec![corp.clone()]).await;
+    let authorize = |redirect_to: &str| {
+        let redirect_to = RawStr::new(redirect_to).percent_encode().to_string();
+        app.client
+            .get(format!("/api/v1/auth/oauth/{}/authorize?redirect_to={}", corp.name, redirect_to))
+            .dispatch()
+    };
+
+    for refused in [
+        "https://evil.example.org/",
+        "//evil.example.org/",
+        "/\\evil.example.org",
+        "javascript:alert(1)",
+        "https://shop.example.com/checkout/",
+        "https://eu.shop.example.com/account",
+        "https://eu.shop.example.com.evil.io/checkout/",
+        "https://user@eu.shop.example.com/checkout/",
+    ] {
+        let response = authorize(refused).await;
+        assert_eq!(response.status(), Status::BadRequest, "{}", refused);
+        assert_eq!(response_json(response).await["code"], "redirect_not_allowed");
+    }
+
+    for (redirect_to, expected) in [
+        ("/dashboard?tab=1", "https://app.example.com/dashboard?tab=1"),
+        ("https://app.example.com/settings", "https://app.example.com/settings"),
+        ("https://eu.shop.example.com/checkout/cart", "https://eu.shop.example.com/checkout/cart"),
+    ] {
+        let response = authorize(redirect_to).await;
+        assert_eq!(response.status(), Status::SeeOther);
+        let location = reqwest::Url::parse(response.headers().get_one("Location").unwrap()).unwrap();
+        let state = location.query_pairs().find(|(key, _)| key == "state").unwrap().1.into_owned();
+        let response = app
+            .post_json(
+                &format!("/api/v1/auth/oauth/{}/callback", corp.name),
+                json!({ "code": "mock-code", "state": state }),
+            )
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let body = response_json(response).await;
+        assert!(body["token"].is_string());
+        assert_eq!(body["redirect_to"], expected);
+    }
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn verification_links_send_the_browser_back_to_the_frontend() {
+    let app = spawn(Vec::new()).await;
+    let email = unique_email();
+
+    let refused = json!({ "email": email, "password": "Str0ng!Passw0rd", "redirect_to": "https://evil.example.org/" });
+    let response = app.post_json("/api/v1/auth/register", refused).await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let body = json!({ "email": email, "password": "Str0ng!Passw0rd", "redirect_to": "/welcome" });
+    let response = app.post_json("/api/v1/auth/register", body).await;
+    assert_eq!(response.status(), Status::Created);
+
+    let message = app.mailbox().messages_to(&email).pop().unwrap().message;
+    let start = message.body.find("/api/v1/auth/verify-email").unwrap();
+    let link = message.body[start..].split_whitespace().next().unwrap().to_string();
+    assert!(link.contains("&redirect_to=https:%2F%2Fapp.example.com%2Fwelcome"), "{}", link);
+
+    let response = app.client.get(link.clone()).dispatch().await;
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(
+        response.headers().get_one("Location"),
+        Some("https://app.example.com/welcome?email_verified=true")
+    );
+    // Used links still go back, saying so
+    let response = app.client.get(link).dispatch().await;
+    assert_eq!(
+        response.headers().get_one("Location"),
+        Some("https://app.example.com/welcome?email_verified=false")
+    );
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn signing_out_everywhere_returns_the_allowed_redirect() {
+    let app = spawn(Vec::new()).await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+
+    let response = app
+        .post_json_authorized("/api/v1/auth/logout-all?redirect_to=https%3A%2F%2Fevil.example.org", &token, json!({}))
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let response = app
+        .post_json_authorized("/api/v1/auth/logout-all?redirect_to=%2Fsigned-out", &token, json!({}))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response_json(response).await["redirect_to"], "https://app.example.com/signed-out");
+}
@@ -0,0 +1,213 @@
+use rocket::http::Status;
+use rocket::serde::json::json;
+
+use std::time::Duration;
+
+use rocket_auth_boilerplate::config::{AppConfig, RegistrationMode, WelcomeEmail};
+use rocket_auth_boilerplate::email::memory::CapturedEmail;
+use rocket_auth_boilerplate::email::sender::Mailer;
+use rocket_auth_boilerplate::email::welcome;
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, unique_email, TestApp};
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn closed_registration_is_refused() {
+    let app = TestApp::spawn_with(|config| config.registration_mode = RegistrationMode::Closed).await;
+
+    let response = app.register(&unique_email(), "password123").await;
+    assert_eq!(response.status(), Status::Forbidden);
+    let body = response_json(response).await;
+    assert_eq!(body["code"], "registration_closed");
+
+    let response = app.post_json("/api/v1/auth/guest", json!({})).await;
+    assert_eq!(response.status(), Status::Forbidden);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn invite_only_registration_consumes_invitations() {
+    let app = TestApp::spawn_with(|config| config.registration_mode = RegistrationMode::InviteOnly).await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let admin_token = app.token_for(&admin.user).await;
+    let email = unique_email();
+
+    let response = app.register(&email, "password123").await;
+    assert_eq!(response.status(), Status::Forbidden);
+    assert_eq!(response_json(response).await["code"], "invitation_required");
+
+    let response = app
+        .post_json_authorized("/api/v1/admin/invitations", &admin_token, json!({ "email": email }))
+        .await;
+    assert_eq!(response.status(), Status::Created);
+    let code = response_json(response).await["code"].as_str().unwrap().to_string();
+    let invitation = app.mailbox().last_to(&email).expect("invitation emailed");
+    assert!(invitation.message.body.contains(&format!("/register?invitation={}", code)));
+
+    // Bound to the invited address
+    let response = app
+        .post_json(
+            "/api/v1/auth/register",
+            json!({ "email": unique_email(), "password": "password123", "invitation_code": code }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::Forbidden);
+    assert_eq!(response_json(response).await["code"], "invitation_invalid");
+
+    let response = app
+        .post_json(
+            "/api/v1/auth/register",
+            json!({ "email": email, "password": "password123", "invitation_code": code }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::Created);
+
+    // Single use
+    let response = app
+        .post_json(
+            "/api/v1/auth/register",
+            json!({ "email": email.replace("user-", "again-"), "password": "password123", "invitation_code": code }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::Forbidden);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn signups_wait_for_admin_approval() {
+    let app = TestApp::spawn_with(|config| config.signup_approval = true).await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let admin_token = app.token_for(&admin.user).await;
+    let approved = unique_email();
+    let rejected = unique_email();
+
+    for email in [&approved, &rejected] {
+        let response = app.register(email, "password123").await;
+        assert_eq!(response.status(), Status::Created);
+        assert_eq!(response_json(response).await["user"]["approval_status"], "pending_approval");
+    }
+
+    let response = app.login(&approved, "password123").await;
+    assert_eq!(response.status(), Status::Forbidden);
+    assert_eq!(response_json(response).await["code"], "approval_pending");
+
+    let body = response_json(app.get_authorized("/api/v1/admin/signups?limit=200", &admin_token).await).await;
+    let id_of = |email: &str| {
+        body["signups"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|signup| signup["email"] == email)
+            .map(|signup| signup["id"].as_str().unwrap().to_string())
+            .expect("signup in queue")
+    };
+    let (approved_id, rejected_id) = (id_of(&approved), id_of(&rejected));
+
+    let uri = format!("/api/v1/admin/signups/{}/approve", approved_id);
+    let response = app.post_json_authorized(&uri, &admin_token, json!({})).await;
+    assert_eq!(response.status(), Status::Ok);
+    let welcome = app.mailbox().last_to(&approved).expect("welcome email sent");
+    assert!(welcome.message.subject.starts_with("Welcome"));
+    assert_eq!(app.login(&approved, "password123").await.status(), Status::Ok);
+
+    // Already decided
+    let response = app.post_json_authorized(&uri, &admin_token, json!({})).await;
+    assert_eq!(response.status(), Status::NotFound);
+
+    let uri = format!("/api/v1/admin/signups/{}/reject", rejected_id);
+    let response = app.post_json_authorized(&uri, &admin_token, json!({})).await;
+    assert_eq!(response.status(), Status::BadRequest);
+    let response = app
+        .post_json_authorized(&uri, &admin_token, json!({ "reason": "Unknown organisation" }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = app.login(&rejected, "password123").await;
+    assert_eq!(response.status(), Status::Forbidden);
+    let body = response_json(response).await;
+    assert_eq!(body["code"], "approval_rejected");
+    assert_eq!(body["details"], "Unknown organisation");
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn uniform_registration_does_not_reveal_existing_accounts() {
+    let app = TestApp::spawn_with(|config| config.registration_uniform_response = true).await;
+    let email = unique_email();
+
+    let response = app.register(&email, "password123").await;
+    assert_eq!(response.status(), Status::Accepted);
+    let first = response_json(response).await;
+    let verification = app.mailbox().last_to(&email).expect("verification email sent");
+    assert_eq!(verification.message.subject, "Verify your email address");
+
+    let response = app.register(&email, "another-password").await;
+    assert_eq!(response.status(), Status::Accepted);
+    assert_eq!(response_json(response).await, first);
+
+    // The owner hears about it instead, and the account is untouched
+    let notice = app.mailbox().last_to(&email).expect("notice sent");
+    assert_eq!(notice.message.subject, "You already have an account");
+    app.login_token(&email, "password123").await;
+    let response = app.login(&email, "another-password").await;
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+/// Wait briefly for the welcome email the event bus subscriber sends to `email`
+async fn welcome_to(app: &TestApp, email: &str) -> Option<CapturedEmail> {
+    for _ in 0..20 {
+        let welcome = app.mailbox().messages_to(email).into_iter().find(|m| m.message.subject == "Welcome!");
+        if welcome.is_some() {
+            return welcome;
+        }
+        rocket::tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    None
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn welcome_email_carries_the_verification_link_and_is_sent_once() {
+    let app = TestApp::spawn_with(|config| config.welcome_email = WelcomeEmail::WithVerification).await;
+
+    // The subscriber starts asynchronously, so register until a welcome comes through
+    let mut received = None;
+    for _ in 0..10 {
+        let email = unique_email();
+        assert_eq!(app.register(&email, "password123").await.status(), Status::Created);
+        if let Some(welcome) = welcome_to(&app, &email).await {
+            received = Some((email, welcome));
+            break;
+        }
+    }
+    let (email, welcome) = received.expect("welcome email sent");
+    assert!(welcome.message.body.contains("verify-email"));
+    assert_eq!(app.mailbox().messages_to(&email).len(), 1, "no separate verification email");
+
+    let rocket = app.client.rocket();
+    let (config, mailer) = (rocket.state::<AppConfig>().unwrap(), rocket.state::<Mailer>().unwrap());
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let mut conn = app.pool.acquire().await.unwrap();
+    welcome::send(&mut conn, config, mailer, user.user.id).await.unwrap();
+    welcome::send(&mut conn, config, mailer, user.user.id).await.unwrap();
+    let sent = app.mailbox().messages_to(user.email());
+    assert_eq!(sent.len(), 1);
+    assert!(!sent[0].message.body.contains("verify-email"), "verified users get no link");
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn pending_signups_get_no_welcome_until_approved() {
+    let app = TestApp::spawn_with(|config| {
+        config.welcome_email = WelcomeEmail::WithVerification;
+        config.signup_approval = true;
+    })
+    .await;
+
+    let email = unique_email();
+    assert_eq!(app.register(&email, "password123").await.status(), Status::Created);
+    let sent = app.mailbox().messages_to(&email);
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].message.subject, "Verify your email address");
+    assert!(welcome_to(&app, &email).await.is_none());
+}
@@ -0,0 +1,51 @@
+use rocket::http::Status;
+use rocket::serde::json::json;
+
+use rocket_auth_boilerplate::config::RequestLog;
+use rocket_auth_boilerplate::request_log::{redact, redact_query};
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, TestApp};
+
+#[test]
+fn credentials_are_redacted() {
+    let mut body = json!({
+        "email": "user@example.com",
+        "password": "hunter22",
+        "new_password": "hunter23",
+        "must_change_password": false,
+        "key": "ak_123",
+        "api_key": { "name": "ci", "key_prefix": "ak_1" },
+        "sessions": [{ "refresh_token": "abc", "id": 1 }]
+    });
+    redact(&mut body);
+    assert_eq!(
+        body,
+        json!({
+            "email": "user@example.com",
+            "password": "[REDACTED]",
+            "new_password": "[REDACTED]",
+            "must_change_password": false,
+            "key": "[REDACTED]",
+            "api_key": { "name": "ci", "key_prefix": "ak_1" },
+            "sessions": [{ "refresh_token": "[REDACTED]", "id": 1 }]
+        })
+    );
+
+    assert_eq!(redact_query("token=abc&next=/home"), "token=[REDACTED]&next=/home");
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn logging_bodies_leaves_requests_and_responses_intact() {
+    let app = TestApp::spawn_with(|config| config.request_log = RequestLog::Bodies).await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+
+    let response = app.login(user.email(), &user.password).await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    let token = body["token"].as_str().unwrap();
+
+    let response = app.get_authorized("/api/v1/auth/me", token).await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response_json(response).await["user"]["email"], user.email());
+}
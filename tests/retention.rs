@@ -0,0 +1,138 @@
+use chrono::{DateTime, Months, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use rocket_auth_boilerplate::config::AppConfig;
+use rocket_auth_boilerplate::repositories::audit;
+use rocket_auth_boilerplate::retention;
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::TestApp;
+
+async fn count(pool: &PgPool, table: &str, column: &str, user_id: Uuid) -> i64 {
+    sqlx::query_scalar::<_, i64>(&format!("SELECT COUNT(*) FROM {} WHERE {} = $1", table, column))
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn old_records_are_purged_after_their_retention_period() {
+    let app = TestApp::spawn_with(|config| {
+        config.audit_retention_days = 90;
+        config.login_history_retention_days = 30;
+        config.session_retention_days = 7;
+    })
+    .await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+
+    // Two logins, each leaving an attempt, a session and a session_created event
+    app.login(user.email(), &user.password).await;
+    app.login(user.email(), &user.password).await;
+    assert_eq!(count(&app.pool, "login_attempts", "user_id", user.id()).await, 2);
+    assert_eq!(count(&app.pool, "sessions", "user_id", user.id()).await, 2);
+    assert_eq!(count(&app.pool, "audit_events", "user_id", user.id()).await, 2);
+
+    // One of each is past its retention period
+    sqlx::query(
+        "UPDATE login_attempts SET created_at = NOW() - INTERVAL '31 days' \
+         WHERE id = (SELECT id FROM login_attempts WHERE user_id = $1 LIMIT 1)",
+    )
+    .bind(user.id())
+    .execute(&app.pool)
+    .await
+    .unwrap();
+    sqlx::query(
+        "UPDATE sessions SET revoked_at = NOW() - INTERVAL '8 days' \
+         WHERE id = (SELECT id FROM sessions WHERE user_id = $1 LIMIT 1)",
+    )
+    .bind(user.id())
+    .execute(&app.pool)
+    .await
+    .unwrap();
+    sqlx::query(
+        "UPDATE audit_events SET occurred_at = NOW() - INTERVAL '91 days' \
+         WHERE id = (SELECT id FROM audit_events WHERE user_id = $1 LIMIT 1)",
+    )
+    .bind(user.id())
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let config = app.client.rocket().state::<AppConfig>().unwrap();
+    let mut conn = app.pool.acquire().await.unwrap();
+
+    // A dry run reports them but deletes nothing
+    let report = retention::cleanup(&mut conn, config, true).await.unwrap();
+    assert!(report.login_attempts >= 1 && report.sessions >= 1 && report.audit_events >= 1);
+    assert_eq!(count(&app.pool, "login_attempts", "user_id", user.id()).await, 2);
+    assert_eq!(count(&app.pool, "sessions", "user_id", user.id()).await, 2);
+    assert_eq!(count(&app.pool, "audit_events", "user_id", user.id()).await, 2);
+
+    let purged = retention::cleanup(&mut conn, config, false).await.unwrap();
+    assert!(purged.login_attempts >= 1 && purged.sessions >= 1 && purged.audit_events >= 1);
+    assert_eq!(count(&app.pool, "login_attempts", "user_id", user.id()).await, 1);
+    assert_eq!(count(&app.pool, "sessions", "user_id", user.id()).await, 1);
+    assert_eq!(count(&app.pool, "audit_events", "user_id", user.id()).await, 1);
+}
+
+async fn partition_exists(pool: &PgPool, name: &str) -> bool {
+    sqlx::query_scalar::<_, bool>("SELECT to_regclass($1) IS NOT NULL")
+        .bind(name)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+async fn insert_event(pool: &PgPool, user_id: Uuid, occurred_at: DateTime<Utc>) {
+    sqlx::query(
+        "INSERT INTO audit_events (id, occurred_at, event_type, user_id) VALUES ($1, $2, 'password_changed', $3)",
+    )
+    .bind(Uuid::new_v4())
+    .bind(occurred_at)
+    .bind(user_id)
+    .execute(pool)
+    .await
+    .unwrap();
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn audit_events_are_kept_in_monthly_partitions() {
+    let app = TestApp::spawn_with(|config| {
+        config.audit_retention_days = 400;
+    })
+    .await;
+    let user = Uuid::new_v4();
+    let mut conn = app.pool.acquire().await.unwrap();
+
+    // Months nobody created a partition for go to the default partition, and
+    // move over when their partition is created
+    let future = Utc::now() + Months::new(20);
+    let future_partition = format!("audit_events_{}", future.format("%Y_%m"));
+    insert_event(&app.pool, user, future).await;
+    assert!(!partition_exists(&app.pool, &future_partition).await);
+    audit::ensure_partitions(&mut conn, future, future).await.unwrap();
+    assert!(partition_exists(&app.pool, &future_partition).await);
+    let in_partition = sqlx::query_scalar::<_, i64>(&format!("SELECT COUNT(*) FROM {} WHERE user_id = $1", future_partition))
+        .bind(user)
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(in_partition, 1);
+    sqlx::query(&format!("DROP TABLE {}", future_partition)).execute(&app.pool).await.unwrap();
+
+    // Retention drops months that are entirely past it
+    let old = Utc::now() - Months::new(15);
+    let old_partition = format!("audit_events_{}", old.format("%Y_%m"));
+    audit::ensure_partitions(&mut conn, old, old).await.unwrap();
+    insert_event(&app.pool, user, old).await;
+    insert_event(&app.pool, user, Utc::now()).await;
+
+    let config = app.client.rocket().state::<AppConfig>().unwrap();
+    let report = retention::cleanup(&mut conn, config, false).await.unwrap();
+    assert!(report.audit_events >= 1);
+    assert!(!partition_exists(&app.pool, &old_partition).await);
+    assert_eq!(count(&app.pool, "audit_events", "user_id", user).await, 1);
+}
@@ -0,0 +1,67 @@
+use rocket::http::{ContentType, Header, Status};
+use rocket::serde::json::json;
+
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, TestApp};
+
+const CHROME_ON_MAC: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 \
+                             (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36";
+
+const SAFARI_ON_IPHONE: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_4 like Mac OS X) AppleWebKit/605.1.15 \
+                                (KHTML, like Gecko) Version/17.4 Mobile/15E148 Safari/604.1";
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn sessions_show_the_browser_os_and_location_they_were_started_from() {
+    let app = TestApp::spawn_with(|config| {
+        config.geo_city_header = Some("CF-IPCity".to_string());
+        config.geo_country_header = Some("CF-IPCountry".to_string());
+    })
+    .await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let login = |user_agent: &'static str, city: &'static str, country: &'static str| {
+        app.client
+            .post("/api/v1/auth/login")
+            .header(ContentType::JSON)
+            .header(Header::new("User-Agent", user_agent))
+            .header(Header::new("CF-IPCity", city))
+            .header(Header::new("CF-IPCountry", country))
+            .body(json!({ "email": user.email(), "password": user.password }).to_string())
+            .dispatch()
+    };
+
+    let response = login(SAFARI_ON_IPHONE, "Paris", "fr").await;
+    assert_eq!(response.status(), Status::Ok);
+    let response = login(CHROME_ON_MAC, "Berlin", "DE").await;
+    assert_eq!(response.status(), Status::Ok);
+    let token = response_json(response).await["token"].as_str().unwrap().to_string();
+
+    let body = response_json(app.get_authorized("/api/v1/auth/me/sessions", &token).await).await;
+    let sessions = body["sessions"].as_array().unwrap();
+    let current = sessions.iter().find(|session| session["current"] == true).unwrap();
+    assert_eq!(current["device"], "Chrome on macOS, Berlin");
+    assert_eq!(current["browser"], "Chrome");
+    assert_eq!(current["os"], "macOS");
+    assert_eq!(current["device_type"], "desktop");
+    assert_eq!(current["city"], "Berlin");
+    assert_eq!(current["country"], "DE");
+
+    let phone = sessions.iter().find(|session| session["current"] == false).unwrap();
+    assert_eq!(phone["device"], "Safari on iOS, Paris");
+    assert_eq!(phone["device_type"], "mobile");
+    assert_eq!(phone["country"], "FR");
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn sessions_without_client_details_have_none() {
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.login_token(user.email(), &user.password).await;
+
+    let body = response_json(app.get_authorized("/api/v1/auth/me/sessions", &token).await).await;
+    let session = &body["sessions"][0];
+    assert!(session["device"].is_null());
+    assert!(session["browser"].is_null());
+    assert!(session["city"].is_null());
+}
@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use rocket::http::Status;
+use rocket::serde::json::json;
+
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, unique_email, TestApp};
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn overrides_apply_on_every_instance_until_reset() {
+    let app = TestApp::spawn_with(|config| config.runtime_settings = true).await;
+    let other = TestApp::spawn_with(|config| config.runtime_settings = true).await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let token = app.token_for(&admin.user).await;
+
+    let body = response_json(app.get_authorized("/api/v1/admin/settings", &token).await).await;
+    let grace_days = body["settings"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|setting| setting["key"] == "mfa.grace_days")
+        .unwrap();
+    assert_eq!(grace_days["default"], 7);
+
+    let response = app
+        .put_json_authorized("/api/v1/admin/settings/registration.mode", &token, json!({ "value": "closed" }))
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response_json(response).await;
+    assert_eq!(body["value"], "closed");
+    assert_eq!(body["default"], "open");
+    assert_eq!(body["overridden"], true);
+    assert_eq!(body["updated_by"], admin.user.id.to_string());
+
+    let response = app.register(&unique_email(), "password123").await;
+    assert_eq!(response.status(), Status::Forbidden);
+    assert_eq!(response_json(response).await["code"], "registration_closed");
+
+    // The other instance hears of the change
+    let mut closed = false;
+    for _ in 0..50 {
+        if other.register(&unique_email(), "password123").await.status() == Status::Forbidden {
+            closed = true;
+            break;
+        }
+        rocket::tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(closed);
+
+    let response = app.delete_authorized("/api/v1/admin/settings/registration.mode", &token).await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response_json(response).await["value"], "open");
+    let response = app.register(&unique_email(), "password123").await;
+    assert_eq!(response.status(), Status::Created);
+
+    let response = app.delete_authorized("/api/v1/admin/settings/registration.mode", &token).await;
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn invalid_settings_are_refused() {
+    let app = TestApp::spawn_with(|config| config.runtime_settings = true).await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let token = app.token_for(&admin.user).await;
+
+    for (key, value) in [
+        ("login.lockout_minutes", json!(0)),
+        ("mfa.grace_days", json!("soon")),
+        ("mfa.required_roles", json!(["admin", ""])),
+        ("registration.mode", json!("sometimes")),
+    ] {
+        let uri = format!("/api/v1/admin/settings/{}", key);
+        let response = app.put_json_authorized(&uri, &token, json!({ "value": value })).await;
+        assert_eq!(response.status(), Status::BadRequest, "{}", key);
+    }
+
+    // Settings with their own endpoints aren't changed through these
+    for key in ["email.smtp", "no.such.setting"] {
+        let uri = format!("/api/v1/admin/settings/{}", key);
+        let response = app.put_json_authorized(&uri, &token, json!({ "value": 1 })).await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let user_token = app.token_for(&user.user).await;
+    let response = app.get_authorized("/api/v1/admin/settings", &user_token).await;
+    assert_eq!(response.status(), Status::Forbidden);
+
+    let disabled = TestApp::spawn().await;
+    let response = disabled.get_authorized("/api/v1/admin/settings", &token).await;
+    assert_eq!(response.status(), Status::NotFound);
+}
@@ -0,0 +1,182 @@
+use std::sync::{Arc, Mutex};
+
+use rocket::http::Status;
+use rocket::serde::json::{json, Value};
+use uuid::Uuid;
+
+use rocket_auth_boilerplate::config::GenericOAuthConfig;
+use rocket_auth_boilerplate::repositories::{organizations, users};
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, unique_email, TestApp};
+
+/// Answers POSTs with an access token and GETs with whatever profile is current, like a provider would
+async fn mock_provider(profile: Arc<Mutex<Value>>) -> String {
+    use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = rocket::tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    rocket::tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut request = vec![0; 8192];
+            let read = stream.read(&mut request).await.unwrap_or(0);
+            let body = match request[..read].starts_with(b"POST") {
+                true => json!({ "access_token": "mock-access-token", "token_type": "bearer" }),
+                false => profile.lock().unwrap().clone(),
+            }
+            .to_string();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        }
+    });
+    format!("http://{}", address)
+}
+
+/// A provider named uniquely, so each test has its own rules
+fn provider(url: &str) -> GenericOAuthConfig {
+    GenericOAuthConfig {
+        name: format!("corp-{}", Uuid::new_v4().simple()),
+        client_id: "corp-client".to_string(),
+        client_secret: "corp-secret".to_string(),
+        authorize_url: format!("{}/oauth/authorize", url),
+        token_url: format!("{}/oauth/token", url),
+        userinfo_url: format!("{}/userinfo", url),
+        scopes: "openid email groups".to_string(),
+        subject_field: "id".to_string(),
+        email_field: "email".to_string(),
+        email_verified_field: Some("email_verified".to_string()),
+        basic_auth: false,
+        pkce: true,
+    }
+}
+
+/// Sign in at the provider through the authorize redirect and the callback
+async fn sign_in(app: &TestApp, provider: &str) -> Value {
+    let response = app.client.get(format!("/api/v1/auth/oauth/{}/authorize", provider)).dispatch().await;
+    let location = reqwest::Url::parse(response.headers().get_one("Location").unwrap()).unwrap();
+    let state = location.query_pairs().find(|(key, _)| key == "state").unwrap().1.into_owned();
+    let response = app
+        .post_json(
+            &format!("/api/v1/auth/oauth/{}/callback", provider),
+            json!({ "code": "mock-code", "state": state }),
+        )
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    response_json(response).await
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn mapping_rules_follow_the_providers_claims_on_each_sign_in() {
+    let email = unique_email();
+    let profile = Arc::new(Mutex::new(json!({
+        "id": Uuid::new_v4().to_string(),
+        "email": email,
+        "email_verified": true,
+        "groups": ["engineering"],
+        "department": { "name": "Support" }
+    })));
+    let corp = provider(&mock_provider(profile.clone()).await);
+    let mappings_uri = format!("/api/v1/admin/sso/{}/mappings", corp.name);
+    let app = TestApp::spawn_with(|config| config.oauth_providers = vec![corp.clone()]).await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let token = app.token_for(&admin.user).await;
+    let mut conn = app.pool.acquire().await.unwrap();
+    let org = organizations::create(&mut conn, "Mapped").await.unwrap();
+
+    let unknown = json!({ "claim": "groups", "value": "x", "role": "staff" });
+    let response = app.post_json_authorized("/api/v1/admin/sso/nope/mappings", &token, unknown).await;
+    assert_eq!(response.status(), Status::NotFound);
+    for invalid in [
+        json!({ "claim": "groups", "value": "engineering" }),
+        json!({ "claim": "groups", "value": "engineering", "organization_role": "admin" }),
+        json!({ "claim": "groups", "value": "engineering", "organization_id": org, "organization_role": "owner" }),
+        json!({ "claim": "groups", "value": "engineering", "organization_id": Uuid::new_v4() }),
+    ] {
+        let response = app.post_json_authorized(&mappings_uri, &token, invalid.clone()).await;
+        assert_eq!(response.status(), Status::BadRequest, "{}", invalid);
+    }
+
+    let mut created = Vec::new();
+    for mapping in [
+        json!({ "claim": "groups", "value": "engineering", "organization_id": org }),
+        json!({ "claim": "groups", "value": "eng-leads", "organization_id": org, "organization_role": "admin", "priority": 10 }),
+        json!({ "claim": "department.name", "value": "Support", "role": "support" }),
+    ] {
+        let response = app.post_json_authorized(&mappings_uri, &token, mapping).await;
+        assert_eq!(response.status(), Status::Created);
+        created.push(response_json(response).await["mapping"].clone());
+    }
+    assert_eq!(created[0]["organization_role"], "member");
+
+    let plain = app.token_for(&UserFactory::verified().insert(&app.pool).await.user).await;
+    let response = app.get_authorized(&mappings_uri, &plain).await;
+    assert_eq!(response.status(), Status::Forbidden);
+    let listed = response_json(app.get_authorized(&mappings_uri, &token).await).await;
+    assert_eq!(listed["mappings"].as_array().unwrap().len(), 3);
+    assert_eq!(listed["mappings"][0]["value"], "eng-leads");
+
+    // First sign-in provisions the account with what the rules grant
+    sign_in(&app, &corp.name).await;
+    let user = users::find_by_email(&mut conn, &email).await.unwrap().unwrap();
+    assert_eq!(user.organization_id, Some(org));
+    assert_eq!(user.organization_role, "member");
+    assert_eq!(user.role, "support");
+
+    // The higher priority rule wins
+    profile.lock().unwrap()["groups"] = json!(["engineering", "eng-leads"]);
+    sign_in(&app, &corp.name).await;
+    let user = users::find_by_email(&mut conn, &email).await.unwrap().unwrap();
+    assert_eq!(user.organization_role, "admin");
+
+    // Losing the claims takes away what they granted
+    profile.lock().unwrap()["groups"] = json!([]);
+    profile.lock().unwrap()["department"] = json!({ "name": "Sales" });
+    sign_in(&app, &corp.name).await;
+    let user = users::find_by_email(&mut conn, &email).await.unwrap().unwrap();
+    assert_eq!(user.organization_id, None);
+    assert_eq!(user.role, "user");
+
+    let delete_uri = format!("{}/{}", mappings_uri, created[2]["id"].as_str().unwrap());
+    assert_eq!(app.delete_authorized(&delete_uri, &token).await.status(), Status::Ok);
+    assert_eq!(app.delete_authorized(&delete_uri, &token).await.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn mapping_rules_leave_other_organizations_and_roles_alone() {
+    let email = unique_email();
+    let profile = Arc::new(Mutex::new(json!({
+        "id": Uuid::new_v4().to_string(),
+        "email": email,
+        "email_verified": true,
+        "groups": ["engineering"]
+    })));
+    let corp = provider(&mock_provider(profile.clone()).await);
+    let mappings_uri = format!("/api/v1/admin/sso/{}/mappings", corp.name);
+    let app = TestApp::spawn_with(|config| config.oauth_providers = vec![corp.clone()]).await;
+    let admin = UserFactory::verified().with_role("admin").insert(&app.pool).await;
+    let token = app.token_for(&admin.user).await;
+    let mut conn = app.pool.acquire().await.unwrap();
+    let mapped = organizations::create(&mut conn, "Mapped").await.unwrap();
+    let elsewhere = organizations::create(&mut conn, "Elsewhere").await.unwrap();
+
+    for mapping in [
+        json!({ "claim": "groups", "value": "engineering", "organization_id": mapped }),
+        json!({ "claim": "groups", "value": "engineering", "role": "engineer" }),
+    ] {
+        let response = app.post_json_authorized(&mappings_uri, &token, mapping).await;
+        assert_eq!(response.status(), Status::Created);
+    }
+
+    // Already in another organization, with a role given by other means
+    let user = UserFactory::verified().with_email(&email).with_role("moderator").insert(&app.pool).await;
+    users::join_organization_if_unset(&mut conn, user.id(), elsewhere).await.unwrap();
+    sign_in(&app, &corp.name).await;
+    let user = users::find_by_id(&mut conn, user.id()).await.unwrap().unwrap();
+    assert_eq!(user.organization_id, Some(elsewhere));
+    assert_eq!(user.role, "moderator");
+}
@@ -0,0 +1,86 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rocket::http::Status;
+use rocket::serde::json::{json, Value};
+
+use rocket_auth_boilerplate::config::VerificationMode;
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::{response_json, TestApp};
+
+fn claims(token: &str) -> Value {
+    let payload = token.split('.').nth(1).unwrap();
+    serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap()
+}
+
+async fn stateless_app() -> TestApp {
+    TestApp::spawn_with(|config| config.verification_mode = VerificationMode::Stateless { token_minutes: 5 }).await
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn stateless_tokens_are_short_lived_and_trusted_until_renewed() {
+    let app = stateless_app().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+
+    let token = app.login_token(user.email(), &user.password).await;
+    let claims = claims(&token);
+    assert!(claims["exp"].as_i64().unwrap() - claims["iat"].as_i64().unwrap() <= 5 * 60);
+    assert_eq!(claims["standing"]["role"], "user");
+
+    // The guard doesn't read the session, so revoking it doesn't reach this token yet
+    sqlx::query("UPDATE sessions SET revoked_at = NOW() WHERE user_id = $1")
+        .bind(user.id())
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    let response = app.get_authorized("/api/v1/auth/me", &token).await;
+    assert_eq!(response.status(), Status::Ok);
+
+    // Renewing checks the session in full
+    let response = app.post_json_authorized("/api/v1/auth/renew-token", &token, json!({})).await;
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn renewed_tokens_carry_the_current_standing() {
+    let app = stateless_app().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.login_token(user.email(), &user.password).await;
+
+    let response = app.post_json_authorized("/api/v1/auth/renew-token", &token, json!({})).await;
+    assert_eq!(response.status(), Status::Ok);
+    let renewed = response_json(response).await["token"].as_str().unwrap().to_string();
+    assert_eq!(claims(&renewed)["sid"], claims(&token)["sid"]);
+
+    sqlx::query("UPDATE users SET must_change_password = TRUE WHERE id = $1")
+        .bind(user.id())
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    let response = app.post_json_authorized("/api/v1/auth/renew-token", &renewed, json!({})).await;
+    assert_eq!(response.status(), Status::Ok);
+    let renewed = response_json(response).await["token"].as_str().unwrap().to_string();
+
+    let response = app.get_authorized("/api/v1/auth/me", &renewed).await;
+    assert_eq!(response.status(), Status::Forbidden);
+    assert_eq!(response_json(response).await["code"], "password_change_required");
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn stateful_verification_refuses_revoked_sessions_at_once() {
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+
+    let token = app.login_token(user.email(), &user.password).await;
+    assert!(claims(&token).get("standing").is_none());
+
+    sqlx::query("UPDATE sessions SET revoked_at = NOW() WHERE user_id = $1")
+        .bind(user.id())
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    let response = app.get_authorized("/api/v1/auth/me", &token).await;
+    assert_eq!(response.status(), Status::Unauthorized);
+}
@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use rocket::http::Status;
+
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::TestApp;
+
+/// Poll `query` until it returns true, for at most about five seconds
+async fn eventually(app: &TestApp, query: &str, user_id: uuid::Uuid) -> bool {
+    for _ in 0..50 {
+        let done = sqlx::query_scalar::<_, bool>(query)
+            .bind(user_id)
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+        if done {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    false
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn batched_telemetry_is_written_on_the_next_flush() {
+    let app = TestApp::spawn_with(|config| config.telemetry_flush_ms = 50).await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+
+    let token = app.login_token(user.email(), &user.password).await;
+    assert!(
+        eventually(&app, "SELECT login_count = 1 AND last_login_at IS NOT NULL FROM users WHERE id = $1", user.id()).await,
+        "last-login details were not written"
+    );
+    assert!(
+        eventually(
+            &app,
+            "SELECT EXISTS (SELECT 1 FROM login_attempts WHERE user_id = $1 AND succeeded)",
+            user.id()
+        )
+        .await,
+        "login attempt was not written"
+    );
+    assert!(
+        eventually(
+            &app,
+            "SELECT EXISTS (SELECT 1 FROM audit_events WHERE user_id = $1 AND event_type = 'session_created')",
+            user.id()
+        )
+        .await,
+        "session event was not written"
+    );
+
+    // Sessions are only touched once a minute
+    sqlx::query("UPDATE sessions SET last_used_at = last_used_at - INTERVAL '5 minutes' WHERE user_id = $1")
+        .bind(user.id())
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    let response = app.get_authorized("/api/v1/auth/me", &token).await;
+    assert_eq!(response.status(), Status::Ok);
+    assert!(
+        eventually(
+            &app,
+            "SELECT last_used_at > CURRENT_TIMESTAMP - INTERVAL '1 minute' FROM sessions WHERE user_id = $1",
+            user.id()
+        )
+        .await,
+        "session touch was not written"
+    );
+}
@@ -0,0 +1,134 @@
+use uuid::Uuid;
+
+use rocket_auth_boilerplate::config::Tenancy;
+use rocket_auth_boilerplate::models::organization::OrgRole;
+use rocket_auth_boilerplate::repositories::{organizations, users};
+use rocket_auth_boilerplate::tenancy::{self, Tenant, TenantOwned};
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::TestApp;
+
+#[derive(Debug, sqlx::FromRow)]
+struct Note {
+    id: Uuid,
+    body: String,
+}
+
+impl TenantOwned for Note {
+    const TABLE: &'static str = "tenancy_test_notes";
+    const COLUMNS: &'static str = "id, body";
+}
+
+/// A tenant of a new organization, acting through a new member
+async fn tenant(app: &TestApp, strategy: Tenancy) -> Tenant {
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let mut conn = app.pool.acquire().await.unwrap();
+    let organization_id = organizations::create(&mut conn, "Acme").await.unwrap();
+    users::set_organization(&mut conn, user.id(), organization_id, OrgRole::Member).await.unwrap();
+    Tenant::for_user(&mut conn, user.id(), strategy).await.unwrap().unwrap()
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn row_scoping_keeps_tenants_apart() {
+    let app = TestApp::spawn().await;
+    let mut conn = app.pool.acquire().await.unwrap();
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS tenancy_test_notes (
+            id UUID PRIMARY KEY, organization_id UUID NOT NULL, body TEXT NOT NULL)",
+    )
+    .execute(&mut *conn)
+    .await
+    .unwrap();
+    tenancy::enable_row_security(&mut conn, "tenancy_test_notes").await.unwrap();
+
+    let (acme, globex) = (tenant(&app, Tenancy::Rows).await, tenant(&app, Tenancy::Rows).await);
+    let mut ids = Vec::new();
+    for tenant in [&acme, &globex] {
+        let id = Uuid::new_v4();
+        sqlx::query("INSERT INTO tenancy_test_notes (id, organization_id, body) VALUES ($1, $2, 'hello')")
+            .bind(id)
+            .bind(tenant.organization_id)
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        ids.push(id);
+    }
+
+    let mut tx = acme.begin(&mut conn).await.unwrap();
+    let notes = tenancy::list::<Note>(&mut tx).await.unwrap();
+    assert_eq!(notes.len(), 1);
+    assert_eq!((notes[0].id, notes[0].body.as_str()), (ids[0], "hello"));
+    assert!(tenancy::find::<Note>(&mut tx, ids[1]).await.unwrap().is_none());
+    assert!(!tenancy::delete::<Note>(&mut tx, ids[1]).await.unwrap());
+    assert!(tenancy::delete::<Note>(&mut tx, ids[0]).await.unwrap());
+    drop(tx);
+
+    // Row-level security holds for raw queries too, for roles it applies to
+    sqlx::query(
+        "DO $$ BEGIN
+            IF NOT EXISTS (SELECT 1 FROM pg_roles WHERE rolname = 'tenancy_test_app') THEN
+                CREATE ROLE tenancy_test_app NOLOGIN;
+            END IF;
+        END $$",
+    )
+    .execute(&mut *conn)
+    .await
+    .unwrap();
+    sqlx::query("GRANT SELECT ON tenancy_test_notes TO tenancy_test_app")
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+    let mut tx = globex.begin(&mut conn).await.unwrap();
+    sqlx::query("SET LOCAL ROLE tenancy_test_app").execute(&mut *tx).await.unwrap();
+    let visible: Vec<Uuid> = sqlx::query_scalar("SELECT id FROM tenancy_test_notes")
+        .fetch_all(&mut *tx)
+        .await
+        .unwrap();
+    assert_eq!(visible, vec![ids[1]]);
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn each_tenant_gets_its_own_schema() {
+    let app = TestApp::spawn().await;
+    let mut conn = app.pool.acquire().await.unwrap();
+    let (acme, globex) = (tenant(&app, Tenancy::Schemas).await, tenant(&app, Tenancy::Schemas).await);
+
+    let mut note_ids = Vec::new();
+    for tenant in [&acme, &globex] {
+        let mut tx = tenant.begin(&mut conn).await.unwrap();
+        sqlx::query("CREATE TABLE IF NOT EXISTS tenancy_test_notes (id UUID PRIMARY KEY, body TEXT NOT NULL)")
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        let id = Uuid::new_v4();
+        sqlx::query("INSERT INTO tenancy_test_notes (id, body) VALUES ($1, 'hello')")
+            .bind(id)
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+        note_ids.push(id);
+    }
+
+    let schema: Option<String> = sqlx::query_scalar("SELECT to_regnamespace($1)::text")
+        .bind(tenancy::schema_name(acme.organization_id))
+        .fetch_one(&mut *conn)
+        .await
+        .unwrap();
+    assert_eq!(schema, Some(tenancy::schema_name(acme.organization_id)));
+
+    let mut tx = acme.begin(&mut conn).await.unwrap();
+    let notes = tenancy::list::<Note>(&mut tx).await.unwrap();
+    assert_eq!(notes.iter().map(|note| note.id).collect::<Vec<_>>(), vec![note_ids[0]]);
+    assert!(tenancy::find::<Note>(&mut tx, note_ids[1]).await.unwrap().is_none());
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn users_outside_organizations_have_no_tenant() {
+    let app = TestApp::spawn().await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let mut conn = app.pool.acquire().await.unwrap();
+    assert!(Tenant::for_user(&mut conn, user.id(), Tenancy::Rows).await.unwrap().is_none());
+}
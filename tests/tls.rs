@@ -0,0 +1,207 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, IsCa, KeyPair};
+use rocket::{get, routes, Shutdown};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{ClientConfig, RootCertStore};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use uuid::Uuid;
+
+use rocket_auth_boilerplate::config::TlsConfig;
+use rocket_auth_boilerplate::tls::{self, TlsError};
+
+#[get("/ping")]
+fn ping() -> &'static str {
+    "pong"
+}
+
+#[get("/shutdown")]
+fn shutdown(shutdown: Shutdown) -> &'static str {
+    shutdown.notify();
+    "bye"
+}
+
+struct Authority {
+    issuer: CertifiedIssuer<'static, KeyPair>,
+}
+
+impl Authority {
+    fn new() -> Self {
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        Authority {
+            issuer: CertifiedIssuer::self_signed(params, KeyPair::generate().unwrap()).unwrap(),
+        }
+    }
+
+    /// A certificate and key for `name`, as PEM
+    fn issue(&self, name: &str) -> (String, String) {
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec![name.to_string()]).unwrap().signed_by(&key, &self.issuer).unwrap();
+        (cert.pem(), key.serialize_pem())
+    }
+
+    fn pem(&self) -> String {
+        self.issuer.as_ref().pem()
+    }
+}
+
+fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rocket-auth-tls-{}", Uuid::new_v4()));
+    std::fs::create_dir(&dir).unwrap();
+    dir
+}
+
+fn write_pair(dir: &Path, (cert, key): &(String, String)) -> TlsConfig {
+    std::fs::write(dir.join("cert.pem"), cert).unwrap();
+    std::fs::write(dir.join("key.pem"), key).unwrap();
+    TlsConfig {
+        cert_path: dir.join("cert.pem"),
+        key_path: dir.join("key.pem"),
+        client_ca_path: None,
+    }
+}
+
+/// Start serving on a free port and wait until it accepts connections
+async fn start(config: TlsConfig) -> (u16, tokio::task::JoinHandle<std::io::Result<()>>) {
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let figment = rocket::Config::figment().merge(("address", "127.0.0.1")).merge(("port", port));
+    let rocket = rocket::custom(figment).mount("/", routes![ping, shutdown]);
+    let server = tokio::spawn(async move { tls::serve(rocket, &config).await });
+    while TcpStream::connect(("127.0.0.1", port)).await.is_err() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    (port, server)
+}
+
+fn connector(authority: &Authority, client: Option<&(String, String)>) -> TlsConnector {
+    let mut roots = RootCertStore::empty();
+    roots.add(CertificateDer::from_pem_slice(authority.pem().as_bytes()).unwrap()).unwrap();
+    let builder = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots);
+    let config = match client {
+        Some((cert, key)) => builder
+            .with_client_auth_cert(
+                vec![CertificateDer::from_pem_slice(cert.as_bytes()).unwrap()],
+                PrivateKeyDer::from_pem_slice(key.as_bytes()).unwrap(),
+            )
+            .unwrap(),
+        None => builder.with_no_client_auth(),
+    };
+    TlsConnector::from(Arc::new(config))
+}
+
+/// GET `uri`, returning the response and the certificate the server presented
+async fn get(connector: &TlsConnector, port: u16, uri: &str) -> std::io::Result<(String, Vec<u8>)> {
+    let stream = TcpStream::connect(("127.0.0.1", port)).await?;
+    let mut stream = connector.connect(ServerName::try_from("localhost").unwrap(), stream).await?;
+    let presented = stream.get_ref().1.peer_certificates().unwrap()[0].to_vec();
+    let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", uri);
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    Ok((response, presented))
+}
+
+fn der(pem: &str) -> Vec<u8> {
+    CertificateDer::from_pem_slice(pem.as_bytes()).unwrap().to_vec()
+}
+
+#[rocket::async_test]
+async fn certificates_are_reloaded_on_sighup() {
+    let authority = Authority::new();
+    let dir = temp_dir();
+    let first = authority.issue("localhost");
+    let (port, server) = start(write_pair(&dir, &first)).await;
+    let connector = connector(&authority, None);
+
+    let (response, presented) = get(&connector, port, "/ping").await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.ends_with("pong"));
+    assert_eq!(presented, der(&first.0));
+
+    // A key that doesn't match is refused, and the current certificate kept
+    let renewed = authority.issue("localhost");
+    std::fs::write(dir.join("cert.pem"), &renewed.0).unwrap();
+    let hangup = || {
+        std::process::Command::new("kill")
+            .args(["-HUP", &std::process::id().to_string()])
+            .status()
+            .unwrap()
+    };
+    hangup();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(get(&connector, port, "/ping").await.unwrap().1, der(&first.0));
+
+    std::fs::write(dir.join("key.pem"), &renewed.1).unwrap();
+    hangup();
+    let mut presented = Vec::new();
+    for _ in 0..100 {
+        presented = get(&connector, port, "/ping").await.unwrap().1;
+        if presented != der(&first.0) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(presented, der(&renewed.0));
+
+    get(&connector, port, "/shutdown").await.unwrap();
+    server.await.unwrap().unwrap();
+}
+
+#[rocket::async_test]
+async fn mutual_tls_requires_a_client_certificate_from_the_configured_ca() {
+    let authority = Authority::new();
+    let clients = Authority::new();
+    let dir = temp_dir();
+    std::fs::write(dir.join("clients.pem"), clients.pem()).unwrap();
+    let config = TlsConfig {
+        client_ca_path: Some(dir.join("clients.pem")),
+        ..write_pair(&dir, &authority.issue("localhost"))
+    };
+    let (port, server) = start(config).await;
+
+    assert!(get(&connector(&authority, None), port, "/ping").await.is_err());
+    let stranger = Authority::new().issue("client");
+    assert!(get(&connector(&authority, Some(&stranger)), port, "/ping").await.is_err());
+
+    let client = connector(&authority, Some(&clients.issue("client")));
+    let (response, _) = get(&client, port, "/ping").await.unwrap();
+    assert!(response.ends_with("pong"));
+
+    get(&client, port, "/shutdown").await.unwrap();
+    server.await.unwrap().unwrap();
+}
+
+#[test]
+fn unusable_certificates_are_rejected_up_front() {
+    let authority = Authority::new();
+    let dir = temp_dir();
+    let config = write_pair(&dir, &authority.issue("localhost"));
+    assert!(tls::server_config(&config).is_ok());
+
+    let mismatched = TlsConfig {
+        key_path: write_pair(&temp_dir(), &authority.issue("localhost")).key_path,
+        ..config.clone()
+    };
+    assert!(matches!(tls::server_config(&mismatched), Err(TlsError::Rustls(_))));
+
+    let missing = TlsConfig {
+        cert_path: dir.join("missing.pem"),
+        ..config.clone()
+    };
+    assert!(matches!(tls::server_config(&missing), Err(TlsError::File { .. })));
+
+    let no_client_ca = TlsConfig {
+        client_ca_path: Some(dir.join("key.pem")),
+        ..config
+    };
+    assert!(matches!(tls::server_config(&no_client_ca), Err(TlsError::File { .. })));
+}
@@ -0,0 +1,66 @@
+use std::os::unix::net::UnixListener as StdUnixListener;
+use std::path::Path;
+
+use rocket::{get, routes, Shutdown};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use uuid::Uuid;
+
+use rocket_auth_boilerplate::unix_socket;
+
+#[get("/ping")]
+fn ping() -> &'static str {
+    "pong"
+}
+
+#[get("/shutdown")]
+fn shutdown(shutdown: Shutdown) -> &'static str {
+    shutdown.notify();
+    "bye"
+}
+
+async fn get(path: &Path, uri: &str) -> String {
+    let mut stream = UnixStream::connect(path).await.unwrap();
+    let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", uri);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[rocket::async_test]
+async fn stale_sockets_are_replaced_and_removed_at_shutdown() {
+    let path = std::env::temp_dir().join(format!("rocket-auth-{}.sock", Uuid::new_v4()));
+    // Left behind by a server that didn't shut down cleanly
+    drop(StdUnixListener::bind(&path).unwrap());
+    assert!(path.exists());
+
+    let rocket = rocket::build().mount("/", routes![ping, shutdown]);
+    let server = tokio::spawn({
+        let path = path.clone();
+        async move { unix_socket::serve(rocket, &path).await }
+    });
+    while UnixStream::connect(&path).await.is_err() {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+
+    let response = get(&path, "/ping").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.ends_with("pong"));
+
+    get(&path, "/shutdown").await;
+    server.await.unwrap().unwrap();
+    assert!(!path.exists());
+}
+
+#[rocket::async_test]
+async fn sockets_in_use_are_not_taken_over() {
+    let path = std::env::temp_dir().join(format!("rocket-auth-{}.sock", Uuid::new_v4()));
+    let _listener = StdUnixListener::bind(&path).unwrap();
+
+    let rocket = rocket::build().mount("/", routes![ping]);
+    let error = unix_socket::serve(rocket, &path).await.unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::AddrInUse);
+    assert!(path.exists());
+    std::fs::remove_file(&path).unwrap();
+}
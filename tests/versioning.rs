@@ -0,0 +1,47 @@
+use chrono::{TimeZone, Utc};
+use rocket::http::Status;
+use rocket::serde::json::json;
+
+use rocket_auth_boilerplate::test_support::factories::UserFactory;
+use rocket_auth_boilerplate::test_support::TestApp;
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn unversioned_paths_are_deprecated() {
+    let sunset = Utc.with_ymd_and_hms(2027, 1, 31, 0, 0, 0).unwrap();
+    let app = TestApp::spawn_with(|config| config.legacy_api_sunset = Some(sunset)).await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+
+    let response = app.get_authorized("/api/v1/auth/me", &token).await;
+    assert_eq!(response.status(), Status::Ok);
+    assert!(response.headers().get_one("Deprecation").is_none());
+    assert!(response.headers().get_one("Sunset").is_none());
+
+    let response = app.get_authorized("/api/auth/me", &token).await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("Deprecation"), Some("true"));
+    assert_eq!(response.headers().get_one("Sunset"), Some("Sun, 31 Jan 2027 00:00:00 GMT"));
+    assert_eq!(
+        response.headers().get_one("Link"),
+        Some("</api/v1/auth/me>; rel=\"successor-version\"")
+    );
+
+    // Errors from old paths are marked too
+    let response = app.post_json("/api/auth/login", json!({ "email": user.email(), "password": "wrong" })).await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert_eq!(response.headers().get_one("Deprecation"), Some("true"));
+}
+
+#[rocket::async_test]
+#[ignore = "requires Docker or TEST_DATABASE_URL"]
+async fn unversioned_paths_can_be_turned_off() {
+    let app = TestApp::spawn_with(|config| config.legacy_api = false).await;
+    let user = UserFactory::verified().insert(&app.pool).await;
+    let token = app.token_for(&user.user).await;
+
+    let response = app.get_authorized("/api/auth/me", &token).await;
+    assert_eq!(response.status(), Status::NotFound);
+    let response = app.get_authorized("/api/v1/auth/me", &token).await;
+    assert_eq!(response.status(), Status::Ok);
+}